@@ -0,0 +1,83 @@
+//! Four local providers disagree about the block number: two agree, one is stale and one errors.
+//!
+//! Runs without network access; each provider is a few lines of raw HTTP on a loopback port.
+
+use std::time::Duration;
+
+use ez_web3_rpc::{
+    ConsensusOptions, HandlerConfig, HandlerSettings, JsonRpcRequest, LogLevel, RpcCalls, RpcConfig, RpcHandler, DataScope,
+};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// A network id with no chainlist entries, so only the local providers take part.
+const LOCAL_NETWORK_ID: u64 = 424242;
+
+/// Answer every POST with `body` after a short delay, returning the provider's URL.
+async fn fake_provider(body: Value) -> Result<String, Box<dyn std::error::Error>> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    let payload = body.to_string();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let payload = payload.clone();
+            tokio::spawn(async move {
+                // The request is small enough to arrive in one read; its content doesn't matter
+                let mut request = [0u8; 8192];
+                if stream.read(&mut request).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{payload}",
+                    payload.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+
+    Ok(url)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut urls = Vec::new();
+    for body in [
+        json!({ "jsonrpc": "2.0", "id": 1, "result": "0x10" }),
+        json!({ "jsonrpc": "2.0", "id": 1, "result": "0x10" }),
+        json!({ "jsonrpc": "2.0", "id": 1, "result": "0xf" }),
+        json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32000, "message": "header not found" } }),
+    ] {
+        urls.push(fake_provider(body).await?);
+    }
+
+    let network_rpcs = urls
+        .iter()
+        .map(|url| Ok(RpcConfig { url: Some(url.parse()?), is_open_source: Some(true), ..RpcConfig::default() }))
+        .collect::<Result<Vec<_>, url::ParseError>>()?;
+    let settings = HandlerSettings {
+        log_level: LogLevel::Error,
+        network_rpcs,
+        network_name: "local".to_string(),
+        data_scope: DataScope::OnlyThisNetwork,
+        ..HandlerSettings::default()
+    };
+    let handler = RpcHandler::new(HandlerConfig { network_id: LOCAL_NETWORK_ID, settings: Some(settings) }, None).await?;
+    let calls = RpcCalls::new(handler);
+
+    let request = JsonRpcRequest::new("eth_blockNumber", json!([]));
+    // The providers share a hostname; let them all be asked at once
+    let options = ConsensusOptions { per_host_concurrency: Some(4), ..ConsensusOptions::default() };
+
+    // Three of four would have to agree
+    let (result, report) = calls.consensus_with_report::<String>(&request, 0.75, Some(options)).await;
+    match result {
+        Ok(block) => println!("consensus: {block}"),
+        Err(e) => println!("consensus failed: {e}"),
+    }
+    println!("{report}");
+
+    Ok(())
+}
@@ -0,0 +1,24 @@
+//! Diagnose a handler's setup without initializing it.
+//!
+//! `cargo run --example doctor -- 137` checks Polygon; the network defaults to Ethereum mainnet.
+//! Exits non-zero when a check fails.
+
+use ez_web3_rpc::{HandlerConfig, RpcHandler};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let network_id = match std::env::args().nth(1) {
+        Some(arg) => arg.parse()?,
+        None => 1,
+    };
+
+    // Default settings rather than `HandlerConfig::new`, which expects the network in the registry
+    let handler = RpcHandler::new(HandlerConfig { network_id, settings: None }, None).await?;
+    let report = handler.doctor().await;
+    println!("{report}");
+
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
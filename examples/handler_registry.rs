@@ -0,0 +1,52 @@
+//! Share one handler between request handlers instead of building one per request.
+//!
+//! `cargo run --example handler_registry` serves a few simulated requests against Ethereum
+//! mainnet. In an axum service the same lookup sits in an extractor, so handlers take the
+//! `Arc<RpcHandler>` as an argument:
+//!
+//! ```ignore
+//! struct Rpc(Arc<RpcHandler>);
+//!
+//! impl<S: Send + Sync> FromRequestParts<S> for Rpc {
+//!     type Rejection = (StatusCode, String);
+//!
+//!     async fn from_request_parts(_: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+//!         let handler = HandlerRegistry::global().get_or_create(mainnet()).await;
+//!         handler.map(Rpc).map_err(|err| (StatusCode::SERVICE_UNAVAILABLE, err.to_string()))
+//!     }
+//! }
+//!
+//! async fn block_number(Rpc(handler): Rpc) -> String { ... }
+//! ```
+
+use std::sync::Arc;
+
+use ez_web3_rpc::prelude::*;
+use serde_json::json;
+
+fn mainnet() -> HandlerConfig {
+    HandlerConfig { network_id: 1, settings: None }
+}
+
+/// What the extractor does: the first request builds and probes the handler, later ones reuse it.
+async fn extract() -> Result<Arc<RpcHandler>> {
+    HandlerRegistry::global().get_or_create(mainnet()).await
+}
+
+async fn block_number(id: u64) -> Result<String> {
+    let handler = extract().await?;
+    let request = JsonRpcRequest::new("eth_blockNumber", json!([])).with_id(id);
+    let response = handler.try_proxy_request(request).await?;
+    Ok(response.result.map(|block| block.to_string()).unwrap_or_default())
+}
+
+#[tokio::main]
+async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let requests = (1..=5).map(block_number);
+    for (id, block) in (1..).zip(futures::future::join_all(requests).await) {
+        println!("request {id}: block {}", block?);
+    }
+    // One miss built the handler; the other requests were hits
+    println!("{:?}", HandlerRegistry::global().stats());
+    Ok(())
+}
@@ -29,12 +29,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let calls = ez_web3_rpc::calls::RpcCalls::new(handler.clone());
 
     // Test basic RPC call
-    let block_request = JsonRpcRequest {
-        jsonrpc: "2.0".to_string(),
-        method: "eth_blockNumber".to_string(),
-        params: json!([]),
-        id: Some(1),
-    };
+    let block_request = JsonRpcRequest::new("eth_blockNumber", json!([]));
 
     match calls.try_rpc_call(&block_request).await {
         Ok(response) => {
@@ -0,0 +1,19 @@
+//! The shortest way to a working handler: build a config, create the handler, initialize it.
+//!
+//! `cargo run --example quick_start -- https://rpc.gnosischain.com` adds that endpoint to
+//! Gnosis's public ones; with no argument only the public ones are probed.
+
+use ez_web3_rpc::prelude::*;
+use serde_json::json;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = HandlerConfig::builder(100).rpc_urls(std::env::args().nth(1).as_deref()).probe_timeout_ms(2000).retry_count(5).build()?;
+    let handler = RpcHandler::new(config, None).await?;
+    handler.init().await?;
+
+    println!("Using {}", handler.get_provider_url().await?);
+    let request = JsonRpcRequest::new("eth_blockNumber", json!([]));
+    println!("Block: {:?}", handler.try_proxy_request(request).await?.result);
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! The primary endpoint dies mid-run: reads, raced across both endpoints, are answered by the
+//! backup from then on, and a refresh moves the handler onto it for good.
+//!
+//! Runs offline: `cargo run --example scenario_failover --features scenarios`
+
+use std::time::Duration;
+
+use ez_web3_rpc::{
+    scenario::{Narrator, Scenario, Script},
+    HandlerSettings, JsonRpcRequest, LogLevel, ProxySettings, RpcHandler,
+};
+use serde_json::json;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    prelude::*,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The handler's own logs, without the HTTP client's
+    tracing_subscriber::registry().with(tracing_subscriber::fmt::layer()).with(Targets::new().with_target("ez_web3_rpc", LevelFilter::DEBUG)).init();
+
+    let mut scenario = Scenario::new();
+    // Two probes, then four reads, then nothing
+    scenario.endpoint("primary", Script::dies_after(6)).await?;
+    scenario.endpoint("backup", Script::healthy().delay(Duration::from_millis(30))).await?;
+
+    let settings = HandlerSettings {
+        log_level: LogLevel::Debug,
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 50, rpc_call_timeout_ms: 1000, connect_timeout_ms: None }),
+        ..scenario.settings()
+    };
+    let handler = RpcHandler::new(scenario.config_with(settings), None).await?;
+    handler.init().await?;
+    let narrator = Narrator::new(&scenario, &handler, true).await;
+    narrator.checkpoint("Probed both endpoints; the faster one serves").await;
+
+    let block_number = JsonRpcRequest::new("eth_blockNumber", json!([]));
+    for read in 1..=8 {
+        match handler.try_proxy_request_attributed(block_number.clone()).await {
+            Ok((response, url)) => narrator.say(format!("read {read}: block {} from {url}", response.result.unwrap_or_default())),
+            Err(error) => narrator.say(format!("read {read} failed: {error}")),
+        }
+        scenario.advance(1);
+    }
+    narrator.checkpoint("The primary died after its fourth read").await;
+
+    handler.refresh().await?;
+    narrator.checkpoint("A refresh probed again and found the primary gone").await;
+
+    for served in scenario.timeline().iter().filter(|served| served.method == "eth_blockNumber") {
+        println!("{:>7.3}s  {:<8} {:?}", served.at.as_secs_f64(), served.endpoint, served.behavior);
+    }
+    Ok(())
+}
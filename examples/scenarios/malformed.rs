@@ -0,0 +1,50 @@
+//! The serving endpoint starts answering with broken JSON: each read fails over to the next
+//! endpoint, the failures are counted as such, and a refresh leaves the broken one behind.
+//!
+//! Runs offline: `cargo run --example scenario_malformed --features scenarios`
+
+use std::time::Duration;
+
+use ez_web3_rpc::{
+    scenario::{Behavior, Narrator, Scenario, Script, Trigger},
+    HandlerSettings, JsonRpcRequest, LogLevel, ProxySettings, RpcHandler,
+};
+use serde_json::json;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    prelude::*,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The handler's own logs, without the HTTP client's
+    tracing_subscriber::registry().with(tracing_subscriber::fmt::layer()).with(Targets::new().with_target("ez_web3_rpc", LevelFilter::DEBUG)).init();
+
+    let mut scenario = Scenario::new();
+    // Answers its probes, then two reads, then a truncated body to everything
+    scenario.endpoint("garbled", Script::healthy().then(Trigger::AfterMethod("eth_blockNumber".into(), 2), Behavior::Malformed)).await?;
+    scenario.endpoint("backup", Script::healthy().delay(Duration::from_millis(30))).await?;
+
+    let settings = HandlerSettings {
+        log_level: LogLevel::Debug,
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 50, rpc_call_timeout_ms: 1000, connect_timeout_ms: None }),
+        ..scenario.settings()
+    };
+    let handler = RpcHandler::new(scenario.config_with(settings), None).await?;
+    handler.init().await?;
+    let narrator = Narrator::new(&scenario, &handler, true).await;
+    narrator.checkpoint("Probed both endpoints").await;
+
+    let block_number = JsonRpcRequest::new("eth_blockNumber", json!([]));
+    for read in 1..=5 {
+        match handler.try_proxy_request_attributed(block_number.clone()).await {
+            Ok((response, url)) => narrator.say(format!("read {read}: block {} from {url}", response.result.unwrap_or_default())),
+            Err(error) => narrator.say(format!("read {read} failed: {error}")),
+        }
+    }
+    narrator.checkpoint("The garbled endpoint broke after two reads").await;
+
+    handler.refresh().await?;
+    narrator.checkpoint("Refreshed").await;
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! One endpoint starts rate limiting: consensus cools it down, longer with each strike, and
+//! still reaches a quorum with the others.
+//!
+//! Runs offline: `cargo run --example scenario_rate_limit --features scenarios,consensus`
+
+use ez_web3_rpc::{
+    scenario::{Behavior, Narrator, Scenario, Script, Trigger},
+    HandlerSettings, JsonRpcRequest, LogLevel, RpcCalls, RpcHandler,
+};
+use serde_json::json;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    prelude::*,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The handler's own logs, without the HTTP client's
+    tracing_subscriber::registry().with(tracing_subscriber::fmt::layer()).with(Targets::new().with_target("ez_web3_rpc", LevelFilter::DEBUG)).init();
+
+    let mut scenario = Scenario::new();
+    scenario.endpoint("alpha", Script::healthy()).await?;
+    scenario.endpoint("beta", Script::healthy()).await?;
+    scenario.endpoint("gamma", Script::healthy()).await?;
+    // Fine through its probes and first read, then over its limit
+    scenario
+        .endpoint("metered", Script::healthy().then(Trigger::AfterMethod("eth_blockNumber".into(), 1), Behavior::RateLimited { retry_after_secs: Some(1) }))
+        .await?;
+
+    let settings = HandlerSettings { log_level: LogLevel::Debug, ..scenario.settings() };
+    let handler = RpcHandler::new(scenario.config_with(settings), None).await?;
+    handler.init().await?;
+    let narrator = Narrator::new(&scenario, &handler, true).await;
+    let calls = RpcCalls::new(handler.clone());
+
+    let block_number = JsonRpcRequest::new("eth_blockNumber", json!([]));
+    // Three of four must agree, so every endpoint is asked
+    for round in 1..=3 {
+        let (result, report) = calls.consensus_with_report::<String>(&block_number, 0.75, None).await;
+        narrator.say(format!("consensus round {round}: {result:?}"));
+        narrator.consensus(&report);
+        scenario.advance(1);
+    }
+    narrator.checkpoint("Three rounds of consensus").await;
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! One endpoint serves blocks from a while ago: probing leaves it out of the ordering, and in
+//! consensus it is outvoted.
+//!
+//! Runs offline: `cargo run --example scenario_stale_blocks --features scenarios,consensus`
+
+use std::time::Duration;
+
+use ez_web3_rpc::{
+    scenario::{Behavior, Narrator, Scenario, Script},
+    HandlerSettings, JsonRpcRequest, LogLevel, RpcCalls, RpcHandler,
+};
+use serde_json::json;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    prelude::*,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The handler's own logs, without the HTTP client's
+    tracing_subscriber::registry().with(tracing_subscriber::fmt::layer()).with(Targets::new().with_target("ez_web3_rpc", LevelFilter::DEBUG)).init();
+
+    let mut scenario = Scenario::new();
+    scenario.endpoint("alpha", Script::healthy().delay(Duration::from_millis(20))).await?;
+    scenario.endpoint("beta", Script::healthy().delay(Duration::from_millis(40))).await?;
+    // The fastest of them, but five blocks behind
+    scenario.endpoint("laggard", Script::new(Behavior::Stale(5))).await?;
+
+    let settings = HandlerSettings { log_level: LogLevel::Debug, ..scenario.settings() };
+    let handler = RpcHandler::new(scenario.config_with(settings), None).await?;
+    handler.init().await?;
+    let narrator = Narrator::new(&scenario, &handler, true).await;
+    narrator.checkpoint("Probed all three").await;
+    for endpoint in handler.health_report().await.endpoints {
+        narrator.say(format!("  {}: {} blocks behind, latency {:?}", endpoint.url, endpoint.head_lag.unwrap_or_default(), endpoint.latency_ms));
+    }
+
+    let calls = RpcCalls::new(handler.clone());
+    let block_number = JsonRpcRequest::new("eth_blockNumber", json!([]));
+    let (result, report) = calls.consensus_with_report::<String>(&block_number, 0.66, None).await;
+    narrator.say(format!("consensus on the head: {result:?}"));
+    narrator.consensus(&report);
+    Ok(())
+}
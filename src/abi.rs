@@ -0,0 +1,459 @@
+//! Call data for contract view functions, built from signature strings, and decoding of what
+//! they return.
+//!
+//! Covers the Solidity types views mostly take and return: `address`, `uintN`, `intN`, `bool`,
+//! `bytesN`, `bytes`, `string`, and one-dimensional arrays of those (`T[]`). Signatures are plain
+//! strings such as `balanceOf(address)`, so no ABI JSON is needed. Tuples, fixed-size arrays and
+//! nested arrays aren't supported.
+
+use std::fmt;
+
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+
+pub(crate) use crate::hex::{hex, unhex};
+use crate::{calls::RpcCalls, JsonRpcRequest, Result, RpcHandlerError};
+
+/// One 32-byte ABI word, big-endian.
+pub type Word = [u8; 32];
+
+/// A Solidity type as it appears in a signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiType {
+    Address,
+    /// Width in bits, a multiple of 8 up to 256
+    Uint(usize),
+    Int(usize),
+    Bool,
+    /// Length in bytes, 1 to 32
+    FixedBytes(usize),
+    Bytes,
+    String,
+    /// `T[]`
+    Array(Box<AbiType>),
+}
+
+/// A value of one of the supported types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiValue {
+    /// `0x`-prefixed, lowercase
+    Address(String),
+    Uint(Word),
+    /// Two's complement
+    Int(Word),
+    Bool(bool),
+    FixedBytes(Vec<u8>),
+    Bytes(Vec<u8>),
+    String(String),
+    Array(Vec<AbiValue>),
+}
+
+pub(crate) fn invalid(detail: impl Into<String>) -> RpcHandlerError {
+    RpcHandlerError::InvalidAbi { detail: detail.into() }
+}
+
+
+impl AbiType {
+    /// Parse a type name; `uint` and `int` stand for `uint256` and `int256`.
+    pub fn parse(name: &str) -> Result<Self> {
+        let unsupported = || invalid(format!("unsupported type {name:?}"));
+        if let Some(element) = name.strip_suffix("[]") {
+            return match Self::parse(element)? {
+                Self::Array(_) => Err(unsupported()),
+                element => Ok(Self::Array(Box::new(element))),
+            };
+        }
+        let width = |digits: &str, default: usize| match digits {
+            "" => Some(default),
+            digits => digits.parse::<usize>().ok().filter(|bits| *bits > 0 && *bits <= 256 && bits % 8 == 0),
+        };
+        match name {
+            "address" => Ok(Self::Address),
+            "bool" => Ok(Self::Bool),
+            "bytes" => Ok(Self::Bytes),
+            "string" => Ok(Self::String),
+            _ => {
+                if let Some(digits) = name.strip_prefix("uint") {
+                    width(digits, 256).map(Self::Uint).ok_or_else(unsupported)
+                } else if let Some(digits) = name.strip_prefix("int") {
+                    width(digits, 256).map(Self::Int).ok_or_else(unsupported)
+                } else if let Some(digits) = name.strip_prefix("bytes") {
+                    digits.parse::<usize>().ok().filter(|len| (1..=32).contains(len)).map(Self::FixedBytes).ok_or_else(unsupported)
+                } else {
+                    Err(unsupported())
+                }
+            }
+        }
+    }
+
+    /// Whether the value lives in the tail, behind an offset in the head.
+    fn is_dynamic(&self) -> bool {
+        matches!(self, Self::Bytes | Self::String | Self::Array(_))
+    }
+}
+
+impl fmt::Display for AbiType {
+    /// The canonical name, as it goes into a selector.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address => write!(f, "address"),
+            Self::Uint(bits) => write!(f, "uint{bits}"),
+            Self::Int(bits) => write!(f, "int{bits}"),
+            Self::Bool => write!(f, "bool"),
+            Self::FixedBytes(len) => write!(f, "bytes{len}"),
+            Self::Bytes => write!(f, "bytes"),
+            Self::String => write!(f, "string"),
+            Self::Array(element) => write!(f, "{element}[]"),
+        }
+    }
+}
+
+impl AbiValue {
+    pub fn uint(value: u128) -> Self {
+        let mut word = [0u8; 32];
+        word[16..].copy_from_slice(&value.to_be_bytes());
+        Self::Uint(word)
+    }
+
+    pub fn int(value: i128) -> Self {
+        let mut word = if value < 0 { [0xff; 32] } else { [0u8; 32] };
+        word[16..].copy_from_slice(&value.to_be_bytes());
+        Self::Int(word)
+    }
+
+    /// A `Uint` that fits in a `u128`.
+    pub fn as_u128(&self) -> Option<u128> {
+        match self {
+            Self::Uint(word) if word[..16].iter().all(|byte| *byte == 0) => Some(u128::from_be_bytes(word[16..].try_into().unwrap())),
+            _ => None,
+        }
+    }
+
+    /// An `Int` that fits in an `i128`.
+    pub fn as_i128(&self) -> Option<i128> {
+        match self {
+            Self::Int(word) => {
+                let value = i128::from_be_bytes(word[16..].try_into().unwrap());
+                let fill = if value < 0 { 0xff } else { 0 };
+                word[..16].iter().all(|byte| *byte == fill).then_some(value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_address(&self) -> Option<&str> {
+        match self {
+            Self::Address(address) => Some(address),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The bytes of a `Bytes` or `FixedBytes`.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) | Self::FixedBytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[AbiValue]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// A function signature: `name(inputs)`, optionally followed by `(outputs)`.
+struct Signature {
+    name: String,
+    inputs: Vec<AbiType>,
+    outputs: Option<Vec<AbiType>>,
+}
+
+impl Signature {
+    /// Parameter names are allowed and ignored: `balanceOf(address owner)` is `balanceOf(address)`.
+    fn parse(signature: &str) -> Result<Self> {
+        let malformed = || invalid(format!("malformed signature {signature:?}"));
+        let (name, rest) = signature.trim().split_once('(').ok_or_else(malformed)?;
+        let (inputs, rest) = rest.split_once(')').ok_or_else(malformed)?;
+        let outputs = match rest.trim() {
+            "" => None,
+            rest => Some(rest.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')).ok_or_else(malformed)?),
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$') {
+            return Err(malformed());
+        }
+        Ok(Self { name: name.to_string(), inputs: parse_types(inputs)?, outputs: outputs.map(parse_types).transpose()? })
+    }
+
+    fn selector(&self) -> [u8; 4] {
+        let types: Vec<String> = self.inputs.iter().map(AbiType::to_string).collect();
+        let hash = Keccak256::digest(format!("{}({})", self.name, types.join(",")).as_bytes());
+        hash[..4].try_into().unwrap()
+    }
+}
+
+fn parse_types(list: &str) -> Result<Vec<AbiType>> {
+    if list.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    list.split(',').map(|item| AbiType::parse(item.split_whitespace().next().unwrap_or_default())).collect()
+}
+
+/// The 4-byte selector of `signature`, from its canonical form.
+pub fn selector(signature: &str) -> Result<[u8; 4]> {
+    Ok(Signature::parse(signature)?.selector())
+}
+
+/// Builds the call data of one function call.
+#[derive(Debug, Clone)]
+pub struct CallDataBuilder {
+    signature: String,
+    args: Vec<AbiValue>,
+}
+
+impl CallDataBuilder {
+    /// A call to `signature`, e.g. `transfer(address,uint256)`.
+    pub fn function(signature: impl Into<String>) -> Self {
+        Self { signature: signature.into(), args: Vec::new() }
+    }
+
+    pub fn arg(mut self, value: AbiValue) -> Self {
+        self.args.push(value);
+        self
+    }
+
+    pub fn arg_address(self, address: &str) -> Self {
+        self.arg(AbiValue::Address(address.to_ascii_lowercase()))
+    }
+
+    pub fn arg_uint(self, value: u128) -> Self {
+        self.arg(AbiValue::uint(value))
+    }
+
+    pub fn arg_int(self, value: i128) -> Self {
+        self.arg(AbiValue::int(value))
+    }
+
+    pub fn arg_bool(self, value: bool) -> Self {
+        self.arg(AbiValue::Bool(value))
+    }
+
+    pub fn arg_fixed_bytes(self, bytes: &[u8]) -> Self {
+        self.arg(AbiValue::FixedBytes(bytes.to_vec()))
+    }
+
+    pub fn arg_bytes(self, bytes: &[u8]) -> Self {
+        self.arg(AbiValue::Bytes(bytes.to_vec()))
+    }
+
+    pub fn arg_string(self, value: &str) -> Self {
+        self.arg(AbiValue::String(value.to_string()))
+    }
+
+    pub fn arg_array(self, values: Vec<AbiValue>) -> Self {
+        self.arg(AbiValue::Array(values))
+    }
+
+    /// The `0x`-prefixed call data, failing with `InvalidAbi` when the signature can't be parsed
+    /// or the arguments don't match it.
+    pub fn try_build(self) -> Result<String> {
+        let signature = Signature::parse(&self.signature)?;
+        let encoded = encode(&signature.inputs, &self.args)?;
+        Ok(format!("0x{}{}", hex(&signature.selector()), hex(&encoded)))
+    }
+
+    /// Like `try_build`.
+    ///
+    /// # Panics
+    ///
+    /// When `try_build` would fail.
+    pub fn build(self) -> String {
+        self.try_build().unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// `values` ABI-encoded as `types`, without a selector.
+pub fn encode(types: &[AbiType], values: &[AbiValue]) -> Result<Vec<u8>> {
+    if types.len() != values.len() {
+        return Err(invalid(format!("expected {} arguments, got {}", types.len(), values.len())));
+    }
+    let mut head = Vec::with_capacity(32 * types.len());
+    let mut tail = Vec::new();
+    for (ty, value) in types.iter().zip(values) {
+        if ty.is_dynamic() {
+            head.extend_from_slice(&usize_word(32 * types.len() + tail.len()));
+            tail.extend(encode_dynamic(ty, value)?);
+        } else {
+            head.extend_from_slice(&encode_static(ty, value)?);
+        }
+    }
+    head.extend(tail);
+    Ok(head)
+}
+
+pub(crate) fn usize_word(value: usize) -> Word {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+/// `bytes` followed by zeroes up to a whole number of words.
+fn padded(bytes: &[u8]) -> Vec<u8> {
+    let mut padded = bytes.to_vec();
+    padded.resize(bytes.len().div_ceil(32) * 32, 0);
+    padded
+}
+
+fn mismatch(ty: &AbiType, value: &AbiValue) -> RpcHandlerError {
+    invalid(format!("expected {ty}, got {value:?}"))
+}
+
+/// Whether `word` holds a value that fits in `bits`, sign-extended when `signed`.
+fn fits(word: &Word, bits: usize, signed: bool) -> bool {
+    let unused = 32 - bits / 8;
+    let fill = if signed && word[unused.min(31)] & 0x80 != 0 { 0xff } else { 0 };
+    bits == 256 || word[..unused].iter().all(|byte| *byte == fill)
+}
+
+fn encode_static(ty: &AbiType, value: &AbiValue) -> Result<Word> {
+    let mut word = [0u8; 32];
+    match (ty, value) {
+        (AbiType::Address, AbiValue::Address(address)) => {
+            let bytes = unhex(address).filter(|bytes| bytes.len() == 20).ok_or_else(|| invalid(format!("invalid address {address:?}")))?;
+            word[12..].copy_from_slice(&bytes);
+        }
+        (AbiType::Uint(bits), AbiValue::Uint(value)) | (AbiType::Int(bits), AbiValue::Int(value)) => {
+            if !fits(value, *bits, matches!(ty, AbiType::Int(_))) {
+                return Err(invalid(format!("{value:?} doesn't fit in {ty}")));
+            }
+            word = *value;
+        }
+        (AbiType::Bool, AbiValue::Bool(value)) => word[31] = u8::from(*value),
+        (AbiType::FixedBytes(len), AbiValue::FixedBytes(bytes)) if bytes.len() == *len => word[..*len].copy_from_slice(bytes),
+        _ => return Err(mismatch(ty, value)),
+    }
+    Ok(word)
+}
+
+fn encode_dynamic(ty: &AbiType, value: &AbiValue) -> Result<Vec<u8>> {
+    let (len, body) = match (ty, value) {
+        (AbiType::Bytes, AbiValue::Bytes(bytes)) => (bytes.len(), padded(bytes)),
+        (AbiType::String, AbiValue::String(value)) => (value.len(), padded(value.as_bytes())),
+        (AbiType::Array(element), AbiValue::Array(values)) => (values.len(), encode(&vec![element.as_ref().clone(); values.len()], values)?),
+        _ => return Err(mismatch(ty, value)),
+    };
+    let mut encoded = usize_word(len).to_vec();
+    encoded.extend(body);
+    Ok(encoded)
+}
+
+/// Decode return data `hex` as `types`, e.g. `&["uint256"]` for `balanceOf`.
+pub fn decode_return(types: &[&str], hex: &str) -> Result<Vec<AbiValue>> {
+    let types = types.iter().map(|name| AbiType::parse(name)).collect::<Result<Vec<_>>>()?;
+    decode(&types, hex)
+}
+
+fn decode(types: &[AbiType], hex: &str) -> Result<Vec<AbiValue>> {
+    let data = unhex(hex).ok_or_else(|| invalid(format!("return data isn't hex: {hex:?}")))?;
+    decode_tuple(types, &data)
+}
+
+fn truncated() -> RpcHandlerError {
+    invalid("return data is shorter than its types need")
+}
+
+pub(crate) fn word_at(data: &[u8], at: usize) -> Result<Word> {
+    Ok(data.get(at..at.checked_add(32).ok_or_else(truncated)?).ok_or_else(truncated)?.try_into().unwrap())
+}
+
+pub(crate) fn word_usize(word: &Word) -> Result<usize> {
+    if word[..24].iter().any(|byte| *byte != 0) {
+        return Err(invalid("offset or length out of range"));
+    }
+    usize::try_from(u64::from_be_bytes(word[24..].try_into().unwrap())).map_err(|_| invalid("offset or length out of range"))
+}
+
+/// Decode a head-and-tail encoding starting at the beginning of `data`.
+pub(crate) fn decode_tuple(types: &[AbiType], data: &[u8]) -> Result<Vec<AbiValue>> {
+    types
+        .iter()
+        .enumerate()
+        .map(|(index, ty)| {
+            let head = word_at(data, 32 * index)?;
+            if ty.is_dynamic() {
+                decode_dynamic(ty, data.get(word_usize(&head)?..).ok_or_else(truncated)?)
+            } else {
+                decode_static(ty, &head)
+            }
+        })
+        .collect()
+}
+
+fn decode_static(ty: &AbiType, word: &Word) -> Result<AbiValue> {
+    let unexpected = || invalid(format!("0x{} isn't a valid {ty}", hex(word)));
+    match ty {
+        AbiType::Address if word[..12].iter().all(|byte| *byte == 0) => Ok(AbiValue::Address(format!("0x{}", hex(&word[12..])))),
+        AbiType::Uint(bits) if fits(word, *bits, false) => Ok(AbiValue::Uint(*word)),
+        AbiType::Int(bits) if fits(word, *bits, true) => Ok(AbiValue::Int(*word)),
+        AbiType::Bool if word[..31].iter().all(|byte| *byte == 0) && word[31] <= 1 => Ok(AbiValue::Bool(word[31] == 1)),
+        AbiType::FixedBytes(len) => Ok(AbiValue::FixedBytes(word[..*len].to_vec())),
+        _ => Err(unexpected()),
+    }
+}
+
+fn decode_dynamic(ty: &AbiType, data: &[u8]) -> Result<AbiValue> {
+    let len = word_usize(&word_at(data, 0)?)?;
+    let body = &data[32..];
+    match ty {
+        AbiType::Bytes | AbiType::String => {
+            let bytes = body.get(..len).ok_or_else(truncated)?.to_vec();
+            match ty {
+                AbiType::Bytes => Ok(AbiValue::Bytes(bytes)),
+                _ => String::from_utf8(bytes).map(AbiValue::String).map_err(|_| invalid("string isn't UTF-8")),
+            }
+        }
+        AbiType::Array(element) => {
+            // Every element takes at least a head word, which bounds what a bogus length can allocate
+            if len > body.len() / 32 {
+                return Err(truncated());
+            }
+            Ok(AbiValue::Array(decode_tuple(&vec![element.as_ref().clone(); len], body)?))
+        }
+        _ => unreachable!("only dynamic types have a tail"),
+    }
+}
+
+impl RpcCalls {
+    /// Call the view function `signature` on `to` at `block_id`, through the retry path, and
+    /// decode what it returns.
+    ///
+    /// `signature` lists the return types after the parameters, the way `cast call` takes
+    /// them: `balanceOf(address)(uint256)`. Fails with `InvalidAbi` without them.
+    pub async fn view_call(&self, to: &str, signature: &str, args: Vec<AbiValue>, block_id: &str) -> Result<Vec<AbiValue>> {
+        let parsed = Signature::parse(signature)?;
+        let outputs = parsed.outputs.as_ref().ok_or_else(|| invalid(format!("{signature:?} has no return types")))?;
+        let data = format!("0x{}{}", hex(&parsed.selector()), hex(&encode(&parsed.inputs, &args)?));
+        let request = JsonRpcRequest::new("eth_call", json!([{ "to": to, "data": data }, block_id]));
+        match self.try_rpc_call(&request).await?.into_result()? {
+            Value::String(result) => decode(outputs, &result),
+            other => Err(RpcHandlerError::SerializationError(format!("eth_call returned {other}"))),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Agreement sampling: ongoing checks that each endpoint serves the same chain as its peers.
+//!
+//! Under `HandlerSettings::agreement_sampling`, a background loop picks a random block a few
+//! blocks behind the head every `interval` and asks a few randomly chosen endpoints for its hash.
+//! Each endpoint that answered is recorded as agreeing or disagreeing with the majority, and one
+//! that keeps disagreeing is flagged `SuspectedDishonest`: consensus calls stop counting its
+//! vote, and with `exclude_from_reads` proxied reads leave it out too.
+//!
+//! A round costs one request per sampled endpoint, plus an `eth_blockNumber` while the proxy
+//! hasn't returned a head yet. Those requests go through the host limits and spend budgets like
+//! any other; an endpoint whose host is at its cap or whose budget is spent sits the round out.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{Arc, Weak},
+};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::AgreementSamplingConfig, events::HandlerEvent, namespaces::parse_quantity, provider::TrafficClass, readiness::Heartbeat, runtime::{self, JoinHandle}, JsonRpcRequest, RpcHandler,
+};
+
+/// How an endpoint's recent samples went.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AgreementStats {
+    /// Samples the rate is taken over, at most `window`
+    pub samples: usize,
+    /// Samples in which it returned the majority hash
+    pub agreements: usize,
+    /// `agreements` over `samples`
+    pub agreement_rate: f64,
+    pub suspected_dishonest: bool,
+}
+
+/// What one sampling round found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AgreementRound {
+    pub block: u64,
+    /// The hash each sampled endpoint returned; endpoints that failed or sat out are absent
+    pub hashes: BTreeMap<String, String>,
+    /// The hash a strict majority of `hashes` returned. Without one the round records nothing.
+    pub majority: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    /// Whether each recent sample agreed with the majority, newest last
+    recent: VecDeque<bool>,
+    flagged: bool,
+}
+
+impl History {
+    fn stats(&self) -> AgreementStats {
+        let samples = self.recent.len();
+        let agreements = self.recent.iter().filter(|agreed| **agreed).count();
+        AgreementStats {
+            samples,
+            agreements,
+            agreement_rate: if samples == 0 { 1.0 } else { agreements as f64 / samples as f64 },
+            suspected_dishonest: self.flagged,
+        }
+    }
+}
+
+/// Recent samples per endpoint. Cloning shares them.
+#[derive(Debug, Clone, Default)]
+pub struct AgreementTracker {
+    endpoints: Arc<parking_lot::Mutex<HashMap<String, History>>>,
+}
+
+impl AgreementTracker {
+    /// Record whether `url` returned the majority hash. Returns its stats when this sample set
+    /// or cleared its flag.
+    pub(crate) fn record(&self, config: &AgreementSamplingConfig, url: &str, agreed: bool) -> Option<AgreementStats> {
+        let mut endpoints = self.endpoints.lock();
+        let history = endpoints.entry(url.to_string()).or_default();
+        history.recent.push_back(agreed);
+        while history.recent.len() > config.window {
+            history.recent.pop_front();
+        }
+        let samples = history.recent.len();
+        let disagreements = history.recent.iter().filter(|agreed| !**agreed).count();
+        let flagged = samples >= config.min_samples && disagreements as f64 > config.max_disagreement_rate * samples as f64;
+        (flagged != std::mem::replace(&mut history.flagged, flagged)).then(|| history.stats())
+    }
+
+    /// Stats of every endpoint sampled so far.
+    pub fn stats(&self) -> HashMap<String, AgreementStats> {
+        self.endpoints.lock().iter().map(|(url, history)| (url.clone(), history.stats())).collect()
+    }
+
+    /// Endpoints flagged `SuspectedDishonest`.
+    pub(crate) fn flagged(&self) -> HashSet<String> {
+        self.endpoints.lock().iter().filter(|(_, history)| history.flagged).map(|(url, _)| url.clone()).collect()
+    }
+
+    /// Forget endpoints outside `known`.
+    pub(crate) fn retain(&self, known: &HashSet<String>) {
+        self.endpoints.lock().retain(|url, _| known.contains(url));
+    }
+}
+
+/// The hash more than half of `hashes` share, if at least two endpoints answered.
+fn majority_hash(hashes: &BTreeMap<String, String>) -> Option<String> {
+    if hashes.len() < 2 {
+        return None;
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for hash in hashes.values() {
+        *counts.entry(hash).or_default() += 1;
+    }
+    counts.into_iter().find(|(_, count)| count * 2 > hashes.len()).map(|(hash, _)| hash.to_string())
+}
+
+impl RpcHandler {
+    /// Run one agreement sampling round now, as the background loop does.
+    ///
+    /// Returns `None`, recording nothing, when sampling is off, fewer than two endpoints are
+    /// available, the head is unknown or the chain is shorter than the sampling depth.
+    pub async fn sample_agreement(&self) -> Option<AgreementRound> {
+        let config = self.config().settings.agreement_sampling?;
+        let mut urls: Vec<String> = self
+            .sweep_targets()
+            .into_iter()
+            .map(|rpc| rpc.url.to_string())
+            .filter(|url| !url.starts_with("wss://"))
+            .collect();
+        urls.shuffle(&mut rand::thread_rng());
+        urls.truncate(config.sample_size);
+        if urls.len() < 2 {
+            return None;
+        }
+        let client = self.http_client().ok()?;
+
+        let head = match self.head_watermark() {
+            Some(head) => head,
+            None => {
+                let request = JsonRpcRequest::new("eth_blockNumber", json!([]));
+                parse_quantity(&self.side_call(&client, &urls[0], &request, TrafficClass::Probe).await?)?
+            }
+        };
+        let depth = rand::thread_rng().gen_range(config.min_depth..=config.max_depth);
+        let block = head.checked_sub(depth)?;
+
+        let request = JsonRpcRequest::new("eth_getBlockByNumber", json!([format!("{block:#x}"), false]));
+        let answers = futures::future::join_all(urls.iter().map(|url| self.side_call(&client, url, &request, TrafficClass::Probe))).await;
+        let hashes: BTreeMap<String, String> = urls
+            .into_iter()
+            .zip(answers)
+            .filter_map(|(url, answer)| Some((url, answer?.get("hash")?.as_str()?.to_lowercase())))
+            .collect();
+        let majority = majority_hash(&hashes);
+
+        if let Some(majority) = &majority {
+            for (url, hash) in &hashes {
+                match self.agreement().record(&config, url, hash == majority) {
+                    Some(AgreementStats { samples, agreements, suspected_dishonest: true, .. }) => {
+                        tracing::warn!(url = %self.redact(url), block, samples, agreements, "Endpoint keeps disagreeing with its peers");
+                        self.emit(HandlerEvent::SuspectedDishonest { url: url.clone(), samples, agreements });
+                    }
+                    Some(_) => tracing::info!(url = %self.redact(url), "Endpoint agrees with its peers again"),
+                    None => {}
+                }
+            }
+        }
+        Some(AgreementRound { block, hashes, majority })
+    }
+
+    /// Agreement stats of every endpoint sampled so far.
+    pub fn agreement_stats(&self) -> HashMap<String, AgreementStats> {
+        self.agreement().stats()
+    }
+}
+
+
+/// Spawn the agreement sampling loop for `handler`. It holds only a weak reference, so it ends
+/// on its own once the handler is dropped, or immediately when `shutdown` is cancelled.
+pub(crate) fn spawn_agreement_sampler(
+    handler: &Arc<RpcHandler>,
+    config: AgreementSamplingConfig,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+    let clock = Arc::clone(handler.clock());
+
+    runtime::spawn(async move {
+        loop {
+            heartbeat.beat();
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = clock.sleep(config.interval) => {}
+            }
+            let Some(handler) = weak.upgrade() else { return };
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = handler.sample_agreement() => {}
+            }
+        }
+    })
+}
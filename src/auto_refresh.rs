@@ -0,0 +1,165 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use sha3::{Digest, Keccak256};
+use tokio_util::sync::CancellationToken;
+use web_time::Instant;
+
+use crate::{
+    clock::Clock,
+    config::{AutoRefreshConfig, SmearingConfig},
+    readiness::Heartbeat,
+    runtime::{self, JoinHandle},
+    Rpc, RpcHandler,
+};
+
+/// How long to wait before retrying a tick deferred for load: `tick` plus up to half again,
+/// scaled by `jitter` in `[0, 1)`, so deferred sweeps of several handlers don't line up.
+pub fn deferral_delay(tick: Duration, jitter: f64) -> Duration {
+    tick + tick.mul_f64(jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// When each of `slots` slots of a smeared sweep goes out, counted from the start of its
+/// interval.
+///
+/// The slots split `window` evenly, and the window sits at `phase` of the room the interval
+/// leaves around it. Each slot goes out `jitter()` of the way into its share, so the offsets
+/// rise strictly and no two slots share a moment.
+pub fn smeared_offsets(slots: usize, interval: Duration, window: Duration, phase: f64, mut jitter: impl FnMut() -> f64) -> Vec<Duration> {
+    let window = window.min(interval);
+    let start = (interval - window).mul_f64(phase.clamp(0.0, 1.0));
+    let width = window / slots.max(1) as u32;
+    (0..slots).map(|slot| start + width * slot as u32 + width.mul_f64(jitter().clamp(0.0, 0.999))).collect()
+}
+
+/// Where in the interval the sweeps of the instance seeded `seed` fall, in `[0, 1)`.
+pub fn instance_phase(seed: u64) -> f64 {
+    let digest = Keccak256::digest(seed.to_be_bytes());
+    (u64::from_be_bytes(digest[..8].try_into().expect("eight bytes")) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A seed that stays the same across restarts on one host and differs between hosts: from the
+/// `HOSTNAME` variable or `/etc/hostname`, the process id when neither is there.
+pub fn host_instance_seed() -> u64 {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| std::process::id().to_string());
+    u64::from_be_bytes(Keccak256::digest(host.as_bytes())[..8].try_into().expect("eight bytes"))
+}
+
+/// Spawn the auto-refresh loop for `handler`. Like the keepalive loop it holds only a weak
+/// reference, and ends once the handler is dropped or `shutdown` is cancelled.
+///
+/// The first sweep starts `interval` after spawning, since `init` has just probed everything.
+pub(crate) fn spawn_auto_refresh(
+    handler: &Arc<RpcHandler>,
+    config: AutoRefreshConfig,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+    let clock = Arc::clone(handler.clock());
+
+    if let Some(smearing) = config.smearing {
+        return runtime::spawn(run_smeared(weak, clock, config, smearing, heartbeat, shutdown));
+    }
+    runtime::spawn(async move {
+        let mut pending: VecDeque<Rpc> = VecDeque::new();
+        let mut delay = config.interval;
+
+        loop {
+            heartbeat.beat();
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = clock.sleep(delay) => {}
+            }
+            let Some(handler) = weak.upgrade() else { return };
+
+            if handler.requests_in_flight() >= config.busy_in_flight {
+                handler.record_refresh_deferral();
+                delay = deferral_delay(config.tick, rand::random());
+                continue;
+            }
+
+            if pending.is_empty() {
+                pending = handler.sweep_targets().into();
+            }
+            let batch: Vec<Rpc> = pending.drain(..config.endpoints_per_tick.min(pending.len())).collect();
+            handler.probe_partial(&batch).await;
+
+            delay = if pending.is_empty() {
+                handler.finish_incremental_sweep().await;
+                config.interval
+            } else {
+                config.tick
+            };
+        }
+    })
+}
+
+/// The smeared loop: a sweep every `interval`, its slots at `smeared_offsets`. A slot that finds
+/// the handler busy is deferred like a tick, and the slots after it wait their turn.
+async fn run_smeared(
+    weak: Weak<RpcHandler>,
+    clock: Arc<dyn Clock>,
+    config: AutoRefreshConfig,
+    smearing: SmearingConfig,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) {
+    let phase = instance_phase(smearing.instance_seed);
+    let mut sweep_start = clock.now_instant() + config.interval;
+
+    loop {
+        if !pause_until(&*clock, sweep_start, &heartbeat, &shutdown).await {
+            return;
+        }
+        let mut targets = match weak.upgrade() {
+            Some(handler) => handler.sweep_targets(),
+            None => return,
+        };
+        // Instances lead with different endpoints, so none gets every fleet's first probe
+        if !targets.is_empty() {
+            let lead = (smearing.instance_seed % targets.len() as u64) as usize;
+            targets.rotate_left(lead);
+        }
+        let slots: Vec<&[Rpc]> = targets.chunks(config.endpoints_per_tick).collect();
+        let offsets = smeared_offsets(slots.len(), config.interval, smearing.window, phase, rand::random::<f64>);
+
+        for (slot, offset) in slots.into_iter().zip(offsets) {
+            let mut at = sweep_start + offset;
+            let handler = loop {
+                if !pause_until(&*clock, at, &heartbeat, &shutdown).await {
+                    return;
+                }
+                let Some(handler) = weak.upgrade() else { return };
+                if handler.requests_in_flight() < config.busy_in_flight {
+                    break handler;
+                }
+                handler.record_refresh_deferral();
+                at = clock.now_instant() + deferral_delay(config.tick, rand::random());
+            };
+            handler.probe_partial(slot).await;
+        }
+        let Some(handler) = weak.upgrade() else { return };
+        handler.finish_incremental_sweep().await;
+
+        // A sweep deferred past its interval pushes the next one back rather than bunching up
+        sweep_start = (sweep_start + config.interval).max(clock.now_instant());
+    }
+}
+
+/// Sleep on `clock` until `at`, beating first. `false` if `shutdown` came first.
+async fn pause_until(clock: &dyn Clock, at: Instant, heartbeat: &Heartbeat, shutdown: &CancellationToken) -> bool {
+    heartbeat.beat();
+    tokio::select! {
+        _ = shutdown.cancelled() => false,
+        _ = clock.sleep(at.saturating_duration_since(clock.now_instant())) => true,
+    }
+}
@@ -0,0 +1,630 @@
+//! Historical backfill over a block range, spread across the healthy endpoints.
+//!
+//! `BackfillRunner` splits the range into fixed-size partitions and works on `concurrency` of
+//! them at once. Each partition goes to the live endpoint with the fewest partitions under way,
+//! so the load spreads over every healthy endpoint instead of piling onto the fastest one, and
+//! every call for a block is pinned to that endpoint. A failing block is retried with backoff,
+//! moving to another endpoint when there is one. An endpoint that fails `ENDPOINT_FAILURE_LIMIT`
+//! attempts in a row without answering is dropped for the rest of the run, unless it is the last
+//! one left. Blocks that fail every retry are reported at the end; they don't stop the run.
+//!
+//! Each finished partition is recorded in a `CheckpointStore`. A runner recreated over the same
+//! store and run id skips the partitions already done. Results reach the sink as blocks finish,
+//! not in block order.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt, io,
+    ops::RangeInclusive,
+    sync::Arc,
+    time::Duration,
+};
+#[cfg(feature = "persistence")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use futures::{future::BoxFuture, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use web_time::Instant;
+
+use crate::{
+    calls::RpcCalls, clock::Clock, error::kb::ErrorCondition, metrics::FailureClass, provider::CallOptions, receipts::RECEIPT_FETCH_CONCURRENCY, JsonRpcError,
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// Blocks per partition when `BackfillOptions::partition_size` isn't changed.
+pub const DEFAULT_PARTITION_SIZE: u64 = 100;
+
+/// Attempts in a row an endpoint may leave unanswered before the run stops sending to it.
+pub const ENDPOINT_FAILURE_LIMIT: u32 = 3;
+
+/// Longest wait between retries of one block.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// A block task: the work done for each block, returning what goes to the sink.
+pub type BlockTaskFn = Arc<dyn Fn(BlockContext) -> BoxFuture<'static, Result<Value>> + Send + Sync>;
+
+/// What to do with each block in the range.
+#[derive(Clone)]
+pub enum BackfillTask {
+    /// `eth_getBlockByNumber` with full transactions
+    FetchBlock,
+    /// The block with full transactions and its receipts, as `{ "block": …, "receipts": […] }`.
+    /// Falls back to per-transaction receipts on endpoints without `eth_getBlockReceipts`.
+    FetchBlockWithReceipts,
+    Custom(BlockTaskFn),
+}
+
+impl BackfillTask {
+    /// A task running `task` for each block.
+    pub fn custom<F, Fut>(task: F) -> Self
+    where
+        F: Fn(BlockContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value>> + Send + 'static,
+    {
+        Self::Custom(Arc::new(move |context| Box::pin(task(context))))
+    }
+
+    async fn run(&self, context: BlockContext) -> Result<Value> {
+        match self {
+            Self::FetchBlock => context.block().await,
+            Self::FetchBlockWithReceipts => {
+                let block = context.block().await?;
+                let receipts = context.receipts(&block).await?;
+                Ok(json!({ "block": block, "receipts": receipts }))
+            }
+            Self::Custom(task) => task(context).await,
+        }
+    }
+}
+
+impl fmt::Debug for BackfillTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FetchBlock => f.write_str("FetchBlock"),
+            Self::FetchBlockWithReceipts => f.write_str("FetchBlockWithReceipts"),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// One attempt at one block, pinned to one endpoint.
+#[derive(Clone)]
+pub struct BlockContext {
+    pub calls: Arc<RpcCalls>,
+    pub number: u64,
+    /// The endpoint this attempt is pinned to
+    pub url: String,
+    /// Keeps a call on `url`, with the runner doing the retrying
+    pub options: CallOptions,
+    shared: Arc<Shared>,
+}
+
+impl BlockContext {
+    /// Call `method` on this attempt's endpoint, within the run's rate limit, and unwrap its result.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        self.shared.pace(&self.url).await;
+        *self.shared.requests.lock().entry(self.url.clone()).or_default() += 1;
+        let request = JsonRpcRequest::new(method, params);
+        let (response, _) = self.calls.handler.try_proxy_request_with(request, self.options.clone()).await?;
+        response.into_result()
+    }
+
+    async fn block(&self) -> Result<Value> {
+        let block = self.call("eth_getBlockByNumber", json!([format!("{:#x}", self.number), true])).await?;
+        if block.is_null() {
+            return Err(RpcHandlerError::MalformedResponse { url: self.url.clone(), violation: format!("no block {}", self.number) });
+        }
+        Ok(block)
+    }
+
+    async fn receipts(&self, block: &Value) -> Result<Value> {
+        match self.call("eth_getBlockReceipts", json!([format!("{:#x}", self.number)])).await {
+            Err(RpcHandlerError::JsonRpcCode { code, message })
+                if self.calls.handler.classify_error(&JsonRpcError { code, message: message.clone(), data: None }, &self.url) == ErrorCondition::MethodNotFound => {}
+            result => return result,
+        }
+        let hashes: Vec<String> = block
+            .get("transactions")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|tx| tx.get("hash").or(Some(tx)).and_then(Value::as_str).map(str::to_string))
+            .collect();
+        let receipts: Vec<Value> = futures::stream::iter(hashes)
+            .map(|hash| async move { self.call("eth_getTransactionReceipt", json!([hash])).await })
+            .buffered(RECEIPT_FETCH_CONCURRENCY)
+            .try_collect()
+            .await?;
+        Ok(Value::Array(receipts))
+    }
+}
+
+/// Receives each finished block's result.
+pub trait BlockSink: Send + Sync {
+    fn block(&self, number: u64, result: Value);
+}
+
+impl<F: Fn(u64, Value) + Send + Sync> BlockSink for F {
+    fn block(&self, number: u64, result: Value) {
+        self(number, result)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackfillOptions {
+    /// Partitions worked on at once
+    pub concurrency: usize,
+    /// Blocks per partition, the unit progress is checkpointed in
+    pub partition_size: u64,
+    /// Requests per second sent to each endpoint at most, unlimited when `None`
+    pub rate_limit: Option<f64>,
+    /// Per-endpoint replacements for `rate_limit`, by URL
+    pub endpoint_rate_limits: HashMap<String, f64>,
+    /// Retries of a failing block before it is reported as failed
+    pub max_retries: u32,
+    /// Wait before a block's first retry, doubling for each one after
+    pub retry_backoff: Duration,
+    /// Key progress is checkpointed under, `"<first>-<last>"` of the range when `None`
+    pub run_id: Option<String>,
+}
+
+impl Default for BackfillOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            partition_size: DEFAULT_PARTITION_SIZE,
+            rate_limit: None,
+            endpoint_rate_limits: HashMap::new(),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(500),
+            run_id: None,
+        }
+    }
+}
+
+/// How far a run has got, from `BackfillRunner::progress`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BackfillProgress {
+    pub total_blocks: u64,
+    /// Blocks done, including those a resumed checkpoint had done
+    pub completed_blocks: u64,
+    pub failed_blocks: u64,
+    /// Blocks finished per second by this run
+    pub blocks_per_second: f64,
+    /// Time left at the current rate, `None` until the first block finishes
+    pub eta: Option<Duration>,
+    pub finished: bool,
+}
+
+/// How a run ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackfillReport {
+    pub run_id: String,
+    /// Blocks this run completed
+    pub completed: u64,
+    /// Blocks skipped because a checkpoint had them done
+    pub resumed: u64,
+    /// Blocks that failed every retry, in this run or a resumed one, with the last error
+    pub failed: BTreeMap<u64, String>,
+    /// Requests this run sent to each endpoint
+    pub requests_by_endpoint: BTreeMap<String, u64>,
+    /// Endpoints the run stopped sending to after repeated failures
+    pub dropped_endpoints: Vec<String>,
+    /// `stop` was called before the whole range was done
+    pub stopped: bool,
+}
+
+/// A run's progress, as kept by a `CheckpointStore`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackfillCheckpoint {
+    pub first_block: u64,
+    pub last_block: u64,
+    pub partition_size: u64,
+    /// First block of each finished partition
+    pub done: BTreeSet<u64>,
+    /// Blocks in finished partitions that failed every retry, with the last error
+    pub failed: BTreeMap<u64, String>,
+}
+
+impl BackfillCheckpoint {
+    fn new(blocks: &RangeInclusive<u64>, partition_size: u64) -> Self {
+        Self { first_block: *blocks.start(), last_block: *blocks.end(), partition_size, done: BTreeSet::new(), failed: BTreeMap::new() }
+    }
+
+    fn matches(&self, blocks: &RangeInclusive<u64>, partition_size: u64) -> bool {
+        (self.first_block, self.last_block, self.partition_size) == (*blocks.start(), *blocks.end(), partition_size)
+    }
+}
+
+/// Store of backfill progress by run id.
+pub trait CheckpointStore: Send + Sync {
+    fn load(&self, run_id: &str) -> Option<BackfillCheckpoint>;
+
+    /// Store `checkpoint`, replacing the one for `run_id`.
+    fn save(&self, run_id: &str, checkpoint: &BackfillCheckpoint) -> io::Result<()>;
+}
+
+/// Checkpoints held in memory, lost on restart. The default for `BackfillRunner`.
+#[derive(Debug, Default)]
+pub struct MemoryCheckpointStore {
+    checkpoints: parking_lot::Mutex<HashMap<String, BackfillCheckpoint>>,
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+    fn load(&self, run_id: &str) -> Option<BackfillCheckpoint> {
+        self.checkpoints.lock().get(run_id).cloned()
+    }
+
+    fn save(&self, run_id: &str, checkpoint: &BackfillCheckpoint) -> io::Result<()> {
+        self.checkpoints.lock().insert(run_id.to_string(), checkpoint.clone());
+        Ok(())
+    }
+}
+
+/// Checkpoints kept in a JSON file, so a backfill survives a restart.
+///
+/// The file is read once on `open` and rewritten on every `save`, through a temporary file
+/// renamed into place so a crash mid-write leaves the previous version.
+#[cfg(feature = "persistence")]
+#[derive(Debug)]
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    checkpoints: parking_lot::Mutex<HashMap<String, BackfillCheckpoint>>,
+}
+
+#[cfg(feature = "persistence")]
+impl FileCheckpointStore {
+    /// Open the store at `path`, starting empty if the file doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        crate::runtime::require_filesystem("FileCheckpointStore")?;
+        let path = path.as_ref().to_path_buf();
+        let checkpoints = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, checkpoints: parking_lot::Mutex::new(checkpoints) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl CheckpointStore for FileCheckpointStore {
+    fn load(&self, run_id: &str) -> Option<BackfillCheckpoint> {
+        self.checkpoints.lock().get(run_id).cloned()
+    }
+
+    fn save(&self, run_id: &str, checkpoint: &BackfillCheckpoint) -> io::Result<()> {
+        let mut checkpoints = self.checkpoints.lock();
+        checkpoints.insert(run_id.to_string(), checkpoint.clone());
+
+        let bytes = serde_json::to_vec(&*checkpoints).map_err(io::Error::other)?;
+        let staging = self.path.with_extension("tmp");
+        fs::write(&staging, bytes)?;
+        fs::rename(&staging, &self.path)
+    }
+}
+
+/// How an endpoint is faring in a run.
+#[derive(Debug, Default)]
+struct EndpointLoad {
+    /// Partitions currently leased to it
+    active: usize,
+    leased: u64,
+    failures_in_a_row: u32,
+    dropped: bool,
+}
+
+/// State shared by a run's workers and the contexts they hand out.
+struct Shared {
+    clock: Arc<dyn Clock>,
+    rate_limit: Option<f64>,
+    endpoint_rate_limits: HashMap<String, f64>,
+    /// Earliest time each rate-limited endpoint may be sent the next request
+    next_slot: parking_lot::Mutex<HashMap<String, Instant>>,
+    requests: parking_lot::Mutex<BTreeMap<String, u64>>,
+}
+
+impl Shared {
+    async fn pace(&self, url: &str) {
+        let Some(rate) = self.endpoint_rate_limits.get(url).copied().or(self.rate_limit).filter(|rate| *rate > 0.0) else {
+            return;
+        };
+        let interval = Duration::from_secs_f64(1.0 / rate);
+        let now = self.clock.now_instant();
+        let slot = {
+            let mut next_slot = self.next_slot.lock();
+            let slot = next_slot.get(url).copied().filter(|slot| *slot > now).unwrap_or(now);
+            next_slot.insert(url.to_string(), slot + interval);
+            slot
+        };
+        if slot > now {
+            self.clock.sleep(slot - now).await;
+        }
+    }
+}
+
+/// Works through a block range with a `BackfillTask`, see the module docs.
+pub struct BackfillRunner {
+    calls: Arc<RpcCalls>,
+    blocks: RangeInclusive<u64>,
+    task: BackfillTask,
+    options: BackfillOptions,
+    store: Arc<dyn CheckpointStore>,
+    progress: watch::Sender<BackfillProgress>,
+    stop: CancellationToken,
+}
+
+impl BackfillRunner {
+    /// A runner checkpointing in memory, so only a runner over the same `RpcCalls`'s lifetime
+    /// can resume it. Fails on an empty range or a zero concurrency or partition size.
+    pub fn new(calls: Arc<RpcCalls>, blocks: RangeInclusive<u64>, task: BackfillTask, options: BackfillOptions) -> Result<Self> {
+        Self::with_checkpoints(calls, blocks, task, options, Arc::new(MemoryCheckpointStore::default()))
+    }
+
+    /// Like `new`, checkpointing in `store`.
+    pub fn with_checkpoints(
+        calls: Arc<RpcCalls>,
+        blocks: RangeInclusive<u64>,
+        task: BackfillTask,
+        options: BackfillOptions,
+        store: Arc<dyn CheckpointStore>,
+    ) -> Result<Self> {
+        let invalid = |detail: &str| Err(RpcHandlerError::InvalidBackfill { detail: detail.to_string() });
+        if blocks.is_empty() {
+            return invalid("the block range is empty");
+        }
+        if options.concurrency == 0 || options.partition_size == 0 {
+            return invalid("concurrency and partition size must be at least 1");
+        }
+        let total_blocks = blocks.end() - blocks.start() + 1;
+        let progress = watch::Sender::new(BackfillProgress { total_blocks, ..BackfillProgress::default() });
+        Ok(Self { calls, blocks, task, options, store, progress, stop: CancellationToken::new() })
+    }
+
+    pub fn run_id(&self) -> String {
+        self.options.run_id.clone().unwrap_or_else(|| format!("{}-{}", self.blocks.start(), self.blocks.end()))
+    }
+
+    /// Progress updates, sent as each block finishes.
+    pub fn progress(&self) -> watch::Receiver<BackfillProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Finish the partitions under way and end the run without starting more. A stopped runner
+    /// stays stopped; recreate it over the same store to carry on.
+    pub fn stop(&self) {
+        self.stop.cancel();
+    }
+
+    /// Work through every partition the checkpoint doesn't have done, handing results to `sink`.
+    ///
+    /// Fails only when no endpoint is healthy to begin with.
+    pub async fn run(&self, sink: &dyn BlockSink) -> Result<BackfillReport> {
+        let urls = self.calls.handler.healthy_urls().await;
+        if urls.is_empty() {
+            return Err(RpcHandlerError::NoAvailableRpcs { network_id: self.calls.handler.network_id });
+        }
+        let run_id = self.run_id();
+        let size = self.options.partition_size;
+        let checkpoint = self
+            .store
+            .load(&run_id)
+            .filter(|checkpoint| checkpoint.matches(&self.blocks, size))
+            .unwrap_or_else(|| BackfillCheckpoint::new(&self.blocks, size));
+        let resumed: u64 = checkpoint.done.iter().map(|start| self.partition(*start).count() as u64).sum::<u64>() - checkpoint.failed.len() as u64;
+        let pending = self.blocks.clone().step_by(size as usize).filter(|start| !checkpoint.done.contains(start)).collect();
+
+        let run = Run {
+            runner: self,
+            sink,
+            run_id,
+            started: self.calls.clock.now_instant(),
+            resumed,
+            resumed_failed: checkpoint.failed.len() as u64,
+            failed: parking_lot::Mutex::new(checkpoint.failed.clone()),
+            queue: parking_lot::Mutex::new(pending),
+            endpoints: parking_lot::Mutex::new(urls.into_iter().map(|url| (url, EndpointLoad::default())).collect()),
+            checkpoint: parking_lot::Mutex::new(checkpoint),
+            completed: parking_lot::Mutex::new(0),
+            shared: Arc::new(Shared {
+                clock: Arc::clone(&self.calls.clock),
+                rate_limit: self.options.rate_limit,
+                endpoint_rate_limits: self.options.endpoint_rate_limits.clone(),
+                next_slot: parking_lot::Mutex::default(),
+                requests: parking_lot::Mutex::default(),
+            }),
+        };
+        run.publish(false);
+        futures::future::join_all((0..self.options.concurrency).map(|_| run.work())).await;
+        run.publish(true);
+        Ok(run.report())
+    }
+
+    fn partition(&self, start: u64) -> RangeInclusive<u64> {
+        start..=start.saturating_add(self.options.partition_size - 1).min(*self.blocks.end())
+    }
+}
+
+struct Run<'a> {
+    runner: &'a BackfillRunner,
+    sink: &'a dyn BlockSink,
+    run_id: String,
+    started: Instant,
+    /// Blocks the resumed checkpoint had completed
+    resumed: u64,
+    /// First blocks of the partitions not yet started
+    queue: parking_lot::Mutex<VecDeque<u64>>,
+    endpoints: parking_lot::Mutex<BTreeMap<String, EndpointLoad>>,
+    checkpoint: parking_lot::Mutex<BackfillCheckpoint>,
+    /// Blocks this run completed
+    completed: parking_lot::Mutex<u64>,
+    /// Blocks that failed every retry, starting with those of the resumed checkpoint
+    failed: parking_lot::Mutex<BTreeMap<u64, String>>,
+    /// Failed blocks that came with the resumed checkpoint
+    resumed_failed: u64,
+    shared: Arc<Shared>,
+}
+
+impl Run<'_> {
+    async fn work(&self) {
+        while !self.runner.stop.is_cancelled() {
+            let Some(start) = self.queue.lock().pop_front() else { return };
+            let mut url = self.lease(None);
+            let mut failed = BTreeMap::new();
+            for number in self.runner.partition(start) {
+                match self.block(number, &mut url).await {
+                    Ok(result) => {
+                        self.sink.block(number, result);
+                        *self.completed.lock() += 1;
+                    }
+                    Err(error) => {
+                        self.failed.lock().insert(number, error.to_string());
+                        failed.insert(number, error.to_string());
+                    }
+                }
+                self.publish(false);
+            }
+            if let Some(url) = url {
+                self.release(&url);
+            }
+            self.finish_partition(start, failed);
+        }
+    }
+
+    /// Run the task for one block, retrying with backoff. `url` is the partition's endpoint,
+    /// which moves to another endpoint when this one fails.
+    async fn block(&self, number: u64, url: &mut Option<String>) -> Result<Value> {
+        let options = &self.runner.options;
+        let mut attempt = 0;
+        loop {
+            if url.as_ref().is_none_or(|leased| self.is_dropped(leased)) {
+                let dropped = url.take();
+                if let Some(dropped) = &dropped {
+                    self.release(dropped);
+                }
+                *url = self.lease(dropped.as_deref());
+            }
+            let Some(current) = url.clone() else {
+                return Err(RpcHandlerError::AllEndpointsFailed);
+            };
+
+            let result = self.runner.task.run(self.context(number, &current)).await;
+            self.record(&current, &result);
+            let Err(error) = result else { return result };
+            if attempt >= options.max_retries {
+                return Err(error);
+            }
+
+            let backoff = options.retry_backoff.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_RETRY_BACKOFF);
+            self.runner.calls.clock.sleep(backoff).await;
+            attempt += 1;
+            // Somewhere else if anywhere else is live, otherwise the same endpoint again
+            if let Some(next) = self.lease(Some(&current)) {
+                self.release(&current);
+                *url = Some(next);
+            }
+        }
+    }
+
+    fn context(&self, number: u64, url: &str) -> BlockContext {
+        let exclude = self.runner.calls.handler.rpcs().iter().map(|rpc| rpc.url.to_string()).filter(|other| other != url).collect();
+        BlockContext {
+            calls: Arc::clone(&self.runner.calls),
+            number,
+            url: url.to_string(),
+            options: CallOptions { exclude, retry_count: Some(1), ..CallOptions::default() },
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// The live endpoint with the fewest partitions under way, other than `avoid` when another
+    /// is live. Counts the lease until `release`.
+    fn lease(&self, avoid: Option<&str>) -> Option<String> {
+        let mut endpoints = self.endpoints.lock();
+        let (url, load) = endpoints
+            .iter_mut()
+            .filter(|(_, load)| !load.dropped)
+            .min_by_key(|(url, load)| (Some(url.as_str()) == avoid, load.active, load.leased))?;
+        load.active += 1;
+        load.leased += 1;
+        Some(url.clone())
+    }
+
+    fn release(&self, url: &str) {
+        if let Some(load) = self.endpoints.lock().get_mut(url) {
+            load.active = load.active.saturating_sub(1);
+        }
+    }
+
+    fn is_dropped(&self, url: &str) -> bool {
+        self.endpoints.lock().get(url).is_some_and(|load| load.dropped)
+    }
+
+    /// Count an attempt at `url` towards dropping it. An error the endpoint answered with is the
+    /// block's problem rather than the endpoint's, and the last live endpoint is never dropped.
+    fn record<T>(&self, url: &str, result: &Result<T>) {
+        let mut endpoints = self.endpoints.lock();
+        let live = endpoints.values().filter(|load| !load.dropped).count();
+        let Some(load) = endpoints.get_mut(url) else { return };
+        if result.as_ref().err().is_none_or(|error| FailureClass::of(error) == FailureClass::JsonRpc) {
+            load.failures_in_a_row = 0;
+            return;
+        }
+        load.failures_in_a_row += 1;
+        if load.failures_in_a_row >= ENDPOINT_FAILURE_LIMIT && !load.dropped && live > 1 {
+            load.dropped = true;
+            tracing::warn!(url = %self.runner.calls.handler.config().redactor.redact(url), "Backfill stopped using an endpoint after repeated failures");
+        }
+    }
+
+    fn finish_partition(&self, start: u64, failed: BTreeMap<u64, String>) {
+        let mut checkpoint = self.checkpoint.lock();
+        checkpoint.done.insert(start);
+        checkpoint.failed.extend(failed);
+        if let Err(e) = self.runner.store.save(&self.run_id, &checkpoint) {
+            tracing::warn!(run_id = %self.run_id, error = %e, "Failed to save backfill checkpoint");
+        }
+    }
+
+    fn publish(&self, finished: bool) {
+        let completed = *self.completed.lock();
+        let failed_blocks = self.failed.lock().len() as u64;
+        let total_blocks = self.runner.progress.borrow().total_blocks;
+        let elapsed = self.runner.calls.clock.elapsed_since(self.started).as_secs_f64();
+        let finished_here = completed + failed_blocks - self.resumed_failed;
+        let blocks_per_second = if elapsed > 0.0 { finished_here as f64 / elapsed } else { 0.0 };
+        let remaining = total_blocks.saturating_sub(self.resumed + completed + failed_blocks);
+        let eta = (blocks_per_second > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / blocks_per_second));
+        self.runner.progress.send_replace(BackfillProgress {
+            total_blocks,
+            completed_blocks: self.resumed + completed,
+            failed_blocks,
+            blocks_per_second,
+            eta: if finished { Some(Duration::ZERO) } else { eta },
+            finished,
+        });
+    }
+
+    fn report(&self) -> BackfillReport {
+        let redactor = self.runner.calls.handler.config().redactor.clone();
+        BackfillReport {
+            run_id: self.run_id.clone(),
+            completed: *self.completed.lock(),
+            resumed: self.resumed,
+            failed: self.failed.lock().clone(),
+            requests_by_endpoint: self.shared.requests.lock().iter().map(|(url, count)| (redactor.redact(url), *count)).collect(),
+            dropped_endpoints: self.endpoints.lock().iter().filter(|(_, load)| load.dropped).map(|(url, _)| redactor.redact(url)).collect(),
+            stopped: self.runner.stop.is_cancelled() && !self.queue.lock().is_empty(),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! The latency benchmark behind the README table.
+//!
+//! `cargo run --release --features bench-bin --bin ez-web3-rpc-bench -- 100 20` runs 20
+//! iterations on Gnosis; the network defaults to Gnosis and the iterations to 20. Each iteration
+//! builds and initializes a fresh handler, then times one of each call through it.
+//!
+//! It times real endpoints from a shell, so on `wasm32` it only says it can't run there.
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
+
+use std::time::Instant;
+
+use ez_web3_rpc::{HandlerConfig, JsonRpcRequest, RpcHandler};
+use serde_json::json;
+
+/// Timings of one phase across iterations, in milliseconds.
+#[derive(Default)]
+struct Phase {
+    samples: Vec<f64>,
+    successes: usize,
+}
+
+impl Phase {
+    fn record(&mut self, started: Instant, ok: bool) {
+        self.samples.push(started.elapsed().as_secs_f64() * 1000.0);
+        self.successes += usize::from(ok);
+    }
+
+    fn row(&self, name: &str, iterations: usize) -> String {
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(f64::total_cmp);
+        let at = |share: f64| sorted.get(((sorted.len() as f64 * share).ceil() as usize).saturating_sub(1)).copied().unwrap_or(0.0);
+        let mean = sorted.iter().sum::<f64>() / sorted.len().max(1) as f64;
+        format!("| {name} | {mean:.2} | {:.2} | {:.2} | {}/{iterations} |", at(0.5), at(0.95), self.successes)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "the benchmark needs a native target").into())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+    let mut args = std::env::args().skip(1);
+    let network_id = args.next().map(|arg| arg.parse()).transpose()?.unwrap_or(100);
+    let iterations: usize = args.next().map(|arg| arg.parse()).transpose()?.unwrap_or(20);
+
+    let calls = [
+        ("eth_blockNumber", JsonRpcRequest::new("eth_blockNumber", json!([]))),
+        ("Heavy block fetch", JsonRpcRequest::new("eth_getBlockByNumber", json!(["latest", true]))),
+        ("eth_gasPrice", JsonRpcRequest::new("eth_gasPrice", json!([]))),
+    ];
+    let mut init = Phase::default();
+    let mut phases: Vec<Phase> = calls.iter().map(|_| Phase::default()).collect();
+
+    for _ in 0..iterations {
+        let started = Instant::now();
+        let handler = RpcHandler::new(HandlerConfig::new(network_id), None).await?;
+        init.record(started, handler.init().await.is_ok());
+
+        for ((_, call), phase) in calls.iter().zip(&mut phases) {
+            let started = Instant::now();
+            let ok = handler.try_proxy_request(call.clone()).await.is_ok_and(|response| response.error.is_none());
+            phase.record(started, ok);
+        }
+    }
+
+    println!("| Phase | Mean (ms) | Median (ms) | p95 (ms) | Success |");
+    println!("|-------|---------:|-----------:|--------:|--------:|");
+    println!("{}", init.row("Init probe (handler construction)", iterations));
+    for ((name, _), phase) in calls.iter().zip(&phases) {
+        println!("{}", phase.row(name, iterations));
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Command-line parsing, by hand to keep the binary's dependencies to the library's own.
+
+use ez_web3_rpc::NetworkId;
+use serde_json::Value;
+
+pub const USAGE: &str = "\
+usage: ez-web3-rpc [--json] [--rpc-url <url>]... <command>
+
+commands:
+  probe <network_id>                          probe every endpoint and print their health
+  fastest <network_id>                        the endpoint the handler would pick
+  consensus <network_id> <method> [params]    ask several endpoints, require a quorum to agree
+      --quorum <share>                        share of answers that must agree, 0.66 by default
+  doctor <network_id>                         check the setup end to end
+  chains [--search <name>]                    known chains by TVL, or those whose name matches
+  chain <network_id>                          one chain and its public endpoints
+  call <network_id> <method> [params]         send one request through the handler
+
+options:
+  --rpc-url <url>   an endpoint to use on top of the chainlist ones, repeatable
+  --json            print JSON instead of text
+  -h, --help        print this help
+
+params are a JSON array, `[]` when left out.
+
+exit codes: 0 ok, 1 a negative answer (problems found, no quorum, a JSON-RPC error, an
+unknown chain), 2 bad usage, 3 no endpoint reachable.";
+
+pub enum Command {
+    Probe(NetworkId),
+    Fastest(NetworkId),
+    Consensus { network_id: NetworkId, method: String, params: Value, quorum: f64 },
+    Doctor(NetworkId),
+    Chains { search: Option<String> },
+    Chain(NetworkId),
+    Call { network_id: NetworkId, method: String, params: Value },
+    Help,
+}
+
+pub struct Args {
+    pub command: Command,
+    pub rpc_urls: Vec<String>,
+    pub json: bool,
+}
+
+impl Args {
+    /// Parse the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut rpc_urls = Vec::new();
+        let mut json = false;
+        let mut quorum = None;
+        let mut search = None;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| format!("{flag} needs a value"));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Self { command: Command::Help, rpc_urls, json }),
+                "--json" => json = true,
+                "--rpc-url" => rpc_urls.push(value("--rpc-url")?),
+                "--quorum" => {
+                    let share = value("--quorum")?;
+                    quorum = Some(share.parse::<f64>().ok().filter(|share| (0.0..=1.0).contains(share)).ok_or_else(|| format!("--quorum must be between 0 and 1, not {share}"))?);
+                }
+                "--search" => search = Some(value("--search")?),
+                flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let name = positional.next().ok_or("no command given")?;
+        let command = match name.as_str() {
+            "probe" => Command::Probe(network_id(positional.next())?),
+            "fastest" => Command::Fastest(network_id(positional.next())?),
+            "doctor" => Command::Doctor(network_id(positional.next())?),
+            "chain" => Command::Chain(network_id(positional.next())?),
+            "chains" => Command::Chains { search },
+            "consensus" => {
+                let network_id = network_id(positional.next())?;
+                let method = positional.next().ok_or("consensus needs a method")?;
+                let params = params(positional.next())?;
+                Command::Consensus { network_id, method, params, quorum: quorum.unwrap_or(0.66) }
+            }
+            "call" => {
+                let network_id = network_id(positional.next())?;
+                let method = positional.next().ok_or("call needs a method")?;
+                Command::Call { network_id, method, params: params(positional.next())? }
+            }
+            other => return Err(format!("unknown command {other}")),
+        };
+        if let Some(extra) = positional.next() {
+            return Err(format!("unexpected argument {extra}"));
+        }
+        Ok(Self { command, rpc_urls, json })
+    }
+}
+
+fn network_id(arg: Option<String>) -> Result<NetworkId, String> {
+    let arg = arg.ok_or("missing network id")?;
+    arg.parse().map_err(|_| format!("invalid network id {arg}"))
+}
+
+fn params(arg: Option<String>) -> Result<Value, String> {
+    let Some(arg) = arg else {
+        return Ok(Value::Array(Vec::new()));
+    };
+    match serde_json::from_str(&arg) {
+        Ok(params @ Value::Array(_)) => Ok(params),
+        _ => Err(format!("params must be a JSON array, not {arg}")),
+    }
+}
@@ -0,0 +1,192 @@
+//! `ez-web3-rpc`, the handler from a shell.
+//!
+//! `cargo run --features cli --bin ez-web3-rpc -- probe 100` probes Gnosis's endpoints and
+//! prints their health; `--help` lists the other commands. Each run builds one handler from
+//! `HandlerConfig::new` plus any `--rpc-url` endpoints, does its one thing and exits with a
+//! code scripts can branch on.
+//!
+//! A shell tool has no use in a browser, so on `wasm32` it only says it can't run there.
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
+
+mod args;
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use args::{Args, Command, USAGE};
+use ez_web3_rpc::chainlist::{find_chains_by_name, get_chain_info, get_chains_by_tvl, get_extra_rpcs};
+use ez_web3_rpc::{HandlerConfig, JsonRpcRequest, NetworkId, Rpc, RpcCalls, RpcConfig, RpcHandler, RpcHandlerError};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// The command did what was asked and the answer was good.
+const OK: u8 = 0;
+/// The command ran, but the answer was no: problems found, no quorum, a JSON-RPC error, an
+/// unknown chain.
+const NEGATIVE: u8 = 1;
+/// The arguments or the endpoints they name were invalid.
+const USAGE_ERROR: u8 = 2;
+/// No endpoint could be reached, or none answered.
+const UNREACHABLE: u8 = 3;
+
+/// A failed run: the exit code and what to print on stderr.
+struct Failure(u8, String);
+
+impl From<RpcHandlerError> for Failure {
+    fn from(error: RpcHandlerError) -> Self {
+        let code = match &error {
+            RpcHandlerError::JsonRpc(_)
+            | RpcHandlerError::JsonRpcCode { .. }
+            | RpcHandlerError::ConsensusFailure { .. }
+            | RpcHandlerError::ChainInfoNotFound { .. } => NEGATIVE,
+            RpcHandlerError::InvalidRpcConfig { .. }
+            | RpcHandlerError::InvalidUrlTemplate { .. }
+            | RpcHandlerError::InvalidErrorMapping { .. }
+            | RpcHandlerError::UnresolvedPlaceholder { .. }
+            | RpcHandlerError::NonIdempotentMethodInConsensus { .. } => USAGE_ERROR,
+            _ => UNREACHABLE,
+        };
+        Failure(code, error.to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() -> ExitCode {
+    eprintln!("error: {}", std::io::Error::new(std::io::ErrorKind::Unsupported, "the CLI needs a native target"));
+    ExitCode::from(USAGE_ERROR)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return ExitCode::from(USAGE_ERROR);
+        }
+    };
+    match run(&args).await {
+        Ok(code) => ExitCode::from(code),
+        Err(Failure(code, message)) => {
+            eprintln!("error: {message}");
+            ExitCode::from(code)
+        }
+    }
+}
+
+async fn run(args: &Args) -> Result<u8, Failure> {
+    match &args.command {
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(OK)
+        }
+        Command::Probe(network_id) => {
+            let handler = handler(*network_id, args).await?;
+            // A failed init still leaves a report saying why
+            let _ = handler.init().await;
+            let report = handler.health_report().await;
+            print(args, &report, &report);
+            Ok(if report.endpoints.iter().any(|endpoint| endpoint.latency_ms.is_some()) { OK } else { UNREACHABLE })
+        }
+        Command::Fastest(network_id) => {
+            let handler = handler(*network_id, args).await?;
+            handler.init().await?;
+            let url = handler.get_provider_url().await?;
+            let latency_ms = handler.get_latencies().await.get(&url).copied();
+            let text = match latency_ms {
+                Some(ms) => format!("{url} ({ms}ms)"),
+                None => url.clone(),
+            };
+            print(args, &json!({ "url": url, "latency_ms": latency_ms }), &text);
+            Ok(OK)
+        }
+        Command::Consensus { network_id, method, params, quorum } => {
+            let handler = handler(*network_id, args).await?;
+            handler.init().await?;
+            let calls = RpcCalls::new(handler);
+            let (result, report) = calls.consensus_with_report::<Value>(&JsonRpcRequest::new(method.as_str(), params.clone()), *quorum, None).await;
+            let (value, error) = match &result {
+                Ok(value) => (Some(value), None),
+                Err(error) => (None, Some(error.to_string())),
+            };
+            let summary = json!({
+                "result": value,
+                "error": error,
+                "most_common": report.most_common,
+                "quorum": report.quorum,
+                "votes": report.votes,
+                "report": report.to_string(),
+            });
+            let text = match value {
+                Some(value) => format!("{}\n\n{report}", pretty(value)),
+                None => report.to_string(),
+            };
+            print(args, &summary, &text);
+            result.map(|_| OK).map_err(Failure::from)
+        }
+        Command::Doctor(network_id) => {
+            let report = handler(*network_id, args).await?.doctor().await;
+            print(args, &report, &report);
+            Ok(if report.passed() { OK } else { NEGATIVE })
+        }
+        Command::Chains { search } => {
+            let chains = match search {
+                Some(name) => find_chains_by_name(name),
+                None => get_chains_by_tvl(),
+            };
+            let text = chains.iter().map(|chain| format!("{:>10}  {}  (tvl {:.0})", chain.chain_id, chain.name, chain.tvl)).collect::<Vec<_>>().join("\n");
+            print(args, &chains, &text);
+            Ok(if chains.is_empty() { NEGATIVE } else { OK })
+        }
+        Command::Chain(network_id) => {
+            let chain = get_chain_info(*network_id).ok_or(RpcHandlerError::ChainInfoNotFound { network_id: *network_id })?;
+            let rpcs = get_extra_rpcs(*network_id);
+            let mut text = format!("{} ({}), tvl {:.0}, {} public endpoints", chain.name, chain.chain_id, chain.tvl, rpcs.len());
+            for rpc in &rpcs {
+                text.push_str(&format!("\n  {}", rpc.url));
+            }
+            print(args, &json!({ "chain": chain, "rpcs": rpcs }), &text);
+            Ok(OK)
+        }
+        Command::Call { network_id, method, params } => {
+            let handler = handler(*network_id, args).await?;
+            handler.init().await?;
+            let response = handler.try_proxy_request(JsonRpcRequest::new(method.as_str(), params.clone())).await?;
+            let text = match (&response.error, &response.result) {
+                (Some(error), _) => format!("error {}: {}", error.code, error.message),
+                (None, Some(result)) => pretty(result),
+                (None, None) => "null".to_string(),
+            };
+            print(args, &response, &text);
+            Ok(if response.error.is_some() { NEGATIVE } else { OK })
+        }
+    }
+}
+
+/// The handler for `network_id`, with the `--rpc-url` endpoints added to the chainlist ones.
+async fn handler(network_id: NetworkId, args: &Args) -> Result<Arc<RpcHandler>, Failure> {
+    let mut config = HandlerConfig::new(network_id);
+    if let Some(settings) = config.settings.as_mut() {
+        for url in &args.rpc_urls {
+            settings.network_rpcs.push(RpcConfig::from(Rpc::from_url(url)?));
+        }
+    }
+    Ok(RpcHandler::new(config, None).await?)
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+/// Print `value` as JSON under `--json`, else `text`.
+fn print(args: &Args, value: &impl Serialize, text: &impl std::fmt::Display) {
+    if args.json {
+        match serde_json::to_string_pretty(value) {
+            Ok(json) => println!("{json}"),
+            Err(error) => eprintln!("error: {error}"),
+        }
+    } else {
+        println!("{text}");
+    }
+}
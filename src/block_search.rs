@@ -0,0 +1,149 @@
+//! Finding the block a chain was at when the clock read a given time.
+//!
+//! `RpcCalls::block_by_timestamp` binary-searches block timestamps between a lower bound and the
+//! head, which works for any block-time pattern as long as timestamps never decrease. The whole
+//! search runs against one endpoint, so slightly different views of the chain near a boundary
+//! can't mix into one answer. If that endpoint fails, the search continues on the next one from
+//! the bounds already verified rather than starting over.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::{
+    calls::RpcCalls,
+    namespaces::parse_quantity,
+    provider::CallOptions,
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// Blocks at least this far below the head are taken as final, and their timestamps cached.
+pub const TIMESTAMP_FINALITY_DEPTH: u64 = 64;
+
+/// Cached block timestamps an `RpcCalls` keeps at most.
+pub const MAX_CACHED_TIMESTAMPS: usize = 4096;
+
+/// Narrows a `block_by_timestamp` search.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchHint {
+    /// A block known to be at or before the timestamp; genesis when `None`
+    pub lower_bound: Option<u64>,
+}
+
+/// A block's number and timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStamp {
+    pub number: u64,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Where a timestamp falls in the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockMatch {
+    /// The last block with a timestamp at or before the one searched for, `None` when it is
+    /// before the lower bound
+    pub at_or_before: Option<BlockStamp>,
+    /// The block after it, for rounding up; `None` when `at_or_before` is the head
+    pub after: Option<BlockStamp>,
+    /// The endpoint the search finished on
+    pub url: String,
+    /// Blocks requested, cached timestamps not counted
+    pub fetched: usize,
+    /// Times the search moved to another endpoint
+    pub failovers: usize,
+}
+
+/// Block timestamps by number, shared by the searches of one `RpcCalls`.
+pub(crate) type TimestampCache = std::sync::Arc<parking_lot::Mutex<BTreeMap<u64, u64>>>;
+
+
+/// A search pinned to one endpoint at a time.
+struct Pinned<'a> {
+    calls: &'a RpcCalls,
+    urls: Vec<String>,
+    current: usize,
+    fetched: usize,
+    /// Blocks at or below this are final
+    final_below: u64,
+}
+
+impl Pinned<'_> {
+    async fn block(&mut self, block: &str) -> Result<BlockStamp> {
+        loop {
+            let url = self.urls.get(self.current).ok_or(RpcHandlerError::AllEndpointsFailed)?;
+            let exclude = self.urls.iter().filter(|other| *other != url).cloned().collect();
+            let result = self.calls.handler.try_proxy_request_with(JsonRpcRequest::new("eth_getBlockByNumber", json!([block, false])), CallOptions { exclude, ..CallOptions::default() }).await;
+            self.fetched += 1;
+            match result.and_then(|(response, url)| stamp(&url, block, response.into_result()?)) {
+                Ok(stamp) => return Ok(stamp),
+                // Bounds verified so far stand; the next endpoint picks up from them
+                Err(_) if self.current + 1 < self.urls.len() => self.current += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn number(&mut self, number: u64) -> Result<BlockStamp> {
+        if let Some(timestamp) = self.calls.block_timestamps.lock().get(&number).copied() {
+            return Ok(BlockStamp { number, timestamp });
+        }
+        let stamp = self.block(&format!("0x{number:x}")).await?;
+        if number <= self.final_below {
+            let mut cache = self.calls.block_timestamps.lock();
+            cache.insert(number, stamp.timestamp);
+            while cache.len() > MAX_CACHED_TIMESTAMPS {
+                cache.pop_first();
+            }
+        }
+        Ok(stamp)
+    }
+}
+
+fn stamp(url: &str, block: &str, result: Value) -> Result<BlockStamp> {
+    let field = |name: &str| {
+        result.get(name).and_then(parse_quantity).ok_or_else(|| RpcHandlerError::MalformedResponse {
+            url: url.to_string(),
+            violation: format!("block {block} has no {name}: {result}"),
+        })
+    };
+    Ok(BlockStamp { number: field("number")?, timestamp: field("timestamp")? })
+}
+
+impl RpcCalls {
+    /// The last block produced at or before `timestamp` (seconds since the Unix epoch), and the one
+    /// after it.
+    ///
+    /// Takes about log2 of the searched range in block requests, fewer when timestamps of final
+    /// blocks are already cached. A timestamp before the lower bound gives no `at_or_before`, and
+    /// one at or past the head's gives no `after`.
+    pub async fn block_by_timestamp(&self, timestamp: u64, hint: SearchHint) -> Result<BlockMatch> {
+        let active = self.handler.get_provider_url().await?;
+        let mut urls = vec![active.clone()];
+        urls.extend(self.fan_out_urls("eth_getBlockByNumber", self.clock.now_instant()).into_iter().filter(|url| *url != active));
+        let mut search = Pinned { calls: self, urls, current: 0, fetched: 0, final_below: 0 };
+
+        let head = search.block("latest").await?;
+        search.final_below = head.number.saturating_sub(TIMESTAMP_FINALITY_DEPTH);
+        let lower = search.number(hint.lower_bound.unwrap_or(0).min(head.number)).await?;
+
+        let (at_or_before, after) = if lower.timestamp > timestamp {
+            (None, Some(lower))
+        } else if head.timestamp <= timestamp {
+            (Some(head), None)
+        } else {
+            // lo is at or before the timestamp, hi after it
+            let (mut lo, mut hi) = (lower, head);
+            while hi.number - lo.number > 1 {
+                let mid = search.number(lo.number + (hi.number - lo.number) / 2).await?;
+                if mid.timestamp <= timestamp {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            (Some(lo), Some(hi))
+        };
+        Ok(BlockMatch { at_or_before, after, url: search.urls[search.current].clone(), fetched: search.fetched, failovers: search.current })
+    }
+}
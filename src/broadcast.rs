@@ -0,0 +1,260 @@
+//! Idempotent transaction broadcast.
+//!
+//! `RpcCalls::broadcast_raw_transaction` sends a signed transaction to every endpoint it should
+//! reach and records, per idempotency key, which endpoints accepted it. Re-broadcasting the same
+//! key, e.g. after a crash and restart over a `FileLedger`, only targets the endpoints that
+//! haven't accepted yet; once all have, the recorded report is returned without sending anything.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    time::{Duration, SystemTime},
+};
+#[cfg(feature = "persistence")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    calls::RpcCalls,
+    hex::{hex, unhex},
+    provider::post_json_rpc,
+    runtime,
+    JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError,
+};
+
+const BROADCAST_METHOD: &str = "eth_sendRawTransaction";
+
+/// How long ledger entries are kept when no TTL is given.
+pub const DEFAULT_LEDGER_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastOptions {
+    /// Key acceptances are recorded under, the transaction hash when `None`
+    pub idempotency_key: Option<String>,
+    /// Per-endpoint timeout, 8s when `None`
+    pub timeout_ms: Option<u64>,
+}
+
+/// Where a transaction has landed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastReport {
+    pub idempotency_key: String,
+    pub tx_hash: String,
+    /// Every endpoint that has accepted the transaction under this key, this broadcast or an earlier one
+    pub accepted: BTreeSet<String>,
+    /// Endpoints that rejected this broadcast, with the error
+    pub rejected: BTreeMap<String, String>,
+    /// Every target had already accepted, so nothing was sent
+    pub replayed: bool,
+}
+
+/// A key's latest report, as kept by a `BroadcastLedger`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub report: BroadcastReport,
+    /// When the key was first recorded; the entry expires a TTL later
+    pub recorded_at: SystemTime,
+}
+
+impl LedgerEntry {
+    fn live(&self, ttl: Duration, now: SystemTime) -> bool {
+        // A clock that went backwards leaves the entry live
+        now.duration_since(self.recorded_at).map_or(true, |age| age < ttl)
+    }
+}
+
+/// Store of which endpoints accepted which broadcast.
+pub trait BroadcastLedger: Send + Sync {
+    /// The entry for `key`, `None` when there is none or it has expired at `now`.
+    fn get(&self, key: &str, now: SystemTime) -> Option<LedgerEntry>;
+
+    /// Store `entry` under `key`, replacing any earlier one and dropping entries expired at `now`.
+    fn record(&self, key: &str, entry: LedgerEntry, now: SystemTime) -> io::Result<()>;
+}
+
+/// Ledger held in memory, lost on restart. The default for `RpcCalls`.
+#[derive(Debug)]
+pub struct MemoryLedger {
+    ttl: Duration,
+    entries: parking_lot::Mutex<HashMap<String, LedgerEntry>>,
+}
+
+impl MemoryLedger {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entries: parking_lot::Mutex::default() }
+    }
+}
+
+impl Default for MemoryLedger {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEDGER_TTL)
+    }
+}
+
+impl BroadcastLedger for MemoryLedger {
+    fn get(&self, key: &str, now: SystemTime) -> Option<LedgerEntry> {
+        self.entries.lock().get(key).filter(|entry| entry.live(self.ttl, now)).cloned()
+    }
+
+    fn record(&self, key: &str, entry: LedgerEntry, now: SystemTime) -> io::Result<()> {
+        let mut entries = self.entries.lock();
+        entries.retain(|_, entry| entry.live(self.ttl, now));
+        entries.insert(key.to_string(), entry);
+        Ok(())
+    }
+}
+
+/// Ledger kept in a JSON file, so acceptances survive a restart.
+///
+/// The file is read once on `open` and rewritten on every `record`, through a temporary file
+/// renamed into place so a crash mid-write leaves the previous version.
+#[cfg(feature = "persistence")]
+#[derive(Debug)]
+pub struct FileLedger {
+    path: PathBuf,
+    ttl: Duration,
+    entries: parking_lot::Mutex<HashMap<String, LedgerEntry>>,
+}
+
+#[cfg(feature = "persistence")]
+impl FileLedger {
+    /// Open the ledger at `path`, starting empty if the file doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>, ttl: Duration) -> io::Result<Self> {
+        runtime::require_filesystem("FileLedger")?;
+        let path = path.as_ref().to_path_buf();
+        let entries = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, ttl, entries: parking_lot::Mutex::new(entries) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl BroadcastLedger for FileLedger {
+    fn get(&self, key: &str, now: SystemTime) -> Option<LedgerEntry> {
+        self.entries.lock().get(key).filter(|entry| entry.live(self.ttl, now)).cloned()
+    }
+
+    fn record(&self, key: &str, entry: LedgerEntry, now: SystemTime) -> io::Result<()> {
+        let mut entries = self.entries.lock();
+        entries.retain(|_, entry| entry.live(self.ttl, now));
+        entries.insert(key.to_string(), entry);
+
+        let bytes = serde_json::to_vec(&*entries).map_err(io::Error::other)?;
+        let staging = self.path.with_extension("tmp");
+        fs::write(&staging, bytes)?;
+        fs::rename(&staging, &self.path)
+    }
+}
+
+/// The hash of a signed transaction: Keccak-256 of its raw bytes, `0x`-prefixed hex.
+pub fn transaction_hash(raw_tx: &str) -> Result<String> {
+    let invalid = |detail: &str| RpcHandlerError::InvalidRawTransaction { detail: detail.to_string() };
+    let digits = raw_tx.strip_prefix("0x").ok_or_else(|| invalid("missing 0x prefix"))?;
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return Err(invalid("expected a non-empty, even number of hex digits"));
+    }
+    let bytes = unhex(raw_tx).ok_or_else(|| invalid("not hex"))?;
+
+    Ok(format!("0x{}", hex(&Keccak256::digest(&bytes))))
+}
+
+/// Whether a rejection means the endpoint already has the transaction, which counts as accepted.
+pub fn already_known(error: &RpcHandlerError) -> bool {
+    let RpcHandlerError::JsonRpcCode { message, .. } = error else { return false };
+    let message = message.to_ascii_lowercase();
+    ["already known", "known transaction", "already imported"].iter().any(|known| message.contains(known))
+}
+
+impl RpcCalls {
+    /// Send a signed transaction to every endpoint a fan-out of `eth_sendRawTransaction` reaches,
+    /// skipping those the ledger says already accepted it under the same idempotency key.
+    ///
+    /// An endpoint answering that it already has the transaction counts as accepted. When every
+    /// target has accepted, the recorded report is returned with `replayed` set and nothing is
+    /// sent. A broadcast no endpoint accepted is reported but not recorded.
+    pub async fn broadcast_raw_transaction(&self, raw_tx: &str, options: Option<BroadcastOptions>) -> Result<BroadcastReport> {
+        let options = options.unwrap_or_default();
+        let tx_hash = transaction_hash(raw_tx)?;
+        let key = options.idempotency_key.unwrap_or_else(|| tx_hash.clone());
+        let timeout = Duration::from_millis(options.timeout_ms.unwrap_or(8000));
+
+        let targets = self.fan_out_urls(BROADCAST_METHOD, self.clock.now_instant());
+        if targets.is_empty() {
+            return Err(RpcHandlerError::NoAvailableRpcs { network_id: self.handler.network_id });
+        }
+        let prior = self.ledger.get(&key, self.clock.now_system());
+        let first_recorded = prior.as_ref().map(|entry| entry.recorded_at);
+        let accepted_before = prior.as_ref().map(|entry| entry.report.accepted.clone()).unwrap_or_default();
+        let pending: Vec<String> = targets.into_iter().filter(|url| !accepted_before.contains(url)).collect();
+        if pending.is_empty()
+            && let Some(entry) = prior
+        {
+            return Ok(BroadcastReport { replayed: true, ..entry.report });
+        }
+
+        let request = JsonRpcRequest::new(BROADCAST_METHOD, json!([raw_tx]));
+        let follow_redirects = self.handler.config().settings.follow_post_redirects;
+        let host_limiter = self.handler.host_limiter();
+        let headers = self.handler.endpoint_headers();
+        let sends = pending.iter().map(|url| async {
+            let send = async {
+                self.handler.spend_meter().charge(url, BROADCAST_METHOD)?;
+                let _slot = host_limiter.acquire(url).await;
+                let response = post_json_rpc(&self.client, url, &request, follow_redirects, headers.get(url)).await?;
+                if !response.status().is_success() {
+                    return Err(RpcHandlerError::HttpStatus { url: url.clone(), status: response.status().as_u16() });
+                }
+                response
+                    .json::<JsonRpcResponse<Value>>()
+                    .await
+                    .map_err(|e| RpcHandlerError::from_reqwest(e, url))?
+                    .into_result()
+            };
+            let outcome = runtime::timeout(timeout, send)
+                .await
+                .unwrap_or_else(|_| Err(RpcHandlerError::request_timeout(url, timeout)));
+            (url.clone(), outcome)
+        });
+
+        let mut report = BroadcastReport {
+            idempotency_key: key.clone(),
+            tx_hash,
+            accepted: accepted_before,
+            rejected: BTreeMap::new(),
+            replayed: false,
+        };
+        for (url, outcome) in join_all(sends).await {
+            match outcome {
+                Err(e) if !already_known(&e) => {
+                    report.rejected.insert(url, e.to_string());
+                }
+                _ => {
+                    report.accepted.insert(url);
+                }
+            }
+        }
+
+        if !report.accepted.is_empty() {
+            let now = self.clock.now_system();
+            let entry = LedgerEntry { report: report.clone(), recorded_at: first_recorded.unwrap_or(now) };
+            if let Err(e) = self.ledger.record(&key, entry, now) {
+                tracing::warn!(key = %key, error = %e, "Could not record broadcast in the ledger");
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,122 @@
+//! Answers to methods whose results never change, kept for calls with equivalent params.
+//!
+//! Only methods the registry marks `cacheable` are kept, under the key `canonical::request_key`
+//! gives them, so a block hash asked for in upper and in lower case is fetched once. Entries
+//! are dropped oldest first past `HandlerSettings::response_cache_entries`.
+//!
+//! Lookups by a concrete transaction or block hash that come back `null` are kept apart, as
+//! negatives tagged with the head watermark they were fetched at: the data may land in any
+//! later block, so a negative is only served until the watermark moves.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Values by key, dropped oldest first past a capacity.
+#[derive(Debug)]
+struct Fifo<V> {
+    values: HashMap<String, V>,
+    /// Keys oldest first
+    order: VecDeque<String>,
+}
+
+impl<V> Default for Fifo<V> {
+    fn default() -> Self {
+        Self { values: HashMap::new(), order: VecDeque::new() }
+    }
+}
+
+impl<V> Fifo<V> {
+    fn insert(&mut self, key: String, value: V, capacity: usize) {
+        if self.values.insert(key.clone(), value).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            self.values.remove(&oldest);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Result and the endpoint that served it, by request key
+    results: Fifo<(Value, String)>,
+    /// Watermark at query time and the endpoint that answered `null`, by request key
+    negatives: Fifo<(Option<u64>, String)>,
+    hits: u64,
+    negative_hits: u64,
+}
+
+/// Sizes of the response cache and the calls it answered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    /// Calls answered from a kept result
+    pub hits: u64,
+    /// Negatives kept, stale ones included until they are replaced or evicted
+    pub negative_entries: usize,
+    /// Calls answered `null` from a negative still at its watermark
+    pub negative_hits: u64,
+}
+
+/// The handler's response cache. Cloning shares it.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseCache {
+    entries: Arc<parking_lot::Mutex<Entries>>,
+}
+
+impl ResponseCache {
+    /// The result kept for `key`, with the endpoint that served it.
+    pub(crate) fn get(&self, key: &str) -> Option<(Value, String)> {
+        let mut entries = self.entries.lock();
+        let found = entries.results.values.get(key).cloned();
+        entries.hits += u64::from(found.is_some());
+        found
+    }
+
+    /// Keep `result` for `key`, evicting the oldest entries beyond `capacity`.
+    pub(crate) fn insert(&self, key: String, result: Value, url: String, capacity: usize) {
+        self.entries.lock().results.insert(key, (result, url), capacity);
+    }
+
+    /// The endpoint that answered `key` with `null`, if it did so at the current `watermark`.
+    pub(crate) fn get_negative(&self, key: &str, watermark: Option<u64>) -> Option<String> {
+        let mut entries = self.entries.lock();
+        let url = match entries.negatives.values.get(key) {
+            Some((at, url)) if *at == watermark => url.clone(),
+            _ => return None,
+        };
+        entries.negative_hits += 1;
+        Some(url)
+    }
+
+    /// Keep a `null` answer for `key` fetched at `watermark`, evicting the oldest negatives
+    /// beyond `capacity`.
+    pub(crate) fn insert_negative(&self, key: String, watermark: Option<u64>, url: String, capacity: usize) {
+        self.entries.lock().negatives.insert(key, (watermark, url), capacity);
+    }
+
+    /// Entries kept.
+    pub fn len(&self) -> usize {
+        self.entries.lock().results.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock();
+        CacheStats {
+            entries: entries.results.values.len(),
+            hits: entries.hits,
+            negative_entries: entries.negatives.values.len(),
+            negative_hits: entries.negative_hits,
+        }
+    }
+}
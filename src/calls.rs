@@ -1,5 +1,19 @@
-use std::{collections::HashMap, sync::Arc, time::{Duration, Instant}};
-use crate::{JsonRpcRequest, JsonRpcResponse, RpcHandler, Result, RpcHandlerError};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use web_time::Instant;
+
+use crate::{
+    block_search::TimestampCache,
+    broadcast::{BroadcastLedger, MemoryLedger},
+    clock::Clock,
+    comparator::{ResultComparator, StableStringComparator},
+    reorg::CanonicalHashCache,
+    routing::route_for,
+    session::Snapshot,
+    tags::CallTags,
+    JsonRpcRequest, JsonRpcResponse, RpcHandler, Result,
+};
+use serde::Serialize;
 use serde_json::Value;
 use tokio::sync::RwLock;
 
@@ -8,6 +22,19 @@ pub struct ConsensusOptions {
     pub timeout_ms: Option<u64>,
     pub concurrency: Option<usize>,
     pub cooldown_ms: Option<u64>,
+    /// Maximum in-flight consensus requests per hostname
+    pub per_host_concurrency: Option<usize>,
+    /// How responses are grouped into agreeing classes, exact comparison when `None`
+    pub comparator: Option<Arc<dyn ResultComparator>>,
+    /// Settle as soon as the first this many answers, in the order they arrive, fall in one class,
+    /// cancelling the requests still in flight. Values below 2 are ignored.
+    pub unanimous_prefix: Option<usize>,
+    /// Send a method that may have side effects anyway, settling on the first endpoint to accept
+    /// it instead of on a quorum, as a broadcast would. Refused with
+    /// `NonIdempotentMethodInConsensus` otherwise.
+    pub allow_side_effects: bool,
+    /// Where the read came from, carried to each endpoint's request, see `tags`
+    pub tags: Option<CallTags>,
 }
 
 impl Default for ConsensusOptions {
@@ -16,343 +43,160 @@ impl Default for ConsensusOptions {
             timeout_ms: Some(8000),
             concurrency: Some(4),
             cooldown_ms: Some(30000),
+            per_host_concurrency: Some(1),
+            comparator: None,
+            unanimous_prefix: None,
+            allow_side_effects: false,
+            tags: None,
+        }
+    }
+}
+
+impl ConsensusOptions {
+    /// The options with defaults filled in, as a consensus run applies them.
+    pub fn describe(&self) -> ConsensusPolicy {
+        let comparator: Arc<dyn ResultComparator> = self.comparator.clone().unwrap_or_else(|| Arc::new(StableStringComparator));
+        ConsensusPolicy {
+            timeout_ms: self.timeout_ms.unwrap_or(8000),
+            concurrency: self.concurrency.unwrap_or(4).max(1),
+            per_host_concurrency: self.per_host_concurrency.unwrap_or(1).max(1),
+            comparator: format!("{comparator:?}"),
+            cooldown: CooldownPolicy::with_base(self.cooldown_ms.unwrap_or(30000)),
+            unanimous_prefix: self.unanimous_prefix.filter(|prefix| *prefix >= 2),
+            allow_side_effects: self.allow_side_effects,
         }
     }
 }
 
+/// Resolved consensus options.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConsensusPolicy {
+    pub timeout_ms: u64,
+    pub concurrency: usize,
+    pub per_host_concurrency: usize,
+    pub comparator: String,
+    pub cooldown: CooldownPolicy,
+    pub unanimous_prefix: Option<usize>,
+    pub allow_side_effects: bool,
+}
+
+/// How long an endpoint that failed a consensus request sits out of the next ones.
+///
+/// The `n`th strike in a row costs `base_ms * factor^(n - 1)`, capped at `max_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CooldownPolicy {
+    pub base_ms: u64,
+    pub normal_factor: f64,
+    /// Rate limited, or not even connectable
+    pub severe_factor: f64,
+    /// Share of the base a heavy call outlasting the total budget costs, without a strike
+    pub light_fraction: f64,
+    pub max_ms: u64,
+    /// Cooled-down endpoints health-checked at most when too few are left to reach a quorum
+    pub max_paroles: usize,
+    /// Time before an endpoint that failed its parole check may be checked again
+    pub parole_interval_ms: u64,
+}
+
+impl CooldownPolicy {
+    pub(crate) fn with_base(base_ms: u64) -> Self {
+        Self { base_ms, normal_factor: 1.5, severe_factor: 2.0, light_fraction: 0.5, max_ms: 5 * 60 * 1000, max_paroles: 3, parole_interval_ms: 10_000 }
+    }
+}
+
 #[derive(Debug, Clone)]
-struct CooldownInfo {
-    until: Instant,
-    strikes: u32,
+#[cfg_attr(not(feature = "consensus"), allow(dead_code))]
+pub(crate) struct CooldownInfo {
+    pub(crate) until: Instant,
+    pub(crate) strikes: u32,
+    /// When the endpoint last had a parole check, which rate-limits the next one
+    pub(crate) paroled_at: Option<Instant>,
 }
 
+pub(crate) type Cooldowns = Arc<RwLock<HashMap<String, CooldownInfo>>>;
+
 pub struct RpcCalls {
-    handler: Arc<RpcHandler>,
-    cooldowns: Arc<RwLock<HashMap<String, CooldownInfo>>>,
-    client: reqwest::Client,
+    pub(crate) handler: Arc<RpcHandler>,
+    pub(crate) cooldowns: Cooldowns,
+    pub(crate) client: reqwest::Client,
+    pub(crate) clock: Arc<dyn Clock>,
+    /// Which endpoints accepted which broadcast
+    pub(crate) ledger: Arc<dyn BroadcastLedger>,
+    /// Timestamps of final blocks seen by `block_by_timestamp`
+    pub(crate) block_timestamps: TimestampCache,
+    /// Hashes of final blocks verified by `ConsistencyCheck::VerifyCanonical`
+    pub(crate) canonical_hashes: CanonicalHashCache,
+    /// Set on the calls of a `ConsistencySession`, whose reads all go through it
+    pub(crate) snapshot: Option<Arc<Snapshot>>,
 }
 
 impl RpcCalls {
+    /// Cooldowns live on the handler, so every `RpcCalls` over the same handler shares them.
     pub fn new(handler: Arc<RpcHandler>) -> Self {
+        Self::with_ledger(handler, Arc::new(MemoryLedger::default()))
+    }
+
+    /// Like `new`, recording broadcasts in `ledger` instead of in memory.
+    pub fn with_ledger(handler: Arc<RpcHandler>, ledger: Arc<dyn BroadcastLedger>) -> Self {
         Self {
+            clock: Arc::clone(handler.clock()),
+            cooldowns: Arc::clone(handler.cooldowns()),
+            client: handler.client().clone(),
             handler,
-            cooldowns: Arc::new(RwLock::new(HashMap::new())),
-            client: reqwest::Client::new(),
+            ledger,
+            block_timestamps: TimestampCache::default(),
+            canonical_hashes: CanonicalHashCache::default(),
+            snapshot: None,
         }
     }
-    
-    /// Basic consensus: require a quorum of identical responses across providers.
-    pub async fn consensus<T>(
-        &self,
-        req: &JsonRpcRequest,
-        quorum_threshold: f64, // e.g., 0.66 for 66%
-        options: Option<ConsensusOptions>,
-    ) -> Result<T> 
-    where
-        T: serde::de::DeserializeOwned,
-    {
-        let opts = options.unwrap_or_default();
-        let attempt = self.consensus_attempt(req, quorum_threshold, &opts, true).await?;
-        
-        if attempt.success {
-            if let Some(value) = attempt.value {
-                return serde_json::from_value(value)
-                    .map_err(|e| RpcHandlerError::SerializationError(e.to_string()));
-            }
-        }
-        
-        Err(RpcHandlerError::ConsensusFailure {
-            most_common: attempt.most_common_key.unwrap_or_else(|| "n/a".to_string()),
-        })
+
+    /// The handler this call layer routes through.
+    pub fn handler(&self) -> &Arc<RpcHandler> {
+        &self.handler
     }
-    
-    /// BFT-style consensus: iteratively lowers quorum requirement if initial threshold fails.
-    pub async fn bft_consensus<T>(
-        &self,
-        req: &JsonRpcRequest,
-        quorum_threshold: f64,
-        min_threshold: f64,
-        options: Option<ConsensusOptions>,
-    ) -> Result<T>
-    where
-        T: serde::de::DeserializeOwned,
-    {
-        let opts = options.unwrap_or_default();
-        let base_attempt = self.consensus_attempt(req, quorum_threshold, &opts, false).await?;
-        
-        if base_attempt.success {
-            if let Some(value) = base_attempt.value {
-                return serde_json::from_value(value)
-                    .map_err(|e| RpcHandlerError::SerializationError(e.to_string()));
-            }
-        }
-        
-        if base_attempt.results.is_empty() {
-            return Err(RpcHandlerError::ConsensusFailure {
-                most_common: "No successful RPC responses for BFT consensus".to_string(),
-            });
-        }
-        
-        // Descend thresholds
-        let mut curr = quorum_threshold - 0.05;
-        while curr >= min_threshold {
-            let needed = (base_attempt.results.len() as f64 * curr).ceil() as usize;
-            if needed == 0 {
-                break;
-            }
-            
-            if let Some(ref most_key) = base_attempt.most_common_key {
-                if base_attempt.counts.get(most_key).unwrap_or(&0) >= &needed {
-                    return serde_json::from_value(base_attempt.key_to_value.get(most_key).unwrap().clone())
-                        .map_err(|e| RpcHandlerError::SerializationError(e.to_string()));
-                }
-            }
-            
-            curr = (curr - 0.05).max(0.0);
-        }
-        
-        Err(RpcHandlerError::ConsensusFailure {
-            most_common: "Could not reach BFT consensus down to minimum threshold".to_string(),
-        })
+
+    /// Time left on `url`'s consensus cooldown, `None` if it isn't cooling down.
+    pub async fn cooldown_remaining(&self, url: &str) -> Option<Duration> {
+        let now = self.clock.now_instant();
+        self.cooldowns.read().await.get(url).and_then(|cd| cd.until.checked_duration_since(now)).filter(|left| !left.is_zero())
     }
     
-    /// Attempt an RPC call using the active provider (with proxy retries).
+    /// Attempt an RPC call using the active provider (with proxy retries), or through the
+    /// session these calls belong to.
     pub async fn try_rpc_call(&self, req: &JsonRpcRequest) -> Result<JsonRpcResponse<Value>> {
-        self.handler.try_proxy_request(req.clone()).await
-    }
-    
-    async fn consensus_attempt(
-        &self,
-        req: &JsonRpcRequest,
-        quorum_threshold: f64,
-        options: &ConsensusOptions,
-        allow_early_abort: bool,
-    ) -> Result<ConsensusAttemptResult> {
-        let timeout_ms = options.timeout_ms.unwrap_or(8000);
-        let concurrency = options.concurrency.unwrap_or(4);
-        let cooldown_ms = options.cooldown_ms.unwrap_or(30000);
-        
-        let now = Instant::now();
-        let cooldowns = self.cooldowns.read().await;
-        
-        let mut rpc_urls: Vec<String> = self.handler.rpcs
-            .iter()
-            .map(|rpc| rpc.url.to_string())
-            .filter(|url| !url.starts_with("wss://"))
-            .filter(|url| {
-                if let Some(cd) = cooldowns.get(url) {
-                    cd.until <= now
-                } else {
-                    true
-                }
-            })
-            .collect();
-        
-        drop(cooldowns);
-        
-        if rpc_urls.is_empty() {
-            return Err(RpcHandlerError::NoAvailableRpcs { 
-                network_id: self.handler.network_id 
-            });
-        }
-        
-        if rpc_urls.len() == 1 {
-            return Err(RpcHandlerError::ConsensusFailure {
-                most_common: "Only one RPC available, could not reach consensus".to_string(),
-            });
-        }
-        
-        // Randomize ordering
-        use rand::seq::SliceRandom;
-        let mut rng = rand::thread_rng();
-        rpc_urls.shuffle(&mut rng);
-        
-        let mut results = Vec::new();
-        let mut counts: HashMap<String, usize> = HashMap::new();
-        let mut key_to_value: HashMap<String, Value> = HashMap::new();
-        let mut aborted = false;
-        
-        let maybe_abort_early = |counts: &HashMap<String, usize>, results_len: usize, key: &str| {
-            if !allow_early_abort {
-                return false;
-            }
-            let dynamic_quorum = (results_len as f64 * quorum_threshold).ceil() as usize;
-            counts.get(key).unwrap_or(&0) >= &dynamic_quorum
-        };
-        
-        let run_request = move |url: String, req: JsonRpcRequest, client: reqwest::Client| async move {
-            let result = tokio::time::timeout(
-                Duration::from_millis(timeout_ms),
-                client.post(&url).json(&req).send()
-            ).await;
-            
-            match result {
-                Ok(Ok(response)) if response.status().is_success() => {
-                    match response.json::<JsonRpcResponse<Value>>().await {
-                        Ok(json_response) => {
-                            if let Some(result) = json_response.result {
-                                Ok((url, result))
-                            } else {
-                                Err((url, "No result in response".to_string()))
-                            }
-                        }
-                        Err(e) => Err((url, format!("JSON parse error: {}", e)))
-                    }
-                }
-                Ok(Ok(_)) => Err((url, "HTTP error".to_string())),
-                Ok(Err(e)) => Err((url, format!("Request error: {}", e))),
-                Err(_) => Err((url, "Timeout".to_string())),
-            }
-        };
-        
-        // Process URLs with concurrency limit
-        let mut index = 0;
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(concurrency));
-        let mut tasks = Vec::new();
-        
-        while index < rpc_urls.len() && !aborted {
-            let url = rpc_urls[index].clone();
-            let req = req.clone();
-            let client = self.client.clone();
-            let permit = semaphore.clone().acquire_owned().await.unwrap();
-            
-            let task = tokio::spawn(async move {
-                let _permit = permit;
-                run_request(url, req, client).await
-            });
-            
-            tasks.push(task);
-            index += 1;
-            
-            // Check if we can process some results
-            if tasks.len() >= concurrency || index >= rpc_urls.len() {
-                for task in tasks.drain(..) {
-                    match task.await {
-                        Ok(Ok((_url, result))) => {
-                            results.push(result.clone());
-                            let key = self.stable_string(&result);
-                            let count = counts.entry(key.clone()).or_insert(0);
-                            *count += 1;
-                            key_to_value.insert(key.clone(), result);
-                            
-                            if maybe_abort_early(&counts, results.len(), &key) {
-                                aborted = true;
-                                break;
-                            }
-                        }
-                        Ok(Err((url, error))) => {
-                            self.apply_cooldown(&url, cooldown_ms, error.contains("429")).await;
-                        }
-                        Err(_) => {
-                            // Task panicked
-                        }
-                    }
-                }
-            }
-        }
-        
-        if results.is_empty() {
-            return Ok(ConsensusAttemptResult {
-                success: false,
-                value: None,
-                counts,
-                results,
-                most_common_key: None,
-                key_to_value,
-            });
-        }
-        
-        let final_quorum = (results.len() as f64 * quorum_threshold).ceil() as usize;
-        let most_common_key = counts
-            .iter()
-            .max_by_key(|(_, count)| *count)
-            .map(|(key, _)| key.clone());
-        
-        if let Some(ref key) = most_common_key {
-            if counts.get(key).unwrap_or(&0) >= &final_quorum {
-                return Ok(ConsensusAttemptResult {
-                    success: true,
-                    value: key_to_value.get(key).cloned(),
-                    counts,
-                    results,
-                    most_common_key,
-                    key_to_value,
-                });
-            }
-        }
-        
-        Ok(ConsensusAttemptResult {
-            success: false,
-            value: None,
-            counts,
-            results,
-            most_common_key,
-            key_to_value,
-        })
-    }
-    
-    fn stable_string(&self, val: &Value) -> String {
-        // Create a stable string representation for comparison
-        match val {
-            Value::String(s) => s.clone(),
-            _ => {
-                // Sort object keys for consistent comparison
-                let sorted = self.sort_value(val.clone());
-                serde_json::to_string(&sorted).unwrap_or_else(|_| "invalid".to_string())
-            }
+        match &self.snapshot {
+            Some(snapshot) => snapshot.send(&self.handler, req.clone()).await.map(|(response, _)| response),
+            None => self.handler.try_proxy_request(req.clone()).await,
         }
     }
-    
-    fn sort_value(&self, val: Value) -> Value {
-        match val {
-            Value::Object(mut obj) => {
-                let mut sorted_obj = serde_json::Map::new();
-                let mut keys: Vec<_> = obj.keys().cloned().collect();
-                keys.sort();
-                for key in keys {
-                    if let Some(value) = obj.remove(&key) {
-                        sorted_obj.insert(key, self.sort_value(value));
-                    }
-                }
-                Value::Object(sorted_obj)
-            }
-            Value::Array(arr) => {
-                Value::Array(arr.into_iter().map(|v| self.sort_value(v)).collect())
-            }
-            _ => val,
+
+    /// Like `try_rpc_call`, also returning the endpoint that answered.
+    pub(crate) async fn try_rpc_call_attributed(&self, req: JsonRpcRequest) -> Result<(JsonRpcResponse<Value>, String)> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.send(&self.handler, req).await,
+            None => self.handler.try_proxy_request_attributed(req).await,
         }
     }
     
-    async fn apply_cooldown(&self, url: &str, base_ms: u64, is_rate_limit: bool) {
-        let mut cooldowns = self.cooldowns.write().await;
-        let existing = cooldowns.get(url);
-        let strikes = existing.map(|cd| cd.strikes).unwrap_or(0) + 1;
-        
-        let factor: f64 = if is_rate_limit { 2.0 } else { 1.5 };
-        let delay = ((base_ms as f64) * factor.powi(strikes as i32 - 1)) as u64;
-        let delay = delay.min(5 * 60 * 1000); // Cap at 5 minutes
-        
-        cooldowns.insert(url.to_string(), CooldownInfo {
-            strikes,
-            until: Instant::now() + Duration::from_millis(delay),
-        });
-        
-        // Log cooldown if handler has logging
-        tracing::warn!(
-            url = %url,
-            strikes = strikes,
-            delay_ms = delay,
-            "Cooling down provider"
-        );
+    /// HTTP endpoints a fan-out of `method` goes to, cooldowns aside.
+    ///
+    /// Routed methods stay on the designated endpoints unless failover is allowed, endpoints
+    /// known not to speak JSON-RPC are left out rather than cooled down again, and so are
+    /// endpoints in scheduled maintenance and those out of spend budget.
+    pub(crate) fn fan_out_urls(&self, method: &str, now: Instant) -> Vec<String> {
+        let candidate_urls: Vec<String> = match route_for(&self.handler.config().routes, method) {
+            Some(rule) if !rule.allow_failover => rule.normalized_urls(),
+            _ => self.handler.rpcs().iter().map(|rpc| rpc.url.to_string()).collect(),
+        };
+        let schedule = self.handler.probe_schedule();
+        let maintenance = self.handler.maintenance();
+        let now_system = self.clock.now_system();
+        let over_budget = self.handler.spend_meter().exhausted();
+        candidate_urls
+            .into_iter()
+            .filter(|url| !url.starts_with("wss://") && !schedule.should_skip(url, now))
+            .filter(|url| maintenance.in_window_until(url, now_system).is_none())
+            .filter(|url| !over_budget.contains(url))
+            .collect()
     }
 }
-
-#[derive(Debug)]
-struct ConsensusAttemptResult {
-    success: bool,
-    value: Option<Value>,
-    counts: HashMap<String, usize>,
-    results: Vec<Value>,
-    most_common_key: Option<String>,
-    key_to_value: HashMap<String, Value>,
-}
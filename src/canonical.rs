@@ -0,0 +1,129 @@
+//! Canonical forms of request params, so that calls meaning the same thing share a key.
+//!
+//! `["0xAbC…", "latest"]` and `["0xabc…", "latest"]` ask for the same balance, and `"0x010"`,
+//! `"0x10"` and `16` name the same block. Normalization follows the method's registered param
+//! schemas, so only slots the registry declares as an address, a hash or a quantity are touched:
+//! calldata, storage keys, topics and anything unregistered keep their exact bytes. Block tags
+//! stay as they are, and object keys are sorted everywhere.
+
+use serde_json::{Map, Value};
+use sha3::{Digest, Keccak256};
+
+use crate::{comparator::{sort_value, stable_string}, methods};
+
+/// `params` in canonical form, with what looked wrong on the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanonicalParams {
+    pub params: Value,
+    /// e.g. a mixed-case address whose EIP-55 checksum doesn't hold; empty unless checksums
+    /// were validated
+    pub warnings: Vec<String>,
+}
+
+impl CanonicalParams {
+    /// The cache key of a `method` call with these params.
+    pub fn key(&self, method: &str) -> String {
+        format!("{method}:{}", stable_string(&self.params))
+    }
+}
+
+/// Canonicalize the params of a `method` call. With `validate_checksums`, mixed-case addresses
+/// are checked against their EIP-55 checksum and a warning is recorded for each that fails.
+pub fn canonicalize_params(method: &str, params: &Value, validate_checksums: bool) -> CanonicalParams {
+    let mut warnings = Vec::new();
+    let schemas = methods::descriptor(method).and_then(|descriptor| descriptor.params_schema.as_array());
+    let params = match (params, schemas) {
+        (Value::Array(items), Some(schemas)) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| match schemas.get(index) {
+                    Some(slot) => canonicalize(item, &slot["schema"], validate_checksums, &mut warnings),
+                    None => item.clone(),
+                })
+                .collect(),
+        ),
+        _ => params.clone(),
+    };
+    CanonicalParams { params: sort_value(params), warnings }
+}
+
+/// Key of a `method` call that is equal for every call with equivalent params.
+pub fn request_key(method: &str, params: &Value) -> String {
+    canonicalize_params(method, params, false).key(method)
+}
+
+/// Whether `address` is all lowercase, all uppercase or carries a valid EIP-55 checksum.
+pub fn is_valid_checksum(address: &str) -> bool {
+    let Some(hex) = address.strip_prefix("0x").filter(|hex| hex.len() == 40 && hex.bytes().all(|byte| byte.is_ascii_hexdigit())) else {
+        return false;
+    };
+    if !hex.bytes().any(|byte| byte.is_ascii_lowercase()) || !hex.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        return true;
+    }
+    let hash = Keccak256::digest(hex.to_ascii_lowercase().as_bytes());
+    hex.bytes().enumerate().all(|(index, byte)| {
+        let nibble = (hash[index / 2] >> if index % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        !byte.is_ascii_alphabetic() || byte.is_ascii_uppercase() == (nibble >= 8)
+    })
+}
+
+/// `value` normalized as the registry's `schema` describes it, by the schema's title.
+fn canonicalize(value: &Value, schema: &Value, validate_checksums: bool, warnings: &mut Vec<String>) -> Value {
+    match (schema["title"].as_str(), value) {
+        (Some("address"), Value::String(address)) if is_hex(address, Some(40)) => {
+            if validate_checksums && !is_valid_checksum(address) {
+                warnings.push(format!("{address} has an invalid EIP-55 checksum"));
+            }
+            Value::String(address.to_ascii_lowercase())
+        }
+        (Some("32 byte hash"), Value::String(hash)) if is_hex(hash, Some(64)) => Value::String(hash.to_ascii_lowercase()),
+        (Some("hex quantity" | "block number or tag"), _) => quantity(value).unwrap_or_else(|| value.clone()),
+        (_, Value::Object(object)) if schema["properties"].is_object() => Value::Object(
+            object
+                .iter()
+                .map(|(key, field)| {
+                    let field = match schema["properties"].get(key) {
+                        Some(field_schema) => canonicalize(field, field_schema, validate_checksums, warnings),
+                        None => field.clone(),
+                    };
+                    (key.clone(), field)
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        (_, Value::Array(items)) if schema["items"].is_object() => {
+            Value::Array(items.iter().map(|item| canonicalize(item, &schema["items"], validate_checksums, warnings)).collect())
+        }
+        // e.g. an address or a list of them: the first alternative the value's shape fits
+        _ if let Some(alternatives) = schema["oneOf"].as_array() => {
+            let fits = |alternative: &&Value| match value {
+                Value::Array(_) => alternative["type"] == "array",
+                _ => alternative["type"] != "array",
+            };
+            match alternatives.iter().find(fits) {
+                Some(alternative) => canonicalize(value, alternative, validate_checksums, warnings),
+                None => value.clone(),
+            }
+        }
+        _ => value.clone(),
+    }
+}
+
+/// A hex quantity as minimal lowercase hex, from a hex string or a JSON number. Tags and
+/// anything else that isn't a quantity give `None`.
+fn quantity(value: &Value) -> Option<Value> {
+    let digits = match value {
+        Value::Number(number) => format!("{:x}", number.as_u64()?),
+        Value::String(hex) if is_hex(hex, None) => hex[2..].trim_start_matches('0').to_ascii_lowercase(),
+        _ => return None,
+    };
+    let digits = digits.trim_start_matches('0');
+    Some(Value::String(format!("0x{}", if digits.is_empty() { "0" } else { digits })))
+}
+
+/// Whether `value` is `0x` followed by hex digits, `len` of them if given.
+fn is_hex(value: &str, len: Option<usize>) -> bool {
+    value.strip_prefix("0x").is_some_and(|hex| {
+        !hex.is_empty() && len.is_none_or(|len| hex.len() == len) && hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+    })
+}
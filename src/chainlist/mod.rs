@@ -0,0 +1,286 @@
+#[cfg(feature = "chainlist")]
+pub mod source;
+pub mod view;
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, LazyLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::types::{NetworkId, Rpc};
+#[cfg(feature = "chainlist")]
+use crate::Result;
+#[cfg(feature = "chainlist")]
+use source::{fetch_registry_snapshot, ChainRegistry, RegistrySnapshot, SourceOptions};
+pub use view::ChainView;
+use url::Url;
+
+/// One of the chain data statics: embedded at build time, replaced in place by a refresh.
+pub type ChainTable<T> = LazyLock<Arc<Mutex<Vec<T>>>>;
+
+// Include the build-time generated chainlist data
+#[cfg(feature = "chainlist")]
+include!(concat!(env!("OUT_DIR"), "/chainlist_data.rs"));
+
+// Without the `chainlist` feature nothing is embedded: the same statics, always empty, so only
+// configured RPCs are used.
+#[cfg(not(feature = "chainlist"))]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChainInfo {
+    pub chain_id: NetworkId,
+    pub name: String,
+    pub tvl: f64,
+}
+
+#[cfg(not(feature = "chainlist"))]
+pub static CHAIN_DATA: ChainTable<ChainInfo> = LazyLock::new(Default::default);
+
+#[cfg(not(feature = "chainlist"))]
+pub static CHAIN_IDS: ChainTable<(NetworkId, String)> = LazyLock::new(Default::default);
+
+#[cfg(not(feature = "chainlist"))]
+pub static EXTRA_RPCS_DATA: ChainTable<(NetworkId, Vec<String>)> = LazyLock::new(Default::default);
+
+#[cfg(not(feature = "chainlist"))]
+pub static CHAIN_ALIASES: ChainTable<(NetworkId, NetworkId)> = LazyLock::new(Default::default);
+
+#[cfg(not(feature = "chainlist"))]
+pub const DATA_GENERATED_AT: Option<u64> = None;
+#[cfg(not(feature = "chainlist"))]
+pub const DATA_SOURCES: &[(&str, Option<&str>)] = &[];
+#[cfg(not(feature = "chainlist"))]
+pub const DATA_CHAINS_COUNT: usize = 0;
+#[cfg(not(feature = "chainlist"))]
+pub const DATA_RPCS_COUNT: usize = 0;
+
+/// Where the chain data in memory came from, and when.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DataProvenance {
+    /// When the data was fetched; `None` when there was nothing to fetch it from, as in an
+    /// offline build or without the `chainlist` feature
+    pub generated_at: Option<SystemTime>,
+    pub chains_count: usize,
+    pub rpcs_count: usize,
+    pub source_urls: Vec<String>,
+    /// The `ETag` each source was served with, by URL, for the sources that sent one
+    pub etags: BTreeMap<String, String>,
+}
+
+impl DataProvenance {
+    /// The embedded data's, from the `DATA_*` constants the build script generated.
+    pub fn embedded() -> Self {
+        Self {
+            generated_at: DATA_GENERATED_AT.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            chains_count: DATA_CHAINS_COUNT,
+            rpcs_count: DATA_RPCS_COUNT,
+            source_urls: DATA_SOURCES.iter().map(|(url, _)| url.to_string()).collect(),
+            etags: DATA_SOURCES.iter().filter_map(|&(url, etag)| Some((url.to_string(), etag?.to_string()))).collect(),
+        }
+    }
+
+    /// Whether the data is the empty stand-in for data that couldn't be fetched.
+    pub fn is_offline(&self) -> bool {
+        self.generated_at.is_none()
+    }
+
+    /// How old the data is at `now`, `Duration::MAX` when offline.
+    pub fn age_at(&self, now: SystemTime) -> Duration {
+        self.generated_at.map_or(Duration::MAX, |at| now.duration_since(at).unwrap_or_default())
+    }
+}
+
+#[cfg(feature = "chainlist")]
+impl From<&RegistrySnapshot> for DataProvenance {
+    fn from(snapshot: &RegistrySnapshot) -> Self {
+        Self {
+            generated_at: snapshot.generated_at,
+            chains_count: snapshot.registry.chains.len(),
+            rpcs_count: snapshot.registry.chains.iter().map(|chain| chain.rpcs.len()).sum(),
+            source_urls: snapshot.sources.iter().map(|source| source.url.clone()).collect(),
+            etags: snapshot.sources.iter().filter_map(|source| Some((source.url.clone(), source.etag.clone()?))).collect(),
+        }
+    }
+}
+
+/// A value read from the chain data, with the provenance of the data it was read from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WithProvenance<T> {
+    pub value: T,
+    pub provenance: DataProvenance,
+}
+
+static PROVENANCE: LazyLock<Mutex<DataProvenance>> = LazyLock::new(|| Mutex::new(DataProvenance::embedded()));
+
+/// Where the chain data in memory came from: the embedded data's, until a refresh replaces it.
+pub fn data_provenance() -> DataProvenance {
+    PROVENANCE.lock().clone()
+}
+
+/// How old the chain data in memory is, `Duration::MAX` when there is none to speak of.
+pub fn data_age() -> Duration {
+    data_provenance().age_at(crate::clock::system_now())
+}
+
+/// Prune the shared chain data down to `chains_to_retain`, for every handler in the process.
+///
+/// Handlers don't call this; they see the data through a `ChainView` scoped by their `DataScope`.
+pub fn initialize_chain_data(chains_to_retain: Vec<NetworkId>) {
+    /*
+     * Calling `.lock()` on a mutex gives us a guard object that holds the lock
+     * until it goes out of scope. Each lock is placed into own scope so that it
+     * is released immediately after data is filtered.
+     *
+     * This avoids holding multiple locks at the same time, which reduces the risk
+     * of deadlocks and improves concurrency.
+     */
+
+    {
+        let mut chain_data = CHAIN_DATA.lock();
+        chain_data.retain(|chain| chains_to_retain.contains(&chain.chain_id));
+    }
+
+    {
+        let mut chain_ids = CHAIN_IDS.lock();
+        chain_ids.retain(|(id, _)| chains_to_retain.contains(id));
+    }
+
+    {
+        let mut extra_rpcs = EXTRA_RPCS_DATA.lock();
+        extra_rpcs.retain(|(id,_)| chains_to_retain.contains(id));
+    }
+
+    {
+        let mut aliases = CHAIN_ALIASES.lock();
+        aliases.retain(|(a, b)| chains_to_retain.contains(a) || chains_to_retain.contains(b));
+    }
+}
+
+/// Replace the in-memory chain data with `registry`, taken to be current.
+///
+/// Any filtering from `initialize_chain_data` is undone; call it again to re-apply.
+#[cfg(feature = "chainlist")]
+pub fn apply_registry(registry: &ChainRegistry) {
+    let snapshot = RegistrySnapshot { registry: registry.clone(), generated_at: Some(crate::clock::system_now()), sources: Vec::new() };
+    apply_snapshot(&snapshot);
+}
+
+/// Like `apply_registry`, recording the snapshot's provenance.
+#[cfg(feature = "chainlist")]
+pub fn apply_snapshot(snapshot: &RegistrySnapshot) {
+    apply_chains(&snapshot.registry);
+    *PROVENANCE.lock() = DataProvenance::from(snapshot);
+}
+
+#[cfg(feature = "chainlist")]
+fn apply_chains(registry: &ChainRegistry) {
+    let chains = &registry.chains;
+    *CHAIN_DATA.lock() = chains
+        .iter()
+        .map(|chain| ChainInfo { chain_id: chain.chain_id, name: chain.name.clone(), tvl: chain.tvl })
+        .collect();
+    *CHAIN_IDS.lock() = chains.iter().map(|chain| (chain.chain_id, chain.name.clone())).collect();
+    *EXTRA_RPCS_DATA.lock() = chains.iter().map(|chain| (chain.chain_id, chain.rpcs.clone())).collect();
+    *CHAIN_ALIASES.lock() = registry.aliases.clone();
+}
+
+/// Re-fetch the registry the build script embeds and apply it, returning the number of chains loaded.
+///
+/// The current data is left untouched if the fetch fails.
+#[cfg(feature = "chainlist")]
+pub async fn refresh_from_network(options: &SourceOptions) -> Result<usize> {
+    let snapshot = fetch_registry_snapshot(options).await?;
+    apply_snapshot(&snapshot);
+    Ok(snapshot.registry.chains.len())
+}
+
+pub fn get_chain_ids() -> Vec<(NetworkId, String)> {
+    CHAIN_IDS.lock().clone()
+}
+
+/// The chain's info, or when the data has none for `chain_id`, that of the first id in its
+/// alias group that has some. The returned `chain_id` then differs from the one asked for.
+pub fn get_chain_info(chain_id: NetworkId) -> Option<ChainInfo> {
+    let chains = CHAIN_DATA.lock();
+    let find = |id: NetworkId| chains.iter().find(|chain| chain.chain_id == id).cloned();
+    find(chain_id).or_else(|| alias_group(chain_id).into_iter().find_map(find))
+}
+
+/// `chain_id` and every id the chain data's aliases connect it to, sorted.
+pub fn alias_group(chain_id: NetworkId) -> Vec<NetworkId> {
+    connected(chain_id, &CHAIN_ALIASES.lock())
+}
+
+/// The ids `pairs` connect to `chain_id`, directly or through others, and `chain_id`, sorted.
+pub(crate) fn connected(chain_id: NetworkId, pairs: &[(NetworkId, NetworkId)]) -> Vec<NetworkId> {
+    let mut group = vec![chain_id];
+    let mut grew = true;
+    while grew {
+        grew = false;
+        for &(a, b) in pairs {
+            match (group.contains(&a), group.contains(&b)) {
+                (true, false) => group.push(b),
+                (false, true) => group.push(a),
+                _ => continue,
+            }
+            grew = true;
+        }
+    }
+    group.sort();
+    group
+}
+
+/// `get_chain_info`, with the provenance of the data it came from.
+pub fn get_chain_info_with_provenance(chain_id: NetworkId) -> Option<WithProvenance<ChainInfo>> {
+    Some(WithProvenance { value: get_chain_info(chain_id)?, provenance: data_provenance() })
+}
+
+/// `get_chains_by_tvl`, with the provenance of the TVL figures.
+pub fn get_chains_by_tvl_with_provenance() -> WithProvenance<Vec<ChainInfo>> {
+    WithProvenance { value: get_chains_by_tvl(), provenance: data_provenance() }
+}
+
+pub fn get_chains_by_tvl() -> Vec<ChainInfo> {
+    let mut chains = CHAIN_DATA.lock().clone();
+    chains.sort_by(|a, b| {
+        b.tvl
+            .partial_cmp(&a.tvl)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    chains
+}
+
+pub fn find_chains_by_name(name: &str) -> Vec<ChainInfo> {
+    let search_term = name.to_lowercase();
+    CHAIN_DATA
+        .lock()
+        .iter()
+        .filter(|chain| chain.name.to_lowercase().contains(&search_term))
+        .cloned()
+        .collect()
+}
+
+pub fn get_extra_rpcs(chain_id: NetworkId) -> Vec<Rpc> {
+    EXTRA_RPCS_DATA
+        .lock()
+        .iter()
+        .find(|(id, _)| *id == chain_id)
+        .map(|(_, rpcs)| rpcs_from_urls(rpcs))
+        .unwrap_or_default()
+}
+
+fn rpcs_from_urls(urls: &[String]) -> Vec<Rpc> {
+    urls.iter()
+        .filter_map(|rpc_url| {
+            Url::parse(rpc_url).ok().map(|url| Rpc {
+                url,
+                tracking: Some(crate::types::Tracking::None),
+                tracking_details: Some("None as default".to_string()),
+                is_open_source: Some(true),
+            })
+        })
+        .collect()
+}
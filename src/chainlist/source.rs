@@ -0,0 +1,417 @@
+//! Fetching and normalizing the public chain registry.
+//!
+//! This file is shared with `build.rs` through a `#[path]` module, so it may only depend on
+//! `std`, `serde`, `serde_json`, `reqwest` and `web_time` — nothing from the rest of the crate.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_CHAINS_URL: &str = "https://chainid.network/chains.json";
+pub const DEFAULT_TVL_URL: &str = "https://api.llama.fi/chains";
+/// Sent unless a user agent is configured, by the crate's clients and this fetcher alike.
+pub const DEFAULT_USER_AGENT: &str = concat!("ez-web3-rpc/", env!("CARGO_PKG_VERSION"));
+
+/// Placeholder some registry entries carry instead of a usable URL.
+const API_KEY_PLACEHOLDER: &str = "${INFURA_API_KEY}";
+
+#[derive(Debug)]
+pub enum SourceError {
+    Network(reqwest::Error),
+    /// Non-success status that couldn't be served from the cache either
+    Status { url: String, status: u16 },
+    Parse { url: String, error: serde_json::Error },
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Network(e) => write!(f, "network error: {e}"),
+            SourceError::Status { url, status } => write!(f, "{url} returned status {status}"),
+            SourceError::Parse { url, error } => write!(f, "invalid JSON from {url}: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<reqwest::Error> for SourceError {
+    fn from(e: reqwest::Error) -> Self {
+        SourceError::Network(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceOptions {
+    pub chains_url: String,
+    pub tvl_url: String,
+    /// Directory for cached responses; `None` always downloads
+    pub cache_dir: Option<PathBuf>,
+    pub timeout: Duration,
+    pub user_agent: String,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        Self {
+            chains_url: DEFAULT_CHAINS_URL.to_string(),
+            tvl_url: DEFAULT_TVL_URL.to_string(),
+            cache_dir: None,
+            timeout: Duration::from_secs(30),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+}
+
+/// One entry of `chains.json`, as published.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChainRecord {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    pub name: String,
+    pub rpc: Vec<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    /// The chain a rebranded or retired entry points to, e.g. `eip155-1`
+    #[serde(default, rename = "redirectChain")]
+    pub redirect_chain: Option<String>,
+    #[serde(default)]
+    pub parent: Option<ParentRecord>,
+}
+
+/// The `parent` of a `chains.json` entry.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ParentRecord {
+    /// `L2` and `shard` parents run a chain of their own; any other kind names the same chain
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// e.g. `eip155-1`
+    pub chain: String,
+}
+
+impl ChainRecord {
+    /// The chain this entry says it is another id of, if any.
+    pub fn alias_hint(&self) -> Option<u64> {
+        let parent = self.parent.as_ref().filter(|parent| !matches!(parent.kind.as_str(), "L2" | "shard"));
+        let target = self.redirect_chain.as_deref().or(parent.map(|parent| parent.chain.as_str()))?;
+        let id = target.strip_prefix("eip155-").unwrap_or(target).parse().ok()?;
+        (id != self.chain_id).then_some(id)
+    }
+}
+
+/// One entry of the TVL listing.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TvlRecord {
+    pub name: String,
+    pub tvl: f64,
+}
+
+/// A chain that survived filtering, with its name and RPC list normalized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistryChain {
+    pub chain_id: u64,
+    pub name: String,
+    pub tvl: f64,
+    pub rpcs: Vec<String>,
+}
+
+/// Normalized chain registry, sorted by TVL descending.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChainRegistry {
+    pub chains: Vec<RegistryChain>,
+    /// Pairs of ids the registry hints are the same chain, sorted; taken from every entry,
+    /// deprecated ones included
+    pub aliases: Vec<(u64, u64)>,
+}
+
+impl ChainRegistry {
+    /// Parse and normalize the raw `chains.json` and TVL documents.
+    pub fn from_json(chains_json: &str, tvl_json: &str) -> Result<Self, SourceError> {
+        let chains: Vec<ChainRecord> = serde_json::from_str(chains_json)
+            .map_err(|error| SourceError::Parse { url: "chains".to_string(), error })?;
+        let tvl: Vec<TvlRecord> = serde_json::from_str(tvl_json)
+            .map_err(|error| SourceError::Parse { url: "tvl".to_string(), error })?;
+        Ok(Self::from_records(chains, &tvl))
+    }
+
+    /// Drop deprecated and RPC-less chains, normalize what's left and attach TVL.
+    pub fn from_records(chains: Vec<ChainRecord>, tvl: &[TvlRecord]) -> Self {
+        let mut aliases: Vec<(u64, u64)> = chains.iter().filter_map(|chain| Some((chain.chain_id, chain.alias_hint()?))).collect();
+        aliases.sort();
+        aliases.dedup();
+
+        let mut out: Vec<RegistryChain> = chains
+            .into_iter()
+            .filter(|chain| chain.status.as_deref() != Some("deprecated"))
+            .filter_map(|chain| {
+                let rpcs = normalize_rpcs(chain.rpc);
+                if rpcs.is_empty() {
+                    return None;
+                }
+                Some(RegistryChain {
+                    chain_id: chain.chain_id,
+                    name: normalize_name(&chain.name),
+                    tvl: tvl_for(tvl, &chain.name),
+                    rpcs,
+                })
+            })
+            .collect();
+
+        // Stable, so chains without TVL keep their registry order
+        out.sort_by(|a, b| b.tvl.partial_cmp(&a.tvl).unwrap_or(std::cmp::Ordering::Equal));
+        Self { chains: out, aliases }
+    }
+
+    pub fn get(&self, chain_id: u64) -> Option<&RegistryChain> {
+        self.chains.iter().find(|chain| chain.chain_id == chain_id)
+    }
+
+    /// Keep only the given chain IDs, and the aliases of any of them.
+    pub fn retain_ids(&mut self, ids: &[u64]) {
+        self.chains.retain(|chain| ids.contains(&chain.chain_id));
+        self.aliases.retain(|(a, b)| ids.contains(a) || ids.contains(b));
+    }
+
+    /// Rust source for the `CHAIN_DATA`, `CHAIN_IDS`, `EXTRA_RPCS_DATA` and `CHAIN_ALIASES` statics.
+    ///
+    /// The including scope must provide `NetworkId` and `ChainTable`; `ChainInfo` is emitted
+    /// alongside.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        output.push_str("// Auto-generated chainlist data -- DO NOT EDIT\n\n");
+
+        output.push_str("#[derive(Debug, Clone, serde::Serialize)]\n");
+        output.push_str("pub struct ChainInfo {\n");
+        output.push_str("   pub chain_id: NetworkId,\n");
+        output.push_str("   pub name: String,\n");
+        output.push_str("   pub tvl: f64,\n");
+        output.push_str("}\n\n");
+
+        output.push_str("pub static CHAIN_DATA: ChainTable<ChainInfo> = std::sync::LazyLock::new(|| {\n");
+        output.push_str("   std::sync::Arc::new(parking_lot::Mutex::new(vec![\n");
+        for chain in &self.chains {
+            output.push_str(&format!(
+                "       ChainInfo {{ chain_id: {}, name: {:?}.to_string(), tvl: {:.1} }},\n",
+                chain.chain_id, chain.name, chain.tvl
+            ));
+        }
+        output.push_str("   ]))\n");
+        output.push_str("});\n\n");
+
+        output.push_str("pub static CHAIN_IDS: ChainTable<(NetworkId, String)> = std::sync::LazyLock::new(|| {\n");
+        output.push_str("   std::sync::Arc::new(parking_lot::Mutex::new(vec![\n");
+        for chain in &self.chains {
+            output.push_str(&format!("       ({}, {:?}.to_string()),\n", chain.chain_id, chain.name));
+        }
+        output.push_str("   ]))\n");
+        output.push_str("});\n\n");
+
+        output.push_str("pub static EXTRA_RPCS_DATA: ChainTable<(NetworkId, Vec<String>)> = std::sync::LazyLock::new(|| {\n");
+        output.push_str("   std::sync::Arc::new(parking_lot::Mutex::new(vec![\n");
+        for chain in &self.chains {
+            let rpcs: Vec<String> = chain.rpcs.iter().map(|rpc| format!("{rpc:?}.to_string()")).collect();
+            output.push_str(&format!("      ({}, vec![{}]),\n", chain.chain_id, rpcs.join(", ")));
+        }
+        output.push_str("   ]))\n");
+        output.push_str("});\n\n");
+
+        output.push_str("pub static CHAIN_ALIASES: ChainTable<(NetworkId, NetworkId)> = std::sync::LazyLock::new(|| {\n");
+        output.push_str(&format!("   std::sync::Arc::new(parking_lot::Mutex::new(vec!{:?}))\n", self.aliases));
+        output.push_str("});\n");
+
+        output
+    }
+}
+
+/// A registry with where and when it was fetched, as the build script embeds it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegistrySnapshot {
+    pub registry: ChainRegistry,
+    /// When the documents were fetched, or read from the cache in their place; `None` for the
+    /// `offline` sentinel
+    pub generated_at: Option<SystemTime>,
+    pub sources: Vec<SourceStamp>,
+}
+
+/// A document the registry was built from, and the `ETag` it was served with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceStamp {
+    pub url: String,
+    pub etag: Option<String>,
+}
+
+impl RegistrySnapshot {
+    /// What an offline build embeds: no chains, and no fetch time or sources to report.
+    pub fn offline() -> Self {
+        Self::default()
+    }
+
+    /// `ChainRegistry::render`, followed by the `DATA_*` constants describing the snapshot.
+    pub fn render(&self) -> String {
+        let mut output = self.registry.render();
+        let generated_at = self.generated_at.map(|at| at.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()));
+        let sources: Vec<(&str, Option<&str>)> = self.sources.iter().map(|source| (source.url.as_str(), source.etag.as_deref())).collect();
+        let rpcs: usize = self.registry.chains.iter().map(|chain| chain.rpcs.len()).sum();
+
+        output.push_str("\n// Unix seconds the documents were fetched at, `None` when the build couldn't fetch them\n");
+        output.push_str(&format!("pub const DATA_GENERATED_AT: Option<u64> = {generated_at:?};\n"));
+        output.push_str("// Each document's URL and the ETag it was served with\n");
+        output.push_str(&format!("pub const DATA_SOURCES: &[(&str, Option<&str>)] = &{sources:?};\n"));
+        output.push_str(&format!("pub const DATA_CHAINS_COUNT: usize = {};\n", self.registry.chains.len()));
+        output.push_str(&format!("pub const DATA_RPCS_COUNT: usize = {rpcs};\n"));
+        output
+    }
+}
+
+/// Lowercase, with spaces replaced by underscores.
+pub fn normalize_name(name: &str) -> String {
+    name.to_lowercase().replace(' ', "_")
+}
+
+/// Drop placeholder URLs, strip trailing slashes, then sort and dedup.
+pub fn normalize_rpcs(rpcs: Vec<String>) -> Vec<String> {
+    let mut rpcs: Vec<String> = rpcs
+        .into_iter()
+        .filter(|rpc| !rpc.contains(API_KEY_PLACEHOLDER))
+        .map(|rpc| remove_trailing_slash(&rpc))
+        .collect();
+    rpcs.sort();
+    rpcs.dedup();
+    rpcs
+}
+
+pub fn remove_trailing_slash(rpc: &str) -> String {
+    rpc.strip_suffix('/').unwrap_or(rpc).to_string()
+}
+
+fn tvl_for(tvl: &[TvlRecord], chain_name: &str) -> f64 {
+    tvl.iter()
+        .find(|t| t.name.eq_ignore_ascii_case(chain_name))
+        .map_or(0.0, |t| t.tvl)
+}
+
+/// Download both documents and build the registry.
+///
+/// With a `cache_dir`, requests are made conditional on the cached ETag/Last-Modified and a
+/// `304` is served from disk. A failed download also falls back to the cached copy when there is one.
+pub async fn fetch_chain_registry(options: &SourceOptions) -> Result<ChainRegistry, SourceError> {
+    Ok(fetch_registry_snapshot(options).await?.registry)
+}
+
+/// Like `fetch_chain_registry`, stamped with the fetch time and each document's `ETag`.
+pub async fn fetch_registry_snapshot(options: &SourceOptions) -> Result<RegistrySnapshot, SourceError> {
+    // Per request rather than on the client: a browser's client has no timeout of its own
+    let client = reqwest::Client::builder().user_agent(options.user_agent.as_str()).build()?;
+    let cache = options.cache_dir.as_deref();
+    let ((chains_json, chains_etag), (tvl_json, tvl_etag)) = tokio::try_join!(
+        fetch_cached(&client, &options.chains_url, options.timeout, cache),
+        fetch_cached(&client, &options.tvl_url, options.timeout, cache),
+    )?;
+
+    let chains: Vec<ChainRecord> = serde_json::from_str(&chains_json)
+        .map_err(|error| SourceError::Parse { url: options.chains_url.clone(), error })?;
+    let tvl: Vec<TvlRecord> = serde_json::from_str(&tvl_json)
+        .map_err(|error| SourceError::Parse { url: options.tvl_url.clone(), error })?;
+    Ok(RegistrySnapshot {
+        registry: ChainRegistry::from_records(chains, &tvl),
+        generated_at: Some(now()),
+        sources: vec![
+            SourceStamp { url: options.chains_url.clone(), etag: chains_etag },
+            SourceStamp { url: options.tvl_url.clone(), etag: tvl_etag },
+        ],
+    })
+}
+
+/// The wall clock, read through `web_time` since `SystemTime::now` panics in a browser.
+fn now() -> SystemTime {
+    UNIX_EPOCH + web_time::SystemTime::now().duration_since(web_time::UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Validators recorded next to a cached body.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheMeta {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Cache file stem for a URL: every non-alphanumeric character becomes `_`.
+pub fn cache_key(url: &str) -> String {
+    url.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// The body, and the `ETag` it was served with, cached copies included.
+async fn fetch_cached(client: &reqwest::Client, url: &str, timeout: Duration, cache_dir: Option<&Path>) -> Result<(String, Option<String>), SourceError> {
+    let Some(dir) = cache_dir else {
+        return fetch_plain(client, url, timeout).await;
+    };
+    let body_path = dir.join(format!("{}.json", cache_key(url)));
+    let meta_path = dir.join(format!("{}.meta", cache_key(url)));
+
+    let cached_body = std::fs::read_to_string(&body_path).ok();
+    let meta: CacheMeta = match cached_body {
+        Some(_) => std::fs::read_to_string(&meta_path)
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default(),
+        None => CacheMeta::default(),
+    };
+
+    let mut request = client.get(url).timeout(timeout);
+    if let Some(etag) = &meta.etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &meta.last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return cached_body.map(|body| (body, meta.etag)).ok_or(SourceError::Network(e)),
+    };
+
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED
+        && let Some(body) = cached_body
+    {
+        return Ok((body, meta.etag));
+    }
+    if !status.is_success() {
+        return cached_body.map(|body| (body, meta.etag)).ok_or(SourceError::Status { url: url.to_string(), status: status.as_u16() });
+    }
+
+    let fresh = CacheMeta {
+        etag: header_string(&response, header::ETAG),
+        last_modified: header_string(&response, header::LAST_MODIFIED),
+    };
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(e) => return cached_body.map(|body| (body, meta.etag)).ok_or(SourceError::Network(e)),
+    };
+
+    // The cache is best-effort; a read-only directory only costs the next download
+    if std::fs::create_dir_all(dir).is_ok()
+        && std::fs::write(&body_path, &body).is_ok()
+        && let Ok(raw) = serde_json::to_string(&fresh)
+    {
+        let _ = std::fs::write(&meta_path, raw);
+    }
+    Ok((body, fresh.etag))
+}
+
+async fn fetch_plain(client: &reqwest::Client, url: &str, timeout: Duration) -> Result<(String, Option<String>), SourceError> {
+    let response = client.get(url).timeout(timeout).send().await?;
+    if !response.status().is_success() {
+        return Err(SourceError::Status { url: url.to_string(), status: response.status().as_u16() });
+    }
+    let etag = header_string(&response, header::ETAG);
+    Ok((response.text().await?, etag))
+}
+
+fn header_string(response: &reqwest::Response, name: header::HeaderName) -> Option<String> {
+    response.headers().get(name)?.to_str().ok().map(str::to_string)
+}
@@ -0,0 +1,70 @@
+//! What one handler sees of the chain data.
+//!
+//! The chain data is shared by every handler in the process, so a handler never prunes it.
+//! A scoped handler instead takes a snapshot of the networks its `DataScope` keeps, while a
+//! `Global` one reads the shared data as it is, including later `refresh_from_network` updates.
+
+use std::sync::Arc;
+
+use super::{alias_group, rpcs_from_urls, ChainInfo, CHAIN_DATA, CHAIN_IDS, EXTRA_RPCS_DATA};
+use crate::types::{NetworkId, Rpc};
+
+#[derive(Debug, Clone)]
+pub struct ChainView {
+    snapshot: Option<Arc<Snapshot>>,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    chains: Vec<ChainInfo>,
+    extra_rpcs: Vec<(NetworkId, Vec<String>)>,
+}
+
+impl ChainView {
+    /// A view of all the shared data.
+    pub fn global() -> Self {
+        Self { snapshot: None }
+    }
+
+    /// A snapshot of the shared data for `networks` only, unaffected by later changes to it.
+    pub fn scoped(networks: &[NetworkId]) -> Self {
+        let chains = CHAIN_DATA.lock().iter().filter(|chain| networks.contains(&chain.chain_id)).cloned().collect();
+        let extra_rpcs = EXTRA_RPCS_DATA.lock().iter().filter(|(id, _)| networks.contains(id)).cloned().collect();
+        Self { snapshot: Some(Arc::new(Snapshot { chains, extra_rpcs })) }
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.snapshot.is_none()
+    }
+
+    pub fn chain_ids(&self) -> Vec<(NetworkId, String)> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.chains.iter().map(|chain| (chain.chain_id, chain.name.clone())).collect(),
+            None => CHAIN_IDS.lock().clone(),
+        }
+    }
+
+    /// Like `chainlist::get_chain_info`, following aliases to a chain in view.
+    pub fn chain_info(&self, chain_id: NetworkId) -> Option<ChainInfo> {
+        let find = |chains: &[ChainInfo], id: NetworkId| chains.iter().find(|chain| chain.chain_id == id).cloned();
+        let group = alias_group(chain_id);
+        let mut ids = std::iter::once(chain_id).chain(group.into_iter().filter(|id| *id != chain_id));
+        match &self.snapshot {
+            Some(snapshot) => ids.find_map(|id| find(&snapshot.chains, id)),
+            None => {
+                let chains = CHAIN_DATA.lock();
+                ids.find_map(|id| find(&chains, id))
+            }
+        }
+    }
+
+    pub fn extra_rpcs(&self, chain_id: NetworkId) -> Vec<Rpc> {
+        let find = |extra: &[(NetworkId, Vec<String>)]| {
+            extra.iter().find(|(id, _)| *id == chain_id).map(|(_, urls)| rpcs_from_urls(urls)).unwrap_or_default()
+        };
+        match &self.snapshot {
+            Some(snapshot) => find(&snapshot.extra_rpcs),
+            None => find(&EXTRA_RPCS_DATA.lock()),
+        }
+    }
+}
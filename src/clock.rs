@@ -0,0 +1,173 @@
+//! Time source for every time-based decision in the crate: cooldowns, retry backoff, TTLs,
+//! keepalive scheduling and latency timestamps.
+//!
+//! Request timeouts stay on the runtime's timer since they bound real network I/O.
+//!
+//! `Instant` is `std::time::Instant` natively. On `wasm32`, where std's clock panics, it is
+//! `web_time`'s, read from `performance.now()`.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+pub use web_time::Instant;
+
+use crate::runtime;
+
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now_instant(&self) -> Instant;
+    fn now_system(&self) -> SystemTime;
+    async fn sleep(&self, duration: Duration);
+
+    /// Time elapsed since `earlier` according to this clock.
+    fn elapsed_since(&self, earlier: Instant) -> Duration {
+        self.now_instant().saturating_duration_since(earlier)
+    }
+}
+
+/// Wall-clock time and the runtime's sleeps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        system_now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        runtime::sleep(duration).await;
+    }
+}
+
+/// The wall clock, through `Date.now()` on `wasm32` since `SystemTime::now` panics there.
+pub(crate) fn system_now() -> SystemTime {
+    if cfg!(target_arch = "wasm32") {
+        SystemTime::UNIX_EPOCH + web_time::SystemTime::now().duration_since(web_time::UNIX_EPOCH).unwrap_or_default()
+    } else {
+        SystemTime::now()
+    }
+}
+
+/// The clock used when none is injected.
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(feature = "test-util")]
+pub use mock::MockClock;
+
+#[cfg(feature = "test-util")]
+mod mock {
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use tokio::sync::oneshot;
+
+    use super::{system_now, Clock, Instant};
+    use crate::runtime;
+
+    struct State {
+        elapsed: Duration,
+        sleepers: Vec<(Duration, oneshot::Sender<()>)>,
+    }
+
+    /// A clock that only moves when `advance` is called. Sleeps complete once the clock has been
+    /// advanced past their deadline, so time-dependent behavior can be tested without real waits.
+    #[derive(Clone)]
+    pub struct MockClock {
+        start_instant: Instant,
+        start_system: SystemTime,
+        state: Arc<Mutex<State>>,
+    }
+
+    impl Default for MockClock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl std::fmt::Debug for MockClock {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let state = self.state.lock();
+            f.debug_struct("MockClock")
+                .field("elapsed", &state.elapsed)
+                .field("sleepers", &state.sleepers.len())
+                .finish()
+        }
+    }
+
+    impl MockClock {
+        pub fn new() -> Self {
+            Self {
+                start_instant: Instant::now(),
+                start_system: system_now(),
+                state: Arc::new(Mutex::new(State { elapsed: Duration::ZERO, sleepers: Vec::new() })),
+            }
+        }
+
+        /// Move the clock forward and wake every sleep whose deadline has passed.
+        pub fn advance(&self, duration: Duration) {
+            let mut state = self.state.lock();
+            state.elapsed += duration;
+            let now = state.elapsed;
+            let (due, pending) = std::mem::take(&mut state.sleepers)
+                .into_iter()
+                .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+            state.sleepers = pending;
+            drop(state);
+
+            for (_, waker) in due {
+                let _ = waker.send(());
+            }
+        }
+
+        /// Number of sleeps currently waiting on the clock.
+        pub fn sleepers(&self) -> usize {
+            self.state.lock().sleepers.iter().filter(|(_, waker)| !waker.is_closed()).count()
+        }
+
+        /// Yield until at least `count` sleeps are waiting, so a test can advance at the right moment.
+        pub async fn wait_for_sleepers(&self, count: usize) {
+            while self.sleepers() < count {
+                runtime::yield_now().await;
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Clock for MockClock {
+        fn now_instant(&self) -> Instant {
+            self.start_instant + self.state.lock().elapsed
+        }
+
+        fn now_system(&self) -> SystemTime {
+            self.start_system + self.state.lock().elapsed
+        }
+
+        async fn sleep(&self, duration: Duration) {
+            if duration.is_zero() {
+                return;
+            }
+            let receiver = {
+                let mut state = self.state.lock();
+                let (sender, receiver) = oneshot::channel();
+                let deadline = state.elapsed + duration;
+                state.sleepers.push((deadline, sender));
+                receiver
+            };
+            let _ = receiver.await;
+        }
+    }
+}
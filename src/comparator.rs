@@ -0,0 +1,210 @@
+//! Equality plugins for consensus: how responses are grouped and which value represents a group.
+
+use std::fmt::Debug;
+
+use serde_json::{Map, Value};
+
+use crate::namespaces::parse_quantity;
+
+pub trait ResultComparator: Send + Sync + Debug {
+    /// Project a response into the key of its equivalence class; responses with equal keys agree.
+    fn key(&self, value: &Value) -> String;
+
+    /// The value returned for a winning class. Defaults to the first response in it.
+    fn merge(&self, values: &[&Value]) -> Value {
+        values.first().map(|value| (*value).clone()).unwrap_or(Value::Null)
+    }
+}
+
+/// Exact agreement: strings compare as-is, everything else by its JSON with object keys sorted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StableStringComparator;
+
+impl ResultComparator for StableStringComparator {
+    fn key(&self, value: &Value) -> String {
+        stable_string(value)
+    }
+}
+
+/// Blocks agree when their hashes match, whatever extra fields each provider adds.
+///
+/// Non-block results (including `null`) fall back to exact comparison.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashOnlyBlockComparator;
+
+impl ResultComparator for HashOnlyBlockComparator {
+    fn key(&self, value: &Value) -> String {
+        match value.get("hash").and_then(Value::as_str) {
+            Some(hash) => format!("hash:{}", hash.to_lowercase()),
+            None => stable_string(value),
+        }
+    }
+}
+
+/// `eth_getProof` results agree when the account fields, storage root and slot values match.
+///
+/// The proof node arrays are left out: clients may legitimately return different but equally
+/// valid node sets. Quantities compare by value, hashes case-insensitively; non-proof results
+/// fall back to exact comparison.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofComparator;
+
+impl ResultComparator for ProofComparator {
+    fn key(&self, value: &Value) -> String {
+        let field = |name: &str| value.get(name).and_then(Value::as_str).map(normalize_hex);
+        let (Some(storage_hash), Some(Value::Array(slots))) = (field("storageHash"), value.get("storageProof")) else {
+            return stable_string(value);
+        };
+        let mut slots: Vec<(String, String)> = slots
+            .iter()
+            .map(|slot| {
+                let quantity = |name: &str| slot.get(name).and_then(Value::as_str).map(normalize_hex).unwrap_or_default();
+                (quantity("key"), quantity("value"))
+            })
+            .collect();
+        slots.sort();
+        let projected = serde_json::json!({
+            "address": field("address"),
+            "balance": field("balance"),
+            "codeHash": field("codeHash"),
+            "nonce": field("nonce"),
+            "storageHash": storage_hash,
+            "storage": slots,
+        });
+        stable_string(&projected)
+    }
+}
+
+/// Lowercase hex without leading zeros, so `0x0A` and `0x000a` compare equal.
+fn normalize_hex(value: &str) -> String {
+    let digits = value.strip_prefix("0x").unwrap_or(value).trim_start_matches('0').to_ascii_lowercase();
+    format!("0x{digits}")
+}
+
+/// Numeric fields agree within a relative `tolerance` (e.g. `0.05` for 5%); every other field must
+/// match exactly. The winning class is returned with each listed field replaced by its median.
+///
+/// Numbers are placed on a geometric grid with ratio `1 + tolerance`, so values sharing a key are
+/// always within `tolerance` of each other. Values close to a grid line may land in neighbouring
+/// cells even when within tolerance, which can cost quorum but never admits a wider spread.
+/// Fields may hold quantities (hex or decimal) or arrays of them, as in `eth_feeHistory`.
+#[derive(Debug, Clone)]
+pub struct NumericToleranceComparator {
+    pub fields: Vec<String>,
+    pub tolerance: f64,
+}
+
+impl NumericToleranceComparator {
+    pub fn new(fields: impl IntoIterator<Item = impl Into<String>>, tolerance: f64) -> Self {
+        Self { fields: fields.into_iter().map(Into::into).collect(), tolerance }
+    }
+
+    fn bucket(&self, value: &Value) -> Value {
+        match value {
+            Value::Array(items) => Value::Array(items.iter().map(|item| self.bucket(item)).collect()),
+            other => match as_f64(other) {
+                Some(n) if n > 0.0 && self.tolerance > 0.0 => Value::from((n.ln() / self.tolerance.ln_1p()).floor() as i64),
+                Some(n) => Value::from(n),
+                None => other.clone(),
+            },
+        }
+    }
+}
+
+impl ResultComparator for NumericToleranceComparator {
+    fn key(&self, value: &Value) -> String {
+        let Value::Object(object) = value else { return stable_string(value) };
+        let projected: Map<String, Value> = object
+            .iter()
+            .map(|(name, field)| {
+                let field = if self.fields.contains(name) { self.bucket(field) } else { field.clone() };
+                (name.clone(), field)
+            })
+            .collect();
+        stable_string(&Value::Object(projected))
+    }
+
+    fn merge(&self, values: &[&Value]) -> Value {
+        let Some(Value::Object(first)) = values.first().copied() else {
+            return values.first().map(|value| (*value).clone()).unwrap_or(Value::Null);
+        };
+        let mut merged = first.clone();
+        for name in &self.fields {
+            let samples: Vec<&Value> = values.iter().filter_map(|value| value.get(name)).collect();
+            if let Some(median) = median_value(&samples) {
+                merged.insert(name.clone(), median);
+            }
+        }
+        Value::Object(merged)
+    }
+}
+
+/// Element-wise median of values with the same shape, keeping the encoding of the first sample.
+fn median_value(samples: &[&Value]) -> Option<Value> {
+    let first = *samples.first()?;
+    match first {
+        Value::Array(items) => {
+            let merged = (0..items.len())
+                .map(|i| {
+                    let column: Vec<&Value> = samples.iter().filter_map(|sample| sample.get(i)).collect();
+                    median_value(&column).unwrap_or_else(|| items[i].clone())
+                })
+                .collect();
+            Some(Value::Array(merged))
+        }
+        _ => {
+            let mut numbers: Vec<f64> = samples.iter().filter_map(|sample| as_f64(sample)).collect();
+            if numbers.len() != samples.len() {
+                return Some(first.clone());
+            }
+            numbers.sort_by(|a, b| a.total_cmp(b));
+            let median = numbers[numbers.len() / 2];
+            Some(match first {
+                Value::String(s) if s.starts_with("0x") => Value::String(format!("{:#x}", median as u64)),
+                Value::String(_) => Value::String((median as u64).to_string()),
+                _ => Value::from(median),
+            })
+        }
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        other => parse_quantity(other).map(|n| n as f64),
+    }
+}
+
+/// Key of a `result: null`. No JSON text or hex string starts with a NUL, so it can't collide
+/// with a string result such as `"null"`.
+pub const NULL_KEY: &str = "\0null";
+
+/// Key of a response with neither `result` nor `error`: never equal to a `null` result.
+pub const MISSING_KEY: &str = "\0missing";
+
+/// Stable string representation used for exact comparison.
+pub fn stable_string(value: &Value) -> String {
+    match value {
+        Value::Null => NULL_KEY.to_string(),
+        Value::String(s) => s.clone(),
+        _ => serde_json::to_string(&sort_value(value.clone())).unwrap_or_else(|_| "invalid".to_string()),
+    }
+}
+
+pub(crate) fn sort_value(value: Value) -> Value {
+    match value {
+        Value::Object(mut object) => {
+            let mut keys: Vec<_> = object.keys().cloned().collect();
+            keys.sort();
+            let mut sorted = Map::new();
+            for key in keys {
+                if let Some(value) = object.remove(&key) {
+                    sorted.insert(key, sort_value(value));
+                }
+            }
+            Value::Object(sorted)
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(sort_value).collect()),
+        other => other,
+    }
+}
@@ -0,0 +1,232 @@
+//! Reads that carry a confidence score instead of a bare success.
+//!
+//! `call_with_confidence` sends the read through the proxy as usual and, alongside it, to up to
+//! `ConfidenceOptions::secondaries` other endpoints, the fastest measured first. Secondaries get
+//! at most `max_overhead` after the primary answers, so a slow one costs that much and no more;
+//! those still out are dropped and listed as unanswered.
+//!
+//! The score is computed from the `Evidence` by `Evidence::score`:
+//!
+//! ```text
+//! confidence = corroboration × agreement × finality
+//!
+//! corroboration = 1 − Π (1 − rᵢ)      over the endpoints whose answer matched the primary's
+//! agreement     = Σ rᵢ (matching) / Σ rᵢ (all that answered)
+//! finality      = 1 for finalized data, `unfinalized_factor` otherwise
+//! ```
+//!
+//! `rᵢ` is endpoint `i`'s reputation from agreement sampling: `(agreements + 1) / (samples + 2)`,
+//! `unknown_reputation` before it has been sampled, and 0 once it is flagged
+//! `SuspectedDishonest`. Corroboration is the chance at least one matching endpoint is honest,
+//! taking them as independent, so it rises with every reputable endpoint that agrees and stays at
+//! the primary's own reputation when none does. Agreement is the reputation-weighted share of
+//! answers on the primary's side. Data is finalized when the block it belongs to is at least
+//! `TIMESTAMP_FINALITY_DEPTH` below the head watermark, or was read at the `finalized` or
+//! `earliest` tag.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    agreement::AgreementStats,
+    block_search::TIMESTAMP_FINALITY_DEPTH,
+    calls::RpcCalls,
+    comparator::{ResultComparator, StableStringComparator},
+    namespaces::parse_quantity,
+    panics,
+    provider::TrafficClass,
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// Options for `RpcCalls::call_with_confidence`.
+#[derive(Debug, Clone)]
+pub struct ConfidenceOptions {
+    /// Other endpoints asked alongside the primary, at most
+    pub secondaries: usize,
+    /// How long the call waits for secondaries once the primary has answered
+    pub max_overhead: Duration,
+    /// Reputation of an endpoint agreement sampling hasn't sampled yet, within `0..=1`
+    pub unknown_reputation: f64,
+    /// Multiplies the score of data that isn't finalized, or whose block is unknown
+    pub unfinalized_factor: f64,
+    /// How answers are compared, exact comparison when `None`
+    pub comparator: Option<Arc<dyn ResultComparator>>,
+}
+
+impl Default for ConfidenceOptions {
+    fn default() -> Self {
+        Self { secondaries: 2, max_overhead: Duration::from_millis(150), unknown_reputation: 0.5, unfinalized_factor: 0.9, comparator: None }
+    }
+}
+
+/// A value with how far it can be trusted.
+#[derive(Debug, Clone)]
+pub struct ConfidentValue<T> {
+    pub value: T,
+    /// Within `0..=1`, see the module docs for how it is computed
+    pub confidence: f64,
+    pub evidence: Evidence,
+}
+
+/// How settled the block the data belongs to is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Finality {
+    /// At least `TIMESTAMP_FINALITY_DEPTH` below the head watermark
+    Finalized,
+    /// Closer to the head than that, or read at `latest`, `safe` or `pending`
+    NearHead,
+    /// Neither the block nor the head is known
+    Unknown,
+}
+
+/// One endpoint's answer to a confidence read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Corroboration {
+    pub url: String,
+    /// Its reputation when the read was scored
+    pub reputation: f64,
+    /// Whether its answer matched the primary's
+    pub agrees: bool,
+}
+
+/// What a confidence score was computed from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Evidence {
+    /// The endpoint the proxy got the returned value from
+    pub primary: String,
+    /// The primary first, then each secondary that answered in time
+    pub responses: Vec<Corroboration>,
+    /// Secondaries that failed, or were still out when `max_overhead` ran out
+    pub unanswered: Vec<String>,
+    /// The block the data belongs to, when the request or the result names one
+    pub block: Option<u64>,
+    pub finality: Finality,
+}
+
+impl Evidence {
+    /// The confidence score of this evidence; deterministic, see the module docs.
+    pub fn score(&self, options: &ConfidenceOptions) -> f64 {
+        let matching = self.responses.iter().filter(|response| response.agrees);
+        let corroboration = 1.0 - matching.clone().map(|response| 1.0 - response.reputation.clamp(0.0, 1.0)).product::<f64>();
+        let total: f64 = self.responses.iter().map(|response| response.reputation.clamp(0.0, 1.0)).sum();
+        let agreement = if total > 0.0 { matching.map(|response| response.reputation.clamp(0.0, 1.0)).sum::<f64>() / total } else { 0.0 };
+        let finality = match self.finality {
+            Finality::Finalized => 1.0,
+            Finality::NearHead | Finality::Unknown => options.unfinalized_factor.clamp(0.0, 1.0),
+        };
+        corroboration * agreement * finality
+    }
+}
+
+/// An endpoint's reputation from its agreement samples, `unknown` before it has any.
+pub fn reputation(stats: Option<&AgreementStats>, unknown: f64) -> f64 {
+    match stats {
+        Some(stats) if stats.suspected_dishonest => 0.0,
+        Some(stats) if stats.samples > 0 => (stats.agreements as f64 + 1.0) / (stats.samples as f64 + 2.0),
+        _ => unknown.clamp(0.0, 1.0),
+    }
+}
+
+/// The block `req` reads at or `result` belongs to, and how settled it is against `head`.
+fn finality(req: &JsonRpcRequest, result: &Value, head: Option<u64>) -> (Option<u64>, Finality) {
+    let from_result = ["blockNumber", "number"].iter().find_map(|field| result.get(field).and_then(parse_quantity));
+    let tag = req.params.as_array().and_then(|params| params.last()).and_then(Value::as_str);
+    let block = from_result.or_else(|| tag.and_then(|tag| parse_quantity(&Value::String(tag.to_string()))));
+    let finality = match (block, head, tag) {
+        (Some(block), Some(head), _) if block.saturating_add(TIMESTAMP_FINALITY_DEPTH) <= head => Finality::Finalized,
+        (Some(_), Some(_), _) => Finality::NearHead,
+        (_, _, Some("finalized" | "earliest")) => Finality::Finalized,
+        (_, _, Some("latest" | "safe" | "pending")) => Finality::NearHead,
+        _ => Finality::Unknown,
+    };
+    (block, finality)
+}
+
+impl RpcCalls {
+    /// Send `req` through the proxy, corroborate the answer with a few other endpoints within
+    /// `options.max_overhead`, and score how far it can be trusted. Fails only when the primary
+    /// read does.
+    pub async fn call_with_confidence<T>(&self, req: &JsonRpcRequest, options: ConfidenceOptions) -> Result<ConfidentValue<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let secondaries = self.confidence_secondaries(&req.method, options.secondaries).await;
+        let mut pending: FuturesUnordered<_> = secondaries
+            .iter()
+            .map(|url| async move { (url.clone(), self.handler.side_call(&self.client, url, req, TrafficClass::Request).await) })
+            .collect();
+
+        // Secondaries answer while the primary is in flight, and for `max_overhead` after it
+        let mut answers = Vec::new();
+        let primary = self.try_rpc_call_attributed(req.clone());
+        tokio::pin!(primary);
+        let (response, primary_url) = loop {
+            tokio::select! {
+                primary = &mut primary => break primary?,
+                Some(answer) = pending.next(), if !pending.is_empty() => answers.push(answer),
+            }
+        };
+        let result = response.into_result()?;
+        let overhead = self.clock.sleep(options.max_overhead);
+        tokio::pin!(overhead);
+        while !pending.is_empty() {
+            tokio::select! {
+                _ = &mut overhead => break,
+                Some(answer) = pending.next() => answers.push(answer),
+            }
+        }
+
+        let comparator = options.comparator.clone().unwrap_or_else(|| Arc::new(StableStringComparator));
+        let key = panics::contain(panics::COMPARATOR, || comparator.key(&result)).inspect_err(|panicked| self.handler.record_panic(panicked))?;
+        let stats = self.handler.agreement_stats();
+        let mut responses = vec![Corroboration { reputation: reputation(stats.get(&primary_url), options.unknown_reputation), url: primary_url.clone(), agrees: true }];
+        let mut unanswered: Vec<String> = secondaries.iter().filter(|url| !answers.iter().any(|(answered, _)| answered == *url)).cloned().collect();
+        for (url, answer) in answers {
+            match answer {
+                // The proxy may have landed on a secondary; its answer is already the primary's
+                _ if url == primary_url => {}
+                Some(value) => {
+                    // A secondary whose answer the comparator can't key doesn't corroborate it
+                    let agrees = match panics::contain(panics::COMPARATOR, || comparator.key(&value)) {
+                        Ok(answer) => answer == key,
+                        Err(panicked) => {
+                            self.handler.record_panic(&panicked);
+                            false
+                        }
+                    };
+                    responses.push(Corroboration { reputation: reputation(stats.get(&url), options.unknown_reputation), agrees, url })
+                }
+                None => unanswered.push(url),
+            }
+        }
+        unanswered.retain(|url| *url != primary_url);
+
+        let (block, finality) = finality(req, &result, self.handler.head_watermark());
+        let evidence = Evidence { primary: primary_url, responses, unanswered, block, finality };
+        let confidence = evidence.score(&options);
+        let value = serde_json::from_value(result).map_err(|e| RpcHandlerError::SerializationError(e.to_string()))?;
+        Ok(ConfidentValue { value, confidence, evidence })
+    }
+
+    /// Up to `count` endpoints to corroborate a `method` read with, fastest first: not the
+    /// active provider, nor one cooling down or flagged by agreement sampling.
+    async fn confidence_secondaries(&self, method: &str, count: usize) -> Vec<String> {
+        let active = self.handler.get_provider_url().await.ok();
+        let now = self.clock.now_instant();
+        let cooling_down: Vec<String> = self.cooldowns.read().await.iter().filter(|(_, cooldown)| cooldown.until > now).map(|(url, _)| url.clone()).collect();
+        let flagged = self.handler.agreement().flagged();
+        let latencies: HashMap<String, u64> = self.handler.get_latencies().await;
+
+        let mut urls: Vec<String> = self
+            .fan_out_urls(method, now)
+            .into_iter()
+            .filter(|url| Some(url) != active.as_ref() && !cooling_down.contains(url) && !flagged.contains(url))
+            .collect();
+        urls.sort_by_key(|url| latencies.get(url).copied().unwrap_or(u64::MAX));
+        urls.truncate(count);
+        urls
+    }
+}
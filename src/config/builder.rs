@@ -0,0 +1,176 @@
+//! Builders for `HandlerSettings` and `HandlerConfig`, for overriding a few settings without
+//! spelling out the rest.
+//!
+//! `HandlerConfig::builder(network_id)` starts from `HandlerConfig::new`, and
+//! `HandlerSettings::builder()` from `HandlerSettings::default()`. Setters that can't fail take
+//! effect right away; the first invalid endpoint URL is kept and returned by `build`. Settings
+//! without a setter of their own are reachable through `with`.
+//!
+//! ```no_run
+//! # async fn run() -> ez_web3_rpc::Result<()> {
+//! use ez_web3_rpc::prelude::*;
+//!
+//! let config = HandlerConfig::builder(1).rpc_url("https://eth.example.com").probe_timeout_ms(2000).retry_count(5).build()?;
+//! let handler = RpcHandler::new(config, None).await?;
+//! handler.init().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    error::{Result, RpcHandlerError},
+    types::{DataScope, FailoverPolicy, HandlerConfig, HandlerSettings, HostLimits, LogLevel, NetworkId, ProxySettings, RpcConfig, Tracking, ValidationMode},
+    Rpc,
+};
+
+/// Builds a `HandlerSettings` from the defaults. See the module docs.
+#[derive(Debug, Default)]
+pub struct HandlerSettingsBuilder {
+    settings: HandlerSettings,
+    error: Option<RpcHandlerError>,
+}
+
+/// Builds a `HandlerConfig` from `HandlerConfig::new`. See the module docs.
+#[derive(Debug)]
+pub struct HandlerConfigBuilder {
+    network_id: NetworkId,
+    settings: HandlerSettings,
+    error: Option<RpcHandlerError>,
+}
+
+impl HandlerSettings {
+    pub fn builder() -> HandlerSettingsBuilder {
+        HandlerSettingsBuilder::default()
+    }
+}
+
+impl HandlerConfig {
+    pub fn builder(network_id: NetworkId) -> HandlerConfigBuilder {
+        let settings = Self::new(network_id).settings.unwrap_or_default();
+        HandlerConfigBuilder { network_id, settings, error: None }
+    }
+}
+
+impl HandlerSettingsBuilder {
+    /// The settings, or the first invalid endpoint URL given.
+    pub fn build(self) -> Result<HandlerSettings> {
+        self.error.map_or(Ok(self.settings), Err)
+    }
+}
+
+impl HandlerConfigBuilder {
+    /// The config, or the first invalid endpoint URL given.
+    pub fn build(self) -> Result<HandlerConfig> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(HandlerConfig { network_id: self.network_id, settings: Some(self.settings) }),
+        }
+    }
+}
+
+/// The setters both builders share, over their `settings` and `error` fields.
+macro_rules! settings_setters {
+    ($builder:ty) => {
+        impl $builder {
+            /// Add an endpoint by URL. An unparseable URL fails `build`.
+            pub fn rpc_url(mut self, url: &str) -> Self {
+                match Rpc::from_url(url) {
+                    Ok(rpc) => self.settings.network_rpcs.push(rpc.into()),
+                    Err(error) => {
+                        self.error.get_or_insert(error);
+                    }
+                }
+                self
+            }
+
+            /// Add endpoints by URL, as `rpc_url` does for each.
+            pub fn rpc_urls<'a>(self, urls: impl IntoIterator<Item = &'a str>) -> Self {
+                urls.into_iter().fold(self, Self::rpc_url)
+            }
+
+            /// Add an endpoint, as an `Rpc` or an `RpcConfig`.
+            pub fn rpc(mut self, rpc: impl Into<RpcConfig>) -> Self {
+                self.settings.network_rpcs.push(rpc.into());
+                self
+            }
+
+            /// Add an endpoint by URL template, filled in from the handler's `SecretResolver`.
+            pub fn rpc_template(self, url_template: impl Into<String>) -> Self {
+                self.rpc(RpcConfig::template(url_template))
+            }
+
+            pub fn network_name(mut self, network_name: impl Into<String>) -> Self {
+                self.settings.network_name = network_name.into();
+                self
+            }
+
+            pub fn log_level(mut self, log_level: LogLevel) -> Self {
+                self.settings.log_level = log_level;
+                self
+            }
+
+            pub fn tracking(mut self, tracking: Tracking) -> Self {
+                self.settings.tracking = tracking;
+                self
+            }
+
+            pub fn probe_timeout_ms(mut self, timeout_ms: u64) -> Self {
+                self.settings.rpc_probe_timeout_ms = timeout_ms;
+                self
+            }
+
+            pub fn retry_count(mut self, retry_count: u32) -> Self {
+                self.proxy_settings().retry_count = retry_count;
+                self
+            }
+
+            pub fn retry_delay_ms(mut self, delay_ms: u64) -> Self {
+                self.proxy_settings().retry_delay_ms = delay_ms;
+                self
+            }
+
+            pub fn rpc_call_timeout_ms(mut self, timeout_ms: u64) -> Self {
+                self.proxy_settings().rpc_call_timeout_ms = timeout_ms;
+                self
+            }
+
+            pub fn connect_timeout_ms(mut self, timeout_ms: u64) -> Self {
+                self.proxy_settings().connect_timeout_ms = Some(timeout_ms);
+                self
+            }
+
+            pub fn data_scope(mut self, data_scope: DataScope) -> Self {
+                self.settings.data_scope = data_scope;
+                self
+            }
+
+            pub fn failover_policy(mut self, failover_policy: FailoverPolicy) -> Self {
+                self.settings.failover_policy = failover_policy;
+                self
+            }
+
+            pub fn validation_mode(mut self, validation_mode: ValidationMode) -> Self {
+                self.settings.validation_mode = validation_mode;
+                self
+            }
+
+            pub fn host_limits(mut self, host_limits: HostLimits) -> Self {
+                self.settings.host_limits = host_limits;
+                self
+            }
+
+            /// Change any other setting, e.g. `.with(|settings| settings.monotonic_head = true)`.
+            pub fn with(mut self, change: impl FnOnce(&mut HandlerSettings)) -> Self {
+                change(&mut self.settings);
+                self
+            }
+
+            fn proxy_settings(&mut self) -> &mut ProxySettings {
+                self.settings.proxy_settings.get_or_insert_with(ProxySettings::default)
+            }
+        }
+    };
+}
+
+settings_setters!(HandlerSettingsBuilder);
+settings_setters!(HandlerConfigBuilder);
@@ -1,3 +1,9 @@
+pub mod builder;
+pub mod policy;
+pub mod profile;
 pub mod resolve_config;
 
-pub use resolve_config::{NormalizedConfig, resolve_config};
+pub use builder::{HandlerConfigBuilder, HandlerSettingsBuilder};
+pub use policy::{EffectivePolicy, DESCRIBED_ATTEMPTS};
+pub use profile::{PartialHandlerSettings, PartialProxySettings, PartialReadinessThresholds, Profile};
+pub use resolve_config::{AgreementSamplingConfig, AutoRefreshConfig, ConstrainedModeConfig, DataStalenessConfig, InitPolicyConfig, KeepaliveConfig, LatencySloConfig, MonotonicHeadConfig, NormalizedConfig, RetryTuningConfig, SmearingConfig, TimestampSanityConfig, resolve_config, resolve_config_with};
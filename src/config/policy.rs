@@ -0,0 +1,434 @@
+//! The behavior a resolved config runs with, spelled out for audits and config review.
+//!
+//! Every part comes from the `describe()` of the option struct the runtime reads, so the
+//! description can't drift from what the handler actually does.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    calls::{ConsensusOptions, ConsensusPolicy},
+    degraded::retry_backoff,
+    config::{resolve_config::RetryConfig, AgreementSamplingConfig, AutoRefreshConfig, ConstrainedModeConfig, DataStalenessConfig, InitPolicyConfig, KeepaliveConfig, LatencySloConfig, MonotonicHeadConfig, NormalizedConfig, RetryTuningConfig, TimestampSanityConfig},
+    error::kb::ErrorMapping,
+    hex::hex,
+    keepalive::KEEPALIVE_DEMOTE_AFTER,
+    region::Region,
+    strategy::Strategy,
+    types::{AdaptiveProbeTimeout, CustomProbePolicy, Egress, FailoverPolicy, HostLimits, RateLimitScheme, RegionAffinityPolicy, RouteRule, SloMeasure, ValidationMode},
+};
+
+/// Backoff delays `RetryPolicy::delays_ms` lists.
+pub const DESCRIBED_ATTEMPTS: usize = 5;
+
+/// Every behavioral setting of a handler, defaults applied.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectivePolicy {
+    pub strategy: StrategyPolicy,
+    pub retry: RetryPolicy,
+    pub racing: RacingPolicy,
+    pub timeouts: TimeoutPolicy,
+    pub failover_policy: FailoverPolicy,
+    /// Latency charged per block of probed head lag when ordering endpoints
+    pub head_lag_penalty_ms: u64,
+    /// Endpoint regions configured by hand, see `region`
+    pub region_hints: BTreeMap<String, Region>,
+    pub region_affinity: Option<RegionAffinityPolicy>,
+    pub validation_mode: ValidationMode,
+    /// Route rules in priority order, URLs redacted
+    pub routes: Vec<RouteRule>,
+    /// What a consensus call does when given `ConsensusOptions::default()`
+    pub consensus: ConsensusPolicy,
+    pub keepalive: Option<KeepalivePolicy>,
+    pub monotonic_head: Option<MonotonicHeadPolicy>,
+    pub auto_refresh: Option<AutoRefreshPolicy>,
+    pub init: Option<InitRetryPolicy>,
+    pub retry_tuning: Option<RetryTuningPolicy>,
+    pub latency_slo: Option<LatencySloPolicy>,
+    pub timestamp_sanity: Option<TimestampSanityPolicy>,
+    pub agreement_sampling: Option<AgreementSamplingPolicy>,
+    pub daily_spend_budget: Option<u64>,
+    pub response_cache_entries: usize,
+    pub negative_cache_entries: usize,
+    pub probing: ProbePolicy,
+    pub host_limits: HostLimitPolicy,
+    pub maintenance_lead_ms: u64,
+    pub follow_post_redirects: bool,
+    pub rotate_secrets_on_auth_error: bool,
+    pub localnet: bool,
+    pub egress: Egress,
+    pub idempotent_methods: Vec<String>,
+    pub metric_tag_keys: Vec<String>,
+    pub error_mappings: Vec<ErrorMapping>,
+    pub staleness: Option<DataStalenessPolicy>,
+    /// `EndpointProbe::name` of each custom probe, in the order they run
+    pub custom_probes: Vec<String>,
+    pub custom_probe_policy: CustomProbePolicy,
+    pub constrained_mode: Option<ConstrainedModePolicy>,
+}
+
+impl EffectivePolicy {
+    /// Keccak-256 of the serialized policy, for telling deploys with different behavior apart.
+    pub fn hash(&self) -> String {
+        let bytes = serde_json::to_vec(self).expect("policy serializes");
+        format!("0x{}", hex(&Keccak256::digest(&bytes)))
+    }
+}
+
+/// How the handler picks its active provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum StrategyPolicy {
+    Fastest,
+    FirstHealthy,
+    FastStart {
+        /// Improvement the full sweep must find to replace the provisional provider
+        margin_ms: u64,
+    },
+    StaticOrder {
+        /// Tried in this order, redacted
+        urls: Vec<String>,
+    },
+}
+
+/// Rounds through the failover plan and the pauses between batches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RetryPolicy {
+    /// Times the whole plan is walked before giving up
+    pub rounds: u32,
+    /// Pause after each failed batch
+    pub delay_ms: u64,
+    /// The pause after each of the first failed batches
+    pub delays_ms: Vec<u64>,
+}
+
+/// How endpoints are raced within a round.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RacingPolicy {
+    /// Endpoints sent the request at once, the first answer winning
+    pub batch_size: usize,
+    /// Whether a batch may mix tiers
+    pub batches_span_tiers: bool,
+}
+
+/// Time limits per phase.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimeoutPolicy {
+    /// Probe timeout, used throughout unless `adaptive_probe` is set
+    pub probe_ms: u64,
+    pub adaptive_probe: Option<AdaptiveProbeTimeout>,
+    /// Budget for one proxied request to one endpoint
+    pub call_total_ms: u64,
+    /// Connecting, within the total budget
+    pub connect_ms: Option<u64>,
+    pub probe_sweep_deadline_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeepalivePolicy {
+    pub after_ms: u64,
+    pub interval_ms: u64,
+    /// Failed pings in a row after which the provider is re-selected
+    pub demote_after_failures: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MonotonicHeadPolicy {
+    pub max_head_lag: u64,
+    pub reorg_tolerance: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AutoRefreshPolicy {
+    pub interval_ms: u64,
+    pub tick_ms: u64,
+    pub endpoints_per_tick: usize,
+    pub busy_in_flight: usize,
+    /// Part of the interval each sweep is spread over, `None` when sweeps aren't smeared
+    pub smear_window_ms: Option<u64>,
+    pub instance_seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InitRetryPolicy {
+    pub max_attempts: u32,
+    /// Pause before each retry, doubling from the first
+    pub backoff_ms: Vec<u64>,
+    pub allow_unprobed_fallback: bool,
+}
+
+/// Bounds the retry parameters are tuned within.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RetryTuningPolicy {
+    pub window_ms: u64,
+    pub min_samples: u64,
+    pub retry_count: (u32, u32),
+    pub retry_delay_ms: (u64, u64),
+    pub batch_size: (usize, usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LatencySloPolicy {
+    pub target_ms: u64,
+    pub violation_window_ms: u64,
+    pub max_violations: usize,
+    pub penalty_ms: u64,
+    /// Sorted, without the heavy methods that are always ignored
+    pub ignore_methods: Vec<String>,
+    pub measure: SloMeasure,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimestampSanityPolicy {
+    pub max_future_drift_ms: u64,
+    pub max_past_lag_ms: Option<u64>,
+    pub strict: bool,
+    pub sane_to_clear: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DataStalenessPolicy {
+    pub max_age_ms: u64,
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConstrainedModePolicy {
+    pub max_total_inflight: usize,
+    /// Slots probes and other background traffic may hold at once
+    pub probe_slots: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AgreementSamplingPolicy {
+    pub interval_ms: u64,
+    pub sample_size: usize,
+    pub min_depth: u64,
+    pub max_depth: u64,
+    pub window: usize,
+    pub min_samples: usize,
+    pub max_disagreement_rate: f64,
+    pub exclude_from_reads: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProbePolicy {
+    pub max_concurrent_probes: usize,
+    pub pin_resolved_ips: bool,
+    pub max_lag_blocks: u64,
+}
+
+/// `HostLimits` with its hosts sorted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostLimitPolicy {
+    pub default_per_host: Option<usize>,
+    pub per_host: BTreeMap<String, usize>,
+    pub rate_limit_headers: Option<RateLimitHeadersPolicy>,
+}
+
+/// Which response headers advertise quota and when a host starts being paced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RateLimitHeadersPolicy {
+    pub schemes: Vec<RateLimitScheme>,
+    pub per_host: BTreeMap<String, Vec<RateLimitScheme>>,
+    pub remaining_floor: u64,
+}
+
+impl NormalizedConfig {
+    /// The policy a handler built from this config runs with under the default strategy.
+    pub fn effective_policy(&self) -> EffectivePolicy {
+        self.effective_policy_with(&Strategy::default())
+    }
+
+    pub(crate) fn effective_policy_with(&self, strategy: &Strategy) -> EffectivePolicy {
+        let settings = &self.settings;
+        let redact = |urls: &[String]| urls.iter().map(|url| self.redactor.redact(url)).collect();
+        EffectivePolicy {
+            strategy: strategy.describe(self),
+            retry: self.retry.describe(),
+            racing: RacingPolicy { batch_size: if matches!(strategy, Strategy::StaticOrder(_)) { 1 } else { self.retry.batch_size }, batches_span_tiers: false },
+            timeouts: TimeoutPolicy {
+                probe_ms: settings.rpc_timeout.as_millis() as u64,
+                adaptive_probe: settings.adaptive_probe_timeout,
+                call_total_ms: settings.rpc_call_timeout.as_millis() as u64,
+                connect_ms: settings.connect_timeout.map(|timeout| timeout.as_millis() as u64),
+                probe_sweep_deadline_ms: settings.probe_sweep_deadline.map(|deadline| deadline.as_millis() as u64),
+            },
+            failover_policy: self.failover_policy,
+            head_lag_penalty_ms: settings.head_lag_penalty_ms,
+            region_hints: settings.region_hints.clone(),
+            region_affinity: settings.region_affinity,
+            validation_mode: self.validation_mode,
+            routes: self.routes.iter().map(|rule| RouteRule { urls: redact(&rule.urls), ..rule.clone() }).collect(),
+            consensus: ConsensusOptions::default().describe(),
+            keepalive: settings.keepalive.as_ref().map(KeepaliveConfig::describe),
+            monotonic_head: settings.monotonic_head.as_ref().map(MonotonicHeadConfig::describe),
+            auto_refresh: settings.auto_refresh.as_ref().map(AutoRefreshConfig::describe),
+            init: settings.init_policy.as_ref().map(InitPolicyConfig::describe),
+            retry_tuning: settings.retry_tuning.as_ref().map(RetryTuningConfig::describe),
+            latency_slo: settings.latency_slo.as_ref().map(LatencySloConfig::describe),
+            timestamp_sanity: settings.timestamp_sanity.as_ref().map(TimestampSanityConfig::describe),
+            agreement_sampling: settings.agreement_sampling.as_ref().map(AgreementSamplingConfig::describe),
+            daily_spend_budget: settings.daily_spend_budget,
+            response_cache_entries: settings.response_cache_entries,
+            negative_cache_entries: settings.negative_cache_entries,
+            probing: ProbePolicy {
+                max_concurrent_probes: settings.max_concurrent_probes,
+                pin_resolved_ips: settings.pin_resolved_ips,
+                max_lag_blocks: settings.max_probe_lag_blocks,
+            },
+            host_limits: settings.host_limits.describe(),
+            maintenance_lead_ms: settings.maintenance_lead.as_millis() as u64,
+            follow_post_redirects: settings.follow_post_redirects,
+            rotate_secrets_on_auth_error: settings.rotate_secrets_on_auth_error,
+            localnet: settings.localnet,
+            egress: settings.egress,
+            idempotent_methods: settings.idempotent_methods.clone(),
+            metric_tag_keys: settings.metric_tag_keys.clone(),
+            error_mappings: settings.error_mappings.clone(),
+            staleness: settings.staleness.as_ref().map(DataStalenessConfig::describe),
+            custom_probes: settings.custom_probes.iter().map(|probe| probe.name().to_string()).collect(),
+            custom_probe_policy: settings.custom_probe_policy,
+            constrained_mode: settings.constrained_mode.as_ref().map(ConstrainedModeConfig::describe),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn describe(&self) -> RetryPolicy {
+        let delay_ms = self.retry_delay.as_millis() as u64;
+        RetryPolicy { rounds: self.retry_count, delay_ms, delays_ms: vec![delay_ms; DESCRIBED_ATTEMPTS] }
+    }
+}
+
+impl KeepaliveConfig {
+    pub fn describe(&self) -> KeepalivePolicy {
+        KeepalivePolicy {
+            after_ms: self.after.as_millis() as u64,
+            interval_ms: self.interval.as_millis() as u64,
+            demote_after_failures: KEEPALIVE_DEMOTE_AFTER,
+        }
+    }
+}
+
+impl MonotonicHeadConfig {
+    pub fn describe(&self) -> MonotonicHeadPolicy {
+        MonotonicHeadPolicy { max_head_lag: self.max_head_lag, reorg_tolerance: self.reorg_tolerance }
+    }
+}
+
+impl AutoRefreshConfig {
+    pub fn describe(&self) -> AutoRefreshPolicy {
+        AutoRefreshPolicy {
+            interval_ms: self.interval.as_millis() as u64,
+            tick_ms: self.tick.as_millis() as u64,
+            endpoints_per_tick: self.endpoints_per_tick,
+            busy_in_flight: self.busy_in_flight,
+            smear_window_ms: self.smearing.map(|smearing| smearing.window.as_millis() as u64),
+            instance_seed: self.smearing.map(|smearing| smearing.instance_seed),
+        }
+    }
+}
+
+impl InitPolicyConfig {
+    pub fn describe(&self) -> InitRetryPolicy {
+        InitRetryPolicy {
+            max_attempts: self.max_attempts,
+            backoff_ms: (1..self.max_attempts).map(|retry| retry_backoff(self.attempt_backoff, retry).as_millis() as u64).collect(),
+            allow_unprobed_fallback: self.allow_unprobed_fallback,
+        }
+    }
+}
+
+impl RetryTuningConfig {
+    pub fn describe(&self) -> RetryTuningPolicy {
+        RetryTuningPolicy {
+            window_ms: self.window.as_millis() as u64,
+            min_samples: self.min_samples,
+            retry_count: (self.min_retry_count, self.max_retry_count),
+            retry_delay_ms: (self.min_retry_delay.as_millis() as u64, self.max_retry_delay.as_millis() as u64),
+            batch_size: (self.min_batch_size, self.max_batch_size),
+        }
+    }
+}
+
+impl LatencySloConfig {
+    pub fn describe(&self) -> LatencySloPolicy {
+        let mut ignore_methods = self.ignore_methods.clone();
+        ignore_methods.sort();
+        ignore_methods.dedup();
+        LatencySloPolicy {
+            target_ms: self.target.as_millis() as u64,
+            violation_window_ms: self.window.as_millis() as u64,
+            max_violations: self.max_violations,
+            penalty_ms: self.penalty.as_millis() as u64,
+            ignore_methods,
+            measure: self.measure,
+        }
+    }
+}
+
+impl TimestampSanityConfig {
+    pub fn describe(&self) -> TimestampSanityPolicy {
+        TimestampSanityPolicy {
+            max_future_drift_ms: self.max_future_drift.as_millis() as u64,
+            max_past_lag_ms: self.max_past_lag.map(|lag| lag.as_millis() as u64),
+            strict: self.strict,
+            sane_to_clear: self.sane_to_clear,
+        }
+    }
+}
+
+impl DataStalenessConfig {
+    pub fn describe(&self) -> DataStalenessPolicy {
+        DataStalenessPolicy { max_age_ms: self.max_age.as_millis() as u64, strict: self.strict }
+    }
+}
+
+impl ConstrainedModeConfig {
+    pub fn describe(&self) -> ConstrainedModePolicy {
+        ConstrainedModePolicy { max_total_inflight: self.max_total_inflight, probe_slots: self.probe_slots }
+    }
+}
+
+impl AgreementSamplingConfig {
+    pub fn describe(&self) -> AgreementSamplingPolicy {
+        AgreementSamplingPolicy {
+            interval_ms: self.interval.as_millis() as u64,
+            sample_size: self.sample_size,
+            min_depth: self.min_depth,
+            max_depth: self.max_depth,
+            window: self.window,
+            min_samples: self.min_samples,
+            max_disagreement_rate: self.max_disagreement_rate,
+            exclude_from_reads: self.exclude_from_reads,
+        }
+    }
+}
+
+impl HostLimits {
+    pub fn describe(&self) -> HostLimitPolicy {
+        HostLimitPolicy {
+            default_per_host: self.default_per_host,
+            per_host: self.per_host.iter().map(|(host, limit)| (host.clone(), *limit)).collect(),
+            rate_limit_headers: self.rate_limit_headers.as_ref().map(|headers| RateLimitHeadersPolicy {
+                schemes: headers.schemes.clone(),
+                per_host: headers.per_host.iter().map(|(host, schemes)| (host.clone(), schemes.clone())).collect(),
+                remaining_floor: headers.remaining_floor,
+            }),
+        }
+    }
+}
+
+impl Strategy {
+    pub fn describe(&self, config: &NormalizedConfig) -> StrategyPolicy {
+        match self {
+            Strategy::Fastest => StrategyPolicy::Fastest,
+            Strategy::FirstHealthy => StrategyPolicy::FirstHealthy,
+            Strategy::FastStart => StrategyPolicy::FastStart { margin_ms: config.settings.fast_start_margin.as_millis() as u64 },
+            Strategy::StaticOrder(urls) => StrategyPolicy::StaticOrder { urls: urls.iter().map(|url| config.redactor.redact(url)).collect() },
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Complete settings for the usual environments, and partial settings to layer over them.
+//!
+//! `HandlerConfig::profile` fills in every setting for a `Profile`. `HandlerConfig::overlay` then
+//! changes only the settings a `PartialHandlerSettings` names, so the production profile with two
+//! endpoints and a longer call timeout is a few lines, or a small file in any format serde reads:
+//!
+//! ```no_run
+//! # fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use ez_web3_rpc::prelude::*;
+//!
+//! let overrides: PartialHandlerSettings = serde_json::from_str(r#"{
+//!     "network_rpcs": [{ "url": "https://eth.example.com", "tier": 0 }, { "url": "https://backup.example.com", "tier": 1 }],
+//!     "proxy_settings": { "rpc_call_timeout_ms": 20000 }
+//! }"#)?;
+//! let config = HandlerConfig::profile(Profile::Production, 1).overlay(overrides);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A setting left out of the partial keeps the profile's value. One that is optional in
+//! `HandlerSettings` is cleared by an explicit `null`. `proxy_settings` and `readiness` are merged
+//! field by field, the rest are replaced whole, lists included.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    error::kb::ErrorMapping,
+    localnet::{LocalNode, DEFAULT_LOCAL_NODES},
+    maintenance::MaintenanceWindow,
+    region::Region,
+    types::{
+        AdaptiveProbeTimeout, AgreementSampling, AutoRefreshSettings, ConstrainedMode, CustomProbePolicy, DataScope, DataStaleness, Egress, FailoverPolicy,
+        HandlerConfig, HandlerSettings, HostLimits, InitPolicy, KeepaliveSettings, LatencySlo, LogLevel, MemoryLimits, NetworkId, NetworkName, ProxySettings,
+        ReadinessThresholds, RegionAffinityPolicy, RetryTuning, RouteRule, RpcConfig, TimestampSanity, Tracking, ValidationMode,
+    },
+};
+
+/// A ready-made set of settings, see `HandlerConfig::profile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    /// Conservative and self-healing, for serving users:
+    ///
+    /// - `Warn` logging, `Strict` validation
+    /// - a 2s probe timeout; calls get 10s, 2s of it to connect, and 3 retries 250ms apart
+    /// - `TierStrict` failover, so endpoints given tier 0 (the paid ones) are exhausted before
+    ///   the rest are tried
+    /// - keepalive pings after 30s idle, a background refresh every 5 minutes
+    /// - `init` tries 3 times from 500ms apart, then serves unprobed until a refresh recovers
+    /// - block hash agreement sampled every minute; endpoints that keep disagreeing are left out
+    ///   of consensus reads and proxied reads alike
+    Production,
+    /// A local node at `http://127.0.0.1:8545`, with `Debug` logging and no chainlist endpoints:
+    /// the settings `LocalNode::config_builder` gives, without looking for the node first.
+    Development,
+    /// Hermetic: `Egress::InjectedOnly`, so nothing but the endpoints configured or added at
+    /// runtime is ever sent a request. Probes skip the Permit2 and sync checks mocks can't pass,
+    /// timeouts are short, retries few, and nothing runs in the background. Endpoints are added
+    /// with `overlay` or afterwards, the profile has none.
+    Ci,
+}
+
+impl HandlerConfig {
+    /// Every setting for `network_id` as `profile` documents it.
+    pub fn profile(profile: Profile, network_id: NetworkId) -> Self {
+        let mut config = Self::new(network_id);
+        let settings = config.settings.get_or_insert_with(HandlerSettings::default);
+        match profile {
+            Profile::Production => {
+                settings.log_level = LogLevel::Warn;
+                settings.rpc_probe_timeout_ms = 2_000;
+                settings.proxy_settings = Some(ProxySettings { retry_count: 3, retry_delay_ms: 250, rpc_call_timeout_ms: 10_000, connect_timeout_ms: Some(2_000) });
+                settings.failover_policy = FailoverPolicy::TierStrict;
+                settings.validation_mode = ValidationMode::Strict;
+                settings.keepalive = Some(KeepaliveSettings { keepalive_after_ms: 30_000, keepalive_interval_ms: 30_000 });
+                settings.auto_refresh = Some(AutoRefreshSettings { interval_ms: 5 * 60_000, tick_ms: 1_000, endpoints_per_tick: 4, busy_in_flight: 64, smearing: None });
+                settings.init_policy = Some(InitPolicy { max_attempts: 3, attempt_backoff_ms: 500, allow_unprobed_fallback: true });
+                settings.agreement_sampling = Some(AgreementSampling {
+                    interval_ms: 60_000,
+                    sample_size: 3,
+                    min_depth: 3,
+                    max_depth: 16,
+                    window: 20,
+                    min_samples: 5,
+                    max_disagreement_rate: 0.2,
+                    exclude_from_reads: true,
+                });
+            }
+            Profile::Development => {
+                let node = LocalNode { url: DEFAULT_LOCAL_NODES[0].to_string(), chain_id: network_id };
+                config = node.config_builder().log_level(LogLevel::Debug).build().expect("the default local node URL parses");
+            }
+            Profile::Ci => {
+                settings.log_level = LogLevel::Warn;
+                settings.egress = Egress::InjectedOnly;
+                settings.localnet = true;
+                settings.rpc_probe_timeout_ms = 1_000;
+                settings.proxy_settings = Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: 2_000, connect_timeout_ms: Some(500) });
+            }
+        }
+        config
+    }
+
+    /// These settings with every one `partial` sets overriding them.
+    pub fn overlay(mut self, partial: PartialHandlerSettings) -> Self {
+        partial.apply(self.settings.get_or_insert_with(HandlerSettings::default));
+        self
+    }
+}
+
+/// An explicit `null` as `Some(None)`, clearing the setting rather than leaving it be.
+fn explicit<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Declares a partial of `$target`: `replace` fields override the target's, `clear` fields are
+/// its optional ones, which `Some(None)` clears, and `merge` fields are partials themselves,
+/// applied onto the target's value or, for `merge_optional`, onto its default when it has none.
+macro_rules! partial_settings {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident for $target:ty {
+            replace { $($field:ident: $ty:ty,)* }
+            clear { $($optional:ident: $optional_ty:ty,)* }
+            merge { $($nested:ident: $nested_ty:ty,)* }
+            merge_optional { $($nested_optional:ident: $nested_optional_ty:ty,)* }
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+        #[serde(default, deny_unknown_fields)]
+        pub struct $name {
+            $(
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub $field: Option<$ty>,
+            )*
+            $(
+                #[serde(deserialize_with = "explicit", skip_serializing_if = "Option::is_none")]
+                pub $optional: Option<Option<$optional_ty>>,
+            )*
+            $(
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub $nested: Option<$nested_ty>,
+            )*
+            $(
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub $nested_optional: Option<$nested_optional_ty>,
+            )*
+        }
+
+        impl $name {
+            /// Override the fields of `target` this sets.
+            pub fn apply(self, target: &mut $target) {
+                $(
+                    if let Some(value) = self.$field {
+                        target.$field = value;
+                    }
+                )*
+                $(
+                    if let Some(value) = self.$optional {
+                        target.$optional = value;
+                    }
+                )*
+                $(
+                    if let Some(partial) = self.$nested {
+                        partial.apply(&mut target.$nested);
+                    }
+                )*
+                $(
+                    if let Some(partial) = self.$nested_optional {
+                        partial.apply(target.$nested_optional.get_or_insert_with(Default::default));
+                    }
+                )*
+            }
+        }
+    };
+}
+
+partial_settings! {
+    /// Some of `HandlerSettings`, for `HandlerConfig::overlay`. Every setting but `custom_probes`,
+    /// which isn't serializable, can be set.
+    pub struct PartialHandlerSettings for HandlerSettings {
+        replace {
+            log_level: LogLevel,
+            tracking: Tracking,
+            network_rpcs: Vec<RpcConfig>,
+            network_name: NetworkName,
+            rpc_probe_timeout_ms: u64,
+            data_scope: DataScope,
+            chain_aliases: Vec<NetworkId>,
+            failover_policy: FailoverPolicy,
+            pin_resolved_ips: bool,
+            browser_local_storage: bool,
+            routes: Vec<RouteRule>,
+            validation_mode: ValidationMode,
+            follow_post_redirects: bool,
+            memory_limits: MemoryLimits,
+            fast_start_margin_ms: u64,
+            host_limits: HostLimits,
+            monotonic_head: bool,
+            max_head_lag: u64,
+            head_reorg_tolerance: u64,
+            maintenance_windows: HashMap<String, Vec<MaintenanceWindow>>,
+            maintenance_lead_ms: u64,
+            max_concurrent_probes: usize,
+            max_probe_lag_blocks: u64,
+            head_lag_penalty_ms: u64,
+            region_hints: BTreeMap<String, Region>,
+            minimal_headers: bool,
+            response_cache_entries: usize,
+            negative_cache_entries: usize,
+            rotate_secrets_on_auth_error: bool,
+            localnet: bool,
+            egress: Egress,
+            idempotent_methods: Vec<String>,
+            metric_tag_keys: Vec<String>,
+            error_mappings: Vec<ErrorMapping>,
+            custom_probe_policy: CustomProbePolicy,
+        }
+        clear {
+            keepalive: KeepaliveSettings,
+            write_endpoint: RouteRule,
+            adaptive_probe_timeout: AdaptiveProbeTimeout,
+            auto_refresh: AutoRefreshSettings,
+            init_policy: InitPolicy,
+            retry_tuning: RetryTuning,
+            probe_sweep_deadline_ms: u64,
+            user_agent: String,
+            latency_slo: LatencySlo,
+            daily_spend_budget: u64,
+            timestamp_sanity: TimestampSanity,
+            agreement_sampling: AgreementSampling,
+            staleness_policy: DataStaleness,
+            constrained_mode: ConstrainedMode,
+            region_affinity: RegionAffinityPolicy,
+        }
+        merge {
+            readiness: PartialReadinessThresholds,
+        }
+        merge_optional {
+            proxy_settings: PartialProxySettings,
+        }
+    }
+}
+
+partial_settings! {
+    /// Some of `ProxySettings`, merged onto the profile's or the defaults.
+    pub struct PartialProxySettings for ProxySettings {
+        replace {
+            retry_count: u32,
+            retry_delay_ms: u64,
+            rpc_call_timeout_ms: u64,
+        }
+        clear {
+            connect_timeout_ms: u64,
+        }
+        merge {}
+        merge_optional {}
+    }
+}
+
+partial_settings! {
+    /// Some of `ReadinessThresholds`.
+    pub struct PartialReadinessThresholds for ReadinessThresholds {
+        replace {
+            max_success_age_ms: u64,
+            heartbeat_grace_ms: u64,
+        }
+        clear {
+            max_pending: usize,
+        }
+        merge {}
+        merge_optional {}
+    }
+}
@@ -1,5 +1,18 @@
-use std::time::Duration;
-use crate::types::{HandlerConfig, NetworkId, Tracking, Rpc};
+use std::{collections::{BTreeMap, HashMap}, sync::Arc, time::Duration};
+use crate::{
+    auto_refresh::host_instance_seed,
+    chainlist::{connected, CHAIN_ALIASES},
+    error::kb::ErrorMapping,
+    maintenance::MaintenanceWindow,
+    methods::write_methods,
+    performance::{EndpointProbe, TierMap},
+    provider::{headers::{header_map, HeaderOverrides}, plan::BATCH_SIZE, DEFAULT_USER_AGENT},
+    region::Region,
+    secrets::{EnvSecretResolver, Redactor, SecretResolver, TemplateRenderer},
+    spend::CostProfile,
+    Result, RpcHandlerError,
+    types::{AdaptiveProbeTimeout, CustomProbePolicy, DataScope, Egress, FailoverPolicy, HandlerConfig, HostLimits, MemoryLimits, NetworkId, ReadinessThresholds, RegionAffinityPolicy, RouteRule, SloMeasure, Tracking, Rpc, ValidationMode},
+};
 
 #[derive(Debug, Clone)]
 pub struct NormalizedConfig {
@@ -11,6 +24,28 @@ pub struct NormalizedConfig {
     pub injected_rpcs: Vec<Rpc>,
     /// Retry settings for failed RPC calls
     pub retry: RetryConfig,
+    /// How endpoints are ordered during failover
+    pub failover_policy: FailoverPolicy,
+    /// Method routing rules in priority order, with the write endpoint rule first
+    pub routes: Vec<RouteRule>,
+    /// How strictly responses are validated
+    pub validation_mode: ValidationMode,
+    /// Which networks' chain data the handler keeps in view
+    pub data_scope: DataScope,
+    /// The other ids of `network_id`'s chain, configured or from the registry's hints, sorted
+    pub chain_aliases: Vec<NetworkId>,
+    /// Puts placeholders back in place of the secrets templated URLs were filled in with
+    pub redactor: Redactor,
+    /// The template each templated injected endpoint's URL was filled in from, by URL
+    pub url_templates: HashMap<String, String>,
+    /// Each tiered injected endpoint's failover tier, by URL
+    pub tiers: TierMap,
+    /// Each injected endpoint's own maintenance windows, by URL
+    pub maintenance_windows: HashMap<String, Vec<MaintenanceWindow>>,
+    /// Each injected endpoint's extra headers, by URL, for the endpoints that have any
+    pub headers: HeaderOverrides,
+    /// What requests to each metered injected endpoint cost, by URL
+    pub cost_profiles: HashMap<String, CostProfile>,
     /// General settings
     pub settings: SettingsConfig,
 }
@@ -21,6 +56,8 @@ pub struct RetryConfig {
     pub retry_count: u32,
     /// Delay between retry attempts
     pub retry_delay: Duration,
+    /// Endpoints raced at once, one at a time under `Strategy::StaticOrder` whatever this is
+    pub batch_size: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -29,21 +66,288 @@ pub struct SettingsConfig {
     pub rpc_timeout: Duration,
     /// Timeout for individual RPC calls
     pub rpc_call_timeout: Duration,
-    /// Whether to use browser localStorage for persisting latency cache
+    /// Timeout for connecting, within `rpc_call_timeout`
+    pub connect_timeout: Option<Duration>,
+    /// Keep latency snapshots in `localStorage` on `wasm32`, ignored natively
     pub browser_local_storage: bool,
     /// Log level for this package including RPC calls
     pub log_level: String,
     /// If true, prune dynamic data to only the configured networkId during init
     pub prune_unused_data: bool,
+    /// Pin endpoint hostnames to the IP measured by the probe
+    pub pin_resolved_ips: bool,
+    /// Idle keepalive for the active provider, disabled when `None`
+    pub keepalive: Option<KeepaliveConfig>,
+    /// Follow one same-host redirect with the original POST
+    pub follow_post_redirects: bool,
+    /// Entry caps for per-endpoint state
+    pub memory_limits: MemoryLimits,
+    /// Probe timeout derived from recent healthy latencies, `rpc_timeout` throughout when `None`
+    pub adaptive_probe_timeout: Option<AdaptiveProbeTimeout>,
+    /// Improvement the fast-start sweep must find before replacing the provisional provider
+    pub fast_start_margin: Duration,
+    /// Concurrent request caps per hostname
+    pub host_limits: HostLimits,
+    /// Keep returned head blocks monotonic across failovers, off when `None`
+    pub monotonic_head: Option<MonotonicHeadConfig>,
+    /// Incremental background sweeps, off when `None`
+    pub auto_refresh: Option<AutoRefreshConfig>,
+    /// Retries and fallback for `init`, a single attempt when `None`
+    pub init_policy: Option<InitPolicyConfig>,
+    /// Bounds the retry parameters are tuned within, fixed when `None`
+    pub retry_tuning: Option<RetryTuningConfig>,
+    /// Announced downtime per endpoint URL, besides each endpoint's own windows
+    pub maintenance_windows: HashMap<String, Vec<MaintenanceWindow>>,
+    /// How long before a window opens the active provider is moved off its endpoint
+    pub maintenance_lead: Duration,
+    /// Endpoints probed at once by a sweep
+    pub max_concurrent_probes: usize,
+    /// Sweep time limit, unbounded when `None`
+    pub probe_sweep_deadline: Option<Duration>,
+    /// Blocks off the sweep's most common head a probed endpoint may be and stay in sync
+    pub max_probe_lag_blocks: u64,
+    /// Latency charged per block of probed head lag when ordering endpoints, none when `0`
+    pub head_lag_penalty_ms: u64,
+    /// Endpoint regions by `host:port`, hostname or parent domain, ahead of the hostname heuristic
+    pub region_hints: BTreeMap<String, Region>,
+    /// Region preference and switch damping, off when `None`
+    pub region_affinity: Option<RegionAffinityPolicy>,
+    /// `User-Agent` the HTTP client sends, none under `minimal_headers` unless one was configured
+    pub user_agent: Option<String>,
+    /// Latency budget that re-selects the provider when repeatedly exceeded, off when `None`
+    pub latency_slo: Option<LatencySloConfig>,
+    /// Handler-wide daily spend ceiling across metered endpoints, unlimited when `None`
+    pub daily_spend_budget: Option<u64>,
+    /// Block timestamp bounds, off when `None`
+    pub timestamp_sanity: Option<TimestampSanityConfig>,
+    /// Cached answers to `cacheable` methods, caching off when `0`
+    pub response_cache_entries: usize,
+    /// Cached `null` answers to hash lookups, none when `0`
+    pub negative_cache_entries: usize,
+    /// Background block hash sampling across endpoints, off when `None`
+    pub agreement_sampling: Option<AgreementSamplingConfig>,
+    /// Resolve a templated endpoint's secrets again when it answers `401` or `403`
+    pub rotate_secrets_on_auth_error: bool,
+    /// Serving a local node: no registry endpoints, Permit2 or sync checks
+    pub localnet: bool,
+    /// Which endpoints requests may go to
+    pub egress: Egress,
+    /// Unregistered methods free of side effects
+    pub idempotent_methods: Vec<String>,
+    /// Caller tag keys the metrics count apart, sorted
+    pub metric_tag_keys: Vec<String>,
+    /// Consulted before the built-in error mappings
+    pub error_mappings: Vec<ErrorMapping>,
+    /// Chain data age checked at construction, unchecked when `None`
+    pub staleness: Option<DataStalenessConfig>,
+    /// Run on each endpoint after the built-in probes
+    pub custom_probes: Vec<Arc<dyn EndpointProbe>>,
+    pub custom_probe_policy: CustomProbePolicy,
+    /// Global in-flight ceiling shared by all traffic, off when `None`
+    pub constrained_mode: Option<ConstrainedModeConfig>,
+    /// Limits for the readiness and liveness verdicts
+    pub readiness: ReadinessThresholds,
 }
 
-pub fn resolve_config(config: HandlerConfig) -> NormalizedConfig {
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicHeadConfig {
+    /// Blocks an endpoint may trail the highest returned head
+    pub max_head_lag: u64,
+    /// Drop in that head accepted as a reorg when no endpoint reaches it
+    pub reorg_tolerance: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AutoRefreshConfig {
+    /// Time between complete sweeps
+    pub interval: Duration,
+    /// Time between ticks within a sweep, and the base of the deferral backoff
+    pub tick: Duration,
+    /// Endpoints probed per tick, at least one
+    pub endpoints_per_tick: usize,
+    /// In-flight requests at which a tick is deferred, at least one
+    pub busy_in_flight: usize,
+    /// Sweeps spread over part of the interval, started every `interval`, when set
+    pub smearing: Option<SmearingConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmearingConfig {
+    /// Part of the interval a sweep's probes are spread over, no longer than the interval
+    pub window: Duration,
+    /// Sets where in the interval this instance's sweeps fall and which endpoint leads
+    pub instance_seed: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InitPolicyConfig {
+    /// Selections `init` tries, at least one
+    pub max_attempts: u32,
+    /// Pause before the second attempt, doubled for each one after
+    pub attempt_backoff: Duration,
+    /// Serve through unprobed endpoints once the attempts run out
+    pub allow_unprobed_fallback: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryTuningConfig {
+    /// Time between changes, and the span each one looks back over
+    pub window: Duration,
+    /// Calls a window needs before any rule applies, at least one
+    pub min_samples: u64,
+    /// At least one, and no more than `max_retry_count`
+    pub min_retry_count: u32,
+    pub max_retry_count: u32,
+    /// No more than `max_retry_delay`
+    pub min_retry_delay: Duration,
+    pub max_retry_delay: Duration,
+    /// At least one, and no more than `max_batch_size`
+    pub min_batch_size: usize,
+    pub max_batch_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct LatencySloConfig {
+    /// Latency above which a served request is a violation
+    pub target: Duration,
+    /// Span violations are counted over
+    pub window: Duration,
+    /// Violations within `window` that breach the budget, at least one
+    pub max_violations: usize,
+    /// How long a breaching endpoint stays behind the others
+    pub penalty: Duration,
+    /// Methods never counted, besides the heavy ones
+    pub ignore_methods: Vec<String>,
+    /// Service time, or queue wait and service together
+    pub measure: SloMeasure,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DataStalenessConfig {
+    /// Age past which the chain data is stale
+    pub max_age: Duration,
+    /// Stale data fails construction rather than only emitting an event
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampSanityConfig {
+    /// How far ahead of the local clock a block may be dated
+    pub max_future_drift: Duration,
+    /// How far behind it a head block may be dated, unchecked when `None`
+    pub max_past_lag: Option<Duration>,
+    /// A skewed block fails its attempt rather than only flagging the endpoint
+    pub strict: bool,
+    /// Sane blocks in a row that clear a flag, at least one
+    pub sane_to_clear: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AgreementSamplingConfig {
+    /// Time between sampling rounds
+    pub interval: Duration,
+    /// Endpoints asked per round, at least two
+    pub sample_size: usize,
+    /// Depth range behind the head the sampled block is drawn from, `min_depth <= max_depth`
+    pub min_depth: u64,
+    pub max_depth: u64,
+    /// Recent samples kept per endpoint, at least one
+    pub window: usize,
+    /// Samples needed before an endpoint can be flagged, at least one and at most `window`
+    pub min_samples: usize,
+    /// Share of disagreeing samples above which an endpoint is flagged, within `0..=1`
+    pub max_disagreement_rate: f64,
+    /// Flagged endpoints are left out of proxied reads too
+    pub exclude_from_reads: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConstrainedModeConfig {
+    /// Requests of any kind in flight at once, at least one
+    pub max_total_inflight: usize,
+    /// Of those, the most probes and other background traffic may hold, at least one
+    pub probe_slots: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeepaliveConfig {
+    /// Idle time before the first ping
+    pub after: Duration,
+    /// Time between pings while still idle
+    pub interval: Duration,
+}
+
+pub fn resolve_config(config: HandlerConfig) -> Result<NormalizedConfig> {
+    resolve_config_with(config, &EnvSecretResolver)
+}
+
+/// Like `resolve_config`, filling URL templates from `secrets` instead of the environment.
+pub fn resolve_config_with(config: HandlerConfig, secrets: &dyn SecretResolver) -> Result<NormalizedConfig> {
     let settings = config.settings.unwrap_or_default();
+    if let Some(retained) = settings.data_scope.retained(config.network_id)
+        && !retained.contains(&config.network_id)
+    {
+        return Err(RpcHandlerError::InvalidDataScope { network_id: config.network_id, retained });
+    }
+
+    let write_rule = settings.write_endpoint.map(|mut rule| {
+        if rule.methods.is_empty() {
+            rule.methods = write_methods().map(str::to_string).collect();
+        }
+        rule
+    });
+    let routes = write_rule.into_iter().chain(settings.routes).collect();
+
+    let mut renderer = TemplateRenderer::new(secrets);
+    let mut url_templates = HashMap::new();
+    let mut tiers = TierMap::new();
+    let mut maintenance_windows = HashMap::new();
+    let mut headers = HashMap::new();
+    let mut cost_profiles = HashMap::new();
+    let injected_rpcs = settings.network_rpcs
+        .into_iter()
+        .map(|mut rpc| {
+            let url = match (rpc.url.take(), rpc.url_template.take()) {
+                (Some(url), None) => url,
+                (None, Some(template)) => {
+                    let url = renderer.render(&template)?;
+                    url_templates.insert(url.to_string(), template);
+                    url
+                }
+                (url, _) => return Err(RpcHandlerError::InvalidRpcConfig {
+                    detail: format!("an injected RPC needs one of `url` and `url_template`, not {}", if url.is_some() { "both" } else { "neither" }),
+                }),
+            };
+            if let Some(extra) = &rpc.headers {
+                headers.insert(url.to_string(), header_map(extra)?);
+            }
+            if let Some(tier) = rpc.tier {
+                tiers.insert(url.to_string(), tier);
+            }
+            if let Some(windows) = rpc.maintenance_windows.take() {
+                maintenance_windows.insert(url.to_string(), windows);
+            }
+            if let Some(profile) = rpc.cost_profile.take() {
+                cost_profiles.insert(url.to_string(), profile);
+            }
+            Ok(rpc.into_rpc(url))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let user_agent = match (settings.user_agent, settings.minimal_headers) {
+        (Some(user_agent), _) => {
+            reqwest::header::HeaderValue::try_from(user_agent.as_str())
+                .map_err(|_| RpcHandlerError::InvalidRpcConfig { detail: format!("invalid user agent {user_agent:?}") })?;
+            Some(user_agent)
+        }
+        (None, true) => None,
+        (None, false) => Some(DEFAULT_USER_AGENT.to_string()),
+    };
     
-    NormalizedConfig {
+    Ok(NormalizedConfig {
         network_id: config.network_id,
         tracking: settings.tracking,
-        injected_rpcs: settings.network_rpcs,
+        injected_rpcs,
         retry: RetryConfig {
             retry_count: settings.proxy_settings
                 .as_ref()
@@ -55,7 +359,19 @@ pub fn resolve_config(config: HandlerConfig) -> NormalizedConfig {
                     .map(|p| p.retry_delay_ms)
                     .unwrap_or(100),
             ),
+            batch_size: BATCH_SIZE,
         },
+        failover_policy: settings.failover_policy,
+        routes,
+        validation_mode: settings.validation_mode,
+        data_scope: settings.data_scope,
+        chain_aliases: alias_group(config.network_id, &settings.chain_aliases).into_iter().filter(|id| *id != config.network_id).collect(),
+        redactor: renderer.into_redactor(),
+        url_templates,
+        tiers,
+        maintenance_windows,
+        headers: Arc::new(headers),
+        cost_profiles,
         settings: SettingsConfig {
             rpc_timeout: Duration::from_millis(settings.rpc_probe_timeout_ms),
             rpc_call_timeout: Duration::from_millis(
@@ -64,7 +380,11 @@ pub fn resolve_config(config: HandlerConfig) -> NormalizedConfig {
                     .map(|p| p.rpc_call_timeout_ms)
                     .unwrap_or(10000),
             ),
-            browser_local_storage: false, // Not applicable for Rust
+            connect_timeout: settings.proxy_settings
+                .as_ref()
+                .and_then(|p| p.connect_timeout_ms)
+                .map(Duration::from_millis),
+            browser_local_storage: settings.browser_local_storage,
             log_level: match settings.log_level {
                 crate::types::LogLevel::Error => "error".to_string(),
                 crate::types::LogLevel::Warn => "warn".to_string(),
@@ -73,6 +393,119 @@ pub fn resolve_config(config: HandlerConfig) -> NormalizedConfig {
                 crate::types::LogLevel::Trace => "trace".to_string(),
             },
             prune_unused_data: false, // Can be made configurable later
+            pin_resolved_ips: settings.pin_resolved_ips,
+            keepalive: settings.keepalive.map(|keepalive| KeepaliveConfig {
+                after: Duration::from_millis(keepalive.keepalive_after_ms),
+                interval: Duration::from_millis(keepalive.keepalive_interval_ms),
+            }),
+            follow_post_redirects: settings.follow_post_redirects,
+            memory_limits: settings.memory_limits,
+            adaptive_probe_timeout: settings.adaptive_probe_timeout,
+            fast_start_margin: Duration::from_millis(settings.fast_start_margin_ms),
+            host_limits: settings.host_limits,
+            monotonic_head: settings.monotonic_head.then_some(MonotonicHeadConfig {
+                max_head_lag: settings.max_head_lag,
+                reorg_tolerance: settings.head_reorg_tolerance,
+            }),
+            auto_refresh: settings.auto_refresh.map(|auto_refresh| AutoRefreshConfig {
+                interval: Duration::from_millis(auto_refresh.interval_ms),
+                tick: Duration::from_millis(auto_refresh.tick_ms),
+                endpoints_per_tick: auto_refresh.endpoints_per_tick.max(1),
+                busy_in_flight: auto_refresh.busy_in_flight.max(1),
+                smearing: auto_refresh.smearing.map(|smearing| SmearingConfig {
+                    window: Duration::from_millis(auto_refresh.interval_ms).mul_f64(f64::from(smearing.spread_percent.clamp(1, 100)) / 100.0),
+                    instance_seed: smearing.instance_seed.unwrap_or_else(host_instance_seed),
+                }),
+            }),
+            init_policy: settings.init_policy.map(|policy| InitPolicyConfig {
+                max_attempts: policy.max_attempts.max(1),
+                attempt_backoff: Duration::from_millis(policy.attempt_backoff_ms),
+                allow_unprobed_fallback: policy.allow_unprobed_fallback,
+            }),
+            retry_tuning: settings.retry_tuning.map(|tuning| {
+                let min_retry_count = tuning.min_retry_count.max(1);
+                let min_batch_size = tuning.min_batch_size.max(1);
+                RetryTuningConfig {
+                    window: Duration::from_millis(tuning.window_ms),
+                    min_samples: tuning.min_samples.max(1),
+                    min_retry_count,
+                    max_retry_count: tuning.max_retry_count.max(min_retry_count),
+                    min_retry_delay: Duration::from_millis(tuning.min_retry_delay_ms),
+                    max_retry_delay: Duration::from_millis(tuning.max_retry_delay_ms.max(tuning.min_retry_delay_ms)),
+                    min_batch_size,
+                    max_batch_size: tuning.max_batch_size.max(min_batch_size),
+                }
+            }),
+            maintenance_windows: settings.maintenance_windows,
+            maintenance_lead: Duration::from_millis(settings.maintenance_lead_ms),
+            max_concurrent_probes: settings.max_concurrent_probes.max(1),
+            probe_sweep_deadline: settings.probe_sweep_deadline_ms.map(Duration::from_millis),
+            max_probe_lag_blocks: settings.max_probe_lag_blocks,
+            head_lag_penalty_ms: settings.head_lag_penalty_ms,
+            region_hints: settings.region_hints,
+            region_affinity: settings.region_affinity,
+            user_agent,
+            latency_slo: settings.latency_slo.map(|slo| LatencySloConfig {
+                target: Duration::from_millis(slo.target_ms),
+                window: Duration::from_millis(slo.violation_window_ms),
+                max_violations: slo.max_violations.max(1),
+                penalty: Duration::from_millis(slo.penalty_ms),
+                ignore_methods: slo.ignore_methods,
+                measure: slo.measure,
+            }),
+            daily_spend_budget: settings.daily_spend_budget,
+            timestamp_sanity: settings.timestamp_sanity.map(|sanity| TimestampSanityConfig {
+                max_future_drift: Duration::from_millis(sanity.max_future_drift_ms),
+                max_past_lag: sanity.max_past_lag_ms.map(Duration::from_millis),
+                strict: sanity.strict,
+                sane_to_clear: sanity.sane_to_clear.max(1),
+            }),
+            response_cache_entries: settings.response_cache_entries,
+            negative_cache_entries: settings.negative_cache_entries,
+            agreement_sampling: settings.agreement_sampling.map(|sampling| {
+                let window = sampling.window.max(1);
+                AgreementSamplingConfig {
+                    interval: Duration::from_millis(sampling.interval_ms),
+                    sample_size: sampling.sample_size.max(2),
+                    min_depth: sampling.min_depth,
+                    max_depth: sampling.max_depth.max(sampling.min_depth),
+                    window,
+                    min_samples: sampling.min_samples.clamp(1, window),
+                    max_disagreement_rate: sampling.max_disagreement_rate.clamp(0.0, 1.0),
+                    exclude_from_reads: sampling.exclude_from_reads,
+                }
+            }),
+            rotate_secrets_on_auth_error: settings.rotate_secrets_on_auth_error,
+            localnet: settings.localnet,
+            egress: settings.egress,
+            idempotent_methods: settings.idempotent_methods,
+            metric_tag_keys: {
+                let mut keys = settings.metric_tag_keys;
+                keys.sort();
+                keys.dedup();
+                keys
+            },
+            error_mappings: settings.error_mappings,
+            staleness: settings.staleness_policy.map(|staleness| DataStalenessConfig {
+                max_age: Duration::from_millis(staleness.max_age_ms),
+                strict: staleness.strict,
+            }),
+            custom_probes: settings.custom_probes,
+            custom_probe_policy: settings.custom_probe_policy,
+            constrained_mode: settings.constrained_mode.map(|mode| {
+                let max_total_inflight = mode.max_total_inflight.max(1);
+                let share = (max_total_inflight as f64 * mode.probe_share.clamp(0.0, 1.0)).floor() as usize;
+                ConstrainedModeConfig { max_total_inflight, probe_slots: share.clamp(1, max_total_inflight) }
+            }),
+            readiness: settings.readiness,
         },
-    }
+    })
+}
+
+/// `network_id` and its other ids: those `configured`, and any the registry's aliases connect
+/// to either.
+fn alias_group(network_id: NetworkId, configured: &[NetworkId]) -> Vec<NetworkId> {
+    let mut pairs = CHAIN_ALIASES.lock().clone();
+    pairs.extend(configured.iter().map(|alias| (network_id, *alias)));
+    connected(network_id, &pairs)
 }
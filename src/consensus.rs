@@ -0,0 +1,864 @@
+//! Consensus reads: the same request fanned out to several endpoints, answered with the value a
+//! quorum of them agrees on.
+//!
+//! Endpoints that fail a consensus request are cooled down and sit out the next ones, with longer
+//! cooldowns for repeated strikes. Needs the `consensus` feature.
+
+use std::{collections::{BTreeMap, HashMap}, fmt, sync::Arc, time::Duration};
+
+#[cfg(feature = "otel")]
+use crate::otel;
+use crate::{
+    calls::{ConsensusOptions, ConsensusPolicy, CooldownInfo, CooldownPolicy, RpcCalls},
+    clock::Clock,
+    comparator::{ResultComparator, StableStringComparator},
+    error::TimeoutPhase,
+    fanout::{fan_out, quorum_of, Tally},
+    jsonrpc::{diff_values, Difference, DiffOptions},
+    memory::evict_to_capacity,
+    methods,
+    panics,
+    performance::ProbeSchedule,
+    provider::{post_json_rpc, NonJsonRpcResponse, TrafficClass},
+    runtime::{self, JoinError},
+    tags::{self, check_tags, CallTags},
+    JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError,
+};
+#[cfg(feature = "otel")]
+use opentelemetry::KeyValue;
+use rand::seq::SliceRandom;
+use reqwest::header::HeaderMap;
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+use tracing::Instrument;
+use web_time::Instant;
+
+/// Differences kept on each minority outcome, the most significant first.
+pub const MAX_MINORITY_DIFFERENCES: usize = 5;
+
+impl RpcCalls {
+    /// Basic consensus: require a quorum of identical responses across providers.
+    pub async fn consensus<T>(
+        &self,
+        req: &JsonRpcRequest,
+        quorum_threshold: f64, // e.g., 0.66 for 66%
+        options: Option<ConsensusOptions>,
+    ) -> Result<T> 
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.consensus_with_report(req, quorum_threshold, options).await.0
+    }
+    
+    /// Like `consensus`, but also returns a report of how the attempt was carried out.
+    pub async fn consensus_with_report<T>(
+        &self,
+        req: &JsonRpcRequest,
+        quorum_threshold: f64,
+        options: Option<ConsensusOptions>,
+    ) -> (Result<T>, ConsensusReport)
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let tags = options.as_ref().and_then(|opts| opts.tags.clone());
+        let span = tags::call_span(&req.method, tags.as_ref());
+        let reported = self.consensus_reported(req, quorum_threshold, options);
+        let (result, report) = self.handler.in_span(format!("consensus {}", req.method), &req.method, reported).instrument(span).await;
+        self.tagged_outcome(&req.method, tags.as_ref(), &result);
+        (result, report)
+    }
+
+    async fn consensus_reported<T>(
+        &self,
+        req: &JsonRpcRequest,
+        quorum_threshold: f64,
+        options: Option<ConsensusOptions>,
+    ) -> (Result<T>, ConsensusReport)
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let opts = options.unwrap_or_default();
+        if let Some(tags) = &opts.tags
+            && let Err(e) = check_tags(tags)
+        {
+            return (Err(e), ConsensusReport::default());
+        }
+        let attempt = match self.consensus_attempt(req, quorum_threshold, &opts, true).await {
+            Ok(attempt) => attempt,
+            Err(e) => {
+                self.handler.metrics().record_consensus(false);
+                return (Err(e), ConsensusReport::default());
+            }
+        };
+        self.handler.metrics().record_consensus(attempt.success && attempt.value.is_some());
+        
+        if attempt.success
+            && let Some(value) = attempt.value
+        {
+            let result = serde_json::from_value(value)
+                .map_err(|e| RpcHandlerError::SerializationError(e.to_string()));
+            return (result, attempt.report);
+        }
+        
+        let err = RpcHandlerError::ConsensusFailure {
+            most_common: attempt.most_common_key.unwrap_or_else(|| "n/a".to_string()),
+        };
+        if let Some(journal) = self.handler.journal() {
+            let failed = attempt
+                .report
+                .outcomes
+                .iter()
+                .filter_map(|(url, outcome)| match outcome {
+                    EndpointOutcome::Failed { error } => Some((url.clone(), error.clone())),
+                    _ => None,
+                })
+                .collect();
+            journal.consensus_failed(req, &err, attempt.report.votes.clone(), failed, opts.tags.clone().unwrap_or_default());
+        }
+        (Err(err), attempt.report)
+    }
+    
+    /// BFT-style consensus: iteratively lowers quorum requirement if initial threshold fails.
+    pub async fn bft_consensus<T>(
+        &self,
+        req: &JsonRpcRequest,
+        quorum_threshold: f64,
+        min_threshold: f64,
+        options: Option<ConsensusOptions>,
+    ) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let tags = options.as_ref().and_then(|opts| opts.tags.clone());
+        let span = tags::call_span(&req.method, tags.as_ref());
+        let attempt = self.bft_attempt(req, quorum_threshold, min_threshold, options);
+        let result = self.handler.in_span(format!("bft_consensus {}", req.method), &req.method, attempt).instrument(span).await;
+        self.handler.metrics().record_consensus(result.is_ok());
+        self.tagged_outcome(&req.method, tags.as_ref(), &result);
+        result
+    }
+
+    /// Count a consensus read under its allowlisted tags and report it if it failed.
+    fn tagged_outcome<T>(&self, method: &str, tags: Option<&CallTags>, result: &Result<T>) {
+        self.handler.metrics().record_tagged(tags, &self.handler.config().settings.metric_tag_keys, result);
+        self.handler.report_tagged(method, tags, result);
+    }
+
+    async fn bft_attempt<T>(
+        &self,
+        req: &JsonRpcRequest,
+        quorum_threshold: f64,
+        min_threshold: f64,
+        options: Option<ConsensusOptions>,
+    ) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let opts = options.unwrap_or_default();
+        if let Some(tags) = &opts.tags {
+            check_tags(tags)?;
+        }
+        let base_attempt = self.consensus_attempt(req, quorum_threshold, &opts, false).await?;
+        
+        if base_attempt.success
+            && let Some(value) = base_attempt.value
+        {
+            return serde_json::from_value(value)
+                .map_err(|e| RpcHandlerError::SerializationError(e.to_string()));
+        }
+        
+        if base_attempt.tally.is_empty() {
+            return Err(RpcHandlerError::ConsensusFailure {
+                most_common: "No successful RPC responses for BFT consensus".to_string(),
+            });
+        }
+        
+        // Descend thresholds
+        let mut curr = quorum_threshold - 0.05;
+        while curr >= min_threshold {
+            let needed = quorum_of(base_attempt.tally.weight(), curr);
+            if needed == 0 {
+                break;
+            }
+            
+            if let (Some(most_key), Some(majority)) = (&base_attempt.most_common_key, &base_attempt.majority)
+                && base_attempt.tally.votes(most_key) >= needed
+            {
+                return serde_json::from_value(majority.clone())
+                    .map_err(|e| RpcHandlerError::SerializationError(e.to_string()));
+            }
+            
+            curr = (curr - 0.05).max(0.0);
+        }
+        
+        Err(RpcHandlerError::ConsensusFailure {
+            most_common: "Could not reach BFT consensus down to minimum threshold".to_string(),
+        })
+    }
+    
+    /// Health-check the cooled-down endpoints among `urls` closest to recovering, at most
+    /// `policy.max_paroles` of them, and clear the cooldowns of those that answer. Returns the
+    /// endpoints let back in.
+    ///
+    /// An endpoint is checked at most once per `parole_interval_ms`, so one that stays broken
+    /// isn't checked again on every call.
+    async fn parole(&self, urls: &[String], policy: &CooldownPolicy, now: Instant) -> Vec<String> {
+        let interval = Duration::from_millis(policy.parole_interval_ms);
+        let candidates: Vec<String> = {
+            let mut cooldowns = self.cooldowns.write().await;
+            let mut cooled: Vec<(&String, Instant)> = urls
+                .iter()
+                .filter_map(|url| {
+                    let cd = cooldowns.get(url)?;
+                    let due = cd.paroled_at.is_none_or(|at| now >= at + interval);
+                    (cd.until > now && due).then_some((url, cd.until))
+                })
+                .collect();
+            cooled.sort_by_key(|(_, until)| *until);
+            cooled.truncate(policy.max_paroles);
+            for (url, _) in &cooled {
+                if let Some(cd) = cooldowns.get_mut(*url) {
+                    cd.paroled_at = Some(now);
+                }
+            }
+            cooled.into_iter().map(|(url, _)| url.clone()).collect()
+        };
+
+        let checks = candidates.into_iter().map(|url| async move {
+            let healthy = self.health_check(&url).await;
+            (url, healthy)
+        });
+        let paroled: Vec<String> = futures::future::join_all(checks).await.into_iter().filter_map(|(url, healthy)| healthy.then_some(url)).collect();
+        if !paroled.is_empty() {
+            let mut cooldowns = self.cooldowns.write().await;
+            let redactor = self.handler.config().redactor.clone();
+            for url in &paroled {
+                cooldowns.remove(url);
+                tracing::info!(url = %redactor.redact(url), "Paroled provider from cooldown");
+            }
+        }
+        paroled
+    }
+
+    /// Whether `url` answers `eth_blockNumber` within the probe timeout.
+    async fn health_check(&self, url: &str) -> bool {
+        let request = JsonRpcRequest::new("eth_blockNumber", json!([]));
+        let config = self.handler.config();
+        let headers = self.handler.endpoint_headers();
+        let check = async {
+            self.handler.spend_meter().charge(url, &request.method)?;
+            let _slot = self.handler.host_limiter().acquire_as(url, TrafficClass::Probe).await;
+            let response = post_json_rpc(&self.client, url, &request, config.settings.follow_post_redirects, headers.get(url)).await?;
+            if !response.status().is_success() {
+                return Err(RpcHandlerError::HttpStatus { url: url.to_string(), status: response.status().as_u16() });
+            }
+            response.json::<JsonRpcResponse<Value>>().await.map_err(|e| RpcHandlerError::from_reqwest(e, url))?.into_result()
+        };
+        matches!(runtime::timeout(config.settings.rpc_timeout, check).await, Ok(Ok(_)))
+    }
+
+    async fn consensus_attempt(
+        &self,
+        req: &JsonRpcRequest,
+        quorum_threshold: f64,
+        options: &ConsensusOptions,
+        allow_early_abort: bool,
+    ) -> Result<ConsensusAttemptResult> {
+        let ConsensusPolicy { timeout_ms, concurrency: configured_concurrency, per_host_concurrency, cooldown, unanimous_prefix, allow_side_effects, .. } = options.describe();
+        // A method that may have side effects is only sent when allowed, and then to every
+        // endpoint, settling on the first to accept it as a broadcast does
+        let broadcast = !methods::known_idempotent(&req.method, &self.handler.config().settings.idempotent_methods);
+        if broadcast && !allow_side_effects {
+            return Err(RpcHandlerError::NonIdempotentMethodInConsensus { method: req.method.clone(), registered: methods::descriptor(&req.method).is_some() });
+        }
+        let unanimous_prefix = unanimous_prefix.filter(|_| !broadcast);
+        
+        let now = self.clock.now_instant();
+        // Endpoints agreement sampling caught serving a chain their peers don't aren't asked to vote
+        let distrusted = self.handler.agreement().flagged();
+        let (distrusted, http_urls): (Vec<String>, Vec<String>) = self.fan_out_urls(&req.method, now).into_iter().partition(|url| distrusted.contains(url));
+        let total_urls = http_urls.len();
+        
+        let mut rpc_urls = available_urls(&http_urls, &*self.cooldowns.read().await, now);
+        // Cooldowns piled up in a rough patch can leave too few endpoints to ever agree; check
+        // on the ones closest to recovering before giving up
+        let mut paroled = Vec::new();
+        if !quorum_feasible(&rpc_urls, &*self.cooldowns.read().await, now) {
+            paroled = self.parole(&http_urls, &cooldown, now).await;
+            if !paroled.is_empty() {
+                rpc_urls = available_urls(&http_urls, &*self.cooldowns.read().await, now);
+            }
+        }
+        
+        if rpc_urls.is_empty() {
+            return Err(RpcHandlerError::NoAvailableRpcs { 
+                network_id: self.handler.network_id 
+            });
+        }
+        
+        if rpc_urls.len() == 1 && !broadcast {
+            return Err(RpcHandlerError::ConsensusFailure {
+                most_common: "Only one RPC available, could not reach consensus".to_string(),
+            });
+        }
+        
+        let (concurrency, concurrency_note) = effective_concurrency(configured_concurrency, rpc_urls.len(), total_urls);
+        if let Some(ref note) = concurrency_note {
+            tracing::debug!(configured = configured_concurrency, effective = concurrency, reason = %note, "Reduced consensus concurrency");
+        }
+        let mut report = ConsensusReport {
+            configured_concurrency,
+            effective_concurrency: concurrency,
+            concurrency_note,
+            per_host_concurrency,
+            paroled,
+            distrusted,
+            ..ConsensusReport::default()
+        };
+        
+        // Randomize ordering
+        rpc_urls.shuffle(&mut rand::thread_rng());
+        
+        // One semaphore per hostname so a provider serving several URLs isn't hit in parallel
+        let mut host_limits: HashMap<String, Arc<tokio::sync::Semaphore>> = HashMap::new();
+        for url in &rpc_urls {
+            host_limits
+                .entry(host_of(url))
+                .or_insert_with(|| Arc::new(tokio::sync::Semaphore::new(per_host_concurrency)));
+        }
+        
+        let mut aborted = false;
+        let mut short_circuited = false;
+        let comparator: Arc<dyn ResultComparator> = options.comparator.clone().unwrap_or_else(|| Arc::new(StableStringComparator));
+        let mut tally = Tally::new(comparator);
+        
+        // Stop once a class holds a quorum of every endpoint being asked, since no later
+        // answers could outvote it
+        let early_quorum = quorum_of(rpc_urls.len(), quorum_threshold).max(1);
+        let maybe_abort_early = |tally: &Tally, key: &str| {
+            allow_early_abort && !broadcast && tally.votes(key) >= early_quorum
+        };
+        
+        let follow_redirects = self.handler.config().settings.follow_post_redirects;
+        let endpoint_headers = self.handler.endpoint_headers();
+        let run_request = move |url: String, req: JsonRpcRequest, client: reqwest::Client, headers: Option<HeaderMap>, clock: Arc<dyn Clock>, schedule: ProbeSchedule| async move {
+            #[cfg(feature = "otel")]
+            let (span, headers) = match otel::AttemptSpan::start(&url, &req.method) {
+                Some(span) => {
+                    let headers = span.inject(headers.as_ref());
+                    (Some(span), Some(headers))
+                }
+                None => (None, headers),
+            };
+            let result = runtime::timeout(
+                Duration::from_millis(timeout_ms),
+                post_json_rpc(&client, &url, &req, follow_redirects, headers.as_ref())
+            ).await;
+            
+            let outcome = match result {
+                Ok(Ok(response)) if response.status().is_success() => {
+                    match response.json::<JsonRpcResponse<Value>>().await {
+                        Ok(json_response) => match consensus_answer(&url, &req.method, json_response) {
+                            Ok(result) => SubRequestOutcome::Responded(url, result, false),
+                            Err(e) => SubRequestOutcome::Failed(url, e, None),
+                        },
+                        Err(e) => {
+                            let error = RpcHandlerError::from_reqwest(e, &url);
+                            SubRequestOutcome::Failed(url, error, None)
+                        }
+                    }
+                }
+                Ok(Ok(response)) => {
+                    let error = RpcHandlerError::HttpStatus { url: url.clone(), status: response.status().as_u16() };
+                    SubRequestOutcome::Failed(url, error, None)
+                }
+                Ok(Err(RpcHandlerError::NotAJsonRpcEndpoint { content_type, status, .. })) => {
+                    let classification = NonJsonRpcResponse { content_type, status };
+                    let error = classification.clone().into_error(&url);
+                    schedule.record_non_json_rpc(&url, classification, clock.now_instant());
+                    SubRequestOutcome::Failed(url, error, None)
+                }
+                Ok(Err(e @ RpcHandlerError::ConnectTimeout { .. })) => {
+                    schedule.record_unreachable(&url, clock.now_instant());
+                    SubRequestOutcome::Failed(url, e, None)
+                }
+                Ok(Err(e)) => SubRequestOutcome::Failed(url, e, None),
+                Err(_) => {
+                    let error = RpcHandlerError::request_timeout(&url, Duration::from_millis(timeout_ms));
+                    SubRequestOutcome::Failed(url, error, None)
+                }
+            };
+            #[cfg(feature = "otel")]
+            if let Some(span) = span {
+                span.finish(match &outcome {
+                    SubRequestOutcome::Failed(_, error, _) => Some(error),
+                    _ => None,
+                });
+            }
+            outcome
+        };
+        
+        let metrics = self.handler.metrics().with_endpoints(rpc_urls.iter().map(String::as_str));
+        
+        let start = |url: &str| {
+            let url = url.to_string();
+            let req = req.clone();
+            let client = self.client.clone();
+            let cooldowns = Arc::clone(&self.cooldowns);
+            let clock = Arc::clone(&self.clock);
+            let schedule = self.handler.probe_schedule().clone();
+            let host_limiter = self.handler.host_limiter().clone();
+            let spend = self.handler.spend_meter().clone();
+            let max_cooldowns = self.handler.config().settings.memory_limits.max_cooldown_entries;
+            let redactor = self.handler.config().redactor.clone();
+            let cooldown_metrics = metrics.clone();
+            let handler = Arc::clone(&self.handler);
+            let host_limit = Arc::clone(&host_limits[&host_of(&url)]);
+            let headers = endpoint_headers.get(&url).cloned();
+            #[cfg(feature = "otel")]
+            let otel_cx = opentelemetry::Context::current();
+            
+            async move {
+                let _host_permit = host_limit.acquire_owned().await.unwrap();
+                
+                // A sibling task may have cooled this host down while we were queued
+                if host_cooling_down(&cooldowns, &url, clock.now_instant()).await {
+                    return SubRequestOutcome::Skipped(url);
+                }
+                // The handler-wide cap is shared with the proxy and probes; a full host sits this fan-out out
+                let Some(_host_slot) = host_limiter.try_acquire(&url) else {
+                    return SubRequestOutcome::Saturated(url);
+                };
+                // Running out of budget mid fan-out says nothing about the endpoint, so no cooldown
+                if spend.charge(&url, &req.method).is_err() {
+                    return SubRequestOutcome::Skipped(url);
+                }
+                
+                let run = run_request(url, req.clone(), client, headers, Arc::clone(&clock), schedule);
+                // Spawned tasks don't inherit the call span, so the attempt is put back under it
+                #[cfg(feature = "otel")]
+                let run = opentelemetry::context::FutureExt::with_context(run, otel_cx);
+                let sent = clock.now_instant();
+                let outcome = run.await;
+                
+                // Cool down before releasing the host permit so queued tasks for this host see it.
+                // A broadcast the endpoint answered with an error was rejected, not failed. Answers
+                // feed the endpoint ordering, those landing after an early abort too.
+                match outcome {
+                    SubRequestOutcome::Responded(url, result, _) => {
+                        let observed = handler.observe_latency(&url, &req.method, clock.now_instant().saturating_duration_since(sent)).await;
+                        SubRequestOutcome::Responded(url, result, observed)
+                    }
+                    SubRequestOutcome::Failed(url, error, _) if !(broadcast && matches!(error, RpcHandlerError::JsonRpcCode { .. })) => {
+                        let cooldown = apply_cooldown(&cooldowns, &url, &cooldown, &error, &req.method, clock.now_instant(), max_cooldowns).await;
+                        cooldown_metrics.record_cooldown();
+                        tracing::warn!(
+                            url = %redactor.redact(&url),
+                            strikes = cooldown.strikes,
+                            delay_ms = cooldown.delay_ms,
+                            "Cooling down provider"
+                        );
+                        SubRequestOutcome::Failed(url, error, Some(cooldown))
+                    }
+                    outcome => outcome,
+                }
+            }
+        };
+        
+        // Keep up to `concurrency` requests in flight, handling answers in the order they arrive
+        let on_outcome = |url: String, joined: std::result::Result<SubRequestOutcome, JoinError>| {
+            let outcome = match joined {
+                Ok(outcome) => outcome,
+                Err(join) => {
+                    // A panicked task fails its endpoint's vote; a cancelled one has nothing to say
+                    match panics::join_failure(panics::FAN_OUT_TASK, join) {
+                        Some(panicked) => {
+                            self.handler.record_panic(&panicked);
+                            metrics.record_attempt(&url, Some(&panicked));
+                            report.outcomes.insert(url, EndpointOutcome::Failed { error: panicked.to_string() });
+                        }
+                        None => {
+                            report.outcomes.insert(url, EndpointOutcome::Cancelled);
+                        }
+                    }
+                    return !aborted;
+                }
+            };
+            match outcome {
+                SubRequestOutcome::Responded(url, result, observed) => {
+                    metrics.record_attempt(&url, None);
+                    report.latency_observations += usize::from(observed);
+                    self.handler.liveness_log().record_attempt(&url, None, self.clock.now_system());
+                    let key = match tally.vote(url.clone(), result, 1) {
+                        Ok(key) => key,
+                        Err(panicked) => {
+                            self.handler.record_panic(&panicked);
+                            report.outcomes.insert(url, EndpointOutcome::Failed { error: panicked.to_string() });
+                            return !aborted;
+                        }
+                    };
+                    
+                    if unanimous_prefix == Some(tally.weight()) && tally.classes() == 1 {
+                        short_circuited = true;
+                        aborted = true;
+                    } else if maybe_abort_early(&tally, &key) {
+                        aborted = true;
+                    }
+                }
+                SubRequestOutcome::Failed(url, error, cooldown) => {
+                    metrics.record_attempt(&url, Some(&error));
+                    self.handler.liveness_log().record_attempt(&url, Some(&error), self.clock.now_system());
+                    // Cooldown was already applied inside the task
+                    report.outcomes.insert(url, EndpointOutcome::Failed { error: error.to_string() });
+                    report.cooldowns.extend(cooldown);
+                }
+                SubRequestOutcome::Skipped(url) => {
+                    report.outcomes.insert(url.clone(), EndpointOutcome::Skipped);
+                    report.skipped_urls.push(url);
+                }
+                SubRequestOutcome::Saturated(url) => {
+                    report.outcomes.insert(url, EndpointOutcome::Saturated);
+                }
+            }
+            !aborted
+        };
+        let pending = fan_out(&rpc_urls, concurrency, start, on_outcome).await;
+        // An early abort lets the requests still in flight finish and cool down endpoints that
+        // fail; a unanimous prefix has all it needs and cancels them
+        if short_circuited {
+            for (url, task) in pending {
+                task.abort();
+                report.outcomes.insert(url, EndpointOutcome::Cancelled);
+            }
+            report.short_circuited = true;
+        }
+        
+        report.votes = tally.votes_by_key();
+        report.most_common = tally.most_common();
+        // A comparator that panics merging a class leaves it without a value, so it can't win
+        let majority = match report.most_common.as_deref().map(|key| tally.value(key)) {
+            Some(Ok(majority)) => majority,
+            Some(Err(panicked)) => {
+                self.handler.record_panic(&panicked);
+                None
+            }
+            None => None,
+        };
+        let mut differences: HashMap<&str, Vec<Difference>> = HashMap::new();
+        for (url, key) in tally.voters() {
+            let outcome = if report.most_common.as_ref() == Some(key) {
+                EndpointOutcome::Majority { key: key.clone() }
+            } else {
+                // Answers in one class share their differences from the majority
+                let differences = differences
+                    .entry(key.as_str())
+                    .or_insert_with(|| match (&majority, tally.value(key)) {
+                        (Some(majority), Ok(Some(minority))) => diff_values(majority, &minority, &DiffOptions::default()).most_significant(MAX_MINORITY_DIFFERENCES),
+                        _ => Vec::new(),
+                    })
+                    .clone();
+                EndpointOutcome::Minority { key: key.clone(), differences }
+            };
+            report.outcomes.insert(url.clone(), outcome);
+        }
+        
+        if tally.is_empty() {
+            return Ok(ConsensusAttemptResult {
+                success: false,
+                value: None,
+                majority: None,
+                most_common_key: None,
+                tally,
+                report,
+            });
+        }
+        
+        let final_quorum = if broadcast { 1 } else { quorum_of(tally.weight(), quorum_threshold) };
+        let most_common_key = report.most_common.clone();
+        report.quorum = Some(final_quorum);
+        #[cfg(feature = "otel")]
+        otel::add_event("consensus.decision", [
+            KeyValue::new("responded", tally.weight() as i64),
+            KeyValue::new("quorum", final_quorum as i64),
+            KeyValue::new("majority_votes", most_common_key.as_ref().map_or(0, |key| tally.votes(key)) as i64),
+            KeyValue::new("classes", tally.classes() as i64),
+            KeyValue::new("cooldowns", report.cooldowns.len() as i64),
+            KeyValue::new("aborted_early", aborted),
+            KeyValue::new("short_circuited", short_circuited),
+        ]);
+        
+        let value = majority.clone().filter(|_| most_common_key.as_ref().is_some_and(|key| tally.votes(key) >= final_quorum));
+        Ok(ConsensusAttemptResult {
+            success: value.is_some(),
+            value,
+            majority,
+            most_common_key,
+            tally,
+            report,
+        })
+    }
+}
+
+impl CooldownPolicy {
+    fn delay_ms(&self, penalty: Penalty, strikes: u32) -> u64 {
+        let delay = match penalty {
+            Penalty::Light => self.base_ms as f64 * self.light_fraction,
+            Penalty::Normal => self.base_ms as f64 * self.normal_factor.powi(strikes as i32 - 1),
+            Penalty::Severe => self.base_ms as f64 * self.severe_factor.powi(strikes as i32 - 1),
+        };
+        (delay as u64).min(self.max_ms)
+    }
+}
+
+/// How hard a failure counts against an endpoint's cooldown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Penalty {
+    /// A heavy call outlasting the total budget: half the base cooldown, no strike
+    Light,
+    Normal,
+    /// Rate limited, or not even connectable: the cooldown doubles with each strike
+    Severe,
+}
+
+impl Penalty {
+    fn for_failure(error: &RpcHandlerError, method: &str) -> Self {
+        match error.timeout_phase() {
+            Some(TimeoutPhase::ConnectPhase) => Penalty::Severe,
+            Some(TimeoutPhase::TotalBudget) if methods::is_heavy(method) => Penalty::Light,
+            _ if error.is_rate_limited() => Penalty::Severe,
+            _ => Penalty::Normal,
+        }
+    }
+}
+
+async fn apply_cooldown(
+    cooldowns: &RwLock<HashMap<String, CooldownInfo>>,
+    url: &str,
+    policy: &CooldownPolicy,
+    error: &RpcHandlerError,
+    method: &str,
+    now: Instant,
+    max_entries: usize,
+) -> AppliedCooldown {
+    let penalty = Penalty::for_failure(error, method);
+    let mut cooldowns = cooldowns.write().await;
+    let existing = cooldowns.get(url);
+    let existing_strikes = existing.map(|cd| cd.strikes).unwrap_or(0);
+    
+    let strikes = match penalty {
+        Penalty::Light => existing_strikes,
+        Penalty::Normal | Penalty::Severe => existing_strikes + 1,
+    };
+    let delay = policy.delay_ms(penalty, strikes);
+    // A light penalty never cuts short a cooldown already running
+    let until = (now + Duration::from_millis(delay)).max(existing.map_or(now, |cd| cd.until));
+    let paroled_at = existing.and_then(|cd| cd.paroled_at);
+    
+    cooldowns.insert(url.to_string(), CooldownInfo {
+        strikes,
+        until,
+        paroled_at,
+    });
+    evict_to_capacity(&mut cooldowns, max_entries, |_, cd| cd.until > now, |_, cd| cd.until);
+    
+    AppliedCooldown { url: url.to_string(), strikes, delay_ms: delay, rate_limited: error.is_rate_limited() }
+}
+
+/// The value `url` votes with. A `result: null` is a vote like any other when `method` may
+/// return it; a response with neither `result` nor `error` is malformed, not a vote for `null`.
+fn consensus_answer(url: &str, method: &str, response: JsonRpcResponse<Value>) -> Result<Value> {
+    let violation = match (&response.result, &response.error) {
+        (None, None) => "response has neither result nor error",
+        (Some(Value::Null), None) if !methods::null_result_valid(method) => "result is null without an error",
+        _ => return response.into_result(),
+    };
+    Err(RpcHandlerError::MalformedResponse { url: url.to_string(), violation: violation.to_string() })
+}
+
+/// Hostname of a URL, falling back to the URL itself if it can't be parsed.
+pub(crate) fn host_of(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Whether any URL sharing `url`'s hostname is currently cooling down.
+async fn host_cooling_down(cooldowns: &RwLock<HashMap<String, CooldownInfo>>, url: &str, now: Instant) -> bool {
+    host_cooled(&*cooldowns.read().await, url, now)
+}
+
+fn host_cooled(cooldowns: &HashMap<String, CooldownInfo>, url: &str, now: Instant) -> bool {
+    let host = host_of(url);
+    cooldowns.iter().any(|(cooled_url, cd)| cd.until > now && host_of(cooled_url) == host)
+}
+
+/// `urls` that aren't cooling down themselves.
+fn available_urls(urls: &[String], cooldowns: &HashMap<String, CooldownInfo>, now: Instant) -> Vec<String> {
+    urls.iter().filter(|url| cooldowns.get(*url).is_none_or(|cd| cd.until <= now)).cloned().collect()
+}
+
+/// Whether `available` could reach a quorum at all.
+///
+/// The quorum is a share of the endpoints that answer, so any two answering endpoints can reach
+/// it; an endpoint whose host is cooling down is skipped rather than asked, so it doesn't count.
+fn quorum_feasible(available: &[String], cooldowns: &HashMap<String, CooldownInfo>, now: Instant) -> bool {
+    available.iter().filter(|url| !host_cooled(cooldowns, url, now)).count() >= 2
+}
+
+/// Scale concurrency down with the share of endpoints still available, so the few endpoints left
+/// outside cooldown aren't hit with the full fan-out meant for the whole set.
+fn effective_concurrency(configured: usize, available: usize, total: usize) -> (usize, Option<String>) {
+    let capped = configured.min(available).max(1);
+    if available >= total || total == 0 {
+        return (capped, None);
+    }
+    
+    let scaled = ((configured * available) as f64 / total as f64).ceil() as usize;
+    let scaled = scaled.clamp(1, capped);
+    if scaled < capped {
+        let note = format!("{} of {} endpoints cooling down", total - available, total);
+        (scaled, Some(note))
+    } else {
+        (capped, None)
+    }
+}
+
+enum SubRequestOutcome {
+    /// Carries whether the task fed the answer's latency into the endpoint ordering
+    Responded(String, Value, bool),
+    /// Carries the cooldown once the task has applied it
+    Failed(String, RpcHandlerError, Option<AppliedCooldown>),
+    /// Not sent because the host entered cooldown while the request was queued
+    Skipped(String),
+    /// Not sent because the host was at its `HostLimits` cap
+    Saturated(String),
+}
+
+/// How a consensus attempt was carried out, independent of whether it reached quorum.
+#[derive(Debug, Clone, Default)]
+pub struct ConsensusReport {
+    pub configured_concurrency: usize,
+    pub effective_concurrency: usize,
+    /// Why `effective_concurrency` is lower than configured, if it is
+    pub concurrency_note: Option<String>,
+    pub per_host_concurrency: usize,
+    /// URLs whose request was dropped because their host was cooled down mid-attempt
+    pub skipped_urls: Vec<String>,
+    /// Responses per comparator key
+    pub votes: BTreeMap<String, usize>,
+    /// Key with the most votes, `None` when nothing answered
+    pub most_common: Option<String>,
+    /// Votes the most common key needed, `None` when nothing answered
+    pub quorum: Option<usize>,
+    /// What each endpoint that was asked contributed; endpoints left unasked after an early
+    /// abort are absent
+    pub outcomes: BTreeMap<String, EndpointOutcome>,
+    /// Cooldowns applied to endpoints that failed during this attempt
+    pub cooldowns: Vec<AppliedCooldown>,
+    /// Cooled-down endpoints let back in early because they passed a health check
+    pub paroled: Vec<String>,
+    /// Endpoints left out because agreement sampling flagged them `SuspectedDishonest`
+    pub distrusted: Vec<String>,
+    /// Settled on `ConsensusOptions::unanimous_prefix` agreeing answers, the count in `votes`
+    pub short_circuited: bool,
+    /// Answers whose latency was fed into the endpoint ordering, or for heavy methods into
+    /// `RpcHandler::heavy_method_latencies`. Answers landing after an early abort are fed in
+    /// too, but after the report is made, so they aren't counted.
+    pub latency_observations: usize,
+}
+
+/// What one endpoint contributed to a consensus attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EndpointOutcome {
+    /// Answered with the most common result
+    Majority { key: String },
+    /// Answered with a result outside the most common class. `differences` lists where its
+    /// class's value departs from the majority's, the majority on the left; empty when they
+    /// differ only in hex case or ignored fields
+    Minority { key: String, differences: Vec<Difference> },
+    /// Errored or timed out, and was cooled down
+    Failed { error: String },
+    /// Not sent because the host entered cooldown while the request was queued
+    Skipped,
+    /// Not sent because the host was at its `HostLimits` cap
+    Saturated,
+    /// Still in flight when a unanimous prefix settled the attempt, and cancelled
+    Cancelled,
+}
+
+/// A cooldown applied to a failing endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedCooldown {
+    pub url: String,
+    /// Consecutive failures so far, each lengthening the cooldown
+    pub strikes: u32,
+    pub delay_ms: u64,
+    pub rate_limited: bool,
+}
+
+impl ConsensusReport {
+    /// URLs whose outcome satisfies `predicate`, in URL order.
+    pub fn urls_where(&self, predicate: impl Fn(&EndpointOutcome) -> bool) -> Vec<&str> {
+        self.outcomes.iter().filter(|(_, outcome)| predicate(outcome)).map(|(url, _)| url.as_str()).collect()
+    }
+}
+
+impl fmt::Display for ConsensusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.most_common, self.quorum) {
+            (Some(key), Some(quorum)) => {
+                let votes = self.votes.get(key).copied().unwrap_or(0);
+                let verdict = if votes >= quorum { "reached" } else { "missed" };
+                writeln!(f, "{key} has {votes} of {quorum} votes needed, quorum {verdict}")?;
+            }
+            _ => writeln!(f, "no endpoint answered")?,
+        }
+        for (url, outcome) in &self.outcomes {
+            match outcome {
+                EndpointOutcome::Majority { key } => writeln!(f, "  majority  {url}  {key}")?,
+                EndpointOutcome::Minority { key, differences } => {
+                    writeln!(f, "  minority  {url}  {key}")?;
+                    for difference in differences {
+                        writeln!(f, "            {difference}")?;
+                    }
+                }
+                EndpointOutcome::Failed { error } => {
+                    write!(f, "  failed    {url}  {error}")?;
+                    match self.cooldowns.iter().find(|cd| &cd.url == url) {
+                        Some(cd) => writeln!(f, "  [cooldown {}ms, strike {}]", cd.delay_ms, cd.strikes)?,
+                        None => writeln!(f)?,
+                    }
+                }
+                EndpointOutcome::Skipped => writeln!(f, "  skipped   {url}")?,
+                EndpointOutcome::Saturated => writeln!(f, "  saturated {url}")?,
+                EndpointOutcome::Cancelled => writeln!(f, "  cancelled {url}")?,
+            }
+        }
+        if self.short_circuited {
+            writeln!(f, "short-circuited on {} identical answers", self.votes.values().sum::<usize>())?;
+        }
+        if !self.paroled.is_empty() {
+            writeln!(f, "paroled {}", self.paroled.join(", "))?;
+        }
+        if !self.distrusted.is_empty() {
+            writeln!(f, "distrusted {}", self.distrusted.join(", "))?;
+        }
+        write!(f, "concurrency {} of {} configured, {} per host", self.effective_concurrency, self.configured_concurrency, self.per_host_concurrency)?;
+        if let Some(note) = &self.concurrency_note {
+            write!(f, " ({note})")?;
+        }
+        writeln!(f)
+    }
+}
+
+#[derive(Debug)]
+struct ConsensusAttemptResult {
+    success: bool,
+    value: Option<Value>,
+    /// What the most common answers settle on, quorum or not
+    majority: Option<Value>,
+    most_common_key: Option<String>,
+    tally: Tally,
+    report: ConsensusReport,
+}
@@ -0,0 +1,59 @@
+//! Serving without a passing probe, and getting back out of it.
+//!
+//! When `init` runs out of `InitPolicy::max_attempts` without a healthy endpoint and the policy
+//! allows it, the handler serves through its endpoints unprobed as `InitState::Degraded`. The
+//! recovery loop then refreshes with the same doubling backoff until a refresh installs a probed
+//! provider, and ends there. A refresh from anywhere else, auto-refresh included, recovers the
+//! handler just the same.
+
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{events::InitState, readiness::Heartbeat, runtime::{self, JoinHandle}, RpcHandler};
+
+/// Longest pause between recovery attempts, however far the backoff has doubled.
+pub const MAX_RECOVERY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The pause before retry number `retry`, counting from one: `base`, doubled for each retry after.
+pub fn retry_backoff(base: Duration, retry: u32) -> Duration {
+    base.saturating_mul(1 << retry.saturating_sub(1).min(16))
+}
+
+/// Spawn the loop that refreshes a degraded `handler` until it serves through a probed provider.
+/// Like the other loops it holds only a weak reference, and ends once the handler is dropped or
+/// `shutdown` is cancelled.
+pub(crate) fn spawn_recovery(
+    handler: &Arc<RpcHandler>,
+    backoff: Duration,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+    let clock = Arc::clone(handler.clock());
+
+    runtime::spawn(async move {
+        let mut retry = 1;
+        loop {
+            heartbeat.beat();
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = clock.sleep(retry_backoff(backoff, retry).min(MAX_RECOVERY_BACKOFF)) => {}
+            }
+            let Some(handler) = weak.upgrade() else { return };
+            if matches!(handler.init_state(), InitState::Degraded { .. })
+                && let Err(e) = handler.refresh().await
+            {
+                handler.log("warn", "Recovery refresh failed", Some(serde_json::json!({ "error": e.to_string() }))).await;
+            }
+            if !matches!(handler.init_state(), InitState::Degraded { .. }) {
+                heartbeat.retire();
+                return;
+            }
+            retry = retry.saturating_add(1);
+        }
+    })
+}
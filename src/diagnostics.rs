@@ -0,0 +1,251 @@
+//! Everything a bug report needs about a handler, gathered in one serializable bundle.
+//!
+//! `RpcHandler::diagnostics_bundle` collects the effective policy, the health report, latencies,
+//! cooldowns, counters, the recent events and failed attempts, the embedded chain data and the
+//! environment. Before it is returned, every string in it, object keys included, goes through
+//! the config's `Redactor`, and `DiagnosticsBundle::redaction` records how many secrets that
+//! replaced. `DiagnosticsOptions::include_raw_urls` skips that pass for local debugging; the
+//! health report, policy and provenance are redacted at the source either way.
+
+use std::{
+    fmt::Write as _,
+    io,
+    path::Path,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    events::RecordedEvent, liveness::AttemptFailure, metrics::MetricsSnapshot, runtime, LatencyRecord, NetworkId, Order, RpcHandler, SortBy,
+};
+
+/// Events and failed attempts `DiagnosticsBundle::summary` lists.
+const SUMMARY_TAIL: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiagnosticsOptions {
+    /// Leave URLs and other strings as the handler holds them, secrets included
+    pub include_raw_urls: bool,
+}
+
+/// Where the handler is running.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentInfo {
+    pub os: String,
+    pub arch: String,
+    pub family: String,
+    pub available_parallelism: Option<usize>,
+    /// `current_thread` or `multi_thread`, `wasm_event_loop` in a browser, `None` outside a Tokio runtime
+    pub runtime: Option<String>,
+    pub runtime_workers: Option<usize>,
+}
+
+/// The chain data the handler sees. It is embedded at build time, so `crate_version` dates it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainDataInfo {
+    /// Built with the `chainlist` feature
+    pub embedded: bool,
+    /// Chains in the handler's `DataScope`
+    pub chains: usize,
+    /// The chain data's name for the handler's network, `None` if it doesn't know it
+    pub network_name: Option<String>,
+    /// Public endpoints the chain data lists for the network
+    pub extra_rpcs: usize,
+    /// Endpoints the handler uses, from every source
+    pub endpoints: usize,
+}
+
+/// What the redaction pass did.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactionAudit {
+    /// Set when the pass was skipped under `DiagnosticsOptions::include_raw_urls`
+    pub raw_urls: bool,
+    /// Placeholders secrets were replaced with, sorted
+    pub placeholders: Vec<String>,
+    /// Secrets found and replaced across the bundle
+    pub secrets_redacted: usize,
+}
+
+/// A consensus cooldown still running.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CooldownEntry {
+    pub url: String,
+    pub remaining_ms: u64,
+    /// Failures in a row that set its length
+    pub strikes: u32,
+}
+
+/// One redacted snapshot of a handler, for attaching to an issue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsBundle {
+    pub generated_at: SystemTime,
+    pub crate_version: String,
+    pub network_id: NetworkId,
+    pub environment: EnvironmentInfo,
+    pub chain_data: ChainDataInfo,
+    pub redaction: RedactionAudit,
+    /// `RpcHandler::effective_policy` as JSON
+    pub effective_policy: Value,
+    /// `RpcHandler::health_report` as JSON
+    pub health: Value,
+    /// Sorted by URL
+    pub latencies: Vec<(String, LatencyRecord)>,
+    pub cooldowns: Vec<CooldownEntry>,
+    pub metrics: MetricsSnapshot,
+    /// Oldest first
+    pub recent_events: Vec<RecordedEvent>,
+    /// Oldest first
+    pub recent_failures: Vec<AttemptFailure>,
+}
+
+impl DiagnosticsBundle {
+    /// Write the bundle as one JSON file, gzipped when `path` ends in `.gz`. Without the `gzip`
+    /// feature a `.gz` path is refused with `ErrorKind::Unsupported`.
+    pub fn write_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        runtime::require_filesystem("DiagnosticsBundle::write_to")?;
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        let bytes = if path.extension().is_some_and(|extension| extension == "gz") { gzip(&json)? } else { json };
+        std::fs::write(path, bytes)
+    }
+
+    /// A one-page plain-text overview for pasting into an issue.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        let policy = &self.effective_policy;
+        let _ = writeln!(out, "ez_web3_rpc {} on {}/{}", self.crate_version, self.environment.os, self.environment.arch);
+        let _ = writeln!(
+            out,
+            "network {} ({}), strategy {}, failover {}",
+            self.network_id,
+            self.chain_data.network_name.as_deref().unwrap_or("unknown"),
+            policy["strategy"]["name"].as_str().unwrap_or("?"),
+            policy["failover_policy"].as_str().unwrap_or("?"),
+        );
+        let _ = writeln!(out, "active: {}", self.health["active_url"].as_str().unwrap_or("none"));
+
+        let endpoints = self.health["endpoints"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let _ = writeln!(out, "\nendpoints ({}):", endpoints.len());
+        for endpoint in endpoints {
+            let latency = endpoint["latency_ms"].as_u64().map_or_else(|| "unhealthy".to_string(), |ms| format!("{ms}ms"));
+            let _ = write!(out, "  {latency:>10}  {}", endpoint["url"].as_str().unwrap_or("?"));
+            if let Some(lag) = endpoint["head_lag"].as_u64().filter(|lag| *lag > 0) {
+                let _ = write!(out, "  [{lag} blocks behind]");
+            }
+            if let Some(flags) = endpoint["flags"].as_array().filter(|flags| !flags.is_empty()) {
+                let flags: Vec<&str> = flags.iter().filter_map(Value::as_str).collect();
+                let _ = write!(out, "  [{}]", flags.join(", "));
+            }
+            let _ = writeln!(out);
+        }
+
+        let totals = &self.metrics.totals;
+        let _ = writeln!(out, "\nrequests {}, successes {}, failovers {}", totals.requests, totals.successes, totals.failovers);
+        if !totals.failures.is_empty() {
+            let failures: Vec<String> = totals.failures.iter().map(|(class, count)| format!("{class:?} {count}")).collect();
+            let _ = writeln!(out, "failures: {}", failures.join(", "));
+        }
+        if !self.cooldowns.is_empty() {
+            let _ = writeln!(out, "cooling down: {}", self.cooldowns.iter().map(|cooldown| cooldown.url.as_str()).collect::<Vec<_>>().join(", "));
+        }
+
+        let _ = writeln!(out, "\nlast failed attempts ({} kept):", self.recent_failures.len());
+        for failure in self.recent_failures.iter().rev().take(SUMMARY_TAIL) {
+            let _ = writeln!(out, "  {:?} {}: {}", failure.class, failure.url, failure.message);
+        }
+        let _ = writeln!(out, "last events ({} kept):", self.recent_events.len());
+        for recorded in self.recent_events.iter().rev().take(SUMMARY_TAIL) {
+            let _ = writeln!(out, "  {:?}", recorded.event);
+        }
+        if self.redaction.raw_urls {
+            let _ = writeln!(out, "\nWARNING: generated with raw URLs, may contain secrets");
+        }
+        out
+    }
+}
+
+impl EnvironmentInfo {
+    fn current() -> Self {
+        let runtime = runtime::flavor();
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            family: std::env::consts::FAMILY.to_string(),
+            available_parallelism: std::thread::available_parallelism().ok().map(usize::from),
+            runtime_workers: runtime.as_ref().map(|(_, workers)| *workers),
+            runtime: runtime.map(|(flavor, _)| flavor),
+        }
+    }
+}
+
+impl RpcHandler {
+    /// `diagnostics_bundle_with` the default options: everything redacted.
+    pub async fn diagnostics_bundle(&self) -> DiagnosticsBundle {
+        self.diagnostics_bundle_with(DiagnosticsOptions::default()).await
+    }
+
+    /// Gather the handler's state into one bundle for a bug report.
+    pub async fn diagnostics_bundle_with(&self, options: DiagnosticsOptions) -> DiagnosticsBundle {
+        let config = self.config();
+        let now = self.clock().now_instant();
+        let cooldowns = self
+            .cooldowns()
+            .read()
+            .await
+            .iter()
+            .filter(|(_, cooldown)| cooldown.until > now)
+            .map(|(url, cooldown)| CooldownEntry { url: url.clone(), remaining_ms: (cooldown.until - now).as_millis() as u64, strikes: cooldown.strikes })
+            .collect();
+        let chain_data = self.chain_data();
+        let mut placeholders = config.redactor.placeholders();
+        placeholders.sort();
+        placeholders.dedup();
+
+        let bundle = DiagnosticsBundle {
+            generated_at: self.clock().now_system(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            network_id: self.network_id,
+            environment: EnvironmentInfo::current(),
+            chain_data: ChainDataInfo {
+                embedded: cfg!(feature = "chainlist"),
+                chains: chain_data.chain_ids().len(),
+                network_name: chain_data.chain_info(self.network_id).map(|chain| chain.name),
+                extra_rpcs: chain_data.extra_rpcs(self.network_id).len(),
+                endpoints: self.rpcs().len(),
+            },
+            redaction: RedactionAudit { raw_urls: options.include_raw_urls, placeholders, secrets_redacted: 0 },
+            effective_policy: serde_json::to_value(self.effective_policy()).unwrap_or_default(),
+            health: serde_json::to_value(self.health_report().await).unwrap_or_default(),
+            latencies: self.latencies_sorted(SortBy::Url, Order::Ascending).await,
+            cooldowns,
+            metrics: self.metrics_snapshot(),
+            recent_events: self.recent_events(),
+            recent_failures: self.recent_failures(),
+        };
+        if options.include_raw_urls {
+            return bundle;
+        }
+
+        let raw = serde_json::to_value(&bundle).expect("a bundle serializes");
+        let secrets_redacted = config.redactor.occurrences(&raw);
+        let mut redacted: DiagnosticsBundle = serde_json::from_value(config.redactor.redact_value(&raw)).expect("redaction keeps a bundle's shape");
+        redacted.redaction.secrets_redacted = secrets_redacted;
+        redacted
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "writing a .gz bundle needs the `gzip` feature"))
+}
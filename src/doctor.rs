@@ -0,0 +1,339 @@
+//! Startup diagnostics.
+//!
+//! `RpcHandler::doctor` runs through the usual reasons a handler ends up with nothing to talk
+//! to: a build without chain data, tracking filters that leave no endpoints, a wrong network id,
+//! blocked egress, or endpoints failing the Permit2 probe. It sends a bounded handful of
+//! requests, skips endpoints that are cooling down, and changes no handler state, so it can run
+//! before `init` and as often as needed.
+
+use std::{
+    fmt,
+    net::IpAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::join_all;
+use reqwest::header;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    chainlist,
+    namespaces::parse_quantity,
+    performance::{measure_rpcs_with_options, TimeoutPolicy},
+    provider::{dns::host_of, post_json_rpc, TrafficClass},
+    runtime,
+    JsonRpcRequest, JsonRpcResponse, NetworkId, Rpc, RpcHandler, RpcHandlerError,
+};
+
+/// Hostnames resolved by the DNS check, at most.
+pub const DNS_SAMPLE: usize = 3;
+const DNS_TIMEOUT: Duration = Duration::from_secs(3);
+/// 2024-01-01; a system clock before this has not been set.
+const EARLIEST_PLAUSIBLE_UNIX_SECS: u64 = 1_704_067_200;
+/// Skew against an endpoint's `Date` header beyond which cooldowns and maintenance windows
+/// are no longer timed as configured.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DoctorCheck {
+    /// The embedded chain registry has data for the network
+    EmbeddedData,
+    /// Endpoints left after tracking filters
+    RpcCount,
+    /// A sample of endpoint hostnames resolves
+    Dns,
+    /// One endpoint passes the block and Permit2 probe
+    LiveProbe,
+    /// The best endpoint serves the configured network
+    ChainId,
+    /// The system clock is plausible and agrees with the endpoint's
+    Clock,
+}
+
+impl fmt::Display for DoctorCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DoctorCheck::EmbeddedData => "embedded chain data",
+            DoctorCheck::RpcCount => "endpoints after filters",
+            DoctorCheck::Dns => "DNS resolution",
+            DoctorCheck::LiveProbe => "live probe",
+            DoctorCheck::ChainId => "chain id",
+            DoctorCheck::Clock => "clock",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "detail", rename_all = "snake_case")]
+pub enum CheckOutcome {
+    Pass,
+    Warn(String),
+    /// What went wrong, and what to try
+    Fail(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub check: DoctorCheck,
+    pub outcome: CheckOutcome,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    pub network_id: NetworkId,
+    /// The endpoint the live checks went to, if any was eligible
+    pub target: Option<String>,
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    pub fn outcome(&self, check: DoctorCheck) -> Option<&CheckOutcome> {
+        self.checks.iter().find(|result| result.check == check).map(|result| &result.outcome)
+    }
+
+    /// Whether no check failed; warnings don't count.
+    pub fn passed(&self) -> bool {
+        !self.checks.iter().any(|result| matches!(result.outcome, CheckOutcome::Fail(..)))
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "doctor: network {}", self.network_id)?;
+        match &self.target {
+            Some(target) => writeln!(f, ", checked against {target}")?,
+            None => writeln!(f)?,
+        }
+        for result in &self.checks {
+            match &result.outcome {
+                CheckOutcome::Pass => writeln!(f, "  [pass] {}", result.check)?,
+                CheckOutcome::Warn(message) => writeln!(f, "  [warn] {}: {message}", result.check)?,
+                CheckOutcome::Fail(message, suggestion) => {
+                    writeln!(f, "  [FAIL] {}: {message}", result.check)?;
+                    writeln!(f, "         try: {suggestion}")?;
+                }
+            }
+        }
+        write!(f, "{}", if self.passed() { "no problems found" } else { "problems found" })
+    }
+}
+
+fn fail(message: impl Into<String>, suggestion: &str) -> CheckOutcome {
+    CheckOutcome::Fail(message.into(), suggestion.to_string())
+}
+
+fn skipped(reason: &str) -> CheckOutcome {
+    CheckOutcome::Warn(format!("skipped, {reason}"))
+}
+
+impl RpcHandler {
+    /// Check the handler's setup end to end and report what looks wrong, with suggestions.
+    pub async fn doctor(&self) -> DoctorReport {
+        let rpcs = self.rpcs();
+        let target = self.doctor_target().await;
+
+        let mut checks = vec![
+            CheckResult { check: DoctorCheck::EmbeddedData, outcome: self.check_embedded_data(&rpcs) },
+            CheckResult { check: DoctorCheck::RpcCount, outcome: self.check_rpc_count(&rpcs) },
+            CheckResult { check: DoctorCheck::Dns, outcome: self.check_dns(&rpcs).await },
+        ];
+        let (live_probe, chain_id, server_date) = match &target {
+            Some(url) => {
+                let live_probe = self.check_live_probe(url).await;
+                let (chain_id, server_date) = self.check_chain_id(url).await;
+                (live_probe, chain_id, server_date)
+            }
+            None if rpcs.is_empty() => (skipped("no endpoints"), skipped("no endpoints"), None),
+            None => (skipped("every endpoint is cooling down or skipped"), skipped("no eligible endpoint"), None),
+        };
+        checks.push(CheckResult { check: DoctorCheck::LiveProbe, outcome: live_probe });
+        checks.push(CheckResult { check: DoctorCheck::ChainId, outcome: chain_id });
+        checks.push(CheckResult { check: DoctorCheck::Clock, outcome: self.check_clock(server_date) });
+
+        // Messages quote endpoint URLs, which may carry templated secrets
+        for result in &mut checks {
+            match &mut result.outcome {
+                CheckOutcome::Warn(message) | CheckOutcome::Fail(message, _) => *message = self.redact(message),
+                CheckOutcome::Pass => {}
+            }
+        }
+        DoctorReport { network_id: self.network_id, target: target.map(|url| self.redact(&url)), checks }
+    }
+
+    /// The active provider, else the fastest measured endpoint, else the first endpoint probes
+    /// would reach; never one that is cooling down.
+    async fn doctor_target(&self) -> Option<String> {
+        let now = self.clock().now_instant();
+        let cooling_down: Vec<String> = self
+            .cooldowns()
+            .read()
+            .await
+            .iter()
+            .filter(|(_, cooldown)| cooldown.until > now)
+            .map(|(url, _)| url.clone())
+            .collect();
+        let mut measured: Vec<(String, u64)> = self.get_latencies().await.into_iter().collect();
+        measured.sort_by_key(|(_, latency)| *latency);
+
+        self.get_provider_url()
+            .await
+            .ok()
+            .into_iter()
+            .chain(measured.into_iter().map(|(url, _)| url))
+            .chain(self.sweep_targets().into_iter().map(|rpc| rpc.url.to_string()))
+            .find(|url| !cooling_down.contains(url))
+    }
+
+    fn check_embedded_data(&self, rpcs: &[Rpc]) -> CheckOutcome {
+        if !cfg!(feature = "chainlist") {
+            return match rpcs.is_empty() {
+                true => fail("built without the `chainlist` feature and no RPCs are configured", "configure `rpcs`, or enable the `chainlist` feature"),
+                false => CheckOutcome::Pass,
+            };
+        }
+        if chainlist::get_chain_ids().is_empty() {
+            let message = "the chain registry is empty, so this build has no chainlist endpoints";
+            return if rpcs.is_empty() {
+                fail(message, "rebuild with network access, or call `chainlist::refresh_from_network` before creating the handler")
+            } else {
+                CheckOutcome::Warn(format!("{message}; only configured endpoints are used"))
+            };
+        }
+        match self.chain_data().chain_info(self.network_id) {
+            Some(_) => CheckOutcome::Pass,
+            None => CheckOutcome::Warn(format!("network {} is not in the chain registry; is `network_id` right?", self.network_id)),
+        }
+    }
+
+    fn check_rpc_count(&self, rpcs: &[Rpc]) -> CheckOutcome {
+        let known = self.rpc_source().rpcs(self.network_id).len();
+        match rpcs.len() {
+            0 if known > 0 => fail(
+                format!("all {known} known endpoints were removed by the `{:?}` tracking filter", self.config().tracking),
+                "allow more tracking, or add endpoints to `network_rpcs`",
+            ),
+            0 => fail(
+                format!("no endpoints for network {}", self.network_id),
+                "check `network_id`, or add endpoints to `network_rpcs`",
+            ),
+            1 => CheckOutcome::Warn("a single endpoint leaves nothing to fail over to".to_string()),
+            _ => CheckOutcome::Pass,
+        }
+    }
+
+    async fn check_dns(&self, rpcs: &[Rpc]) -> CheckOutcome {
+        let mut hosts: Vec<String> = Vec::new();
+        for host in rpcs.iter().filter_map(|rpc| host_of(rpc.url.as_str())) {
+            let host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+            if host.parse::<IpAddr>().is_err() && !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        if hosts.is_empty() {
+            return if rpcs.is_empty() { skipped("no endpoints") } else { CheckOutcome::Pass };
+        }
+        hosts.truncate(DNS_SAMPLE);
+
+        let resolver = self.host_resolver();
+        let lookups = hosts.iter().map(|host| async move {
+            match runtime::timeout(DNS_TIMEOUT, resolver.lookup(host)).await {
+                Ok(Ok(ips)) if !ips.is_empty() => None,
+                _ => Some(host.clone()),
+            }
+        });
+        let unresolved: Vec<String> = join_all(lookups).await.into_iter().flatten().collect();
+        match unresolved.len() {
+            0 => CheckOutcome::Pass,
+            n if n == hosts.len() => fail(
+                format!("none of the sampled hosts resolved: {}", unresolved.join(", ")),
+                "check DNS settings and that outbound lookups aren't blocked",
+            ),
+            _ => CheckOutcome::Warn(format!("{} of {} sampled hosts did not resolve: {}", unresolved.len(), hosts.len(), unresolved.join(", "))),
+        }
+    }
+
+    async fn check_live_probe(&self, url: &str) -> CheckOutcome {
+        let Ok(rpc_url) = url.parse() else { return fail(format!("{url} is not a valid URL"), "fix the endpoint URL") };
+        let rpc = Rpc { url: rpc_url, tracking: None, tracking_details: None, is_open_source: None };
+        let options = self.measure_options(TimeoutPolicy::Fixed(self.config().settings.rpc_timeout));
+        let client = match self.http_client() {
+            Ok(client) => client,
+            Err(e) => return fail(e.to_string(), "check the TLS and proxy setup of this machine"),
+        };
+        let result = match measure_rpcs_with_options(&client, &[rpc], &options).await {
+            Ok((_, results)) => results.into_iter().next(),
+            Err(e) => return fail(e.to_string(), "check outbound HTTPS access"),
+        };
+
+        match result {
+            Some(result) if result.success => CheckOutcome::Pass,
+            Some(result) if result.non_json_rpc.is_some() => fail(
+                format!("{url} answered with something other than JSON-RPC"),
+                "the URL probably points at a web page; use the provider's RPC URL",
+            ),
+            Some(result) if result.block_number.is_some() && !result.bytecode_ok => fail(
+                format!("{url} answers but doesn't have the Permit2 contract deployed"),
+                "check `network_id`: endpoints of another chain fail this probe",
+            ),
+            _ => fail(
+                format!("{url} did not answer the probe within {}ms", self.config().settings.rpc_timeout.as_millis()),
+                "check that outbound HTTPS isn't firewalled, or raise `rpc_probe_timeout_ms`",
+            ),
+        }
+    }
+
+    /// The chain id check, and the endpoint's `Date` header for the clock check.
+    async fn check_chain_id(&self, url: &str) -> (CheckOutcome, Option<SystemTime>) {
+        let request = JsonRpcRequest::new("eth_chainId", json!([]));
+        let timeout = self.config().settings.rpc_timeout;
+        let send = async {
+            let client = self.http_client()?;
+            self.spend_meter().charge(url, &request.method)?;
+            let _slot = self.host_limiter().acquire_as(url, TrafficClass::Probe).await;
+            let response = post_json_rpc(&client, url, &request, self.config().settings.follow_post_redirects, self.endpoint_headers().get(url)).await?;
+            let date = response
+                .headers()
+                .get(header::DATE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+                .map(SystemTime::from);
+            if !response.status().is_success() {
+                return Err(RpcHandlerError::HttpStatus { url: url.to_string(), status: response.status().as_u16() });
+            }
+            let body: JsonRpcResponse<Value> = response.json().await.map_err(|e| RpcHandlerError::from_reqwest(e, url))?;
+            Ok((body.into_result()?, date))
+        };
+        let (result, date) = match runtime::timeout(timeout, send).await {
+            Ok(Ok(answer)) => answer,
+            Ok(Err(e)) => return (fail(format!("eth_chainId failed: {e}"), "check that the endpoint is reachable and serves JSON-RPC"), None),
+            Err(_) => return (fail(format!("eth_chainId timed out after {}ms", timeout.as_millis()), "check that outbound HTTPS isn't firewalled"), None),
+        };
+
+        let reported = parse_quantity(&result);
+        let outcome = match reported {
+            Some(id) if id == self.network_id => CheckOutcome::Pass,
+            Some(id) => fail(
+                format!("{url} serves chain {id}, not {}", self.network_id),
+                "check `network_id`, or remove the endpoint from `network_rpcs`",
+            ),
+            None => fail(format!("{url} answered eth_chainId with {result}"), "the endpoint is not an Ethereum JSON-RPC node"),
+        };
+        (outcome, date)
+    }
+
+    fn check_clock(&self, server_date: Option<SystemTime>) -> CheckOutcome {
+        let now = self.clock().now_system();
+        if now < UNIX_EPOCH + Duration::from_secs(EARLIEST_PLAUSIBLE_UNIX_SECS) {
+            return fail("the system clock is before 2024", "set the system clock; cooldowns and maintenance windows depend on it");
+        }
+        let Some(server_date) = server_date else { return CheckOutcome::Pass };
+        let skew = now.duration_since(server_date).unwrap_or_else(|e| e.duration());
+        if skew > MAX_CLOCK_SKEW {
+            CheckOutcome::Warn(format!("the clock is {}s off the endpoint's", skew.as_secs()))
+        } else {
+            CheckOutcome::Pass
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! ENS name resolution, agreed on by a quorum of endpoints.
+//!
+//! Resolving a name takes two `eth_call`s: `resolver(node)` on the registry for the name's
+//! resolver, then `addr(node)` on that resolver. Each goes through consensus, so a single endpoint
+//! lying about either step can't redirect the answer. Reverse lookups do the same for
+//! `<address>.addr.reverse` and `name(node)`. Where there aren't two endpoints to ask, each step
+//! is a plain call instead and the resolution says so.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    calls::{ConsensusOptions, RpcCalls},
+    consensus::EndpointOutcome,
+    hex::{hex, unhex},
+    JsonRpcRequest, NetworkId, Result, RpcHandlerError,
+};
+
+/// The ENS registry on mainnet.
+pub const MAINNET_REGISTRY: &str = "0x00000000000c2e074ec69a0dfb2997ba6c7d2e1e";
+
+/// Options for `resolve_ens` and `lookup_address`.
+#[derive(Debug, Clone)]
+pub struct EnsOptions {
+    /// Share of responding endpoints that must agree on each step
+    pub quorum: f64,
+    pub consensus: Option<ConsensusOptions>,
+    /// ENS registries on networks other than mainnet; mainnet's is built in
+    pub registries: HashMap<NetworkId, String>,
+}
+
+impl Default for EnsOptions {
+    fn default() -> Self {
+        Self { quorum: 0.66, consensus: None, registries: HashMap::new() }
+    }
+}
+
+/// A name and the address it resolves to, either way round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnsResolution {
+    pub name: String,
+    /// `0x`-prefixed, lowercase
+    pub address: String,
+    /// The resolver the answer came from
+    pub resolver: String,
+    /// Endpoints that agreed on a step and contradicted none, in URL order; under consensus, an
+    /// endpoint not needed to reach quorum may not have been asked. Outside consensus, the one
+    /// endpoint asked
+    pub agreed_by: Vec<String>,
+    /// `false` when too few endpoints were available and each step was answered by one
+    pub consensus: bool,
+}
+
+/// The namehash of `name`, per EIP-137.
+///
+/// Names are only lowercased, not fully UTS-46 normalized: pass names normalized already if
+/// they contain anything beyond ASCII.
+pub fn namehash(name: &str) -> [u8; 32] {
+    let name = name.to_ascii_lowercase();
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return node;
+    }
+    for label in name.rsplit('.') {
+        let mut joined = [0u8; 64];
+        joined[..32].copy_from_slice(&node);
+        joined[32..].copy_from_slice(&Keccak256::digest(label.as_bytes()));
+        node = Keccak256::digest(joined).into();
+    }
+    node
+}
+
+/// Calldata calling `signature`, a function taking one `bytes32`, with `node`.
+pub fn encode_node_call(signature: &str, node: &[u8; 32]) -> String {
+    format!("0x{}{}", hex(&Keccak256::digest(signature.as_bytes())[..4]), hex(node))
+}
+
+/// The reverse record name of `address`, e.g. `d8da…6045.addr.reverse`.
+pub fn reverse_name(address: &str) -> String {
+    format!("{}.addr.reverse", address.trim_start_matches("0x").to_ascii_lowercase())
+}
+
+fn malformed(call: &str, result: &str) -> RpcHandlerError {
+    RpcHandlerError::SerializationError(format!("{call} returned {result}"))
+}
+
+/// The address in an ABI-encoded `address` return, `None` for the zero address.
+fn decode_address(call: &str, result: &str) -> Result<Option<String>> {
+    let bytes = unhex(result).filter(|bytes| bytes.len() >= 32).ok_or_else(|| malformed(call, result))?;
+    let address = &bytes[12..32];
+    Ok(address.iter().any(|byte| *byte != 0).then(|| format!("0x{}", hex(address))))
+}
+
+/// The string in an ABI-encoded `string` return, `None` when it is empty.
+fn decode_string(call: &str, result: &str) -> Result<Option<String>> {
+    let bytes = unhex(result).ok_or_else(|| malformed(call, result))?;
+    let word = |at: usize| -> Option<usize> {
+        let word = bytes.get(at..at.checked_add(32)?)?;
+        word[..24].iter().all(|byte| *byte == 0).then(|| u64::from_be_bytes(word[24..].try_into().unwrap()) as usize)
+    };
+    let decoded = (|| {
+        let offset = word(0)?;
+        let len = word(offset)?;
+        let start = offset.checked_add(32)?;
+        String::from_utf8(bytes.get(start..start.checked_add(len)?)?.to_vec()).ok()
+    })();
+    let name = decoded.ok_or_else(|| malformed(call, result))?;
+    Ok((!name.is_empty()).then_some(name))
+}
+
+
+/// What one step came to, and who stood behind it.
+struct Agreed {
+    result: String,
+    agreed_by: Vec<String>,
+    /// Answered, but with something else
+    dissented: Vec<String>,
+    by_consensus: bool,
+}
+
+impl RpcCalls {
+    /// Resolve `name` to an address.
+    ///
+    /// Fails with `EnsNotFound` when the name has no resolver or its resolver no address, and with
+    /// `EnsUnsupportedOnNetwork` off mainnet unless `options.registries` names a registry.
+    pub async fn resolve_ens(&self, name: &str, options: &EnsOptions) -> Result<EnsResolution> {
+        let node = namehash(name);
+        let (resolver, mut steps) = self.ens_resolver(name, &node, options).await?;
+
+        let call = "addr(bytes32)";
+        let agreed = self.ens_step(JsonRpcRequest::new("eth_call", json!([{ "to": resolver, "data": encode_node_call(call, &node) }, "latest"])), options).await?;
+        let address = decode_address(call, &agreed.result)?.ok_or_else(|| RpcHandlerError::EnsNotFound { name: name.to_string() })?;
+        steps.push(agreed);
+        Ok(resolution(name.to_string(), address, resolver, steps))
+    }
+
+    /// The name `address` has set as its primary name, from its reverse record.
+    ///
+    /// The reverse record is whatever the address's owner set: forward-resolve the name with
+    /// `resolve_ens` before treating it as belonging to `address`.
+    pub async fn lookup_address(&self, address: &str, options: &EnsOptions) -> Result<EnsResolution> {
+        let reverse = reverse_name(address);
+        let node = namehash(&reverse);
+        let (resolver, mut steps) = self.ens_resolver(&reverse, &node, options).await?;
+
+        let call = "name(bytes32)";
+        let agreed = self.ens_step(JsonRpcRequest::new("eth_call", json!([{ "to": resolver, "data": encode_node_call(call, &node) }, "latest"])), options).await?;
+        let name = decode_string(call, &agreed.result)?.ok_or(RpcHandlerError::EnsNotFound { name: reverse })?;
+        steps.push(agreed);
+        Ok(resolution(name, address.to_ascii_lowercase(), resolver, steps))
+    }
+
+    async fn ens_resolver(&self, name: &str, node: &[u8; 32], options: &EnsOptions) -> Result<(String, Vec<Agreed>)> {
+        let network_id = self.handler.network_id;
+        let registry = match network_id {
+            1 => options.registries.get(&1).map_or(MAINNET_REGISTRY, String::as_str),
+            _ => options.registries.get(&network_id).ok_or(RpcHandlerError::EnsUnsupportedOnNetwork { network_id })?,
+        };
+        let call = "resolver(bytes32)";
+        let agreed = self.ens_step(JsonRpcRequest::new("eth_call", json!([{ "to": registry, "data": encode_node_call(call, node) }, "latest"])), options).await?;
+        let resolver = decode_address(call, &agreed.result)?.ok_or_else(|| RpcHandlerError::EnsNotFound { name: name.to_string() })?;
+        Ok((resolver, vec![agreed]))
+    }
+
+    /// One `eth_call` by consensus, or by the active provider when fewer than two endpoints
+    /// could be asked.
+    async fn ens_step(&self, request: JsonRpcRequest, options: &EnsOptions) -> Result<Agreed> {
+        if self.fan_out_urls(&request.method, self.clock.now_instant()).len() < 2 {
+            let (response, url) = self.handler.try_proxy_request_attributed(request).await?;
+            let result = match response.into_result()? {
+                Value::String(result) => result,
+                other => return Err(malformed("eth_call", &other.to_string())),
+            };
+            return Ok(Agreed { result, agreed_by: vec![url], dissented: Vec::new(), by_consensus: false });
+        }
+
+        let (result, report) = self.consensus_with_report::<String>(&request, options.quorum, options.consensus.clone()).await;
+        let urls = |majority: bool| {
+            report.urls_where(|outcome| match outcome {
+                EndpointOutcome::Majority { .. } => majority,
+                EndpointOutcome::Minority { .. } => !majority,
+                _ => false,
+            })
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+        };
+        Ok(Agreed { result: result?, agreed_by: urls(true), dissented: urls(false), by_consensus: true })
+    }
+}
+
+fn resolution(name: String, address: String, resolver: String, steps: Vec<Agreed>) -> EnsResolution {
+    let consensus = steps.iter().all(|step| step.by_consensus);
+    let dissented: BTreeSet<&String> = steps.iter().flat_map(|step| &step.dissented).collect();
+    let agreed: BTreeSet<&String> = steps.iter().flat_map(|step| &step.agreed_by).collect();
+    let agreed_by = agreed.difference(&dissented).map(|url| url.to_string()).collect();
+    EnsResolution { name, address, resolver, agreed_by, consensus }
+}
@@ -1,3 +1,5 @@
+pub mod kb;
+
 #[derive(Debug, thiserror::Error)]
 pub enum RpcHandlerError {
     #[error("No available RPCs for network {network_id}")]
@@ -6,26 +8,277 @@ pub enum RpcHandlerError {
     #[error("JSON-RPC error from {0}")]
     JsonRpc(String),
 
+    #[error("JSON-RPC error {code}: {message}")]
+    JsonRpcCode { code: i64, message: String },
+
     #[error("Request timed out after {duration_ms}ms")]
     Timeout { duration_ms: u64 },
 
     #[error("All endpoints failed")]
     AllEndpointsFailed,
 
+    #[error("All routed endpoints for {method} failed: {urls:?}")]
+    RoutedEndpointsFailed { method: String, urls: Vec<String> },
+
+    #[error("Malformed response from {url}: {violation}")]
+    MalformedResponse { url: String, violation: String },
+
+    #[error("{url} is not a JSON-RPC endpoint (status {status}, content type {content_type:?})")]
+    NotAJsonRpcEndpoint { url: String, content_type: Option<String>, status: u16 },
+
+    /// Under strict `timestamp_sanity`, a block dated further ahead of the local clock than allowed
+    #[error("Block from {url} is dated {drift_ms}ms in the future")]
+    ClockSkew { url: String, drift_ms: u64 },
+
+    /// Under strict `staleness_policy`, chain data older than `max_age_ms`; `age_ms` is `None`
+    /// for data that was never fetched
+    #[error("Chain data is {} old, over the {max_age_ms}ms allowed; refresh it with chainlist::refresh_from_network", .age_ms.map_or("of unknown age (never fetched)".to_string(), |age| format!("{age}ms")))]
+    ChainDataStale { age_ms: Option<u64>, max_age_ms: u64 },
+
+    #[error("No provider within {max_head_lag} blocks of block {watermark}")]
+    NoSufficientlySyncedProvider { watermark: u64, max_head_lag: u64 },
+
+    /// Every endpoint asked for a guarded state read was more than `max_lag_blocks` behind
+    #[error("No endpoint within {max_lag_blocks} blocks of block {watermark} for a state read")]
+    StaleState { watermark: u64, max_lag_blocks: u64 },
+
+    /// No endpoint a consistency session could fail over to holds its block
+    #[error("No endpoint holds block {block} ({hash}) of the session")]
+    SnapshotUnavailable { block: u64, hash: String },
+
+    /// A held call's `max_wait` ran out; `attempts` lists each round's failure, first to last
+    #[error("No endpoint recovered within {held_ms}ms of holding ({} attempts): {last}", .attempts.len())]
+    HoldExpired { held_ms: u64, attempts: Vec<String>, last: Box<RpcHandlerError> },
+
+    /// A streamed call failed after the sink was given `items_emitted` items, so it wasn't retried
+    #[error("Stream from {url} failed after {items_emitted} items: {cause}")]
+    PartialStream { url: String, items_emitted: u64, cause: Box<RpcHandlerError> },
+
+    /// Under `ConsistencyCheck::VerifyCanonical`, `provider` kept serving data from block
+    /// `got_hash` at `height` where the canonical block is `expected_hash`, `None` when no
+    /// endpoint had a block there
+    #[error("Data from {provider} is from block {got_hash} at height {height}, not the canonical {}", .expected_hash.as_deref().unwrap_or("block (none found)"))]
+    NonCanonicalData { height: u64, expected_hash: Option<String>, got_hash: String, provider: String },
+
     #[error("Consensus failure: {most_common}")]
     ConsensusFailure { most_common: String },
 
+    /// Consecutive rounds of a temporal consensus read kept agreeing on different values; the
+    /// last two are named
+    #[error("Consensus changed between rounds: {previous} then {latest} ({rounds} rounds)")]
+    TemporalConsensus { previous: String, latest: String, rounds: usize },
+
+    /// A temporal consensus read was cancelled, or the handler shut down, while it waited to recheck
+    #[error("Temporal consensus cancelled after {rounds} rounds")]
+    TemporalConsensusCancelled { rounds: usize },
+
     #[error("Serialization error: {0}")]
     SerializationError(String),
 
-    #[error("Network error: {0}")]
-    Network(#[from] reqwest::Error),
+    #[error("Invalid raw transaction: {detail}")]
+    InvalidRawTransaction { detail: String },
+
+    #[error("Invalid proof: {detail}")]
+    InvalidProof { detail: String },
+
+    #[error("Invalid ABI encoding: {detail}")]
+    InvalidAbi { detail: String },
+
+    #[error("Invalid backfill: {detail}")]
+    InvalidBackfill { detail: String },
+
+    #[error("ENS isn't supported on network {network_id}: no registry is known for it")]
+    EnsUnsupportedOnNetwork { network_id: crate::NetworkId },
+
+    #[error("ENS name {name} is not set")]
+    EnsNotFound { name: String },
+
+    #[error("No value for placeholder {{{placeholder}}} in URL template {template}")]
+    UnresolvedPlaceholder { placeholder: String, template: String },
+
+    #[error("Invalid URL template {template}: {detail}")]
+    InvalidUrlTemplate { template: String, detail: String },
+
+    #[error("Invalid RPC config: {detail}")]
+    InvalidRpcConfig { detail: String },
+
+    /// An `ErrorMapping::message` that isn't a valid regular expression
+    #[error("Invalid error mapping pattern {pattern}: {detail}")]
+    InvalidErrorMapping { pattern: String, detail: String },
+
+    /// Something the target can't do, e.g. pinning resolved IPs from a browser, which resolves
+    /// hostnames itself
+    #[error("{feature} isn't supported on this target")]
+    Unsupported { feature: String },
+
+    /// Tags beyond `tags::MAX_CALL_TAGS` or `tags::MAX_TAG_LEN`
+    #[error("Invalid call tags: {detail}")]
+    InvalidCallTags { detail: String },
+
+    /// A user-supplied callback panicked; see `panics`
+    #[error("{component} panicked: {message}")]
+    CallbackPanicked { component: String, message: String },
+
+    /// `Strategy::StaticOrder` lists a URL that isn't one of the handler's endpoints
+    #[error("Static order names {url}, which is not one of the handler's endpoints")]
+    UnknownStaticOrderUrl { url: String },
+
+    #[error("Data scope {retained:?} leaves out the handler's network {network_id}")]
+    InvalidDataScope { network_id: crate::NetworkId, retained: Vec<crate::NetworkId> },
+
+    /// An endpoint merged in through `HandlerSettings::chain_aliases` or the registry's alias
+    /// hints reported a chain id outside the alias group, so the aliased ids aren't one chain
+    #[error("{url} serves chain {reported}, outside the alias group {accepted:?}; the aliased ids are different chains")]
+    ChainAliasMismatch { url: String, reported: crate::NetworkId, accepted: Vec<crate::NetworkId> },
 
-    #[error("Timeout error: {0}")]
-    TimeoutError(#[from] tokio::time::error::Elapsed),
+    /// A consensus read was asked for a method that may have side effects, without
+    /// `ConsensusOptions::allow_side_effects`
+    #[error("Refusing to send {method} for consensus: {}", side_effects_hint(*.registered))]
+    NonIdempotentMethodInConsensus { method: String, registered: bool },
+
+    /// `HandlerConfig::localnet` found no node answering at any of the addresses it tried
+    #[error("No local node answered at {}", .tried.join(", "))]
+    NoLocalNodeFound { tried: Vec<String> },
+
+    /// `apply_config` was given changes a running handler can't take on
+    #[error("Config changes to {} need a new handler", .fields.join(", "))]
+    ConfigNotReloadable { fields: Vec<String> },
+
+    /// Sending to a metered endpoint would take it past a daily spend budget
+    #[error("Daily {scope} spend budget exhausted, not sending to {url}")]
+    BudgetExhausted { url: String, scope: crate::spend::BudgetScope },
+
+    #[error("DNS resolution failed for {url}")]
+    DnsFailure { url: String },
+
+    #[error("Could not connect to {url}")]
+    ConnectFailure { url: String },
+
+    #[error("TLS handshake with {url} failed")]
+    TlsFailure { url: String },
+
+    /// The total budget for a request ran out, connection included
+    #[error("Request to {url} timed out after {configured_ms}ms")]
+    RequestTimeout { url: String, configured_ms: u64 },
+
+    /// No connection within `ProxySettings::connect_timeout_ms`
+    #[error("Connecting to {url} timed out")]
+    ConnectTimeout { url: String },
+
+    #[error("Could not decode response body from {url}: {detail}")]
+    BodyDecode { url: String, detail: String },
+
+    #[error("HTTP {status} from {url}")]
+    HttpStatus { url: String, status: u16 },
+
+    /// Under `Egress::InjectedOnly`, a request was for a URL that isn't one of the handler's
+    /// injected or runtime-added endpoints, so it wasn't sent
+    #[error("Not sending to {url}: egress is limited to the injected endpoints")]
+    EgressDenied { url: String },
+
+    /// An endpoint answered the health probes but failed one of their checks
+    #[error("Probe of {url} failed: {reason}")]
+    ProbeFailed { url: String, reason: String },
+
+    /// Transport errors that don't fit a more specific variant
+    #[error("Network error: {0}")]
+    Network(reqwest::Error),
 
     #[error("Chain info not found for network {network_id}")]
     ChainInfoNotFound { network_id: crate::NetworkId },
+
+    #[cfg(feature = "chainlist")]
+    #[error("Chain registry error: {0}")]
+    ChainRegistry(#[from] crate::chainlist::source::SourceError),
+}
+
+/// Whether `err` failed connecting. A browser's fetch doesn't tell, so on `wasm32` every
+/// failure is left a plain `Network` error.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_connect(err: &reqwest::Error) -> bool {
+    err.is_connect()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn is_connect(_err: &reqwest::Error) -> bool {
+    false
+}
+
+impl RpcHandlerError {
+    /// Classify a `reqwest` failure for a request to `url`.
+    ///
+    /// hyper doesn't expose its connect error types, so DNS and TCP failures are told apart by
+    /// the stage hyper names in the error's source chain. A connect failure past both stages
+    /// is the TLS handshake.
+    pub fn from_reqwest(err: reqwest::Error, url: &str) -> Self {
+        let url = url.to_string();
+        if err.is_decode() {
+            return RpcHandlerError::BodyDecode { url, detail: err.to_string() };
+        }
+        if !is_connect(&err) {
+            return RpcHandlerError::Network(err);
+        }
+        if err.is_timeout() {
+            return RpcHandlerError::ConnectTimeout { url };
+        }
+
+        let mut source = std::error::Error::source(&err);
+        while let Some(cause) = source {
+            match cause.to_string().as_str() {
+                "dns error" => return RpcHandlerError::DnsFailure { url },
+                "tcp connect error" => return RpcHandlerError::ConnectFailure { url },
+                _ => source = cause.source(),
+            }
+        }
+        RpcHandlerError::TlsFailure { url }
+    }
+
+    /// A request to `url` that exceeded this crate's own `configured` timeout.
+    pub fn request_timeout(url: &str, configured: std::time::Duration) -> Self {
+        RpcHandlerError::RequestTimeout { url: url.to_string(), configured_ms: configured.as_millis() as u64 }
+    }
+
+    /// Failed before a response arrived: DNS, connect, TLS or timeout.
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            RpcHandlerError::DnsFailure { .. }
+                | RpcHandlerError::ConnectFailure { .. }
+                | RpcHandlerError::TlsFailure { .. }
+                | RpcHandlerError::RequestTimeout { .. }
+                | RpcHandlerError::ConnectTimeout { .. }
+                | RpcHandlerError::Network(_)
+        )
+    }
+
+    /// Which budget ran out, for timeouts.
+    pub fn timeout_phase(&self) -> Option<TimeoutPhase> {
+        match self {
+            RpcHandlerError::ConnectTimeout { .. } => Some(TimeoutPhase::ConnectPhase),
+            RpcHandlerError::RequestTimeout { .. } => Some(TimeoutPhase::TotalBudget),
+            _ => None,
+        }
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(self, RpcHandlerError::HttpStatus { status: 429, .. })
+    }
+}
+
+fn side_effects_hint(registered: bool) -> &'static str {
+    match registered {
+        true => "it has side effects; set ConsensusOptions::allow_side_effects to broadcast it instead",
+        false => "it isn't registered, so may have side effects; list it in HandlerSettings::idempotent_methods if it has none, or set ConsensusOptions::allow_side_effects to broadcast it",
+    }
+}
+
+/// The phase of a request a timeout cut short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Never connected: the endpoint is likely down or unreachable from here
+    ConnectPhase,
+    /// Connected, but the answer didn't arrive in time: possibly just a heavy call
+    TotalBudget,
 }
 
 pub type Result<T> = std::result::Result<T, RpcHandlerError>;
\ No newline at end of file
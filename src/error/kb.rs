@@ -0,0 +1,158 @@
+//! What JSON-RPC errors mean, whichever provider sent them.
+//!
+//! Providers report the same condition under different codes and messages: a block the node
+//! doesn't have is `-32000 header not found` from geth, `-32001 Resource not found` elsewhere, and
+//! `missing trie node` once its state is pruned. `classify_jsonrpc_error` maps an error to one
+//! `ErrorCondition` with a table of `ErrorMapping`s embedded from `kb.json`, and every decision
+//! that depends on an error's meaning, such as retrying it or learning a method is unsupported,
+//! goes through it. `HandlerSettings::error_mappings` are consulted first, so a provider's quirks
+//! can be taught without a release.
+//!
+//! Messages are matched with regular expressions found anywhere in the message, such as
+//! `header not found|unknown block`, compiled once when the table loads. Hostnames are matched
+//! with globs, where `*` stands for any run of characters. Both ignore case.
+
+use std::sync::LazyLock;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{JsonRpcError, Result, RpcHandlerError};
+
+/// What a JSON-RPC error says went wrong, independent of how the provider phrased it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCondition {
+    /// The block or state asked about isn't on this node, e.g. pruned or not yet imported
+    StateUnavailable,
+    RateLimited,
+    /// The node doesn't implement the method, or has it disabled
+    MethodNotFound,
+    /// The call executed and reverted
+    Reverted,
+    /// The node is still catching up with the chain
+    NodeSyncing,
+    /// The request is malformed or asks for more than the node will answer
+    InvalidParams,
+    /// The node failed in a way that says nothing about the request
+    Internal,
+    /// None of the above, e.g. a rejected transaction
+    Unclassified,
+}
+
+impl ErrorCondition {
+    /// Whether another endpoint, or the same one a moment later, may answer differently.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, Self::StateUnavailable | Self::RateLimited | Self::NodeSyncing | Self::Internal)
+    }
+}
+
+/// One row of the table: an error matching every pattern given stands for `condition`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorMapping {
+    /// Codes the row applies to, any when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codes: Vec<i64>,
+    /// Pattern searched for in the error message, any message when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<MessagePattern>,
+    /// Glob over the hostname of the endpoint that answered, any endpoint when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    pub condition: ErrorCondition,
+}
+
+impl ErrorMapping {
+    pub fn matches(&self, error: &JsonRpcError, host: &str) -> bool {
+        (self.codes.is_empty() || self.codes.contains(&error.code))
+            && self.message.as_ref().is_none_or(|pattern| pattern.is_match(&error.message))
+            && self.host.as_deref().is_none_or(|pattern| glob_matches(pattern, host))
+    }
+}
+
+/// A regular expression over error messages, compiled once and matched ignoring case.
+#[derive(Debug, Clone)]
+pub struct MessagePattern(Regex);
+
+impl MessagePattern {
+    /// Fails with `InvalidErrorMapping` if `pattern` isn't a valid regular expression.
+    pub fn new(pattern: &str) -> Result<Self> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map(Self)
+            .map_err(|err| RpcHandlerError::InvalidErrorMapping { pattern: pattern.to_string(), detail: err.to_string() })
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Whether the pattern occurs anywhere in `message`.
+    pub fn is_match(&self, message: &str) -> bool {
+        self.0.is_match(message)
+    }
+}
+
+impl PartialEq for MessagePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for MessagePattern {}
+
+impl Serialize for MessagePattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for MessagePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+static BUILT_IN: LazyLock<Vec<ErrorMapping>> =
+    LazyLock::new(|| serde_json::from_str(include_str!("kb.json")).expect("the embedded error table is valid"));
+
+/// The embedded table, in the order it is consulted: provider-specific rows, then messages, then
+/// bare codes.
+pub fn built_in() -> &'static [ErrorMapping] {
+    &BUILT_IN
+}
+
+/// The condition `error` from the endpoint at `url` stands for under the embedded table.
+pub fn classify_jsonrpc_error(error: &JsonRpcError, url: &str) -> ErrorCondition {
+    classify_with(&[], error, url)
+}
+
+/// Like `classify_jsonrpc_error`, with `mappings` consulted before the embedded table.
+pub fn classify_with(mappings: &[ErrorMapping], error: &JsonRpcError, url: &str) -> ErrorCondition {
+    let host = url::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string)).unwrap_or_default();
+    mappings
+        .iter()
+        .chain(built_in())
+        .find(|mapping| mapping.matches(error, &host))
+        .map_or(ErrorCondition::Unclassified, |mapping| mapping.condition)
+}
+
+/// Whether `text` matches `pattern` as a whole, ignoring case, `*` matching any run of characters.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.to_lowercase(), text.to_lowercase());
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else { return false };
+    let mut parts: Vec<&str> = parts.collect();
+    // Without a `*` the pattern is the whole text
+    let Some(last) = parts.pop() else { return rest.is_empty() };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
@@ -0,0 +1,168 @@
+//! Lifecycle notifications from the handler.
+//!
+//! Subscribe with `RpcHandler::subscribe`. Events are broadcast: a receiver that falls more
+//! than `EVENT_CAPACITY` events behind skips the oldest ones, and nothing is buffered for
+//! subscribers that don't exist yet. The handler keeps the last `EVENT_HISTORY_LEN` itself,
+//! subscribers or not, for `RpcHandler::recent_events`.
+
+use std::{collections::VecDeque, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{tags::CallTags, FailureClass};
+
+/// Events buffered per subscriber before the oldest are dropped.
+pub const EVENT_CAPACITY: usize = 64;
+
+/// Events the handler keeps for `RpcHandler::recent_events`.
+pub const EVENT_HISTORY_LEN: usize = 128;
+
+/// How far `RpcHandler::init` has got in choosing a provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InitState {
+    /// No provider has been chosen yet
+    Starting,
+    /// Serving through the first endpoint that passed a probe while the full sweep runs,
+    /// only reached under `Strategy::FastStart`
+    Provisional { url: String },
+    /// Serving through the provider the strategy settled on
+    Final { url: String },
+    /// No endpoint passed a probe within `InitPolicy::max_attempts`, so `url`, the first in
+    /// tier and listing order, serves unprobed until a refresh finds a healthy one
+    Degraded { url: String },
+}
+
+impl InitState {
+    /// The URL being served through, `None` while starting.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            InitState::Starting => None,
+            InitState::Provisional { url } | InitState::Final { url } | InitState::Degraded { url } => Some(url),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandlerEvent {
+    InitStateChanged(InitState),
+    /// `init` gave up on probing after `attempts` selections and serves through `url` unprobed
+    Degraded { url: String, attempts: u32 },
+    /// A refresh replaced the unprobed provider with one that passed its probe
+    Recovered { from: String, to: String },
+    /// The sweep behind a provisional provider found one faster by more than the margin
+    ProviderUpgraded {
+        from: String,
+        to: String,
+        /// The provisional provider's sweep latency, `None` if it failed the sweep
+        from_latency_ms: Option<u64>,
+        to_latency_ms: u64,
+    },
+    /// The active provider was replaced ahead of a scheduled maintenance window on its endpoint
+    MaintenanceFailover { from: String, to: String },
+    /// An endpoint exceeded `HandlerSettings::latency_slo` and went behind the others for the penalty
+    LatencySloBreached {
+        url: String,
+        /// Violations counted within the window
+        violations: usize,
+        /// The endpoint that took over, if `url` was the active provider and one was available
+        replacement: Option<String>,
+    },
+    /// A daily spend budget ran out: the endpoint's own, or `HandlerSettings::daily_spend_budget`
+    /// when `url` is `None`. Metered endpoints it covers are left out until the UTC day rolls over.
+    BudgetExhausted {
+        url: Option<String>,
+        spent: u64,
+        budget: u64,
+        /// The endpoint that took over, if the active provider ran out and one was available
+        replacement: Option<String>,
+    },
+    /// A call found no endpoint answering and is holding under `CallOptions::hold_on_total_failure`
+    RequestHeld {
+        method: String,
+        /// Calls holding now, this one included
+        holding: usize,
+        /// The call's caller tags
+        #[serde(default)]
+        tags: CallTags,
+    },
+    /// A held call stopped holding
+    HoldReleased {
+        method: String,
+        /// Calls still holding
+        holding: usize,
+        /// An endpoint answered; `false` if the hold expired or the call was dropped
+        recovered: bool,
+        #[serde(default)]
+        tags: CallTags,
+    },
+    /// A call with caller tags failed for good, after any hold; untagged calls are left to the
+    /// metrics and the failure journal
+    RequestFailed {
+        method: String,
+        class: FailureClass,
+        /// The error, redacted
+        error: String,
+        tags: CallTags,
+    },
+    /// A user-supplied callback panicked and was contained, see `panics`
+    CallbackPanicked {
+        component: String,
+        /// The panic message, redacted
+        message: String,
+    },
+    /// A managed log filter moved to another endpoint and read the blocks in between
+    FilterReinstalled {
+        from: String,
+        to: String,
+        /// Logs the backfill delivered that hadn't been delivered already
+        backfilled: usize,
+    },
+    /// Agreement sampling found an endpoint returning block hashes its peers don't, in more of
+    /// its recent samples than `max_disagreement_rate` allows; consensus no longer counts its vote
+    SuspectedDishonest {
+        url: String,
+        /// Recent samples the rate was taken over
+        samples: usize,
+        /// Of those, the ones that matched the majority
+        agreements: usize,
+    },
+    /// Templated endpoints moved to the URLs their rotated secrets fill in, redacted
+    SecretRotated { urls: Vec<String> },
+    /// `location_changed` found the client on another network; keys are hashed fingerprints
+    LocationChanged {
+        from: Option<String>,
+        to: Option<String>,
+        /// The new location's latency snapshot was restored instead of probing
+        restored: bool,
+    },
+    /// The chain data was older than `HandlerSettings::staleness_policy` allows when the handler
+    /// was built; `age_ms` is `None` for data that was never fetched
+    ChainDataStale { age_ms: Option<u64>, max_age_ms: u64 },
+}
+
+/// An event as the handler emitted it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub at: SystemTime,
+    pub event: HandlerEvent,
+}
+
+/// The last `EVENT_HISTORY_LEN` events, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct EventHistory {
+    events: parking_lot::Mutex<VecDeque<RecordedEvent>>,
+}
+
+impl EventHistory {
+    pub(crate) fn record(&self, at: SystemTime, event: HandlerEvent) {
+        let mut events = self.events.lock();
+        if events.len() == EVENT_HISTORY_LEN {
+            events.pop_front();
+        }
+        events.push_back(RecordedEvent { at, event });
+    }
+
+    pub(crate) fn events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().iter().cloned().collect()
+    }
+}
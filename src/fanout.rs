@@ -0,0 +1,455 @@
+//! Race-with-failover and quorum reads over any JSON-RPC endpoints.
+//!
+//! These are the engines `RpcHandler` sends requests and consensus reads with, minus everything
+//! EVM: no chainlist, network id, probing or Permit2 check. Endpoints are plain URLs reached
+//! through a `TransportFactory`, so the methods can be anything the endpoints serve.
+//!
+//! ```no_run
+//! # async fn run() -> ez_web3_rpc::Result<()> {
+//! use std::{sync::Arc, time::Duration};
+//! use ez_web3_rpc::{fanout, Endpoint, HttpTransportFactory, JsonRpcRequest, QuorumConfig, RaceConfig};
+//!
+//! let endpoints = vec![Endpoint::new("https://indexer-a.example"), Endpoint::new("https://indexer-b.example")];
+//! let transport = Arc::new(HttpTransportFactory::new(Duration::from_secs(5)));
+//! let request = JsonRpcRequest::new("indexer_status", serde_json::json!([]));
+//!
+//! let (status, attribution) = fanout::race(&endpoints, &request, &RaceConfig::new(transport.clone())).await?;
+//! println!("{} answered {status}", attribution.url);
+//!
+//! let outcome = fanout::quorum(&endpoints, &request, &QuorumConfig::new(transport, 0.66)).await?;
+//! println!("agreed: {:?}", outcome.value);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::Instrument;
+
+use crate::{
+    clock::{system_clock, Clock},
+    comparator::{ResultComparator, StableStringComparator},
+    panics,
+    provider::plan::BATCH_SIZE,
+    runtime::{self, AbortHandle, JoinError},
+    transport::TransportFactory,
+    JsonRpcError, JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError,
+};
+
+/// An endpoint to fan out to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub url: String,
+    /// Raced earlier the higher it is, and counted this many times in a quorum
+    pub weight: u32,
+    /// Kept with the endpoint for the caller; the engines don't read it
+    pub metadata: Value,
+}
+
+impl Endpoint {
+    /// An endpoint of weight 1 without metadata.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into(), weight: 1, metadata: Value::Null }
+    }
+}
+
+/// How `race` goes through the endpoints.
+#[derive(Clone)]
+pub struct RaceConfig {
+    pub transport: Arc<dyn TransportFactory>,
+    /// Endpoints sent the request at once; the first of them in order to answer wins
+    pub batch_size: usize,
+    /// Passes over every batch before giving up
+    pub rounds: usize,
+    /// Wait after each failed batch
+    pub retry_delay: Duration,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl fmt::Debug for RaceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RaceConfig")
+            .field("batch_size", &self.batch_size)
+            .field("rounds", &self.rounds)
+            .field("retry_delay", &self.retry_delay)
+            .finish()
+    }
+}
+
+impl RaceConfig {
+    /// One pass in batches of the handler's size, with no delay between them.
+    pub fn new(transport: Arc<dyn TransportFactory>) -> Self {
+        Self { transport, batch_size: BATCH_SIZE, rounds: 1, retry_delay: Duration::ZERO, clock: system_clock() }
+    }
+}
+
+/// Which endpoint answered a `race`, and what failed before it did.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Attribution {
+    pub url: String,
+    /// Pass over the batches it answered in, from 0
+    pub round: usize,
+    /// Batch it answered in, from 0
+    pub batch: usize,
+    pub failures: Vec<FailedAttempt>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailedAttempt {
+    pub url: String,
+    pub error: String,
+}
+
+/// Send `request` to `endpoints` in batches, heaviest first, returning the first result.
+///
+/// Every endpoint in a batch is asked at once and the first in order that answers wins; a batch
+/// that fails moves the race on to the next one. A JSON-RPC error that no endpoint would answer
+/// differently, such as invalid params, ends the race. A lone endpoint's last error is returned
+/// as-is, otherwise `AllEndpointsFailed`.
+pub async fn race(endpoints: &[Endpoint], request: &JsonRpcRequest, config: &RaceConfig) -> Result<(Value, Attribution)> {
+    let urls = by_weight(endpoints)?;
+    let batches: Vec<&[String]> = urls.chunks(config.batch_size.max(1)).collect();
+    let mut failures = Vec::new();
+    let mut last_error = RpcHandlerError::AllEndpointsFailed;
+    for position in positions(batches.len(), config.rounds) {
+        let batch = batches[position.batch];
+        let attempts = batch.iter().map(|url| async move {
+            let attempt = async { config.transport.transport(url).request(request).await.and_then(JsonRpcResponse::into_result) };
+            panics::contain_future(panics::FAN_OUT_TASK, attempt).await.and_then(|result| result)
+        });
+        let results = futures::future::join_all(attempts).await;
+        let mut decisive = None;
+        let settled = settle(
+            results,
+            |i, result| match result {
+                Ok(value) => Verdict::Won(value),
+                Err(e) => {
+                    failures.push(FailedAttempt { url: batch[i].clone(), error: e.to_string() });
+                    match is_decisive(&e) {
+                        true => {
+                            decisive.get_or_insert(e);
+                            Verdict::Lost(None)
+                        }
+                        false => Verdict::Lost(Some(e)),
+                    }
+                }
+            },
+            |_, _| {},
+        );
+        match settled {
+            Ok((i, value)) => {
+                let attribution = Attribution { url: batch[i].clone(), round: position.round, batch: position.batch, failures };
+                return Ok((value, attribution));
+            }
+            Err(e) => {
+                if let Some(decisive) = decisive {
+                    return Err(decisive);
+                }
+                last_error = e;
+                if !position.last {
+                    config.clock.sleep(config.retry_delay).await;
+                }
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// A JSON-RPC error another endpoint would answer the same way.
+fn is_decisive(error: &RpcHandlerError) -> bool {
+    match error {
+        RpcHandlerError::JsonRpcCode { code, message } => !JsonRpcError { code: *code, message: message.clone(), data: None }.is_retryable(),
+        _ => false,
+    }
+}
+
+/// How `quorum` asks the endpoints and decides.
+#[derive(Clone)]
+pub struct QuorumConfig {
+    pub transport: Arc<dyn TransportFactory>,
+    /// Share of the weight that answered the winning answer needs, e.g. `0.66`
+    pub threshold: f64,
+    /// Requests in flight at once
+    pub concurrency: usize,
+    /// Which answers agree, and the value a group of them settles on
+    pub comparator: Arc<dyn ResultComparator>,
+    /// Stop asking once an answer holds a quorum of every endpoint's weight, since no later
+    /// answers could outvote it
+    pub stop_at_quorum: bool,
+}
+
+impl fmt::Debug for QuorumConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuorumConfig")
+            .field("threshold", &self.threshold)
+            .field("concurrency", &self.concurrency)
+            .field("comparator", &self.comparator)
+            .field("stop_at_quorum", &self.stop_at_quorum)
+            .finish()
+    }
+}
+
+impl QuorumConfig {
+    /// Every endpoint asked at once, exact comparison, stopping once a quorum is certain.
+    pub fn new(transport: Arc<dyn TransportFactory>, threshold: f64) -> Self {
+        Self { transport, threshold, concurrency: usize::MAX, comparator: Arc::new(StableStringComparator), stop_at_quorum: true }
+    }
+}
+
+/// What a `quorum` read decided.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuorumOutcome {
+    /// The agreed answer, `None` when no answer reached the quorum
+    pub value: Option<Value>,
+    /// Comparator key with the most votes, `None` when nothing answered
+    pub most_common: Option<String>,
+    /// Votes the most common key needed
+    pub quorum: usize,
+    /// Weight that answered, per comparator key
+    pub votes: BTreeMap<String, usize>,
+    /// The key each endpoint that answered voted for
+    pub voters: BTreeMap<String, String>,
+    pub failures: Vec<FailedAttempt>,
+}
+
+/// Send `request` to `endpoints` and settle on the answer at least `config.threshold` of the
+/// answering weight agrees on.
+///
+/// Endpoints that fail don't vote, and neither does one whose transport or answer makes a
+/// callback panic; both are listed in `failures`. Not reaching a quorum isn't an error: the
+/// outcome has no `value`, and its `votes` show how the answers split.
+pub async fn quorum(endpoints: &[Endpoint], request: &JsonRpcRequest, config: &QuorumConfig) -> Result<QuorumOutcome> {
+    let urls = by_weight(endpoints)?;
+    let weights: HashMap<&str, usize> = endpoints.iter().map(|endpoint| (endpoint.url.as_str(), endpoint.weight as usize)).collect();
+    let early_quorum = quorum_of(weights.values().sum(), config.threshold).max(1);
+    let mut tally = Tally::new(Arc::clone(&config.comparator));
+    let mut failures = Vec::new();
+    let start = |url: &str| {
+        let transport = config.transport.transport(url);
+        let request = request.clone();
+        async move { transport.request(&request).await.and_then(JsonRpcResponse::into_result) }
+    };
+    fan_out(&urls, config.concurrency, start, |url, joined| {
+        let result = match joined {
+            Ok(result) => result,
+            Err(join) => match panics::join_failure(panics::FAN_OUT_TASK, join) {
+                Some(panicked) => Err(panicked),
+                None => return true,
+            },
+        };
+        let weight = weights[url.as_str()];
+        match result.and_then(|value| tally.vote(url.clone(), value, weight)) {
+            Ok(key) => !(config.stop_at_quorum && tally.votes(&key) >= early_quorum),
+            Err(e) => {
+                failures.push(FailedAttempt { url, error: e.to_string() });
+                true
+            }
+        }
+    })
+    .await;
+
+    let quorum = quorum_of(tally.weight(), config.threshold);
+    let most_common = tally.most_common();
+    let value = match most_common.as_ref().filter(|key| tally.votes(key) >= quorum) {
+        Some(key) => tally.value(key)?,
+        None => None,
+    };
+    Ok(QuorumOutcome {
+        value,
+        most_common,
+        quorum,
+        votes: tally.votes_by_key(),
+        voters: tally.voters().iter().cloned().collect(),
+        failures,
+    })
+}
+
+/// The URLs of `endpoints`, heaviest first, ties in the order given.
+fn by_weight(endpoints: &[Endpoint]) -> Result<Vec<String>> {
+    if endpoints.is_empty() {
+        return Err(RpcHandlerError::InvalidRpcConfig { detail: "no endpoints to fan out to".to_string() });
+    }
+    let mut sorted: Vec<&Endpoint> = endpoints.iter().collect();
+    sorted.sort_by_key(|endpoint| std::cmp::Reverse(endpoint.weight));
+    Ok(sorted.into_iter().map(|endpoint| endpoint.url.clone()).collect())
+}
+
+/// Votes a key needs out of `total` for a quorum of `threshold`.
+pub(crate) fn quorum_of(total: usize, threshold: f64) -> usize {
+    (total as f64 * threshold).ceil() as usize
+}
+
+/// Where a race is among its rounds and batches.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Position {
+    pub round: usize,
+    pub batch: usize,
+    /// The last batch of the last round
+    pub last: bool,
+}
+
+impl Position {
+    pub(crate) fn is_first(&self) -> bool {
+        self.round == 0 && self.batch == 0
+    }
+}
+
+/// Every batch, in order, `rounds` times over.
+pub(crate) fn positions(batches: usize, rounds: usize) -> impl Iterator<Item = Position> {
+    (0..rounds).flat_map(move |round| {
+        (0..batches).map(move |batch| Position { round, batch, last: round + 1 == rounds && batch + 1 == batches })
+    })
+}
+
+/// What `settle` makes of one attempt.
+pub(crate) enum Verdict<T> {
+    Won(T),
+    /// Carries the error to report if the batch fails, `None` to keep the previous one
+    Lost(Option<RpcHandlerError>),
+}
+
+/// Settle a batch whose attempts have all finished: the first, in endpoint order, that `judge`
+/// lets win answers. The results after it are handed to `rest` unjudged.
+///
+/// When nothing wins, a lone endpoint's error is returned as-is, so its URL and category reach
+/// the caller; a larger batch fails with `AllEndpointsFailed`.
+pub(crate) fn settle<T, U>(results: Vec<T>, mut judge: impl FnMut(usize, T) -> Verdict<U>, mut rest: impl FnMut(usize, T)) -> Result<(usize, U)> {
+    let lone = results.len() == 1;
+    let mut last_error = None;
+    let mut results = results.into_iter().enumerate();
+    while let Some((i, result)) = results.next() {
+        match judge(i, result) {
+            Verdict::Won(value) => {
+                for (j, result) in results {
+                    rest(j, result);
+                }
+                return Ok((i, value));
+            }
+            Verdict::Lost(Some(error)) => last_error = Some(error),
+            Verdict::Lost(None) => {}
+        }
+    }
+    match last_error {
+        Some(error) if lone => Err(error),
+        _ => Err(RpcHandlerError::AllEndpointsFailed),
+    }
+}
+
+/// Run `start(url)` as a task for each of `urls`, at most `concurrency` at a time, and hand each
+/// outcome to `on_outcome` as it arrives until that returns `false`.
+///
+/// A task that panicked or was cancelled is handed over as its `JoinError`, so the caller can
+/// count the panic as the endpoint failing. Returns the tasks still running once `on_outcome`
+/// stops, for the caller to cancel or leave to finish.
+pub(crate) async fn fan_out<T, Fut>(
+    urls: &[String],
+    concurrency: usize,
+    mut start: impl FnMut(&str) -> Fut,
+    mut on_outcome: impl FnMut(String, std::result::Result<T, JoinError>) -> bool,
+) -> HashMap<String, AbortHandle>
+where
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let concurrency = concurrency.max(1);
+    let mut index = 0;
+    let mut in_flight = FuturesUnordered::new();
+    let mut pending: HashMap<String, AbortHandle> = HashMap::new();
+    let mut going = true;
+    while going && (index < urls.len() || !in_flight.is_empty()) {
+        while index < urls.len() && in_flight.len() < concurrency {
+            let url = urls[index].clone();
+            // Detached, so the caller's span is handed on for what the sub-request logs
+            let task = runtime::spawn(start(&url).instrument(tracing::Span::current()));
+            pending.insert(url.clone(), task.abort_handle());
+            in_flight.push(async move { (url, task.await) });
+            index += 1;
+        }
+
+        let Some((url, joined)) = in_flight.next().await else { break };
+        pending.remove(&url);
+        going = on_outcome(url, joined);
+    }
+    pending.retain(|_, task| !task.is_finished());
+    pending
+}
+
+/// Answers grouped by what `comparator` makes them agree on.
+#[derive(Debug)]
+pub(crate) struct Tally {
+    comparator: Arc<dyn ResultComparator>,
+    /// Votes and answers per comparator key
+    classes: HashMap<String, (usize, Vec<Value>)>,
+    /// URL and key of each answer, in arrival order
+    voters: Vec<(String, String)>,
+    weight: usize,
+}
+
+impl Tally {
+    pub(crate) fn new(comparator: Arc<dyn ResultComparator>) -> Self {
+        Self { comparator, classes: HashMap::new(), voters: Vec::new(), weight: 0 }
+    }
+
+    /// Count `value` from `url` `weight` times, returning the key it went to. Counts nothing if
+    /// the comparator panics.
+    pub(crate) fn vote(&mut self, url: String, value: Value, weight: usize) -> Result<String> {
+        let key = panics::contain(panics::COMPARATOR, || self.comparator.key(&value))?;
+        let (votes, values) = self.classes.entry(key.clone()).or_default();
+        *votes += weight;
+        values.push(value);
+        self.voters.push((url, key.clone()));
+        self.weight += weight;
+        Ok(key)
+    }
+
+    pub(crate) fn votes(&self, key: &str) -> usize {
+        self.classes.get(key).map_or(0, |(votes, _)| *votes)
+    }
+
+    /// Distinct answers so far.
+    #[cfg_attr(not(feature = "consensus"), allow(dead_code))]
+    pub(crate) fn classes(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Votes cast so far.
+    pub(crate) fn weight(&self) -> usize {
+        self.weight
+    }
+
+    #[cfg_attr(not(feature = "consensus"), allow(dead_code))]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.voters.is_empty()
+    }
+
+    /// The key with the most votes, ties to the lowest key.
+    pub(crate) fn most_common(&self) -> Option<String> {
+        self.classes
+            .iter()
+            .max_by(|(a_key, (a_votes, _)), (b_key, (b_votes, _))| a_votes.cmp(b_votes).then_with(|| b_key.cmp(a_key)))
+            .map(|(key, _)| key.clone())
+    }
+
+    /// The value `key`'s answers settle on, `None` for a key nothing voted for.
+    pub(crate) fn value(&self, key: &str) -> Result<Option<Value>> {
+        let Some((_, values)) = self.classes.get(key) else { return Ok(None) };
+        panics::contain(panics::COMPARATOR, || self.comparator.merge(&values.iter().collect::<Vec<_>>())).map(Some)
+    }
+
+    pub(crate) fn votes_by_key(&self) -> BTreeMap<String, usize> {
+        self.classes.iter().map(|(key, (votes, _))| (key.clone(), *votes)).collect()
+    }
+
+    pub(crate) fn voters(&self) -> &[(String, String)] {
+        &self.voters
+    }
+}
@@ -0,0 +1,280 @@
+//! Polling log filters that survive failover.
+//!
+//! A filter installed with `eth_newFilter` only exists on the node that installed it, so polls
+//! are pinned to that endpoint. When it stops answering, or the handler moves to another provider,
+//! the filter is installed again elsewhere and the blocks in between are read with `eth_getLogs`.
+
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use reqwest::header::HeaderMap;
+use serde_json::{json, Value};
+
+use crate::{
+    calls::RpcCalls,
+    events::HandlerEvent,
+    namespaces::parse_quantity,
+    provider::{post_json_rpc, TrafficClass},
+    runtime,
+    JsonRpcRequest, JsonRpcResponse, Result, RpcHandler, RpcHandlerError,
+};
+
+/// Most blocks a single backfill `eth_getLogs` asks for; longer gaps are read in several queries.
+pub const BACKFILL_SPAN_BLOCKS: u64 = 2_000;
+
+/// A log filter pinned to one endpoint, moved to another when that one fails.
+///
+/// Delivery is at-least-once across a move, with logs already delivered dropped by
+/// `(blockHash, logIndex)`. Removed logs from a reorg are always passed on. Dropping it uninstalls
+/// the filter from its endpoint best-effort.
+pub struct ManagedFilter {
+    handler: Arc<RpcHandler>,
+    client: reqwest::Client,
+    spec: Value,
+    url: String,
+    filter_id: String,
+    /// The handler's provider when last looked, so only a move by the handler moves the filter
+    active: Option<String>,
+    /// First block a backfill after a move reads from
+    resume_from: u64,
+    /// Delivered logs by `(blockHash, logIndex)`, with their block number so old ones can be let go
+    seen: HashMap<(String, String), u64>,
+}
+
+impl fmt::Debug for ManagedFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManagedFilter")
+            .field("spec", &self.spec)
+            .field("url", &self.url)
+            .field("filter_id", &self.filter_id)
+            .field("resume_from", &self.resume_from)
+            .finish()
+    }
+}
+
+impl RpcCalls {
+    /// Install `filter_spec` (the `eth_newFilter` parameter object) on the active provider.
+    ///
+    /// Logs are delivered from the block after installation onward; anything older is for
+    /// `eth_getLogs`. If the active provider refuses the filter, the next endpoint in the failover
+    /// order is tried.
+    pub async fn managed_log_filter(&self, filter_spec: Value) -> Result<ManagedFilter> {
+        let mut filter = ManagedFilter {
+            handler: Arc::clone(&self.handler),
+            client: self.client.clone(),
+            spec: filter_spec,
+            url: String::new(),
+            filter_id: String::new(),
+            active: self.handler.get_provider_url().await.ok(),
+            resume_from: 0,
+            seen: HashMap::new(),
+        };
+
+        let mut last_error = None;
+        for url in filter.candidates(None).await {
+            match filter.install_on(&url).await {
+                Ok((filter_id, head)) => {
+                    (filter.url, filter.filter_id, filter.resume_from) = (url, filter_id, head + 1);
+                    return Ok(filter);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or(RpcHandlerError::NoAvailableRpcs { network_id: self.handler.network_id }))
+    }
+}
+
+impl ManagedFilter {
+    /// The endpoint holding the filter.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The filter's id on that endpoint.
+    pub fn filter_id(&self) -> &str {
+        &self.filter_id
+    }
+
+    /// New logs since the last poll.
+    ///
+    /// If the endpoint fails or the handler has moved to another provider, the filter is installed
+    /// on the new endpoint and the logs since the last delivered block are returned instead. When
+    /// no endpoint takes the filter the error is returned and the next poll starts over.
+    pub async fn poll(&mut self) -> Result<Vec<Value>> {
+        let active = self.handler.get_provider_url().await.ok();
+        let moved = active != self.active && active.as_ref().is_some_and(|active| *active != self.url);
+        self.active = active;
+        if moved {
+            return self.relocate(None).await;
+        }
+
+        let changes = self.call(&self.url, "eth_getFilterChanges", json!([self.filter_id])).await;
+        match changes {
+            Ok(Value::Array(logs)) => Ok(self.deliver(logs)),
+            Ok(other) => Err(RpcHandlerError::SerializationError(format!("eth_getFilterChanges returned a non-array result: {other}"))),
+            Err(e) => {
+                self.handler
+                    .log("warn", "Managed filter poll failed, reinstalling", Some(json!({ "url": self.url, "error": e.to_string() })))
+                    .await;
+                let failed = self.url.clone();
+                self.relocate(Some(&failed)).await
+            }
+        }
+    }
+
+    /// Move the filter off its endpoint, skipping `failed`, and backfill the gap.
+    async fn relocate(&mut self, failed: Option<&str>) -> Result<Vec<Value>> {
+        let mut last_error = None;
+        for url in self.candidates(failed).await {
+            let (filter_id, head) = match self.install_on(&url).await {
+                Ok(installed) => installed,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            match self.backfill(&url, head).await {
+                Ok(logs) => {
+                    let previous = std::mem::replace(&mut self.url, url);
+                    let previous_id = std::mem::replace(&mut self.filter_id, filter_id);
+                    if failed.is_none() {
+                        self.uninstall_in_background(previous.clone(), previous_id);
+                    }
+                    let delivered = self.deliver(logs);
+                    self.handler.emit(HandlerEvent::FilterReinstalled {
+                        from: previous,
+                        to: self.url.clone(),
+                        backfilled: delivered.len(),
+                    });
+                    return Ok(delivered);
+                }
+                Err(e) => {
+                    self.uninstall_in_background(url, filter_id);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(RpcHandlerError::NoAvailableRpcs { network_id: self.handler.network_id }))
+    }
+
+    /// The active provider first, then the rest of the failover order.
+    async fn candidates(&self, failed: Option<&str>) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        let active = self.handler.get_provider_url().await.ok();
+        let ordered = self.handler.ordered_rpcs().await.into_iter().map(|ordered| ordered.rpc.url.to_string());
+        for url in active.into_iter().chain(ordered) {
+            if Some(url.as_str()) != failed && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        urls
+    }
+
+    /// Install the filter on `url`, returning its id and the endpoint's head when it was installed.
+    async fn install_on(&self, url: &str) -> Result<(String, u64)> {
+        let filter_id = match self.call(url, "eth_newFilter", json!([self.spec])).await? {
+            Value::String(filter_id) => filter_id,
+            other => return Err(RpcHandlerError::SerializationError(format!("eth_newFilter returned a non-string result: {other}"))),
+        };
+        let head = self.call(url, "eth_blockNumber", json!([])).await.and_then(|head| {
+            parse_quantity(&head)
+                .ok_or_else(|| RpcHandlerError::SerializationError(format!("eth_blockNumber returned a non-numeric result: {head}")))
+        });
+        match head {
+            Ok(head) => Ok((filter_id, head)),
+            Err(e) => {
+                self.uninstall_in_background(url.to_string(), filter_id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Logs from `resume_from` up to `head` on `url`, `BACKFILL_SPAN_BLOCKS` at a time.
+    async fn backfill(&self, url: &str, head: u64) -> Result<Vec<Value>> {
+        let to_block = match self.spec.get("toBlock").and_then(parse_quantity) {
+            Some(limit) => head.min(limit),
+            None => head,
+        };
+        let mut logs = Vec::new();
+        let mut from = self.resume_from;
+        while from <= to_block {
+            let to = to_block.min(from + BACKFILL_SPAN_BLOCKS - 1);
+            let mut query = self.spec.clone();
+            if let Value::Object(fields) = &mut query {
+                fields.insert("fromBlock".into(), json!(format!("0x{from:x}")));
+                fields.insert("toBlock".into(), json!(format!("0x{to:x}")));
+            }
+            match self.call(url, "eth_getLogs", json!([query])).await? {
+                Value::Array(batch) => logs.extend(batch),
+                other => return Err(RpcHandlerError::SerializationError(format!("eth_getLogs returned a non-array result: {other}"))),
+            }
+            from = to + 1;
+        }
+        Ok(logs)
+    }
+
+    /// Drop logs already delivered and remember the rest.
+    fn deliver(&mut self, logs: Vec<Value>) -> Vec<Value> {
+        let mut delivered = Vec::with_capacity(logs.len());
+        for log in logs {
+            let removed = log.get("removed").and_then(Value::as_bool).unwrap_or(false);
+            let key = log.get("blockHash").and_then(Value::as_str).zip(log.get("logIndex").and_then(Value::as_str));
+            let block = log.get("blockNumber").and_then(parse_quantity);
+            if let (false, Some((hash, index)), Some(block)) = (removed, key, block) {
+                if self.seen.insert((hash.to_string(), index.to_string()), block).is_some() {
+                    continue;
+                }
+                self.resume_from = self.resume_from.max(block);
+            }
+            delivered.push(log);
+        }
+        // A backfill never reads below `resume_from`, so older logs can't come back
+        let resume_from = self.resume_from;
+        self.seen.retain(|_, block| *block >= resume_from);
+        delivered
+    }
+
+    async fn call(&self, url: &str, method: &str, params: Value) -> Result<Value> {
+        self.handler.spend_meter().charge(url, method)?;
+        let _slot = self.handler.host_limiter().reserve(TrafficClass::Request).await;
+        let settings = &self.handler.config().settings;
+        let headers = self.handler.endpoint_headers();
+        call(&self.client, url, headers.get(url), settings.follow_post_redirects, settings.rpc_call_timeout, method, params).await
+    }
+
+    fn uninstall_in_background(&self, url: String, filter_id: String) {
+        if !runtime::available() {
+            return;
+        }
+        let client = self.client.clone();
+        let settings = &self.handler.config().settings;
+        let (follow_redirects, timeout) = (settings.follow_post_redirects, settings.rpc_call_timeout);
+        let headers = self.handler.endpoint_headers().get(&url).cloned();
+        let host_limiter = self.handler.host_limiter().clone();
+        if self.handler.spend_meter().charge(&url, "eth_uninstallFilter").is_err() {
+            return;
+        }
+        runtime::spawn(async move {
+            let _slot = host_limiter.reserve(TrafficClass::Probe).await;
+            let _ = call(&client, &url, headers.as_ref(), follow_redirects, timeout, "eth_uninstallFilter", json!([filter_id])).await;
+        });
+    }
+}
+
+impl Drop for ManagedFilter {
+    fn drop(&mut self) {
+        if !self.filter_id.is_empty() {
+            let (url, filter_id) = (std::mem::take(&mut self.url), std::mem::take(&mut self.filter_id));
+            self.uninstall_in_background(url, filter_id);
+        }
+    }
+}
+
+/// One JSON-RPC call straight to `url`, bypassing failover.
+async fn call(client: &reqwest::Client, url: &str, headers: Option<&HeaderMap>, follow_redirects: bool, timeout: Duration, method: &str, params: Value) -> Result<Value> {
+    let request = JsonRpcRequest::new(method, params);
+    let response = runtime::timeout(timeout, post_json_rpc(client, url, &request, follow_redirects, headers))
+        .await
+        .map_err(|_| RpcHandlerError::request_timeout(url, timeout))??;
+    let body: JsonRpcResponse<Value> = response.json().await.map_err(|e| RpcHandlerError::from_reqwest(e, url))?;
+    body.into_result()
+}
@@ -1,68 +1,390 @@
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
+use std::{cmp::Reverse, collections::{BTreeSet, HashMap, HashSet, VecDeque}, io, net::IpAddr, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, SystemTime}};
+use tokio::sync::{broadcast, mpsc::UnboundedReceiver, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use web_time::Instant;
 
+#[cfg(feature = "abi")]
+use crate::multicall::Deployments;
+#[cfg(feature = "consensus")]
+use crate::{methods, performance::observed};
+#[cfg(feature = "otel")]
+use crate::otel::{self, SharedTracerProvider};
 use crate::{
-    config::{resolve_config, NormalizedConfig},
-    provider::{create_provider, wrap_with_retry, RetryOptions},
-    provider::retry_proxy::RetryProvider,
-    rpc::select_base_rpc_set,
-    strategy::{get_fastest, get_first_healthy, Strategy},
-    JsonRpcRequest, JsonRpcResponse, NetworkId, Result, RpcHandlerError, Rpc,
+    auto_refresh::spawn_auto_refresh,
+    degraded::{retry_backoff, spawn_recovery},
+    calls::Cooldowns,
+    clock::{system_clock, Clock},
+    chainlist::{data_provenance, ChainView},
+    config::{resolve_config_with, resolve_config::SettingsConfig, EffectivePolicy, NormalizedConfig},
+    error::kb::{self, ErrorCondition},
+    events::{EventHistory, HandlerEvent, InitState, RecordedEvent, EVENT_CAPACITY},
+    head::HeadTracker,
+    health::{sort_endpoints, sort_latencies, EndpointHealth, HealthPage, HealthReport, Order, SortBy},
+    hold::{is_total_failure, HoldState},
+    journal::{summarize, FailureJournal, FailureSummary, JournalStore},
+    location::{browser_store, location_key, DefaultRouteLocation, LatencySnapshot, LatencyStore, LocationProvider},
+    keepalive::{spawn_keepalive, KEEPALIVE_DEMOTE_AFTER},
+    maintenance::{spawn_maintenance_watch, MaintenanceSchedule, MAINTENANCE_RECHECK},
+    memory::{evict_to_capacity, MemoryReport},
+    metrics::{Metrics, MetricsSnapshot},
+    namespaces::{parse_quantity, EndpointCapabilities},
+    performance::{lagging_latencies, observed::HeavyLatencies, measure_rpcs_with_transport, pick_fastest, pick_fastest_in_lowest_tier, tier_of, LatencyMap, MeasureOptions, ProbeSchedule, ProbeTimeouts, NamedProbeOutcome, RpcCheckResult, TimeoutPolicy},
+    provider::{batch::batch_name, create_provider, dns::host_of, BatchEntry, BatchOptions, headers::{header_map, HeaderOverrides}, plan::Candidates, AttributedResponse, post_json_rpc, rpc_client_builder, CallOptions, HostLimiter, HostResolver, InFlightGauge, PinningResolver, RequestPlan, RetryOptions, SystemResolver, TrafficClass, WeightedSemaphore},
+    routing::normalize_url,
+    provider::retry_proxy::{MalformedCounts, RetryProvider},
+    region::{preferred_region, region_of, region_penalty, switch_margin, Region},
+    rpc::{select_aliased_rpc_set, RpcOrigin, RpcSource, TrackedRpc},
+    rotation::{spawn_rotation_watch, AuthFailures},
+    runtime::{self, JoinHandle},
+    secrets::{EnvSecretResolver, Redactor, SecretResolver},
+    shadow::{ShadowReport, Shadows},
+    slo::{spawn_slo_watch, SloBreach, SloGuard},
+    cache::{CacheStats, ResponseCache},
+    timestamps::{HealthFlag, TimestampGuard},
+    agreement::{spawn_agreement_sampler, AgreementTracker},
+    tuning::{spawn_retry_tuner, RetryTuner},
+    liveness::{AttemptFailure, LivenessLog, RpcProvenance, REPORTED_UPTIME_WINDOW},
+    readiness::{BackgroundTask, Heartbeats},
+    spend::{spawn_budget_watch, BudgetExhaustion, SpendMeter, SpendReport, SpendStore},
+    strategy::{first_responsive, get_first_healthy, Strategy},
+    tags,
+    transport::HttpTransportFactory,
+    Egress, FailoverPolicy, JsonRpcError, JsonRpcRequest, JsonRpcResponse, LatencyRecord, NetworkId, Result, RpcHandlerError, Rpc, RpcConfig,
 };
 
+/// Healthy probe latencies kept for deriving the adaptive probe timeout.
+pub const PROBE_HISTORY_LEN: usize = 256;
+
+/// Pluggable pieces of the handler that default to the real implementations.
+#[derive(Clone, Default)]
+pub struct HandlerComponents {
+    /// Hostname resolution for `pin_resolved_ips` and `doctor`, defaults to the system resolver
+    pub host_resolver: Option<Arc<dyn HostResolver>>,
+    /// Time source for cooldowns, backoff, TTLs and keepalive scheduling, defaults to the system clock
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Fills in `url_template` placeholders, defaults to environment variables
+    pub secret_resolver: Option<Arc<dyn SecretResolver>>,
+    /// Keeps latency snapshots per network location, so `init` can skip the first sweep; none by
+    /// default, or `localStorage` with `browser_local_storage` on `wasm32`
+    pub latency_store: Option<Arc<dyn LatencyStore>>,
+    /// Tells network locations apart for `latency_store`, defaults to `DefaultRouteLocation`
+    pub location_provider: Option<Arc<dyn LocationProvider>>,
+    /// Keeps the day's spend on metered endpoints across restarts; in memory only by default
+    pub spend_store: Option<Arc<dyn SpendStore>>,
+    /// Keeps terminal failures for `failure_summary`; nothing is journaled without one
+    pub failure_journal: Option<Arc<dyn JournalStore>>,
+    /// Endpoints added to the configured ones, defaults to the chainlist data in the `DataScope`
+    pub rpc_source: Option<Arc<dyn RpcSource>>,
+    /// Provides the tracer for a span per call and attempt; calls aren't traced without one
+    #[cfg(feature = "otel")]
+    pub tracer_provider: Option<SharedTracerProvider>,
+}
+
 pub struct RpcHandler {
-    pub config: NormalizedConfig,
+    /// Swapped whole by `apply_config`, so a reader always sees one consistent config
+    config: parking_lot::RwLock<Arc<NormalizedConfig>>,
     pub network_id: NetworkId,
-    pub rpcs: Vec<Rpc>,
+    /// The endpoints, each with how it came to be in the set
+    rpcs: parking_lot::RwLock<Vec<TrackedRpc>>,
     latencies: Arc<RwLock<HashMap<String, u64>>>,
+    lagging: Arc<RwLock<LatencyMap>>,
+    /// Latencies heavy-method requests observed, kept out of the ordering
+    heavy_latencies: HeavyLatencies,
+    /// The chain id each endpoint answered `eth_chainId` with, checked while the network has aliases
+    reported_chain_ids: parking_lot::Mutex<HashMap<String, NetworkId>>,
+    /// Blocks each endpoint trailed the most common head by at its last probe
+    head_lags: Arc<parking_lot::Mutex<HashMap<String, u64>>>,
+    /// When each endpoint was last probed, by a full sweep or a part of one
+    probed_at: parking_lot::Mutex<HashMap<String, SystemTime>>,
+    /// Custom probe outcomes for each endpoint at its last probe
+    custom_probe_outcomes: parking_lot::Mutex<HashMap<String, Vec<NamedProbeOutcome>>>,
+    client_versions: Arc<RwLock<HashMap<String, String>>>,
+    capabilities: Arc<RwLock<HashMap<String, EndpointCapabilities>>>,
+    failure_counts: Arc<RwLock<HashMap<String, u32>>>,
+    malformed_counts: MalformedCounts,
+    probe_schedule: ProbeSchedule,
+    cooldowns: Cooldowns,
+    /// When each endpoint's state last changed, the eviction order for maps without timestamps
+    last_updated: parking_lot::Mutex<HashMap<String, Instant>>,
+    /// Recent healthy probe latencies across the network, newest last
+    probe_history: parking_lot::Mutex<VecDeque<u64>>,
+    probe_timeouts: parking_lot::Mutex<Option<ProbeTimeouts>>,
     provider: Arc<RwLock<Option<RetryProvider>>>,
     strategy: Strategy,
     client: reqwest::Client,
+    host_resolver: Arc<dyn HostResolver>,
+    resolver: Option<PinningResolver>,
+    clock: Arc<dyn Clock>,
+    shutdown: CancellationToken,
+    keepalive_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    init_state: parking_lot::RwLock<InitState>,
+    /// The background sweep behind a `FastStart` provisional provider
+    sweep_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    events: broadcast::Sender<HandlerEvent>,
+    event_history: EventHistory,
+    host_limiter: HostLimiter,
+    heads: HeadTracker,
+    in_flight: InFlightGauge,
+    auto_refresh_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    maintenance_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    /// Refreshes a degraded handler until it serves through a probed provider
+    recovery_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    /// Violations and penalties under `HandlerSettings::latency_slo`
+    slo: SloGuard,
+    /// Breaches the SLO watch hasn't taken over yet; `None` once it runs
+    slo_breaches: parking_lot::Mutex<Option<UnboundedReceiver<SloBreach>>>,
+    slo_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    /// The day's spend against the cost profiles and `HandlerSettings::daily_spend_budget`
+    spend: SpendMeter,
+    /// Exhausted budgets the budget watch hasn't taken over yet; `None` once it runs
+    spend_exhaustions: parking_lot::Mutex<Option<UnboundedReceiver<BudgetExhaustion>>>,
+    spend_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    /// Endpoints flagged under `HandlerSettings::timestamp_sanity`
+    timestamps: TimestampGuard,
+    /// Recent samples and flags under `HandlerSettings::agreement_sampling`
+    agreement: AgreementTracker,
+    agreement_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    /// Outcomes and changes under `HandlerSettings::retry_tuning`
+    pub(crate) tuner: RetryTuner,
+    tuner_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    /// Up and down outcomes of every probe, ping and attempt, per endpoint
+    liveness: LivenessLog,
+    /// Terminal failures, under `HandlerComponents::failure_journal`
+    journal: Option<FailureJournal>,
+    response_cache: ResponseCache,
+    /// Auto-refresh ticks skipped because the handler was busy
+    refresh_deferrals: AtomicU64,
+    last_full_sweep: parking_lot::Mutex<Option<SystemTime>>,
+    hold: HoldState,
+    /// The chain data this handler's `DataScope` keeps in view
+    chain_data: ChainView,
+    rpc_source: Arc<dyn RpcSource>,
+    metrics: Metrics,
+    shadows: Shadows,
+    /// Fills in URL templates when `apply_config` resolves a new config or a secret rotates
+    secrets: Arc<dyn SecretResolver>,
+    /// Templated endpoints that answered `401` or `403`
+    auth_failures: AuthFailures,
+    /// Failures the rotation watch hasn't taken over yet; `None` once it runs
+    auth_failure_reports: parking_lot::Mutex<Option<UnboundedReceiver<String>>>,
+    rotation_task: parking_lot::Mutex<Option<JoinHandle<()>>>,
+    /// Last sign of life of each background loop, for `liveness`
+    heartbeats: Heartbeats,
+    latency_store: Option<Arc<dyn LatencyStore>>,
+    location_provider: Arc<dyn LocationProvider>,
+    /// Key of the network location the latencies were measured at
+    location: parking_lot::Mutex<Option<String>>,
+    #[cfg(feature = "otel")]
+    tracer: Option<Arc<opentelemetry::global::BoxedTracer>>,
+    /// Which Multicall3 addresses were found to have code on this network
+    #[cfg(feature = "abi")]
+    multicall3: Deployments,
 }
 
 impl RpcHandler {
     pub async fn new(config: crate::HandlerConfig, strategy: Option<Strategy>) -> Result<Arc<Self>> {
-        let normalized_config = resolve_config(config);
-        let strategy = strategy.unwrap_or(Strategy::Fastest);
+        Self::with_components(config, strategy, HandlerComponents::default()).await
+    }
+
+    /// Like `new`, but with some of the handler's components swapped out.
+    pub async fn with_components(
+        config: crate::HandlerConfig,
+        strategy: Option<Strategy>,
+        components: HandlerComponents,
+    ) -> Result<Arc<Self>> {
+        let secrets = components.secret_resolver.clone().unwrap_or_else(|| Arc::new(EnvSecretResolver));
+        let normalized_config = resolve_config_with(config, secrets.as_ref())?;
+        // A local node's chain id says nothing about which public endpoints would serve it
+        let chain_data = match normalized_config.data_scope.retained(normalized_config.network_id) {
+            _ if normalized_config.settings.localnet => ChainView::scoped(&[]),
+            Some(networks) => ChainView::scoped(&[networks, normalized_config.chain_aliases.clone()].concat()),
+            None => ChainView::global(),
+        };
+        let rpc_source = components.rpc_source.unwrap_or_else(|| Arc::new(chain_data.clone()));
         
         // Select base RPC set
-        let rpcs = select_base_rpc_set(
+        let rpcs = select_aliased_rpc_set(
+            rpc_source.as_ref(),
             normalized_config.network_id,
+            &normalized_config.chain_aliases,
             normalized_config.tracking.clone(),
             normalized_config.injected_rpcs.clone(),
+            normalized_config.settings.egress,
         );
+        let strategy = match strategy.unwrap_or_default() {
+            Strategy::StaticOrder(urls) => Strategy::StaticOrder(static_order(&urls, &rpcs, &normalized_config.redactor)?),
+            strategy => strategy,
+        };
 
+        let clock = components.clock.unwrap_or_else(system_clock);
+        let stale_data = stale_chain_data(&normalized_config, clock.now_system())?;
+        let host_resolver = components.host_resolver.unwrap_or_else(|| Arc::new(SystemResolver));
+        if cfg!(target_arch = "wasm32") && normalized_config.settings.pin_resolved_ips {
+            return Err(RpcHandlerError::Unsupported { feature: "pin_resolved_ips".to_string() });
+        }
+        let resolver = normalized_config.settings.pin_resolved_ips.then(|| {
+            PinningResolver::new(Arc::clone(&host_resolver)).with_clock(Arc::clone(&clock))
+        });
+        let mut host_limiter = HostLimiter::new(normalized_config.settings.host_limits.clone()).with_clock(Arc::clone(&clock));
+        if let Some(mode) = normalized_config.settings.constrained_mode {
+            host_limiter = host_limiter.with_budget(WeightedSemaphore::new(mode.max_total_inflight, mode.probe_slots));
+        }
+        let (slo, slo_breaches) = SloGuard::new();
+        let (auth_failures, auth_failure_reports) = AuthFailures::new();
+        let (spend, spend_exhaustions) = SpendMeter::new(normalized_config.network_id, Arc::clone(&clock), components.spend_store);
+        let profiles = rpcs.iter().filter_map(|tracked| Some((tracked.rpc.url.to_string(), normalized_config.cost_profiles.get(tracked.rpc.url.as_str())?.clone()))).collect();
+        spend.configure(profiles, normalized_config.settings.daily_spend_budget, &normalized_config.redactor);
+
+        let latency_store = components.latency_store.or_else(|| browser_store(normalized_config.settings.browser_local_storage));
+        let journal = components.failure_journal.map(|store| FailureJournal::new(store, Arc::clone(&clock)));
+        // Falling back to a default client would drop the configured user agent and timeout
+        let client = client_builder(&normalized_config.settings)
+            .build()
+            .map_err(|e| RpcHandlerError::InvalidRpcConfig { detail: format!("couldn't build the HTTP client: {e}") })?;
+        let heartbeats = Heartbeats::new(Arc::clone(&clock));
         let handler = Arc::new(Self {
             network_id: normalized_config.network_id,
-            rpcs,
+            rpcs: parking_lot::RwLock::new(rpcs),
             latencies: Arc::new(RwLock::new(HashMap::new())),
+            lagging: Arc::new(RwLock::new(HashMap::new())),
+            heavy_latencies: HeavyLatencies::default(),
+            reported_chain_ids: parking_lot::Mutex::default(),
+            head_lags: Arc::default(),
+            probed_at: parking_lot::Mutex::default(),
+            custom_probe_outcomes: parking_lot::Mutex::default(),
+            client_versions: Arc::new(RwLock::new(HashMap::new())),
+            capabilities: Arc::new(RwLock::new(HashMap::new())),
+            failure_counts: Arc::new(RwLock::new(HashMap::new())),
+            malformed_counts: MalformedCounts::default(),
+            probe_schedule: ProbeSchedule::default(),
+            cooldowns: Cooldowns::default(),
+            last_updated: parking_lot::Mutex::new(HashMap::new()),
+            probe_history: parking_lot::Mutex::new(VecDeque::new()),
+            probe_timeouts: parking_lot::Mutex::new(None),
             provider: Arc::new(RwLock::new(None)),
             strategy,
-            client: reqwest::Client::new(),
-            config: normalized_config,
+            client,
+            host_resolver,
+            resolver,
+            clock,
+            shutdown: CancellationToken::new(),
+            keepalive_task: parking_lot::Mutex::new(None),
+            init_state: parking_lot::RwLock::new(InitState::Starting),
+            sweep_task: parking_lot::Mutex::new(None),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            event_history: EventHistory::default(),
+            host_limiter,
+            heads: match normalized_config.settings.monotonic_head {
+                Some(guard) => HeadTracker::new(true, guard.max_head_lag, guard.reorg_tolerance),
+                None => HeadTracker::default(),
+            },
+            in_flight: InFlightGauge::default(),
+            auto_refresh_task: parking_lot::Mutex::new(None),
+            maintenance_task: parking_lot::Mutex::new(None),
+            recovery_task: parking_lot::Mutex::new(None),
+            slo,
+            slo_breaches: parking_lot::Mutex::new(Some(slo_breaches)),
+            slo_task: parking_lot::Mutex::new(None),
+            spend,
+            spend_exhaustions: parking_lot::Mutex::new(Some(spend_exhaustions)),
+            spend_task: parking_lot::Mutex::new(None),
+            timestamps: TimestampGuard::default(),
+            agreement: AgreementTracker::default(),
+            agreement_task: parking_lot::Mutex::new(None),
+            tuner: RetryTuner::default(),
+            tuner_task: parking_lot::Mutex::new(None),
+            liveness: LivenessLog::default(),
+            journal,
+            response_cache: ResponseCache::default(),
+            refresh_deferrals: AtomicU64::new(0),
+            last_full_sweep: parking_lot::Mutex::new(None),
+            hold: HoldState::default(),
+            chain_data,
+            rpc_source,
+            metrics: Metrics::default(),
+            shadows: Shadows::default(),
+            config: parking_lot::RwLock::new(Arc::new(normalized_config)),
+            secrets,
+            auth_failures,
+            auth_failure_reports: parking_lot::Mutex::new(Some(auth_failure_reports)),
+            rotation_task: parking_lot::Mutex::new(None),
+            heartbeats,
+            latency_store,
+            location_provider: components.location_provider.unwrap_or_else(|| Arc::new(DefaultRouteLocation)),
+            location: parking_lot::Mutex::new(None),
+            #[cfg(feature = "otel")]
+            tracer: components.tracer_provider.as_ref().map(otel::tracer),
+            #[cfg(feature = "abi")]
+            multicall3: Deployments::default(),
         });
 
+        if let Some(event) = stale_data {
+            handler.emit(event);
+        }
         Ok(handler)
     }
 
     pub async fn init(self: &Arc<Self>) -> Result<()> {
+        *self.location.lock() = self.locate();
+        self.verify_chain_ids().await?;
+        if !self.restore_snapshot().await? {
+            self.select_initial_provider().await?;
+        }
+
+        self.collect_garbage().await;
+        self.start_keepalive();
+        self.start_auto_refresh();
+        self.start_maintenance_watch();
+        self.start_slo_watch();
+        self.start_budget_watch();
+        self.start_agreement_sampler();
+        self.start_retry_tuner();
+        self.start_rotation_watch();
+        
+        Ok(())
+    }
+
+    /// Select the first provider, retrying under `HandlerSettings::init_policy` and falling
+    /// back to serving unprobed once the attempts run out, if the policy allows it.
+    async fn select_initial_provider(self: &Arc<Self>) -> Result<()> {
+        let Some(policy) = self.config().settings.init_policy else { return self.select_provider().await };
+        let mut attempt = 1;
+        loop {
+            match self.select_provider().await {
+                Err(RpcHandlerError::NoAvailableRpcs { .. }) if attempt < policy.max_attempts => {
+                    let backoff = retry_backoff(policy.attempt_backoff, attempt);
+                    self.log("warn", "No endpoint passed the probes, retrying", Some(serde_json::json!({
+                        "attempt": attempt,
+                        "backoff_ms": backoff.as_millis() as u64,
+                    }))).await;
+                    self.clock.sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(RpcHandlerError::NoAvailableRpcs { .. }) if policy.allow_unprobed_fallback => {
+                    return self.degrade(attempt, policy.attempt_backoff).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// One selection by the strategy, `NoAvailableRpcs` when no endpoint qualifies.
+    async fn select_provider(self: &Arc<Self>) -> Result<()> {
         match self.strategy {
             Strategy::Fastest => {
-                let (fastest, latencies) = get_fastest(&self.rpcs, self.config.settings.rpc_timeout).await?;
+                let (fastest, latencies, lagging) = self.measure_fastest().await?;
                 
                 if let Some(fastest_url) = fastest {
                     {
                         let mut latencies_lock = self.latencies.write().await;
                         *latencies_lock = latencies;
                     }
+                    *self.lagging.write().await = lagging;
+                    self.save_snapshot().await;
+                    self.install_provider(fastest_url).await?;
                     
-                    let provider = self.build_provider(fastest_url).await?;
-                    {
-                        let mut provider_lock = self.provider.write().await;
-                        *provider_lock = Some(provider);
-                    }
-                    
-                    self.log("info", "Initialized fastest provider", None).await;
+                    self.log("info", "Initialized fastest provider", self.probe_timeouts_log()).await;
                 } else {
                     return Err(RpcHandlerError::NoAvailableRpcs { 
                         network_id: self.network_id 
@@ -70,14 +392,10 @@ impl RpcHandler {
                 }
             }
             Strategy::FirstHealthy => {
-                let first_healthy = get_first_healthy(&self.rpcs, self.config.settings.rpc_timeout, Some(false)).await?;
+                let first_healthy = get_first_healthy(&self.transport_factory()?, &self.rpcs(), self.config().settings.rpc_timeout, Some(false)).await?;
                 
                 if let Some(url) = first_healthy {
-                    let provider = self.build_provider(url).await?;
-                    {
-                        let mut provider_lock = self.provider.write().await;
-                        *provider_lock = Some(provider);
-                    }
+                    self.install_provider(url).await?;
                     
                     self.log("info", "Initialized first healthy provider", None).await;
                 } else {
@@ -86,11 +404,691 @@ impl RpcHandler {
                     });
                 }
             }
+            Strategy::FastStart => {
+                // Start the sweep first so the race doesn't delay it
+                let sweep = {
+                    let handler = Arc::clone(self);
+                    runtime::spawn(async move { handler.measure_fastest().await })
+                };
+                let options = self.measure_options(self.probe_timeout_policy().await);
+                let probed = self.probed_rpcs(self.clock.now_instant());
+                
+                if let Some(url) = first_responsive(&self.transport_factory()?, &probed, &options).await {
+                    self.install_provider_as(url.clone(), InitState::Provisional { url: url.clone() }).await?;
+                    self.log("info", "Serving through provisional provider", Some(serde_json::json!({ "url": url }))).await;
+                    
+                    let handler = Arc::clone(self);
+                    *self.sweep_task.lock() = Some(runtime::spawn(async move { handler.finish_fast_start(url, sweep).await }));
+                } else {
+                    sweep.abort();
+                    return Err(RpcHandlerError::NoAvailableRpcs { 
+                        network_id: self.network_id 
+                    });
+                }
+            }
+            Strategy::StaticOrder(ref urls) => {
+                let Some(first) = urls.first() else {
+                    return Err(RpcHandlerError::NoAvailableRpcs { network_id: self.network_id });
+                };
+                self.install_provider(first.clone()).await?;
+                self.log("info", "Serving through the first endpoint of the static order", None).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Serve through the endpoints unprobed, first by tier and then in listing order, and keep
+    /// refreshing until one passes.
+    async fn degrade(self: &Arc<Self>, attempts: u32, backoff: Duration) -> Result<()> {
+        let Some(url) = self.unprobed_order().into_iter().next() else {
+            return Err(RpcHandlerError::NoAvailableRpcs { network_id: self.network_id });
+        };
+        self.install_provider_as(url.clone(), InitState::Degraded { url: url.clone() }).await?;
+        self.emit(HandlerEvent::Degraded { url: url.clone(), attempts });
+        self.log("warn", "Serving unprobed after init ran out of attempts", Some(serde_json::json!({ "url": url, "attempts": attempts }))).await;
+        self.start_recovery(backoff);
+        Ok(())
+    }
+
+    /// Every endpoint, lowest tier first and in listing order within a tier.
+    fn unprobed_order(&self) -> Vec<String> {
+        let tiers = &self.config().tiers;
+        let mut urls: Vec<String> = self.rpcs().iter().map(|rpc| rpc.url.to_string()).collect();
+        urls.sort_by_key(|url| tier_of(tiers, url));
+        urls
+    }
+
+    /// Wait for the fast-start sweep, then switch to its fastest endpoint if that beats the
+    /// provisional one by more than `fast_start_margin`.
+    async fn finish_fast_start(self: Arc<Self>, provisional: String, sweep: JoinHandle<Result<(Option<String>, LatencyMap, LatencyMap)>>) {
+        let (fastest, latencies, lagging) = match sweep.await {
+            Ok(Ok(measured)) => measured,
+            Ok(Err(e)) => {
+                self.log("warn", "Fast-start sweep failed, keeping provisional provider", Some(serde_json::json!({ "error": e.to_string() }))).await;
+                self.set_init_state(InitState::Final { url: provisional });
+                return;
+            }
+            Err(_) => return,
+        };
+        // A refresh that finished first has already replaced the provisional provider
+        if !matches!(self.init_state(), InitState::Provisional { .. }) {
+            return;
         }
         
+        // The provisional provider is replaced when the sweep found it unhealthy or out of sync,
+        // when `TierStrict` prefers a lower tier, or when the fastest beats it by over the margin
+        let provisional_latency = latencies.get(&provisional).copied();
+        let tiers = self.config().tiers.clone();
+        let tier = |url: &str| tier_of(&tiers, url);
+        let margin = self.config().settings.fast_start_margin.as_millis() as u64;
+        let upgrade = fastest
+            .filter(|url| *url != provisional)
+            .and_then(|url| latencies.get(&url).map(|latency| (url, *latency)))
+            .filter(|(url, latency)| match provisional_latency {
+                None => true,
+                Some(_) if self.config().failover_policy == FailoverPolicy::TierStrict && tier(url) < tier(&provisional) => true,
+                Some(current) => current.saturating_sub(*latency) > margin,
+            });
+        *self.latencies.write().await = latencies;
+        *self.lagging.write().await = lagging;
+        self.save_snapshot().await;
+        
+        match upgrade {
+            Some((url, latency)) => {
+                let provider = match self.build_provider(url.clone()).await {
+                    Ok(provider) => provider,
+                    Err(e) => {
+                        self.log("warn", "Fast-start upgrade failed, keeping provisional provider", Some(serde_json::json!({ "error": e.to_string() }))).await;
+                        self.set_init_state(InitState::Final { url: provisional });
+                        return;
+                    }
+                };
+                *self.provider.write().await = Some(provider);
+                self.emit(HandlerEvent::ProviderUpgraded {
+                    from: provisional.clone(),
+                    to: url.clone(),
+                    from_latency_ms: provisional_latency,
+                    to_latency_ms: latency,
+                });
+                self.set_init_state(InitState::Final { url: url.clone() });
+                self.log("info", "Upgraded provisional provider to fastest", Some(serde_json::json!({
+                    "from": provisional,
+                    "to": url,
+                    "from_latency_ms": provisional_latency,
+                    "to_latency_ms": latency,
+                }))).await;
+            }
+            None => {
+                self.set_init_state(InitState::Final { url: provisional });
+                self.log("info", "Kept provisional provider after sweep", self.probe_timeouts_log()).await;
+            }
+        }
+        self.collect_garbage().await;
+    }
+
+    /// Start the idle keepalive loop if it is configured and not already running.
+    fn start_keepalive(self: &Arc<Self>) {
+        let Some(keepalive) = self.config().settings.keepalive else { return };
+        let mut task = self.keepalive_task.lock();
+        if task.is_none() && !self.shutdown.is_cancelled() {
+            let heartbeat = self.heartbeats.start(BackgroundTask::Keepalive, Some(keepalive.after.max(keepalive.interval)));
+            *task = Some(spawn_keepalive(self, keepalive, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the incremental auto-refresh loop if it is configured and not already running.
+    /// A static order has nothing to refresh.
+    fn start_auto_refresh(self: &Arc<Self>) {
+        let Some(auto_refresh) = self.config().settings.auto_refresh else { return };
+        if matches!(self.strategy, Strategy::StaticOrder(_)) {
+            return;
+        }
+        let mut task = self.auto_refresh_task.lock();
+        if task.is_none() && !self.shutdown.is_cancelled() {
+            // A deferred tick waits up to one and a half ticks
+            let heartbeat = self.heartbeats.start(BackgroundTask::AutoRefresh, Some(auto_refresh.interval.max(auto_refresh.tick * 2)));
+            *task = Some(spawn_auto_refresh(self, auto_refresh, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the loop that moves the active provider off endpoints about to enter maintenance,
+    /// if any endpoint has windows and it is not already running.
+    fn start_maintenance_watch(self: &Arc<Self>) {
+        if self.maintenance().is_empty() {
+            return;
+        }
+        let mut task = self.maintenance_task.lock();
+        if task.is_none() && !self.shutdown.is_cancelled() {
+            let heartbeat = self.heartbeats.start(BackgroundTask::MaintenanceWatch, Some(MAINTENANCE_RECHECK));
+            *task = Some(spawn_maintenance_watch(self, self.config().settings.maintenance_lead, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the loop that refreshes a degraded handler, unless it is already running.
+    fn start_recovery(self: &Arc<Self>, backoff: Duration) {
+        let mut task = self.recovery_task.lock();
+        if task.is_none() && !self.shutdown.is_cancelled() {
+            // Its pauses grow, so it is only checked for having stopped
+            let heartbeat = self.heartbeats.start(BackgroundTask::Recovery, None);
+            *task = Some(spawn_recovery(self, backoff, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the loop that re-selects the provider on latency-SLO breaches, unless it already ran.
+    ///
+    /// It runs whether or not `latency_slo` is set, since a reload can turn the guard on.
+    fn start_slo_watch(self: &Arc<Self>) {
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        if let Some(breaches) = self.slo_breaches.lock().take() {
+            let heartbeat = self.heartbeats.start(BackgroundTask::SloWatch, None);
+            *self.slo_task.lock() = Some(spawn_slo_watch(self, breaches, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the loop that reports exhausted spend budgets, unless it already ran.
+    fn start_budget_watch(self: &Arc<Self>) {
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        if let Some(exhaustions) = self.spend_exhaustions.lock().take() {
+            let heartbeat = self.heartbeats.start(BackgroundTask::BudgetWatch, None);
+            *self.spend_task.lock() = Some(spawn_budget_watch(self, exhaustions, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the agreement sampling loop if it is configured and not already running.
+    fn start_agreement_sampler(self: &Arc<Self>) {
+        let Some(sampling) = self.config().settings.agreement_sampling else { return };
+        let mut task = self.agreement_task.lock();
+        if task.is_none() && !self.shutdown.is_cancelled() {
+            let heartbeat = self.heartbeats.start(BackgroundTask::AgreementSampler, Some(sampling.interval));
+            *task = Some(spawn_agreement_sampler(self, sampling, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the retry tuning loop if it is configured and not already running.
+    fn start_retry_tuner(self: &Arc<Self>) {
+        let Some(tuning) = self.config().settings.retry_tuning else { return };
+        let mut task = self.tuner_task.lock();
+        if task.is_none() && !self.shutdown.is_cancelled() {
+            let heartbeat = self.heartbeats.start(BackgroundTask::RetryTuner, Some(tuning.window));
+            *task = Some(spawn_retry_tuner(self, tuning, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// Start the loop that rotates secrets after authentication failures, unless it already ran.
+    ///
+    /// It runs whether or not `rotate_secrets_on_auth_error` is set, since a reload can turn it on.
+    fn start_rotation_watch(self: &Arc<Self>) {
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        if let Some(failures) = self.auth_failure_reports.lock().take() {
+            let heartbeat = self.heartbeats.start(BackgroundTask::RotationWatch, None);
+            *self.rotation_task.lock() = Some(spawn_rotation_watch(self, failures, heartbeat, self.shutdown.child_token()));
+        }
+    }
+
+    /// The config as last applied.
+    pub fn config(&self) -> Arc<NormalizedConfig> {
+        Arc::clone(&self.config.read())
+    }
+
+    /// What `error` from the endpoint at `url` stands for, under `HandlerSettings::error_mappings`
+    /// and then the built-in table.
+    pub fn classify_error(&self, error: &JsonRpcError, url: &str) -> ErrorCondition {
+        kb::classify_with(&self.config().settings.error_mappings, error, url)
+    }
+
+    pub(crate) fn set_config(&self, config: NormalizedConfig) {
+        *self.config.write() = Arc::new(config);
+        self.configure_spend();
+    }
+
+    /// Hand the spend meter the cost profiles of the current endpoints and the handler budget.
+    fn configure_spend(&self) {
+        let config = self.config();
+        let profiles = self.rpcs().iter().filter_map(|rpc| Some((rpc.url.to_string(), config.cost_profiles.get(rpc.url.as_str())?.clone()))).collect();
+        self.spend.configure(profiles, config.settings.daily_spend_budget, &config.redactor);
+    }
+
+    pub(crate) fn secret_resolver(&self) -> &dyn SecretResolver {
+        self.secrets.as_ref()
+    }
+
+    pub(crate) fn auth_failures(&self) -> &AuthFailures {
+        &self.auth_failures
+    }
+
+    /// The time source shared by everything time-based in this handler.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// The endpoints this handler probes and fails over between.
+    pub fn rpcs(&self) -> Vec<Rpc> {
+        self.rpcs.read().iter().map(|tracked| tracked.rpc.clone()).collect()
+    }
+
+    /// Add an endpoint at runtime. It is probed from the next `refresh`.
+    ///
+    /// Returns `false` if an endpoint with the same URL is already configured.
+    pub fn add_rpc(&self, rpc: Rpc) -> bool {
+        self.add_tracked_rpc(TrackedRpc { rpc, origin: RpcOrigin::RuntimeAdded, listed_under: None })
+    }
+
+    /// Like `add_rpc`, keeping the origin `rpc` was selected with.
+    pub(crate) fn add_tracked_rpc(&self, rpc: TrackedRpc) -> bool {
+        {
+            let mut rpcs = self.rpcs.write();
+            if rpcs.iter().any(|existing| existing.rpc.url == rpc.rpc.url) {
+                return false;
+            }
+            rpcs.push(rpc);
+        }
+        self.configure_spend();
+        true
+    }
+
+    /// Swap in `rpc` for the configured endpoint with its URL, keeping what was learned about it.
+    ///
+    /// Returns `false` if no endpoint had that URL.
+    pub(crate) fn replace_rpc(&self, rpc: Rpc) -> bool {
+        let replaced = match self.rpcs.write().iter_mut().find(|existing| existing.rpc.url == rpc.url) {
+            Some(existing) => {
+                existing.rpc = rpc;
+                true
+            }
+            None => false,
+        };
+        if replaced {
+            self.configure_spend();
+        }
+        replaced
+    }
+
+    /// Give the endpoint at `from` the URL `to`, moving everything learned about it along.
+    pub(crate) async fn move_endpoint(&self, from: &str, to: url::Url) {
+        fn rename<V>(map: &mut HashMap<String, V>, from: &str, to: &str) {
+            if let Some(value) = map.remove(from) {
+                map.insert(to.to_string(), value);
+            }
+        }
+
+        if let Some(tracked) = self.rpcs.write().iter_mut().find(|tracked| tracked.rpc.url.as_str() == from) {
+            tracked.rpc.url = to.clone();
+        }
+        let to = to.as_str();
+        rename(&mut *self.latencies.write().await, from, to);
+        rename(&mut *self.lagging.write().await, from, to);
+        rename(&mut self.head_lags.lock(), from, to);
+        rename(&mut self.probed_at.lock(), from, to);
+        rename(&mut self.custom_probe_outcomes.lock(), from, to);
+        rename(&mut *self.client_versions.write().await, from, to);
+        rename(&mut *self.capabilities.write().await, from, to);
+        rename(&mut *self.failure_counts.write().await, from, to);
+        rename(&mut self.malformed_counts.lock(), from, to);
+        rename(&mut *self.cooldowns.write().await, from, to);
+        rename(&mut self.last_updated.lock(), from, to);
+        self.liveness.rename(from, to);
+        self.spend.rename(from, to);
+    }
+
+    /// Remove an endpoint and forget everything learned about it.
+    ///
+    /// Removing the active provider takes effect at the next `refresh`. Returns `false` if
+    /// no endpoint had that URL.
+    pub async fn remove_rpc(&self, url: &str) -> bool {
+        let url = normalize_url(url);
+        let removed = {
+            let mut rpcs = self.rpcs.write();
+            let before = rpcs.len();
+            rpcs.retain(|tracked| tracked.rpc.url.as_str() != url);
+            rpcs.len() != before
+        };
+        if removed {
+            self.configure_spend();
+            self.collect_garbage().await;
+        }
+        removed
+    }
+
+    /// Where each endpoint came from, when it was last seen up and how much of the last
+    /// `REPORTED_UPTIME_WINDOW` it was up, in the order the endpoints were added.
+    pub fn rpc_provenance(&self) -> Vec<RpcProvenance> {
+        let now = self.clock.now_system();
+        let tracked = self.rpcs.read().clone();
+        let reported = self.reported_chain_ids();
+        tracked
+            .iter()
+            .map(|TrackedRpc { rpc, origin, listed_under }| RpcProvenance {
+                url: self.redact(rpc.url.as_str()),
+                origin: *origin,
+                listed_under: *listed_under,
+                reported_chain_id: reported.get(rpc.url.as_str()).copied(),
+                last_healthy: self.liveness.last_healthy(rpc.url.as_str()),
+                uptime: self.liveness.uptime(rpc.url.as_str(), REPORTED_UPTIME_WINDOW, now),
+            })
+            .collect()
+    }
+
+    /// The chain id each endpoint last reported, by URL. Endpoints are only asked while the
+    /// network has aliases, from `HandlerSettings::chain_aliases` or the registry.
+    pub fn reported_chain_ids(&self) -> HashMap<String, NetworkId> {
+        self.reported_chain_ids.lock().clone()
+    }
+
+    /// Ask every endpoint which chain it serves when the network has aliases, recording the
+    /// answers. An id outside the alias group means two different chains were aliased, so
+    /// this fails with `ChainAliasMismatch` rather than serve from a mixed set. Endpoints that
+    /// don't answer are left to the probes.
+    async fn verify_chain_ids(&self) -> Result<()> {
+        let config = self.config();
+        if config.chain_aliases.is_empty() {
+            return Ok(());
+        }
+        let mut accepted = config.chain_aliases.clone();
+        accepted.push(self.network_id);
+        accepted.sort();
+
+        let request = JsonRpcRequest::new("eth_chainId", serde_json::json!([]));
+        let rpcs = self.rpcs();
+        let answers = futures::future::join_all(rpcs.iter().map(|rpc| async {
+            let answer = self.side_call(&self.client, rpc.url.as_str(), &request, TrafficClass::Probe).await;
+            (rpc.url.to_string(), answer.as_ref().and_then(parse_quantity))
+        }))
+        .await;
+
+        let mut mismatch = None;
+        {
+            let mut reported = self.reported_chain_ids.lock();
+            for (url, chain_id) in answers {
+                let Some(chain_id) = chain_id else { continue };
+                if !accepted.contains(&chain_id) && mismatch.is_none() {
+                    mismatch = Some((url.clone(), chain_id));
+                }
+                reported.insert(url, chain_id);
+            }
+        }
+        match mismatch {
+            Some((url, reported)) => Err(RpcHandlerError::ChainAliasMismatch { url: self.redact(&url), reported, accepted }),
+            None => Ok(()),
+        }
+    }
+
+    /// The share of the last `window` that `url` was up, going by this handler's probes, pings
+    /// and requests. `None` before the first of them.
+    pub fn uptime(&self, url: &str, window: Duration) -> Option<f64> {
+        self.liveness.uptime(&normalize_url(url), window, self.clock.now_system())
+    }
+
+    /// The last `RECENT_FAILURES_LEN` failed attempts across all endpoints, oldest first. URLs
+    /// and messages aren't redacted.
+    pub fn recent_failures(&self) -> Vec<AttemptFailure> {
+        self.liveness.recent_failures()
+    }
+
+    /// The journal under `HandlerComponents::failure_journal`, redacting with the current config.
+    pub(crate) fn journal(&self) -> Option<FailureJournal> {
+        self.journal.as_ref().map(|journal| journal.with_redactor(self.config().redactor.clone()))
+    }
+
+    /// The failures journaled at or after `since`, aggregated, once everything queued is
+    /// written. Empty without `HandlerComponents::failure_journal`.
+    pub async fn failure_summary(&self, since: SystemTime) -> io::Result<FailureSummary> {
+        let Some(journal) = self.journal.clone() else { return Ok(summarize(&[], since)) };
+        journal.flush().await;
+        let entries = runtime::spawn_blocking(move || journal.entries()).await.map_err(io::Error::other)??;
+        Ok(summarize(&entries, since))
+    }
+
+    /// The request counters as they stand. Diff two snapshots for rates over the time between.
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            spend: self.spend.report_spend(),
+            cache: self.response_cache.stats(),
+            provenance: self.rpc_provenance(),
+            ..self.metrics.snapshot(self.clock.now_system())
+        }
+    }
+
+    /// Zero the request counters. They are never reset otherwise.
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    #[cfg(feature = "abi")]
+    pub(crate) fn multicall3_deployments(&self) -> &Deployments {
+        &self.multicall3
+    }
+
+    pub(crate) fn liveness_log(&self) -> &LivenessLog {
+        &self.liveness
+    }
+
+    /// Replay a `sample_rate` fraction of successful idempotent requests against `rpc`, to see
+    /// how it would answer production traffic before adding it. `rpc` serves nothing.
+    ///
+    /// Adding a URL that is already shadowed starts its tallies over. Replays are sent with
+    /// `rpc.headers`, charged by `rpc.cost_profile` and skipped once it can't afford them. Fails
+    /// on a header HTTP doesn't allow, or without a `url`, as templates aren't filled in here.
+    pub fn add_shadow_rpc(&self, rpc: impl Into<RpcConfig>, sample_rate: f64) -> Result<()> {
+        let rpc = rpc.into();
+        let Some(url) = rpc.url else {
+            return Err(RpcHandlerError::InvalidRpcConfig { detail: "a shadow endpoint needs a `url`".to_string() });
+        };
+        let headers = rpc.headers.as_ref().map(header_map).transpose()?;
+        self.shadows.insert(url.to_string(), headers, sample_rate, self.http_client()?, self.spend.clone(), rpc.cost_profile);
         Ok(())
     }
 
+    /// Stop shadowing `url`, returning how it compared. Replays already under way still finish.
+    pub fn remove_shadow_rpc(&self, url: &str) -> Option<ShadowReport> {
+        self.shadows.remove(url).map(|report| self.redact_shadow_report(report))
+    }
+
+    /// How each shadow endpoint compared with production so far, by URL.
+    pub fn shadow_reports(&self) -> Vec<ShadowReport> {
+        self.shadows.reports().into_iter().map(|report| self.redact_shadow_report(report)).collect()
+    }
+
+    fn redact_shadow_report(&self, report: ShadowReport) -> ShadowReport {
+        ShadowReport { url: self.config().redactor.redact(&report.url), ..report }
+    }
+
+    pub(crate) fn cooldowns(&self) -> &Cooldowns {
+        &self.cooldowns
+    }
+
+    pub(crate) fn host_resolver(&self) -> &Arc<dyn HostResolver> {
+        &self.host_resolver
+    }
+
+    /// `text` with the secrets in templated endpoint URLs put back as placeholders.
+    pub fn redact(&self, text: &str) -> String {
+        self.config().redactor.redact(text)
+    }
+
+    /// When `url`'s state last changed, on the wall clock.
+    pub(crate) fn updated_at(&self, url: &str) -> Option<SystemTime> {
+        let at = *self.last_updated.lock().get(url)?;
+        self.clock.now_system().checked_sub(self.clock.now_instant().saturating_duration_since(at))
+    }
+
+    pub(crate) async fn failure_counts(&self) -> HashMap<String, u32> {
+        self.failure_counts.read().await.clone()
+    }
+
+    fn touch(&self, url: &str) {
+        self.last_updated.lock().insert(url.to_string(), self.clock.now_instant());
+    }
+
+    /// Drop state for URLs that are neither configured nor routed to, then cap every map at
+    /// its `MemoryLimits` entry count. See `memory` for what is protected from eviction.
+    pub async fn collect_garbage(&self) {
+        let limits = self.config().settings.memory_limits;
+        let now = self.clock.now_instant();
+        let active_url = self.provider.read().await.as_ref().map(|p| p.base_url.clone());
+        let active = active_url.as_deref();
+
+        let mut known: HashSet<String> = self.rpcs.read().iter().map(|tracked| tracked.rpc.url.to_string()).collect();
+        known.extend(self.config().routes.iter().flat_map(|rule| rule.normalized_urls()));
+        if let Some(url) = active {
+            known.insert(url.to_string());
+        }
+        let known_hosts: HashSet<String> = known.iter().filter_map(|url| host_of(url)).collect();
+
+        let last_updated = {
+            let mut last_updated = self.last_updated.lock();
+            last_updated.retain(|url, _| known.contains(url));
+            evict_to_capacity(&mut last_updated, limits.max_endpoint_entries, |url, _| Some(url) == active, |_, at| *at);
+            last_updated.clone()
+        };
+        let updated_at = |url: &str| last_updated.get(url).copied();
+
+        for map in [&self.latencies, &self.lagging] {
+            let mut map = map.write().await;
+            map.retain(|url, _| known.contains(url));
+            evict_to_capacity(&mut map, limits.max_latency_entries, |url, _| Some(url) == active, |url, latency| (updated_at(url), Reverse(*latency)));
+        }
+        {
+            let mut map = self.client_versions.write().await;
+            map.retain(|url, _| known.contains(url));
+            evict_to_capacity(&mut map, limits.max_endpoint_entries, |url, _| Some(url) == active, |url, _| updated_at(url));
+        }
+        {
+            let mut map = self.capabilities.write().await;
+            map.retain(|url, _| known.contains(url));
+            evict_to_capacity(&mut map, limits.max_endpoint_entries, |url, _| Some(url) == active, |url, _| updated_at(url));
+        }
+        {
+            let mut map = self.failure_counts.write().await;
+            map.retain(|url, _| known.contains(url));
+            evict_to_capacity(&mut map, limits.max_endpoint_entries, |url, _| Some(url) == active, |url, _| updated_at(url));
+        }
+        self.head_lags.lock().retain(|url, _| known.contains(url));
+        self.probed_at.lock().retain(|url, _| known.contains(url));
+        self.custom_probe_outcomes.lock().retain(|url, _| known.contains(url));
+        {
+            let mut map = self.malformed_counts.lock();
+            map.retain(|url, _| known.contains(url));
+            evict_to_capacity(&mut map, limits.max_endpoint_entries, |url, _| Some(url) == active, |url, _| updated_at(url));
+        }
+        {
+            let mut map = self.cooldowns.write().await;
+            map.retain(|url, cd| known.contains(url) || cd.until > now);
+            evict_to_capacity(&mut map, limits.max_cooldown_entries, |_, cd| cd.until > now, |_, cd| cd.until);
+        }
+
+        self.probe_schedule.compact(limits.max_negative_entries, now, |url| known.contains(url));
+        self.agreement.retain(&known);
+        self.liveness.retain(&known);
+        if let Some(resolver) = &self.resolver {
+            let active_host = active.and_then(host_of);
+            resolver.compact(limits.max_negative_entries, |host| known_hosts.contains(host), active_host.as_deref());
+        }
+    }
+
+    /// Entry counts for every per-endpoint structure this handler keeps.
+    pub async fn memory_report(&self) -> MemoryReport {
+        let latencies = self.latencies.read().await.len();
+        let lagging = self.lagging.read().await.len();
+        let client_versions = self.client_versions.read().await.len();
+        let capabilities = self.capabilities.read().await.len();
+        let failure_counts = self.failure_counts.read().await.len();
+        let cooldowns = self.cooldowns.read().await.len();
+
+        MemoryReport {
+            endpoints: self.rpcs.read().len(),
+            latencies,
+            lagging,
+            client_versions,
+            capabilities,
+            failure_counts,
+            malformed_counts: self.malformed_counts.lock().len(),
+            last_updated: self.last_updated.lock().len(),
+            cooldowns,
+            probe_skips: self.probe_schedule.len(),
+            dns_pins: self.resolver.as_ref().map_or(0, PinningResolver::pin_count),
+            dns_dead_hosts: self.resolver.as_ref().map_or(0, PinningResolver::dead_host_count),
+        }
+    }
+
+    /// Stop background work such as the keepalive loop. Requests still work afterwards.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+        self.keepalive_task.lock().take();
+        self.auto_refresh_task.lock().take();
+        self.maintenance_task.lock().take();
+        self.recovery_task.lock().take();
+        self.slo_task.lock().take();
+        self.spend_task.lock().take();
+        self.agreement_task.lock().take();
+        self.tuner_task.lock().take();
+        self.rotation_task.lock().take();
+        if let Some(sweep) = self.sweep_task.lock().take() {
+            sweep.abort();
+        }
+    }
+
+    /// Abort one background loop as if it had died, leaving the rest running. Returns `false`
+    /// if it wasn't running.
+    #[cfg(feature = "test-util")]
+    pub async fn abort_background_task(&self, task: BackgroundTask) -> bool {
+        let slot = match task {
+            BackgroundTask::Keepalive => &self.keepalive_task,
+            BackgroundTask::AutoRefresh => &self.auto_refresh_task,
+            BackgroundTask::MaintenanceWatch => &self.maintenance_task,
+            BackgroundTask::SloWatch => &self.slo_task,
+            BackgroundTask::BudgetWatch => &self.spend_task,
+            BackgroundTask::AgreementSampler => &self.agreement_task,
+            BackgroundTask::RetryTuner => &self.tuner_task,
+            BackgroundTask::RotationWatch => &self.rotation_task,
+            BackgroundTask::Recovery => &self.recovery_task,
+        };
+        let Some(handle) = slot.lock().take() else { return false };
+        handle.abort();
+        let _ = handle.await;
+        true
+    }
+
+    /// Feed a keepalive outcome into the endpoint's failure count.
+    ///
+    /// Failures are logged at debug level only; reaching `KEEPALIVE_DEMOTE_AFTER` consecutive
+    /// failures re-selects the active provider once.
+    pub(crate) async fn record_keepalive(self: &Arc<Self>, url: &str, result: Result<()>) {
+        self.touch(url);
+        self.liveness.record_attempt(url, result.as_ref().err(), self.clock.now_system());
+        let failures = {
+            let mut counts = self.failure_counts.write().await;
+            match result {
+                Ok(()) => {
+                    counts.remove(url);
+                    return;
+                }
+                Err(_) => {
+                    let count = counts.entry(url.to_string()).or_insert(0);
+                    *count += 1;
+                    *count
+                }
+            }
+        };
+
+        self.log("debug", "Keepalive ping failed", Some(serde_json::json!({ "url": url, "failures": failures }))).await;
+        if failures == KEEPALIVE_DEMOTE_AFTER {
+            self.log("warn", "Active provider keeps failing keepalive, re-selecting", Some(serde_json::json!({ "url": url }))).await;
+            if let Err(e) = self.refresh().await {
+                self.log("warn", "Re-selection after keepalive failures failed", Some(serde_json::json!({ "error": e.to_string() }))).await;
+            }
+        }
+    }
+
     pub async fn get_provider(&self) -> Result<RetryProvider> {
         let provider_lock = self.provider.read().await;
         provider_lock
@@ -107,68 +1105,997 @@ impl RpcHandler {
         self.latencies.read().await.clone()
     }
 
+    /// Latencies consensus requests for heavy methods observed per endpoint. They are kept apart
+    /// so a slow `eth_getLogs` doesn't push an endpoint down the ordering.
+    pub fn heavy_method_latencies(&self) -> HashMap<String, u64> {
+        self.heavy_latencies.snapshot()
+    }
+
+    /// Move `url`'s measured latency toward a `method` request it answered in `elapsed`, or put
+    /// the observation in the heavy bucket for a heavy method. Returns whether it was kept: an
+    /// endpoint the last probe didn't find healthy waits for the next probe.
+    #[cfg(feature = "consensus")]
+    pub(crate) async fn observe_latency(&self, url: &str, method: &str, elapsed: Duration) -> bool {
+        if methods::is_heavy(method) {
+            self.heavy_latencies.observe(url, elapsed);
+            return true;
+        }
+        let observed = elapsed.as_millis() as u64;
+        for measured in [&self.latencies, &self.lagging] {
+            if let Some(latency) = measured.write().await.get_mut(url) {
+                *latency = observed::blend(*latency, observed);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// `get_latencies` as records sorted by `by` in `order`, ties by URL.
+    pub async fn latencies_sorted(&self, by: SortBy, order: Order) -> Vec<(String, LatencyRecord)> {
+        let failure_counts = self.failure_counts().await;
+        let now = self.clock.now_system();
+        let mut records: Vec<(String, LatencyRecord)> = self
+            .latencies
+            .read()
+            .await
+            .iter()
+            .map(|(url, latency_ms)| {
+                let record = LatencyRecord {
+                    latency_ms: *latency_ms,
+                    last_tested: self.updated_at(url).unwrap_or(now),
+                    failure_count: failure_counts.get(url).copied().unwrap_or(0),
+                };
+                (url.clone(), record)
+            })
+            .collect();
+        sort_latencies(&mut records, by, order);
+        records
+    }
+
+    /// Endpoints that passed their last probe, at the head or behind it, sorted.
+    pub(crate) async fn healthy_urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = self.latencies.read().await.keys().chain(self.lagging.read().await.keys()).cloned().collect();
+        urls.sort();
+        urls.dedup();
+        urls
+    }
+
     pub async fn refresh(self: &Arc<Self>) -> Result<()> {
+        // A deliberate refresh starts a new session as far as head monotonicity goes
+        self.heads.reset();
+        self.verify_chain_ids().await?;
         match self.strategy {
-            Strategy::Fastest => {
-                let (fastest, latencies) = get_fastest(&self.rpcs, self.config.settings.rpc_timeout).await?;
+            Strategy::Fastest | Strategy::FastStart => {
+                let (fastest, latencies, lagging) = self.measure_fastest().await?;
+                let fastest = self.damp_switch(fastest, &latencies).await;
                 
                 if let Some(fastest_url) = fastest {
                     {
                         let mut latencies_lock = self.latencies.write().await;
                         *latencies_lock = latencies;
                     }
+                    *self.lagging.write().await = lagging;
+                    self.save_snapshot().await;
+                    self.install_provider(fastest_url).await?;
                     
-                    let provider = self.build_provider(fastest_url).await?;
-                    {
-                        let mut provider_lock = self.provider.write().await;
-                        *provider_lock = Some(provider);
-                    }
-                    
-                    self.log("info", "Refreshed fastest provider", None).await;
+                    self.log("info", "Refreshed fastest provider", self.probe_timeouts_log()).await;
                 } else {
-                    self.log("warn", "No fastest provider found", None).await;
+                    self.log("warn", "No fastest provider found", self.probe_timeouts_log()).await;
                 }
             }
             Strategy::FirstHealthy => {
-                let first_healthy = get_first_healthy(&self.rpcs, self.config.settings.rpc_timeout, Some(false)).await?;
+                let first_healthy = get_first_healthy(&self.transport_factory()?, &self.rpcs(), self.config().settings.rpc_timeout, Some(false)).await?;
                 
                 if let Some(url) = first_healthy {
-                    let provider = self.build_provider(url).await?;
-                    {
-                        let mut provider_lock = self.provider.write().await;
-                        *provider_lock = Some(provider);
-                    }
+                    self.install_provider(url).await?;
                     
                     self.log("info", "Refreshed first healthy provider", None).await;
                 } else {
                     self.log("warn", "No healthy provider found", None).await;
                 }
             }
+            // Nothing is measured, so there is nothing to refresh
+            Strategy::StaticOrder(_) => {}
         }
         
+        self.collect_garbage().await;
+        Ok(())
+    }
+
+    /// Check the client's network location again, e.g. when the app sees connectivity change.
+    ///
+    /// If it differs from where the latencies were measured, the snapshot taken at the new
+    /// location is restored, or every endpoint is probed afresh when there is none, and a
+    /// `LocationChanged` event is emitted. Returns whether the location changed.
+    pub async fn location_changed(self: &Arc<Self>) -> Result<bool> {
+        let current = self.locate();
+        let previous = std::mem::replace(&mut *self.location.lock(), current.clone());
+        if previous == current {
+            return Ok(false);
+        }
+        let restored = self.restore_snapshot().await?;
+        if !restored {
+            self.refresh().await?;
+        }
+        self.emit(HandlerEvent::LocationChanged { from: previous, to: current, restored });
+        Ok(true)
+    }
+
+    /// Key of the network location the latencies were measured or restored at, `None` if it
+    /// couldn't be told.
+    pub fn location_key(&self) -> Option<String> {
+        self.location.lock().clone()
+    }
+
+    fn locate(&self) -> Option<String> {
+        self.location_provider.fingerprint().map(|fingerprint| location_key(&fingerprint))
+    }
+
+    /// Serve through the fastest endpoint of the snapshot taken at the current location, without
+    /// probing. `false` when there is no snapshot for a configured endpoint, or the strategy
+    /// doesn't go by latency.
+    async fn restore_snapshot(self: &Arc<Self>) -> Result<bool> {
+        if matches!(self.strategy, Strategy::FirstHealthy | Strategy::StaticOrder(_)) {
+            return Ok(false);
+        }
+        let (Some(store), Some(location)) = (&self.latency_store, self.location_key()) else { return Ok(false) };
+        let Some(snapshot) = store.load(self.network_id, &location, self.clock.now_system()) else { return Ok(false) };
+        let redactor = self.config().redactor.clone();
+        let latencies: LatencyMap = self
+            .rpcs()
+            .iter()
+            .filter_map(|rpc| snapshot.latencies.get(&redactor.redact(rpc.url.as_str())).map(|latency| (rpc.url.to_string(), *latency)))
+            .collect();
+        let Some(fastest) = self.pick_fastest(&latencies) else { return Ok(false) };
+
+        let endpoints = latencies.len();
+        *self.latencies.write().await = latencies;
+        self.lagging.write().await.clear();
+        self.install_provider(fastest).await?;
+        self.log("info", "Restored latency snapshot", Some(serde_json::json!({ "location": location, "endpoints": endpoints }))).await;
+        Ok(true)
+    }
+
+    /// Record the current latencies under the current location, URLs redacted.
+    async fn save_snapshot(&self) {
+        let (Some(store), Some(location)) = (&self.latency_store, self.location_key()) else { return };
+        let redactor = self.config().redactor.clone();
+        let latencies = self.latencies.read().await.iter().map(|(url, latency)| (redactor.redact(url), *latency)).collect();
+        let snapshot = LatencySnapshot { latencies, recorded_at: self.clock.now_system() };
+        if let Err(e) = store.save(self.network_id, &location, snapshot) {
+            self.log("warn", "Could not save latency snapshot", Some(serde_json::json!({ "error": e.to_string() }))).await;
+        }
+    }
+
+    /// Make `url` the active provider, settling the init state on it.
+    pub(crate) async fn install_provider(self: &Arc<Self>, url: String) -> Result<()> {
+        self.install_provider_as(url.clone(), InitState::Final { url }).await
+    }
+
+    /// Swap the active provider in one write, so in-flight lookups see either the old or the new one.
+    async fn install_provider_as(self: &Arc<Self>, url: String, state: InitState) -> Result<()> {
+        let provider = self.build_provider_for(url, &state).await?;
+        *self.provider.write().await = Some(provider);
+        self.set_init_state(state);
         Ok(())
     }
 
+    /// Where provider selection stands; see `InitState`.
+    pub fn init_state(&self) -> InitState {
+        self.init_state.read().clone()
+    }
+
+    fn set_init_state(&self, state: InitState) {
+        let mut current = self.init_state.write();
+        if *current != state {
+            let previous = std::mem::replace(&mut *current, state.clone());
+            drop(current);
+            self.emit(HandlerEvent::InitStateChanged(state.clone()));
+            if let (InitState::Degraded { url: from }, InitState::Final { url: to }) = (previous, state) {
+                self.emit(HandlerEvent::Recovered { from, to });
+            }
+        }
+    }
+
+    /// The retry, failover and timeout behavior this handler runs with, its strategy included.
+    pub fn effective_policy(&self) -> EffectivePolicy {
+        self.config().effective_policy_with(&self.strategy)
+    }
+
+    /// Receive lifecycle events emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<HandlerEvent> {
+        self.events.subscribe()
+    }
+
+    /// The last `EVENT_HISTORY_LEN` events emitted, oldest first, whether or not anything was subscribed.
+    pub fn recent_events(&self) -> Vec<RecordedEvent> {
+        self.event_history.events()
+    }
+
+    pub(crate) fn emit(&self, event: HandlerEvent) {
+        self.event_history.record(self.clock.now_system(), event.clone());
+        // No subscribers is not an error
+        let _ = self.events.send(event);
+    }
+
+    /// Measure all RPCs and pick the fastest, constrained to the lowest healthy tier under `TierStrict`.
+    ///
+    /// Also returns the endpoints that were healthy but out of sync, which stay usable for
+    /// methods that don't depend on chain state.
+    ///
+    /// Endpoints the probe schedule is skipping are left out entirely.
+    async fn measure_fastest(&self) -> Result<(Option<String>, LatencyMap, LatencyMap)> {
+        let (latencies, lagging) = self.probe(&self.sweep_targets()).await?;
+        *self.last_full_sweep.lock() = Some(self.clock.now_system());
+        Ok((self.pick_fastest(&latencies), latencies, lagging))
+    }
+
+    /// Probe options from the settings, with `timeout_policy`.
+    pub(crate) fn measure_options(&self, timeout_policy: TimeoutPolicy) -> MeasureOptions {
+        let settings = &self.config().settings;
+        MeasureOptions {
+            follow_redirects: settings.follow_post_redirects,
+            host_limiter: self.host_limiter.clone(),
+            max_concurrent_probes: settings.max_concurrent_probes,
+            sweep_deadline: settings.probe_sweep_deadline,
+            spend: Some(self.spend.clone()),
+            // A local node is the only one there is, so there is no head to be in sync with
+            max_lag_blocks: if settings.localnet { u64::MAX } else { settings.max_probe_lag_blocks },
+            check_bytecode: !settings.localnet,
+            custom_probes: settings.custom_probes.clone(),
+            custom_probe_policy: settings.custom_probe_policy,
+            ..MeasureOptions::new(timeout_policy)
+        }
+    }
+
+    /// Probe `rpcs`, returning the in-sync latencies and the lagging ones.
+    async fn probe(&self, rpcs: &[Rpc]) -> Result<(LatencyMap, LatencyMap)> {
+        let now = self.clock.now_instant();
+        let panicked: Arc<parking_lot::Mutex<Vec<RpcHandlerError>>> = Arc::default();
+        let options = MeasureOptions {
+            on_panic: Some({
+                let panicked = Arc::clone(&panicked);
+                Arc::new(move |error| panicked.lock().push(error))
+            }),
+            ..self.measure_options(self.probe_timeout_policy().await)
+        };
+        *self.probe_timeouts.lock() = Some(options.timeout_policy.timeouts());
+
+        let (latencies, results) = measure_rpcs_with_transport(&self.transport_factory()?, rpcs, &options).await?;
+        for error in std::mem::take(&mut *panicked.lock()) {
+            self.record_panic(&error);
+        }
+        self.pin_measured_ips(&results, &latencies);
+        self.schedule_probe_results(&results, now);
+        self.record_probe_history(&results);
+        self.check_probe_timestamps(&results);
+        self.record_probe_liveness(&results);
+        self.record_head_lags(&results);
+        self.record_custom_probes(&results);
+        self.record_probed_at(&results);
+        for url in latencies.keys() {
+            self.touch(url);
+        }
+        let lagging = lagging_latencies(&results, &latencies);
+
+        Ok((latencies, lagging))
+    }
+
+    /// Keep each probed endpoint's head lag, forgetting it for those that reported no block.
+    fn record_head_lags(&self, results: &[RpcCheckResult]) {
+        let mut head_lags = self.head_lags.lock();
+        for result in results {
+            match result.head_lag {
+                Some(lag) => head_lags.insert(result.url.clone(), lag),
+                None => head_lags.remove(&result.url),
+            };
+        }
+    }
+
+    /// Keep each probed endpoint's custom probe outcomes, replacing the previous probe's.
+    fn record_custom_probes(&self, results: &[RpcCheckResult]) {
+        let mut outcomes = self.custom_probe_outcomes.lock();
+        for result in results {
+            match result.custom_probes.is_empty() {
+                true => outcomes.remove(&result.url),
+                false => outcomes.insert(result.url.clone(), result.custom_probes.clone()),
+            };
+        }
+    }
+
+    /// Blocks each endpoint trailed the most common head by at its last probe.
+    pub fn head_lags(&self) -> HashMap<String, u64> {
+        self.head_lags.lock().clone()
+    }
+
+    fn record_probed_at(&self, results: &[RpcCheckResult]) {
+        let now = self.clock.now_system();
+        self.probed_at.lock().extend(results.iter().map(|result| (result.url.clone(), now)));
+    }
+
+    /// When each endpoint was last probed, on the wall clock. A smeared auto-refresh moves these
+    /// one slot at a time rather than all at once.
+    pub fn probe_freshness(&self) -> HashMap<String, SystemTime> {
+        self.probed_at.lock().clone()
+    }
+
+    /// Record each probe as an up or down outcome. A probe the endpoint's budget couldn't afford
+    /// was never sent, so it records nothing.
+    fn record_probe_liveness(&self, results: &[RpcCheckResult]) {
+        let now = self.clock.now_system();
+        let unaffordable = self.spend.exhausted();
+        for result in results.iter().filter(|result| !unaffordable.contains(&result.url)) {
+            self.liveness.record(&result.url, result.success, now);
+        }
+    }
+
+    /// Hold each probed head block to `HandlerSettings::timestamp_sanity`.
+    fn check_probe_timestamps(&self, results: &[RpcCheckResult]) {
+        let Some(sanity) = self.config().settings.timestamp_sanity else { return };
+        let now = self.clock.now_system();
+        for result in results {
+            if let Some(timestamp) = result.block_timestamp {
+                self.timestamps.check(&sanity, &result.url, timestamp, true, now);
+            }
+        }
+    }
+
+    /// The fastest of `latencies`, constrained to the lowest tier under `TierStrict`, with
+    /// `HandlerSettings::head_lag_penalty_ms` added per block of probed head lag and the
+    /// `region_affinity` penalty for endpoints outside the preferred region. Endpoints the
+    /// timestamp checks or agreement sampling flagged are only picked when nothing else is left.
+    fn pick_fastest(&self, latencies: &LatencyMap) -> Option<String> {
+        let flagged = self.timestamps.flags();
+        let dishonest = self.agreement.flagged();
+        let penalty = self.config().settings.head_lag_penalty_ms;
+        let head_lags = self.head_lags();
+        let affinity = self.config().settings.region_affinity;
+        let regions = self.regions();
+        let preferred = affinity.and_then(|policy| preferred_region(&policy, latencies, &regions));
+        let scored = |(url, &latency): (&String, &u64)| {
+            let lag = head_lags.get(url).copied().unwrap_or(0);
+            let away = affinity.map_or(0, |policy| region_penalty(&policy, url, &regions, preferred));
+            (url.clone(), latency.saturating_add(lag.saturating_mul(penalty)).saturating_add(away))
+        };
+        let trusted: LatencyMap = latencies
+            .iter()
+            .filter(|(url, _)| !flagged.contains_key(*url) && !dishonest.contains(*url))
+            .map(scored)
+            .collect();
+        let all: LatencyMap = latencies.iter().map(scored).collect();
+        let latencies = if trusted.is_empty() { &all } else { &trusted };
+        match self.config().failover_policy {
+            FailoverPolicy::Latency => pick_fastest(latencies),
+            FailoverPolicy::TierStrict => pick_fastest_in_lowest_tier(latencies, &self.config().tiers),
+        }
+    }
+
+    /// `fastest`, unless `HandlerSettings::region_affinity` keeps the active provider: while it
+    /// is measured in `latencies` and nothing flagged it, it only gives way to an endpoint faster
+    /// by more than the `region::switch_margin` between their regions, or in a lower tier under
+    /// `TierStrict`.
+    async fn damp_switch(&self, fastest: Option<String>, latencies: &LatencyMap) -> Option<String> {
+        let Some(policy) = self.config().settings.region_affinity else { return fastest };
+        let candidate = fastest?;
+        let Some(active) = self.provider.read().await.as_ref().map(|provider| provider.base_url.clone()) else { return Some(candidate) };
+        let (Some(&active_latency), Some(&candidate_latency)) = (latencies.get(&active), latencies.get(&candidate)) else { return Some(candidate) };
+        if active == candidate || self.timestamps.flags().contains_key(&active) || self.agreement.flagged().contains(&active) {
+            return Some(candidate);
+        }
+        let tiers = self.config().tiers.clone();
+        let tier = |url: &str| tier_of(&tiers, url);
+        if self.config().failover_policy == FailoverPolicy::TierStrict && tier(&candidate) < tier(&active) {
+            return Some(candidate);
+        }
+
+        let regions = self.regions();
+        let margin = switch_margin(&policy, regions.get(&active).copied(), regions.get(&candidate).copied());
+        if active_latency.saturating_sub(candidate_latency) > margin {
+            return Some(candidate);
+        }
+        self.log("debug", "Kept provider within the region switch margin", Some(serde_json::json!({
+            "active": active,
+            "fastest": candidate,
+            "active_latency_ms": active_latency,
+            "fastest_latency_ms": candidate_latency,
+            "margin_ms": margin,
+        }))).await;
+        Some(active)
+    }
+
+    /// Region of each endpoint whose region is known, from `HandlerSettings::region_hints` or
+    /// read off its hostname.
+    pub fn regions(&self) -> HashMap<String, Region> {
+        let hints = &self.config().settings.region_hints;
+        self.rpcs().iter().filter_map(|rpc| region_of(rpc.url.as_str(), hints).map(|region| (rpc.url.to_string(), region))).collect()
+    }
+
+    /// Endpoints a sweep starting now would probe: those the probe schedule isn't skipping.
+    pub(crate) fn sweep_targets(&self) -> Vec<Rpc> {
+        self.probed_rpcs(self.clock.now_instant())
+    }
+
+    /// Probe part of an incremental sweep and merge the results into the latency maps,
+    /// leaving every endpoint outside `rpcs` as it was.
+    ///
+    /// Sync is judged within the part only, since the chain moves on between ticks.
+    pub(crate) async fn probe_partial(&self, rpcs: &[Rpc]) {
+        if rpcs.is_empty() {
+            return;
+        }
+        let (latencies, lagging) = match self.probe(rpcs).await {
+            Ok(measured) => measured,
+            Err(e) => {
+                self.log("warn", "Incremental probe failed", Some(serde_json::json!({ "error": e.to_string() }))).await;
+                return;
+            }
+        };
+        let probed: HashSet<String> = rpcs.iter().map(|rpc| rpc.url.to_string()).collect();
+        {
+            let mut current = self.latencies.write().await;
+            current.retain(|url, _| !probed.contains(url));
+            current.extend(latencies);
+        }
+        let mut current = self.lagging.write().await;
+        current.retain(|url, _| !probed.contains(url));
+        current.extend(lagging);
+    }
+
+    /// Close an incremental sweep: note its completion and switch to the fastest endpoint if
+    /// the active one is no longer it.
+    pub(crate) async fn finish_incremental_sweep(self: &Arc<Self>) {
+        *self.last_full_sweep.lock() = Some(self.clock.now_system());
+        self.save_snapshot().await;
+        let latencies = self.latencies.read().await.clone();
+        let fastest = self.damp_switch(self.pick_fastest(&latencies), &latencies).await;
+        let active = self.provider.read().await.as_ref().map(|provider| provider.base_url.clone());
+
+        // A degraded handler settles even on the endpoint it was already serving through
+        if let Some(url) = fastest
+            && (active.as_ref() != Some(&url) || matches!(self.init_state(), InitState::Degraded { .. }))
+        {
+            match self.install_provider(url.clone()).await {
+                Ok(()) => self.log("info", "Auto-refresh switched provider", Some(serde_json::json!({ "from": active, "to": url }))).await,
+                Err(e) => self.log("warn", "Auto-refresh failed to switch provider", Some(serde_json::json!({ "error": e.to_string() }))).await,
+            }
+        }
+        self.collect_garbage().await;
+    }
+
+    /// Proxied requests currently in flight, counted from the first attempt to the final answer.
+    pub fn requests_in_flight(&self) -> usize {
+        self.in_flight.current()
+    }
+
+    /// The chain data in this handler's `DataScope`.
+    pub fn chain_data(&self) -> &ChainView {
+        &self.chain_data
+    }
+
+    /// Where this handler's endpoints beyond the configured ones come from.
+    pub fn rpc_source(&self) -> &dyn RpcSource {
+        self.rpc_source.as_ref()
+    }
+
+    pub(crate) fn hold_state(&self) -> &HoldState {
+        &self.hold
+    }
+
+    pub(crate) fn heartbeats(&self) -> &Heartbeats {
+        &self.heartbeats
+    }
+
+    pub(crate) fn shutdown_token(&self) -> &CancellationToken {
+        &self.shutdown
+    }
+
+    pub(crate) fn record_refresh_deferral(&self) {
+        self.refresh_deferrals.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Configured endpoints the probe schedule isn't skipping at `now`, outside maintenance and
+    /// with spend budget left.
+    fn probed_rpcs(&self, now: Instant) -> Vec<Rpc> {
+        let maintenance = self.maintenance();
+        let over_budget = self.spend.exhausted();
+        let now_system = self.clock.now_system();
+        self.rpcs()
+            .into_iter()
+            .filter(|rpc| !self.probe_schedule.should_skip(rpc.url.as_str(), now))
+            .filter(|rpc| maintenance.in_window_until(rpc.url.as_str(), now_system).is_none())
+            .filter(|rpc| !over_budget.contains(rpc.url.as_str()))
+            .collect()
+    }
+
+    /// Maintenance windows of the configured endpoints and from the settings.
+    pub fn maintenance(&self) -> MaintenanceSchedule {
+        let config = self.config();
+        MaintenanceSchedule::new(&config.maintenance_windows, &config.settings.maintenance_windows)
+    }
+
+    /// Move the active provider off its endpoint if that is in maintenance or enters it within
+    /// `lead`, returning how long until this should be checked again.
+    pub(crate) async fn step_around_maintenance(self: &Arc<Self>, lead: Duration) -> Duration {
+        let now = self.clock.now_system();
+        let maintenance = self.maintenance();
+        let Ok(active) = self.get_provider_url().await else { return MAINTENANCE_RECHECK };
+
+        let active = if maintenance.unavailable_within(&active, now, lead) {
+            let mut available = self.latencies.read().await.clone();
+            available.retain(|url, _| !maintenance.unavailable_within(url, now, lead));
+            let Some(replacement) = self.pick_fastest(&available) else {
+                self.log("warn", "Active provider is entering maintenance with no endpoint to replace it", Some(serde_json::json!({ "url": active }))).await;
+                return MAINTENANCE_RECHECK;
+            };
+            if let Err(e) = self.install_provider(replacement.clone()).await {
+                self.log("warn", "Failed to replace provider entering maintenance", Some(serde_json::json!({ "error": e.to_string() }))).await;
+                return MAINTENANCE_RECHECK;
+            }
+            self.emit(HandlerEvent::MaintenanceFailover { from: active.clone(), to: replacement.clone() });
+            self.log("info", "Replaced provider entering maintenance", Some(serde_json::json!({ "from": active, "to": replacement }))).await;
+            replacement
+        } else {
+            active
+        };
+
+        maintenance
+            .next_start(&active, now)
+            .map(|start| start.duration_since(now).unwrap_or_default().saturating_sub(lead))
+            .map_or(MAINTENANCE_RECHECK, |until_lead| until_lead.min(MAINTENANCE_RECHECK))
+    }
+
+    /// Report a latency-SLO breach, replacing the active provider first if the breach is its own:
+    /// with the fastest endpoint that isn't serving a penalty or in maintenance.
+    pub(crate) async fn step_off_slow_provider(self: &Arc<Self>, breach: SloBreach) {
+        let SloBreach { url, violations } = breach;
+        let mut replacement = None;
+        if self.get_provider_url().await.is_ok_and(|active| active == url) {
+            let penalized = self.slo.penalized(self.clock.now_instant());
+            let maintenance = self.maintenance();
+            let now = self.clock.now_system();
+            let mut available = self.latencies.read().await.clone();
+            available.retain(|candidate, _| !penalized.contains_key(candidate) && maintenance.in_window_until(candidate, now).is_none());
+            match self.pick_fastest(&available) {
+                Some(fastest) => match self.install_provider(fastest.clone()).await {
+                    Ok(()) => replacement = Some(fastest),
+                    Err(e) => self.log("warn", "Failed to replace provider over its latency budget", Some(serde_json::json!({ "error": e.to_string() }))).await,
+                },
+                None => self.log("warn", "Active provider is over its latency budget with no endpoint to replace it", Some(serde_json::json!({ "url": url }))).await,
+            }
+        }
+        self.log("warn", "Provider exceeded its latency budget", Some(serde_json::json!({ "url": url, "violations": violations, "replacement": replacement }))).await;
+        self.emit(HandlerEvent::LatencySloBreached { url, violations, replacement });
+    }
+
+    /// Report a spend budget that ran out, replacing the active provider first if it can no
+    /// longer afford requests: with the fastest endpoint that still can and isn't in maintenance.
+    pub(crate) async fn report_budget_exhausted(self: &Arc<Self>, exhaustion: BudgetExhaustion) {
+        let BudgetExhaustion { url, spent, budget } = exhaustion;
+        let over_budget = self.spend.exhausted();
+        let mut replacement = None;
+        if let Ok(active) = self.get_provider_url().await
+            && over_budget.contains(&active)
+        {
+            let maintenance = self.maintenance();
+            let now = self.clock.now_system();
+            let mut available = self.latencies.read().await.clone();
+            available.retain(|candidate, _| !over_budget.contains(candidate) && maintenance.in_window_until(candidate, now).is_none());
+            match self.pick_fastest(&available) {
+                Some(fastest) => match self.install_provider(fastest.clone()).await {
+                    Ok(()) => replacement = Some(fastest),
+                    Err(e) => self.log("warn", "Failed to replace provider out of spend budget", Some(serde_json::json!({ "error": e.to_string() }))).await,
+                },
+                None => self.log("warn", "Active provider is out of spend budget with no endpoint to replace it", Some(serde_json::json!({ "url": active }))).await,
+            }
+        }
+        self.log("warn", "Daily spend budget exhausted", Some(serde_json::json!({ "url": url, "spent": spent, "budget": budget, "replacement": replacement }))).await;
+        self.emit(HandlerEvent::BudgetExhausted { url, spent, budget, replacement });
+    }
+
+    /// The day's spend on metered endpoints against their budgets and the handler's.
+    pub fn spend_report(&self) -> SpendReport {
+        self.spend.report_spend()
+    }
+
+    /// Endpoints the timestamp checks have flagged, with their flags.
+    pub fn timestamp_flags(&self) -> HashMap<String, BTreeSet<HealthFlag>> {
+        self.timestamps.flags()
+    }
+
+    /// Samples and flags of agreement sampling, shared with consensus.
+    pub(crate) fn agreement(&self) -> &AgreementTracker {
+        &self.agreement
+    }
+
+    /// Entries in the response cache and the calls it answered, negatives counted apart.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.response_cache.stats()
+    }
+
+    /// Adaptive when configured, with the endpoints that were healthy at the last probe as the
+    /// ones eligible for the derived timeout.
+    async fn probe_timeout_policy(&self) -> TimeoutPolicy {
+        let fallback = self.config().settings.rpc_timeout;
+        let Some(settings) = self.config().settings.adaptive_probe_timeout else {
+            return TimeoutPolicy::Fixed(fallback);
+        };
+
+        let mut known_healthy: HashSet<String> = self.latencies.read().await.keys().cloned().collect();
+        known_healthy.extend(self.lagging.read().await.keys().cloned());
+        TimeoutPolicy::Adaptive {
+            fallback,
+            settings,
+            history: self.probe_history.lock().iter().copied().collect(),
+            known_healthy,
+        }
+    }
+
+    fn record_probe_history(&self, results: &[RpcCheckResult]) {
+        self.restore_probe_history(results.iter().filter(|result| result.success).map(|result| result.duration));
+    }
+
+    /// The timeouts the most recent probe used, `None` before the first one.
+    pub fn probe_timeouts(&self) -> Option<ProbeTimeouts> {
+        *self.probe_timeouts.lock()
+    }
+
+    /// Seed the latency history the adaptive probe timeout is derived from, e.g. from a previous run.
+    pub fn restore_probe_history(&self, latencies_ms: impl IntoIterator<Item = u64>) {
+        let mut history = self.probe_history.lock();
+        history.extend(latencies_ms);
+        let excess = history.len().saturating_sub(PROBE_HISTORY_LEN);
+        history.drain(..excess);
+    }
+
+    fn probe_timeouts_log(&self) -> Option<serde_json::Value> {
+        self.probe_timeouts().map(|timeouts| serde_json::json!({ "probe_timeouts": timeouts }))
+    }
+
+    /// Skip endpoints that answered like a web page, and clear endpoints that answered properly.
+    fn schedule_probe_results(&self, results: &[RpcCheckResult], now: Instant) {
+        for result in results {
+            match &result.non_json_rpc {
+                Some(classification) => self.probe_schedule.record_non_json_rpc(&result.url, classification.clone(), now),
+                None if result.success => self.probe_schedule.record_success(&result.url),
+                None => {}
+            }
+        }
+    }
+
+    /// Per-host request caps shared by the proxy, consensus and probes.
+    pub fn host_limiter(&self) -> &HostLimiter {
+        &self.host_limiter
+    }
+
+    /// The highest head block the proxy has returned since init or the last `refresh()`.
+    pub fn head_watermark(&self) -> Option<u64> {
+        self.heads.watermark()
+    }
+
+    /// Head blocks the proxy has seen each endpoint report since init or the last `refresh()`.
+    pub fn observed_heads(&self) -> HashMap<String, u64> {
+        self.heads.heads()
+    }
+
+    /// Spend accounting shared by every path that sends to an endpoint.
+    pub(crate) fn spend_meter(&self) -> &SpendMeter {
+        &self.spend
+    }
+
+    /// Endpoints currently kept out of probes, shared with the request paths that classify them.
+    pub fn probe_schedule(&self) -> &ProbeSchedule {
+        &self.probe_schedule
+    }
+
+    /// The shared client, for request paths that don't pin.
+    pub(crate) fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Each endpoint's own extra headers, sent on top of the client's.
+    pub(crate) fn endpoint_headers(&self) -> HeaderOverrides {
+        Arc::clone(&self.config().headers)
+    }
+
+    /// HTTP client for probes and requests.
+    ///
+    /// With pinning enabled every call builds a fresh client so connections pooled before a
+    /// pin changed are never reused.
+    pub(crate) fn http_client(&self) -> Result<reqwest::Client> {
+        match &self.resolver {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(resolver) => client_builder(&self.config().settings)
+                .dns_resolver(Arc::new(resolver.clone()))
+                .build()
+                .map_err(RpcHandlerError::Network),
+            _ => Ok(self.client.clone()),
+        }
+    }
+
+    /// The transports probes and strategies go through: the probe client, each endpoint's own
+    /// headers and the redirect setting, over HTTP.
+    pub(crate) fn transport_factory(&self) -> Result<HttpTransportFactory> {
+        let settings = &self.config().settings;
+        let factory = HttpTransportFactory::with_client(self.http_client()?, settings.rpc_timeout)
+            .follow_redirects(settings.follow_post_redirects)
+            .headers(self.endpoint_headers());
+        Ok(match self.egress_allowed() {
+            Some(allowed) => factory.only(allowed),
+            None => factory,
+        })
+    }
+
+    /// The URLs `Egress::InjectedOnly` lets requests go to, `None` when they may go anywhere.
+    fn egress_allowed(&self) -> Option<HashSet<String>> {
+        (self.config().settings.egress == Egress::InjectedOnly).then(|| {
+            self.rpcs
+                .read()
+                .iter()
+                .filter(|tracked| matches!(tracked.origin, RpcOrigin::Injected | RpcOrigin::RuntimeAdded))
+                .map(|tracked| tracked.rpc.url.to_string())
+                .collect()
+        })
+    }
+
+    /// The result of `request` sent straight to `url`, outside the proxy and its cache. `None`
+    /// if it failed, `egress` doesn't allow `url`, or the host's cap, `class`'s share of the
+    /// budget or the spend budget left no room for it.
+    pub(crate) async fn side_call(&self, client: &reqwest::Client, url: &str, request: &JsonRpcRequest, class: TrafficClass) -> Option<serde_json::Value> {
+        if self.egress_allowed().is_some_and(|allowed| !allowed.contains(url)) {
+            return None;
+        }
+        let config = self.config();
+        let headers = self.endpoint_headers();
+        let _slot = self.host_limiter.try_acquire_as(url, class)?;
+        self.spend.charge(url, &request.method).ok()?;
+        let call = async {
+            let response = post_json_rpc(client, url, request, config.settings.follow_post_redirects, headers.get(url)).await.ok()?;
+            if !response.status().is_success() {
+                return None;
+            }
+            response.json::<JsonRpcResponse<serde_json::Value>>().await.ok()?.into_result().ok()
+        };
+        runtime::timeout(config.settings.rpc_timeout, call).await.ok().flatten()
+    }
+
+    /// Pin each healthy endpoint to the IP its probe connected to, leaving live pins untouched
+    /// so their TTL keeps running.
+    fn pin_measured_ips(&self, results: &[RpcCheckResult], latencies: &LatencyMap) {
+        let Some(resolver) = &self.resolver else { return };
+        for result in results {
+            if let Some(ip) = result.remote_ip
+                && latencies.contains_key(&result.url)
+                && resolver.pinned_ip(&result.url).is_none()
+            {
+                resolver.pin(&result.url, ip);
+            }
+        }
+    }
+
+    /// Drop the pinned IP for `url`'s hostname so the next connection re-resolves it.
+    ///
+    /// Returns `false` when pinning is disabled or nothing was pinned.
+    pub fn unpin(&self, url: &str) -> bool {
+        self.resolver.as_ref().is_some_and(|resolver| resolver.unpin(url))
+    }
+
+    /// The IP currently pinned for `url`'s hostname, if any.
+    pub fn pinned_ip(&self, url: &str) -> Option<IpAddr> {
+        self.resolver.as_ref().and_then(|resolver| resolver.pinned_ip(url))
+    }
+
+    /// Remember the client version reported by an endpoint for the health report.
+    pub(crate) async fn record_client_version(&self, url: String, version: String) {
+        self.touch(&url);
+        self.client_versions.write().await.insert(url, version);
+    }
+
+    /// Capabilities learned for `url` so far.
+    pub async fn capabilities_for(&self, url: &str) -> EndpointCapabilities {
+        self.capabilities.read().await.get(url).cloned().unwrap_or_default()
+    }
+
+    /// Capabilities learned for every endpoint, e.g. to persist and hand back to `restore_capabilities`.
+    pub async fn capabilities(&self) -> HashMap<String, EndpointCapabilities> {
+        self.capabilities.read().await.clone()
+    }
+
+    /// Seed capabilities learned by an earlier handler so unsupported methods aren't retried.
+    pub async fn restore_capabilities(&self, capabilities: HashMap<String, EndpointCapabilities>) {
+        self.capabilities.write().await.extend(capabilities);
+    }
+
+    pub(crate) async fn record_capability(&self, url: &str, update: impl FnOnce(&mut EndpointCapabilities)) {
+        self.touch(url);
+        update(self.capabilities.write().await.entry(url.to_string()).or_default());
+    }
+
+    /// Snapshot of every configured endpoint with its tier, latency and whether it is active.
+    pub async fn health_report(&self) -> HealthReport {
+        let latencies = self.latencies.read().await.clone();
+        let client_versions = self.client_versions.read().await.clone();
+        let failure_counts = self.failure_counts.read().await.clone();
+        let malformed_counts = self.malformed_counts.lock().clone();
+        let head_lags = self.head_lags();
+        let mut custom_probes = self.custom_probe_outcomes.lock().clone();
+        let capabilities = self.capabilities.read().await.clone();
+        let active_url = self.provider.read().await.as_ref().map(|p| p.base_url.clone());
+        let maintenance = self.maintenance();
+        let tiers = self.config().tiers.clone();
+        let now = self.clock.now_system();
+        let mut spend = self.spend.report_spend().endpoints;
+        let mut timestamp_flags = self.timestamp_flags();
+        for url in self.agreement.flagged() {
+            timestamp_flags.entry(url).or_default().insert(HealthFlag::SuspectedDishonest);
+        }
+        let mut agreement = self.agreement.stats();
+
+        let mut regions = self.regions();
+        let tracked = self.rpcs.read().clone();
+        let endpoints = tracked
+            .iter()
+            .map(|TrackedRpc { rpc, origin, .. }| {
+                let url = rpc.url.to_string();
+                EndpointHealth {
+                    latency_ms: latencies.get(&url).copied(),
+                    head_lag: head_lags.get(&url).copied(),
+                    active: active_url.as_deref() == Some(url.as_str()),
+                    tier: tiers.get(&url).copied(),
+                    client_version: client_versions.get(&url).cloned(),
+                    pinned_ip: self.pinned_ip(&url),
+                    region: regions.remove(&url),
+                    consecutive_failures: failure_counts.get(&url).copied().unwrap_or(0),
+                    malformed_responses: malformed_counts.get(&url).copied().unwrap_or(0),
+                    capabilities: capabilities.get(&url).cloned().unwrap_or_default(),
+                    non_json_rpc: self.probe_schedule.classification(&url),
+                    maintenance_until: maintenance.in_window_until(&url, now),
+                    spend: spend.remove(&url),
+                    flags: timestamp_flags.remove(&url).unwrap_or_default(),
+                    agreement: agreement.remove(&url),
+                    origin: *origin,
+                    last_healthy: self.liveness.last_healthy(&url),
+                    uptime: self.liveness.uptime(&url, REPORTED_UPTIME_WINDOW, now),
+                    custom_probes: custom_probes.remove(&url).unwrap_or_default(),
+                    url: self.redact(&url),
+                }
+            })
+            .collect();
+
+        HealthReport {
+            network_id: self.network_id,
+            failover_policy: self.config().failover_policy,
+            active_url: active_url.map(|url| self.redact(&url)),
+            probe_timeouts: self.probe_timeouts(),
+            host_in_flight: self.host_limiter.in_flight(),
+            host_quotas: self.host_limiter.quotas(),
+            requests_in_flight: self.requests_in_flight(),
+            refresh_deferrals: self.refresh_deferrals.load(Ordering::Relaxed),
+            last_full_sweep: *self.last_full_sweep.lock(),
+            request_latency: self.metrics.latency(),
+            endpoints,
+        }
+    }
+
+    /// Up to `limit` endpoints of the health report from `offset` on, sorted by `sort`, ties by
+    /// URL. An offset past the end gives an empty page.
+    pub async fn health_report_page(&self, offset: usize, limit: usize, sort: (SortBy, Order)) -> HealthPage {
+        let mut report = self.health_report().await;
+        sort_endpoints(&mut report.endpoints, sort.0, sort.1);
+        let total_endpoints = report.endpoints.len();
+        let end = offset.saturating_add(limit).min(total_endpoints);
+        report.endpoints = report.endpoints.drain(offset.min(end)..end).collect();
+        HealthPage { report, total_endpoints, next_offset: (end < total_endpoints).then_some(end) }
+    }
+
     async fn build_provider(self: &Arc<Self>, url: String) -> Result<RetryProvider> {
+        self.build_provider_for(url, &self.init_state()).await
+    }
+
+    /// A provider for `url` with the options of `state`, the init state it is installed under.
+    async fn build_provider_for(self: &Arc<Self>, url: String, state: &InitState) -> Result<RetryProvider> {
         let _base_provider = create_provider(url.clone(), self.network_id)?;
-        
+        Ok(RetryProvider::with_client(url, self.network_id, self.retry_options_for(state), self.http_client()?))
+    }
+
+    /// Put the current config into the active provider's options. Requests already sent keep
+    /// the options they started with.
+    pub(crate) async fn reload_provider_options(&self) {
+        if let Some(provider) = self.provider.read().await.as_ref() {
+            *provider.options.write().await = self.retry_options_for(&self.init_state());
+        }
+    }
+
+    /// Provider options from the current config and endpoint set, for a provider installed under
+    /// `state`. A degraded one has no latencies to go by, so it walks the unprobed order instead.
+    fn retry_options_for(&self, state: &InitState) -> RetryOptions {
+        let config = self.config();
         let latencies = Arc::clone(&self.latencies);
+        let lagging = Arc::clone(&self.lagging);
+        let cooldowns = Arc::clone(&self.cooldowns);
+        let head_lags = Arc::clone(&self.head_lags);
+        let heads = self.heads.clone();
+        let maintenance = self.maintenance();
+        let slo = self.slo.clone();
+        let spend = self.spend.clone();
+        let host_limiter = self.host_limiter.clone();
+        let endpoints: Vec<String> = self.rpcs().iter().map(|rpc| rpc.url.to_string()).collect();
+        let timestamps = config.settings.timestamp_sanity.is_some().then(|| self.timestamps.clone());
+        let agreement = config.settings.agreement_sampling.is_some_and(|sampling| sampling.exclude_from_reads).then(|| self.agreement.clone());
+        let clock = Arc::clone(&self.clock);
+        let failover_policy = config.failover_policy;
+        let redactor = config.redactor.clone();
         
-        let retry_options = RetryOptions {
-            retry_count: self.config.retry.retry_count,
-            retry_delay: self.config.retry.retry_delay,
-            get_ordered_urls: Arc::new(move || {
-                let latencies_guard = futures::executor::block_on(latencies.read());
-                let mut ordered: Vec<_> = latencies_guard
-                    .iter()
-                    .map(|(url, &latency)| (url.clone(), latency))
-                    .collect();
-                ordered.sort_by_key(|(_, latency)| *latency);
-                ordered.into_iter().map(|(url, _)| url).collect()
+        RetryOptions {
+            journal: self.journal(),
+            retry_count: config.retry.retry_count,
+            retry_delay: config.retry.retry_delay,
+            batch_size: config.retry.batch_size,
+            outcomes: config.settings.retry_tuning.is_some().then(|| self.tuner.clone()),
+            get_candidates: Arc::new(move || {
+                let now = clock.now_instant();
+                let latencies = futures::executor::block_on(latencies.read()).clone();
+                let lagging = futures::executor::block_on(lagging.read()).clone();
+                Candidates {
+                    in_maintenance: maintenance.in_window(clock.now_system()),
+                    heads: heads.heads(),
+                    head_lags: head_lags.lock().clone(),
+                    head_guard: heads.guard_for(latencies.keys().chain(lagging.keys())),
+                    latencies,
+                    lagging,
+                    cooling_down: futures::executor::block_on(cooldowns.read())
+                        .iter()
+                        .filter(|(_, cooldown)| cooldown.until > now)
+                        .map(|(url, cooldown)| (url.clone(), cooldown.until - now))
+                        .collect(),
+                    slo_penalized: slo.penalized(now),
+                    quota_paced: host_limiter.paced(&endpoints),
+                    over_budget: spend.exhausted(),
+                    clock_skewed: timestamps.as_ref().map(|guard| guard.flagged(HealthFlag::ClockSkewSuspected)).unwrap_or_default(),
+                    stale_blocks: timestamps.as_ref().map(|guard| guard.flagged(HealthFlag::BlocksLagging)).unwrap_or_default(),
+                    suspected_dishonest: agreement.as_ref().map(AgreementTracker::flagged).unwrap_or_default(),
+                }
             }),
             chain_id: self.network_id,
-            rpc_call_timeout: self.config.settings.rpc_call_timeout,
+            chain_aliases: config.chain_aliases.clone(),
+            rpc_call_timeout: config.settings.rpc_call_timeout,
+            failover_policy,
+            head_lag_penalty_ms: config.settings.head_lag_penalty_ms,
+            regions: self.regions(),
+            region_affinity: config.settings.region_affinity,
+            tiers: config.tiers.clone(),
+            static_order: match (&self.strategy, state) {
+                (Strategy::StaticOrder(urls), _) => Some(urls.clone()),
+                (_, InitState::Degraded { .. }) => Some(self.unprobed_order()),
+                _ => None,
+            },
+            resolver: self.resolver.clone(),
+            routes: config.routes.clone(),
+            validation_mode: config.validation_mode,
+            malformed_counts: Arc::clone(&self.malformed_counts),
+            follow_redirects: config.settings.follow_post_redirects,
+            headers: Arc::clone(&config.headers),
+            probe_schedule: self.probe_schedule.clone(),
+            host_limiter: self.host_limiter.clone(),
+            in_flight: self.in_flight.clone(),
+            heads: self.heads.clone(),
+            metrics: self.metrics.with_endpoints(self.rpcs().iter().map(|rpc| rpc.url.as_str())),
+            liveness: self.liveness.clone(),
+            shadows: self.shadows.clone(),
+            latency_slo: config.settings.latency_slo.clone(),
+            slo: self.slo.clone(),
+            spend: self.spend.clone(),
+            timestamp_sanity: config.settings.timestamp_sanity,
+            timestamps: self.timestamps.clone(),
+            response_cache: self.response_cache.clone(),
+            response_cache_entries: config.settings.response_cache_entries,
+            negative_cache_entries: config.settings.negative_cache_entries,
+            auth_failures: config.settings.rotate_secrets_on_auth_error.then(|| self.auth_failures.clone()),
+            error_mappings: config.settings.error_mappings.clone(),
+            metric_tag_keys: config.settings.metric_tag_keys.clone(),
+            clock: Arc::clone(&self.clock),
             on_log: Some(Arc::new(move |level, msg, meta| {
+                let msg = redactor.redact(msg);
+                let meta = meta.map(|meta| redactor.redact_value(&meta));
                 match level {
                     "error" => tracing::error!(message = %msg, metadata = ?meta, "RPC log"),
                     "warn" => tracing::warn!(message = %msg, metadata = ?meta, "RPC log"),
@@ -184,18 +2111,122 @@ impl RpcHandler {
                     Ok(())
                 })
             }),
-        };
-        
-        Ok(wrap_with_retry(url, self.network_id, retry_options))
+        }
     }
 
     pub async fn try_proxy_request(&self, request: JsonRpcRequest) -> Result<JsonRpcResponse<serde_json::Value>> {
+        self.try_proxy_request_attributed(request).await.map(|(response, _url)| response)
+    }
+
+    /// Send `batch` in one round trip through the active provider, re-sending only the entries
+    /// that failed retryably. Answers come back in request order with the caller's ids, each
+    /// entry with its own outcome; see `RetryProvider::send_batch`.
+    pub async fn try_proxy_batch(&self, batch: &[JsonRpcRequest], options: Option<BatchOptions>) -> Result<Vec<BatchEntry>> {
+        if batch.is_empty() {
+            return Ok(Vec::new());
+        }
+        let name = batch_name(batch);
+        self.in_span(name.clone(), &name, async { self.get_provider().await?.send_batch(batch, options).await }).await
+    }
+
+    /// Like `try_proxy_batch`, with per-call exclusions and overrides as for
+    /// `RetryProvider::send_batch_with`.
+    ///
+    /// Under `CallOptions::hold_on_total_failure` a batch that no endpoint answers is held and
+    /// sent again as endpoints recover, failing with `HoldExpired` if none does in time.
+    pub async fn try_proxy_batch_with(self: &Arc<Self>, batch: &[JsonRpcRequest], options: Option<BatchOptions>, call: CallOptions) -> Result<Vec<BatchEntry>> {
+        if batch.is_empty() {
+            return Ok(Vec::new());
+        }
+        let name = batch_name(batch);
+        let tags = call.tags.clone().or_else(|| options.as_ref().and_then(|options| options.tags.clone()));
+        let call = &call;
+        let send = || {
+            let options = options.clone();
+            async move { self.get_provider().await?.send_batch_with(batch, options, call).await }
+        };
+        let result = self
+            .in_span(name.clone(), &name, async {
+                match (send().await, call.hold_on_total_failure) {
+                    (Err(e), Some(policy)) if is_total_failure(&e) => self.hold_with(&name, tags.clone().unwrap_or_default(), policy, e, send).await,
+                    (result, _) => result,
+                }
+            })
+            .await;
+        self.report_tagged(&name, tags.as_ref(), &result);
+        result
+    }
+
+    /// Like `try_proxy_request`, but also returns the URL that served the response.
+    pub async fn try_proxy_request_attributed(&self, request: JsonRpcRequest) -> Result<(JsonRpcResponse<serde_json::Value>, String)> {
+        // Queue wait is counted from here, waiting for the provider included
+        let submitted = Instant::now();
+        self.in_span(request.method.clone(), &request.method, async {
+            let attributed = self.send_with(&request, &CallOptions::default(), submitted).await?;
+            Ok((attributed.response, attributed.url))
+        })
+        .await
+    }
+
+    /// Like `try_proxy_request_attributed`, with per-call exclusions and overrides.
+    ///
+    /// Under `CallOptions::hold_on_total_failure` a call that no endpoint answers is held and
+    /// retried as endpoints recover, failing with `HoldExpired` if none does in time.
+    pub async fn try_proxy_request_with(self: &Arc<Self>, request: JsonRpcRequest, options: CallOptions) -> Result<(JsonRpcResponse<serde_json::Value>, String)> {
+        self.try_proxy_request_attributed_with(request, options).await.map(|attributed| (attributed.response, attributed.url))
+    }
+
+    /// Like `try_proxy_request_with`, also returning the block a `"latest"` state read was
+    /// pinned to under `CallOptions::max_state_lag_blocks`.
+    pub async fn try_proxy_request_attributed_with(self: &Arc<Self>, request: JsonRpcRequest, options: CallOptions) -> Result<AttributedResponse> {
+        let submitted = Instant::now();
+        let span = tags::call_span(&request.method, options.tags.as_ref());
+        let result = self
+            .in_span(request.method.clone(), &request.method, async {
+                match (self.send_with(&request, &options, submitted).await, options.hold_on_total_failure) {
+                    (Err(e), Some(policy)) if is_total_failure(&e) => self.hold(&request, &options, policy, e).await,
+                    (result, _) => result,
+                }
+            })
+            .instrument(span)
+            .await;
+        self.report_tagged(&request.method, options.tags.as_ref(), &result);
+        result
+    }
+
+    /// Run `future` as a call span named `name` when a tracer provider is installed.
+    #[cfg(feature = "otel")]
+    pub(crate) async fn in_span<F>(&self, name: String, method: &str, future: F) -> F::Output
+    where
+        F: Future,
+        F::Output: otel::SpanOutcome,
+    {
+        match &self.tracer {
+            Some(tracer) => otel::traced(tracer, name, otel::rpc_attributes(method), future).await,
+            None => future.await,
+        }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) async fn in_span<F: Future>(&self, _name: String, _method: &str, future: F) -> F::Output {
+        future.await
+    }
+
+    /// Send through the active provider a request the caller submitted at `submitted`.
+    pub(crate) async fn send_with(&self, request: &JsonRpcRequest, options: &CallOptions, submitted: Instant) -> Result<AttributedResponse> {
+        let provider = self.get_provider().await?;
+        provider.send_submitted(request, options, submitted).await
+    }
+
+    /// The endpoints `try_proxy_request_with` would try for `request`, in order and annotated with
+    /// the rule that placed each one, without sending anything.
+    pub async fn plan_request(&self, request: &JsonRpcRequest, options: Option<CallOptions>) -> Result<RequestPlan> {
         let provider = self.get_provider().await?;
-        provider.send_request(&request).await
+        Ok(provider.plan(request, &options.unwrap_or_default()).await)
     }
 
-    async fn log(&self, level: &str, message: &str, metadata: Option<serde_json::Value>) {
-        let log_level = &self.config.settings.log_level;
+    pub(crate) async fn log(&self, level: &str, message: &str, metadata: Option<serde_json::Value>) {
+        let log_level = &self.config().settings.log_level;
         
         // Simple level filtering
         let should_log = match (log_level.as_str(), level) {
@@ -208,6 +2239,8 @@ impl RpcHandler {
         };
         
         if should_log {
+            let message = self.redact(message);
+            let metadata = metadata.map(|metadata| self.config().redactor.redact_value(&metadata));
             match level {
                 "error" => tracing::error!(
                     network_id = %self.network_id,
@@ -238,3 +2271,55 @@ impl RpcHandler {
         }
     }
 }
+
+/// `rpc_client_builder` with the configured connect timeout and user agent, if any.
+fn client_builder(settings: &SettingsConfig) -> reqwest::ClientBuilder {
+    let builder = match &settings.user_agent {
+        Some(user_agent) => rpc_client_builder().user_agent(user_agent.as_str()),
+        None => rpc_client_builder(),
+    };
+    with_connect_timeout(builder, settings.connect_timeout)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn with_connect_timeout(builder: reqwest::ClientBuilder, timeout: Option<Duration>) -> reqwest::ClientBuilder {
+    match timeout {
+        Some(timeout) => builder.connect_timeout(timeout),
+        None => builder,
+    }
+}
+
+/// A browser has no connect timeout to set; the overall request timeout still applies there.
+#[cfg(target_arch = "wasm32")]
+fn with_connect_timeout(builder: reqwest::ClientBuilder, _timeout: Option<Duration>) -> reqwest::ClientBuilder {
+    builder
+}
+
+/// The `ChainDataStale` event to emit when the chain data is older than `staleness_policy`
+/// allows at `now`, or the error under a strict policy. A local node doesn't use the data.
+fn stale_chain_data(config: &NormalizedConfig, now: SystemTime) -> Result<Option<HandlerEvent>> {
+    let Some(staleness) = config.settings.staleness.filter(|_| !config.settings.localnet) else { return Ok(None) };
+    let provenance = data_provenance();
+    if provenance.age_at(now) <= staleness.max_age {
+        return Ok(None);
+    }
+    let age_ms = provenance.generated_at.map(|_| provenance.age_at(now).as_millis() as u64);
+    let max_age_ms = staleness.max_age.as_millis() as u64;
+    match staleness.strict {
+        true => Err(RpcHandlerError::ChainDataStale { age_ms, max_age_ms }),
+        false => Ok(Some(HandlerEvent::ChainDataStale { age_ms, max_age_ms })),
+    }
+}
+
+/// `urls` normalized like the endpoint URLs, each checked to be one of `rpcs`.
+fn static_order(urls: &[String], rpcs: &[TrackedRpc], redactor: &Redactor) -> Result<Vec<String>> {
+    urls.iter()
+        .map(|url| {
+            let url = normalize_url(url);
+            match rpcs.iter().any(|tracked| tracked.rpc.url.as_str() == url) {
+                true => Ok(url),
+                false => Err(RpcHandlerError::UnknownStaticOrderUrl { url: redactor.redact(&url) }),
+            }
+        })
+        .collect()
+}
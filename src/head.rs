@@ -0,0 +1,125 @@
+//! The highest block the proxy has returned, for keeping the head monotonic across failovers.
+//!
+//! Heads are learned from responses flowing through the proxy: `eth_blockNumber`, and
+//! `eth_getBlockByNumber` for `latest`. Each endpoint's last reported head is kept so an endpoint
+//! seen behind can be skipped before it is asked again, until the next `refresh()`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{namespaces::parse_quantity, JsonRpcRequest, RpcHandlerError};
+
+/// How far behind the watermark an endpoint may be under `HandlerSettings::monotonic_head`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HeadGuard {
+    /// Highest block returned so far
+    pub watermark: u64,
+    pub max_head_lag: u64,
+}
+
+impl HeadGuard {
+    /// The lowest head an endpoint may report and still be used.
+    pub fn floor(&self) -> u64 {
+        self.watermark.saturating_sub(self.max_head_lag)
+    }
+
+    pub fn error(&self) -> RpcHandlerError {
+        RpcHandlerError::NoSufficientlySyncedProvider { watermark: self.watermark, max_head_lag: self.max_head_lag }
+    }
+}
+
+#[derive(Debug, Default)]
+struct HeadState {
+    watermark: Option<u64>,
+    heads: HashMap<String, u64>,
+}
+
+/// Tracks the watermark and each endpoint's last reported head. Cloning shares the state.
+#[derive(Debug, Clone, Default)]
+pub struct HeadTracker {
+    state: Arc<parking_lot::Mutex<HeadState>>,
+    /// `(max_head_lag, reorg_tolerance)` when the guard is on
+    guard: Option<(u64, u64)>,
+}
+
+/// The head block a response reports, if the request asked for the current head.
+pub fn reported_head(request: &JsonRpcRequest, result: &Value) -> Option<u64> {
+    match request.method.as_str() {
+        "eth_blockNumber" => parse_quantity(result),
+        "eth_getBlockByNumber" if request.params.get(0).and_then(Value::as_str) == Some("latest") => {
+            result.get("number").and_then(parse_quantity)
+        }
+        _ => None,
+    }
+}
+
+impl HeadTracker {
+    /// A tracker that enforces the guard when `monotonic` is set; heads are tracked either way.
+    pub fn new(monotonic: bool, max_head_lag: u64, reorg_tolerance: u64) -> Self {
+        Self { state: Arc::default(), guard: monotonic.then_some((max_head_lag, reorg_tolerance)) }
+    }
+
+    pub fn watermark(&self) -> Option<u64> {
+        self.state.lock().watermark
+    }
+
+    /// Each endpoint's last reported head.
+    pub fn heads(&self) -> HashMap<String, u64> {
+        self.state.lock().heads.clone()
+    }
+
+    /// The guard to plan `candidates` with, `None` when it is off or nothing has been returned yet.
+    ///
+    /// When every candidate is known to be behind the floor but the best of them is within the
+    /// reorg tolerance of the watermark, the chain is taken to have reorganized to a lower head:
+    /// the watermark follows it down instead of failing the call.
+    pub fn guard_for<'a>(&self, candidates: impl IntoIterator<Item = &'a String>) -> Option<HeadGuard> {
+        let (max_head_lag, reorg_tolerance) = self.guard?;
+        let mut state = self.state.lock();
+        let watermark = state.watermark?;
+        let floor = watermark.saturating_sub(max_head_lag);
+
+        let mut best = None;
+        for url in candidates {
+            match state.heads.get(url) {
+                Some(&head) if head < floor => best = best.max(Some(head)),
+                // An unknown or recent enough endpoint can still keep the watermark
+                _ => return Some(HeadGuard { watermark, max_head_lag }),
+            }
+        }
+        if let Some(best) = best
+            && watermark - best <= reorg_tolerance
+        {
+            state.watermark = Some(best);
+            return Some(HeadGuard { watermark: best, max_head_lag });
+        }
+        Some(HeadGuard { watermark, max_head_lag })
+    }
+
+    /// Record the head `url` reported for `request`.
+    ///
+    /// Under the guard, a head below the floor is an error: the response must not be returned.
+    pub fn observe(&self, url: &str, request: &JsonRpcRequest, result: &Value) -> Result<(), RpcHandlerError> {
+        let Some(head) = reported_head(request, result) else {
+            return Ok(());
+        };
+        let mut state = self.state.lock();
+        state.heads.insert(url.to_string(), head);
+
+        if let (Some((max_head_lag, _)), Some(watermark)) = (self.guard, state.watermark) {
+            let guard = HeadGuard { watermark, max_head_lag };
+            if head < guard.floor() {
+                return Err(guard.error());
+            }
+        }
+        state.watermark = state.watermark.max(Some(head));
+        Ok(())
+    }
+
+    /// Forget the watermark and every endpoint's head.
+    pub fn reset(&self) {
+        *self.state.lock() = HeadState::default();
+    }
+}
@@ -0,0 +1,217 @@
+use std::{cmp::Ordering, collections::{BTreeMap, BTreeSet}, fmt, net::IpAddr, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::Serialize;
+
+use crate::{agreement::AgreementStats, metrics::RequestLatency, namespaces::EndpointCapabilities, performance::{NamedProbeOutcome, ProbeOutcome, ProbeTimeouts}, provider::{HostQuota, NonJsonRpcResponse}, region::Region, rpc::RpcOrigin, spend::EndpointSpend, timestamps::HealthFlag, FailoverPolicy, LatencyRecord, NetworkId};
+
+/// What endpoint listings are sorted by. Ties always go by URL, ascending, so a listing comes
+/// back in the same order on every call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SortBy {
+    /// Measured latency; endpoints without one come last in either order
+    Latency,
+    Url,
+    /// Consecutive failed keepalive pings
+    FailureCount,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+impl Order {
+    fn apply(self, ordering: Ordering) -> Ordering {
+        match self {
+            Order::Ascending => ordering,
+            Order::Descending => ordering.reverse(),
+        }
+    }
+}
+
+/// Sort latency records by `by` in `order`, ties by URL.
+pub(crate) fn sort_latencies(records: &mut [(String, LatencyRecord)], by: SortBy, order: Order) {
+    records.sort_by(|(a_url, a), (b_url, b)| {
+        let ordering = match by {
+            SortBy::Latency => a.latency_ms.cmp(&b.latency_ms),
+            SortBy::Url => Ordering::Equal,
+            SortBy::FailureCount => a.failure_count.cmp(&b.failure_count),
+        };
+        order.apply(ordering).then_with(|| a_url.cmp(b_url))
+    });
+}
+
+/// Sort endpoint health by `by` in `order`, ties by URL.
+pub(crate) fn sort_endpoints(endpoints: &mut [EndpointHealth], by: SortBy, order: Order) {
+    endpoints.sort_by(|a, b| {
+        let ordering = match by {
+            SortBy::Latency => a.latency_ms.is_none().cmp(&b.latency_ms.is_none()).then_with(|| order.apply(a.latency_ms.cmp(&b.latency_ms))),
+            SortBy::Url => Ordering::Equal,
+            SortBy::FailureCount => order.apply(a.consecutive_failures.cmp(&b.consecutive_failures)),
+        };
+        ordering.then_with(|| a.url.cmp(&b.url))
+    });
+}
+
+/// Point-in-time view of the handler's endpoints, intended for logging and status pages.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub network_id: NetworkId,
+    pub failover_policy: FailoverPolicy,
+    pub active_url: Option<String>,
+    /// Timeouts the most recent probe used, `None` before the first probe
+    pub probe_timeouts: Option<ProbeTimeouts>,
+    /// Requests in flight per hostname under a `HostLimits` cap
+    pub host_in_flight: BTreeMap<String, usize>,
+    /// Quota hosts advertised in their response headers, per hostname, under
+    /// `HostLimits::rate_limit_headers`
+    pub host_quotas: BTreeMap<String, HostQuota>,
+    /// Proxied requests in flight, the load auto-refresh yields to
+    pub requests_in_flight: usize,
+    /// Auto-refresh ticks deferred because the handler was busy
+    pub refresh_deferrals: u64,
+    /// When the last sweep covering every probed endpoint completed, `None` before the first
+    pub last_full_sweep: Option<SystemTime>,
+    /// Proxied request latency since the metrics were last reset, queue wait and service apart
+    pub request_latency: RequestLatency,
+    pub endpoints: Vec<EndpointHealth>,
+}
+
+/// One page of a sorted `HealthReport`, from `RpcHandler::health_report_page`.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthPage {
+    /// The handler-wide fields, with `endpoints` holding just this page
+    pub report: HealthReport,
+    /// Endpoints across all pages
+    pub total_endpoints: usize,
+    /// Offset of the next page, `None` on the last one
+    pub next_offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointHealth {
+    pub url: String,
+    /// Configured tier, `None` for untiered endpoints (lowest priority)
+    pub tier: Option<u8>,
+    /// Last measured latency, `None` if the endpoint was not healthy at the last probe
+    pub latency_ms: Option<u64>,
+    /// Blocks behind the most common head at the last probe, `None` if it reported no block
+    pub head_lag: Option<u64>,
+    pub active: bool,
+    /// Backend fingerprint from `web3_clientVersion`, if it has been observed
+    pub client_version: Option<String>,
+    /// IP the endpoint's hostname is pinned to, when resolved-IP pinning is enabled
+    pub pinned_ip: Option<IpAddr>,
+    /// Where its servers are, from `HandlerSettings::region_hints` or the hostname
+    pub region: Option<Region>,
+    /// Consecutive failed keepalive pings, reset by the next success
+    pub consecutive_failures: u32,
+    /// Responses rejected by strict validation
+    pub malformed_responses: u64,
+    /// Optional-method support learned from this endpoint's responses
+    pub capabilities: EndpointCapabilities,
+    /// Set when the endpoint answered with a redirect or a web page rather than JSON-RPC
+    pub non_json_rpc: Option<NonJsonRpcResponse>,
+    /// End of the scheduled maintenance window the endpoint is in, `None` outside maintenance
+    pub maintenance_until: Option<SystemTime>,
+    /// Today's spend, `None` for an endpoint without a cost profile
+    pub spend: Option<EndpointSpend>,
+    /// What `HandlerSettings::timestamp_sanity` and `agreement_sampling` found wrong with its blocks
+    pub flags: BTreeSet<HealthFlag>,
+    /// How often it agreed with its peers, `None` until agreement sampling has sampled it
+    pub agreement: Option<AgreementStats>,
+    /// How it came to be in the set
+    pub origin: RpcOrigin,
+    /// The last probe, ping or request that found it up
+    pub last_healthy: Option<SystemTime>,
+    /// Share of the last `REPORTED_UPTIME_WINDOW` it was up, `None` before its first outcome
+    pub uptime: Option<f64>,
+    /// What each `HandlerSettings::custom_probes` check found at the last probe, empty when none ran
+    pub custom_probes: Vec<NamedProbeOutcome>,
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "network {} ({:?})", self.network_id, self.failover_policy)?;
+        match self.probe_timeouts {
+            Some(ProbeTimeouts { fallback_ms, adaptive_ms: Some(adaptive_ms), p95_ms: Some(p95_ms) }) => {
+                writeln!(f, "  probe timeout {adaptive_ms}ms (p95 {p95_ms}ms), {fallback_ms}ms for new endpoints")?
+            }
+            Some(ProbeTimeouts { fallback_ms, .. }) => writeln!(f, "  probe timeout {fallback_ms}ms")?,
+            None => writeln!(f)?,
+        }
+        for endpoint in &self.endpoints {
+            let tier = endpoint.tier.map_or_else(|| "-".to_string(), |t| t.to_string());
+            let latency = match (endpoint.latency_ms, &endpoint.non_json_rpc) {
+                (Some(ms), _) => format!("{ms}ms"),
+                (None, Some(_)) => "not-rpc".to_string(),
+                (None, None) => "unhealthy".to_string(),
+            };
+            let marker = if endpoint.active { "*" } else { " " };
+            write!(f, "{marker} tier {tier:>3}  {latency:>10}  {}", endpoint.url)?;
+            if let Some(ip) = endpoint.pinned_ip {
+                write!(f, " -> {ip}")?;
+            }
+            if let Some(region) = endpoint.region {
+                write!(f, "  [{region}]")?;
+            }
+            if let Some(lag) = endpoint.head_lag.filter(|lag| *lag > 0) {
+                write!(f, "  [{lag} blocks behind]")?;
+            }
+            if endpoint.consecutive_failures > 0 {
+                write!(f, "  [{} failed pings]", endpoint.consecutive_failures)?;
+            }
+            if endpoint.malformed_responses > 0 {
+                write!(f, "  [{} malformed]", endpoint.malformed_responses)?;
+            }
+            if endpoint.maintenance_until.is_some() {
+                write!(f, "  [scheduled maintenance]")?;
+            }
+            if endpoint.spend.as_ref().is_some_and(|spend| spend.exhausted) {
+                write!(f, "  [out of spend budget]")?;
+            }
+            if let Some(agreement) = endpoint.agreement.filter(|agreement| agreement.suspected_dishonest) {
+                write!(f, "  [suspected dishonest, agreed {}/{}]", agreement.agreements, agreement.samples)?;
+            }
+            for probe in &endpoint.custom_probes {
+                if let ProbeOutcome::Fail(reason) = &probe.outcome {
+                    write!(f, "  [{} failed: {reason}]", probe.probe)?;
+                }
+            }
+            if let Some(non_json_rpc) = &endpoint.non_json_rpc {
+                let content_type = non_json_rpc.content_type.as_deref().unwrap_or("no content type");
+                write!(f, "  [not JSON-RPC: {} {content_type}]", non_json_rpc.status)?;
+            }
+            match &endpoint.client_version {
+                Some(version) => writeln!(f, "  ({version})")?,
+                None => writeln!(f)?,
+            }
+        }
+        let busy: Vec<String> = self.host_in_flight
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(host, count)| format!("{host} {count}"))
+            .collect();
+        if !busy.is_empty() {
+            writeln!(f, "in flight: {}", busy.join(", "))?;
+        }
+        let paced: Vec<String> = self.host_quotas
+            .iter()
+            .filter(|(_, quota)| quota.paced)
+            .map(|(host, quota)| format!("{host} {} left, resets in {}s", quota.remaining, quota.reset_in_ms.div_ceil(1000)))
+            .collect();
+        if !paced.is_empty() {
+            writeln!(f, "paced by quota: {}", paced.join(", "))?;
+        }
+        let (queue_wait, service) = (&self.request_latency.queue_wait, &self.request_latency.service);
+        if let (Some(queue_p99), Some(service_p99)) = (queue_wait.quantile_upper_bound_ms(0.99), service.quantile_upper_bound_ms(0.99)) {
+            writeln!(f, "requests p99: queued <= {queue_p99}ms, served <= {service_p99}ms")?;
+        }
+        if let Some(at) = self.last_full_sweep.and_then(|at| at.duration_since(UNIX_EPOCH).ok()) {
+            writeln!(f, "last full sweep at {}s, {} deferred refresh ticks", at.as_secs(), self.refresh_deferrals)?;
+        }
+        Ok(())
+    }
+}
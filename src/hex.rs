@@ -0,0 +1,25 @@
+//! Hex encoding for call data, proofs and raw transactions, which arrive from endpoints and
+//! callers as `0x`-prefixed strings.
+
+use sha3::{Digest, Keccak256};
+
+/// `bytes` as lowercase hex digits, without a prefix.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The first 8 bytes of `data`'s Keccak-256 as `0x`-prefixed hex, for keys that only need
+/// to tell inputs apart.
+pub(crate) fn short_digest(data: &[u8]) -> String {
+    format!("0x{}", hex(&Keccak256::digest(data)[..8]))
+}
+
+/// The bytes of `0x`-prefixed hex with an even number of digits, `None` for anything else.
+pub(crate) fn unhex(value: &str) -> Option<Vec<u8>> {
+    let digits = value.strip_prefix("0x")?;
+    if digits.len() % 2 == 1 {
+        return None;
+    }
+    // `get` rather than indexing: a multi-byte character makes the pair a non-boundary slice
+    (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok()).collect()
+}
@@ -0,0 +1,159 @@
+//! Holding a proxied call through a network-wide outage instead of failing it at once.
+//!
+//! With `CallOptions::hold_on_total_failure` set, a call that finds every endpoint failing parks
+//! and retries every `retry_interval`, after asking the handler to re-probe, until an endpoint
+//! answers or `max_wait` runs out. A parked call holds no host slot or in-flight count, so each
+//! retry queues for them like any other request.
+
+use std::{
+    future::Future,
+    sync::{atomic::{AtomicUsize, Ordering}, Arc},
+};
+
+use web_time::Instant;
+
+use crate::{
+    events::HandlerEvent,
+    provider::{plan::HoldPolicy, AttributedResponse, CallOptions},
+    tags::CallTags,
+    JsonRpcRequest, Result, RpcHandler, RpcHandlerError,
+};
+
+/// Held calls on a handler, and when one of them last triggered a refresh.
+#[derive(Debug, Default)]
+pub(crate) struct HoldState {
+    holding: AtomicUsize,
+    last_refresh: parking_lot::Mutex<Option<Instant>>,
+}
+
+impl HoldState {
+    pub(crate) fn holding(&self) -> usize {
+        self.holding.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts a call as held for as long as it lives, so a held call that is dropped is released too.
+struct Held<'a> {
+    handler: &'a RpcHandler,
+    method: String,
+    tags: CallTags,
+    recovered: bool,
+}
+
+impl<'a> Held<'a> {
+    fn enter(handler: &'a RpcHandler, method: &str, tags: CallTags) -> Self {
+        let holding = handler.hold_state().holding.fetch_add(1, Ordering::Relaxed) + 1;
+        handler.emit(HandlerEvent::RequestHeld { method: method.to_string(), holding, tags: tags.clone() });
+        Self { handler, method: method.to_string(), tags, recovered: false }
+    }
+}
+
+impl Drop for Held<'_> {
+    fn drop(&mut self) {
+        let holding = self.handler.hold_state().holding.fetch_sub(1, Ordering::Relaxed) - 1;
+        let (method, tags) = (std::mem::take(&mut self.method), std::mem::take(&mut self.tags));
+        self.handler.emit(HandlerEvent::HoldReleased { method, holding, recovered: self.recovered, tags });
+    }
+}
+
+/// Failures that say nothing answered, as opposed to an answer the caller has to deal with.
+pub(crate) fn is_total_failure(error: &RpcHandlerError) -> bool {
+    match error {
+        RpcHandlerError::AllEndpointsFailed
+        | RpcHandlerError::RoutedEndpointsFailed { .. }
+        | RpcHandlerError::NoAvailableRpcs { .. } => true,
+        RpcHandlerError::HttpStatus { status, .. } => *status >= 500 || *status == 429,
+        other => other.is_transport(),
+    }
+}
+
+impl RpcHandler {
+    /// Calls currently held under `CallOptions::hold_on_total_failure`.
+    pub fn holding_requests(&self) -> usize {
+        self.hold_state().holding()
+    }
+
+    /// Retry a call that failed with `first_error` until it succeeds, fails for another reason,
+    /// or `policy.max_wait` is up. Handler shutdown ends the hold early.
+    pub(crate) async fn hold(
+        self: &Arc<Self>,
+        request: &JsonRpcRequest,
+        options: &CallOptions,
+        policy: HoldPolicy,
+        first_error: RpcHandlerError,
+    ) -> Result<AttributedResponse> {
+        let tags = options.tags.clone().unwrap_or_default();
+        self.hold_with(&request.method, tags, policy, first_error, || self.send_with(request, options, Instant::now())).await
+    }
+
+    /// Like `hold`, retrying whatever `attempt` sends; `name` is what the hold events report.
+    pub(crate) async fn hold_with<T, F, Fut>(
+        self: &Arc<Self>,
+        name: &str,
+        tags: CallTags,
+        policy: HoldPolicy,
+        first_error: RpcHandlerError,
+        mut attempt: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let clock = Arc::clone(self.clock());
+        let started = clock.now_instant();
+        let deadline = started + policy.max_wait;
+        let mut held = Held::enter(self, name, tags);
+        let mut attempts = vec![first_error.to_string()];
+        let mut last = first_error;
+
+        loop {
+            let now = clock.now_instant();
+            if now >= deadline {
+                break;
+            }
+            tokio::select! {
+                _ = clock.sleep(policy.retry_interval.min(deadline - now)) => {}
+                _ = self.shutdown_token().cancelled() => break,
+            }
+            self.refresh_for_held(policy).await;
+
+            let remaining = deadline.saturating_duration_since(clock.now_instant());
+            let result = tokio::select! {
+                result = attempt() => result,
+                _ = clock.sleep(remaining) => break,
+                _ = self.shutdown_token().cancelled() => break,
+            };
+            match result {
+                Err(e) if is_total_failure(&e) => {
+                    attempts.push(e.to_string());
+                    last = e;
+                }
+                answered => {
+                    held.recovered = true;
+                    return answered;
+                }
+            }
+        }
+
+        Err(RpcHandlerError::HoldExpired {
+            held_ms: clock.now_instant().saturating_duration_since(started).as_millis() as u64,
+            attempts,
+            last: Box::new(last),
+        })
+    }
+
+    /// Re-probe so a recovered endpoint can be found, at most once per retry interval however
+    /// many calls are held.
+    async fn refresh_for_held(self: &Arc<Self>, policy: HoldPolicy) {
+        let now = self.clock().now_instant();
+        {
+            let mut last_refresh = self.hold_state().last_refresh.lock();
+            if last_refresh.is_some_and(|at| now.saturating_duration_since(at) < policy.retry_interval) {
+                return;
+            }
+            *last_refresh = Some(now);
+        }
+        // Nothing answering is what got the call here, so a failed refresh is expected
+        let _ = self.refresh().await;
+    }
+}
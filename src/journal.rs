@@ -0,0 +1,473 @@
+//! Failure journal: terminal failures kept across restarts, for telling a provider what went wrong.
+//!
+//! With `HandlerComponents::failure_journal` set, every proxied request that fails for good is
+//! appended to the journal with the attempts it made, as is every consensus read that misses its
+//! quorum, with the votes, and every `eth_chainId` answered with another network's id. Requests
+//! that end up answered, after however many failed attempts, leave nothing.
+//!
+//! Entries are redacted before they are queued, and written by a background task, so a slow or
+//! failing store never holds up or fails a request; write errors are only logged.
+//! `summarize` aggregates entries by kind, error class, method and endpoint, and
+//! `FailureSummary::to_markdown` renders that for a support ticket or an issue. Params never
+//! leave the handler: an entry carries a digest of them, equal for equivalent calls. The caller
+//! tags of the call, see `tags`, are kept as they are, so a report names where failures came from.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Write as _,
+    io,
+    sync::Arc,
+    time::SystemTime,
+};
+#[cfg(feature = "persistence")]
+use std::{
+    fs,
+    io::{BufRead, Write as _},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    canonical::request_key, clock::Clock, hex::short_digest, liveness::AttemptFailure, runtime, secrets::Redactor, tags::CallTags, FailureClass, JsonRpcRequest,
+    NetworkId, RpcHandlerError,
+};
+
+/// Entries `MemoryJournal::default` keeps.
+pub const DEFAULT_JOURNAL_ENTRIES: usize = 1000;
+
+/// Size a `FileJournal` file grows to before it is rotated, when none is given.
+pub const DEFAULT_JOURNAL_MAX_BYTES: u64 = 1024 * 1024;
+
+/// What kind of failure an entry records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// A proxied request no endpoint answered
+    RequestFailed,
+    /// A consensus read that missed its quorum
+    ConsensusFailed,
+    /// An `eth_chainId` answer naming another network
+    ChainIdMismatch,
+}
+
+/// One terminal failure, redacted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub at: SystemTime,
+    pub kind: FailureKind,
+    /// Class of the error the caller got
+    pub class: FailureClass,
+    pub method: String,
+    /// The endpoint to blame, when there is exactly one
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// The error, as displayed
+    pub message: String,
+    /// Every failed attempt the request made, oldest first
+    #[serde(default)]
+    pub attempts: Vec<AttemptFailure>,
+    /// Answers per result key, for consensus failures
+    #[serde(default)]
+    pub votes: BTreeMap<String, usize>,
+    /// Keccak-256 of the canonical method and params, first 8 bytes as hex
+    pub payload_digest: String,
+    /// The failed call's caller tags
+    #[serde(default)]
+    pub tags: CallTags,
+}
+
+impl JournalEntry {
+    /// Every endpoint the entry names, once each.
+    pub fn endpoints(&self) -> BTreeSet<&str> {
+        self.endpoint.iter().map(String::as_str).chain(self.attempts.iter().map(|attempt| attempt.url.as_str())).collect()
+    }
+}
+
+/// Store of journal entries. Called from the journal's writer task only.
+pub trait JournalStore: Send + Sync {
+    fn append(&self, entry: &JournalEntry) -> io::Result<()>;
+
+    /// Every entry still kept, oldest first.
+    fn entries(&self) -> io::Result<Vec<JournalEntry>>;
+}
+
+/// Journal held in memory, dropping the oldest entry past `max_entries`.
+#[derive(Debug)]
+pub struct MemoryJournal {
+    max_entries: usize,
+    entries: parking_lot::Mutex<VecDeque<JournalEntry>>,
+}
+
+impl MemoryJournal {
+    pub fn new(max_entries: usize) -> Self {
+        Self { max_entries, entries: parking_lot::Mutex::default() }
+    }
+}
+
+impl Default for MemoryJournal {
+    fn default() -> Self {
+        Self::new(DEFAULT_JOURNAL_ENTRIES)
+    }
+}
+
+impl JournalStore for MemoryJournal {
+    fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let mut entries = self.entries.lock();
+        if entries.len() == self.max_entries {
+            entries.pop_front();
+        }
+        if self.max_entries > 0 {
+            entries.push_back(entry.clone());
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> io::Result<Vec<JournalEntry>> {
+        Ok(self.entries.lock().iter().cloned().collect())
+    }
+}
+
+/// Journal kept as JSON Lines in a file, so entries survive a restart.
+///
+/// Once an entry would take the file past `max_bytes`, the file is renamed to `rotated_path`,
+/// replacing the one rotated before it, and a new one is started; at most about twice
+/// `max_bytes` is kept. Lines that don't parse, such as one cut short by a crash, are skipped
+/// when reading.
+#[cfg(feature = "persistence")]
+#[derive(Debug)]
+pub struct FileJournal {
+    path: PathBuf,
+    max_bytes: u64,
+    /// Size of the file at `path`
+    size: parking_lot::Mutex<u64>,
+}
+
+#[cfg(feature = "persistence")]
+impl FileJournal {
+    /// Open the journal at `path`, appending to the file if it exists.
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64) -> io::Result<Self> {
+        runtime::require_filesystem("FileJournal")?;
+        let path = path.as_ref().to_path_buf();
+        let size = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, max_bytes, size: parking_lot::Mutex::new(size) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where the previous file goes on rotation: `path` with `.1` appended.
+    pub fn rotated_path(&self) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        rotated.into()
+    }
+
+    fn read(path: &Path) -> io::Result<Vec<JournalEntry>> {
+        match fs::File::open(path) {
+            Ok(file) => Ok(io::BufReader::new(file).lines().map_while(|line| line.ok()).filter_map(|line| serde_json::from_str(&line).ok()).collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl JournalStore for FileJournal {
+    fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let mut size = self.size.lock();
+        if *size > 0 && *size + line.len() as u64 > self.max_bytes {
+            fs::rename(&self.path, self.rotated_path())?;
+            *size = 0;
+        }
+        fs::OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(&line)?;
+        *size += line.len() as u64;
+        Ok(())
+    }
+
+    fn entries(&self) -> io::Result<Vec<JournalEntry>> {
+        let _size = self.size.lock();
+        let mut entries = Self::read(&self.rotated_path())?;
+        entries.extend(Self::read(&self.path)?);
+        Ok(entries)
+    }
+}
+
+enum Message {
+    Append(JournalEntry),
+    /// Answered once everything queued before it is written
+    Flush(oneshot::Sender<()>),
+}
+
+/// The handler's side of the journal: redacts entries and queues them for the writer task.
+/// Cloning shares the queue.
+#[derive(Clone)]
+pub struct FailureJournal {
+    store: Arc<dyn JournalStore>,
+    sender: mpsc::UnboundedSender<Message>,
+    redactor: Redactor,
+    clock: Arc<dyn Clock>,
+}
+
+impl std::fmt::Debug for FailureJournal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FailureJournal").finish_non_exhaustive()
+    }
+}
+
+impl FailureJournal {
+    /// A journal writing to `store` from a task of its own, which ends once every clone is dropped.
+    pub(crate) fn new(store: Arc<dyn JournalStore>, clock: Arc<dyn Clock>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let writer = Arc::clone(&store);
+        runtime::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                match message {
+                    Message::Append(entry) => {
+                        let writer = Arc::clone(&writer);
+                        match runtime::spawn_blocking(move || writer.append(&entry)).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => tracing::warn!(error = %e, "Failed to write to the failure journal"),
+                            Err(e) => tracing::warn!(error = %e, "Failure journal write panicked"),
+                        }
+                    }
+                    Message::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Self { store, sender, redactor: Redactor::default(), clock }
+    }
+
+    /// This journal, redacting with `redactor`.
+    pub(crate) fn with_redactor(&self, redactor: Redactor) -> Self {
+        Self { redactor, ..self.clone() }
+    }
+
+    /// Wait for everything queued so far to be written.
+    pub(crate) async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = written.await;
+        }
+    }
+
+    pub(crate) fn entries(&self) -> io::Result<Vec<JournalEntry>> {
+        self.store.entries()
+    }
+
+    /// Record `request`, tagged `tags`, failing with `error` after `attempts`.
+    pub(crate) fn request_failed(&self, request: &JsonRpcRequest, error: &RpcHandlerError, attempts: Vec<AttemptFailure>, tags: CallTags) {
+        let mut urls = attempts.iter().map(|attempt| &attempt.url);
+        let endpoint = urls.next().filter(|first| urls.all(|url| url == *first)).cloned();
+        self.append(request, FailureKind::RequestFailed, error, endpoint, attempts, BTreeMap::new(), tags);
+    }
+
+    /// Record a consensus read, tagged `tags`, failing with `error`, after `votes` and the
+    /// endpoints in `failed` erroring with their message.
+    #[cfg(feature = "consensus")]
+    pub(crate) fn consensus_failed(&self, request: &JsonRpcRequest, error: &RpcHandlerError, votes: BTreeMap<String, usize>, failed: Vec<(String, String)>, tags: CallTags) {
+        let at = self.clock.now_system();
+        let attempts = failed.into_iter().map(|(url, message)| AttemptFailure { at, url, class: FailureClass::Other, message }).collect();
+        self.append(request, FailureKind::ConsensusFailed, error, None, attempts, votes, tags);
+    }
+
+    /// Record `url` answering `eth_chainId` with `answered` on a handler for `expected`.
+    pub(crate) fn chain_id_mismatch(&self, request: &JsonRpcRequest, url: &str, answered: &str, expected: NetworkId) {
+        let entry = JournalEntry {
+            at: self.clock.now_system(),
+            kind: FailureKind::ChainIdMismatch,
+            class: FailureClass::Malformed,
+            method: request.method.clone(),
+            endpoint: Some(self.redactor.redact(url)),
+            message: format!("answered chain id {answered} to a handler for {expected} ({expected:#x})"),
+            attempts: Vec::new(),
+            votes: BTreeMap::new(),
+            payload_digest: payload_digest(request),
+            tags: CallTags::new(),
+        };
+        let _ = self.sender.send(Message::Append(entry));
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn append(
+        &self,
+        request: &JsonRpcRequest,
+        kind: FailureKind,
+        error: &RpcHandlerError,
+        endpoint: Option<String>,
+        attempts: Vec<AttemptFailure>,
+        votes: BTreeMap<String, usize>,
+        tags: CallTags,
+    ) {
+        let redact = |text: &str| self.redactor.redact(text);
+        let entry = JournalEntry {
+            at: self.clock.now_system(),
+            kind,
+            class: FailureClass::of(error),
+            method: request.method.clone(),
+            endpoint: endpoint.as_deref().map(redact),
+            message: redact(&error.to_string()),
+            attempts: attempts
+                .into_iter()
+                .map(|attempt| AttemptFailure { url: redact(&attempt.url), message: redact(&attempt.message), ..attempt })
+                .collect(),
+            votes: votes.into_iter().map(|(key, count)| (redact(&key), count)).collect(),
+            payload_digest: payload_digest(request),
+            tags,
+        };
+        let _ = self.sender.send(Message::Append(entry));
+    }
+}
+
+/// Digest of `request`'s canonical method and params.
+pub fn payload_digest(request: &JsonRpcRequest) -> String {
+    short_digest(request_key(&request.method, &request.params).as_bytes())
+}
+
+/// Entries sharing a kind, error class and method.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailureGroup {
+    pub kind: FailureKind,
+    pub class: FailureClass,
+    pub method: String,
+    pub count: usize,
+    /// Distinct payload digests, in order of first appearance
+    pub payload_digests: Vec<String>,
+    /// The latest entry's message
+    pub example: String,
+    pub last_at: SystemTime,
+}
+
+/// Journal entries since a point in time, aggregated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailureSummary {
+    pub since: SystemTime,
+    pub total: usize,
+    pub by_kind: BTreeMap<FailureKind, usize>,
+    pub by_class: BTreeMap<FailureClass, usize>,
+    pub by_method: BTreeMap<String, usize>,
+    /// Entries naming each endpoint, as the one to blame or in an attempt
+    pub by_endpoint: BTreeMap<String, usize>,
+    /// Entries carrying each caller tag, as `key=value`
+    #[serde(default)]
+    pub by_tag: BTreeMap<String, usize>,
+    /// Most frequent first
+    pub groups: Vec<FailureGroup>,
+}
+
+/// Payload digests `FailureGroup::payload_digests` lists.
+const GROUP_DIGESTS: usize = 5;
+
+/// Aggregate the `entries` recorded at or after `since`.
+pub fn summarize(entries: &[JournalEntry], since: SystemTime) -> FailureSummary {
+    let mut summary = FailureSummary {
+        since,
+        total: 0,
+        by_kind: BTreeMap::new(),
+        by_class: BTreeMap::new(),
+        by_method: BTreeMap::new(),
+        by_endpoint: BTreeMap::new(),
+        by_tag: BTreeMap::new(),
+        groups: Vec::new(),
+    };
+    let mut groups: BTreeMap<(FailureKind, FailureClass, &str), FailureGroup> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| entry.at >= since) {
+        summary.total += 1;
+        *summary.by_kind.entry(entry.kind).or_default() += 1;
+        *summary.by_class.entry(entry.class).or_default() += 1;
+        *summary.by_method.entry(entry.method.clone()).or_default() += 1;
+        for url in entry.endpoints() {
+            *summary.by_endpoint.entry(url.to_string()).or_default() += 1;
+        }
+        for (key, value) in &entry.tags {
+            *summary.by_tag.entry(format!("{key}={value}")).or_default() += 1;
+        }
+        let group = groups.entry((entry.kind, entry.class, &entry.method)).or_insert_with(|| FailureGroup {
+            kind: entry.kind,
+            class: entry.class,
+            method: entry.method.clone(),
+            count: 0,
+            payload_digests: Vec::new(),
+            example: String::new(),
+            last_at: entry.at,
+        });
+        group.count += 1;
+        if group.payload_digests.len() < GROUP_DIGESTS && !group.payload_digests.contains(&entry.payload_digest) {
+            group.payload_digests.push(entry.payload_digest.clone());
+        }
+        if entry.at >= group.last_at || group.example.is_empty() {
+            group.example = entry.message.clone();
+            group.last_at = entry.at;
+        }
+    }
+    summary.groups = groups.into_values().collect();
+    summary.groups.sort_by_key(|group| Reverse(group.count));
+    summary
+}
+
+fn timestamp(at: SystemTime) -> String {
+    DateTime::<Utc>::from(at).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// `text` safe in a Markdown table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+fn counts_table<K>(out: &mut String, heading: &str, column: &str, counts: &BTreeMap<K, usize>, label: impl Fn(&K) -> String) {
+    if counts.is_empty() {
+        return;
+    }
+    let mut rows: Vec<(&K, &usize)> = counts.iter().collect();
+    rows.sort_by(|a, b| b.1.cmp(a.1));
+    let _ = writeln!(out, "\n### {heading}\n\n| {column} | Failures |\n| --- | ---: |");
+    for (key, count) in rows {
+        let _ = writeln!(out, "| {} | {count} |", cell(&label(key)));
+    }
+}
+
+impl FailureSummary {
+    /// The summary as Markdown, for a provider support ticket or an issue.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::from("## Failure summary\n\n");
+        if self.total == 0 {
+            let _ = writeln!(out, "No failures recorded since {}.", timestamp(self.since));
+            return out;
+        }
+        let _ = writeln!(out, "{} failures recorded since {}.", self.total, timestamp(self.since));
+        counts_table(&mut out, "By kind", "Kind", &self.by_kind, |kind| format!("{kind:?}"));
+        counts_table(&mut out, "By error class", "Class", &self.by_class, |class| format!("{class:?}"));
+        counts_table(&mut out, "By method", "Method", &self.by_method, |method| format!("`{method}`"));
+        counts_table(&mut out, "By endpoint", "Endpoint", &self.by_endpoint, |url| url.clone());
+        counts_table(&mut out, "By caller tag", "Tag", &self.by_tag, |tag| format!("`{tag}`"));
+
+        let _ = writeln!(out, "\n### Examples\n\n| Kind | Class | Method | Failures | Last seen | Payload digests | Example |\n| --- | --- | --- | ---: | --- | --- | --- |");
+        for group in &self.groups {
+            let digests: Vec<String> = group.payload_digests.iter().map(|digest| format!("`{digest}`")).collect();
+            let _ = writeln!(
+                out,
+                "| {:?} | {:?} | `{}` | {} | {} | {} | {} |",
+                group.kind,
+                group.class,
+                cell(&group.method),
+                group.count,
+                timestamp(group.last_at),
+                digests.join(" "),
+                cell(&group.example),
+            );
+        }
+        out
+    }
+}
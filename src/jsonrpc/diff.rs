@@ -0,0 +1,216 @@
+//! Structural differences between two JSON-RPC results, for finding which field two providers
+//! disagreed on without diffing whole blocks by hand.
+//!
+//! Only semantic differences are reported: hex strings compare case-insensitively and fields on
+//! `DiffOptions::ignore_fields` are skipped wherever they appear. Arrays compare element by
+//! element over their common length, with a differing length reported once at the array.
+//!
+//! Values in the report are cut down to `max_leaf_len` characters. A subtree deeper than
+//! `max_depth` is compared whole and reported at its root, and comparison stops after
+//! `max_nodes` nodes or `max_differences` differences, marking the diff incomplete.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Fields some providers add to blocks and others leave out, e.g. `totalDifficulty` since the merge.
+pub const DEFAULT_IGNORED_FIELDS: &[&str] = &["totalDifficulty"];
+
+/// How `diff_values` compares.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffOptions {
+    /// Object keys skipped at any depth
+    pub ignore_fields: Vec<String>,
+    /// Compare `0x` strings case-insensitively
+    pub ignore_hex_case: bool,
+    /// Levels below the root compared field by field
+    pub max_depth: usize,
+    /// Nodes visited before giving up
+    pub max_nodes: usize,
+    /// Differences recorded before giving up
+    pub max_differences: usize,
+    /// Characters of a reported value kept
+    pub max_leaf_len: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            ignore_fields: DEFAULT_IGNORED_FIELDS.iter().map(|field| field.to_string()).collect(),
+            ignore_hex_case: true,
+            max_depth: 32,
+            max_nodes: 100_000,
+            max_differences: 1_000,
+            max_leaf_len: 80,
+        }
+    }
+}
+
+/// Everything `diff_values` found, in the order it walked the values.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueDiff {
+    pub differences: Vec<Difference>,
+    /// Comparison stopped at `max_nodes` or `max_differences`, so there may be more
+    pub incomplete: bool,
+}
+
+/// One place the values differ.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Difference {
+    /// From `$`, the root, e.g. `$.transactions[3].logs[0].data`
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: DifferenceKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DifferenceKind {
+    /// Present on both sides with different values, or different types
+    Changed { left: Value, right: Value },
+    /// An object field only the left side has
+    OnlyLeft { value: Value },
+    /// An object field only the right side has
+    OnlyRight { value: Value },
+    /// Arrays of different lengths; their common elements are compared on their own
+    LengthMismatch { left: usize, right: usize },
+}
+
+impl DifferenceKind {
+    /// Lower sorts first in `ValueDiff::most_significant`: changed values, then lengths, then
+    /// fields on one side only, which are most often a provider's extras.
+    fn rank(&self) -> u8 {
+        match self {
+            DifferenceKind::Changed { .. } => 0,
+            DifferenceKind::LengthMismatch { .. } => 1,
+            DifferenceKind::OnlyLeft { .. } | DifferenceKind::OnlyRight { .. } => 2,
+        }
+    }
+}
+
+impl Difference {
+    fn depth(&self) -> usize {
+        self.path.matches(['.', '[']).count()
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match &self.kind {
+            DifferenceKind::Changed { left, right } => write!(f, "{path}: {left} != {right}"),
+            DifferenceKind::OnlyLeft { value } => write!(f, "{path}: only left, {value}"),
+            DifferenceKind::OnlyRight { value } => write!(f, "{path}: only right, {value}"),
+            DifferenceKind::LengthMismatch { left, right } => write!(f, "{path}: length {left} != {right}"),
+        }
+    }
+}
+
+impl ValueDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    /// The `k` differences most likely to matter: changed values before length mismatches
+    /// before one-sided fields, shallower paths first within each.
+    pub fn most_significant(&self, k: usize) -> Vec<Difference> {
+        let mut ranked: Vec<&Difference> = self.differences.iter().collect();
+        ranked.sort_by_key(|difference| (difference.kind.rank(), difference.depth()));
+        ranked.into_iter().take(k).cloned().collect()
+    }
+}
+
+/// The semantic differences between `left` and `right`. See the module docs.
+pub fn diff_values(left: &Value, right: &Value, options: &DiffOptions) -> ValueDiff {
+    let mut walk = Walk { options, diff: ValueDiff::default(), nodes: 0 };
+    walk.compare("$".to_string(), left, right, 0);
+    walk.diff
+}
+
+struct Walk<'a> {
+    options: &'a DiffOptions,
+    diff: ValueDiff,
+    nodes: usize,
+}
+
+impl Walk<'_> {
+    fn compare(&mut self, path: String, left: &Value, right: &Value, depth: usize) {
+        if self.diff.incomplete {
+            return;
+        }
+        self.nodes += 1;
+        if self.nodes > self.options.max_nodes {
+            self.diff.incomplete = true;
+            return;
+        }
+        match (left, right) {
+            (Value::Object(left), Value::Object(right)) if depth < self.options.max_depth => {
+                let options = self.options;
+                let ignored = |key: &String| options.ignore_fields.contains(key);
+                for (key, left_value) in left.iter().filter(|(key, _)| !ignored(key)) {
+                    match right.get(key) {
+                        Some(right_value) => self.compare(format!("{path}.{key}"), left_value, right_value, depth + 1),
+                        None => self.record(format!("{path}.{key}"), DifferenceKind::OnlyLeft { value: self.truncate(left_value) }),
+                    }
+                }
+                for (key, right_value) in right.iter().filter(|(key, _)| !ignored(key) && !left.contains_key(*key)) {
+                    self.record(format!("{path}.{key}"), DifferenceKind::OnlyRight { value: self.truncate(right_value) });
+                }
+            }
+            (Value::Array(left), Value::Array(right)) if depth < self.options.max_depth => {
+                if left.len() != right.len() {
+                    self.record(path.clone(), DifferenceKind::LengthMismatch { left: left.len(), right: right.len() });
+                }
+                for (i, (left_item, right_item)) in left.iter().zip(right).enumerate() {
+                    self.compare(format!("{path}[{i}]"), left_item, right_item, depth + 1);
+                }
+            }
+            _ if self.equivalent(left, right) => {}
+            _ => self.record(path, DifferenceKind::Changed { left: self.truncate(left), right: self.truncate(right) }),
+        }
+    }
+
+    /// Equal once hex case and ignored fields are set aside, for leaves and subtrees past `max_depth`.
+    fn equivalent(&self, left: &Value, right: &Value) -> bool {
+        match (left, right) {
+            (Value::String(left), Value::String(right)) if self.options.ignore_hex_case && left.starts_with("0x") && right.starts_with("0x") => {
+                left.eq_ignore_ascii_case(right)
+            }
+            (Value::Object(left), Value::Object(right)) => {
+                let kept = |object: &serde_json::Map<String, Value>| object.keys().filter(|key| !self.options.ignore_fields.contains(key)).count();
+                kept(left) == kept(right)
+                    && left
+                        .iter()
+                        .filter(|(key, _)| !self.options.ignore_fields.contains(key))
+                        .all(|(key, value)| right.get(key).is_some_and(|other| self.equivalent(value, other)))
+            }
+            (Value::Array(left), Value::Array(right)) => left.len() == right.len() && left.iter().zip(right).all(|(a, b)| self.equivalent(a, b)),
+            _ => left == right,
+        }
+    }
+
+    fn record(&mut self, path: String, kind: DifferenceKind) {
+        if self.diff.differences.len() >= self.options.max_differences {
+            self.diff.incomplete = true;
+            return;
+        }
+        self.diff.differences.push(Difference { path, kind });
+    }
+
+    /// `value` as reported: short values whole, longer ones as their first `max_leaf_len`
+    /// characters with the full length noted.
+    fn truncate(&self, value: &Value) -> Value {
+        let text = match value {
+            Value::String(text) => text.clone(),
+            Value::Object(_) | Value::Array(_) => value.to_string(),
+            _ => return value.clone(),
+        };
+        let len = text.chars().count();
+        if len <= self.options.max_leaf_len {
+            return value.clone();
+        }
+        let kept: String = text.chars().take(self.options.max_leaf_len).collect();
+        Value::String(format!("{kept}… ({len} chars)"))
+    }
+}
@@ -0,0 +1,220 @@
+pub mod diff;
+
+pub use diff::{diff_values, DiffOptions, Difference, DifferenceKind, ValueDiff};
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{
+    de::{self, DeserializeOwned, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Number, Value};
+
+use crate::{error::kb, Result, RpcHandlerError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: Value,
+    /// `None` leaves the id out, making the request a notification; `Some(JsonRpcId::Null)`
+    /// sends an explicit `null`.
+    #[serde(default, deserialize_with = "present_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<JsonRpcId>,
+}
+
+/// The id the next `JsonRpcRequest::new` takes, shared by the whole process.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+impl JsonRpcRequest {
+    /// A JSON-RPC 2.0 request with a fresh numeric id, unique among the requests built this way
+    /// in the process, so requests multiplexed over one connection or batch can't collide.
+    pub fn new(method: impl Into<String>, params: Value) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Self { jsonrpc: "2.0".to_string(), method: method.into(), params, id: Some(JsonRpcId::Number(id.into())) }
+    }
+
+    /// The same request under `id` instead.
+    pub fn with_id(self, id: impl Into<JsonRpcId>) -> Self {
+        Self { id: Some(id.into()), ..self }
+    }
+
+    /// The id as a `u64`, the type it had before string and `null` ids were accepted. `None` when
+    /// it is absent or anything other than a non-negative integer.
+    pub fn numeric_id(&self) -> Option<u64> {
+        self.id.as_ref().and_then(JsonRpcId::as_u64)
+    }
+}
+
+/// A request id as JSON-RPC 2.0 allows it: a number, a string or `null`. A response echoes the
+/// id it answers with the same JSON type, so `1` and `"1"` are different ids. Numbers are kept
+/// as they were written, negative and fractional ones included.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum JsonRpcId {
+    Number(Number),
+    String(String),
+    Null,
+}
+
+impl JsonRpcId {
+    /// The id as a `u64`, when it is a non-negative integer. A fractional `1.0` is not one.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            JsonRpcId::Number(id) => id.as_u64(),
+            _ => None,
+        }
+    }
+}
+
+impl From<u64> for JsonRpcId {
+    fn from(id: u64) -> Self {
+        JsonRpcId::Number(id.into())
+    }
+}
+
+impl From<String> for JsonRpcId {
+    fn from(id: String) -> Self {
+        JsonRpcId::String(id)
+    }
+}
+
+impl From<&str> for JsonRpcId {
+    fn from(id: &str) -> Self {
+        JsonRpcId::String(id.to_string())
+    }
+}
+
+impl fmt::Display for JsonRpcId {
+    /// As it appears in JSON, so a string id is quoted.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonRpcId::Number(id) => write!(f, "{id}"),
+            JsonRpcId::String(id) => write!(f, "{}", Value::String(id.clone())),
+            JsonRpcId::Null => f.write_str("null"),
+        }
+    }
+}
+
+impl Serialize for JsonRpcId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            JsonRpcId::Number(id) => id.serialize(serializer),
+            JsonRpcId::String(id) => serializer.serialize_str(id),
+            JsonRpcId::Null => serializer.serialize_unit(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonRpcId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(IdVisitor)
+    }
+}
+
+struct IdVisitor;
+
+impl<'de> Visitor<'de> for IdVisitor {
+    type Value = JsonRpcId;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number, a string or null")
+    }
+
+    fn visit_u64<E: de::Error>(self, id: u64) -> std::result::Result<JsonRpcId, E> {
+        Ok(JsonRpcId::Number(id.into()))
+    }
+
+    fn visit_i64<E: de::Error>(self, id: i64) -> std::result::Result<JsonRpcId, E> {
+        Ok(JsonRpcId::Number(id.into()))
+    }
+
+    fn visit_f64<E: de::Error>(self, id: f64) -> std::result::Result<JsonRpcId, E> {
+        Number::from_f64(id).map(JsonRpcId::Number).ok_or_else(|| E::invalid_value(de::Unexpected::Float(id), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, id: &str) -> std::result::Result<JsonRpcId, E> {
+        Ok(JsonRpcId::String(id.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, id: String) -> std::result::Result<JsonRpcId, E> {
+        Ok(JsonRpcId::String(id))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> std::result::Result<JsonRpcId, E> {
+        Ok(JsonRpcId::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> std::result::Result<JsonRpcId, E> {
+        Ok(JsonRpcId::Null)
+    }
+}
+
+/// Keep a present `null` id apart from an absent one, which `Option`'s own impl would merge.
+fn present_id<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<JsonRpcId>, D::Error> {
+    JsonRpcId::deserialize(deserializer).map(Some)
+}
+
+/// Several calls sent in one round trip, in the JSON-RPC array form.
+pub type JsonRpcBatch = Vec<JsonRpcRequest>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: DeserializeOwned"))]
+pub struct JsonRpcResponse<T> {
+    pub jsonrpc: String,
+    /// `None` only when the field is absent: a `result: null` is `Some` for any `T` that can
+    /// hold it, such as `Value::Null` or `Option::None`.
+    #[serde(default, deserialize_with = "present_result")]
+    pub result: Option<T>,
+    pub error: Option<JsonRpcError>,
+    /// The id of the request answered, `None` when the response left it out.
+    #[serde(default, deserialize_with = "present_id")]
+    pub id: Option<JsonRpcId>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+/// Keep a present `null` apart from an absent field, for a `T` that can represent it.
+fn present_result<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(T::deserialize(Value::Null).ok()),
+        value => T::deserialize(value).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+impl JsonRpcError {
+    /// Whether another endpoint, or the same one a moment later, may answer differently.
+    ///
+    /// Internal errors, limits and unavailable resources are provider-side; malformed requests,
+    /// unknown methods, bad params and reverts would fail the same way anywhere. Classified by
+    /// the built-in table alone; the handler also consults `HandlerSettings::error_mappings`.
+    pub fn is_retryable(&self) -> bool {
+        kb::classify_jsonrpc_error(self, "").is_retryable()
+    }
+}
+
+impl<T> JsonRpcResponse<T> {
+    /// Unwrap the `result`, turning an embedded JSON-RPC error or a missing result into an error.
+    pub fn into_result(self) -> Result<T> {
+        if let Some(error) = self.error {
+            return Err(RpcHandlerError::JsonRpcCode { code: error.code, message: error.message });
+        }
+        self.result
+            .ok_or_else(|| RpcHandlerError::SerializationError("response has neither result nor error".to_string()))
+    }
+
+    /// The answered id as a `u64`, as `JsonRpcRequest::numeric_id` reads it.
+    pub fn numeric_id(&self) -> Option<u64> {
+        self.id.as_ref().and_then(JsonRpcId::as_u64)
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio_util::sync::CancellationToken;
+use web_time::Instant;
+
+use crate::{config::KeepaliveConfig, readiness::Heartbeat, runtime::{self, JoinHandle}, RpcHandler};
+
+/// Consecutive keepalive failures after which the active provider is re-selected.
+pub const KEEPALIVE_DEMOTE_AFTER: u32 = 3;
+
+/// How long to wait before the next keepalive check, or `None` if a ping is due now.
+///
+/// `idle` is the time since the last real request and `since_ping` the time since the last ping,
+/// if one was sent during the current idle period.
+pub fn next_ping_delay(
+    idle: Duration,
+    since_ping: Option<Duration>,
+    config: &KeepaliveConfig,
+) -> Option<Duration> {
+    if idle < config.after {
+        return Some(config.after - idle);
+    }
+    match since_ping {
+        Some(since) if since < config.interval => Some(config.interval - since),
+        _ => None,
+    }
+}
+
+/// Spawn the keepalive loop for `handler`. It holds only a weak reference, so it ends on its own
+/// once the handler is dropped, or immediately when `shutdown` is cancelled.
+pub(crate) fn spawn_keepalive(
+    handler: &Arc<RpcHandler>,
+    config: KeepaliveConfig,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+    let clock = Arc::clone(handler.clock());
+
+    runtime::spawn(async move {
+        let mut last_ping: Option<Instant> = None;
+
+        loop {
+            let delay = {
+                let Some(handler) = weak.upgrade() else { return };
+
+                match handler.get_provider().await {
+                    Ok(provider) => {
+                        let idle = provider.idle_for();
+                        // A ping from before the latest request doesn't belong to this idle period
+                        let since_ping = last_ping.map(|at| clock.elapsed_since(at)).filter(|since| *since < idle);
+
+                        match next_ping_delay(idle, since_ping, &config) {
+                            Some(delay) => delay,
+                            None => {
+                                last_ping = Some(clock.now_instant());
+                                let result = provider.ping().await;
+                                handler.record_keepalive(&provider.base_url, result).await;
+                                config.interval
+                            }
+                        }
+                    }
+                    Err(_) => config.interval,
+                }
+            };
+
+            heartbeat.beat();
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = clock.sleep(delay) => {}
+            }
+        }
+    })
+}
@@ -1,27 +1,165 @@
+#[cfg(feature = "abi")]
+pub mod abi;
+pub mod agreement;
+pub mod auto_refresh;
+pub mod backfill;
+pub mod block_search;
+pub mod broadcast;
+pub mod cache;
 pub mod calls;
+pub mod canonical;
 pub mod chainlist;
+pub mod clock;
+pub mod comparator;
+pub mod confidence;
 pub mod config;
+#[cfg(feature = "consensus")]
+pub mod consensus;
+pub mod degraded;
+pub mod diagnostics;
+pub mod doctor;
+#[cfg(feature = "consensus")]
+pub mod ens;
 pub mod error;
+pub mod events;
+pub mod fanout;
+pub mod filters;
 pub mod handler;
+pub mod head;
+pub mod health;
+pub(crate) mod hex;
+pub mod hold;
+pub mod journal;
 pub mod jsonrpc;
+pub mod keepalive;
+pub mod liveness;
+pub mod localnet;
+pub mod location;
+pub mod maintenance;
+pub mod memory;
+pub mod methods;
+pub mod metrics;
+#[cfg(feature = "abi")]
+pub mod multicall;
+#[cfg(feature = "consensus")]
+pub mod multichain;
+pub mod namespaces;
+pub mod ordered;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod panics;
 pub mod performance;
+pub mod prelude;
+pub mod proof;
 pub mod provider;
+pub mod readiness;
+pub mod receipts;
+pub mod region;
+pub mod reorg;
+pub mod registry;
+pub mod reload;
+pub mod rotation;
+pub mod routing;
 pub mod rpc;
+pub(crate) mod runtime;
+#[cfg(feature = "scenarios")]
+pub mod scenario;
+#[cfg(all(feature = "scenarios", target_arch = "wasm32"))]
+compile_error!("the `scenarios` feature serves endpoints from local TCP listeners, which wasm32 doesn't have");
+pub mod secrets;
+pub mod session;
+pub mod shadow;
+pub mod slo;
+pub mod spend;
 pub mod strategy;
+pub mod tags;
+#[cfg(feature = "consensus")]
+pub mod temporal;
+pub mod timestamps;
+pub mod transport;
+pub mod tuning;
 pub mod types;
+pub mod validation;
 
 // Legacy module for backward compatibility
 pub mod rpc_service;
 
-pub use error::{RpcHandlerError, Result};
-pub use handler::RpcHandler;
-pub use jsonrpc::{JsonRpcRequest, JsonRpcResponse, JsonRpcError};
+#[cfg(feature = "abi")]
+pub use abi::{decode_return, AbiType, AbiValue, CallDataBuilder};
+#[cfg(feature = "abi")]
+pub use multicall::{MulticallItem, MulticallOptions, MulticallResult};
+pub use agreement::{AgreementRound, AgreementStats};
+pub use clock::{Clock, SystemClock};
+pub use confidence::{ConfidenceOptions, ConfidentValue, Corroboration, Evidence, Finality};
+#[cfg(feature = "test-util")]
+pub use clock::MockClock;
+pub use diagnostics::{DiagnosticsBundle, DiagnosticsOptions};
+pub use doctor::{CheckOutcome, CheckResult, DoctorCheck, DoctorReport};
+#[cfg(feature = "consensus")]
+pub use ens::{namehash, EnsOptions, EnsResolution};
+pub use error::{RpcHandlerError, Result, TimeoutPhase};
+pub use error::kb::{classify_jsonrpc_error, ErrorCondition, ErrorMapping, MessagePattern};
+pub use events::{HandlerEvent, InitState, RecordedEvent};
+pub use filters::ManagedFilter;
+pub use handler::{HandlerComponents, RpcHandler};
+pub use health::{EndpointHealth, HealthPage, HealthReport, Order, SortBy};
+pub use liveness::{AttemptFailure, RpcProvenance};
+pub use localnet::{LocalNode, LocalnetOptions};
+pub use location::{DefaultRouteLocation, LatencySnapshot, LatencyStore, LocationProvider, MemoryLatencyStore};
+#[cfg(target_arch = "wasm32")]
+pub use location::LocalStorageLatencyStore;
+pub use maintenance::MaintenanceWindow;
+pub use memory::MemoryReport;
+pub use registry::{HandlerRegistry, RegistryOptions, RegistryStats};
+pub use readiness::{BackgroundTask, LivenessStatus, NotLive, NotReady, ReadinessStatus, TaskHeartbeat};
+pub use reload::{ConfigDiff, FieldChange};
+#[cfg(feature = "consensus")]
+pub use multichain::{AgreedRead, ChainRead, CrossChainOptions, CrossChainResult, MultiChainHandler, SkewReport};
+pub use metrics::{CounterValues, EndpointDelta, EndpointValues, FailureClass, HistogramValues, MetricsDelta, MetricsSnapshot, RequestLatency, RequestTiming, TaggedCounts, TaggedValues};
+pub use journal::{summarize, FailureGroup, FailureKind, FailureSummary, JournalEntry, JournalStore, MemoryJournal};
+pub use jsonrpc::{diff_values, DiffOptions, Difference, DifferenceKind, JsonRpcBatch, JsonRpcId, JsonRpcRequest, JsonRpcResponse, JsonRpcError, ValueDiff};
 pub use types::{
-    NetworkId, NetworkName, Rpc, Tracking, LogLevel,
-    LatencyRecord, HandlerConfig, ProxySettings, HandlerSettings, WipeChainData
+    NetworkId, NetworkName, Rpc, RpcConfig, Tracking, LogLevel, FailoverPolicy, Egress,
+    LatencyRecord, HandlerConfig, ProxySettings, HandlerSettings, DataScope, KeepaliveSettings, AutoRefreshSettings, ProbeSmearing, InitPolicy, RetryTuning, MemoryLimits, AdaptiveProbeTimeout, HostLimits, ConstrainedMode, ReadinessThresholds, RateLimitHeaders, RateLimitScheme, LatencySlo, SloMeasure, CustomProbePolicy, TimestampSanity, DataStaleness, AgreementSampling, RegionAffinityPolicy, RouteRule, ValidationMode
 };
+#[allow(deprecated)]
+pub use types::WipeChainData;
 
 // Re-export commonly used items
-pub use calls::RpcCalls;
-pub use config::{NormalizedConfig, resolve_config};
-pub use strategy::Strategy;
\ No newline at end of file
+pub use backfill::{
+    BackfillCheckpoint, BackfillOptions, BackfillProgress, BackfillReport, BackfillRunner, BackfillTask, BlockContext, BlockSink, CheckpointStore,
+    MemoryCheckpointStore,
+};
+pub use block_search::{BlockMatch, BlockStamp, SearchHint};
+pub use reorg::{CanonicalRetry, ConsistencyCheck};
+#[cfg(feature = "persistence")]
+pub use {backfill::FileCheckpointStore, broadcast::FileLedger, journal::FileJournal, location::FileLatencyStore, spend::FileSpendStore};
+pub use broadcast::{BroadcastLedger, BroadcastOptions, BroadcastReport, MemoryLedger};
+pub use cache::{CacheStats, ResponseCache};
+pub use canonical::{canonicalize_params, is_valid_checksum, request_key, CanonicalParams};
+pub use calls::{ConsensusOptions, RpcCalls};
+#[cfg(feature = "consensus")]
+pub use consensus::{AppliedCooldown, ConsensusReport, EndpointOutcome};
+#[cfg(feature = "consensus")]
+pub use temporal::{Recheck, RoundSummary, TemporalOptions, TemporalReport};
+pub use provider::{AttributedResponse, BatchEntry, BatchOptions, HostQuota, ResultSink, StreamSummary, DEFAULT_USER_AGENT};
+pub use provider::plan::{CallOptions, Exclusion, ExcludedUrl, HoldPolicy, Placement, PlannedUrl, RequestPlan};
+pub use comparator::{HashOnlyBlockComparator, NumericToleranceComparator, ProofComparator, ResultComparator, StableStringComparator};
+pub use rpc::{RpcOrigin, RpcSource};
+pub use proof::{verify_storage_value, ProofResponse, StorageProof};
+pub use secrets::{EnvSecretResolver, Redactor, SecretResolver};
+pub use session::ConsistencySession;
+pub use shadow::{LatencyDeltaStats, MismatchSample, ShadowReport};
+pub use timestamps::HealthFlag;
+pub use spend::{BudgetScope, CostProfile, DailySpend, EndpointSpend, MemorySpendStore, SpendReport, SpendStore};
+pub use ordered::{HealthCheckLevel, OrderedRpc};
+pub use performance::{EndpointProbe, NamedProbeOutcome, ProbeOutcome};
+pub use transport::{HttpTransport, HttpTransportFactory, JsonRpcTransport, ProbeExchange, TransportFactory};
+pub use fanout::{Attribution, Endpoint, FailedAttempt, QuorumConfig, QuorumOutcome, RaceConfig};
+#[cfg(feature = "otel")]
+pub use otel::SharedTracerProvider;
+pub use namespaces::{BlockReceiptsMethod, EndpointCapabilities, TxPoolStatus};
+pub use config::{EffectivePolicy, NormalizedConfig, PartialHandlerSettings, Profile, resolve_config, resolve_config_with};
+pub use strategy::Strategy;
+pub use tags::CallTags;
+pub use region::Region;
@@ -0,0 +1,182 @@
+//! Liveness history: whether each endpoint was up, as this handler saw it, and since when.
+//!
+//! Every probe, keepalive ping and request attempt is an outcome for its endpoint: up when it
+//! answered with JSON-RPC, even with an error, and down when it couldn't be reached, timed out, was
+//! rate limited or answered with something else. The last `LIVENESS_HISTORY_LEN` outcomes are kept
+//! per endpoint, each packed into one word with its timestamp.
+//!
+//! Uptime over a window is time-weighted: an endpoint is taken to stay up or down from one outcome
+//! until the next, and the time before its oldest kept outcome doesn't count either way.
+//!
+//! The last `RECENT_FAILURES_LEN` failed attempts are also kept across all endpoints, with the
+//! error they failed with, for diagnostics.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{rpc::RpcOrigin, FailureClass, NetworkId, RpcHandlerError};
+
+/// Outcomes kept per endpoint.
+pub const LIVENESS_HISTORY_LEN: usize = 512;
+
+/// Failed attempts kept, across all endpoints.
+pub const RECENT_FAILURES_LEN: usize = 100;
+
+/// The window the uptime in health reports and provenance is taken over.
+pub const REPORTED_UPTIME_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where an endpoint came from and how it has been doing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcProvenance {
+    /// Redacted
+    pub url: String,
+    pub origin: RpcOrigin,
+    /// The alias of the network it was listed under, `None` for the network's own id
+    #[serde(default)]
+    pub listed_under: Option<NetworkId>,
+    /// The chain id it answered `eth_chainId` with, when it was asked
+    #[serde(default)]
+    pub reported_chain_id: Option<NetworkId>,
+    /// The last outcome that found it up, kept after it leaves the history
+    pub last_healthy: Option<SystemTime>,
+    /// Share of the last `REPORTED_UPTIME_WINDOW` it was up, `None` before its first outcome
+    pub uptime: Option<f64>,
+}
+
+/// One failed attempt at an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttemptFailure {
+    pub at: SystemTime,
+    /// Not redacted; `RpcHandler::redact` it before showing it
+    pub url: String,
+    pub class: FailureClass,
+    /// The error, as displayed
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    /// Milliseconds since the Unix epoch, shifted left one bit, with the low bit set when up; oldest first
+    outcomes: VecDeque<u64>,
+    last_healthy: Option<SystemTime>,
+}
+
+/// Recent outcomes per endpoint. Cloning shares them.
+#[derive(Debug, Clone, Default)]
+pub struct LivenessLog {
+    endpoints: Arc<parking_lot::Mutex<HashMap<String, History>>>,
+    /// Oldest first
+    failures: Arc<parking_lot::Mutex<VecDeque<AttemptFailure>>>,
+}
+
+impl LivenessLog {
+    /// Record whether `url` was up at `at`.
+    pub(crate) fn record(&self, url: &str, up: bool, at: SystemTime) {
+        let mut endpoints = self.endpoints.lock();
+        let history = endpoints.entry(url.to_string()).or_default();
+        if history.outcomes.len() == LIVENESS_HISTORY_LEN {
+            history.outcomes.pop_front();
+        }
+        history.outcomes.push_back(millis(at) << 1 | up as u64);
+        if up {
+            history.last_healthy = Some(at);
+        }
+    }
+
+    /// Record an attempt at `url` that failed with `failure`, if any. Failures that say nothing
+    /// about the endpoint, like a spent budget, aren't recorded.
+    pub(crate) fn record_attempt(&self, url: &str, failure: Option<&RpcHandlerError>, at: SystemTime) {
+        if let Some(up) = failure.map_or(Some(true), is_up) {
+            self.record(url, up, at);
+        }
+        if let Some(error) = failure {
+            let mut failures = self.failures.lock();
+            if failures.len() == RECENT_FAILURES_LEN {
+                failures.pop_front();
+            }
+            failures.push_back(AttemptFailure { at, url: url.to_string(), class: FailureClass::of(error), message: error.to_string() });
+        }
+    }
+
+    /// The last `RECENT_FAILURES_LEN` failed attempts, oldest first.
+    pub(crate) fn recent_failures(&self) -> Vec<AttemptFailure> {
+        self.failures.lock().iter().cloned().collect()
+    }
+
+    pub(crate) fn last_healthy(&self, url: &str) -> Option<SystemTime> {
+        self.endpoints.lock().get(url).and_then(|history| history.last_healthy)
+    }
+
+    /// The last outcome that found any endpoint up.
+    pub(crate) fn last_healthy_any(&self) -> Option<SystemTime> {
+        self.endpoints.lock().values().filter_map(|history| history.last_healthy).max()
+    }
+
+    /// The share of `window` up to `now` that `url` was up, `None` before its first outcome.
+    pub(crate) fn uptime(&self, url: &str, window: Duration, now: SystemTime) -> Option<f64> {
+        let endpoints = self.endpoints.lock();
+        let outcomes = endpoints.get(url)?.outcomes.iter().map(|packed| (packed >> 1, packed & 1 == 1));
+        uptime_of(outcomes, millis(now).saturating_sub(window.as_millis() as u64), millis(now))
+    }
+
+    /// Move `from`'s history over to `to`, the same endpoint under a new URL.
+    pub(crate) fn rename(&self, from: &str, to: &str) {
+        let mut endpoints = self.endpoints.lock();
+        if let Some(history) = endpoints.remove(from) {
+            endpoints.insert(to.to_string(), history);
+        }
+    }
+
+    /// Forget endpoints outside `known`.
+    pub(crate) fn retain(&self, known: &HashSet<String>) {
+        self.endpoints.lock().retain(|url, _| known.contains(url));
+    }
+}
+
+/// Whether a failed attempt found the endpoint up, `None` when the failure isn't the endpoint's.
+fn is_up(error: &RpcHandlerError) -> Option<bool> {
+    match FailureClass::of(error) {
+        FailureClass::Timeout | FailureClass::Transport | FailureClass::HttpStatus | FailureClass::RateLimited | FailureClass::NotJsonRpc => Some(false),
+        FailureClass::JsonRpc | FailureClass::Malformed | FailureClass::BehindHead => Some(true),
+        FailureClass::NoEndpoints | FailureClass::BudgetExhausted | FailureClass::Exhausted | FailureClass::Other => None,
+    }
+}
+
+/// Time-weighted uptime over `[start, end]` of `outcomes`, oldest first, each holding until the
+/// next. Outcomes all at one instant are counted instead.
+fn uptime_of(outcomes: impl Iterator<Item = (u64, bool)>, start: u64, end: u64) -> Option<f64> {
+    let (mut up, mut total) = (0u64, 0u64);
+    let (mut seen, mut seen_up) = (0u64, 0u64);
+    let mut current: Option<(u64, bool)> = None;
+    for (at, is_up) in outcomes.filter(|(at, _)| *at <= end) {
+        if at >= start {
+            seen += 1;
+            seen_up += is_up as u64;
+        }
+        if let Some((since, was_up)) = current {
+            let span = at.saturating_sub(since.max(start));
+            total += span;
+            up += if was_up { span } else { 0 };
+        }
+        current = Some((at, is_up));
+    }
+    let (since, was_up) = current?;
+    let span = end.saturating_sub(since.max(start));
+    total += span;
+    up += if was_up { span } else { 0 };
+
+    match (total, seen) {
+        (0, 0) => None,
+        (0, _) => Some(seen_up as f64 / seen as f64),
+        _ => Some(up as f64 / total as f64),
+    }
+}
+
+fn millis(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
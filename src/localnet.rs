@@ -0,0 +1,126 @@
+//! Serving a development node on this machine, such as anvil or hardhat, without configuring it.
+//!
+//! `HandlerConfig::localnet` asks the conventional local addresses, after any listed in
+//! `EZ_WEB3_RPC_LOCAL_NODES`, for `eth_chainId` at once, and builds a config for the first of them
+//! in that order that answers, on the network it reported. The config sets
+//! `HandlerSettings::localnet`, which leaves the chainlist endpoints out and skips the Permit2
+//! bytecode and sync checks when probing, and it retries quickly: a local node is either up or not.
+//!
+//! ```no_run
+//! # async fn run() -> ez_web3_rpc::Result<()> {
+//! use ez_web3_rpc::prelude::*;
+//!
+//! let handler = RpcHandler::localnet().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use futures::future::join_all;
+use serde_json::json;
+
+use crate::{
+    config::HandlerConfigBuilder,
+    error::{Result, RpcHandlerError},
+    jsonrpc::{JsonRpcRequest, JsonRpcResponse},
+    namespaces::parse_quantity,
+    provider::{post_json_rpc, rpc_client},
+    runtime,
+    HandlerConfig, NetworkId, RpcHandler,
+};
+
+/// Comma-separated endpoint URLs to try before the conventional ones.
+pub const LOCAL_NODES_ENV: &str = "EZ_WEB3_RPC_LOCAL_NODES";
+
+/// Where anvil, a second anvil, hardhat bound to `localhost`, and ganache listen by default.
+pub const DEFAULT_LOCAL_NODES: &[&str] = &["http://127.0.0.1:8545", "http://127.0.0.1:8546", "http://localhost:8545", "http://127.0.0.1:7545"];
+
+/// Where to look for a local node, and for how long.
+#[derive(Debug, Clone)]
+pub struct LocalnetOptions {
+    /// Tried at once; the first in this order that answers is adopted
+    pub urls: Vec<String>,
+    /// How long an address has to answer `eth_chainId`
+    pub probe_timeout_ms: u64,
+}
+
+impl Default for LocalnetOptions {
+    /// The addresses in `EZ_WEB3_RPC_LOCAL_NODES`, then `DEFAULT_LOCAL_NODES`.
+    fn default() -> Self {
+        let listed = std::env::var(LOCAL_NODES_ENV).map(|value| parse_local_nodes(&value)).unwrap_or_default();
+        let urls = listed.into_iter().chain(DEFAULT_LOCAL_NODES.iter().map(|url| url.to_string())).collect();
+        Self { urls, probe_timeout_ms: 300 }
+    }
+}
+
+/// The URLs in a comma-separated list, as `EZ_WEB3_RPC_LOCAL_NODES` holds them.
+pub fn parse_local_nodes(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|url| !url.is_empty()).map(str::to_string).collect()
+}
+
+/// A local node that answered, and the network it reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalNode {
+    pub url: String,
+    pub chain_id: NetworkId,
+}
+
+impl LocalNode {
+    /// A config for this node, to adjust before `build`: probing and retries suited to a local
+    /// node, and `HandlerSettings::localnet` set.
+    pub fn config_builder(&self) -> HandlerConfigBuilder {
+        HandlerConfig::builder(self.chain_id)
+            .rpc_url(&self.url)
+            .probe_timeout_ms(500)
+            .retry_count(2)
+            .retry_delay_ms(25)
+            .connect_timeout_ms(250)
+            .with(|settings| settings.localnet = true)
+    }
+}
+
+/// The first of `options.urls` that answers `eth_chainId`, or `NoLocalNodeFound` naming them all.
+pub async fn discover(options: &LocalnetOptions) -> Result<LocalNode> {
+    let client = rpc_client();
+    let timeout = Duration::from_millis(options.probe_timeout_ms);
+    let request = JsonRpcRequest::new("eth_chainId", json!([]));
+    let answers = join_all(options.urls.iter().map(|url| async {
+        runtime::timeout(timeout, chain_id(&client, url, &request)).await.ok().flatten()
+    }))
+    .await;
+
+    options
+        .urls
+        .iter()
+        .zip(answers)
+        .find_map(|(url, chain_id)| Some(LocalNode { url: url.clone(), chain_id: chain_id? }))
+        .ok_or_else(|| RpcHandlerError::NoLocalNodeFound { tried: options.urls.clone() })
+}
+
+async fn chain_id(client: &reqwest::Client, url: &str, request: &JsonRpcRequest) -> Option<NetworkId> {
+    let response = post_json_rpc(client, url, request, false, None).await.ok()?;
+    let response: JsonRpcResponse<serde_json::Value> = response.error_for_status().ok()?.json().await.ok()?;
+    response.result.as_ref().and_then(parse_quantity)
+}
+
+impl HandlerConfig {
+    /// A config for the first local node found where `LocalnetOptions::default()` looks.
+    pub async fn localnet() -> Result<Self> {
+        Self::localnet_with(&LocalnetOptions::default()).await
+    }
+
+    /// A config for the first local node found where `options` looks.
+    pub async fn localnet_with(options: &LocalnetOptions) -> Result<Self> {
+        discover(options).await?.config_builder().build()
+    }
+}
+
+impl RpcHandler {
+    /// An initialized handler for the first local node found. See the module docs.
+    pub async fn localnet() -> Result<Arc<Self>> {
+        let handler = Self::new(HandlerConfig::localnet().await?, None).await?;
+        handler.init().await?;
+        Ok(handler)
+    }
+}
@@ -0,0 +1,257 @@
+//! Latency snapshots kept across restarts, per network and client location.
+//!
+//! Latencies measured from one network say little about another: after moving from the office
+//! to home or onto a VPN, the endpoint that was nearest can be the furthest. A `LatencyStore`
+//! keeps one snapshot per network and location, and a handler only restores the one taken where
+//! it is now. Locations are told apart by a `LocationProvider`'s fingerprint, which is hashed
+//! before it is stored or reported, so no address reaches disk.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    net::{IpAddr, UdpSocket},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+#[cfg(feature = "persistence")]
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{hex::short_digest, NetworkId};
+
+/// How long snapshots are restored for when no TTL is given.
+pub const DEFAULT_SNAPSHOT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Tells the networks a client connects from apart.
+pub trait LocationProvider: Send + Sync {
+    /// Something that differs between networks, e.g. the default gateway's address. It is
+    /// hashed before use. `None` when the location can't be told.
+    fn fingerprint(&self) -> Option<String>;
+}
+
+/// Fingerprints the route to the internet: the prefix of the local address the OS picks for it,
+/// and on Linux the default gateway. Nothing is sent.
+///
+/// A browser doesn't show a page its route, so on `wasm32` every location is the same one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultRouteLocation;
+
+impl LocationProvider for DefaultRouteLocation {
+    fn fingerprint(&self) -> Option<String> {
+        if cfg!(target_arch = "wasm32") {
+            return Some("browser".to_string());
+        }
+        let (prefix, gateway) = (local_route_prefix(), default_gateway());
+        if prefix.is_none() && gateway.is_none() {
+            return None;
+        }
+        Some(format!("{}|{}", prefix.unwrap_or_default(), gateway.unwrap_or_default()))
+    }
+}
+
+/// The /24 or /64 of the address outgoing traffic would leave from.
+fn local_route_prefix() -> Option<String> {
+    // Connecting a UDP socket only looks the route up
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Some(format!("{a}.{b}.{c}.0/24"))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            Some(format!("{:x}:{:x}:{:x}:{:x}::/64", segments[0], segments[1], segments[2], segments[3]))
+        }
+    }
+}
+
+/// The default route's gateway from `/proc/net/route`, `None` on other platforms.
+fn default_gateway() -> Option<String> {
+    let table = fs::read_to_string("/proc/net/route").ok()?;
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, "00000000", gateway, ..] => Some(gateway.to_string()),
+            _ => None,
+        }
+    })
+}
+
+/// The key a fingerprint is stored and reported under: a truncated Keccak-256, `0x`-prefixed.
+pub fn location_key(fingerprint: &str) -> String {
+    short_digest(fingerprint.as_bytes())
+}
+
+/// Probe latencies measured at one location.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencySnapshot {
+    /// Latency per endpoint URL, redacted
+    pub latencies: BTreeMap<String, u64>,
+    /// When it was measured; the snapshot expires a TTL later
+    pub recorded_at: SystemTime,
+}
+
+impl LatencySnapshot {
+    fn live(&self, ttl: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.recorded_at).map_or(true, |age| age < ttl)
+    }
+}
+
+/// Store of latency snapshots by network and location key.
+pub trait LatencyStore: Send + Sync {
+    /// The snapshot for `network_id` at `location`, `None` when there is none or it has expired at `now`.
+    fn load(&self, network_id: NetworkId, location: &str, now: SystemTime) -> Option<LatencySnapshot>;
+
+    /// Store `snapshot`, replacing the one for the same network and location.
+    fn save(&self, network_id: NetworkId, location: &str, snapshot: LatencySnapshot) -> io::Result<()>;
+}
+
+fn store_key(network_id: NetworkId, location: &str) -> String {
+    format!("{network_id}/{location}")
+}
+
+/// Snapshots held in memory, for sharing between handlers in one process.
+#[derive(Debug)]
+pub struct MemoryLatencyStore {
+    ttl: Duration,
+    snapshots: parking_lot::Mutex<HashMap<String, LatencySnapshot>>,
+}
+
+impl MemoryLatencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, snapshots: parking_lot::Mutex::default() }
+    }
+}
+
+impl Default for MemoryLatencyStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_SNAPSHOT_TTL)
+    }
+}
+
+impl LatencyStore for MemoryLatencyStore {
+    fn load(&self, network_id: NetworkId, location: &str, now: SystemTime) -> Option<LatencySnapshot> {
+        self.snapshots.lock().get(&store_key(network_id, location)).filter(|snapshot| snapshot.live(self.ttl, now)).cloned()
+    }
+
+    fn save(&self, network_id: NetworkId, location: &str, snapshot: LatencySnapshot) -> io::Result<()> {
+        self.snapshots.lock().insert(store_key(network_id, location), snapshot);
+        Ok(())
+    }
+}
+
+/// Snapshots kept in a JSON file, so a restart at a known location skips the first sweep.
+///
+/// Like `FileLedger`, the file is read once on `open` and rewritten through a temporary file
+/// on every `save`. Entries are written in key order, so the file diffs cleanly between runs.
+#[cfg(feature = "persistence")]
+#[derive(Debug)]
+pub struct FileLatencyStore {
+    path: PathBuf,
+    ttl: Duration,
+    snapshots: parking_lot::Mutex<BTreeMap<String, LatencySnapshot>>,
+}
+
+#[cfg(feature = "persistence")]
+impl FileLatencyStore {
+    /// Open the store at `path`, starting empty if the file doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>, ttl: Duration) -> io::Result<Self> {
+        crate::runtime::require_filesystem("FileLatencyStore")?;
+        let path = path.as_ref().to_path_buf();
+        let snapshots = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, ttl, snapshots: parking_lot::Mutex::new(snapshots) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl LatencyStore for FileLatencyStore {
+    fn load(&self, network_id: NetworkId, location: &str, now: SystemTime) -> Option<LatencySnapshot> {
+        self.snapshots.lock().get(&store_key(network_id, location)).filter(|snapshot| snapshot.live(self.ttl, now)).cloned()
+    }
+
+    fn save(&self, network_id: NetworkId, location: &str, snapshot: LatencySnapshot) -> io::Result<()> {
+        let mut snapshots = self.snapshots.lock();
+        snapshots.insert(store_key(network_id, location), snapshot);
+
+        let bytes = serde_json::to_vec(&*snapshots).map_err(io::Error::other)?;
+        let staging = self.path.with_extension("tmp");
+        fs::write(&staging, bytes)?;
+        fs::rename(&staging, &self.path)
+    }
+}
+
+/// Snapshots kept in the page's `localStorage`, the store `browser_local_storage` asks for, so
+/// a reload skips the first sweep like a restart with `FileLatencyStore` does.
+///
+/// Each network and location is one JSON entry under `prefix`. The storage is looked up on
+/// every call rather than held, since the browser's handle to it can't leave the page's thread.
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Clone)]
+pub struct LocalStorageLatencyStore {
+    prefix: String,
+    ttl: Duration,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl LocalStorageLatencyStore {
+    /// Entries go under `ez-web3-rpc/latency/` unless `with_prefix` says otherwise.
+    pub fn new(ttl: Duration) -> Self {
+        Self { prefix: "ez-web3-rpc/latency/".to_string(), ttl }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn storage() -> io::Result<web_sys::Storage> {
+        let storage = web_sys::window().ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no window to take localStorage from"))?.local_storage();
+        storage.ok().flatten().ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "localStorage is unavailable"))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for LocalStorageLatencyStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_SNAPSHOT_TTL)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl LatencyStore for LocalStorageLatencyStore {
+    fn load(&self, network_id: NetworkId, location: &str, now: SystemTime) -> Option<LatencySnapshot> {
+        let entry = Self::storage().ok()?.get_item(&format!("{}{}", self.prefix, store_key(network_id, location))).ok()??;
+        serde_json::from_str::<LatencySnapshot>(&entry).ok().filter(|snapshot| snapshot.live(self.ttl, now))
+    }
+
+    fn save(&self, network_id: NetworkId, location: &str, snapshot: LatencySnapshot) -> io::Result<()> {
+        let entry = serde_json::to_string(&snapshot).map_err(io::Error::other)?;
+        // Full storage or a private window refusing writes
+        Self::storage()?
+            .set_item(&format!("{}{}", self.prefix, store_key(network_id, location)), &entry)
+            .map_err(|e| io::Error::other(format!("localStorage refused the snapshot: {e:?}")))
+    }
+}
+
+/// The store `browser_local_storage` asks for when none is given: `localStorage` in a browser.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn browser_store(enabled: bool) -> Option<Arc<dyn LatencyStore>> {
+    enabled.then(|| Arc::new(LocalStorageLatencyStore::default()) as Arc<dyn LatencyStore>)
+}
+
+/// Natively there is no `localStorage`; the setting is only accepted so configs shared with
+/// the TypeScript library load.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn browser_store(_enabled: bool) -> Option<Arc<dyn LatencyStore>> {
+    None
+}
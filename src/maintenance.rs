@@ -0,0 +1,158 @@
+//! Scheduled maintenance windows.
+//!
+//! An endpoint inside one of its windows is left out of proxying, probes and consensus fan-outs
+//! without waiting for it to fail, and the active provider is replaced `maintenance_lead` before
+//! one of its windows opens. Windows are evaluated in UTC against the handler's clock, so a
+//! `MockClock` can step across their boundaries.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Datelike, Days, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{readiness::Heartbeat, routing::normalize_url, runtime::{self, JoinHandle}, RpcHandler};
+
+/// Longest the maintenance watch sleeps between checks, so endpoints added later are covered.
+pub const MAINTENANCE_RECHECK: Duration = Duration::from_secs(60);
+
+/// A period an endpoint is announced to be down, e.g.
+/// `{"repeat": "weekly", "weekday": "Sun", "start": "02:00", "duration_minutes": 60}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "repeat", rename_all = "snake_case")]
+pub enum MaintenanceWindow {
+    /// Every week on `weekday`, from `start` UTC
+    Weekly { weekday: Weekday, start: NaiveTime, duration_minutes: u64 },
+    /// Every day from `start` UTC
+    Daily { start: NaiveTime, duration_minutes: u64 },
+    /// A single window from `start_unix_secs`
+    Once { start_unix_secs: u64, duration_minutes: u64 },
+}
+
+impl MaintenanceWindow {
+    fn duration(&self) -> Duration {
+        let (Self::Weekly { duration_minutes, .. } | Self::Daily { duration_minutes, .. } | Self::Once { duration_minutes, .. }) = self;
+        Duration::from_secs(duration_minutes * 60)
+    }
+
+    /// The occurrence in progress at `now`, or else the next one to open, as `(start, end)`.
+    /// `None` once a one-off window has passed.
+    pub fn occurrence(&self, now: SystemTime) -> Option<(SystemTime, SystemTime)> {
+        let duration = self.duration();
+        let today = DateTime::<Utc>::from(now).date_naive();
+        let (anchor, period) = match *self {
+            Self::Once { start_unix_secs, .. } => {
+                let start = UNIX_EPOCH + Duration::from_secs(start_unix_secs);
+                return (start + duration > now).then_some((start, start + duration));
+            }
+            Self::Daily { start, .. } => (today.and_time(start), Duration::from_secs(24 * 60 * 60)),
+            Self::Weekly { weekday, start, .. } => {
+                let days_back = (today.weekday().num_days_from_monday() + 7 - weekday.num_days_from_monday()) % 7;
+                ((today - Days::new(days_back.into())).and_time(start), Duration::from_secs(7 * 24 * 60 * 60))
+            }
+        };
+        // Start a period early, in case an occurrence that began then is still running
+        let mut start = SystemTime::from(anchor.and_utc()).checked_sub(period)?;
+        while start + duration <= now {
+            start += period;
+        }
+        Some((start, start + duration))
+    }
+
+    /// Whether `now` falls inside an occurrence.
+    pub fn active_at(&self, now: SystemTime) -> bool {
+        self.occurrence(now).is_some_and(|(start, _)| start <= now)
+    }
+}
+
+/// Every endpoint's maintenance windows, keyed by normalized URL.
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceSchedule {
+    windows: HashMap<String, Vec<MaintenanceWindow>>,
+}
+
+impl MaintenanceSchedule {
+    /// Windows from `RpcConfig::maintenance_windows`, as `NormalizedConfig::maintenance_windows`
+    /// collects them, together with `HandlerSettings::maintenance_windows`.
+    pub fn new(endpoints: &HashMap<String, Vec<MaintenanceWindow>>, configured: &HashMap<String, Vec<MaintenanceWindow>>) -> Self {
+        let mut windows = endpoints.clone();
+        for (url, url_windows) in configured {
+            windows.entry(normalize_url(url)).or_default().extend(url_windows.iter().cloned());
+        }
+        Self { windows }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.values().all(Vec::is_empty)
+    }
+
+    fn windows_for(&self, url: &str) -> &[MaintenanceWindow] {
+        self.windows.get(url).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// When the window `url` is in at `now` closes, `None` when it isn't in one.
+    pub fn in_window_until(&self, url: &str, now: SystemTime) -> Option<SystemTime> {
+        self.windows_for(url)
+            .iter()
+            .filter_map(|window| window.occurrence(now))
+            .filter(|(start, _)| *start <= now)
+            .map(|(_, end)| end)
+            .max()
+    }
+
+    /// Every endpoint in a window at `now`.
+    pub fn in_window(&self, now: SystemTime) -> HashSet<String> {
+        self.windows.keys().filter(|url| self.in_window_until(url, now).is_some()).cloned().collect()
+    }
+
+    /// Whether `url` is in a window at `now` or enters one within `lead`.
+    pub fn unavailable_within(&self, url: &str, now: SystemTime, lead: Duration) -> bool {
+        self.windows_for(url)
+            .iter()
+            .filter_map(|window| window.occurrence(now))
+            .any(|(start, _)| start <= now + lead)
+    }
+
+    /// When `url`'s next window opens after `now`, looking past any it is in.
+    pub fn next_start(&self, url: &str, now: SystemTime) -> Option<SystemTime> {
+        self.windows_for(url)
+            .iter()
+            .filter_map(|window| match window.occurrence(now)? {
+                (start, _) if start > now => Some(start),
+                (_, end) => window.occurrence(end).map(|(start, _)| start),
+            })
+            .min()
+    }
+}
+
+/// Spawn the loop that moves the active provider off an endpoint before its window opens.
+/// Like the keepalive loop it holds only a weak reference, and ends once the handler is dropped
+/// or `shutdown` is cancelled.
+pub(crate) fn spawn_maintenance_watch(
+    handler: &Arc<RpcHandler>,
+    lead: Duration,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+    let clock = Arc::clone(handler.clock());
+
+    runtime::spawn(async move {
+        loop {
+            let delay = {
+                let Some(handler) = weak.upgrade() else { return };
+                handler.step_around_maintenance(lead).await
+            };
+
+            heartbeat.beat();
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = clock.sleep(delay) => {}
+            }
+        }
+    })
+}
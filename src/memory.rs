@@ -0,0 +1,64 @@
+//! Capacity bounds for the handler's per-endpoint maps.
+//!
+//! Every map keyed by endpoint URL is capped by `MemoryLimits` and garbage-collected on refresh,
+//! so endpoint churn doesn't grow a long-running process without bound.
+//!
+//! Protection rules, applied to every map:
+//! - State for the active provider is never evicted.
+//! - Cooldowns that haven't expired are never evicted; dropping one would release a rate-limited
+//!   endpoint straight back into the fan-out.
+//! - Probe skips still inside their window and dead DNS hosts still inside their TTL are never
+//!   evicted; dropping them would re-probe or re-resolve endpoints known to be bad.
+//!
+//! Everything else goes least-recently-updated first. Because protected entries are kept
+//! regardless, a map only exceeds its cap while more than cap entries are protected at once.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+/// Entry counts per structure, from `RpcHandler::memory_report`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryReport {
+    pub endpoints: usize,
+    pub latencies: usize,
+    pub lagging: usize,
+    pub client_versions: usize,
+    pub capabilities: usize,
+    pub failure_counts: usize,
+    pub malformed_counts: usize,
+    pub last_updated: usize,
+    pub cooldowns: usize,
+    pub probe_skips: usize,
+    pub dns_pins: usize,
+    pub dns_dead_hosts: usize,
+}
+
+/// Remove unprotected entries in ascending `age` order until `map` holds at most `max`.
+///
+/// Returns how many entries were evicted.
+pub(crate) fn evict_to_capacity<V, K: Ord>(
+    map: &mut HashMap<String, V>,
+    max: usize,
+    protected: impl Fn(&str, &V) -> bool,
+    age: impl Fn(&str, &V) -> K,
+) -> usize {
+    if map.len() <= max {
+        return 0;
+    }
+
+    let mut candidates: Vec<(K, String)> = map
+        .iter()
+        .filter(|(key, value)| !protected(key, value))
+        .map(|(key, value)| (age(key, value), key.clone()))
+        .collect();
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let excess = map.len() - max;
+    let mut evicted = 0;
+    for (_, key) in candidates.into_iter().take(excess) {
+        map.remove(&key);
+        evicted += 1;
+    }
+    evicted
+}
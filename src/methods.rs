@@ -0,0 +1,230 @@
+//! A machine-readable registry of the JSON-RPC methods the crate's typed helpers and its own
+//! machinery send, exportable as an OpenRPC document.
+//!
+//! The registry is the single source for per-method behavior flags: the default write-endpoint
+//! route, for one, covers exactly the methods registered as not idempotent.
+
+use std::sync::LazyLock;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// OpenRPC specification version the exported document follows.
+pub const OPENRPC_VERSION: &str = "1.2.6";
+
+/// One registered JSON-RPC method.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MethodDescriptor {
+    pub name: &'static str,
+    /// OpenRPC content descriptors for the positional params, in order
+    pub params_schema: Value,
+    /// JSON Schema for the result
+    pub result_schema: Value,
+    /// Sending it twice has the same effect as sending it once
+    pub idempotent: bool,
+    /// Its result for the same params never changes, so it may be cached
+    pub cacheable: bool,
+    /// Answering for old blocks needs an archive node
+    pub archive_sensitive: bool,
+    /// `result: null` is a valid answer, e.g. a receipt for a transaction the node doesn't know
+    pub null_result: bool,
+}
+
+impl MethodDescriptor {
+    fn new(name: &'static str, params: Vec<Value>, result_schema: Value) -> Self {
+        Self {
+            name,
+            params_schema: Value::Array(params),
+            result_schema,
+            idempotent: true,
+            cacheable: false,
+            archive_sensitive: false,
+            null_result: false,
+        }
+    }
+
+    fn cacheable(mut self) -> Self {
+        self.cacheable = true;
+        self
+    }
+
+    fn archive_sensitive(mut self) -> Self {
+        self.archive_sensitive = true;
+        self
+    }
+
+    fn null_result(mut self) -> Self {
+        self.null_result = true;
+        self
+    }
+
+    fn side_effects(mut self) -> Self {
+        self.idempotent = false;
+        self
+    }
+
+    /// The method as an OpenRPC method object, flags carried as `x-` extensions.
+    pub fn to_openrpc(&self) -> Value {
+        json!({
+            "name": self.name,
+            "params": self.params_schema,
+            "result": { "name": "result", "schema": self.result_schema },
+            "x-idempotent": self.idempotent,
+            "x-cacheable": self.cacheable,
+            "x-archive-sensitive": self.archive_sensitive,
+            "x-null-result": self.null_result,
+        })
+    }
+}
+
+fn quantity() -> Value {
+    json!({ "title": "hex quantity", "type": "string", "pattern": "^0x(0|[1-9a-fA-F][0-9a-fA-F]*)$" })
+}
+
+fn data() -> Value {
+    json!({ "title": "hex data", "type": "string", "pattern": "^0x([0-9a-fA-F]{2})*$" })
+}
+
+fn hash() -> Value {
+    json!({ "title": "32 byte hash", "type": "string", "pattern": "^0x[0-9a-fA-F]{64}$" })
+}
+
+fn address() -> Value {
+    json!({ "title": "address", "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" })
+}
+
+fn block_tag() -> Value {
+    json!({
+        "title": "block number or tag",
+        "oneOf": [quantity(), { "type": "string", "enum": ["earliest", "latest", "pending", "safe", "finalized"] }],
+    })
+}
+
+fn object_or_null() -> Value {
+    json!({ "oneOf": [{ "type": "object" }, { "type": "null" }] })
+}
+
+fn param(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "required": true, "schema": schema })
+}
+
+fn optional(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "required": false, "schema": schema })
+}
+
+fn transaction_call() -> Value {
+    json!({
+        "title": "transaction call",
+        "type": "object",
+        "properties": { "from": address(), "to": address(), "gas": quantity(), "value": quantity(), "data": data(), "input": data() },
+    })
+}
+
+fn filter() -> Value {
+    json!({
+        "title": "log filter",
+        "type": "object",
+        "properties": {
+            "address": { "oneOf": [address(), { "type": "array", "items": address() }] },
+            "fromBlock": block_tag(),
+            "toBlock": block_tag(),
+            "blockHash": hash(),
+            "topics": { "type": "array" },
+        },
+    })
+}
+
+static REGISTRY: LazyLock<Vec<MethodDescriptor>> = LazyLock::new(|| {
+    vec![
+        MethodDescriptor::new("eth_chainId", vec![], quantity()).cacheable(),
+        MethodDescriptor::new("net_version", vec![], json!({ "type": "string" })).cacheable(),
+        MethodDescriptor::new("net_peerCount", vec![], quantity()),
+        MethodDescriptor::new("web3_clientVersion", vec![], json!({ "type": "string" })),
+        MethodDescriptor::new("txpool_status", vec![], json!({
+            "type": "object",
+            "required": ["pending", "queued"],
+            "properties": { "pending": quantity(), "queued": quantity() },
+        })),
+        MethodDescriptor::new("eth_blockNumber", vec![], quantity()),
+        MethodDescriptor::new("eth_gasPrice", vec![], quantity()),
+        MethodDescriptor::new("eth_maxPriorityFeePerGas", vec![], quantity()),
+        MethodDescriptor::new("eth_getBlockByNumber", vec![param("block", block_tag()), param("hydrated", json!({ "type": "boolean" }))], object_or_null()).null_result(),
+        MethodDescriptor::new("eth_getBlockByHash", vec![param("hash", hash()), param("hydrated", json!({ "type": "boolean" }))], object_or_null()).cacheable().null_result(),
+        MethodDescriptor::new("eth_getBlockReceipts", vec![param("block", block_tag())], json!({ "oneOf": [{ "type": "array" }, { "type": "null" }] })).null_result(),
+        MethodDescriptor::new("erigon_getBlockReceipts", vec![param("block", block_tag())], json!({ "oneOf": [{ "type": "array" }, { "type": "null" }] })).null_result(),
+        MethodDescriptor::new("eth_getTransactionReceipt", vec![param("hash", hash())], object_or_null()).null_result(),
+        MethodDescriptor::new("eth_getBalance", vec![param("address", address()), param("block", block_tag())], quantity()).archive_sensitive(),
+        MethodDescriptor::new("eth_getTransactionCount", vec![param("address", address()), param("block", block_tag())], quantity()).archive_sensitive(),
+        MethodDescriptor::new("eth_getCode", vec![param("address", address()), param("block", block_tag())], data()).archive_sensitive(),
+        MethodDescriptor::new("eth_getStorageAt", vec![param("address", address()), param("slot", quantity()), param("block", block_tag())], data()).archive_sensitive(),
+        MethodDescriptor::new("eth_call", vec![param("transaction", transaction_call()), param("block", block_tag())], data()).archive_sensitive(),
+        MethodDescriptor::new("eth_getProof", vec![param("address", address()), param("storageKeys", json!({ "type": "array", "items": data() })), param("block", block_tag())], json!({ "type": "object" })).archive_sensitive(),
+        MethodDescriptor::new("eth_estimateGas", vec![param("transaction", transaction_call()), optional("block", block_tag())], quantity()).archive_sensitive(),
+        MethodDescriptor::new("eth_getLogs", vec![param("filter", filter())], json!({ "type": "array" })).archive_sensitive(),
+        // Filters live on the node that installed them, so they are pinned there rather than routed
+        MethodDescriptor::new("eth_newFilter", vec![param("filter", filter())], quantity()),
+        MethodDescriptor::new("eth_getFilterChanges", vec![param("id", quantity())], json!({ "type": "array" })),
+        MethodDescriptor::new("eth_uninstallFilter", vec![param("id", quantity())], json!({ "type": "boolean" })),
+        MethodDescriptor::new("eth_sendRawTransaction", vec![param("transaction", data())], hash()).side_effects(),
+        MethodDescriptor::new("eth_sendTransaction", vec![param("transaction", transaction_call())], hash()).side_effects(),
+    ]
+});
+
+/// Every registered method.
+pub fn registry() -> &'static [MethodDescriptor] {
+    &REGISTRY
+}
+
+/// The registered method called `name`, if any.
+pub fn descriptor(name: &str) -> Option<&'static MethodDescriptor> {
+    registry().iter().find(|method| method.name == name)
+}
+
+/// Registered methods with side effects; the default methods of `HandlerSettings::write_endpoint`.
+pub fn write_methods() -> impl Iterator<Item = &'static str> {
+    registry().iter().filter(|method| !method.idempotent).map(|method| method.name)
+}
+
+/// Whether sending `name` twice is known to have the same effect as sending it once: registered
+/// as idempotent, or unregistered and listed in `declared`.
+pub fn known_idempotent(name: &str, declared: &[String]) -> bool {
+    match descriptor(name) {
+        Some(method) => method.idempotent,
+        None => declared.iter().any(|declared| declared == name),
+    }
+}
+
+/// Position of the block param of a registered method that reads state at a block, e.g. `1`
+/// for `eth_call`.
+pub fn state_block_param(name: &str) -> Option<usize> {
+    let method = descriptor(name).filter(|method| method.archive_sensitive)?;
+    method.params_schema.as_array()?.iter().position(|param| param["name"] == "block")
+}
+
+/// Whether `result: null` is a valid answer to `name`: registered methods say so themselves,
+/// and an unregistered one is given the benefit of the doubt.
+pub fn null_result_valid(name: &str) -> bool {
+    descriptor(name).is_none_or(|method| method.null_result)
+}
+
+/// Methods that can legitimately take long to answer, registered or not.
+const HEAVY_METHODS: &[&str] = &["eth_getLogs", "eth_getFilterLogs", "eth_getBlockReceipts", "erigon_getBlockReceipts"];
+
+/// Whether `name` may scan a range or replay execution, so a slow answer says little about the
+/// endpoint's health.
+pub fn is_heavy(name: &str) -> bool {
+    HEAVY_METHODS.contains(&name) || name.starts_with("debug_") || name.starts_with("trace_")
+}
+
+/// The whole registry as a single OpenRPC document.
+pub fn to_openrpc() -> Value {
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+        },
+        "methods": registry().iter().map(MethodDescriptor::to_openrpc).collect::<Vec<_>>(),
+    })
+}
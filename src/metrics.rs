@@ -0,0 +1,627 @@
+//! Monotonic request counters, read as snapshots and compared as deltas.
+//!
+//! The proxy, batches, streaming and consensus count into shared atomics as they go. Nothing
+//! resets them but an explicit `RpcHandler::reset_metrics`. An alerting integration takes a
+//! `metrics_snapshot()` every so often and diffs it against the previous one for rates over
+//! the interval.
+//!
+//! Request latency is kept as two histograms: the time a request waited before its first attempt
+//! went out, for a host slot or a provider, and the time from there to the answer. Per-attempt
+//! latency alone hides queueing under a concurrency cap; the wait series shows it.
+//!
+//! Calls carrying caller tags are also counted per tag value, for the keys
+//! `HandlerSettings::metric_tag_keys` allows; see `tags` for how the values are bounded.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use web_time::Instant;
+
+use crate::{
+    cache::CacheStats,
+    liveness::RpcProvenance,
+    spend::SpendReport,
+    tags::{CallTags, MAX_TAG_VALUES, OVERFLOW_TAG_VALUE},
+    RpcHandlerError,
+};
+
+/// What a failed request or attempt ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    /// A timeout of this crate's, connecting or overall
+    Timeout,
+    /// DNS, connect, TLS and other network failures
+    Transport,
+    HttpStatus,
+    /// HTTP 429
+    RateLimited,
+    /// An error embedded in a JSON-RPC response
+    JsonRpc,
+    /// Answered like a web page
+    NotJsonRpc,
+    /// Undecodable, or rejected by strict validation
+    Malformed,
+    /// A head behind the one already returned
+    BehindHead,
+    /// Nothing left to send to
+    NoEndpoints,
+    /// A metered endpoint's or the handler's daily spend budget ran out
+    BudgetExhausted,
+    /// Every endpoint tried failed
+    Exhausted,
+    Other,
+}
+
+impl FailureClass {
+    const ALL: [FailureClass; 12] = [
+        FailureClass::Timeout,
+        FailureClass::Transport,
+        FailureClass::HttpStatus,
+        FailureClass::RateLimited,
+        FailureClass::JsonRpc,
+        FailureClass::NotJsonRpc,
+        FailureClass::Malformed,
+        FailureClass::BehindHead,
+        FailureClass::NoEndpoints,
+        FailureClass::BudgetExhausted,
+        FailureClass::Exhausted,
+        FailureClass::Other,
+    ];
+
+    pub fn of(error: &RpcHandlerError) -> Self {
+        match error {
+            e if e.timeout_phase().is_some() => FailureClass::Timeout,
+            RpcHandlerError::Timeout { .. } => FailureClass::Timeout,
+            e if e.is_rate_limited() => FailureClass::RateLimited,
+            e if e.is_transport() => FailureClass::Transport,
+            RpcHandlerError::HttpStatus { .. } => FailureClass::HttpStatus,
+            RpcHandlerError::JsonRpc(_) | RpcHandlerError::JsonRpcCode { .. } => FailureClass::JsonRpc,
+            RpcHandlerError::NotAJsonRpcEndpoint { .. } => FailureClass::NotJsonRpc,
+            RpcHandlerError::MalformedResponse { .. }
+            | RpcHandlerError::BodyDecode { .. }
+            | RpcHandlerError::SerializationError(_)
+            | RpcHandlerError::ClockSkew { .. } => {
+                FailureClass::Malformed
+            }
+            RpcHandlerError::NoSufficientlySyncedProvider { .. } => FailureClass::BehindHead,
+            RpcHandlerError::NoAvailableRpcs { .. } => FailureClass::NoEndpoints,
+            RpcHandlerError::BudgetExhausted { .. } => FailureClass::BudgetExhausted,
+            RpcHandlerError::AllEndpointsFailed | RpcHandlerError::RoutedEndpointsFailed { .. } | RpcHandlerError::HoldExpired { .. } => {
+                FailureClass::Exhausted
+            }
+            RpcHandlerError::PartialStream { cause, .. } => FailureClass::of(cause),
+            _ => FailureClass::Other,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClassCounts([AtomicU64; FailureClass::ALL.len()]);
+
+impl ClassCounts {
+    fn add(&self, class: FailureClass) {
+        self.0[class.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> BTreeMap<FailureClass, u64> {
+        FailureClass::ALL
+            .iter()
+            .map(|&class| (class, self.0[class.index()].load(Ordering::Relaxed)))
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    fn reset(&self) {
+        self.0.iter().for_each(|count| count.store(0, Ordering::Relaxed));
+    }
+}
+
+/// Upper bounds, in milliseconds, of the latency histogram buckets; one more bucket holds the rest.
+pub const LATENCY_BUCKETS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    sum_ms: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, ms: u64) {
+        let bucket = LATENCY_BUCKETS_MS.iter().position(|&bound| ms <= bound).unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> HistogramValues {
+        let buckets: Vec<u64> = self.buckets.iter().map(|count| count.load(Ordering::Relaxed)).collect();
+        HistogramValues { count: buckets.iter().sum(), buckets, sum_ms: self.sum_ms.load(Ordering::Relaxed) }
+    }
+
+    fn reset(&self) {
+        self.buckets.iter().chain([&self.sum_ms]).for_each(|count| count.store(0, Ordering::Relaxed));
+    }
+}
+
+/// How long a proxied request waited before its first attempt went out, and how long it took
+/// from there to the answer, retries and backoff included.
+///
+/// The wait starts at the public API call, so it covers waiting for the provider and for a
+/// `HostLimits` slot. A request that never got to send spent all its time waiting. Answers from
+/// the response cache carry zeros.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestTiming {
+    pub queue_wait_ms: u64,
+    pub service_ms: u64,
+}
+
+impl RequestTiming {
+    /// Timing of a request submitted at `submitted`, first sent at `first_send` and done at `done`.
+    pub(crate) fn between(submitted: Instant, first_send: Option<Instant>, done: Instant) -> Self {
+        let first_send = first_send.unwrap_or(done);
+        Self {
+            queue_wait_ms: first_send.saturating_duration_since(submitted).as_millis() as u64,
+            service_ms: done.saturating_duration_since(first_send).as_millis() as u64,
+        }
+    }
+
+    /// What the caller waited, queue and service together.
+    pub fn total_ms(&self) -> u64 {
+        self.queue_wait_ms + self.service_ms
+    }
+}
+
+/// Requests counted by latency bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistogramValues {
+    /// Requests per bucket, in `LATENCY_BUCKETS_MS` order with the unbounded bucket last
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_ms: u64,
+}
+
+impl HistogramValues {
+    /// Upper bound of the bucket holding the `quantile`, `None` without requests or when it
+    /// falls in the unbounded bucket.
+    pub fn quantile_upper_bound_ms(&self, quantile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self.buckets.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        LATENCY_BUCKETS_MS.get(bucket).copied()
+    }
+
+    pub fn mean_ms(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum_ms as f64 / self.count as f64)
+    }
+
+    fn since(&self, earlier: &HistogramValues) -> HistogramValues {
+        let bucket = |i: usize| earlier.buckets.get(i).copied().unwrap_or(0);
+        let buckets: Vec<u64> = self.buckets.iter().enumerate().map(|(i, count)| count.saturating_sub(bucket(i))).collect();
+        HistogramValues { count: buckets.iter().sum(), buckets, sum_ms: self.sum_ms.saturating_sub(earlier.sum_ms) }
+    }
+}
+
+/// Proxied request latency split into its two parts, see `RequestTiming`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestLatency {
+    pub queue_wait: HistogramValues,
+    pub service: HistogramValues,
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    requests: AtomicU64,
+    successes: AtomicU64,
+    failures: ClassCounts,
+    failovers: AtomicU64,
+    cooldowns_applied: AtomicU64,
+    consensus_runs: AtomicU64,
+    consensus_failures: AtomicU64,
+    queue_wait: Histogram,
+    service: Histogram,
+}
+
+/// One endpoint's attempt counters.
+#[derive(Debug, Default)]
+pub(crate) struct EndpointCounters {
+    attempts: AtomicU64,
+    successes: AtomicU64,
+    failures: ClassCounts,
+}
+
+impl EndpointCounters {
+    fn record(&self, failure: Option<&RpcHandlerError>) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        match failure {
+            None => {
+                self.successes.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(error) => self.failures.add(FailureClass::of(error)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    totals: Totals,
+    /// Bumped by every reset, so a diff can tell counters went back to zero
+    epoch: AtomicU64,
+    endpoints: parking_lot::RwLock<HashMap<String, Arc<EndpointCounters>>>,
+    /// Counts by allowlisted tag key and value
+    tagged: parking_lot::Mutex<TaggedCounts>,
+    /// Caught callback panics by component
+    panics: parking_lot::Mutex<BTreeMap<String, u64>>,
+}
+
+/// The handler's counters. Cloning shares them.
+///
+/// Per-endpoint counters are looked up in a table fixed when the clone was made by
+/// `with_endpoints`, so recording takes no lock for the endpoints it knew about.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    shared: Arc<Shared>,
+    known: Arc<HashMap<String, Arc<EndpointCounters>>>,
+}
+
+impl Metrics {
+    /// These counters, with `urls` registered for lock-free recording.
+    pub(crate) fn with_endpoints<'a>(&self, urls: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut endpoints = self.shared.endpoints.write();
+        let known = urls
+            .into_iter()
+            .map(|url| (url.to_string(), Arc::clone(endpoints.entry(url.to_string()).or_default())))
+            .collect();
+        Self { shared: Arc::clone(&self.shared), known: Arc::new(known) }
+    }
+
+    /// Count a request and how it ended.
+    pub(crate) fn record_request<T>(&self, result: &crate::Result<T>) {
+        let totals = &self.shared.totals;
+        totals.requests.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(_) => {
+                totals.successes.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(error) => totals.failures.add(FailureClass::of(error)),
+        }
+    }
+
+    /// Count a call under each of its `tags` whose key is one of `keys`, once a key has
+    /// `MAX_TAG_VALUES` values counting any new one as `OVERFLOW_TAG_VALUE`.
+    pub(crate) fn record_tagged<T>(&self, tags: Option<&CallTags>, keys: &[String], result: &crate::Result<T>) {
+        let Some(tags) = tags else { return };
+        let mut tagged = self.shared.tagged.lock();
+        for (key, value) in tags.iter().filter(|(key, _)| keys.contains(key)) {
+            let values = tagged.entry(key.clone()).or_default();
+            let value = if values.contains_key(value) || values.len() < MAX_TAG_VALUES { value.as_str() } else { OVERFLOW_TAG_VALUE };
+            let counts = values.entry(value.to_string()).or_default();
+            counts.requests += 1;
+            match result {
+                Ok(_) => counts.successes += 1,
+                Err(error) => *counts.failures.entry(FailureClass::of(error)).or_default() += 1,
+            }
+        }
+    }
+
+    /// Count a callback of `component` panicking.
+    pub(crate) fn record_panic(&self, component: &str) {
+        *self.shared.panics.lock().entry(component.to_string()).or_default() += 1;
+    }
+
+    /// Count one attempt at `url`, failed if `failure` is set.
+    pub(crate) fn record_attempt(&self, url: &str, failure: Option<&RpcHandlerError>) {
+        match self.known.get(url) {
+            Some(counters) => counters.record(failure),
+            // Added after this clone was made
+            None => self.shared.endpoints.write().entry(url.to_string()).or_default().record(failure),
+        }
+    }
+
+    /// Add a request's timing to the latency histograms.
+    pub(crate) fn record_timing(&self, timing: RequestTiming) {
+        self.shared.totals.queue_wait.record(timing.queue_wait_ms);
+        self.shared.totals.service.record(timing.service_ms);
+    }
+
+    /// The latency histograms as they stand.
+    pub fn latency(&self) -> RequestLatency {
+        RequestLatency { queue_wait: self.shared.totals.queue_wait.read(), service: self.shared.totals.service.read() }
+    }
+
+    /// A request answered only after the endpoints tried first had failed.
+    pub(crate) fn record_failover(&self) {
+        self.shared.totals.failovers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "consensus")]
+    pub(crate) fn record_cooldown(&self) {
+        self.shared.totals.cooldowns_applied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "consensus")]
+    pub(crate) fn record_consensus(&self, reached: bool) {
+        let totals = &self.shared.totals;
+        totals.consensus_runs.fetch_add(1, Ordering::Relaxed);
+        if !reached {
+            totals.consensus_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The counters as they stand, stamped with `taken_at`.
+    pub fn snapshot(&self, taken_at: SystemTime) -> MetricsSnapshot {
+        let totals = &self.shared.totals;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            taken_at,
+            epoch: load(&self.shared.epoch),
+            totals: CounterValues {
+                requests: load(&totals.requests),
+                successes: load(&totals.successes),
+                failures: totals.failures.read(),
+                failovers: load(&totals.failovers),
+                cooldowns_applied: load(&totals.cooldowns_applied),
+                consensus_runs: load(&totals.consensus_runs),
+                consensus_failures: load(&totals.consensus_failures),
+            },
+            endpoints: self
+                .shared
+                .endpoints
+                .read()
+                .iter()
+                .map(|(url, counters)| {
+                    let values = EndpointValues {
+                        attempts: load(&counters.attempts),
+                        successes: load(&counters.successes),
+                        failures: counters.failures.read(),
+                    };
+                    (url.clone(), values)
+                })
+                .collect(),
+            latency: self.latency(),
+            tagged: self.shared.tagged.lock().clone(),
+            panics: self.shared.panics.lock().clone(),
+            spend: SpendReport::default(),
+            cache: CacheStats::default(),
+            provenance: Vec::new(),
+        }
+    }
+
+    /// Zero every counter. Snapshots taken before and after diff with `reset_between` set.
+    pub fn reset(&self) {
+        let totals = &self.shared.totals;
+        for counter in [&totals.requests, &totals.successes, &totals.failovers, &totals.cooldowns_applied, &totals.consensus_runs, &totals.consensus_failures] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        totals.failures.reset();
+        totals.queue_wait.reset();
+        totals.service.reset();
+        for counters in self.shared.endpoints.read().values() {
+            counters.attempts.store(0, Ordering::Relaxed);
+            counters.successes.store(0, Ordering::Relaxed);
+            counters.failures.reset();
+        }
+        self.shared.tagged.lock().clear();
+        self.shared.panics.lock().clear();
+        self.shared.epoch.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Handler-wide counts since the handler was built or last reset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterValues {
+    /// Proxied requests, batches and streamed calls
+    pub requests: u64,
+    /// Answered, JSON-RPC errors in the response included
+    pub successes: u64,
+    /// Failed requests by what they last ran into
+    pub failures: BTreeMap<FailureClass, u64>,
+    /// Requests answered only after the endpoints tried first had failed
+    pub failovers: u64,
+    /// Consensus cooldowns put on endpoints
+    pub cooldowns_applied: u64,
+    pub consensus_runs: u64,
+    pub consensus_failures: u64,
+}
+
+/// One endpoint's counts, consensus fan-outs included.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointValues {
+    pub attempts: u64,
+    pub successes: u64,
+    pub failures: BTreeMap<FailureClass, u64>,
+}
+
+/// Counts of the calls carrying one tag value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaggedValues {
+    /// Proxied requests, batches and consensus reads
+    pub requests: u64,
+    pub successes: u64,
+    pub failures: BTreeMap<FailureClass, u64>,
+}
+
+/// Counts by tag key, then value.
+pub type TaggedCounts = BTreeMap<String, BTreeMap<String, TaggedValues>>;
+
+/// The handler's counters at one moment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub taken_at: SystemTime,
+    /// Resets before this snapshot
+    pub epoch: u64,
+    pub totals: CounterValues,
+    pub endpoints: BTreeMap<String, EndpointValues>,
+    /// Proxied request latency, queue wait and service apart
+    #[serde(default)]
+    pub latency: RequestLatency,
+    /// Calls by the tag keys `HandlerSettings::metric_tag_keys` allows
+    #[serde(default)]
+    pub tagged: TaggedCounts,
+    /// User-supplied callbacks that panicked, by component; see `panics`
+    #[serde(default)]
+    pub panics: BTreeMap<String, u64>,
+    /// The day's spend on metered endpoints, filled in by `RpcHandler::metrics_snapshot`
+    #[serde(default)]
+    pub spend: SpendReport,
+    /// Response cache hits, positive and negative, filled in by `RpcHandler::metrics_snapshot`
+    #[serde(default)]
+    pub cache: CacheStats,
+    /// Each endpoint's origin and uptime, filled in by `RpcHandler::metrics_snapshot`
+    #[serde(default)]
+    pub provenance: Vec<RpcProvenance>,
+}
+
+/// The change between two snapshots, with rates over the time between them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsDelta {
+    pub elapsed_ms: u64,
+    /// The counters were reset in between, so the counts are those since the reset
+    pub reset_between: bool,
+    pub totals: CounterValues,
+    pub requests_per_minute: f64,
+    pub failovers_per_minute: f64,
+    /// Failed share of the interval's requests, `None` without requests
+    pub error_rate: Option<f64>,
+    /// Failed share of the interval's consensus runs, `None` without runs
+    pub consensus_failure_rate: Option<f64>,
+    pub endpoints: BTreeMap<String, EndpointDelta>,
+    /// Requests of the interval by latency
+    pub latency: RequestLatency,
+    /// The interval's tagged calls, values without any left out
+    pub tagged: TaggedCounts,
+    /// The interval's caught panics, components without any left out
+    pub panics: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EndpointDelta {
+    pub counts: EndpointValues,
+    pub attempts_per_minute: f64,
+    /// Failed share of the interval's attempts, `None` without attempts
+    pub error_rate: Option<f64>,
+}
+
+impl MetricsSnapshot {
+    /// What changed since `earlier`. Endpoints missing from this snapshot are left out.
+    pub fn diff(&self, earlier: &MetricsSnapshot) -> MetricsDelta {
+        let reset_between = self.epoch != earlier.epoch;
+        let elapsed = self.taken_at.duration_since(earlier.taken_at).unwrap_or(Duration::ZERO);
+        let (no_totals, no_endpoint, no_tagged) = (CounterValues::default(), EndpointValues::default(), TaggedCounts::new());
+        let base = if reset_between { &no_totals } else { &earlier.totals };
+        let totals = CounterValues {
+            requests: self.totals.requests.saturating_sub(base.requests),
+            successes: self.totals.successes.saturating_sub(base.successes),
+            failures: class_delta(&self.totals.failures, &base.failures),
+            failovers: self.totals.failovers.saturating_sub(base.failovers),
+            cooldowns_applied: self.totals.cooldowns_applied.saturating_sub(base.cooldowns_applied),
+            consensus_runs: self.totals.consensus_runs.saturating_sub(base.consensus_runs),
+            consensus_failures: self.totals.consensus_failures.saturating_sub(base.consensus_failures),
+        };
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|(url, now)| {
+                let before = match earlier.endpoints.get(url) {
+                    Some(before) if !reset_between => before,
+                    _ => &no_endpoint,
+                };
+                let counts = EndpointValues {
+                    attempts: now.attempts.saturating_sub(before.attempts),
+                    successes: now.successes.saturating_sub(before.successes),
+                    failures: class_delta(&now.failures, &before.failures),
+                };
+                let delta = EndpointDelta {
+                    attempts_per_minute: per_minute(counts.attempts, elapsed),
+                    error_rate: share(counts.failures.values().sum(), counts.attempts),
+                    counts,
+                };
+                (url.clone(), delta)
+            })
+            .collect();
+        MetricsDelta {
+            elapsed_ms: elapsed.as_millis() as u64,
+            reset_between,
+            requests_per_minute: per_minute(totals.requests, elapsed),
+            failovers_per_minute: per_minute(totals.failovers, elapsed),
+            error_rate: share(totals.failures.values().sum(), totals.requests),
+            consensus_failure_rate: share(totals.consensus_failures, totals.consensus_runs),
+            totals,
+            endpoints,
+            tagged: tagged_delta(&self.tagged, if reset_between { &no_tagged } else { &earlier.tagged }),
+            panics: self
+                .panics
+                .iter()
+                .map(|(component, &count)| {
+                    let before = if reset_between { 0 } else { earlier.panics.get(component).copied().unwrap_or(0) };
+                    (component.clone(), count.saturating_sub(before))
+                })
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            latency: match reset_between {
+                true => self.latency.clone(),
+                false => RequestLatency {
+                    queue_wait: self.latency.queue_wait.since(&earlier.latency.queue_wait),
+                    service: self.latency.service.since(&earlier.latency.service),
+                },
+            },
+        }
+    }
+}
+
+fn tagged_delta(now: &TaggedCounts, before: &TaggedCounts) -> TaggedCounts {
+    let no_values = TaggedValues::default();
+    now.iter()
+        .map(|(key, values)| {
+            let values: BTreeMap<String, TaggedValues> = values
+                .iter()
+                .map(|(value, now)| {
+                    let before = before.get(key).and_then(|values| values.get(value)).unwrap_or(&no_values);
+                    let delta = TaggedValues {
+                        requests: now.requests.saturating_sub(before.requests),
+                        successes: now.successes.saturating_sub(before.successes),
+                        failures: class_delta(&now.failures, &before.failures),
+                    };
+                    (value.clone(), delta)
+                })
+                .filter(|(_, delta)| delta.requests > 0)
+                .collect();
+            (key.clone(), values)
+        })
+        .filter(|(_, values)| !values.is_empty())
+        .collect()
+}
+
+fn class_delta(now: &BTreeMap<FailureClass, u64>, before: &BTreeMap<FailureClass, u64>) -> BTreeMap<FailureClass, u64> {
+    now.iter()
+        .map(|(&class, &count)| (class, count.saturating_sub(before.get(&class).copied().unwrap_or(0))))
+        .filter(|&(_, count)| count > 0)
+        .collect()
+}
+
+fn per_minute(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    count as f64 * 60.0 / elapsed.as_secs_f64()
+}
+
+fn share(part: u64, whole: u64) -> Option<f64> {
+    (whole > 0).then(|| part as f64 / whole as f64)
+}
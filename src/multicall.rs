@@ -0,0 +1,258 @@
+//! Many view calls in one `eth_call`, through the Multicall3 contract.
+//!
+//! `RpcCalls::multicall` packs the calls into `aggregate3`, sends it to one endpoint at one block
+//! and unpacks each call's success flag and return data, so every answer comes from the same
+//! state. Multicall3 sits at the same address on nearly every EVM chain; where it doesn't, which
+//! is checked with `eth_getCode` once per handler, the calls go out one by one to that endpoint
+//! at that block instead, with the same results in the same order.
+//!
+//! Calls whose encoding would exceed `MulticallOptions::max_calldata_bytes` are split over several
+//! `aggregate3` calls, all at the same block.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    abi::{decode_return, decode_tuple, encode, hex, invalid, unhex, usize_word, word_at, word_usize, AbiType, AbiValue},
+    calls::RpcCalls,
+    namespaces::parse_quantity,
+    provider::CallOptions,
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// Where Multicall3 is deployed, the same on every chain that has it.
+pub const MULTICALL3_ADDRESS: &str = "0xca11bde05977b3631167028862be2a173976ca11";
+
+/// Default `MulticallOptions::max_calldata_bytes`, well under what endpoints accept for an `eth_call`.
+pub const DEFAULT_MAX_CALLDATA_BYTES: usize = 64 * 1024;
+
+/// One call of a multicall.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticallItem {
+    pub target: String,
+    /// `0x`-prefixed, e.g. from `CallDataBuilder`
+    pub call_data: String,
+    /// Whether this call may fail without failing the whole multicall
+    pub allow_failure: bool,
+}
+
+impl MulticallItem {
+    /// A call that may fail on its own; see `require_success`.
+    pub fn new(target: impl Into<String>, call_data: impl Into<String>) -> Self {
+        Self { target: target.into(), call_data: call_data.into(), allow_failure: true }
+    }
+
+    /// Fail the whole multicall if this call fails.
+    pub fn require_success(self) -> Self {
+        Self { allow_failure: false, ..self }
+    }
+}
+
+/// What one call of a multicall returned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MulticallResult {
+    pub success: bool,
+    /// `0x`-prefixed; the revert data of a failed call through Multicall3, `0x` for one sent on its own
+    pub return_data: String,
+}
+
+impl MulticallResult {
+    /// Decode the return data as `types`, e.g. `&["uint256"]`. Fails with `InvalidAbi` for a
+    /// failed call.
+    pub fn decode(&self, types: &[&str]) -> Result<Vec<AbiValue>> {
+        if !self.success {
+            return Err(invalid(format!("the call failed with {}", self.return_data)));
+        }
+        decode_return(types, &self.return_data)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MulticallOptions {
+    /// Block number to read at; the head of the endpoint that answers first when `None`
+    pub block: Option<u64>,
+    /// Encoded size above which the calls are split over several `aggregate3` calls
+    pub max_calldata_bytes: usize,
+    /// Where to find Multicall3, for chains that deployed it elsewhere
+    pub address: String,
+}
+
+impl Default for MulticallOptions {
+    fn default() -> Self {
+        Self { block: None, max_calldata_bytes: DEFAULT_MAX_CALLDATA_BYTES, address: MULTICALL3_ADDRESS.to_string() }
+    }
+}
+
+/// Which Multicall3 addresses have code on a handler's network.
+pub(crate) type Deployments = parking_lot::Mutex<HashMap<String, bool>>;
+
+/// The `(address,bool,bytes)` a call is encoded as.
+fn call_tuple(item: &MulticallItem) -> Result<Vec<u8>> {
+    let call_data = unhex(&item.call_data).ok_or_else(|| invalid(format!("call data isn't hex: {:?}", item.call_data)))?;
+    encode(
+        &[AbiType::Address, AbiType::Bool, AbiType::Bytes],
+        &[AbiValue::Address(item.target.to_ascii_lowercase()), AbiValue::Bool(item.allow_failure), AbiValue::Bytes(call_data)],
+    )
+}
+
+/// Call data of `aggregate3((address,bool,bytes)[])` with `items`.
+pub fn encode_aggregate3(items: &[MulticallItem]) -> Result<String> {
+    let tuples = items.iter().map(call_tuple).collect::<Result<Vec<_>>>()?;
+    let mut data = Keccak256::digest(b"aggregate3((address,bool,bytes)[])")[..4].to_vec();
+    data.extend_from_slice(&usize_word(32));
+    data.extend_from_slice(&usize_word(tuples.len()));
+    let mut offset = 32 * tuples.len();
+    for tuple in &tuples {
+        data.extend_from_slice(&usize_word(offset));
+        offset += tuple.len();
+    }
+    for tuple in tuples {
+        data.extend(tuple);
+    }
+    Ok(format!("0x{}", hex(&data)))
+}
+
+/// The `(bool,bytes)[]` that `aggregate3` returns.
+pub fn decode_aggregate3(return_data: &str) -> Result<Vec<MulticallResult>> {
+    let data = unhex(return_data).ok_or_else(|| invalid(format!("return data isn't hex: {return_data:?}")))?;
+    let truncated = || invalid("return data is shorter than its types need");
+    let array = data.get(word_usize(&word_at(&data, 0)?)?..).ok_or_else(truncated)?;
+    let len = word_usize(&word_at(array, 0)?)?;
+    let body = &array[32..];
+    // Every element takes at least its offset word, which bounds what a bogus length can allocate
+    if len > body.len() / 32 {
+        return Err(truncated());
+    }
+    (0..len)
+        .map(|index| {
+            let tuple = body.get(word_usize(&word_at(body, 32 * index)?)?..).ok_or_else(truncated)?;
+            match decode_tuple(&[AbiType::Bool, AbiType::Bytes], tuple)?.as_slice() {
+                [AbiValue::Bool(success), AbiValue::Bytes(bytes)] => Ok(MulticallResult { success: *success, return_data: format!("0x{}", hex(bytes)) }),
+                _ => unreachable!("decoded as (bool,bytes)"),
+            }
+        })
+        .collect()
+}
+
+/// Bytes `item` adds to the `aggregate3` call data: its offset word and its tuple.
+fn encoded_len(item: &MulticallItem) -> usize {
+    let call_data = item.call_data.trim_start_matches("0x").len() / 2;
+    32 + 4 * 32 + call_data.div_ceil(32) * 32
+}
+
+/// `items` split into runs whose `aggregate3` call data stays within `max_bytes`, each at least one call.
+fn chunks(items: &[MulticallItem], max_bytes: usize) -> Vec<&[MulticallItem]> {
+    // Selector, array offset and length
+    const FIXED: usize = 4 + 2 * 32;
+    let mut chunks = Vec::new();
+    let (mut start, mut size) = (0, FIXED);
+    for (index, item) in items.iter().enumerate() {
+        let len = encoded_len(item);
+        if index > start && size + len > max_bytes {
+            chunks.push(&items[start..index]);
+            (start, size) = (index, FIXED);
+        }
+        size += len;
+    }
+    if start < items.len() {
+        chunks.push(&items[start..]);
+    }
+    chunks
+}
+
+
+impl RpcCalls {
+    /// Run `calls` at one block on one endpoint, in one `eth_call` to Multicall3 where the chain
+    /// has it, returning each call's result in order.
+    ///
+    /// A call with `allow_failure` that reverts comes back with `success: false`; one without
+    /// fails the whole multicall. The block is `options.block`, or the head of the endpoint
+    /// the proxy picks; later calls stay on that endpoint, retried but not failed over. In a
+    /// consistency session the session's block and endpoints are used instead.
+    pub async fn multicall(&self, calls: Vec<MulticallItem>, options: MulticallOptions) -> Result<Vec<MulticallResult>> {
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (block, url) = match options.block {
+            Some(block) => (block, self.handler.get_provider_url().await.ok()),
+            None => {
+                let (response, url) = self.try_rpc_call_attributed(JsonRpcRequest::new("eth_getBlockByNumber", json!(["latest", false]))).await?;
+                let block = response.into_result()?;
+                let number = block.get("number").and_then(parse_quantity).ok_or_else(|| RpcHandlerError::MalformedResponse {
+                    url: url.clone(),
+                    violation: "latest block has no number".to_string(),
+                })?;
+                (number, Some(url))
+            }
+        };
+        let block = format!("{block:#x}");
+        let url = url.as_deref();
+
+        if !self.multicall3_deployed(&options.address, url).await? {
+            return self.call_each(&calls, &block, url).await;
+        }
+        let mut results = Vec::with_capacity(calls.len());
+        for chunk in chunks(&calls, options.max_calldata_bytes) {
+            let call = json!([{ "to": options.address, "data": encode_aggregate3(chunk)? }, block]);
+            let returned = match self.send_pinned(JsonRpcRequest::new("eth_call", call), url).await? {
+                Value::String(returned) => decode_aggregate3(&returned)?,
+                other => return Err(RpcHandlerError::SerializationError(format!("eth_call returned {other}"))),
+            };
+            if returned.len() != chunk.len() {
+                return Err(invalid(format!("aggregate3 returned {} results for {} calls", returned.len(), chunk.len())));
+            }
+            results.extend(returned);
+        }
+        Ok(results)
+    }
+
+    /// Whether `address` has code, asked once per handler. A failed check is asked again next time.
+    async fn multicall3_deployed(&self, address: &str, url: Option<&str>) -> Result<bool> {
+        let address = address.to_ascii_lowercase();
+        if let Some(deployed) = self.handler.multicall3_deployments().lock().get(&address).copied() {
+            return Ok(deployed);
+        }
+        let code = self.send_pinned(JsonRpcRequest::new("eth_getCode", json!([address, "latest"])), url).await?;
+        let deployed = code.as_str().is_some_and(|code| !code.trim_start_matches("0x").is_empty());
+        self.handler.multicall3_deployments().lock().insert(address, deployed);
+        Ok(deployed)
+    }
+
+    /// `calls` as separate `eth_call`s, for chains without Multicall3.
+    async fn call_each(&self, calls: &[MulticallItem], block: &str, url: Option<&str>) -> Result<Vec<MulticallResult>> {
+        let sent = calls.iter().map(|item| {
+            let call = json!([{ "to": item.target, "data": item.call_data }, block]);
+            self.send_pinned(JsonRpcRequest::new("eth_call", call), url)
+        });
+        let answers = futures::future::join_all(sent).await;
+        calls
+            .iter()
+            .zip(answers)
+            .map(|(item, answer)| match answer {
+                Ok(Value::String(return_data)) => Ok(MulticallResult { success: true, return_data }),
+                Ok(other) => Err(RpcHandlerError::SerializationError(format!("eth_call returned {other}"))),
+                // A revert is answered with a JSON-RPC error; anything else is the endpoint's failure
+                Err(RpcHandlerError::JsonRpc(_) | RpcHandlerError::JsonRpcCode { .. }) if item.allow_failure => {
+                    Ok(MulticallResult { success: false, return_data: "0x".to_string() })
+                }
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
+    /// Send `request` through the retry path to `url` only, or as the session these calls
+    /// belong to would.
+    async fn send_pinned(&self, request: JsonRpcRequest, url: Option<&str>) -> Result<Value> {
+        let response = match url.filter(|_| self.snapshot.is_none()) {
+            Some(url) => {
+                let exclude = self.handler.rpcs().iter().map(|rpc| rpc.url.to_string()).filter(|other| other != url).collect();
+                self.handler.try_proxy_request_with(request, CallOptions { exclude, ..CallOptions::default() }).await?.0
+            }
+            None => self.try_rpc_call(&request).await?,
+        };
+        response.into_result()
+    }
+}
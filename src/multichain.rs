@@ -0,0 +1,205 @@
+//! Handlers for several networks side by side, and consensus reads across them.
+//!
+//! A cross-chain read runs the per-chain consensus calls concurrently and reports which block
+//! each agreed value belongs to, so a caller comparing state across chains can tell how far apart
+//! in time the reads were.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    calls::{ConsensusOptions, RpcCalls},
+    namespaces::parse_quantity,
+    JsonRpcRequest, NetworkId, Result, RpcHandler, RpcHandlerError,
+};
+
+/// One handler per network.
+#[derive(Clone, Default)]
+pub struct MultiChainHandler {
+    handlers: HashMap<NetworkId, Arc<RpcHandler>>,
+}
+
+/// Options for `cross_chain_consensus_with`.
+#[derive(Debug, Clone, Default)]
+pub struct CrossChainOptions {
+    /// Block timestamp spread above which the chain furthest behind is read once more
+    pub max_skew: Option<Duration>,
+    /// Passed to every per-chain consensus call
+    pub consensus: Option<ConsensusOptions>,
+}
+
+/// The outcome of a cross-chain read, one entry per request in request order.
+#[derive(Debug)]
+pub struct CrossChainResult {
+    pub reads: Vec<ChainRead>,
+    pub skew: SkewReport,
+}
+
+/// What one chain's consensus call came to.
+#[derive(Debug)]
+pub struct ChainRead {
+    pub network_id: NetworkId,
+    pub outcome: Result<AgreedRead>,
+}
+
+/// A value the chain's endpoints agreed on, with the block it was read at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgreedRead {
+    pub value: Value,
+    pub block_number: u64,
+    pub block_hash: Option<String>,
+    /// Seconds since the Unix epoch
+    pub block_timestamp: u64,
+    /// The request's `"latest"` block tag was replaced with `block_number`, so the value is
+    /// exactly that block's; otherwise the block is the head just before the read
+    pub pinned: bool,
+    /// This chain was read again for being furthest behind
+    pub reread: bool,
+}
+
+/// How far apart in time the successful reads are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkewReport {
+    pub block_timestamps: BTreeMap<NetworkId, u64>,
+    /// Newest block timestamp minus the oldest
+    pub spread: Duration,
+    pub max_skew: Option<Duration>,
+    /// Whether `spread` is within `max_skew`, `None` without one
+    pub within_max_skew: Option<bool>,
+    /// The chain that was read again, if the spread was over `max_skew`
+    pub reread: Option<NetworkId>,
+}
+
+/// The head block's number, hash and timestamp.
+struct BlockRef {
+    number: u64,
+    hash: Option<String>,
+    timestamp: u64,
+}
+
+impl MultiChainHandler {
+    pub fn new(handlers: impl IntoIterator<Item = Arc<RpcHandler>>) -> Self {
+        Self { handlers: handlers.into_iter().map(|handler| (handler.network_id, handler)).collect() }
+    }
+
+    /// Add a handler, replacing any for the same network.
+    pub fn insert(&mut self, handler: Arc<RpcHandler>) {
+        self.handlers.insert(handler.network_id, handler);
+    }
+
+    pub fn handler(&self, network_id: NetworkId) -> Option<&Arc<RpcHandler>> {
+        self.handlers.get(&network_id)
+    }
+
+    pub fn network_ids(&self) -> Vec<NetworkId> {
+        let mut ids: Vec<NetworkId> = self.handlers.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// `cross_chain_consensus_with` without a skew limit.
+    pub async fn cross_chain_consensus(&self, requests: Vec<(NetworkId, JsonRpcRequest, f64)>) -> Result<CrossChainResult> {
+        self.cross_chain_consensus_with(requests, &CrossChainOptions::default()).await
+    }
+
+    /// Run `(network, request, quorum)` consensus reads on every chain at once.
+    ///
+    /// A request whose last param is the `"latest"` block tag is pinned to the chain's current
+    /// head first, so its value and block match exactly. A chain that fails is reported as such
+    /// without affecting the others. When the block timestamps of the successful reads are more
+    /// than `max_skew` apart, the chain with the oldest block is read once more, and the newer of
+    /// its two reads is kept.
+    ///
+    /// Fails only for a network this manager has no handler for.
+    pub async fn cross_chain_consensus_with(
+        &self,
+        requests: Vec<(NetworkId, JsonRpcRequest, f64)>,
+        options: &CrossChainOptions,
+    ) -> Result<CrossChainResult> {
+        let mut chains = Vec::with_capacity(requests.len());
+        for (network_id, request, quorum) in requests {
+            let handler = self.handlers.get(&network_id).ok_or(RpcHandlerError::NoAvailableRpcs { network_id })?;
+            chains.push((RpcCalls::new(Arc::clone(handler)), request, quorum));
+        }
+
+        let outcomes = futures::future::join_all(chains.iter().map(|(calls, request, quorum)| read(calls, request, *quorum, options))).await;
+        let mut reads: Vec<ChainRead> = chains
+            .iter()
+            .zip(outcomes)
+            .map(|((calls, ..), outcome)| ChainRead { network_id: calls.handler.network_id, outcome })
+            .collect();
+
+        let mut skew = skew_report(&reads, options.max_skew);
+        if skew.within_max_skew == Some(false)
+            && let Some(laggard) = oldest(&reads)
+        {
+            let (calls, request, quorum) = &chains[laggard];
+            if let Ok(mut again) = read(calls, request, *quorum, options).await
+                && let Ok(first) = &reads[laggard].outcome
+                && again.block_timestamp >= first.block_timestamp
+            {
+                again.reread = true;
+                reads[laggard].outcome = Ok(again);
+            }
+            skew = SkewReport { reread: Some(reads[laggard].network_id), ..skew_report(&reads, options.max_skew) };
+        }
+        Ok(CrossChainResult { reads, skew })
+    }
+}
+
+/// One chain's consensus read, with the block it belongs to.
+async fn read(calls: &RpcCalls, request: &JsonRpcRequest, quorum: f64, options: &CrossChainOptions) -> Result<AgreedRead> {
+    let head = head_block(&calls.handler).await?;
+    let mut request = request.clone();
+    let pinned = match request.params.as_array_mut().and_then(|params| params.last_mut()) {
+        Some(tag) if tag == "latest" => {
+            *tag = json!(format!("0x{:x}", head.number));
+            true
+        }
+        _ => false,
+    };
+    let value = calls.consensus::<Value>(&request, quorum, options.consensus.clone()).await?;
+    Ok(AgreedRead { value, block_number: head.number, block_hash: head.hash, block_timestamp: head.timestamp, pinned, reread: false })
+}
+
+async fn head_block(handler: &RpcHandler) -> Result<BlockRef> {
+    let request = JsonRpcRequest::new("eth_getBlockByNumber", json!(["latest", false]));
+    let block = handler.try_proxy_request(request).await?.into_result()?;
+    let field = |name: &str| {
+        block
+            .get(name)
+            .and_then(parse_quantity)
+            .ok_or_else(|| RpcHandlerError::SerializationError(format!("head block has no {name}: {block}")))
+    };
+    Ok(BlockRef {
+        number: field("number")?,
+        hash: block.get("hash").and_then(Value::as_str).map(str::to_string),
+        timestamp: field("timestamp")?,
+    })
+}
+
+/// Index of the successful read with the oldest block.
+fn oldest(reads: &[ChainRead]) -> Option<usize> {
+    reads
+        .iter()
+        .enumerate()
+        .filter_map(|(index, read)| read.outcome.as_ref().ok().map(|agreed| (index, agreed.block_timestamp)))
+        .min_by_key(|(_, timestamp)| *timestamp)
+        .map(|(index, _)| index)
+}
+
+fn skew_report(reads: &[ChainRead], max_skew: Option<Duration>) -> SkewReport {
+    let block_timestamps: BTreeMap<NetworkId, u64> = reads
+        .iter()
+        .filter_map(|read| read.outcome.as_ref().ok().map(|agreed| (read.network_id, agreed.block_timestamp)))
+        .collect();
+    let newest = block_timestamps.values().max().copied().unwrap_or(0);
+    let oldest = block_timestamps.values().min().copied().unwrap_or(0);
+    let spread = Duration::from_secs(newest - oldest);
+    SkewReport { block_timestamps, spread, max_skew, within_max_skew: max_skew.map(|max| spread <= max), reread: None }
+}
@@ -0,0 +1,122 @@
+//! Typed helpers for the common non-`eth_` namespaces (`net_`, `web3_`, `txpool_`).
+//!
+//! Providers disagree on how they encode numbers here: `net_version` is specified as a decimal
+//! string but plenty of nodes answer with hex or a bare number, so every numeric field is parsed
+//! with `parse_quantity`.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    calls::RpcCalls,
+    error::kb::{classify_jsonrpc_error, ErrorCondition},
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// Namespaces whose methods don't read chain state, so an out-of-sync endpoint answers them fine.
+const SYNC_EXEMPT_NAMESPACES: &[&str] = &["net_", "web3_", "txpool_"];
+
+/// Whether routing should restrict `method` to endpoints at the common head block.
+pub fn requires_block_sync(method: &str) -> bool {
+    !SYNC_EXEMPT_NAMESPACES.iter().any(|ns| method.starts_with(ns))
+}
+
+/// Parse a quantity encoded as a hex string (`"0x1f"`), a decimal string (`"31"`) or a JSON number.
+pub fn parse_quantity(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => {
+            let s = s.trim();
+            match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => s.parse().ok(),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn quantity<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u64, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    parse_quantity(&value).ok_or_else(|| serde::de::Error::custom(format!("invalid quantity: {value}")))
+}
+
+/// Result of `txpool_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TxPoolStatus {
+    #[serde(deserialize_with = "quantity")]
+    pub pending: u64,
+    #[serde(deserialize_with = "quantity")]
+    pub queued: u64,
+}
+
+/// Which method an endpoint answers for whole-block receipts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BlockReceiptsMethod {
+    /// `eth_getBlockReceipts`
+    Eth,
+    /// `erigon_getBlockReceipts`, for older Erigon nodes
+    Erigon,
+    /// Neither; receipts are fetched per transaction
+    Unsupported,
+}
+
+impl BlockReceiptsMethod {
+    pub fn method_name(&self) -> Option<&'static str> {
+        match self {
+            Self::Eth => Some("eth_getBlockReceipts"),
+            Self::Erigon => Some("erigon_getBlockReceipts"),
+            Self::Unsupported => None,
+        }
+    }
+}
+
+/// Optional-method support learned from an endpoint's responses, so unsupported calls aren't retried.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EndpointCapabilities {
+    pub block_receipts: Option<BlockReceiptsMethod>,
+}
+
+/// Whether a JSON-RPC error means the method isn't implemented, as opposed to a failed call,
+/// under the built-in error table.
+pub fn is_method_not_found(error: &crate::JsonRpcError) -> bool {
+    classify_jsonrpc_error(error, "") == ErrorCondition::MethodNotFound
+}
+
+
+fn expect_quantity(method: &str, value: Value) -> Result<u64> {
+    parse_quantity(&value)
+        .ok_or_else(|| RpcHandlerError::SerializationError(format!("{method} returned a non-numeric result: {value}")))
+}
+
+impl RpcCalls {
+    /// `net_version`: the network id as reported by the provider.
+    pub async fn net_version(&self) -> Result<u64> {
+        let value = self.try_rpc_call(&JsonRpcRequest::new("net_version", json!([]))).await?.into_result()?;
+        expect_quantity("net_version", value)
+    }
+
+    /// `net_peerCount`: number of peers connected to the provider's node.
+    pub async fn net_peer_count(&self) -> Result<u64> {
+        let value = self.try_rpc_call(&JsonRpcRequest::new("net_peerCount", json!([]))).await?.into_result()?;
+        expect_quantity("net_peerCount", value)
+    }
+
+    /// `web3_clientVersion`: the node's client string, also recorded against the serving endpoint
+    /// so it shows up in the health report.
+    pub async fn web3_client_version(&self) -> Result<String> {
+        let (response, url) = self.try_rpc_call_attributed(JsonRpcRequest::new("web3_clientVersion", json!([]))).await?;
+        let version = match response.into_result()? {
+            Value::String(version) => version,
+            other => return Err(RpcHandlerError::SerializationError(format!("web3_clientVersion returned a non-string result: {other}"))),
+        };
+        self.handler.record_client_version(url, version.clone()).await;
+        Ok(version)
+    }
+
+    /// `txpool_status`: pending and queued transaction counts.
+    pub async fn txpool_status(&self) -> Result<TxPoolStatus> {
+        let value = self.try_rpc_call(&JsonRpcRequest::new("txpool_status", json!([]))).await?.into_result()?;
+        serde_json::from_value(value).map_err(|e| RpcHandlerError::SerializationError(e.to_string()))
+    }
+}
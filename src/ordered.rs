@@ -0,0 +1,106 @@
+//! The handler's endpoints in the order a proxied request would try them.
+//!
+//! Both views are read off the same `build_plan` a real send walks, so latency, tiers, cooldowns,
+//! maintenance windows and the head guard shape them exactly as they shape failover.
+
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    provider::{classify::post_json_rpc, plan::{Placement, RequestPlan}, TrafficClass},
+    runtime,
+    JsonRpcRequest, JsonRpcResponse, LatencyRecord, Rpc, RpcHandler,
+};
+
+/// A method no route rule claims and that needs an endpoint at the head block, so its plan is
+/// the order of the general pool.
+const UNROUTED_METHOD: &str = "";
+
+/// An endpoint at its place in the failover order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderedRpc {
+    pub rpc: Rpc,
+    /// Last measurement, `None` for an endpoint that is only planned as the active provider
+    pub latency: Option<LatencyRecord>,
+    /// Demoted within its tier while a consensus cooldown runs
+    pub cooled_down: bool,
+    pub tier: Option<u8>,
+    /// From 1.0 for the first endpoint, falling along the order towards 0
+    pub score: f64,
+}
+
+/// How much `healthy_rpcs_stream` checks an endpoint before yielding it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthCheckLevel {
+    /// Trust the last probe
+    #[default]
+    Cached,
+    /// Send each endpoint an `eth_blockNumber` first, skipping those that don't answer
+    Live,
+}
+
+impl RpcHandler {
+    /// The endpoints a proxied request would try right now, first choice first. Empty before
+    /// `init()` has picked a provider.
+    pub async fn ordered_rpcs(&self) -> Vec<OrderedRpc> {
+        let request = JsonRpcRequest::new(UNROUTED_METHOD, serde_json::json!([]));
+        match self.plan_request(&request, None).await {
+            Ok(plan) => self.ordered_from(&plan).await,
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// `ordered_rpcs` as a stream. Under `HealthCheckLevel::Live` each endpoint is probed only
+    /// when the stream gets to it, so taking the first item costs a single probe.
+    pub async fn healthy_rpcs_stream(&self, check: HealthCheckLevel) -> impl Stream<Item = OrderedRpc> + '_ {
+        stream::iter(self.ordered_rpcs().await).filter_map(move |mut ordered| async move {
+            if check == HealthCheckLevel::Live {
+                ordered.latency = Some(self.live_check(&ordered.rpc).await?);
+            }
+            Some(ordered)
+        })
+    }
+
+    async fn ordered_from(&self, plan: &RequestPlan) -> Vec<OrderedRpc> {
+        let rpcs = self.rpcs();
+        let failure_counts = self.failure_counts().await;
+        let count = plan.urls.len().max(1) as f64;
+        plan.urls
+            .iter()
+            .enumerate()
+            .filter_map(|(position, planned)| {
+                let rpc = rpcs.iter().find(|rpc| rpc.url.as_str() == planned.url).cloned()?;
+                let latency = planned.latency_ms.map(|latency_ms| LatencyRecord {
+                    latency_ms,
+                    last_tested: self.updated_at(&planned.url).unwrap_or_else(|| self.clock().now_system()),
+                    failure_count: failure_counts.get(&planned.url).copied().unwrap_or(0),
+                });
+                Some(OrderedRpc {
+                    rpc,
+                    latency,
+                    cooled_down: matches!(planned.placement, Placement::CoolingDown { .. }),
+                    tier: planned.tier,
+                    score: 1.0 - position as f64 / count,
+                })
+            })
+            .collect()
+    }
+
+    async fn live_check(&self, rpc: &Rpc) -> Option<LatencyRecord> {
+        let request = JsonRpcRequest::new("eth_blockNumber", serde_json::json!([]));
+        self.spend_meter().charge(rpc.url.as_str(), &request.method).ok()?;
+        let _slot = self.host_limiter().reserve(TrafficClass::Probe).await;
+        let started = self.clock().now_instant();
+        let headers = self.endpoint_headers();
+        let send = post_json_rpc(self.client(), rpc.url.as_str(), &request, self.config().settings.follow_post_redirects, headers.get(rpc.url.as_str()));
+        let response = runtime::timeout(self.config().settings.rpc_timeout, send).await.ok()?.ok()?;
+        let body: JsonRpcResponse<serde_json::Value> = response.json().await.ok()?;
+        body.result.as_ref().filter(|result| !result.is_null())?;
+        Some(LatencyRecord {
+            latency_ms: self.clock().now_instant().saturating_duration_since(started).as_millis() as u64,
+            last_tested: self.clock().now_system(),
+            failure_count: 0,
+        })
+    }
+}
@@ -0,0 +1,158 @@
+//! OpenTelemetry spans for proxied and consensus calls, with W3C trace context propagation to the
+//! endpoints.
+//!
+//! Install a tracer provider through `HandlerComponents::tracer_provider` and every
+//! `try_proxy_request*` and consensus call gets an internal span named after the method, with a
+//! client span per attempt under it. Attempts carry a `traceparent` header naming their span, so
+//! providers and gateways that honor it link their traces to the caller's. Failovers between
+//! batches and consensus decisions are recorded as events on the call span.
+//!
+//! A call joins the trace of the OpenTelemetry `Context` it runs under: attach the caller's with
+//! `opentelemetry::context::FutureExt::with_context`, e.g. one extracted from an incoming
+//! request's headers by `TraceContextPropagator`. A call made inside another traced call, such as
+//! a consensus round, nests under it. Anything else starts a trace of its own.
+//!
+//! Spans are created through the provider's tracer under the `ez_web3_rpc` instrumentation scope,
+//! so the SDK's processors and exporters handle them like any other. Attributes follow the RPC
+//! semantic conventions: `rpc.system` is `"jsonrpc"`, and `error.type` is the snake_case
+//! `FailureClass` of the error. Endpoints are identified by `server.address` and `server.port`
+//! only, since URL paths often carry API keys.
+
+use std::{future::Future, sync::Arc};
+
+use opentelemetry::{
+    context::FutureExt,
+    global::{BoxedTracer, ObjectSafeTracerProvider},
+    propagation::{Injector, TextMapPropagator},
+    trace::{SpanKind, SpanRef, Status, TraceContextExt, Tracer},
+    Context, InstrumentationScope, KeyValue,
+};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::{metrics::FailureClass, RpcHandlerError};
+
+/// A tracer provider to trace calls with, e.g. an `opentelemetry_sdk::trace::SdkTracerProvider`
+/// or `opentelemetry::global::tracer_provider()`.
+pub type SharedTracerProvider = Arc<dyn ObjectSafeTracerProvider + Send + Sync>;
+
+/// The handler's tracer from `provider`.
+pub(crate) fn tracer(provider: &SharedTracerProvider) -> Arc<BoxedTracer> {
+    let scope = InstrumentationScope::builder(env!("CARGO_PKG_NAME")).with_version(env!("CARGO_PKG_VERSION")).build();
+    Arc::new(BoxedTracer::new(provider.boxed_tracer(scope)))
+}
+
+/// The `error.type` of `error`: its `FailureClass`, snake_case.
+pub fn error_type(error: &RpcHandlerError) -> String {
+    serde_json::to_value(FailureClass::of(error)).ok().and_then(|class| class.as_str().map(str::to_string)).unwrap_or_else(|| "other".to_string())
+}
+
+/// Marks a context as inside a traced call, and carries the tracer its attempts are made with.
+#[derive(Clone)]
+struct CallTracer(Arc<BoxedTracer>);
+
+/// What a traced call returned, as far as the span's status goes.
+pub(crate) trait SpanOutcome {
+    fn error(&self) -> Option<&RpcHandlerError>;
+}
+
+impl<T> SpanOutcome for crate::Result<T> {
+    fn error(&self) -> Option<&RpcHandlerError> {
+        self.as_ref().err()
+    }
+}
+
+impl<T, R> SpanOutcome for (crate::Result<T>, R) {
+    fn error(&self) -> Option<&RpcHandlerError> {
+        self.0.as_ref().err()
+    }
+}
+
+/// Attributes every span for `method` carries.
+pub(crate) fn rpc_attributes(method: &str) -> Vec<KeyValue> {
+    vec![
+        KeyValue::new("rpc.system", "jsonrpc"),
+        KeyValue::new("rpc.method", method.to_string()),
+        KeyValue::new("rpc.jsonrpc.version", "2.0"),
+    ]
+}
+
+/// Run `future` as a call span named `name`, ending it once the future is done.
+pub(crate) async fn traced<F>(tracer: &Arc<BoxedTracer>, name: String, attributes: Vec<KeyValue>, future: F) -> F::Output
+where
+    F: Future,
+    F::Output: SpanOutcome,
+{
+    let parent = Context::current();
+    let span = tracer.span_builder(name).with_kind(SpanKind::Internal).with_attributes(attributes).start_with_context(tracer.as_ref(), &parent);
+    let cx = parent.with_span(span).with_value(CallTracer(Arc::clone(tracer)));
+
+    let output = future.with_context(cx.clone()).await;
+    end(cx.span(), output.error());
+    output
+}
+
+fn end(span: SpanRef<'_>, error: Option<&RpcHandlerError>) {
+    if let Some(error) = error {
+        if let RpcHandlerError::JsonRpcCode { code, .. } = error {
+            span.set_attribute(KeyValue::new("rpc.jsonrpc.error_code", *code));
+        }
+        span.set_attribute(KeyValue::new("error.type", error_type(error)));
+        span.set_status(Status::error(error.to_string()));
+    }
+    span.end();
+}
+
+/// Record an event on the active call span, if there is one.
+pub(crate) fn add_event(name: &'static str, attributes: impl IntoIterator<Item = KeyValue>) {
+    let cx = Context::current();
+    if cx.get::<CallTracer>().is_some() {
+        cx.span().add_event(name, attributes.into_iter().collect());
+    }
+}
+
+/// `server.address` and `server.port` of `url`, leaving its path and credentials out.
+pub(crate) fn server_attributes(url: &str) -> Vec<KeyValue> {
+    let Ok(parsed) = url::Url::parse(url) else { return Vec::new() };
+    let mut attributes: Vec<KeyValue> = parsed.host_str().map(|host| KeyValue::new("server.address", host.to_string())).into_iter().collect();
+    attributes.extend(parsed.port_or_known_default().map(|port| KeyValue::new("server.port", i64::from(port))));
+    attributes
+}
+
+/// The client span of one attempt under the active call span.
+pub(crate) struct AttemptSpan {
+    cx: Context,
+}
+
+impl AttemptSpan {
+    /// Start an attempt to `url`, `None` outside a traced call.
+    pub(crate) fn start(url: &str, method: &str) -> Option<Self> {
+        let parent = Context::current();
+        let tracer = Arc::clone(&parent.get::<CallTracer>()?.0);
+        let mut attributes = rpc_attributes(method);
+        attributes.extend(server_attributes(url));
+        let span = tracer.span_builder(method.to_string()).with_kind(SpanKind::Client).with_attributes(attributes).start_with_context(tracer.as_ref(), &parent);
+        Some(Self { cx: parent.with_span(span) })
+    }
+
+    /// `headers` with a `traceparent` naming this attempt added.
+    pub(crate) fn inject(&self, headers: Option<&HeaderMap>) -> HeaderMap {
+        let mut headers = headers.cloned().unwrap_or_default();
+        TraceContextPropagator::new().inject_context(&self.cx, &mut HeaderInjector(&mut headers));
+        headers
+    }
+
+    pub(crate) fn finish(self, error: Option<&RpcHandlerError>) {
+        end(self.cx.span(), error);
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
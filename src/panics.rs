@@ -0,0 +1,59 @@
+//! Panics in user-supplied callbacks, caught where they happen.
+//!
+//! A `ResultComparator`, an `EndpointProbe` or a `TransportFactory`'s transport that panics fails
+//! only what it was called for: a vote, a probe, one endpoint's part of a fan-out. The panic
+//! becomes `RpcHandlerError::CallbackPanicked`, which counts as that endpoint failing rather than
+//! vanishing from the tally, and the handler counts it per component in
+//! `MetricsSnapshot::panics` and emits `HandlerEvent::CallbackPanicked`. No callback is called
+//! with a lock of the handler's held, so a panic can't leave one held or poisoned.
+
+use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+
+use futures::FutureExt;
+
+use crate::{events::HandlerEvent, runtime::JoinError, Result, RpcHandler, RpcHandlerError};
+
+/// `ResultComparator::key` and `merge`.
+pub const COMPARATOR: &str = "comparator";
+/// `EndpointProbe::probe`.
+pub const CUSTOM_PROBE: &str = "custom_probe";
+/// One endpoint's part of a race, quorum or consensus read, its transport included.
+pub const FAN_OUT_TASK: &str = "fan_out_task";
+
+/// Run `callback`, failing with `CallbackPanicked` for `component` if it panics.
+pub(crate) fn contain<R>(component: &str, callback: impl FnOnce() -> R) -> Result<R> {
+    std::panic::catch_unwind(AssertUnwindSafe(callback)).map_err(|payload| panicked(component, &*payload))
+}
+
+/// Await `future`, failing with `CallbackPanicked` for `component` if it panics.
+pub(crate) async fn contain_future<F: Future>(component: &str, future: F) -> Result<F::Output> {
+    AssertUnwindSafe(future).catch_unwind().await.map_err(|payload| panicked(component, &*payload))
+}
+
+/// The error for a task that panicked, `None` for one that was cancelled.
+pub(crate) fn join_failure(component: &str, error: JoinError) -> Option<RpcHandlerError> {
+    error.try_into_panic().ok().map(|payload| panicked(component, &*payload))
+}
+
+fn panicked(component: &str, payload: &(dyn Any + Send)) -> RpcHandlerError {
+    RpcHandlerError::CallbackPanicked { component: component.to_string(), message: panic_message(payload).to_string() }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string payload")
+}
+
+impl RpcHandler {
+    /// Count and announce `error` if it is a caught panic.
+    pub(crate) fn record_panic(&self, error: &RpcHandlerError) {
+        let RpcHandlerError::CallbackPanicked { component, message } = error else { return };
+        let message = self.redact(message);
+        tracing::error!(component = %component, message = %message, "Callback panicked");
+        self.metrics().record_panic(component);
+        self.emit(HandlerEvent::CallbackPanicked { component: component.clone(), message });
+    }
+}
@@ -0,0 +1,105 @@
+//! Application-specific health checks run after the built-in block and bytecode probes.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+use web_time::Instant;
+
+use super::measure::PanicSink;
+use crate::{panics, provider::{HostLimiter, TrafficClass}, runtime, transport::JsonRpcTransport, JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError};
+
+/// A check of an endpoint beyond the built-in ones, set in `HandlerSettings::custom_probes`.
+///
+/// Runs once per endpoint and probe sweep, only for endpoints that passed the built-in probes,
+/// with the rest of the endpoint's probe timeout to finish in. A probe that panics or runs out of
+/// time fails.
+#[async_trait]
+pub trait EndpointProbe: Send + Sync {
+    /// Reported with the probe's outcomes.
+    fn name(&self) -> &str;
+
+    /// Check the endpoint at `url`, sending any requests through `transport`.
+    async fn probe(&self, url: &str, transport: &dyn JsonRpcTransport) -> ProbeOutcome;
+}
+
+impl fmt::Debug for dyn EndpointProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EndpointProbe").field(&self.name()).finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeOutcome {
+    Pass,
+    Fail(String),
+    /// The probe doesn't apply to the endpoint; counts as neither pass nor fail
+    Skip,
+}
+
+impl ProbeOutcome {
+    pub fn is_fail(&self) -> bool {
+        matches!(self, ProbeOutcome::Fail(_))
+    }
+}
+
+/// What a custom probe found at one endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NamedProbeOutcome {
+    /// `EndpointProbe::name`
+    pub probe: String,
+    pub outcome: ProbeOutcome,
+}
+
+/// The transport custom probes get: the endpoint's own transport, behind the host limits and
+/// with no request outliving the endpoint's probe deadline.
+pub(crate) struct ScopedTransport<'a> {
+    pub inner: &'a dyn JsonRpcTransport,
+    pub host_limiter: &'a HostLimiter,
+    /// The endpoint's probe timeout, which `deadline` ends
+    pub timeout: Duration,
+    pub deadline: Instant,
+}
+
+#[async_trait]
+impl JsonRpcTransport for ScopedTransport<'_> {
+    fn url(&self) -> &str {
+        self.inner.url()
+    }
+
+    async fn request(&self, request: &JsonRpcRequest) -> Result<JsonRpcResponse<Value>> {
+        let url = self.url();
+        let timed_out = || RpcHandlerError::RequestTimeout { url: url.to_string(), configured_ms: self.timeout.as_millis() as u64 };
+        let send = async {
+            let _permit = self.host_limiter.acquire_as(url, TrafficClass::Probe).await;
+            let body = self.inner.exchange(request).await.body?;
+            serde_json::from_value(body).map_err(|e| RpcHandlerError::BodyDecode { url: url.to_string(), detail: e.to_string() })
+        };
+        runtime::timeout(self.deadline.saturating_duration_since(Instant::now()), send).await.map_err(|_| timed_out())?
+    }
+}
+
+/// Run `probes` one after another against `transport`'s endpoint, each failing if it panics or
+/// is still running at `transport.deadline`. Panics are passed to `on_panic` as well.
+pub(crate) async fn run_custom_probes(probes: &[Arc<dyn EndpointProbe>], transport: &ScopedTransport<'_>, on_panic: Option<&PanicSink>) -> Vec<NamedProbeOutcome> {
+    let mut outcomes = Vec::with_capacity(probes.len());
+    for probe in probes {
+        let run = panics::contain_future(panics::CUSTOM_PROBE, probe.probe(transport.url(), transport));
+        let outcome = match runtime::timeout(transport.deadline.saturating_duration_since(Instant::now()), run).await {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(panicked)) => {
+                let RpcHandlerError::CallbackPanicked { message, .. } = &panicked else { unreachable!("contain_future fails only with CallbackPanicked") };
+                let outcome = ProbeOutcome::Fail(format!("probe panicked: {message}"));
+                if let Some(on_panic) = on_panic {
+                    on_panic(panicked);
+                }
+                outcome
+            }
+            Err(_) => ProbeOutcome::Fail("probe deadline passed".to_string()),
+        };
+        outcomes.push(NamedProbeOutcome { probe: probe.name().to_string(), outcome });
+    }
+    outcomes
+}
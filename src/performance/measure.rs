@@ -1,17 +1,224 @@
-use std::{collections::HashMap, time::{Duration, Instant}};
-use crate::{JsonRpcRequest, Rpc, Result};
-use futures::future::join_all;
+use std::{collections::{HashMap, HashSet}, fmt, net::IpAddr, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+use web_time::Instant;
+use crate::{methods, namespaces::parse_quantity, provider::{HostLimiter, NonJsonRpcResponse, TrafficClass}, runtime, spend::SpendMeter, transport::{HttpTransportFactory, JsonRpcTransport, TransportFactory}, AdaptiveProbeTimeout, CustomProbePolicy, JsonRpcRequest, Rpc, Result, RpcHandlerError};
+use super::custom_probe::{run_custom_probes, EndpointProbe, NamedProbeOutcome, ScopedTransport};
+use futures::{stream::FuturesUnordered, StreamExt};
+use serde::Serialize;
 use serde_json::{json, Value};
+use tokio::sync::Semaphore;
 
 pub type LatencyMap = HashMap<String, u64>;
 
+/// Endpoints probed at once unless `MeasureOptions::max_concurrent_probes` says otherwise.
+pub const DEFAULT_MAX_CONCURRENT_PROBES: usize = 16;
+
+/// Called with `(probed, total)` as each endpoint's probes finish.
+pub type ProbeProgress = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Called with the `CallbackPanicked` error of each custom probe that panics.
+pub type PanicSink = Arc<dyn Fn(RpcHandlerError) + Send + Sync>;
+
+/// How `measure_rpcs_with_options` probes.
+#[derive(Clone)]
+pub struct MeasureOptions {
+    pub timeout_policy: TimeoutPolicy,
+    /// Re-send a probe once to a same-host redirect target. Only for the HTTP transports
+    /// `measure_rpcs_with_options` builds; a `TransportFactory` handles redirects itself
+    pub follow_redirects: bool,
+    /// Per-host caps probes wait on, for at most the probe timeout
+    pub host_limiter: HostLimiter,
+    /// Endpoints probed at once, each holding one slot for its block and code requests.
+    /// Halved, down to one, when probes are rate limited.
+    pub max_concurrent_probes: usize,
+    /// Stop the sweep after this long, returning what was measured so far
+    pub sweep_deadline: Option<Duration>,
+    pub on_progress: Option<ProbeProgress>,
+    /// Charged for both of an endpoint's probes before they go out; probes are free when `None`
+    pub spend: Option<SpendMeter>,
+    /// Blocks an endpoint's head may be off the most common one and still count as in sync
+    pub max_lag_blocks: u64,
+    /// Probe for the Permit2 bytecode alongside the block; off for a local node, which may not
+    /// have it deployed
+    pub check_bytecode: bool,
+    /// Run after the built-in probes, within what is left of the endpoint's timeout
+    pub custom_probes: Vec<Arc<dyn EndpointProbe>>,
+    pub custom_probe_policy: CustomProbePolicy,
+    pub on_panic: Option<PanicSink>,
+}
+
+impl fmt::Debug for MeasureOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MeasureOptions")
+            .field("timeout_policy", &self.timeout_policy)
+            .field("follow_redirects", &self.follow_redirects)
+            .field("host_limiter", &self.host_limiter)
+            .field("max_concurrent_probes", &self.max_concurrent_probes)
+            .field("sweep_deadline", &self.sweep_deadline)
+            .field("has_on_progress", &self.on_progress.is_some())
+            .field("spend", &self.spend)
+            .field("max_lag_blocks", &self.max_lag_blocks)
+            .field("check_bytecode", &self.check_bytecode)
+            .field("custom_probes", &self.custom_probes)
+            .field("custom_probe_policy", &self.custom_probe_policy)
+            .field("has_on_panic", &self.on_panic.is_some())
+            .finish()
+    }
+}
+
+impl MeasureOptions {
+    /// Probe with `timeout_policy`, the default concurrency and no deadline.
+    pub fn new(timeout_policy: TimeoutPolicy) -> Self {
+        Self {
+            timeout_policy,
+            follow_redirects: false,
+            host_limiter: HostLimiter::default(),
+            max_concurrent_probes: DEFAULT_MAX_CONCURRENT_PROBES,
+            sweep_deadline: None,
+            on_progress: None,
+            spend: None,
+            max_lag_blocks: 0,
+            check_bytecode: true,
+            custom_probes: Vec::new(),
+            custom_probe_policy: CustomProbePolicy::default(),
+            on_panic: None,
+        }
+    }
+}
+
+/// The probe slots of one sweep, shrinking when endpoints start answering 429.
+struct ProbeSlots {
+    semaphore: Semaphore,
+    limit: AtomicUsize,
+    /// Slots to retire as probes release them
+    to_retire: AtomicUsize,
+    /// Bumped by each reduction, so a wave of 429s sent at the old limit reduces it once
+    generation: AtomicUsize,
+}
+
+impl ProbeSlots {
+    fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self { semaphore: Semaphore::new(limit), limit: AtomicUsize::new(limit), to_retire: AtomicUsize::new(0), generation: AtomicUsize::new(0) }
+    }
+
+    fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Halve the limit after a probe started in `generation` was rate limited.
+    fn rate_limited(&self, generation: usize) {
+        if self.generation.compare_exchange(generation, generation + 1, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return;
+        }
+        let limit = self.limit.load(Ordering::Acquire);
+        let reduced = (limit / 2).max(1);
+        self.limit.store(reduced, Ordering::Release);
+        self.to_retire.fetch_add(limit - reduced, Ordering::AcqRel);
+    }
+
+    /// Give a slot back, or retire it if the limit went down since it was taken.
+    fn release(&self, permit: tokio::sync::SemaphorePermit<'_>) {
+        let retired = self.to_retire.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1)).is_ok();
+        if retired {
+            permit.forget();
+        }
+    }
+}
+
+/// Picks the timeout each endpoint's probes get.
+#[derive(Debug, Clone)]
+pub enum TimeoutPolicy {
+    /// The same timeout for every endpoint
+    Fixed(Duration),
+    /// A multiple of the recent healthy-latency p95 for endpoints already known to be healthy
+    Adaptive {
+        /// Used for endpoints outside `known_healthy`, and for all of them while `history` is empty
+        fallback: Duration,
+        settings: AdaptiveProbeTimeout,
+        /// Recent healthy probe latencies for the network, in milliseconds
+        history: Vec<u64>,
+        /// Endpoints whose previous probe succeeded
+        known_healthy: HashSet<String>,
+    },
+}
+
+/// The timeouts a measurement used, for logs and reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ProbeTimeouts {
+    /// Timeout for endpoints without a healthy probe yet
+    pub fallback_ms: u64,
+    /// Timeout for previously healthy endpoints, `None` when there was no history to derive it from
+    pub adaptive_ms: Option<u64>,
+    /// p95 of the history `adaptive_ms` was derived from
+    pub p95_ms: Option<u64>,
+}
+
+impl TimeoutPolicy {
+    /// The adaptive timeout and the p95 it came from, `None` for fixed policies or without history.
+    fn adaptive(&self) -> Option<(Duration, u64)> {
+        let TimeoutPolicy::Adaptive { settings, history, .. } = self else { return None };
+        let p95 = percentile(history, 0.95)?;
+        let scaled = (p95 as f64 * settings.multiplier).ceil() as u64;
+        let clamped = scaled.clamp(settings.floor_ms, settings.ceiling_ms.max(settings.floor_ms));
+        Some((Duration::from_millis(clamped), p95))
+    }
+
+    fn fallback(&self) -> Duration {
+        match self {
+            TimeoutPolicy::Fixed(timeout) => *timeout,
+            TimeoutPolicy::Adaptive { fallback, .. } => *fallback,
+        }
+    }
+
+    /// The timeouts this policy resolves to for one measurement.
+    pub fn timeouts(&self) -> ProbeTimeouts {
+        let adaptive = self.adaptive();
+        ProbeTimeouts {
+            fallback_ms: self.fallback().as_millis() as u64,
+            adaptive_ms: adaptive.map(|(timeout, _)| timeout.as_millis() as u64),
+            p95_ms: adaptive.map(|(_, p95)| p95),
+        }
+    }
+
+    /// The probe timeout for `url`.
+    pub fn timeout_for(&self, url: &str) -> Duration {
+        match self {
+            TimeoutPolicy::Adaptive { known_healthy, .. } if known_healthy.contains(url) => {
+                self.adaptive().map_or_else(|| self.fallback(), |(timeout, _)| timeout)
+            }
+            _ => self.fallback(),
+        }
+    }
+}
+
+/// Nearest-rank percentile of `samples`, `None` when empty.
+fn percentile(samples: &[u64], quantile: f64) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
 #[derive(Debug, Clone)]
 pub struct RpcCheckResult {
     pub url: String,
     pub success: bool,
     pub duration: u64,
     pub block_number: Option<String>,
+    /// Blocks behind the most common head of the sweep, `None` without a block number
+    pub head_lag: Option<u64>,
+    /// Timestamp of the probed head block, seconds since the Unix epoch
+    pub block_timestamp: Option<u64>,
     pub bytecode_ok: bool,
+    /// The IP the block probe actually connected to, when known
+    pub remote_ip: Option<IpAddr>,
+    /// Set when either probe was answered with a redirect or a non-JSON body
+    pub non_json_rpc: Option<NonJsonRpcResponse>,
+    /// Outcomes of `MeasureOptions::custom_probes`, empty when the built-in probes failed
+    pub custom_probes: Vec<NamedProbeOutcome>,
 }
 
 const PERMIT2_ADDRESS: &str = "0x000000000022D473030F116dDEE9F6B43aC78BA3";
@@ -25,146 +232,228 @@ fn is_permit2_bytecode_valid(bytecode: Option<&str>) -> bool {
     }
 }
 
+struct ProbeResponse {
+    ok: bool,
+    rate_limited: bool,
+    data: Option<Value>,
+    duration: u64,
+    remote_ip: Option<IpAddr>,
+    non_json_rpc: Option<NonJsonRpcResponse>,
+}
+
 async fn post_request(
-    client: &reqwest::Client,
-    url: &str,
+    transport: &dyn JsonRpcTransport,
     payload: &JsonRpcRequest,
     timeout: Duration,
-) -> Result<(bool, Option<Value>, u64)> {
+    host_limiter: &HostLimiter,
+) -> ProbeResponse {
+    // The wait for a host slot is bounded by the probe timeout but left out of the latency
+    let Ok(_permit) = runtime::timeout(timeout, host_limiter.acquire_as(transport.url(), TrafficClass::Probe)).await else {
+        return ProbeResponse { ok: false, rate_limited: false, data: None, duration: timeout.as_millis() as u64, remote_ip: None, non_json_rpc: None };
+    };
     let start = Instant::now();
     
-    let response = tokio::time::timeout(
-        timeout,
-        client.post(url)
-            .json(payload)
-            .send()
-    ).await;
+    let exchange = runtime::timeout(timeout, transport.exchange(payload)).await;
     
     let duration = start.elapsed().as_millis() as u64;
+    let failed = |non_json_rpc| ProbeResponse { ok: false, rate_limited: false, data: None, duration, remote_ip: None, non_json_rpc };
     
-    match response {
-        Ok(Ok(res)) => {
-            if res.status().is_success() {
-                match res.json::<Value>().await {
-                    Ok(json_data) => {
-                        let has_result = json_data.get("result").is_some();
-                        Ok((has_result, Some(json_data), duration))
-                    }
-                    Err(_) => Ok((false, None, duration))
-                }
-            } else {
-                Ok((false, None, duration))
-            }
+    let Ok(exchange) = exchange else { return failed(None) };
+    let remote_ip = exchange.remote_ip;
+    match exchange.body {
+        Ok(json_data) => {
+            // Absent is a failure; `null` only for a method that may answer with it
+            let answered = match json_data.get("result") {
+                None => false,
+                Some(Value::Null) => methods::null_result_valid(&payload.method),
+                Some(_) => true,
+            };
+            ProbeResponse { ok: answered, rate_limited: false, data: Some(json_data), duration, remote_ip, non_json_rpc: None }
+        }
+        Err(RpcHandlerError::NotAJsonRpcEndpoint { content_type, status, .. }) => {
+            failed(Some(NonJsonRpcResponse { content_type, status }))
         }
-        Ok(Err(_)) | Err(_) => Ok((false, None, duration))
+        Err(error) => ProbeResponse { remote_ip, rate_limited: error.is_rate_limited(), ..failed(None) }
     }
 }
 
 /// Measure RPCs: run block + code requests in parallel, validate common block number logic later externally.
-pub async fn measure_rpcs(rpcs: &[Rpc], timeout: Duration) -> Result<(LatencyMap, Vec<RpcCheckResult>)> {
-    let client = reqwest::Client::new();
-    
-    let block_payload = JsonRpcRequest {
-        jsonrpc: "2.0".to_string(),
-        method: "eth_getBlockByNumber".to_string(),
-        params: json!(["latest", false]),
-        id: Some(1),
-    };
-    
-    let code_payload = JsonRpcRequest {
-        jsonrpc: "2.0".to_string(),
-        method: "eth_getCode".to_string(),
-        params: json!([PERMIT2_ADDRESS, "latest"]),
-        id: Some(1),
-    };
+///
+/// Each endpoint is probed through the transport `transport` hands out for its URL;
+/// `HttpTransportFactory::new(timeout)` probes over plain HTTP.
+pub async fn measure_rpcs(transport: &dyn TransportFactory, rpcs: &[Rpc], timeout: Duration) -> Result<(LatencyMap, Vec<RpcCheckResult>)> {
+    measure_rpcs_with_transport(transport, rpcs, &MeasureOptions::new(TimeoutPolicy::Fixed(timeout))).await
+}
+
+/// `measure_rpcs` over a caller-supplied client, e.g. one with a custom DNS resolver.
+///
+/// The client should not follow redirects itself (see `provider::rpc_client_builder`);
+/// `follow_redirects` re-sends a probe once to a same-host redirect target.
+pub async fn measure_rpcs_with_client(
+    client: &reqwest::Client,
+    rpcs: &[Rpc],
+    timeout: Duration,
+    follow_redirects: bool,
+) -> Result<(LatencyMap, Vec<RpcCheckResult>)> {
+    let options = MeasureOptions { follow_redirects, ..MeasureOptions::new(TimeoutPolicy::Fixed(timeout)) };
+    measure_rpcs_with_options(client, rpcs, &options).await
+}
+
+/// `measure_rpcs_with_client` with each endpoint's timeout chosen by `options.timeout_policy`,
+/// at most `options.max_concurrent_probes` endpoints at a time.
+///
+/// Results come back in the order of `rpcs`. Past `options.sweep_deadline` the probes still
+/// running are dropped and left out of the results.
+pub async fn measure_rpcs_with_options(
+    client: &reqwest::Client,
+    rpcs: &[Rpc],
+    options: &MeasureOptions,
+) -> Result<(LatencyMap, Vec<RpcCheckResult>)> {
+    let transport = HttpTransportFactory::with_client(client.clone(), options.timeout_policy.fallback())
+        .follow_redirects(options.follow_redirects);
+    measure_rpcs_with_transport(&transport, rpcs, options).await
+}
+
+/// `measure_rpcs_with_options` over the transports `transport` hands out, which decide how each
+/// endpoint is reached, its headers and redirects included.
+pub async fn measure_rpcs_with_transport(
+    transport: &dyn TransportFactory,
+    rpcs: &[Rpc],
+    options: &MeasureOptions,
+) -> Result<(LatencyMap, Vec<RpcCheckResult>)> {
+    let host_limiter = &options.host_limiter;
+    let block_payload = JsonRpcRequest::new("eth_getBlockByNumber", json!(["latest", false]));
+    let code_payload = JsonRpcRequest::new("eth_getCode", json!([PERMIT2_ADDRESS, "latest"]));
     
-    let tasks: Vec<_> = rpcs.iter().map(|rpc| {
+    let slots = ProbeSlots::new(options.max_concurrent_probes);
+    let mut tasks: FuturesUnordered<_> = rpcs.iter().enumerate().map(|(index, rpc)| {
         let url = rpc.url.to_string();
-        let client = &client;
+        let timeout = options.timeout_policy.timeout_for(&url);
         let block_req = &block_payload;
         let code_req = &code_payload;
+        let slots = &slots;
+        let endpoint = transport.transport(&url);
         
         async move {
-            let block_future = post_request(client, &url, block_req, timeout);
-            let code_future = post_request(client, &url, code_req, timeout);
+            // One slot covers both requests, so an endpoint's probes always go out together
+            let permit = slots.semaphore.acquire().await.expect("probe slots are never closed");
+            let deadline = Instant::now() + timeout;
+            let generation = slots.generation();
+            // An endpoint that can't afford its probes fails them without sending
+            let methods: &[&str] = if options.check_bytecode { &[&block_req.method, &code_req.method] } else { &[&block_req.method] };
+            let affordable = options.spend.as_ref().is_none_or(|spend| spend.charge_all(&url, methods).is_ok());
+            if !affordable {
+                slots.release(permit);
+                return (index, RpcCheckResult { url, success: false, duration: 0, block_number: None, head_lag: None, block_timestamp: None, bytecode_ok: false, remote_ip: None, non_json_rpc: None, custom_probes: Vec::new() });
+            }
+            let block_future = post_request(&*endpoint, block_req, timeout, host_limiter);
+            let code_future = async {
+                match options.check_bytecode {
+                    true => post_request(&*endpoint, code_req, timeout, host_limiter).await,
+                    false => ProbeResponse { ok: true, rate_limited: false, data: None, duration: 0, remote_ip: None, non_json_rpc: None },
+                }
+            };
             
             let (block_result, code_result) = tokio::join!(block_future, code_future);
+            if block_result.rate_limited || code_result.rate_limited {
+                slots.rate_limited(generation);
+            }
             
-            let mut block_number: Option<String> = None;
-            let mut block_ok = false;
-            let mut block_duration = 0u64;
+            let remote_ip = block_result.remote_ip;
+            let non_json_rpc = block_result.non_json_rpc.clone().or(code_result.non_json_rpc.clone());
             
-            if let Ok((ok, data, dur)) = block_result {
-                block_ok = ok;
-                block_duration = dur;
-                if let Some(json_data) = data {
-                    if let Some(result) = json_data.get("result") {
-                        if let Some(number) = result.get("number") {
-                            if let Some(num_str) = number.as_str() {
-                                block_number = Some(num_str.to_string());
-                            }
-                        }
-                    }
-                }
-            }
+            let block = block_result.data.as_ref().and_then(|json_data| json_data.get("result"));
+            let block_number = block
+                .and_then(|result| result.get("number"))
+                .and_then(|number| number.as_str())
+                .map(str::to_string);
+            let block_timestamp = block.and_then(|result| result.get("timestamp")).and_then(parse_quantity);
             
-            let mut code_ok = false;
-            let mut code_duration = 0u64;
-            let mut bytecode: Option<String> = None;
+            let bytecode = code_result.data
+                .as_ref()
+                .and_then(|json_data| json_data.get("result"))
+                .and_then(|result| result.as_str());
             
-            if let Ok((ok, data, dur)) = code_result {
-                code_ok = ok;
-                code_duration = dur;
-                if let Some(json_data) = data {
-                    if let Some(result) = json_data.get("result") {
-                        if let Some(code_str) = result.as_str() {
-                            bytecode = Some(code_str.to_string());
-                        }
-                    }
+            let bytecode_ok = !options.check_bytecode || is_permit2_bytecode_valid(bytecode);
+            let mut success = block_result.ok && code_result.ok && bytecode_ok;
+            let duration = std::cmp::max(block_result.duration, code_result.duration);
+
+            let mut custom_probes = Vec::new();
+            if success && !options.custom_probes.is_empty() {
+                let transport = ScopedTransport { inner: &*endpoint, host_limiter, timeout, deadline };
+                custom_probes = run_custom_probes(&options.custom_probes, &transport, options.on_panic.as_ref()).await;
+                if options.custom_probe_policy == CustomProbePolicy::Exclude {
+                    success = !custom_probes.iter().any(|probe| probe.outcome.is_fail());
                 }
             }
+            slots.release(permit);
             
-            let bytecode_ok = is_permit2_bytecode_valid(bytecode.as_deref());
-            let success = block_ok && code_ok && bytecode_ok;
-            let duration = std::cmp::max(block_duration, code_duration);
-            
-            RpcCheckResult {
+            (index, RpcCheckResult {
                 url,
                 success,
                 duration,
                 block_number,
+                head_lag: None,
+                block_timestamp,
                 bytecode_ok,
-            }
+                remote_ip,
+                non_json_rpc,
+                custom_probes,
+            })
         }
     }).collect();
     
-    let results = join_all(tasks).await;
+    let mut finished = Vec::with_capacity(rpcs.len());
+    let deadline = async {
+        match options.sweep_deadline {
+            Some(deadline) => runtime::sleep(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            next = tasks.next() => match next {
+                Some(result) => {
+                    finished.push(result);
+                    if let Some(on_progress) = &options.on_progress {
+                        on_progress(finished.len(), rpcs.len());
+                    }
+                }
+                None => break,
+            },
+            _ = &mut deadline => break,
+        }
+    }
+    drop(tasks);
+    finished.sort_by_key(|(index, _)| *index);
+    let mut results: Vec<RpcCheckResult> = finished.into_iter().map(|(_, result)| result).collect();
     
     // Determine most common block number
-    let mut counts: HashMap<String, usize> = HashMap::new();
-    for result in &results {
-        if let Some(ref block_num) = result.block_number {
-            *counts.entry(block_num.clone()).or_insert(0) += 1;
-        }
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for number in results.iter().filter_map(probed_block) {
+        *counts.entry(number).or_insert(0) += 1;
     }
     
     let most_common = counts
         .into_iter()
-        .max_by_key(|(_, count)| *count)
-        .map(|(block_num, _)| block_num);
+        .max_by_key(|(number, count)| (*count, *number))
+        .map(|(number, _)| number);
     
     // Build latency map excluding out-of-sync RPCs
     let mut latencies = HashMap::new();
-    for result in &results {
+    for result in &mut results {
+        let block = probed_block(result);
+        result.head_lag = block.zip(most_common).map(|(number, common)| common.saturating_sub(number));
         if !result.success {
             continue;
         }
         
-        // Skip if out of sync with most common block number
-        if let (Some(block_num), Some(common)) = (&result.block_number, &most_common) {
-            if block_num != common {
-                continue;
-            }
+        // Skip if further from the most common block number than allowed, ahead or behind
+        if let (Some(number), Some(common)) = (block, most_common)
+            && number.abs_diff(common) > options.max_lag_blocks
+        {
+            continue;
         }
         
         latencies.insert(result.url.clone(), result.duration);
@@ -172,3 +461,19 @@ pub async fn measure_rpcs(rpcs: &[Rpc], timeout: Duration) -> Result<(LatencyMap
     
     Ok((latencies, results))
 }
+
+fn probed_block(result: &RpcCheckResult) -> Option<u64> {
+    let number = result.block_number.as_deref()?;
+    parse_quantity(&Value::String(number.to_string()))
+}
+
+/// Endpoints whose probes succeeded but were left out of the latency map for being out of sync.
+///
+/// Methods that don't depend on chain state (see `namespaces::requires_block_sync`) can still use them.
+pub fn lagging_latencies(results: &[RpcCheckResult], latencies: &LatencyMap) -> LatencyMap {
+    results
+        .iter()
+        .filter(|result| result.success && !latencies.contains_key(&result.url))
+        .map(|result| (result.url.clone(), result.duration))
+        .collect()
+}
@@ -1,5 +1,13 @@
+pub mod custom_probe;
 pub mod measure;
+pub mod observed;
+pub mod ordering;
 pub mod pick_fastest;
+pub mod probe_schedule;
 
-pub use measure::{measure_rpcs, LatencyMap, RpcCheckResult};
-pub use pick_fastest::pick_fastest;
+pub use custom_probe::{EndpointProbe, NamedProbeOutcome, ProbeOutcome};
+pub use measure::{lagging_latencies, measure_rpcs, measure_rpcs_with_client, measure_rpcs_with_options, measure_rpcs_with_transport, LatencyMap, MeasureOptions, PanicSink, ProbeProgress, ProbeTimeouts, RpcCheckResult, TimeoutPolicy, DEFAULT_MAX_CONCURRENT_PROBES};
+pub use ordering::{group_by_tier, order_urls, TierMap};
+pub(crate) use ordering::tier_of;
+pub use pick_fastest::{pick_fastest, pick_fastest_in_lowest_tier};
+pub use probe_schedule::ProbeSchedule;
@@ -0,0 +1,36 @@
+//! Latencies learned from traffic between probes.
+//!
+//! Probes set each endpoint's latency outright. Requests the handler fans out itself, such as
+//! consensus sub-requests, then move it toward what they observe, so a handler doing mostly that
+//! kind of work still orders its endpoints by how they answer now. Heavy methods answer slowly
+//! by nature; their observations are kept in a bucket of their own and never touch the ordering.
+
+use std::{collections::HashMap, sync::Arc};
+#[cfg(feature = "consensus")]
+use std::time::Duration;
+
+/// Weight an observation gets against the latency it is blended into.
+pub const OBSERVATION_WEIGHT: f64 = 0.3;
+
+/// `current` moved `OBSERVATION_WEIGHT` of the way toward `observed`.
+pub fn blend(current: u64, observed: u64) -> u64 {
+    (current as f64 + OBSERVATION_WEIGHT * (observed as f64 - current as f64)).round() as u64
+}
+
+/// Blended latencies of heavy-method requests per endpoint. Cloning shares them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HeavyLatencies(Arc<parking_lot::Mutex<HashMap<String, u64>>>);
+
+impl HeavyLatencies {
+    #[cfg(feature = "consensus")]
+    pub(crate) fn observe(&self, url: &str, elapsed: Duration) {
+        let observed = elapsed.as_millis() as u64;
+        let mut latencies = self.0.lock();
+        let latency = latencies.entry(url.to_string()).or_insert(observed);
+        *latency = blend(*latency, observed);
+    }
+
+    pub(crate) fn snapshot(&self) -> HashMap<String, u64> {
+        self.0.lock().clone()
+    }
+}
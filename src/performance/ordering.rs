@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::{performance::LatencyMap, FailoverPolicy};
+
+/// Tier lookup keyed by the endpoint URL as it appears in the latency map. Untiered endpoints
+/// are left out and sort last.
+pub type TierMap = HashMap<String, u8>;
+
+/// `url`'s tier, with untiered endpoints placed in the lowest priority tier.
+pub(crate) fn tier_of(tiers: &TierMap, url: &str) -> u8 {
+    tiers.get(url).copied().unwrap_or(u8::MAX)
+}
+
+/// Order measured URLs for failover.
+///
+/// Under `FailoverPolicy::Latency` only latency matters, under `TierStrict` the tier is the
+/// primary key and latency only breaks ties within a tier. URL is the final tie-break so the
+/// ordering is stable between calls.
+pub fn order_urls(latencies: &LatencyMap, tiers: &TierMap, policy: FailoverPolicy) -> Vec<String> {
+    let mut ordered: Vec<(&String, u64, u8)> = latencies
+        .iter()
+        .map(|(url, &latency)| (url, latency, tier_of(tiers, url)))
+        .collect();
+
+    match policy {
+        FailoverPolicy::Latency => ordered.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0))),
+        FailoverPolicy::TierStrict => {
+            ordered.sort_by(|a, b| a.2.cmp(&b.2).then(a.1.cmp(&b.1)).then_with(|| a.0.cmp(b.0)))
+        }
+    }
+
+    ordered.into_iter().map(|(url, _, _)| url.clone()).collect()
+}
+
+/// Split an ordered URL list into the groups the retry proxy must exhaust in sequence.
+///
+/// Every URL lands in a single group under `Latency`; under `TierStrict` each tier is its own group.
+pub fn group_by_tier(urls: &[String], tiers: &TierMap, policy: FailoverPolicy) -> Vec<Vec<String>> {
+    if policy == FailoverPolicy::Latency {
+        return if urls.is_empty() { Vec::new() } else { vec![urls.to_vec()] };
+    }
+
+    let mut groups: Vec<(u8, Vec<String>)> = Vec::new();
+    for url in urls {
+        let tier = tier_of(tiers, url);
+        match groups.iter_mut().find(|(t, _)| *t == tier) {
+            Some((_, group)) => group.push(url.clone()),
+            None => groups.push((tier, vec![url.clone()])),
+        }
+    }
+    groups.sort_by_key(|(tier, _)| *tier);
+    groups.into_iter().map(|(_, group)| group).collect()
+}
@@ -1,4 +1,4 @@
-use crate::performance::LatencyMap;
+use crate::performance::{LatencyMap, TierMap};
 
 pub fn pick_fastest(latencies: &LatencyMap) -> Option<String> {
     latencies
@@ -6,3 +6,11 @@ pub fn pick_fastest(latencies: &LatencyMap) -> Option<String> {
         .min_by_key(|(_, latency)| *latency)
         .map(|(url, _)| url.clone())
 }
+
+/// Fastest URL within the lowest tier that has any measured (healthy) endpoint.
+pub fn pick_fastest_in_lowest_tier(latencies: &LatencyMap, tiers: &TierMap) -> Option<String> {
+    latencies
+        .iter()
+        .min_by_key(|(url, latency)| (tiers.get(*url).copied().unwrap_or(u8::MAX), **latency))
+        .map(|(url, _)| url.clone())
+}
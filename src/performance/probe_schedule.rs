@@ -0,0 +1,80 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use web_time::Instant;
+
+use crate::{memory::evict_to_capacity, provider::NonJsonRpcResponse};
+
+/// How long an endpoint classified as not JSON-RPC is left out of probes after the first strike.
+pub const NON_JSON_RPC_SKIP_BASE: Duration = Duration::from_secs(10 * 60);
+/// Upper bound for the doubling skip window.
+pub const NON_JSON_RPC_SKIP_MAX: Duration = Duration::from_secs(6 * 60 * 60);
+/// How long an endpoint that couldn't be connected to within the connect timeout is left out.
+pub const UNREACHABLE_SKIP: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+struct SkipEntry {
+    /// `None` for endpoints that were only unreachable
+    classification: Option<NonJsonRpcResponse>,
+    strikes: u32,
+    until: Instant,
+}
+
+/// Endpoints kept out of probes because they don't speak JSON-RPC, or couldn't be connected to.
+///
+/// Unlike transient failures the former are unlikely to fix themselves, so each repeat
+/// classification doubles the skip window. An unreachable endpoint gets a short fixed window
+/// instead. A successful probe once the window lapses clears the entry.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeSchedule {
+    entries: Arc<parking_lot::Mutex<HashMap<String, SkipEntry>>>,
+}
+
+impl ProbeSchedule {
+    pub fn record_non_json_rpc(&self, url: &str, classification: NonJsonRpcResponse, now: Instant) {
+        let mut entries = self.entries.lock();
+        let strikes = entries.get(url).map_or(0, |entry| entry.strikes) + 1;
+        let window = NON_JSON_RPC_SKIP_BASE
+            .saturating_mul(1 << (strikes - 1).min(16))
+            .min(NON_JSON_RPC_SKIP_MAX);
+        entries.insert(url.to_string(), SkipEntry { classification: Some(classification), strikes, until: now + window });
+    }
+
+    /// Skip `url` for `UNREACHABLE_SKIP`, never shortening a window it is already in.
+    pub fn record_unreachable(&self, url: &str, now: Instant) {
+        let until = now + UNREACHABLE_SKIP;
+        self.entries
+            .lock()
+            .entry(url.to_string())
+            .and_modify(|entry| entry.until = entry.until.max(until))
+            .or_insert(SkipEntry { classification: None, strikes: 0, until });
+    }
+
+    pub fn record_success(&self, url: &str) {
+        self.entries.lock().remove(url);
+    }
+
+    /// Whether `url` should be left out of probes and fan-outs at `now`.
+    pub fn should_skip(&self, url: &str, now: Instant) -> bool {
+        self.entries.lock().get(url).is_some_and(|entry| entry.until > now)
+    }
+
+    /// The last classification recorded for `url`, whether or not its window has lapsed.
+    pub fn classification(&self, url: &str) -> Option<NonJsonRpcResponse> {
+        self.entries.lock().get(url).and_then(|entry| entry.classification.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+
+    /// Forget URLs `keep` rejects, then cap at `max` entries, never evicting a skip still in its window.
+    pub(crate) fn compact(&self, max: usize, now: Instant, keep: impl Fn(&str) -> bool) {
+        let mut entries = self.entries.lock();
+        entries.retain(|url, _| keep(url));
+        evict_to_capacity(&mut entries, max, |_, entry| entry.until > now, |_, entry| entry.until);
+    }
+}
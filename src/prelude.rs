@@ -0,0 +1,14 @@
+//! The types most handlers are set up and called with, for `use ez_web3_rpc::prelude::*;`.
+
+pub use crate::{
+    calls::{ConsensusOptions, RpcCalls},
+    config::{HandlerConfigBuilder, HandlerSettingsBuilder, PartialHandlerSettings, Profile},
+    error::{Result, RpcHandlerError},
+    handler::{HandlerComponents, RpcHandler},
+    health::HealthReport,
+    jsonrpc::{JsonRpcError, JsonRpcRequest, JsonRpcResponse},
+    localnet::LocalnetOptions,
+    registry::HandlerRegistry,
+    strategy::Strategy,
+    types::{DataScope, FailoverPolicy, HandlerConfig, HandlerSettings, LogLevel, NetworkId, ProxySettings, Rpc, RpcConfig, Tracking},
+};
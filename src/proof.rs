@@ -0,0 +1,317 @@
+//! `eth_getProof` responses and their verification against a state root.
+//!
+//! A proof is checked the way a light client would: the account proof must lead from the state
+//! root to the account's leaf by way of Keccak-256 node hashes, the leaf must hold the account
+//! fields the response claims, and each storage proof must do the same from the account's
+//! storage root to the claimed slot value. Nothing in the response is taken on trust except
+//! what those hashes pin down.
+
+use std::collections::HashMap;
+#[cfg(feature = "consensus")]
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha3::{Digest, Keccak256};
+
+#[cfg(feature = "consensus")]
+use crate::{calls::ConsensusOptions, comparator::ProofComparator};
+use crate::{calls::RpcCalls, hex::unhex, JsonRpcRequest, Result, RpcHandlerError};
+
+/// Root of the empty trie: Keccak-256 of the RLP empty string.
+pub const EMPTY_TRIE_ROOT: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
+/// An `eth_getProof` result, hex fields kept as the endpoint sent them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofResponse {
+    pub address: String,
+    /// RLP-encoded trie nodes from the state root down to the account
+    pub account_proof: Vec<String>,
+    pub balance: String,
+    pub code_hash: String,
+    pub nonce: String,
+    /// Root of the account's storage trie
+    pub storage_hash: String,
+    pub storage_proof: Vec<StorageProof>,
+}
+
+/// One requested slot: its value and the nodes from the storage root down to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageProof {
+    pub key: String,
+    pub value: String,
+    pub proof: Vec<String>,
+}
+
+
+impl RpcCalls {
+    /// Account and storage proofs for `address` at `block_id`.
+    ///
+    /// Proofs for old blocks need an archive node, so `eth_getProof` follows its route like any
+    /// other method: give it a `RouteRule` to the archive endpoints to keep it off pruned ones.
+    pub async fn get_proof(&self, address: &str, storage_keys: &[&str], block_id: &str) -> Result<ProofResponse> {
+        let result = self.try_rpc_call(&JsonRpcRequest::new("eth_getProof", json!([address, storage_keys, block_id]))).await?.into_result()?;
+        serde_json::from_value(result).map_err(|e| RpcHandlerError::SerializationError(format!("eth_getProof: {e}")))
+    }
+
+    /// Like `get_proof`, but the answer must be agreed on by a quorum of endpoints.
+    ///
+    /// Endpoints agree when the account fields, storage root and slot values match; the proof
+    /// node arrays may differ between clients and are ignored. Uses `ProofComparator` unless
+    /// `options` bring their own comparator.
+    #[cfg(feature = "consensus")]
+    pub async fn get_proof_consensus(
+        &self,
+        address: &str,
+        storage_keys: &[&str],
+        block_id: &str,
+        quorum_threshold: f64,
+        options: Option<ConsensusOptions>,
+    ) -> Result<ProofResponse> {
+        let mut options = options.unwrap_or_default();
+        options.comparator.get_or_insert_with(|| Arc::new(ProofComparator));
+        self.consensus(&JsonRpcRequest::new("eth_getProof", json!([address, storage_keys, block_id])), quorum_threshold, Some(options)).await
+    }
+}
+
+/// Verify `proof` against `expected_state_root`, e.g. a block's `stateRoot` obtained by consensus.
+///
+/// `Ok(true)` when the account proof leads to the claimed account and every storage proof to
+/// its claimed value, absent accounts and zero slots included; `Ok(false)` when any of it doesn't
+/// hold up. Fails only if a field isn't valid hex or a node isn't valid RLP.
+pub fn verify_storage_value(proof: &ProofResponse, expected_state_root: &str) -> Result<bool> {
+    let state_root = hash_field("state root", expected_state_root)?;
+    let storage_root = hash_field("storageHash", &proof.storage_hash)?;
+    let claimed_account = [
+        quantity_field("nonce", &proof.nonce)?,
+        quantity_field("balance", &proof.balance)?,
+        storage_root.to_vec(),
+        hash_field("codeHash", &proof.code_hash)?.to_vec(),
+    ];
+
+    let account_proof = nodes_field("accountProof", &proof.account_proof)?;
+    let account = match prove(&state_root, &hex_field("address", &proof.address)?, &account_proof)? {
+        Proven::Broken => return Ok(false),
+        Proven::Absent => [Vec::new(), Vec::new(), EMPTY_TRIE_ROOT.to_vec(), Keccak256::digest([]).to_vec()],
+        Proven::Value(encoded) => {
+            let fields = Rlp::decode(&encoded)?.into_list()?;
+            let [nonce, balance, storage_hash, code_hash] = fields.as_slice() else {
+                return Err(invalid(format!("account with {} fields", fields.len())));
+            };
+            [nonce.bytes()?.to_vec(), balance.bytes()?.to_vec(), storage_hash.bytes()?.to_vec(), code_hash.bytes()?.to_vec()]
+        }
+    };
+    if account != claimed_account {
+        return Ok(false);
+    }
+
+    for slot in &proof.storage_proof {
+        let key = hex_field("storage key", &slot.key)?;
+        if key.len() > 32 {
+            return Err(invalid(format!("storage key {} is longer than 32 bytes", slot.key)));
+        }
+        let mut padded = [0u8; 32];
+        padded[32 - key.len()..].copy_from_slice(&key);
+        let claimed = quantity_field("storage value", &slot.value)?;
+
+        let stored = match prove(&storage_root, &padded, &nodes_field("storage proof", &slot.proof)?)? {
+            Proven::Broken => return Ok(false),
+            // Zero slots are deleted from the trie rather than stored
+            Proven::Absent => Vec::new(),
+            Proven::Value(encoded) => Rlp::decode(&encoded)?.bytes()?.to_vec(),
+        };
+        if stored != claimed {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn invalid(detail: String) -> RpcHandlerError {
+    RpcHandlerError::InvalidProof { detail }
+}
+
+fn hex_field(field: &str, value: &str) -> Result<Vec<u8>> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    let padded = if digits.len() % 2 == 1 { format!("0x0{digits}") } else { format!("0x{digits}") };
+    unhex(&padded).ok_or_else(|| invalid(format!("{field} {value:?} is not hex")))
+}
+
+fn hash_field(field: &str, value: &str) -> Result<[u8; 32]> {
+    hex_field(field, value)?
+        .try_into()
+        .map_err(|_| invalid(format!("{field} {value:?} is not 32 bytes")))
+}
+
+/// A quantity as the trie stores it: big-endian without leading zeros.
+fn quantity_field(field: &str, value: &str) -> Result<Vec<u8>> {
+    let bytes = hex_field(field, value)?;
+    let significant = bytes.iter().position(|&byte| byte != 0).unwrap_or(bytes.len());
+    Ok(bytes[significant..].to_vec())
+}
+
+fn nodes_field(field: &str, nodes: &[String]) -> Result<Vec<Vec<u8>>> {
+    nodes.iter().map(|node| hex_field(field, node)).collect()
+}
+
+/// A decoded RLP item, borrowing from the encoding.
+enum Rlp<'a> {
+    Bytes(&'a [u8]),
+    List(Vec<Rlp<'a>>),
+}
+
+impl<'a> Rlp<'a> {
+    /// Decode exactly one item spanning all of `input`.
+    fn decode(input: &'a [u8]) -> Result<Self> {
+        match Self::decode_item(input)? {
+            (item, []) => Ok(item),
+            (_, rest) => Err(invalid(format!("{} trailing bytes after RLP item", rest.len()))),
+        }
+    }
+
+    /// The first item in `input`, and what follows it.
+    fn decode_item(input: &'a [u8]) -> Result<(Self, &'a [u8])> {
+        let truncated = || invalid("truncated RLP".to_string());
+        let &prefix = input.first().ok_or_else(truncated)?;
+        let (is_list, offset, len) = match prefix {
+            0x00..=0x7f => return Ok((Rlp::Bytes(&input[..1]), &input[1..])),
+            0x80..=0xb7 => (false, 1, (prefix - 0x80) as usize),
+            0xc0..=0xf7 => (true, 1, (prefix - 0xc0) as usize),
+            _ => {
+                let (is_list, len_of_len) = if prefix < 0xc0 { (false, (prefix - 0xb7) as usize) } else { (true, (prefix - 0xf7) as usize) };
+                let len_bytes = input.get(1..1 + len_of_len).ok_or_else(truncated)?;
+                if len_of_len > std::mem::size_of::<usize>() {
+                    return Err(invalid("RLP length overflows".to_string()));
+                }
+                let len = len_bytes.iter().fold(0usize, |len, &byte| (len << 8) | byte as usize);
+                (is_list, 1 + len_of_len, len)
+            }
+        };
+        // The length comes from the node, so it may point anywhere
+        let end = offset.checked_add(len).ok_or_else(truncated)?;
+        let payload = input.get(offset..end).ok_or_else(truncated)?;
+        let rest = &input[end..];
+        if !is_list {
+            return Ok((Rlp::Bytes(payload), rest));
+        }
+        let mut items = Vec::new();
+        let mut remaining = payload;
+        while !remaining.is_empty() {
+            let (item, after) = Self::decode_item(remaining)?;
+            items.push(item);
+            remaining = after;
+        }
+        Ok((Rlp::List(items), rest))
+    }
+
+    fn bytes(&self) -> Result<&'a [u8]> {
+        match self {
+            Rlp::Bytes(bytes) => Ok(bytes),
+            Rlp::List(_) => Err(invalid("expected an RLP string, found a list".to_string())),
+        }
+    }
+
+    fn into_list(self) -> Result<Vec<Rlp<'a>>> {
+        match self {
+            Rlp::List(items) => Ok(items),
+            Rlp::Bytes(_) => Err(invalid("expected an RLP list, found a string".to_string())),
+        }
+    }
+}
+
+/// What a proof says about a key.
+enum Proven {
+    Value(Vec<u8>),
+    /// The trie provably holds nothing at the key
+    Absent,
+    /// The nodes don't hash up to the root
+    Broken,
+}
+
+/// A reference from one trie node to the next.
+enum Child<'a> {
+    Hash([u8; 32]),
+    /// Nodes under 32 bytes are embedded in their parent instead of hashed
+    Inline(Rlp<'a>),
+    Empty,
+}
+
+impl<'a> Child<'a> {
+    fn of(item: Rlp<'a>) -> Result<Self> {
+        match item {
+            Rlp::Bytes([]) => Ok(Child::Empty),
+            Rlp::Bytes(hash) if hash.len() == 32 => Ok(Child::Hash(hash.try_into().expect("32 bytes"))),
+            Rlp::Bytes(other) => Err(invalid(format!("trie reference of {} bytes", other.len()))),
+            list => Ok(Child::Inline(list)),
+        }
+    }
+}
+
+/// Walk from `root` along Keccak-256 of `key`, as in Ethereum's secure tries, looking nodes up
+/// in `proof` by hash so their order doesn't matter.
+fn prove(root: &[u8; 32], key: &[u8], proof: &[Vec<u8>]) -> Result<Proven> {
+    let path: Vec<u8> = Keccak256::digest(key).iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect();
+    let nodes: HashMap<[u8; 32], &[u8]> = proof.iter().map(|node| (Keccak256::digest(node).into(), node.as_slice())).collect();
+    let mut next = Child::Hash(*root);
+    let mut depth = 0;
+
+    loop {
+        let node = match next {
+            Child::Hash(hash) => {
+                let Some(encoded) = nodes.get(&hash) else {
+                    // The empty trie proves absence without any nodes
+                    return Ok(if hash == EMPTY_TRIE_ROOT { Proven::Absent } else { Proven::Broken });
+                };
+                Rlp::decode(encoded)?
+            }
+            Child::Inline(node) => node,
+            Child::Empty => return Ok(Proven::Absent),
+        };
+
+        let mut items = node.into_list()?;
+        match items.len() {
+            17 => {
+                let Some(&nibble) = path.get(depth) else {
+                    let value = items.swap_remove(16).bytes()?;
+                    return Ok(if value.is_empty() { Proven::Absent } else { Proven::Value(value.to_vec()) });
+                };
+                depth += 1;
+                next = Child::of(items.swap_remove(nibble as usize))?;
+            }
+            2 => {
+                let value = items.pop().expect("two items");
+                let (is_leaf, partial) = compact_path(items[0].bytes()?)?;
+                let remaining = &path[depth..];
+                if is_leaf {
+                    return Ok(if remaining == partial { Proven::Value(value.bytes()?.to_vec()) } else { Proven::Absent });
+                }
+                if !remaining.starts_with(&partial) {
+                    return Ok(Proven::Absent);
+                }
+                depth += partial.len();
+                next = Child::of(value)?;
+            }
+            other => return Err(invalid(format!("trie node with {other} items"))),
+        }
+    }
+}
+
+/// Decode a leaf or extension node's hex-prefix encoded path into its flag and nibbles.
+fn compact_path(encoded: &[u8]) -> Result<(bool, Vec<u8>)> {
+    let &first = encoded.first().ok_or_else(|| invalid("empty node path".to_string()))?;
+    let flag = first >> 4;
+    if flag > 3 {
+        return Err(invalid(format!("node path flag {flag}")));
+    }
+    let mut nibbles = Vec::with_capacity(encoded.len() * 2);
+    // An odd-length path keeps its first nibble next to the flag
+    if flag & 1 == 1 {
+        nibbles.push(first & 0x0f);
+    }
+    nibbles.extend(encoded[1..].iter().flat_map(|byte| [byte >> 4, byte & 0x0f]));
+    Ok((flag & 2 == 2, nibbles))
+}
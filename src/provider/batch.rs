@@ -0,0 +1,299 @@
+//! JSON-RPC batches with partial retry.
+//!
+//! Entries are grouped by the endpoints planned for their method, so a routed method is never
+//! sent where its route doesn't allow, and each group is posted whole to the first endpoint of
+//! its plan that answers it. Entries that came back with a retryable error, or no answer at all,
+//! are then re-sent as a smaller batch to the next endpoint in the plan and their answers merged
+//! back into place. Entries that succeeded, failed deterministically, or call a method with side
+//! effects are never sent again.
+
+use std::{collections::HashMap, time::Duration};
+
+use serde_json::Value;
+use tracing::Instrument;
+
+use crate::{
+    error::kb::{self, ErrorMapping},
+    methods,
+    runtime,
+    tags::{call_span, check_tags, CallTags},
+    provider::{classify::post_json_rpc, plan::{build_plan, CallOptions}, retry_proxy::RetryProvider, RetryOptions},
+    validation::validate_response,
+    JsonRpcId, JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError, ValidationMode,
+};
+
+/// Rounds of partial retry when no `BatchOptions` are given.
+pub const DEFAULT_MAX_PARTIAL_RETRIES: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// Rounds of re-sending failed entries after the first send; 0 returns the first answers as-is
+    pub max_partial_retries: u32,
+    /// Where the batch came from, see `tags`
+    pub tags: Option<CallTags>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self { max_partial_retries: DEFAULT_MAX_PARTIAL_RETRIES, tags: None }
+    }
+}
+
+/// One batch entry's outcome, at the position of its request.
+#[derive(Debug, Clone, Default)]
+pub struct BatchEntry {
+    /// The latest answer, carrying the caller's id; `None` if no endpoint answered this entry,
+    /// and always for a notification, which gets no answer
+    pub response: Option<JsonRpcResponse<Value>>,
+    /// How many times the entry was sent
+    pub attempts: u32,
+    /// The endpoint `response` came from
+    pub served_by: Option<String>,
+}
+
+impl BatchEntry {
+    /// Unanswered, or answered with an error another endpoint may not give.
+    fn retryable(&self, mappings: &[ErrorMapping]) -> bool {
+        let served_by = self.served_by.as_deref().unwrap_or_default();
+        self.response
+            .as_ref()
+            .is_none_or(|response| response.error.as_ref().is_some_and(|error| kb::classify_with(mappings, error, served_by).is_retryable()))
+    }
+}
+
+/// The name a batch's span and failures go by.
+pub(crate) fn batch_name(batch: &[JsonRpcRequest]) -> String {
+    format!("batch[{}]", batch.len())
+}
+
+/// Methods with side effects are sent once, whatever comes back, and so are notifications,
+/// since nothing comes back for them.
+fn resendable(request: &JsonRpcRequest) -> bool {
+    request.id.is_some() && methods::descriptor(&request.method).is_none_or(|descriptor| descriptor.idempotent)
+}
+
+impl RetryProvider {
+    /// Send `batch` in one round trip, then re-send only the entries that failed retryably.
+    ///
+    /// Each entry's endpoints are ordered as for its own method, and entries whose orders differ,
+    /// such as a write under `write_endpoint` among reads, go out as separate batches. Calls go
+    /// out with their position as id, so answers are matched up whatever order and ids the
+    /// endpoint uses, and are returned in request order with the caller's ids. Notifications,
+    /// entries without an id, go out without one and are sent once. Fails only if no
+    /// endpoint answers any of the first sends; otherwise the entries of a batch that went
+    /// unanswered come back without a response.
+    pub async fn send_batch(&self, batch: &[JsonRpcRequest], options: Option<BatchOptions>) -> Result<Vec<BatchEntry>> {
+        self.send_batch_with(batch, options, &CallOptions::default()).await
+    }
+
+    /// Like `send_batch`, with per-call exclusions and a timeout override. `call.tags`, when set,
+    /// replace `BatchOptions::tags`. `retry_count` and `max_state_lag_blocks` don't apply to
+    /// batches, and `hold_on_total_failure` is up to `RpcHandler::try_proxy_batch_with`.
+    pub async fn send_batch_with(&self, batch: &[JsonRpcRequest], options: Option<BatchOptions>, call: &CallOptions) -> Result<Vec<BatchEntry>> {
+        let options = options.unwrap_or_default();
+        let tags = call.tags.as_ref().or(options.tags.as_ref());
+        if let Some(tags) = tags {
+            check_tags(tags)?;
+        }
+        if batch.is_empty() {
+            return Ok(Vec::new());
+        }
+        *self.last_activity.lock() = self.clock.now_instant();
+        let guard = self.options.read().await.clone();
+        let _in_flight = guard.in_flight.enter();
+        let span = call_span(&batch_name(batch), tags);
+        let result = self.send_batch_planned(batch, call, options.max_partial_retries, &guard).instrument(span).await;
+        guard.metrics.record_request(&result);
+        guard.metrics.record_tagged(tags, &guard.metric_tag_keys, &result);
+        result
+    }
+
+    async fn send_batch_planned(&self, batch: &[JsonRpcRequest], call: &CallOptions, max_partial_retries: u32, guard: &RetryOptions) -> Result<Vec<BatchEntry>> {
+        let overridden;
+        let guard = match call.rpc_call_timeout_ms {
+            Some(timeout_ms) => {
+                overridden = RetryOptions { rpc_call_timeout: Duration::from_millis(timeout_ms), ..guard.clone() };
+                &overridden
+            }
+            None => guard,
+        };
+
+        // One plan per method, and one batch per distinct endpoint order
+        let candidates = (guard.get_candidates)();
+        let mut plans: HashMap<&str, Vec<String>> = HashMap::new();
+        let mut routes: Vec<(Vec<String>, Vec<usize>)> = Vec::new();
+        for (i, request) in batch.iter().enumerate() {
+            let urls = plans.entry(&request.method).or_insert_with(|| {
+                build_plan(&self.base_url, &request.method, &candidates, guard, call).urls.into_iter().map(|planned| planned.url).collect()
+            });
+            match routes.iter_mut().find(|(route, _)| route == urls) {
+                Some((_, indices)) => indices.push(i),
+                None => routes.push((urls.clone(), vec![i])),
+            }
+        }
+
+        let mut entries = vec![BatchEntry::default(); batch.len()];
+        let (mut answered, mut failure) = (false, None);
+        for (urls, indices) in &routes {
+            match self.send_route(batch, indices, urls, max_partial_retries, guard, &mut entries).await {
+                Ok(()) => answered = true,
+                Err(e) => failure = Some(e),
+            }
+        }
+        match failure {
+            Some(e) if !answered => Err(e),
+            _ => Ok(entries),
+        }
+    }
+
+    /// Send the entries at `indices` along `urls`, then re-send those that failed retryably.
+    async fn send_route(
+        &self,
+        batch: &[JsonRpcRequest],
+        indices: &[usize],
+        urls: &[String],
+        max_partial_retries: u32,
+        guard: &RetryOptions,
+        entries: &mut [BatchEntry],
+    ) -> Result<()> {
+        if urls.is_empty() {
+            return Err(RpcHandlerError::NoAvailableRpcs { network_id: self.chain_id });
+        }
+
+        let mut served = self.send_round(batch, indices, urls, 0, guard, entries).await?;
+
+        for _ in 0..max_partial_retries {
+            let pending: Vec<usize> = indices
+                .iter()
+                .copied()
+                .filter(|&i| entries[i].retryable(&guard.error_mappings) && resendable(&batch[i]))
+                .collect();
+            if pending.is_empty() {
+                break;
+            }
+            if let Some(ref logger) = guard.on_log {
+                logger("debug", "Re-sending failed batch entries", Some(serde_json::json!({ "entries": pending.len() })));
+            }
+            // With a single endpoint there is nowhere else to go, so give it a moment
+            if urls.len() == 1 {
+                guard.clock.sleep(guard.retry_delay).await;
+            }
+            match self.send_round(batch, &pending, urls, (served + 1) % urls.len(), guard, entries).await {
+                Ok(at) => served = at,
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the entries at `indices` to the first endpoint, from `start` on and wrapping around,
+    /// that answers them, and merge the answers into `entries`. Returns that endpoint's index.
+    async fn send_round(
+        &self,
+        batch: &[JsonRpcRequest],
+        indices: &[usize],
+        urls: &[String],
+        start: usize,
+        options: &RetryOptions,
+        entries: &mut [BatchEntry],
+    ) -> Result<usize> {
+        let requests: Vec<JsonRpcRequest> = indices
+            .iter()
+            // A notification keeps its missing id, or the endpoint would answer it
+            .map(|&i| JsonRpcRequest { id: batch[i].id.as_ref().map(|_| JsonRpcId::from(i as u64)), ..batch[i].clone() })
+            .collect();
+        let sent: HashMap<usize, &JsonRpcRequest> = indices.iter().copied().zip(&requests).collect();
+
+        let mut last_error = None;
+        for offset in 0..urls.len() {
+            let at = (start + offset) % urls.len();
+            let url = &urls[at];
+            for &i in indices {
+                entries[i].attempts += 1;
+            }
+            let answers = self.post_batch(url, &requests, options).await;
+            options.metrics.record_attempt(url, answers.as_ref().err());
+            options.liveness.record_attempt(url, answers.as_ref().err(), options.clock.now_system());
+            let answers = match answers {
+                Ok(answers) => answers,
+                Err(e) => {
+                    if let Some(ref logger) = options.on_log {
+                        logger("debug", "Batch attempt failed", Some(serde_json::json!({ "url": url, "error": format!("{:?}", e) })));
+                    }
+                    if let Some(ref resolver) = options.resolver
+                        && e.is_transport()
+                    {
+                        resolver.unpin(url);
+                    }
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+
+            for raw in answers {
+                let Some((&i, &request)) = raw.get("id").and_then(Value::as_u64).and_then(|id| sent.get_key_value(&(id as usize))) else {
+                    continue;
+                };
+                // Checked against the renumbered entry, whose id the answer echoes
+                if let Some(response) = self.accept_answer(url, request, raw, options) {
+                    entries[i].response = Some(JsonRpcResponse { id: batch[i].id.clone(), ..response });
+                    entries[i].served_by = Some(url.clone());
+                }
+            }
+            return Ok(at);
+        }
+
+        // A lone endpoint's failure is reported as-is, so its URL and category reach the caller
+        Err(match last_error {
+            Some(e) if urls.len() == 1 => e,
+            _ => RpcHandlerError::AllEndpointsFailed,
+        })
+    }
+
+    /// POST `requests` to `url` and return the array it answers with.
+    async fn post_batch(&self, url: &str, requests: &[JsonRpcRequest], options: &RetryOptions) -> Result<Vec<Value>> {
+        let methods: Vec<&str> = requests.iter().map(|request| request.method.as_str()).collect();
+        options.spend.charge_all(url, &methods)?;
+        let send = async {
+            let _permit = options.host_limiter.acquire(url).await;
+            let response = post_json_rpc(&self.client, url, requests, options.follow_redirects, options.headers.get(url)).await?;
+            options.host_limiter.observe(url, response.headers());
+            if !response.status().is_success() {
+                return Err(RpcHandlerError::HttpStatus { url: url.to_string(), status: response.status().as_u16() });
+            }
+            // Endpoints answer a batch of only notifications with an empty body
+            if requests.iter().all(|request| request.id.is_none()) {
+                return Ok(Value::Array(Vec::new()));
+            }
+            response.json::<Value>().await.map_err(|e| RpcHandlerError::from_reqwest(e, url))
+        };
+        let body = runtime::timeout(options.rpc_call_timeout, send)
+            .await
+            .map_err(|_| RpcHandlerError::request_timeout(url, options.rpc_call_timeout))??;
+
+        match body {
+            Value::Array(answers) => Ok(answers),
+            // Endpoints without batch support answer the whole array with a single error
+            other => Err(match serde_json::from_value::<JsonRpcResponse<Value>>(other) {
+                Ok(JsonRpcResponse { error: Some(error), .. }) => RpcHandlerError::JsonRpcCode { code: error.code, message: error.message },
+                _ => RpcHandlerError::MalformedResponse { url: url.to_string(), violation: "batch answered with a single response".to_string() },
+            }),
+        }
+    }
+
+    /// Parse one entry's answer, `None` if it is malformed or behind the returned head, so the
+    /// entry counts as unanswered.
+    fn accept_answer(&self, url: &str, request: &JsonRpcRequest, raw: Value, options: &RetryOptions) -> Option<JsonRpcResponse<Value>> {
+        if options.validation_mode == ValidationMode::Strict
+            && validate_response(request, &raw).is_some()
+        {
+            *options.malformed_counts.lock().entry(url.to_string()).or_insert(0) += 1;
+            return None;
+        }
+        let response: JsonRpcResponse<Value> = serde_json::from_value(raw).ok()?;
+        match response.result {
+            Some(ref value) => options.heads.observe(url, request, value).ok().map(|()| response),
+            None => Some(response),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! One in-flight ceiling across every request the handler sends, for `ConstrainedMode`.
+//!
+//! A `WeightedSemaphore` hands out `capacity` slots to a fixed set of traffic classes. Each class
+//! may hold at most its own cap at once, and when a slot frees up it goes to the first class, in
+//! priority order, with a waiter and room under its cap. `HostLimiter` holds one shared by every
+//! clone, so user requests are served ahead of probes and probes never exceed their share.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use tokio::sync::oneshot;
+
+/// Which share of the budget a request counts against, highest priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrafficClass {
+    /// Proxied, batch, streamed, broadcast and consensus requests
+    Request,
+    /// Probes, keepalive pings, shadow replays and other background checks
+    Probe,
+}
+
+impl TrafficClass {
+    fn index(self) -> usize {
+        match self {
+            TrafficClass::Request => 0,
+            TrafficClass::Probe => 1,
+        }
+    }
+}
+
+/// Slots shared by prioritized classes with their own caps. Cloning shares the slots.
+#[derive(Debug, Clone)]
+pub struct WeightedSemaphore {
+    state: Arc<parking_lot::Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    capacity: usize,
+    in_use: usize,
+    classes: Vec<Class>,
+}
+
+#[derive(Debug)]
+struct Class {
+    cap: usize,
+    in_use: usize,
+    waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+impl State {
+    fn has_room(&self, class: usize) -> bool {
+        self.in_use < self.capacity && self.classes[class].in_use < self.classes[class].cap
+    }
+
+    fn take(&mut self, class: usize) {
+        self.in_use += 1;
+        self.classes[class].in_use += 1;
+    }
+
+    /// Hand freed slots to waiters, highest priority class first.
+    fn dispatch(&mut self) {
+        for class in 0..self.classes.len() {
+            while self.has_room(class) {
+                let Some(waiter) = self.classes[class].waiters.pop_front() else { break };
+                // A waiter that gave up has dropped its receiver
+                if waiter.send(()).is_ok() {
+                    self.take(class);
+                }
+            }
+        }
+    }
+}
+
+/// Holds one slot until dropped.
+#[derive(Debug)]
+pub struct WeightedPermit {
+    state: Arc<parking_lot::Mutex<State>>,
+    class: usize,
+}
+
+impl Drop for WeightedPermit {
+    fn drop(&mut self) {
+        release(&self.state, self.class);
+    }
+}
+
+fn release(state: &parking_lot::Mutex<State>, class: usize) {
+    let mut state = state.lock();
+    state.in_use -= 1;
+    state.classes[class].in_use -= 1;
+    state.dispatch();
+}
+
+/// Gives a slot granted after its `acquire` was dropped back.
+struct Waiter<'a> {
+    semaphore: &'a WeightedSemaphore,
+    class: usize,
+    granted: oneshot::Receiver<()>,
+    done: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        self.granted.close();
+        if self.granted.try_recv().is_ok() {
+            release(&self.semaphore.state, self.class);
+        }
+    }
+}
+
+impl WeightedSemaphore {
+    /// `capacity` slots, with `Request` traffic allowed all of them and `Probe` traffic `probe_cap`.
+    pub fn new(capacity: usize, probe_cap: usize) -> Self {
+        let capacity = capacity.max(1);
+        let caps = [capacity, probe_cap.clamp(1, capacity)];
+        let classes = caps.into_iter().map(|cap| Class { cap, in_use: 0, waiters: VecDeque::new() }).collect();
+        Self { state: Arc::new(parking_lot::Mutex::new(State { capacity, in_use: 0, classes })) }
+    }
+
+    /// A slot for `class` if one is free for it right now.
+    pub fn try_acquire(&self, class: TrafficClass) -> Option<WeightedPermit> {
+        let class = class.index();
+        let mut state = self.state.lock();
+        // Queued waiters of the class go first
+        if !state.has_room(class) || !state.classes[class].waiters.is_empty() {
+            return None;
+        }
+        state.take(class);
+        Some(WeightedPermit { state: Arc::clone(&self.state), class })
+    }
+
+    /// Wait for a slot for `class`. Wrap in a timeout to bound the wait.
+    pub async fn acquire(&self, class: TrafficClass) -> WeightedPermit {
+        let index = class.index();
+        let granted = {
+            let mut state = self.state.lock();
+            if state.has_room(index) && state.classes[index].waiters.is_empty() {
+                state.take(index);
+                return WeightedPermit { state: Arc::clone(&self.state), class: index };
+            }
+            let (send, granted) = oneshot::channel();
+            state.classes[index].waiters.push_back(send);
+            granted
+        };
+        let mut waiter = Waiter { semaphore: self, class: index, granted, done: false };
+        // The sender is only dropped after sending
+        let _ = (&mut waiter.granted).await;
+        waiter.done = true;
+        WeightedPermit { state: Arc::clone(&self.state), class: index }
+    }
+
+    /// Slots held right now, all classes together.
+    pub fn in_use(&self) -> usize {
+        self.state.lock().in_use
+    }
+
+    /// Slots `class` holds right now.
+    pub fn in_use_by(&self, class: TrafficClass) -> usize {
+        self.state.lock().classes[class.index()].in_use
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.state.lock().capacity
+    }
+}
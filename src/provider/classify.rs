@@ -0,0 +1,122 @@
+//! Recognising endpoints that answer JSON-RPC POSTs with something other than JSON-RPC.
+//!
+//! Many public URLs redirect to a docs page or serve an HTML landing page. Those answers are
+//! classified up front from the status and `Content-Type`, so they surface as
+//! `RpcHandlerError::NotAJsonRpcEndpoint` rather than a JSON parse error.
+
+use reqwest::{header::{self, HeaderMap}, StatusCode};
+use serde::Serialize;
+
+use crate::{provider::DEFAULT_USER_AGENT, Result, RpcHandlerError};
+
+/// What a non-JSON-RPC endpoint answered with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NonJsonRpcResponse {
+    pub content_type: Option<String>,
+    pub status: u16,
+}
+
+impl NonJsonRpcResponse {
+    pub fn into_error(self, url: &str) -> RpcHandlerError {
+        RpcHandlerError::NotAJsonRpcEndpoint { url: url.to_string(), content_type: self.content_type, status: self.status }
+    }
+}
+
+/// The response `post_json_rpc` hands back: reqwest's natively, and on `wasm32` a wrapper over
+/// the fetch response with the same methods that can be held across `.await`s in `Send` futures.
+#[cfg(not(target_arch = "wasm32"))]
+pub type HttpResponse = reqwest::Response;
+#[cfg(target_arch = "wasm32")]
+pub type HttpResponse = crate::runtime::SendResponse;
+
+/// Client builder for JSON-RPC traffic.
+///
+/// Redirects are never followed automatically: reqwest would turn a `301` POST into a GET and
+/// hand back whatever page it lands on. `post_json_rpc` decides what to do with them instead.
+/// A browser's fetch follows redirects itself, so on `wasm32` the page a redirect lands on is
+/// what gets classified.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn rpc_client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder().redirect(reqwest::redirect::Policy::none())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn rpc_client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+}
+
+/// `rpc_client_builder` sending `DEFAULT_USER_AGENT`.
+pub fn rpc_client() -> reqwest::Client {
+    rpc_client_builder().user_agent(DEFAULT_USER_AGENT).build().unwrap_or_default()
+}
+
+/// Classify a response from its status and `Content-Type`, `None` if it may be JSON-RPC.
+///
+/// Redirects always qualify. `text/*` bodies only qualify on success statuses, since an
+/// HTML error page from a gateway on a `5xx` is a transient failure, not a landing page.
+/// A missing `Content-Type` is given the benefit of the doubt.
+pub fn classify(status: StatusCode, content_type: Option<&str>) -> Option<NonJsonRpcResponse> {
+    let textual = content_type
+        .and_then(|ct| ct.split(';').next())
+        .is_some_and(|mime| mime.trim().to_ascii_lowercase().starts_with("text/"));
+
+    (status.is_redirection() || (status.is_success() && textual)).then(|| NonJsonRpcResponse {
+        content_type: content_type.map(str::to_string),
+        status: status.as_u16(),
+    })
+}
+
+pub fn classify_response(response: &HttpResponse) -> Option<NonJsonRpcResponse> {
+    let content_type = response.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    classify(response.status(), content_type)
+}
+
+/// POST `body` to `url` and reject non-JSON-RPC answers before anything tries to parse them.
+///
+/// With `follow_redirect`, a single redirect to the same host is re-sent as a POST; anything
+/// else (a second redirect, another host) is classified. Error statuses are returned as-is
+/// for the caller to handle. `headers`, the endpoint's own, go on top of the client's.
+pub async fn post_json_rpc<T: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    url: &str,
+    body: &T,
+    follow_redirect: bool,
+    headers: Option<&HeaderMap>,
+) -> Result<HttpResponse> {
+    let post = |target: &str| match headers {
+        Some(headers) => client.post(target).headers(headers.clone()).json(body),
+        None => client.post(target).json(body),
+    };
+    let mut response = send(post(url)).await.map_err(|e| RpcHandlerError::from_reqwest(e, url))?;
+
+    if follow_redirect
+        && response.status().is_redirection()
+        && let Some(target) = same_host_location(url, &response)
+    {
+        response = send(post(target.as_str())).await.map_err(|e| RpcHandlerError::from_reqwest(e, url))?;
+    }
+
+    match classify_response(&response) {
+        Some(classification) => Err(classification.into_error(url)),
+        None => Ok(response),
+    }
+}
+
+/// Send `request`, as an `HttpResponse`.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> reqwest::Result<HttpResponse> {
+    request.send().await
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> reqwest::Result<HttpResponse> {
+    send_wrapper::SendWrapper::new(request.send()).await.map(HttpResponse::from)
+}
+
+/// The redirect target, if it stays on `url`'s host and port.
+fn same_host_location(url: &str, response: &HttpResponse) -> Option<url::Url> {
+    let base = url::Url::parse(url).ok()?;
+    let location = response.headers().get(header::LOCATION)?.to_str().ok()?;
+    let target = base.join(location).ok()?;
+    (target.host_str() == base.host_str() && target.port_or_known_default() == base.port_or_known_default()).then_some(target)
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use web_time::Instant;
+
+use crate::{clock::{system_clock, Clock}, memory::evict_to_capacity};
+use parking_lot::RwLock;
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+/// How long a measured IP stays pinned before the hostname is resolved again.
+pub const DEFAULT_PIN_TTL: Duration = Duration::from_secs(300);
+/// How long a hostname that failed to resolve is treated as dead.
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(60);
+
+/// Resolves a hostname to candidate IPs. Swap this out to control DNS in tests or to use a
+/// resolver other than the system one.
+#[async_trait]
+pub trait HostResolver: Send + Sync {
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+/// The operating system resolver, via `tokio::net::lookup_host`. A browser doesn't expose its
+/// resolver, so on `wasm32` every lookup fails with `ErrorKind::Unsupported`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl HostResolver for SystemResolver {
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(tokio::net::lookup_host((host, 0)).await?.map(|addr| addr.ip()).collect())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("can't resolve {host}: the browser doesn't expose DNS")))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PinnedIp {
+    ip: IpAddr,
+    pinned_at: Instant,
+}
+
+/// A `reqwest` DNS resolver that can pin a hostname to the IP a probe actually measured.
+///
+/// Round-robin hostnames can front backends with very different latency; once an IP is pinned
+/// every new connection for that hostname goes to it until the pin expires, is removed after a
+/// failure, or is removed by hand. Hostnames that fail to resolve are remembered for a short
+/// while so dead hosts fail fast instead of paying for a lookup on every attempt.
+#[derive(Clone)]
+pub struct PinningResolver {
+    inner: Arc<dyn HostResolver>,
+    pins: Arc<RwLock<HashMap<String, PinnedIp>>>,
+    dead_hosts: Arc<RwLock<HashMap<String, Instant>>>,
+    pin_ttl: Duration,
+    negative_ttl: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl std::fmt::Debug for PinningResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PinningResolver")
+            .field("pins", &self.pinned())
+            .field("pin_ttl", &self.pin_ttl)
+            .field("negative_ttl", &self.negative_ttl)
+            .finish()
+    }
+}
+
+impl PinningResolver {
+    pub fn new(inner: Arc<dyn HostResolver>) -> Self {
+        Self {
+            inner,
+            pins: Arc::new(RwLock::new(HashMap::new())),
+            dead_hosts: Arc::new(RwLock::new(HashMap::new())),
+            pin_ttl: DEFAULT_PIN_TTL,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            clock: system_clock(),
+        }
+    }
+
+    /// Measure pin and negative-cache TTLs against `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_pin_ttl(mut self, ttl: Duration) -> Self {
+        self.pin_ttl = ttl;
+        self
+    }
+
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    /// Pin the hostname of `url` to `ip`.
+    pub fn pin(&self, url: &str, ip: IpAddr) {
+        if let Some(host) = host_of(url) {
+            self.pins.write().insert(host, PinnedIp { ip, pinned_at: self.clock.now_instant() });
+        }
+    }
+
+    /// Drop the pin for the hostname of `url`, forcing the next connection to re-resolve.
+    pub fn unpin(&self, url: &str) -> bool {
+        host_of(url).is_some_and(|host| self.pins.write().remove(&host).is_some())
+    }
+
+    /// The IP currently pinned for the hostname of `url`, if the pin hasn't expired.
+    pub fn pinned_ip(&self, url: &str) -> Option<IpAddr> {
+        let host = host_of(url)?;
+        self.fresh_pin(&host)
+    }
+
+    /// All unexpired pins, keyed by hostname.
+    pub fn pinned(&self) -> HashMap<String, IpAddr> {
+        self.pins
+            .read()
+            .iter()
+            .filter(|(_, pin)| self.clock.elapsed_since(pin.pinned_at) < self.pin_ttl)
+            .map(|(host, pin)| (host.clone(), pin.ip))
+            .collect()
+    }
+
+    /// Whether `host` recently failed to resolve and is being skipped.
+    pub fn is_dead_host(&self, host: &str) -> bool {
+        self.dead_hosts
+            .read()
+            .get(host)
+            .is_some_and(|since| self.clock.elapsed_since(*since) < self.negative_ttl)
+    }
+
+    pub fn pin_count(&self) -> usize {
+        self.pins.read().len()
+    }
+
+    pub fn dead_host_count(&self) -> usize {
+        self.dead_hosts.read().len()
+    }
+
+    /// Forget hostnames `keep` rejects, then cap pins and dead hosts at `max` entries each.
+    ///
+    /// `protected_host`'s pin and dead hosts still inside the negative TTL are never evicted.
+    pub(crate) fn compact(&self, max: usize, keep: impl Fn(&str) -> bool, protected_host: Option<&str>) {
+        let mut pins = self.pins.write();
+        pins.retain(|host, _| keep(host));
+        evict_to_capacity(&mut pins, max, |host, _| Some(host) == protected_host, |_, pin| pin.pinned_at);
+        drop(pins);
+
+        let mut dead_hosts = self.dead_hosts.write();
+        dead_hosts.retain(|host, _| keep(host));
+        evict_to_capacity(
+            &mut dead_hosts,
+            max,
+            |_, since| self.clock.elapsed_since(*since) < self.negative_ttl,
+            |_, since| *since,
+        );
+    }
+
+    fn fresh_pin(&self, host: &str) -> Option<IpAddr> {
+        let pin = *self.pins.read().get(host)?;
+        if self.clock.elapsed_since(pin.pinned_at) < self.pin_ttl {
+            Some(pin.ip)
+        } else {
+            self.pins.write().remove(host);
+            None
+        }
+    }
+
+    // Only reqwest calls this, and a browser's fetch can't be handed a resolver
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        if let Some(ip) = self.fresh_pin(host) {
+            return Ok(vec![ip]);
+        }
+        if self.is_dead_host(host) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{host} recently failed to resolve")));
+        }
+
+        match self.inner.lookup(host).await {
+            Ok(ips) if !ips.is_empty() => {
+                self.dead_hosts.write().remove(host);
+                Ok(ips)
+            }
+            Ok(_) => {
+                self.dead_hosts.write().insert(host.to_string(), self.clock.now_instant());
+                Err(io::Error::new(io::ErrorKind::NotFound, format!("{host} resolved to no addresses")))
+            }
+            Err(e) => {
+                self.dead_hosts.write().insert(host.to_string(), self.clock.now_instant());
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Resolve for PinningResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let ips = resolver.lookup(name.as_str()).await?;
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| std::net::SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+pub(crate) fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.host_str().map(str::to_string)
+}
@@ -0,0 +1,35 @@
+//! Request headers beyond what a JSON-RPC POST needs: the user agent, and extra headers an
+//! endpoint asks for.
+//!
+//! The user agent is set on the HTTP client, so probes, proxied calls and fan-outs all send the
+//! same one. An endpoint's own headers are added per request on top of it, and win where both
+//! set the same header, so one endpoint's API key header never reaches another.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::{Result, RpcHandlerError};
+
+#[cfg(feature = "chainlist")]
+pub use crate::chainlist::source::DEFAULT_USER_AGENT;
+/// Sent unless a user agent is configured.
+#[cfg(not(feature = "chainlist"))]
+pub const DEFAULT_USER_AGENT: &str = concat!("ez-web3-rpc/", env!("CARGO_PKG_VERSION"));
+
+/// Extra headers by endpoint URL, for the endpoints that have any.
+pub type HeaderOverrides = Arc<HashMap<String, HeaderMap>>;
+
+/// `headers` as a `HeaderMap`, failing on a name or value HTTP doesn't allow.
+pub fn header_map(headers: &BTreeMap<String, String>) -> Result<HeaderMap> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let invalid = || RpcHandlerError::InvalidRpcConfig { detail: format!("invalid header {name:?}") };
+            Ok((HeaderName::try_from(name.as_str()).map_err(|_| invalid())?, HeaderValue::try_from(value.as_str()).map_err(|_| invalid())?))
+        })
+        .collect()
+}
@@ -0,0 +1,216 @@
+//! Handler-wide caps on concurrent requests per hostname.
+//!
+//! The retry proxy, the consensus fan-out and probes share one `HostLimiter`, so together they
+//! never have more than the cap in flight to a single provider. Racing contexts use
+//! `try_acquire` and leave a saturated host out of the race; a lone candidate waits with
+//! `acquire`, bounded by the caller's deadline. A host paced by the quota it advertises (see
+//! `quota`) is left out of races the same way, but a lone candidate is sent without waiting.
+//!
+//! Under `ConstrainedMode` the limiter also holds the handler's global budget (see `budget`):
+//! every slot takes one from it first, counted against the caller's `TrafficClass`, and traffic
+//! that isn't host-capped reserves one with `reserve`.
+
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::Arc};
+
+use reqwest::header::HeaderMap;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use web_time::Instant;
+
+use crate::{
+    clock::{system_clock, Clock},
+    provider::{
+        budget::{TrafficClass, WeightedPermit, WeightedSemaphore},
+        dns::host_of,
+        quota::{advertised, HostQuota, QuotaState},
+    },
+    types::HostLimits,
+};
+
+/// Each capped host's cap and the semaphore enforcing it.
+type HostSlots = HashMap<String, (usize, Arc<Semaphore>)>;
+
+/// Caps in-flight requests per hostname. Cloning shares the counts.
+#[derive(Clone)]
+pub struct HostLimiter {
+    limits: Arc<HostLimits>,
+    hosts: Arc<parking_lot::Mutex<HostSlots>>,
+    quotas: Arc<parking_lot::Mutex<HashMap<String, QuotaState>>>,
+    clock: Arc<dyn Clock>,
+    budget: Option<WeightedSemaphore>,
+}
+
+impl Default for HostLimiter {
+    fn default() -> Self {
+        Self::new(HostLimits::default())
+    }
+}
+
+impl std::fmt::Debug for HostLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostLimiter")
+            .field("limits", &self.limits)
+            .field("in_flight", &self.in_flight())
+            .field("quotas", &self.quotas())
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+/// Holds a slot for one request until dropped.
+#[derive(Debug)]
+pub struct HostPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    _budget: Option<WeightedPermit>,
+}
+
+impl HostLimiter {
+    pub fn new(limits: HostLimits) -> Self {
+        Self { limits: Arc::new(limits), hosts: Arc::default(), quotas: Arc::default(), clock: system_clock(), budget: None }
+    }
+
+    /// Date advertised quota resets by `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Take every slot from `budget` as well, shared by all clones.
+    pub fn with_budget(mut self, budget: WeightedSemaphore) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// The global budget under `ConstrainedMode`.
+    pub fn budget(&self) -> Option<&WeightedSemaphore> {
+        self.budget.as_ref()
+    }
+
+    /// The cap for `host`, `None` when it is unlimited.
+    pub fn cap_for(&self, host: &str) -> Option<usize> {
+        self.limits.per_host.get(host).copied().or(self.limits.default_per_host).map(|cap| cap.max(1))
+    }
+
+    fn semaphore(&self, url: &str) -> Option<Arc<Semaphore>> {
+        let host = host_of(url)?;
+        let cap = self.cap_for(&host)?;
+        let mut hosts = self.hosts.lock();
+        Some(Arc::clone(&hosts.entry(host).or_insert_with(|| (cap, Arc::new(Semaphore::new(cap)))).1))
+    }
+
+    /// A slot for `url`'s host if one is free right now, `None` if the host is saturated or paced.
+    pub fn try_acquire(&self, url: &str) -> Option<HostPermit> {
+        self.try_acquire_as(url, TrafficClass::Request)
+    }
+
+    /// `try_acquire`, also `None` when the budget has no slot free for `class`.
+    pub fn try_acquire_as(&self, url: &str, class: TrafficClass) -> Option<HostPermit> {
+        if self.is_paced(url) {
+            return None;
+        }
+        let budget = match &self.budget {
+            Some(budget) => Some(budget.try_acquire(class)?),
+            None => None,
+        };
+        let permit = match self.semaphore(url) {
+            Some(semaphore) => Some(semaphore.try_acquire_owned().ok()?),
+            None => None,
+        };
+        self.record_send(url);
+        Some(HostPermit { _permit: permit, _budget: budget })
+    }
+
+    /// Wait for a slot for `url`'s host. Wrap in a timeout to bound the wait.
+    pub async fn acquire(&self, url: &str) -> HostPermit {
+        self.acquire_as(url, TrafficClass::Request).await
+    }
+
+    /// `acquire`, waiting for the budget first when there is one.
+    pub async fn acquire_as(&self, url: &str, class: TrafficClass) -> HostPermit {
+        let budget = self.reserve(class).await;
+        let permit = match self.semaphore(url) {
+            // The semaphore is never closed
+            Some(semaphore) => semaphore.acquire_owned().await.ok(),
+            None => None,
+        };
+        self.record_send(url);
+        HostPermit { _permit: permit, _budget: budget }
+    }
+
+    /// Wait for a slot from the budget alone, for traffic no host cap applies to. `None`
+    /// without a budget.
+    pub async fn reserve(&self, class: TrafficClass) -> Option<WeightedPermit> {
+        match &self.budget {
+            Some(budget) => Some(budget.acquire(class).await),
+            None => None,
+        }
+    }
+
+    /// Update `url`'s host's quota from the headers of a response it sent, when
+    /// `rate_limit_headers` is set and the headers advertise one.
+    pub fn observe(&self, url: &str, headers: &HeaderMap) {
+        let Some(config) = &self.limits.rate_limit_headers else { return };
+        let Some(host) = host_of(url) else { return };
+        let schemes = config.per_host.get(&host).unwrap_or(&config.schemes);
+        let Some(advertised) = advertised(headers, schemes, self.clock.now_system()) else { return };
+        let now = self.clock.now_instant();
+        let mut quotas = self.quotas.lock();
+        match quotas.get_mut(&host) {
+            Some(quota) if !quota.expired(now) => quota.update(advertised, now),
+            _ => {
+                quotas.insert(host, advertised.into_state(now));
+            }
+        }
+    }
+
+    /// Whether `url`'s host is below its quota floor and was sent to too recently.
+    pub fn is_paced(&self, url: &str) -> bool {
+        let Some(config) = &self.limits.rate_limit_headers else { return false };
+        let Some(host) = host_of(url) else { return false };
+        let now = self.clock.now_instant();
+        self.live_quota(&host, now).is_some_and(|quota| quota.paced(config.remaining_floor, now))
+    }
+
+    /// Those of `urls` whose host is paced right now.
+    pub fn paced(&self, urls: impl IntoIterator<Item = impl AsRef<str>>) -> HashSet<String> {
+        urls.into_iter().filter(|url| self.is_paced(url.as_ref())).map(|url| url.as_ref().to_string()).collect()
+    }
+
+    /// The advertised quota of every host whose reset is still ahead.
+    pub fn quotas(&self) -> BTreeMap<String, HostQuota> {
+        let Some(config) = &self.limits.rate_limit_headers else { return BTreeMap::new() };
+        let now = self.clock.now_instant();
+        let mut quotas = self.quotas.lock();
+        quotas.retain(|_, quota| !quota.expired(now));
+        quotas.iter().map(|(host, quota)| (host.clone(), quota.report(config.remaining_floor, now))).collect()
+    }
+
+    /// `host`'s quota, forgotten once its reset has passed.
+    fn live_quota(&self, host: &str, now: Instant) -> Option<QuotaState> {
+        let mut quotas = self.quotas.lock();
+        if quotas.get(host)?.expired(now) {
+            quotas.remove(host);
+            return None;
+        }
+        quotas.get(host).cloned()
+    }
+
+    fn record_send(&self, url: &str) {
+        if self.limits.rate_limit_headers.is_none() {
+            return;
+        }
+        let Some(host) = host_of(url) else { return };
+        let now = self.clock.now_instant();
+        if let Some(quota) = self.quotas.lock().get_mut(&host).filter(|quota| !quota.expired(now)) {
+            quota.record_send(now);
+        }
+    }
+
+    /// Requests currently holding a slot, per capped hostname that has had any.
+    pub fn in_flight(&self) -> BTreeMap<String, usize> {
+        self.hosts
+            .lock()
+            .iter()
+            .map(|(host, (cap, semaphore))| (host.clone(), cap - semaphore.available_permits()))
+            .collect()
+    }
+}
@@ -0,0 +1,40 @@
+//! Handler-wide count of proxied requests in flight.
+//!
+//! The retry proxy holds an `InFlightGuard` for the whole of each request, retries included.
+//! Background work such as the auto-refresh scheduler reads the count to stay out of the way
+//! of real traffic.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Counts requests in flight. Cloning shares the count.
+#[derive(Debug, Clone, Default)]
+pub struct InFlightGauge {
+    count: Arc<AtomicUsize>,
+}
+
+/// Counts one request until dropped.
+#[derive(Debug)]
+pub struct InFlightGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlightGauge {
+    /// Count a request until the returned guard is dropped.
+    pub fn enter(&self) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard { count: Arc::clone(&self.count) }
+    }
+
+    pub fn current(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
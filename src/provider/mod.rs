@@ -1,5 +1,26 @@
+pub mod batch;
+pub mod budget;
+pub mod classify;
 pub mod create_provider;
+pub mod dns;
+pub mod headers;
+pub mod host_limiter;
+pub mod in_flight;
+pub mod pinned;
+pub mod plan;
+pub mod quota;
 pub mod retry_proxy;
+pub mod stream;
 
+pub use batch::{BatchEntry, BatchOptions};
 pub use create_provider::create_provider;
-pub use retry_proxy::{RetryOptions, wrap_with_retry};
+pub use retry_proxy::{AttributedResponse, RetryOptions, wrap_with_retry};
+pub use stream::{ResultSink, StreamSummary};
+pub use dns::{HostResolver, PinningResolver, SystemResolver};
+pub use headers::{HeaderOverrides, DEFAULT_USER_AGENT};
+pub use budget::{TrafficClass, WeightedPermit, WeightedSemaphore};
+pub use host_limiter::{HostLimiter, HostPermit};
+pub use in_flight::{InFlightGauge, InFlightGuard};
+pub use plan::{CallOptions, RequestPlan};
+pub use quota::HostQuota;
+pub use classify::{post_json_rpc, rpc_client, rpc_client_builder, HttpResponse, NonJsonRpcResponse};
@@ -0,0 +1,124 @@
+//! Pinning `"latest"` state reads to a block the endpoints asked are known to have.
+//!
+//! An endpoint a few blocks behind answers `eth_call` at `"latest"` from stale state, and the
+//! answer looks just like a fresh one. Under `CallOptions::max_state_lag_blocks` each batch first
+//! resolves its endpoints' heads with `eth_blockNumber`, cached for `STATE_HEAD_TTL`, leaves out
+//! those more than the limit behind the freshest head known, and sends the read to the rest at
+//! the lowest of their heads.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::future::join_all;
+use serde_json::{json, Value};
+use web_time::Instant;
+
+use crate::{
+    methods::state_block_param,
+    metrics::RequestTiming,
+    namespaces::parse_quantity,
+    provider::retry_proxy::{AttributedResponse, RetryOptions, RetryProvider, Sidelined},
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// How long a resolved endpoint head is reused for pinning.
+pub const STATE_HEAD_TTL: Duration = Duration::from_secs(1);
+
+/// Head per endpoint, with when it was resolved.
+pub(crate) type StateHeads = Arc<parking_lot::Mutex<HashMap<String, (u64, Instant)>>>;
+
+/// Index of `request`'s block param if it reads state at `"latest"`, named or left out.
+///
+/// Reads at a number, hash or any other tag are left alone.
+pub fn latest_block_param(request: &JsonRpcRequest) -> Option<usize> {
+    let index = state_block_param(&request.method)?;
+    match request.params.as_array()?.get(index) {
+        None | Some(Value::Null) => Some(index),
+        Some(tag) if tag == "latest" => Some(index),
+        Some(_) => None,
+    }
+}
+
+/// `request` reading at `block` instead.
+pub(crate) fn pin(request: &JsonRpcRequest, index: usize, block: u64) -> JsonRpcRequest {
+    let mut pinned = request.clone();
+    if let Some(params) = pinned.params.as_array_mut() {
+        if params.len() <= index {
+            params.resize(index + 1, Value::Null);
+        }
+        params[index] = json!(format!("0x{block:x}"));
+    }
+    pinned
+}
+
+impl RetryProvider {
+    /// Race `request`, pinned to a block, among the endpoints of `urls` within `max_lag_blocks`
+    /// of the freshest head known.
+    ///
+    /// The heads of all `candidates` count towards the freshest, so a batch of lagging endpoints
+    /// is left out even when a fresher one would only be tried later. Fails with `StaleState`
+    /// if every endpoint of `urls` that answered is too far behind.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) async fn race_pinned(
+        &self,
+        urls: &[String],
+        candidates: &[String],
+        request: &JsonRpcRequest,
+        param: usize,
+        max_lag_blocks: u64,
+        options: &RetryOptions,
+        sidelined: &mut Sidelined,
+    ) -> Result<AttributedResponse> {
+        let heads: HashMap<&String, Result<u64>> =
+            candidates.iter().zip(join_all(candidates.iter().map(|url| self.state_head(url, options))).await).collect();
+        let watermark = heads.values().filter_map(|head| head.as_ref().ok().copied()).chain(options.heads.watermark()).max();
+
+        let mut fresh = Vec::new();
+        let mut stale = Vec::new();
+        for url in urls {
+            match heads.get(url) {
+                Some(Ok(head)) if watermark.is_none_or(|watermark| head.saturating_add(max_lag_blocks) >= watermark) => fresh.push((url.clone(), *head)),
+                Some(Ok(_)) => stale.push(url.clone()),
+                // Unreachable now; `race_batch` would only fail on it too
+                Some(Err(_)) | None => {}
+            }
+        }
+        if !stale.is_empty()
+            && let Some(ref logger) = options.on_log
+        {
+            logger("debug", "Left out endpoints too far behind for a state read", Some(json!({ "urls": stale, "watermark": watermark })));
+        }
+
+        let Some(block) = fresh.iter().map(|(_, head)| *head).min() else {
+            return Err(match watermark {
+                Some(watermark) if !stale.is_empty() => RpcHandlerError::StaleState { watermark, max_lag_blocks },
+                _ => RpcHandlerError::AllEndpointsFailed,
+            });
+        };
+        let urls: Vec<String> = fresh.into_iter().map(|(url, _)| url).collect();
+        let (response, url) = self.race_batch(&urls, &pin(request, param, block), options, sidelined).await?;
+        Ok(AttributedResponse { response, url, block_number: Some(block), timing: RequestTiming::default() })
+    }
+
+    /// `url`'s head, from the last `STATE_HEAD_TTL` or asked with `eth_blockNumber`.
+    async fn state_head(&self, url: &str, options: &RetryOptions) -> Result<u64> {
+        let now = options.clock.now_instant();
+        if let Some((head, resolved_at)) = self.state_heads.lock().get(url).copied()
+            && now.saturating_duration_since(resolved_at) < STATE_HEAD_TTL
+        {
+            return Ok(head);
+        }
+
+        let request = JsonRpcRequest::new("eth_blockNumber", json!([]));
+        let value = self.attempt_rpc(&self.client, url, &request, options).await?.into_result()?;
+        let head = parse_quantity(&value)
+            .ok_or_else(|| RpcHandlerError::MalformedResponse { url: url.to_string(), violation: format!("eth_blockNumber returned {value}") })?;
+        // Only recorded here: a head behind the monotonic watermark is dealt with above
+        let _ = options.heads.observe(url, &request, &value);
+        self.state_heads.lock().insert(url.to_string(), (head, now));
+        Ok(head)
+    }
+}
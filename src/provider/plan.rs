@@ -0,0 +1,345 @@
+//! The order in which a proxied request tries endpoints, and why.
+//!
+//! `RetryProvider` sends by walking the plan `build_plan` produces, so a plan computed without
+//! sending anything (`RpcHandler::plan_request`) is the order a real send follows. Only what is
+//! decided at send time is missing: hosts at their `HostLimits` cap sit out their batch, and
+//! endpoints that answer like a web page aren't retried.
+
+use std::{collections::{HashMap, HashSet}, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    head::HeadGuard,
+    namespaces::requires_block_sync,
+    performance::{group_by_tier, order_urls, LatencyMap},
+    provider::RetryOptions,
+    region::{preferred_region, region_penalty},
+    routing::{normalize_url, route_for},
+    tags::CallTags,
+    FailoverPolicy, RouteRule,
+};
+
+/// Number of URLs raced together in a single attempt batch, unless `HandlerSettings::retry_tuning`
+/// has moved it.
+pub const BATCH_SIZE: usize = 3;
+
+/// Per-call adjustments to how a proxied request is sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CallOptions {
+    /// Endpoints this call must not be sent to
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Replaces `ProxySettings::retry_count` for this call
+    pub retry_count: Option<u32>,
+    /// Replaces `ProxySettings::rpc_call_timeout_ms` for this call
+    pub rpc_call_timeout_ms: Option<u64>,
+    /// Instead of failing when no endpoint answers, hold the call and retry it as endpoints recover
+    #[serde(default)]
+    pub hold_on_total_failure: Option<HoldPolicy>,
+    /// For state reads at `"latest"`: skip endpoints more than this many blocks behind the
+    /// freshest head, and pin the read to a block number the rest have
+    #[serde(default)]
+    pub max_state_lag_blocks: Option<u64>,
+    /// Where the call came from, e.g. `component=indexer`, see `tags`
+    #[serde(default)]
+    pub tags: Option<CallTags>,
+}
+
+/// How long a call waits out a network-wide outage, and how often it retries meanwhile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HoldPolicy {
+    /// After this long the call fails with `HoldExpired`
+    pub max_wait: Duration,
+    /// Time between re-probing and retrying
+    pub retry_interval: Duration,
+}
+
+/// The handler's endpoint state a plan is built from.
+#[derive(Debug, Clone, Default)]
+pub struct Candidates {
+    /// Healthy endpoints at the common head block
+    pub latencies: LatencyMap,
+    /// Healthy endpoints behind the head block
+    pub lagging: LatencyMap,
+    /// Endpoints in a consensus cooldown, with the time left on it
+    pub cooling_down: HashMap<String, Duration>,
+    /// Head block each endpoint last reported through the proxy
+    pub heads: HashMap<String, u64>,
+    /// Blocks each endpoint trailed the most common head by at its last probe
+    pub head_lags: HashMap<String, u64>,
+    /// Set under `HandlerSettings::monotonic_head` once a head has been returned
+    pub head_guard: Option<HeadGuard>,
+    /// Endpoints inside a scheduled maintenance window
+    pub in_maintenance: HashSet<String>,
+    /// Endpoints that breached `HandlerSettings::latency_slo`, with the time left on the penalty
+    pub slo_penalized: HashMap<String, Duration>,
+    /// Endpoints whose host is spreading out its last advertised quota
+    pub quota_paced: HashSet<String>,
+    /// Metered endpoints that can't afford another request today
+    pub over_budget: HashSet<String>,
+    /// Endpoints flagged `ClockSkewSuspected` by the timestamp checks
+    pub clock_skewed: HashSet<String>,
+    /// Endpoints flagged `BlocksLagging` by the timestamp checks, behind the head like `lagging`
+    pub stale_blocks: HashSet<String>,
+    /// Endpoints agreement sampling flagged `SuspectedDishonest`, when reads are to avoid them
+    pub suspected_dishonest: HashSet<String>,
+}
+
+/// What put an endpoint at its position in the plan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Placement {
+    /// Designated by the request's route rule, ahead of the general pool
+    Routed,
+    /// The active provider, added first because it has no latency record
+    Active,
+    /// Ordered by measured latency, plus `head_lag_penalty_ms` per block of probed head lag and
+    /// any `region_affinity` penalty
+    Latency,
+    /// Ordered by tier, then latency, under `FailoverPolicy::TierStrict`
+    Tier { tier: u8 },
+    /// Behind the head block, but the method doesn't read chain state
+    LaggingAllowed,
+    /// Demoted to the end of its tier while its consensus cooldown runs
+    CoolingDown { remaining_ms: u64 },
+    /// Raced only after the rest of its tier while its latency-SLO penalty runs
+    SloPenalized { remaining_ms: u64 },
+    /// Raced last in its tier while it serves blocks dated in the future
+    ClockSkewSuspected,
+    /// Raced after the rest of its tier while its host is short of advertised quota
+    QuotaPaced,
+    /// At its place in the list of `Strategy::StaticOrder`, tried on its own
+    Static,
+}
+
+/// Why a known endpoint is left out of the plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Exclusion {
+    /// Listed in `CallOptions::exclude`
+    Caller,
+    /// Behind the head block, by probe or by its head block's timestamp, and the method reads
+    /// chain state
+    Lagging,
+    /// The route rule doesn't allow failover to the general pool
+    RouteWithoutFailover,
+    /// Last reported a head further behind the returned head than `max_head_lag` allows
+    BehindHead,
+    /// Inside a scheduled maintenance window
+    Maintenance,
+    /// A metered endpoint whose daily spend budget, or the handler's, has run out
+    BudgetExhausted,
+    /// Flagged by agreement sampling under `exclude_from_reads`
+    SuspectedDishonest,
+}
+
+/// One endpoint the request will try.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedUrl {
+    pub url: String,
+    /// Index of the batch it is raced in
+    pub batch: usize,
+    pub tier: Option<u8>,
+    pub latency_ms: Option<u64>,
+    pub placement: Placement,
+}
+
+/// One known endpoint the request won't try.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExcludedUrl {
+    pub url: String,
+    pub reason: Exclusion,
+}
+
+/// Every endpoint a proxied request would try, in order, with the retry schedule around them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestPlan {
+    pub method: String,
+    /// The route rule that claimed the method, if any
+    pub route: Option<RouteRule>,
+    pub failover_policy: FailoverPolicy,
+    /// The head floor endpoints were held to, if any
+    pub head_guard: Option<HeadGuard>,
+    /// Tried in this order; each batch is raced, and batches are tried in sequence
+    pub urls: Vec<PlannedUrl>,
+    pub excluded: Vec<ExcludedUrl>,
+    /// Passes over every batch before giving up
+    pub retry_count: u32,
+    /// Wait after each failed batch
+    pub retry_delay_ms: u64,
+    /// Deadline for each attempt
+    pub rpc_call_timeout_ms: u64,
+}
+
+impl RequestPlan {
+    /// The URLs of each batch, in the order they are tried.
+    pub fn batches(&self) -> Vec<Vec<String>> {
+        let mut batches: Vec<Vec<String>> = Vec::new();
+        for planned in &self.urls {
+            if batches.len() <= planned.batch {
+                batches.push(Vec::new());
+            }
+            batches[planned.batch].push(planned.url.clone());
+        }
+        batches
+    }
+
+    /// Whether every endpoint was left out for trailing the returned head.
+    pub fn all_behind_head(&self) -> bool {
+        self.urls.is_empty() && self.excluded.iter().any(|e| e.reason == Exclusion::BehindHead)
+    }
+
+    /// Whether every endpoint was left out for having spent its budget.
+    pub fn all_over_budget(&self) -> bool {
+        self.urls.is_empty() && self.excluded.iter().any(|e| e.reason == Exclusion::BudgetExhausted)
+    }
+
+    /// Whether a failure on the routed endpoints ends the request.
+    pub fn routed_only(&self) -> bool {
+        self.route.as_ref().is_some_and(|rule| !rule.allow_failover)
+    }
+}
+
+/// `latencies` with `RetryOptions::head_lag_penalty_ms` added per block of each endpoint's head lag,
+/// and the `region_affinity` penalty for endpoints outside the preferred region, the score
+/// endpoints are ordered by.
+fn lag_scored(latencies: &LatencyMap, candidates: &Candidates, options: &RetryOptions) -> LatencyMap {
+    let preferred = options.region_affinity.and_then(|policy| preferred_region(&policy, &candidates.latencies, &options.regions));
+    latencies
+        .iter()
+        .map(|(url, &latency)| {
+            let lag = candidates.head_lags.get(url).copied().unwrap_or(0);
+            let away = options.region_affinity.map_or(0, |policy| region_penalty(&policy, url, &options.regions, preferred));
+            (url.clone(), latency.saturating_add(lag.saturating_mul(options.head_lag_penalty_ms)).saturating_add(away))
+        })
+        .collect()
+}
+
+/// Build the plan for sending `method` through the provider at `base_url`.
+///
+/// Pure: every input is passed in, so the plan can be computed without sending and tested
+/// without a network.
+pub fn build_plan(
+    base_url: &str,
+    method: &str,
+    candidates: &Candidates,
+    options: &RetryOptions,
+    call: &CallOptions,
+) -> RequestPlan {
+    let route = route_for(&options.routes, method);
+    let caller_excluded: Vec<String> = call.exclude.iter().map(|url| normalize_url(url)).collect();
+    let mut excluded = Vec::new();
+
+    let mut routed = route.map(RouteRule::normalized_urls).unwrap_or_default();
+
+    // Out-of-sync endpoints are still fine for methods that don't read chain state
+    let mut measured = candidates.latencies.clone();
+    if requires_block_sync(method) {
+        excluded.extend(order_urls(&lag_scored(&candidates.lagging, candidates, options), &options.tiers, options.failover_policy)
+            .into_iter()
+            .filter(|url| url != base_url && !measured.contains_key(url) && !routed.contains(url))
+            .map(|url| ExcludedUrl { url, reason: Exclusion::Lagging }));
+    } else {
+        measured.extend(candidates.lagging.iter().map(|(url, &latency)| (url.clone(), latency)));
+    }
+
+    let mut pool = match &options.static_order {
+        Some(order) => order.clone(),
+        None => order_urls(&lag_scored(&measured, candidates, options), &options.tiers, options.failover_policy),
+    };
+    let active_added = !pool.iter().any(|url| url == base_url);
+    if active_added {
+        pool.insert(0, base_url.to_string());
+    }
+    pool.retain(|url| !routed.contains(url));
+    if route.is_some_and(|rule| !rule.allow_failover) {
+        excluded.extend(pool.drain(..).map(|url| ExcludedUrl { url, reason: Exclusion::RouteWithoutFailover }));
+    }
+
+    let behind_head = |url: &String| {
+        candidates.head_guard.zip(candidates.heads.get(url)).is_some_and(|(guard, &head)| head < guard.floor())
+    };
+    for list in [&mut routed, &mut pool] {
+        list.retain(|url| {
+            let reason = if caller_excluded.contains(url) {
+                Exclusion::Caller
+            } else if candidates.in_maintenance.contains(url) {
+                Exclusion::Maintenance
+            } else if candidates.over_budget.contains(url) {
+                Exclusion::BudgetExhausted
+            } else if candidates.suspected_dishonest.contains(url) {
+                Exclusion::SuspectedDishonest
+            } else if behind_head(url) {
+                Exclusion::BehindHead
+            } else if requires_block_sync(method) && url != base_url && candidates.stale_blocks.contains(url) {
+                Exclusion::Lagging
+            } else {
+                return true;
+            };
+            excluded.push(ExcludedUrl { url: url.clone(), reason });
+            false
+        });
+    }
+
+    // Stable, so latency order is kept within the cooling and the ready endpoints
+    pool.sort_by_key(|url| candidates.cooling_down.contains_key(url));
+
+    let mut urls = Vec::new();
+    // Designated endpoints go first; batches never span tiers, so under TierStrict a tier is
+    // exhausted before the next is touched. A static order is walked as given, one at a time
+    let (pool_groups, batch_size) = match options.static_order {
+        Some(_) => (vec![pool], 1),
+        None => (group_by_tier(&pool, &options.tiers, options.failover_policy), options.batch_size.max(1)),
+    };
+    let groups = std::iter::once((routed, true)).chain(pool_groups.into_iter().map(|group| (group, false)));
+    let mut batch = 0;
+    for (group, is_routed) in groups {
+        // Penalized endpoints get batches of their own, so a slow one doesn't hold up the race,
+        // and those with a suspect clock come after them. Hosts short of quota only get what
+        // the others can't answer
+        let (skewed, group): (Vec<String>, Vec<String>) = group.into_iter().partition(|url| !is_routed && candidates.clock_skewed.contains(url));
+        let (penalized, group): (Vec<String>, Vec<String>) =
+            group.into_iter().partition(|url| !is_routed && candidates.slo_penalized.contains_key(url));
+        let (paced, ready): (Vec<String>, Vec<String>) = group.into_iter().partition(|url| !is_routed && candidates.quota_paced.contains(url));
+        let chunks = ready.chunks(batch_size).chain(paced.chunks(batch_size)).chain(penalized.chunks(batch_size)).chain(skewed.chunks(batch_size));
+        for chunk in chunks {
+            for url in chunk {
+                let placement = match candidates.cooling_down.get(url) {
+                    _ if is_routed => Placement::Routed,
+                    _ if candidates.clock_skewed.contains(url) => Placement::ClockSkewSuspected,
+                    _ if let Some(remaining) = candidates.slo_penalized.get(url) => Placement::SloPenalized { remaining_ms: remaining.as_millis() as u64 },
+                    _ if candidates.quota_paced.contains(url) => Placement::QuotaPaced,
+                    Some(remaining) => Placement::CoolingDown { remaining_ms: remaining.as_millis() as u64 },
+                    None if active_added && url == base_url => Placement::Active,
+                    None if options.static_order.is_some() => Placement::Static,
+                    None if !candidates.latencies.contains_key(url) => Placement::LaggingAllowed,
+                    None => match options.failover_policy {
+                        FailoverPolicy::Latency => Placement::Latency,
+                        FailoverPolicy::TierStrict => Placement::Tier { tier: options.tiers.get(url).copied().unwrap_or(u8::MAX) },
+                    },
+                };
+                urls.push(PlannedUrl {
+                    url: url.clone(),
+                    batch,
+                    tier: options.tiers.get(url).copied(),
+                    latency_ms: candidates.latencies.get(url).or_else(|| candidates.lagging.get(url)).copied(),
+                    placement,
+                });
+            }
+            batch += 1;
+        }
+    }
+
+    RequestPlan {
+        method: method.to_string(),
+        route: route.cloned(),
+        failover_policy: options.failover_policy,
+        head_guard: candidates.head_guard,
+        urls,
+        excluded,
+        retry_count: call.retry_count.unwrap_or(options.retry_count),
+        retry_delay_ms: options.retry_delay.as_millis() as u64,
+        rpc_call_timeout_ms: call.rpc_call_timeout_ms.unwrap_or(options.rpc_call_timeout.as_millis() as u64),
+    }
+}
@@ -0,0 +1,137 @@
+//! Quota hosts advertise in their response headers, and pacing requests by it.
+//!
+//! Under `HostLimits::rate_limit_headers`, every response is read for how many requests its
+//! host has left and when that count resets. Once a host is below `remaining_floor`, what it
+//! has left is spread over the time to the reset: it takes one request per slice of that time,
+//! and sits out races in between, so its requests go to other hosts instead of waiting for it.
+//! A host with nothing left sits out until the reset. After the reset the model is forgotten
+//! until the next response advertises one again.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use web_time::Instant;
+
+use crate::RateLimitScheme;
+
+/// Reset values above this are Unix timestamps rather than seconds to go.
+const EPOCH_THRESHOLD: u64 = 1_000_000_000;
+
+/// A host's advertised quota, as the health report shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostQuota {
+    /// Requests left, as last advertised less those sent since
+    pub remaining: u64,
+    pub reset_in_ms: u64,
+    /// Below `remaining_floor`, so requests to it are being spread out
+    pub paced: bool,
+}
+
+/// One host's quota model.
+#[derive(Debug, Clone)]
+pub(crate) struct QuotaState {
+    remaining: u64,
+    reset_at: Instant,
+    last_sent: Option<Instant>,
+}
+
+impl QuotaState {
+    pub(crate) fn expired(&self, now: Instant) -> bool {
+        now >= self.reset_at
+    }
+
+    /// Whether a request now would be ahead of the host's share of its remaining quota.
+    pub(crate) fn paced(&self, floor: u64, now: Instant) -> bool {
+        if self.expired(now) || self.remaining >= floor {
+            return false;
+        }
+        if self.remaining == 0 {
+            return true;
+        }
+        self.last_sent.is_some_and(|sent| {
+            let interval = self.reset_at.saturating_duration_since(sent) / (self.remaining as u32).saturating_add(1);
+            now < sent + interval
+        })
+    }
+
+    pub(crate) fn record_send(&mut self, now: Instant) {
+        self.remaining = self.remaining.saturating_sub(1);
+        self.last_sent = Some(now);
+    }
+
+    /// Replace the advertised figures, keeping when the host was last sent to.
+    pub(crate) fn update(&mut self, advertised: Advertised, now: Instant) {
+        self.remaining = advertised.remaining;
+        self.reset_at = now + advertised.reset_in;
+    }
+
+    pub(crate) fn report(&self, floor: u64, now: Instant) -> HostQuota {
+        HostQuota {
+            remaining: self.remaining,
+            reset_in_ms: self.reset_at.saturating_duration_since(now).as_millis() as u64,
+            paced: self.remaining < floor,
+        }
+    }
+}
+
+/// What a response said about its host's quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Advertised {
+    pub remaining: u64,
+    pub reset_in: Duration,
+}
+
+impl Advertised {
+    pub(crate) fn into_state(self, now: Instant) -> QuotaState {
+        QuotaState { remaining: self.remaining, reset_at: now + self.reset_in, last_sent: None }
+    }
+}
+
+/// The quota `headers` advertise under the first of `schemes` they carry, `None` if none
+/// parses. `now` dates reset timestamps.
+pub(crate) fn advertised(headers: &HeaderMap, schemes: &[RateLimitScheme], now: SystemTime) -> Option<Advertised> {
+    schemes.iter().find_map(|scheme| match scheme {
+        RateLimitScheme::XRateLimit => pair(headers, "x-ratelimit-remaining", "x-ratelimit-reset", now),
+        RateLimitScheme::RateLimit => pair(headers, "ratelimit-remaining", "ratelimit-reset", now).or_else(|| combined(headers, now)),
+        RateLimitScheme::RetryAfter => {
+            let value = header(headers, RETRY_AFTER.as_str())?;
+            let reset_in = reset_in(value, now).or_else(|| {
+                let at = SystemTime::from(chrono::DateTime::parse_from_rfc2822(value).ok()?);
+                Some(at.duration_since(now).unwrap_or_default())
+            })?;
+            Some(Advertised { remaining: 0, reset_in })
+        }
+        RateLimitScheme::Custom { remaining, reset } => pair(headers, remaining, reset, now),
+    })
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok().map(str::trim)
+}
+
+fn pair(headers: &HeaderMap, remaining: &str, reset: &str, now: SystemTime) -> Option<Advertised> {
+    Some(Advertised { remaining: header(headers, remaining)?.parse().ok()?, reset_in: reset_in(header(headers, reset)?, now)? })
+}
+
+/// The draft's single `ratelimit: limit=100, remaining=50, reset=30` form.
+fn combined(headers: &HeaderMap, now: SystemTime) -> Option<Advertised> {
+    let value = header(headers, "ratelimit")?;
+    let field = |name: &str| {
+        value.split([',', ';']).find_map(|part| {
+            let (key, value) = part.split_once('=')?;
+            (key.trim().eq_ignore_ascii_case(name)).then(|| value.trim())
+        })
+    };
+    Some(Advertised { remaining: field("remaining")?.parse().ok()?, reset_in: reset_in(field("reset")?, now)? })
+}
+
+/// Seconds to go, possibly fractional, or a Unix timestamp past `EPOCH_THRESHOLD`.
+fn reset_in(value: &str, now: SystemTime) -> Option<Duration> {
+    let seconds: f64 = value.parse().ok().filter(|seconds: &f64| seconds.is_finite() && *seconds >= 0.0)?;
+    if seconds > EPOCH_THRESHOLD as f64 {
+        let at = UNIX_EPOCH + Duration::from_secs(seconds as u64);
+        return Some(at.duration_since(now).unwrap_or_default());
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
@@ -1,14 +1,130 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
+use reqwest::header::HeaderMap;
 use tokio::sync::RwLock;
-use crate::{NetworkId, JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError};
+use web_time::Instant;
+#[cfg(feature = "otel")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "otel")]
+use crate::otel;
+use crate::{
+    clock::Clock,
+    config::{LatencySloConfig, TimestampSanityConfig},
+    error::{kb::ErrorMapping, TimeoutPhase},
+    fanout::{positions, settle, Verdict},
+    head::HeadTracker,
+    journal::FailureJournal,
+    methods,
+    liveness::{AttemptFailure, LivenessLog},
+    metrics::{FailureClass, Metrics, RequestTiming},
+    namespaces::parse_quantity,
+    performance::{ProbeSchedule, TierMap},
+    region::Region,
+    rotation::AuthFailures,
+    runtime,
+    shadow::Shadows,
+    cache::ResponseCache,
+    canonical::canonicalize_params,
+    slo::SloGuard,
+    spend::SpendMeter,
+    timestamps::TimestampGuard,
+    tags::check_tags,
+    tuning::RetryTuner,
+    types::{RegionAffinityPolicy, SloMeasure},
+    provider::{
+        classify::{post_json_rpc, rpc_client, NonJsonRpcResponse},
+        dns::PinningResolver,
+        headers::HeaderOverrides,
+        pinned::{latest_block_param, StateHeads},
+        plan::{build_plan, CallOptions, Candidates, Exclusion, RequestPlan},
+        HostLimiter, HostPermit, InFlightGauge, TrafficClass,
+    },
+    validation::validate_response,
+    FailoverPolicy, NetworkId, JsonRpcRequest, JsonRpcResponse, Result, RouteRule, RpcHandlerError, ValidationMode,
+};
+
+/// Per-URL count of responses rejected by strict validation.
+pub type MalformedCounts = Arc<parking_lot::Mutex<HashMap<String, u64>>>;
+
+/// Snapshots the endpoint state requests are planned from.
+pub type CandidatesFn = Arc<dyn Fn() -> Candidates + Send + Sync>;
 
 #[derive(Clone)]
 pub struct RetryOptions {
     pub retry_count: u32,
     pub retry_delay: Duration,
-    pub get_ordered_urls: Arc<dyn Fn() -> Vec<String> + Send + Sync>,
+    /// Endpoints raced at once outside a static order
+    pub batch_size: usize,
+    /// Where each call ended up, under `HandlerSettings::retry_tuning`
+    pub outcomes: Option<RetryTuner>,
+    /// Latencies, lag and cooldowns each request's plan is built from
+    pub get_candidates: CandidatesFn,
     pub chain_id: NetworkId,
+    /// Other ids of the chain, which an `eth_chainId` answer may name without being journaled
+    /// as a mismatch
+    pub chain_aliases: Vec<NetworkId>,
     pub rpc_call_timeout: Duration,
+    pub failover_policy: FailoverPolicy,
+    /// Latency charged per block of `Candidates::head_lags` when ordering, none when `0`
+    pub head_lag_penalty_ms: u64,
+    /// Region of each endpoint whose region is known
+    pub regions: HashMap<String, Region>,
+    /// Latency charged to endpoints outside the preferred region when ordering, off when `None`
+    pub region_affinity: Option<RegionAffinityPolicy>,
+    pub tiers: TierMap,
+    /// Under `Strategy::StaticOrder`, the endpoints tried one at a time in this order instead
+    /// of by latency
+    pub static_order: Option<Vec<String>>,
+    /// Resolver holding pinned IPs; a failed attempt unpins its endpoint so the next one re-resolves
+    pub resolver: Option<PinningResolver>,
+    /// Method routing rules consulted before the ordered URL list is built
+    pub routes: Vec<RouteRule>,
+    pub validation_mode: ValidationMode,
+    /// Incremented for every response that fails strict validation
+    pub malformed_counts: MalformedCounts,
+    /// Re-send once to a same-host redirect target instead of classifying the redirect
+    pub follow_redirects: bool,
+    /// Each endpoint's own extra headers
+    pub headers: HeaderOverrides,
+    /// Receives endpoints found not to speak JSON-RPC so probes skip them
+    pub probe_schedule: ProbeSchedule,
+    /// Per-host caps shared with consensus and probes
+    pub host_limiter: HostLimiter,
+    /// Counts each request for as long as it is in flight, retries included
+    pub in_flight: InFlightGauge,
+    /// Learns heads from responses, and rejects those behind the returned head under `monotonic_head`
+    pub heads: HeadTracker,
+    /// Request and per-endpoint counters shared with the handler
+    pub metrics: Metrics,
+    /// Each attempt's up or down outcome, shared with the handler
+    pub liveness: LivenessLog,
+    /// Candidate endpoints replaying a sample of successful reads
+    pub shadows: Shadows,
+    /// Latency budget served requests are held to, off when `None`
+    pub latency_slo: Option<LatencySloConfig>,
+    /// Violations against `latency_slo`, shared with the handler
+    pub slo: SloGuard,
+    /// Charged for every attempt before it is sent, shared with the handler
+    pub spend: SpendMeter,
+    /// Bounds on block timestamps, unchecked when `None`
+    pub timestamp_sanity: Option<TimestampSanityConfig>,
+    /// Endpoints flagged by the timestamp checks, shared with the handler
+    pub timestamps: TimestampGuard,
+    /// Answers to `cacheable` methods, shared with the handler
+    pub response_cache: ResponseCache,
+    /// Entries `response_cache` keeps, caching off when `0`
+    pub response_cache_entries: usize,
+    /// `null` answers to hash lookups `response_cache` keeps, none when `0`
+    pub negative_cache_entries: usize,
+    /// Told about endpoints answering `401` or `403`, under `rotate_secrets_on_auth_error`
+    pub auth_failures: Option<AuthFailures>,
+    /// Told about requests that fail for good, under `HandlerComponents::failure_journal`
+    pub journal: Option<FailureJournal>,
+    /// `HandlerSettings::error_mappings`, consulted before the built-in ones
+    pub error_mappings: Vec<ErrorMapping>,
+    /// Caller tag keys counted apart in `metrics`, `HandlerSettings::metric_tag_keys`
+    pub metric_tag_keys: Vec<String>,
+    /// Time source for backoff sleeps and idle tracking
+    pub clock: Arc<dyn Clock>,
     pub on_log: Option<Arc<dyn Fn(&str, &str, Option<serde_json::Value>) + Send + Sync>>,
     pub refresh: Arc<dyn Fn() -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>> + Send + Sync>,
 }
@@ -18,9 +134,37 @@ impl std::fmt::Debug for RetryOptions {
         f.debug_struct("RetryOptions")
             .field("retry_count", &self.retry_count)
             .field("retry_delay", &self.retry_delay)
+            .field("batch_size", &self.batch_size)
             .field("chain_id", &self.chain_id)
+            .field("chain_aliases", &self.chain_aliases)
             .field("rpc_call_timeout", &self.rpc_call_timeout)
-            .field("has_get_ordered_urls", &true)
+            .field("failover_policy", &self.failover_policy)
+            .field("head_lag_penalty_ms", &self.head_lag_penalty_ms)
+            .field("regions", &self.regions)
+            .field("region_affinity", &self.region_affinity)
+            .field("tiers", &self.tiers)
+            .field("static_order", &self.static_order)
+            .field("resolver", &self.resolver)
+            .field("routes", &self.routes)
+            .field("validation_mode", &self.validation_mode)
+            .field("follow_redirects", &self.follow_redirects)
+            .field("headers", &self.headers.keys().collect::<Vec<_>>())
+            .field("host_limiter", &self.host_limiter)
+            .field("in_flight", &self.in_flight)
+            .field("heads", &self.heads)
+            .field("metrics", &self.metrics)
+            .field("liveness", &self.liveness)
+            .field("shadows", &self.shadows)
+            .field("latency_slo", &self.latency_slo)
+            .field("spend", &self.spend)
+            .field("timestamp_sanity", &self.timestamp_sanity)
+            .field("timestamps", &self.timestamps)
+            .field("response_cache_entries", &self.response_cache_entries)
+            .field("negative_cache_entries", &self.negative_cache_entries)
+            .field("auth_failures", &self.auth_failures)
+            .field("journal", &self.journal)
+            .field("error_mappings", &self.error_mappings)
+            .field("has_get_candidates", &true)
             .field("has_on_log", &self.on_log.is_some())
             .field("has_refresh", &true)
             .finish()
@@ -32,148 +176,547 @@ pub struct RetryProvider {
     pub base_url: String,
     pub chain_id: NetworkId,
     pub options: Arc<RwLock<RetryOptions>>,
-    client: reqwest::Client,
+    pub(super) client: reqwest::Client,
+    pub(super) clock: Arc<dyn Clock>,
+    pub(super) last_activity: Arc<parking_lot::Mutex<Instant>>,
+    /// Endpoint heads resolved for `CallOptions::max_state_lag_blocks`
+    pub(super) state_heads: StateHeads,
+}
+
+/// A response with where it came from.
+#[derive(Debug, Clone)]
+pub struct AttributedResponse {
+    pub response: JsonRpcResponse<serde_json::Value>,
+    /// The endpoint that served it
+    pub url: String,
+    /// The block a `"latest"` state read was pinned to under `CallOptions::max_state_lag_blocks`
+    pub block_number: Option<u64>,
+    /// Time waited before the first attempt went out, and time from there to the answer
+    pub timing: RequestTiming,
+}
+
+impl AttributedResponse {
+    fn unpinned((response, url): (JsonRpcResponse<serde_json::Value>, String)) -> Self {
+        Self { response, url, block_number: None, timing: RequestTiming::default() }
+    }
 }
 
 impl RetryProvider {
     pub fn new(base_url: String, chain_id: NetworkId, options: RetryOptions) -> Self {
+        Self::with_client(base_url, chain_id, options, rpc_client())
+    }
+
+    /// Like `new`, but sends every attempt through `client`.
+    pub fn with_client(base_url: String, chain_id: NetworkId, options: RetryOptions, client: reqwest::Client) -> Self {
+        let clock = Arc::clone(&options.clock);
         Self {
             base_url,
             chain_id,
             options: Arc::new(RwLock::new(options)),
-            client: reqwest::Client::new(),
+            client,
+            last_activity: Arc::new(parking_lot::Mutex::new(clock.now_instant())),
+            clock,
+            state_heads: StateHeads::default(),
         }
     }
+
+    /// Time since the last request went through this provider. Pings don't count.
+    pub fn idle_for(&self) -> Duration {
+        self.clock.elapsed_since(*self.last_activity.lock())
+    }
+
+    /// Send a single `eth_chainId` to the base URL only, with no failover or retries.
+    ///
+    /// Used to keep the pooled connection warm; it doesn't count as activity.
+    pub async fn ping(&self) -> Result<()> {
+        let request = JsonRpcRequest::new("eth_chainId", serde_json::json!([]));
+        let options = self.options.read().await.clone();
+        let _slot = options.host_limiter.reserve(TrafficClass::Probe).await;
+        self.attempt_rpc(&self.client, &self.base_url, &request, &options).await.map(|_| ())
+    }
     
     pub async fn send_request(&self, request: &JsonRpcRequest) -> Result<JsonRpcResponse<serde_json::Value>> {
+        self.send_request_attributed(request).await.map(|(response, _url)| response)
+    }
+
+    /// Like `send_request`, but also returns the URL that served the response.
+    pub async fn send_request_attributed(&self, request: &JsonRpcRequest) -> Result<(JsonRpcResponse<serde_json::Value>, String)> {
+        self.send_request_with(request, &CallOptions::default()).await
+    }
+
+    /// The endpoints `request` would be sent to, in order, without sending anything.
+    pub async fn plan(&self, request: &JsonRpcRequest, call: &CallOptions) -> RequestPlan {
         let options = self.options.read().await;
-        let ordered_urls = (options.get_ordered_urls)();
-        
-        // Ensure base URL is in the list
-        let mut urls = ordered_urls;
-        if !urls.contains(&self.base_url) {
-            urls.insert(0, self.base_url.clone());
+        build_plan(&self.base_url, &request.method, &(options.get_candidates)(), &options, call)
+    }
+
+    /// Like `send_request_attributed`, with per-call adjustments.
+    pub async fn send_request_with(&self, request: &JsonRpcRequest, call: &CallOptions) -> Result<(JsonRpcResponse<serde_json::Value>, String)> {
+        self.send_request_attributed_with(request, call).await.map(|attributed| (attributed.response, attributed.url))
+    }
+
+    /// Like `send_request_with`, also returning the block a guarded state read was pinned to.
+    pub async fn send_request_attributed_with(&self, request: &JsonRpcRequest, call: &CallOptions) -> Result<AttributedResponse> {
+        self.send_submitted(request, call, Instant::now()).await
+    }
+
+    /// Like `send_request_attributed_with`, for a request the caller submitted at `submitted`, so
+    /// its queue wait includes what happened before it got here.
+    pub(crate) async fn send_submitted(&self, request: &JsonRpcRequest, call: &CallOptions, submitted: Instant) -> Result<AttributedResponse> {
+        if let Some(tags) = &call.tags {
+            check_tags(tags)?;
         }
+        *self.last_activity.lock() = self.clock.now_instant();
+        // A snapshot, so options applied meanwhile leave a request already under way alone
+        let guard = self.options.read().await.clone();
+        let cache_key = self.cache_key(request, &guard);
+        if let Some(key) = &cache_key
+            && let Some((result, url)) = guard.response_cache.get(key)
+        {
+            let response = JsonRpcResponse { jsonrpc: "2.0".to_string(), result: Some(result), error: None, id: request.id.clone() };
+            return Ok(AttributedResponse::unpinned((response, url)));
+        }
+        // Taken before sending, so a head seen while the lookup is under way already makes it stale
+        let watermark = guard.heads.watermark();
+        let negative_key = Self::negative_cache_key(request, &guard);
+        if let Some(key) = &negative_key
+            && let Some(url) = guard.response_cache.get_negative(key, watermark)
+        {
+            let response = JsonRpcResponse { jsonrpc: "2.0".to_string(), result: Some(serde_json::Value::Null), error: None, id: request.id.clone() };
+            return Ok(AttributedResponse::unpinned((response, url)));
+        }
+        let _in_flight = guard.in_flight.enter();
+        let mut sidelined = Sidelined::default();
+        let result = self.send_planned(request, call, &guard, &mut sidelined).await;
+        let timing = RequestTiming::between(submitted, sidelined.first_send, Instant::now());
+        if let Ok(attributed) = &result
+            && let (Some(result), None) = (&attributed.response.result, &attributed.response.error)
+        {
+            match (cache_key, negative_key) {
+                (Some(key), _) if !result.is_null() => {
+                    guard.response_cache.insert(key, result.clone(), attributed.url.clone(), guard.response_cache_entries);
+                }
+                (_, Some(key)) if result.is_null() => {
+                    guard.response_cache.insert_negative(key, watermark, attributed.url.clone(), guard.negative_cache_entries);
+                }
+                _ => {}
+            }
+        }
+        guard.metrics.record_request(&result);
+        guard.metrics.record_tagged(call.tags.as_ref(), &guard.metric_tag_keys, &result);
+        guard.metrics.record_timing(timing);
+        if let (Some(journal), Ok(attributed)) = (&guard.journal, &result)
+            && request.method == "eth_chainId"
+            && let Some(reported) = attributed.response.result.as_ref()
+            && let Some(answered) = reported.as_str()
+            && parse_quantity(reported).is_some_and(|chain_id| chain_id != self.chain_id && !guard.chain_aliases.contains(&chain_id))
+        {
+            journal.chain_id_mismatch(request, &attributed.url, answered, self.chain_id);
+        }
+        if let Ok(attributed) = &result {
+            let service = Duration::from_millis(timing.service_ms);
+            if let Some(slo) = &guard.latency_slo {
+                let latency = match slo.measure {
+                    SloMeasure::Service => service,
+                    SloMeasure::Total => Duration::from_millis(timing.total_ms()),
+                };
+                guard.slo.record(slo, &attributed.url, &request.method, latency, guard.clock.now_instant());
+            }
+            guard.shadows.observe(request, &attributed.response, service, guard.rpc_call_timeout, guard.follow_redirects, guard.host_limiter.budget());
+        }
+        result.map(|attributed| AttributedResponse { timing, ..attributed })
+    }
+
+    /// Key `request` is cached under, `None` when caching is off or its result may change.
+    fn cache_key(&self, request: &JsonRpcRequest, options: &RetryOptions) -> Option<String> {
+        if options.response_cache_entries == 0 || !methods::descriptor(&request.method).is_some_and(|method| method.cacheable) {
+            return None;
+        }
+        let canonical = canonicalize_params(&request.method, &request.params, true);
+        for warning in &canonical.warnings {
+            tracing::warn!(method = %request.method, "{warning}");
+        }
+        Some(canonical.key(&request.method))
+    }
+
+    /// Key a `null` answer to `request` is kept under: only for lookups by a concrete
+    /// transaction or block hash, never for anything relative to a block tag.
+    fn negative_cache_key(request: &JsonRpcRequest, options: &RetryOptions) -> Option<String> {
+        if options.negative_cache_entries == 0 {
+            return None;
+        }
+        let method = methods::descriptor(&request.method).filter(|method| method.null_result && method.idempotent)?;
+        let slots = method.params_schema.as_array()?;
+        if slots.first()?["schema"]["title"] != "32 byte hash" || slots.iter().any(|slot| slot["schema"]["title"] == "block number or tag") {
+            return None;
+        }
+        let canonical = canonicalize_params(&request.method, &request.params, false);
+        let hash = canonical.params.get(0)?.as_str()?;
+        hash.strip_prefix("0x").is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|byte| byte.is_ascii_hexdigit())).then(|| canonical.key(&request.method))
+    }
+
+    async fn send_planned(&self, request: &JsonRpcRequest, call: &CallOptions, guard: &RetryOptions, sidelined: &mut Sidelined) -> Result<AttributedResponse> {
+        let plan = build_plan(&self.base_url, &request.method, &(guard.get_candidates)(), guard, call);
         
-        if urls.is_empty() {
-            if let Some(ref logger) = options.on_log {
+        if plan.urls.is_empty() {
+            if let Some(ref logger) = guard.on_log {
                 logger("error", "No RPCs available", None);
             }
+            if plan.all_behind_head()
+                && let Some(head_guard) = plan.head_guard
+            {
+                return Err(head_guard.error());
+            }
+            if plan.all_over_budget()
+                && let Some(over) = plan.excluded.iter().find(|e| e.reason == Exclusion::BudgetExhausted)
+            {
+                return Err(guard.spend.refusal(&over.url));
+            }
             return Err(RpcHandlerError::NoAvailableRpcs { network_id: self.chain_id });
         }
         
-        let mut loops = options.retry_count;
-        while loops > 0 {
-            // Process URLs in batches of 3
-            for chunk in urls.chunks(3) {
-                let batch_result = self.race_batch(chunk, request, &options).await;
-                
-                match batch_result {
-                    Ok(response) => {
-                        // Non-blocking refresh after successful call
-                        let refresh_fn = Arc::clone(&options.refresh);
-                        tokio::spawn(async move {
-                            if let Err(_e) = refresh_fn().await {
-                                // Log refresh failure if needed
-                            }
-                        });
-                        
-                        return Ok(response);
+        let overridden;
+        let options = if call.retry_count.is_some() || call.rpc_call_timeout_ms.is_some() {
+            overridden = RetryOptions {
+                retry_count: plan.retry_count,
+                rpc_call_timeout: Duration::from_millis(plan.rpc_call_timeout_ms),
+                ..guard.clone()
+            };
+            &overridden
+        } else {
+            guard
+        };
+        let batches = plan.batches();
+        let state_guard = call.max_state_lag_blocks.zip(latest_block_param(request));
+        let candidates: Vec<String> = plan.urls.iter().map(|planned| planned.url.clone()).collect();
+        
+        // Whether an attempt timed out in a batch that went on to fail
+        let mut timed_out_before = false;
+        sidelined.timed_out = false;
+        let record = |answered_at, timed_out_before| {
+            if let Some(ref outcomes) = options.outcomes {
+                outcomes.record(answered_at, batches.len(), options.retry_count as usize, timed_out_before);
+            }
+        };
+        for position in positions(batches.len(), options.retry_count as usize) {
+            let batch = &batches[position.batch];
+            let live: Vec<String> = batch.iter().filter(|url| !sidelined.contains(url)).cloned().collect();
+            let batch_result = if live.is_empty() {
+                Err(RpcHandlerError::AllEndpointsFailed)
+            } else if let Some((max_lag_blocks, param)) = state_guard {
+                self.race_pinned(&live, &candidates, request, param, max_lag_blocks, options, sidelined).await
+            } else {
+                self.race_batch(&live, request, options, sidelined).await.map(AttributedResponse::unpinned)
+            };
+            
+            match batch_result {
+                Ok(response) => {
+                    if !position.is_first() {
+                        options.metrics.record_failover();
                     }
-                    Err(batch_err) => {
-                        let is_last_batch = chunk.len() < 3 || chunk.as_ptr() == urls.chunks(3).last().unwrap().as_ptr();
-                        if loops == 1 && is_last_batch {
-                            if let Some(ref logger) = options.on_log {
-                                logger("error", "Failed after all retries", Some(serde_json::json!({
-                                    "error": format!("{:?}", batch_err)
-                                })));
-                            }
-                            return Err(batch_err);
+                    record(Some(position), timed_out_before);
+                    // Non-blocking refresh after successful call
+                    let refresh_fn = Arc::clone(&options.refresh);
+                    runtime::spawn(async move {
+                        if let Err(_e) = refresh_fn().await {
+                            // Log refresh failure if needed
                         }
-                        
+                    });
+                    
+                    return Ok(response);
+                }
+                Err(batch_err) => {
+                    timed_out_before |= std::mem::take(&mut sidelined.timed_out);
+                    if position.last {
+                        record(None, timed_out_before);
                         if let Some(ref logger) = options.on_log {
-                            logger("debug", "Batch failed, backing off", Some(serde_json::json!({
-                                "delay_ms": options.retry_delay.as_millis()
-                            })));
+                            let mut metadata = serde_json::json!({ "error": format!("{:?}", batch_err) });
+                            if let Some(tags) = &call.tags {
+                                metadata["tags"] = serde_json::json!(tags);
+                            }
+                            logger("error", "Failed after all retries", Some(metadata));
+                        }
+                        let attempts = std::mem::take(&mut sidelined.failures);
+                        let err = terminal_error(sidelined, &plan, request, batch_err);
+                        if let Some(ref journal) = options.journal {
+                            journal.request_failed(request, &err, attempts, call.tags.clone().unwrap_or_default());
                         }
-                        
-                        tokio::time::sleep(options.retry_delay).await;
+                        return Err(err);
+                    }
+                    
+                    #[cfg(feature = "otel")]
+                    otel::add_event("failover", [
+                        KeyValue::new("error.type", otel::error_type(&batch_err)),
+                        KeyValue::new("batch", position.batch as i64),
+                        KeyValue::new("round", position.round as i64 + 1),
+                    ]);
+                    if let Some(ref logger) = options.on_log {
+                        logger("debug", "Batch failed, backing off", Some(serde_json::json!({
+                            "delay_ms": options.retry_delay.as_millis()
+                        })));
+                    }
+                    
+                    if !live.is_empty() {
+                        options.clock.sleep(options.retry_delay).await;
                     }
                 }
             }
-            loops -= 1;
         }
         
         Err(RpcHandlerError::AllEndpointsFailed)
     }
     
-    async fn race_batch(
+    pub(super) async fn race_batch(
         &self,
         urls: &[String],
         request: &JsonRpcRequest,
         options: &RetryOptions,
-    ) -> Result<JsonRpcResponse<serde_json::Value>> {
-        let tasks: Vec<_> = urls.iter().map(|url| {
+        sidelined: &mut Sidelined,
+    ) -> Result<(JsonRpcResponse<serde_json::Value>, String)> {
+        let (urls, permits) = self.acquire_hosts(urls, options).await?;
+        sidelined.first_send.get_or_insert_with(Instant::now);
+        let tasks: Vec<_> = urls.iter().zip(permits).map(|(url, permit)| {
             let url = url.clone();
             let request = request.clone();
             let client = self.client.clone();
-            let timeout = options.rpc_call_timeout;
             
             async move {
-                self.attempt_rpc(&client, &url, &request, timeout).await
+                let _permit = permit;
+                self.attempt_rpc(&client, &url, &request, options).await
             }
         }).collect();
         
         // Race the requests and return the first successful one
         let results = futures::future::join_all(tasks).await;
-        
-        for (i, result) in results.into_iter().enumerate() {
-            match result {
+        let judge = |i: usize, result: Result<JsonRpcResponse<serde_json::Value>>| {
+            // A head behind what was already returned fails the attempt, so the race moves on, as
+            // does a block from the future under strict timestamp checks
+            let result = result.and_then(|response| match response.result {
+                Some(ref value) => options
+                    .heads
+                    .observe(&urls[i], request, value)
+                    .and_then(|()| {
+                        let now = options.clock.now_system();
+                        options.timestamps.observe(options.timestamp_sanity.as_ref(), &urls[i], request, value, now)
+                    })
+                    .map(|()| response),
+                None => Ok(response),
+            });
+            options.metrics.record_attempt(&urls[i], result.as_ref().err());
+            options.liveness.record_attempt(&urls[i], result.as_ref().err(), options.clock.now_system());
+            let e = match result {
                 Ok(response) => {
                     if let Some(ref logger) = options.on_log {
                         logger("debug", "Successfully called provider method", Some(serde_json::json!({
                             "url": urls[i]
                         })));
                     }
-                    return Ok(response);
+                    return Verdict::Won(response);
                 }
-                Err(e) => {
-                    if let Some(ref logger) = options.on_log {
-                        logger("debug", "Provider attempt failed", Some(serde_json::json!({
-                            "url": urls[i],
-                            "error": format!("{:?}", e)
-                        })));
+                Err(e) => e,
+            };
+            if options.journal.is_some() {
+                let failure = AttemptFailure { at: options.clock.now_system(), url: urls[i].clone(), class: FailureClass::of(&e), message: e.to_string() };
+                sidelined.failures.push(failure);
+            }
+            if let Some(ref logger) = options.on_log {
+                logger("debug", "Provider attempt failed", Some(serde_json::json!({
+                    "url": urls[i],
+                    "error": format!("{:?}", e)
+                })));
+            }
+            // Only failures to reach the pinned IP say anything about the pin; a heavy call
+            // outlasting its budget got through fine
+            sidelined.timed_out |= e.timeout_phase().is_some();
+            let slow_heavy_call = e.timeout_phase() == Some(TimeoutPhase::TotalBudget) && methods::is_heavy(&request.method);
+            if let Some(ref resolver) = options.resolver
+                && e.is_transport()
+                && !slow_heavy_call
+            {
+                resolver.unpin(&urls[i]);
+            }
+            match e {
+                RpcHandlerError::ConnectTimeout { .. } => {
+                    options.probe_schedule.record_unreachable(&urls[i], options.clock.now_instant());
+                    sidelined.unreachable.insert(urls[i].clone());
+                    sidelined.first_unreachable.get_or_insert(e);
+                    Verdict::Lost(None)
+                }
+                RpcHandlerError::NotAJsonRpcEndpoint { ref content_type, status, .. } => {
+                    let classification = NonJsonRpcResponse { content_type: content_type.clone(), status };
+                    options.probe_schedule.record_non_json_rpc(&urls[i], classification, options.clock.now_instant());
+                    sidelined.not_json_rpc.insert(urls[i].clone());
+                    sidelined.first_not_json_rpc.get_or_insert(e);
+                    Verdict::Lost(None)
+                }
+                RpcHandlerError::NoSufficientlySyncedProvider { .. } => {
+                    sidelined.behind_head.insert(urls[i].clone());
+                    sidelined.head_error = Some(e);
+                    Verdict::Lost(None)
+                }
+                RpcHandlerError::BudgetExhausted { .. } => {
+                    sidelined.over_budget.insert(urls[i].clone());
+                    sidelined.first_over_budget.get_or_insert(e);
+                    Verdict::Lost(None)
+                }
+                RpcHandlerError::HttpStatus { status: 401 | 403, .. } => {
+                    if let Some(ref auth_failures) = options.auth_failures {
+                        auth_failures.report(&urls[i]);
                     }
+                    Verdict::Lost(Some(e))
                 }
+                _ => Verdict::Lost(Some(e)),
             }
+        };
+        // The rest of the race is never looked at, but still counts for its endpoints
+        let rest = |j: usize, result: Result<JsonRpcResponse<serde_json::Value>>| {
+            options.metrics.record_attempt(&urls[j], result.as_ref().err());
+            options.liveness.record_attempt(&urls[j], result.as_ref().err(), options.clock.now_system());
+        };
+        let (winner, response) = settle(results, judge, rest)?;
+        Ok((response, urls[winner].clone()))
+    }
+    
+    /// Host slots for a batch: saturated hosts sit out the race, but when that leaves nothing
+    /// the first URL waits for its host, bounded by the call timeout.
+    async fn acquire_hosts(&self, urls: &[String], options: &RetryOptions) -> Result<(Vec<String>, Vec<HostPermit>)> {
+        let (racing, permits): (Vec<String>, Vec<HostPermit>) = urls
+            .iter()
+            .filter_map(|url| options.host_limiter.try_acquire(url).map(|permit| (url.clone(), permit)))
+            .unzip();
+        if !racing.is_empty() {
+            return Ok((racing, permits));
         }
         
-        Err(RpcHandlerError::AllEndpointsFailed)
+        let url = &urls[0];
+        let permit = runtime::timeout(options.rpc_call_timeout, options.host_limiter.acquire(url))
+            .await
+            .map_err(|_| RpcHandlerError::request_timeout(url, options.rpc_call_timeout))?;
+        Ok((vec![url.clone()], vec![permit]))
     }
     
-    async fn attempt_rpc(
+    pub(super) async fn attempt_rpc(
         &self,
         client: &reqwest::Client,
         url: &str,
         request: &JsonRpcRequest,
-        timeout: Duration,
+        options: &RetryOptions,
     ) -> Result<JsonRpcResponse<serde_json::Value>> {
-        let response = tokio::time::timeout(
-            timeout,
-            client.post(url).json(request).send()
-        ).await?;
+        #[cfg(feature = "otel")]
+        if let Some(span) = otel::AttemptSpan::start(url, &request.method) {
+            let headers = span.inject(options.headers.get(url));
+            let result = self.send_attempt(client, url, request, options, Some(&headers)).await;
+            let jsonrpc_error = result.as_ref().ok().and_then(|response| response.error.as_ref()).map(|error| {
+                RpcHandlerError::JsonRpcCode { code: error.code, message: error.message.clone() }
+            });
+            span.finish(result.as_ref().err().or(jsonrpc_error.as_ref()));
+            return result;
+        }
+        self.send_attempt(client, url, request, options, options.headers.get(url)).await
+    }
+
+    async fn send_attempt(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        request: &JsonRpcRequest,
+        options: &RetryOptions,
+        headers: Option<&HeaderMap>,
+    ) -> Result<JsonRpcResponse<serde_json::Value>> {
+        options.spend.charge(url, &request.method)?;
+        let response = runtime::timeout(
+            options.rpc_call_timeout,
+            post_json_rpc(client, url, request, options.follow_redirects, headers)
+        ).await
+            .map_err(|_| RpcHandlerError::request_timeout(url, options.rpc_call_timeout))?;
         
         let response = response?;
+        options.host_limiter.observe(url, response.headers());
         
-        if response.status().is_success() {
-            let json_response = response.json().await?;
-            Ok(json_response)
-        } else {
-            Err(RpcHandlerError::JsonRpc(url.to_string()))
+        if !response.status().is_success() {
+            return Err(RpcHandlerError::HttpStatus { url: url.to_string(), status: response.status().as_u16() });
+        }
+
+        match options.validation_mode {
+            ValidationMode::Lenient => response.json().await.map_err(|e| RpcHandlerError::from_reqwest(e, url)),
+            ValidationMode::Strict => {
+                let raw: serde_json::Value = response.json().await.map_err(|e| RpcHandlerError::from_reqwest(e, url))?;
+                if let Some(violation) = validate_response(request, &raw) {
+                    *options.malformed_counts.lock().entry(url.to_string()).or_insert(0) += 1;
+                    return Err(RpcHandlerError::MalformedResponse { url: url.to_string(), violation });
+                }
+                serde_json::from_value(raw).map_err(|e| RpcHandlerError::SerializationError(e.to_string()))
+            }
         }
     }
 }
 
+/// Endpoints left out of the rest of a request after answering in a way a retry won't change.
+#[derive(Default)]
+pub(super) struct Sidelined {
+    /// Answered like a web page
+    not_json_rpc: HashSet<String>,
+    first_not_json_rpc: Option<RpcHandlerError>,
+    /// Reported a head behind the one already returned
+    behind_head: HashSet<String>,
+    head_error: Option<RpcHandlerError>,
+    /// Couldn't be connected to within the connect timeout
+    unreachable: HashSet<String>,
+    first_unreachable: Option<RpcHandlerError>,
+    /// Ran out of spend budget
+    over_budget: HashSet<String>,
+    first_over_budget: Option<RpcHandlerError>,
+    /// Every failed attempt, when they are journaled
+    failures: Vec<AttemptFailure>,
+    /// When the request's first attempt went out, once it had a host slot
+    first_send: Option<Instant>,
+    /// An attempt in the current batch timed out
+    timed_out: bool,
+}
+
+/// The error a request that ran out of endpoints and retries fails with, `batch_err` being the
+/// last batch's.
+fn terminal_error(sidelined: &mut Sidelined, plan: &RequestPlan, request: &JsonRpcRequest, batch_err: RpcHandlerError) -> RpcHandlerError {
+    let total_urls = plan.urls.len();
+    // An endpoint that answered behind the returned head says more than the ones that failed
+    if let Some(err) = sidelined.head_error.take() {
+        return err;
+    }
+    if sidelined.len() == total_urls
+        && let Some(err) = sidelined.first_not_json_rpc.take()
+    {
+        return err;
+    }
+    if sidelined.over_budget.len() == total_urls
+        && let Some(err) = sidelined.first_over_budget.take()
+    {
+        return err;
+    }
+    if total_urls == 1
+        && let Some(err) = sidelined.first_unreachable.take()
+    {
+        return err;
+    }
+    if plan.routed_only() {
+        return RpcHandlerError::RoutedEndpointsFailed {
+            method: request.method.clone(),
+            urls: plan.route.as_ref().map(RouteRule::normalized_urls).unwrap_or_default(),
+        };
+    }
+    batch_err
+}
+
+impl Sidelined {
+    fn contains(&self, url: &str) -> bool {
+        self.not_json_rpc.contains(url) || self.behind_head.contains(url) || self.unreachable.contains(url) || self.over_budget.contains(url)
+    }
+
+    /// Endpoints sidelined for what they answered; unreachable ones never answered.
+    fn len(&self) -> usize {
+        self.not_json_rpc.len() + self.behind_head.len()
+    }
+}
+
 pub fn wrap_with_retry(
     url: String,
     chain_id: NetworkId,
@@ -0,0 +1,412 @@
+//! Streaming receive for responses too large to buffer.
+//!
+//! The body is parsed as it arrives. Once the top-level `result` is found, an array is handed to
+//! the sink one element at a time, so only the element being read is ever held in memory; any
+//! other result is handed over whole. An `error` member fails the call wherever it appears.
+//!
+//! Endpoints are tried one after another rather than raced, since two bodies can't feed one
+//! sink. A failure before the sink got anything moves on to the next endpoint; once it has, the
+//! call fails with `PartialStream` instead of starting over and repeating items.
+
+use std::ops::ControlFlow;
+
+use serde_json::Value;
+
+use crate::{
+    error::kb,
+    provider::{classify::post_json_rpc, plan::{build_plan, CallOptions}, retry_proxy::RetryProvider, RetryOptions},
+    runtime,
+    JsonRpcError, JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// Receives a streamed `result`.
+pub trait ResultSink: Send {
+    /// The next element of an array result, or a non-array result whole. `Break` stops reading
+    /// the response.
+    fn item(&mut self, item: Value) -> ControlFlow<()>;
+}
+
+impl<F: FnMut(Value) -> ControlFlow<()> + Send> ResultSink for F {
+    fn item(&mut self, item: Value) -> ControlFlow<()> {
+        self(item)
+    }
+}
+
+/// How a streamed call ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamSummary {
+    /// The endpoint that served the stream
+    pub url: String,
+    pub items_emitted: u64,
+    /// The sink broke off before the end of the result
+    pub stopped_early: bool,
+}
+
+impl RetryProvider {
+    /// Send `request` and feed its `result` into `sink` as the body arrives.
+    ///
+    /// `rpc_call_timeout` bounds the wait for the response and then for each chunk of the body,
+    /// not the whole transfer. Streamed results skip strict validation and head tracking.
+    pub async fn send_request_streaming(&self, request: &JsonRpcRequest, sink: &mut dyn ResultSink) -> Result<StreamSummary> {
+        *self.last_activity.lock() = self.clock.now_instant();
+        let guard = self.options.read().await.clone();
+        let _in_flight = guard.in_flight.enter();
+        let result = self.stream_planned(request, sink, &guard).await;
+        guard.metrics.record_request(&result);
+        result
+    }
+
+    async fn stream_planned(&self, request: &JsonRpcRequest, sink: &mut dyn ResultSink, guard: &RetryOptions) -> Result<StreamSummary> {
+        let plan = build_plan(&self.base_url, &request.method, &(guard.get_candidates)(), guard, &CallOptions::default());
+        let urls: Vec<String> = plan.urls.into_iter().map(|planned| planned.url).collect();
+        if urls.is_empty() {
+            return Err(RpcHandlerError::NoAvailableRpcs { network_id: self.chain_id });
+        }
+
+        let mut last_error = None;
+        for round in 0..guard.retry_count.max(1) {
+            if round > 0 {
+                guard.clock.sleep(guard.retry_delay).await;
+            }
+            for (at, url) in urls.iter().enumerate() {
+                let mut items_emitted = 0;
+                let attempt = self.stream_from(url, request, guard, sink, &mut items_emitted).await;
+                guard.metrics.record_attempt(url, attempt.as_ref().err());
+                guard.liveness.record_attempt(url, attempt.as_ref().err(), guard.clock.now_system());
+                let error = match attempt {
+                    Ok(stopped_early) => {
+                        if round > 0 || at > 0 {
+                            guard.metrics.record_failover();
+                        }
+                        return Ok(StreamSummary { url: url.clone(), items_emitted, stopped_early });
+                    }
+                    Err(e) if items_emitted > 0 => {
+                        return Err(RpcHandlerError::PartialStream { url: url.clone(), items_emitted, cause: Box::new(e) });
+                    }
+                    Err(e) => e,
+                };
+                if let RpcHandlerError::JsonRpcCode { code, ref message } = error
+                    && !kb::classify_with(&guard.error_mappings, &JsonRpcError { code, message: message.clone(), data: None }, url).is_retryable()
+                {
+                    return Err(error);
+                }
+                if let Some(ref logger) = guard.on_log {
+                    logger("debug", "Streaming attempt failed", Some(serde_json::json!({ "url": url, "error": format!("{:?}", error) })));
+                }
+                if let Some(ref resolver) = guard.resolver
+                    && error.is_transport()
+                {
+                    resolver.unpin(url);
+                }
+                last_error = Some(error);
+            }
+        }
+
+        // A lone endpoint's failure is reported as-is, so its URL and category reach the caller
+        Err(match last_error {
+            Some(e) if urls.len() == 1 => e,
+            _ => RpcHandlerError::AllEndpointsFailed,
+        })
+    }
+
+    /// Stream one attempt's result into `sink`, counting what it was given. Returns whether the
+    /// sink stopped early.
+    async fn stream_from(
+        &self,
+        url: &str,
+        request: &JsonRpcRequest,
+        options: &RetryOptions,
+        sink: &mut dyn ResultSink,
+        items_emitted: &mut u64,
+    ) -> Result<bool> {
+        options.spend.charge(url, &request.method)?;
+        let timed_out = |_| RpcHandlerError::request_timeout(url, options.rpc_call_timeout);
+        let _permit = runtime::timeout(options.rpc_call_timeout, options.host_limiter.acquire(url)).await.map_err(timed_out)?;
+        let mut response = runtime::timeout(options.rpc_call_timeout, post_json_rpc(&self.client, url, request, options.follow_redirects, options.headers.get(url)))
+            .await
+            .map_err(timed_out)??;
+        options.host_limiter.observe(url, response.headers());
+        if !response.status().is_success() {
+            return Err(RpcHandlerError::HttpStatus { url: url.to_string(), status: response.status().as_u16() });
+        }
+
+        let mut parser = ResponseParser::default();
+        while let Some(chunk) = runtime::timeout(options.rpc_call_timeout, response.chunk())
+            .await
+            .map_err(timed_out)?
+            .map_err(|e| RpcHandlerError::from_reqwest(e, url))?
+        {
+            let mut emit = |item| {
+                *items_emitted += 1;
+                sink.item(item)
+            };
+            if parser.feed(&chunk, &mut emit).map_err(|e| e.into_error(url))?.is_break() {
+                return Ok(true);
+            }
+        }
+        parser.finish().map_err(|e| e.into_error(url))?;
+        Ok(false)
+    }
+}
+
+/// Why a body couldn't be streamed.
+enum ParseError {
+    Malformed(&'static str),
+    Rpc(JsonRpcError),
+}
+
+impl ParseError {
+    fn into_error(self, url: &str) -> RpcHandlerError {
+        match self {
+            ParseError::Malformed(violation) => RpcHandlerError::MalformedResponse { url: url.to_string(), violation: violation.to_string() },
+            ParseError::Rpc(error) => RpcHandlerError::JsonRpcCode { code: error.code, message: error.message },
+        }
+    }
+}
+
+/// The top-level member a value belongs to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Member {
+    Result,
+    Error,
+    Other,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    /// Before the response object
+    #[default]
+    Start,
+    /// Inside the object, before a key or its closing brace
+    BeforeKey,
+    Key,
+    Colon(Member),
+    BeforeValue(Member),
+    Value(Member),
+    /// Inside an array `result`, before an element or its closing bracket
+    BeforeElement,
+    Element,
+    AfterElement,
+    AfterValue,
+    Done,
+}
+
+/// Finds where one JSON value ends, without parsing it.
+#[derive(Default)]
+struct Scan {
+    depth: u32,
+    in_string: bool,
+    escaped: bool,
+    scalar: bool,
+}
+
+enum Step {
+    More,
+    /// The value ended with this byte
+    Done,
+    /// The value ended just before this byte
+    DoneBefore,
+    Invalid,
+}
+
+impl Scan {
+    fn step(&mut self, byte: u8) -> Step {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' {
+                self.escaped = true;
+            } else if byte == b'"' {
+                self.in_string = false;
+                if self.depth == 0 {
+                    return Step::Done;
+                }
+            }
+            return Step::More;
+        }
+        if self.scalar {
+            return if matches!(byte, b',' | b'}' | b']') || byte.is_ascii_whitespace() { Step::DoneBefore } else { Step::More };
+        }
+        match byte {
+            b'"' => self.in_string = true,
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' if self.depth == 0 => return Step::Invalid,
+            b'}' | b']' => {
+                self.depth -= 1;
+                if self.depth == 0 {
+                    return Step::Done;
+                }
+            }
+            b',' | b':' if self.depth == 0 => return Step::Invalid,
+            _ if self.depth == 0 => self.scalar = true,
+            _ => {}
+        }
+        Step::More
+    }
+}
+
+/// Incremental parser for a single JSON-RPC response, holding at most one value at a time.
+#[derive(Default)]
+struct ResponseParser {
+    state: State,
+    scan: Scan,
+    /// The key or value being read, when it is kept
+    buffer: Vec<u8>,
+    saw_result: bool,
+}
+
+impl ResponseParser {
+    /// Read `chunk`, handing each completed result value to `emit`.
+    fn feed(&mut self, chunk: &[u8], emit: &mut dyn FnMut(Value) -> ControlFlow<()>) -> std::result::Result<ControlFlow<()>, ParseError> {
+        let mut at = 0;
+        while at < chunk.len() {
+            let byte = chunk[at];
+            let blank = byte.is_ascii_whitespace();
+            match self.state {
+                State::Start => match byte {
+                    _ if blank => {}
+                    b'{' => self.state = State::BeforeKey,
+                    _ => return Err(ParseError::Malformed("response is not a JSON object")),
+                },
+                State::BeforeKey => match byte {
+                    _ if blank => {}
+                    b',' => {}
+                    b'}' => self.state = State::Done,
+                    b'"' => {
+                        self.start_value();
+                        self.state = State::Key;
+                        continue;
+                    }
+                    _ => return Err(ParseError::Malformed("expected a key")),
+                },
+                State::Key => {
+                    self.buffer.push(byte);
+                    if let Step::Done = self.scan.step(byte) {
+                        let member = match serde_json::from_slice::<String>(&self.buffer).as_deref() {
+                            Ok("result") => Member::Result,
+                            Ok("error") => Member::Error,
+                            Ok(_) => Member::Other,
+                            Err(_) => return Err(ParseError::Malformed("invalid key")),
+                        };
+                        self.state = State::Colon(member);
+                    }
+                }
+                State::Colon(member) => match byte {
+                    _ if blank => {}
+                    b':' => self.state = State::BeforeValue(member),
+                    _ => return Err(ParseError::Malformed("expected `:` after a key")),
+                },
+                State::BeforeValue(member) => {
+                    if !blank {
+                        if member == Member::Result {
+                            self.saw_result = true;
+                        }
+                        if member == Member::Result && byte == b'[' {
+                            self.state = State::BeforeElement;
+                        } else {
+                            self.start_value();
+                            self.state = State::Value(member);
+                            continue;
+                        }
+                    }
+                }
+                State::Value(member) => {
+                    let step = self.scan.step(byte);
+                    if member != Member::Other && !matches!(step, Step::DoneBefore) {
+                        self.buffer.push(byte);
+                    }
+                    match step {
+                        Step::More => {}
+                        Step::Invalid => return Err(ParseError::Malformed("invalid JSON value")),
+                        Step::Done | Step::DoneBefore => {
+                            self.state = State::AfterValue;
+                            match member {
+                                Member::Result => {
+                                    if emit(self.parsed()?).is_break() {
+                                        return Ok(ControlFlow::Break(()));
+                                    }
+                                }
+                                Member::Error => {
+                                    let error: JsonRpcError = serde_json::from_slice(&self.buffer)
+                                        .map_err(|_| ParseError::Malformed("invalid error object"))?;
+                                    return Err(ParseError::Rpc(error));
+                                }
+                                Member::Other => {}
+                            }
+                            if let Step::DoneBefore = step {
+                                continue;
+                            }
+                        }
+                    }
+                }
+                State::BeforeElement => match byte {
+                    _ if blank => {}
+                    b']' => self.state = State::AfterValue,
+                    _ => {
+                        self.start_value();
+                        self.state = State::Element;
+                        continue;
+                    }
+                },
+                State::Element => {
+                    let step = self.scan.step(byte);
+                    if !matches!(step, Step::DoneBefore) {
+                        self.buffer.push(byte);
+                    }
+                    match step {
+                        Step::More => {}
+                        Step::Invalid => return Err(ParseError::Malformed("invalid JSON value in result")),
+                        Step::Done | Step::DoneBefore => {
+                            self.state = State::AfterElement;
+                            if emit(self.parsed()?).is_break() {
+                                return Ok(ControlFlow::Break(()));
+                            }
+                            if let Step::DoneBefore = step {
+                                continue;
+                            }
+                        }
+                    }
+                }
+                State::AfterElement => match byte {
+                    _ if blank => {}
+                    b',' => self.state = State::BeforeElement,
+                    b']' => self.state = State::AfterValue,
+                    _ => return Err(ParseError::Malformed("expected `,` or `]` in result")),
+                },
+                State::AfterValue => match byte {
+                    _ if blank => {}
+                    b',' => self.state = State::BeforeKey,
+                    b'}' => self.state = State::Done,
+                    _ => return Err(ParseError::Malformed("expected `,` or `}`")),
+                },
+                State::Done => {
+                    if !blank {
+                        return Err(ParseError::Malformed("trailing data after the response"));
+                    }
+                }
+            }
+            at += 1;
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Check the body ended where a response may end.
+    fn finish(&self) -> std::result::Result<(), ParseError> {
+        if self.state != State::Done {
+            return Err(ParseError::Malformed("body ended mid-response"));
+        }
+        if !self.saw_result {
+            return Err(ParseError::Malformed("response has neither result nor error"));
+        }
+        Ok(())
+    }
+
+    fn start_value(&mut self) {
+        self.scan = Scan::default();
+        self.buffer.clear();
+    }
+
+    fn parsed(&self) -> std::result::Result<Value, ParseError> {
+        serde_json::from_slice(&self.buffer).map_err(|_| ParseError::Malformed("invalid JSON value in result"))
+    }
+}
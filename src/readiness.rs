@@ -0,0 +1,247 @@
+//! Readiness and liveness verdicts for orchestrated deployments, from state the handler keeps
+//! anyway, so a health endpoint can answer without sending a request.
+//!
+//! Readiness asks whether the handler can serve: a provider is selected, some endpoint answered
+//! within `ReadinessThresholds::max_success_age_ms`, not every endpoint is cooling down and the
+//! held requests stay under `max_pending`.
+//!
+//! Liveness asks whether the handler's own machinery still runs. Each background loop gets a
+//! `Heartbeat` when it is spawned and beats each time it goes back to waiting; the heartbeat is
+//! dropped with the loop's future, so a loop that returned, panicked or was aborted shows as
+//! stopped. A periodic loop whose last beat is older than its period plus `heartbeat_grace_ms`,
+//! i.e. one stuck in its work, shows as stalled.
+//! Loops that wait on a channel have no period and are only checked for having stopped.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use web_time::Instant;
+
+use crate::{clock::Clock, events::InitState, RpcHandler};
+
+/// A loop the handler runs in the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundTask {
+    Keepalive,
+    AutoRefresh,
+    MaintenanceWatch,
+    SloWatch,
+    BudgetWatch,
+    AgreementSampler,
+    RetryTuner,
+    RotationWatch,
+    /// Only while `init` left the handler degraded; it ends once a probed provider takes over
+    Recovery,
+}
+
+/// Whether the handler can take traffic, and if not, why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReadinessStatus {
+    pub ready: bool,
+    /// Empty when ready
+    pub reasons: Vec<NotReady>,
+    /// Redacted, `None` before a provider is selected
+    pub active_url: Option<String>,
+    /// The last outcome that found any endpoint up: a probe, keepalive ping or request attempt
+    pub last_success: Option<SystemTime>,
+    /// Calls held under `CallOptions::hold_on_total_failure`
+    pub pending_requests: usize,
+    /// Endpoints in a consensus cooldown
+    pub cooling_down: usize,
+    /// Serving through endpoints no probe has passed, see `InitState::Degraded`
+    pub degraded: bool,
+}
+
+/// Why the handler isn't ready.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum NotReady {
+    /// `RpcHandler::shutdown` was called
+    ShutDown,
+    /// `init` hasn't selected a provider yet
+    NoProvider,
+    /// No endpoint answered within `max_age_ms`; `since_success_ms` is `None` when none ever did
+    NoRecentSuccess { since_success_ms: Option<u64>, max_age_ms: u64 },
+    /// Every endpoint is in a consensus cooldown
+    AllCoolingDown { endpoints: usize },
+    /// More calls are held through an outage than `max_pending` allows
+    Backlog { pending: usize, max_pending: usize },
+}
+
+/// Whether the handler's background machinery is running, and if not, what stopped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LivenessStatus {
+    pub live: bool,
+    /// Empty when live
+    pub reasons: Vec<NotLive>,
+    /// Every background loop started so far, by task
+    pub tasks: Vec<TaskHeartbeat>,
+}
+
+/// Why the handler isn't live.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum NotLive {
+    /// `RpcHandler::shutdown` was called, which stops every loop
+    ShutDown,
+    /// The loop ended, panicked or was aborted while the handler was running
+    TaskStopped { task: BackgroundTask },
+    /// The loop hasn't beaten within its period plus the grace
+    TaskStalled { task: BackgroundTask, since_beat_ms: u64, expected_within_ms: u64 },
+}
+
+/// One background loop's last sign of life.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskHeartbeat {
+    pub task: BackgroundTask,
+    pub running: bool,
+    /// Time since its last beat, or since it started if it hasn't beaten
+    pub since_beat_ms: u64,
+    /// Longest it may go between beats, `None` for loops driven by a channel
+    pub period_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Beat {
+    last: Instant,
+    period: Option<Duration>,
+    running: bool,
+}
+
+/// The last beat of every background loop started on a handler.
+pub(crate) struct Heartbeats {
+    beats: Arc<parking_lot::Mutex<BTreeMap<BackgroundTask, Beat>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Heartbeats {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { beats: Arc::default(), clock }
+    }
+
+    /// Register `task` as running, beating at least every `period` when it has one. The returned
+    /// heartbeat goes into the task; dropping it marks the task stopped.
+    pub(crate) fn start(&self, task: BackgroundTask, period: Option<Duration>) -> Heartbeat {
+        self.beats.lock().insert(task, Beat { last: self.clock.now_instant(), period, running: true });
+        Heartbeat { task, beats: Arc::clone(&self.beats), clock: Arc::clone(&self.clock) }
+    }
+
+    /// Every registered task as of now, in `BackgroundTask` order.
+    pub(crate) fn snapshot(&self) -> Vec<TaskHeartbeat> {
+        self.beats
+            .lock()
+            .iter()
+            .map(|(task, beat)| TaskHeartbeat {
+                task: *task,
+                running: beat.running,
+                since_beat_ms: self.clock.elapsed_since(beat.last).as_millis() as u64,
+                period_ms: beat.period.map(|period| period.as_millis() as u64),
+            })
+            .collect()
+    }
+}
+
+/// Held by a background loop for as long as it runs.
+pub(crate) struct Heartbeat {
+    task: BackgroundTask,
+    beats: Arc<parking_lot::Mutex<BTreeMap<BackgroundTask, Beat>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Heartbeat {
+    pub(crate) fn beat(&self) {
+        if let Some(beat) = self.beats.lock().get_mut(&self.task) {
+            beat.last = self.clock.now_instant();
+        }
+    }
+
+    /// The loop finished its work: it stops showing at all rather than as stopped.
+    pub(crate) fn retire(self) {
+        self.beats.lock().remove(&self.task);
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        if let Some(beat) = self.beats.lock().get_mut(&self.task) {
+            beat.running = false;
+        }
+    }
+}
+
+impl RpcHandler {
+    /// Whether this handler can take traffic, under `HandlerSettings::readiness`. Sends nothing.
+    pub fn readiness(&self) -> ReadinessStatus {
+        let thresholds = self.config().settings.readiness;
+        let init_state = self.init_state();
+        let active_url = init_state.url().map(|url| self.redact(url));
+        let last_success = self.liveness_log().last_healthy_any();
+        let pending_requests = self.holding_requests();
+        let urls: Vec<String> = self.rpcs().iter().map(|rpc| rpc.url.to_string()).collect();
+        let now = self.clock().now_instant();
+        // A cooldown being written is about to change anyway; count none rather than wait for it
+        let cooling_down = self
+            .cooldowns()
+            .try_read()
+            .map(|cooldowns| urls.iter().filter(|url| cooldowns.get(*url).is_some_and(|cooldown| cooldown.until > now)).count())
+            .unwrap_or(0);
+
+        let mut reasons = Vec::new();
+        if self.shutdown_token().is_cancelled() {
+            reasons.push(NotReady::ShutDown);
+        }
+        if active_url.is_none() {
+            reasons.push(NotReady::NoProvider);
+        }
+        let since_success_ms = last_success.map(|at| self.clock().now_system().duration_since(at).unwrap_or_default().as_millis() as u64);
+        if since_success_ms.is_none_or(|since| since > thresholds.max_success_age_ms) {
+            reasons.push(NotReady::NoRecentSuccess { since_success_ms, max_age_ms: thresholds.max_success_age_ms });
+        }
+        if !urls.is_empty() && cooling_down == urls.len() {
+            reasons.push(NotReady::AllCoolingDown { endpoints: cooling_down });
+        }
+        if let Some(max_pending) = thresholds.max_pending.filter(|max| pending_requests > *max) {
+            reasons.push(NotReady::Backlog { pending: pending_requests, max_pending });
+        }
+
+        let degraded = matches!(init_state, InitState::Degraded { .. });
+        ReadinessStatus { ready: reasons.is_empty(), reasons, active_url, last_success, pending_requests, cooling_down, degraded }
+    }
+
+    /// Whether this handler's background loops are running and keeping to their periods, under
+    /// `HandlerSettings::readiness`. Sends nothing.
+    pub fn liveness(&self) -> LivenessStatus {
+        let grace = Duration::from_millis(self.config().settings.readiness.heartbeat_grace_ms);
+        let tasks = self.heartbeats().snapshot();
+        let reasons = liveness_reasons(&tasks, grace, self.shutdown_token().is_cancelled());
+        LivenessStatus { live: reasons.is_empty(), reasons, tasks }
+    }
+}
+
+/// Why `tasks` say the handler isn't live, allowing `grace` past each period. Stopped tasks don't
+/// count once the handler is shut down, since shutting down stops them.
+pub(crate) fn liveness_reasons(tasks: &[TaskHeartbeat], grace: Duration, shut_down: bool) -> Vec<NotLive> {
+    if shut_down {
+        return vec![NotLive::ShutDown];
+    }
+    let grace_ms = grace.as_millis() as u64;
+    tasks
+        .iter()
+        .filter_map(|heartbeat| {
+            if !heartbeat.running {
+                return Some(NotLive::TaskStopped { task: heartbeat.task });
+            }
+            let expected_within_ms = heartbeat.period_ms? + grace_ms;
+            (heartbeat.since_beat_ms > expected_within_ms).then_some(NotLive::TaskStalled {
+                task: heartbeat.task,
+                since_beat_ms: heartbeat.since_beat_ms,
+                expected_within_ms,
+            })
+        })
+        .collect()
+}
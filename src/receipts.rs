@@ -0,0 +1,99 @@
+//! Whole-block receipt fetching that picks between `eth_getBlockReceipts`, its Erigon variant
+//! and per-transaction `eth_getTransactionReceipt` calls depending on what the endpoint supports.
+
+use futures::{StreamExt, TryStreamExt};
+use serde_json::{json, Value};
+
+use crate::{
+    calls::RpcCalls,
+    error::kb::ErrorCondition,
+    namespaces::BlockReceiptsMethod,
+    JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError,
+};
+
+/// Maximum in-flight `eth_getTransactionReceipt` calls when falling back to per-transaction fetches.
+pub const RECEIPT_FETCH_CONCURRENCY: usize = 8;
+
+/// Block receipt methods in the order they are tried on an endpoint with unknown support.
+const BLOCK_RECEIPT_METHODS: &[BlockReceiptsMethod] = &[BlockReceiptsMethod::Eth, BlockReceiptsMethod::Erigon];
+
+
+/// A block hash rather than a number or tag.
+fn is_block_hash(block_id: &str) -> bool {
+    block_id.len() == 66 && block_id.starts_with("0x")
+}
+
+impl RpcCalls {
+    /// All receipts for a block, in transaction order.
+    ///
+    /// `block_id` is a block number (`"0x10"`), a tag (`"latest"`) or a block hash. Support for the
+    /// single-call methods is remembered per endpoint, so an endpoint that rejected them goes
+    /// straight to per-transaction fetches next time.
+    pub async fn get_block_receipts(&self, block_id: &str) -> Result<Vec<Value>> {
+        let active_url = self.handler.get_provider_url().await?;
+        let known = self.handler.capabilities_for(&active_url).await.block_receipts;
+
+        let candidates: &[BlockReceiptsMethod] = match known {
+            Some(BlockReceiptsMethod::Unsupported) => &[],
+            Some(BlockReceiptsMethod::Eth) => &BLOCK_RECEIPT_METHODS[..1],
+            Some(BlockReceiptsMethod::Erigon) => &BLOCK_RECEIPT_METHODS[1..],
+            None => BLOCK_RECEIPT_METHODS,
+        };
+
+        let mut rejected_by = None;
+        for candidate in candidates {
+            let Some(method) = candidate.method_name() else { continue };
+            let (response, url) = self.try_rpc_call_attributed(JsonRpcRequest::new(method, json!([block_id]))).await?;
+
+            if response.error.as_ref().is_some_and(|error| self.handler.classify_error(error, &url) == ErrorCondition::MethodNotFound) {
+                rejected_by = Some(url);
+                continue;
+            }
+            if response.error.is_none() {
+                self.handler.record_capability(&url, |caps| caps.block_receipts = Some(*candidate)).await;
+            }
+            return receipts_array(method, response);
+        }
+
+        if let Some(url) = rejected_by {
+            self.handler.record_capability(&url, |caps| caps.block_receipts = Some(BlockReceiptsMethod::Unsupported)).await;
+        }
+        self.get_block_receipts_per_transaction(block_id).await
+    }
+
+    /// Fetch the block's transaction hashes and then each receipt, stitched back in block order.
+    async fn get_block_receipts_per_transaction(&self, block_id: &str) -> Result<Vec<Value>> {
+        let block_request = if is_block_hash(block_id) {
+            JsonRpcRequest::new("eth_getBlockByHash", json!([block_id, false]))
+        } else {
+            JsonRpcRequest::new("eth_getBlockByNumber", json!([block_id, false]))
+        };
+        let block = self.try_rpc_call(&block_request).await?.into_result()?;
+        let hashes: Vec<String> = block
+            .get("transactions")
+            .and_then(Value::as_array)
+            .ok_or_else(|| RpcHandlerError::SerializationError(format!("block {block_id} has no transaction list")))?
+            .iter()
+            .filter_map(|tx| tx.as_str().map(str::to_string))
+            .collect();
+
+        futures::stream::iter(hashes)
+            .map(|hash| async move {
+                let receipt = self.try_rpc_call(&JsonRpcRequest::new("eth_getTransactionReceipt", json!([hash]))).await?.into_result()?;
+                if receipt.is_null() {
+                    return Err(RpcHandlerError::SerializationError(format!("missing receipt for {hash}")));
+                }
+                Ok(receipt)
+            })
+            .buffered(RECEIPT_FETCH_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+}
+
+fn receipts_array(method: &str, response: JsonRpcResponse<Value>) -> Result<Vec<Value>> {
+    match response.into_result()? {
+        Value::Array(receipts) => Ok(receipts),
+        other => Err(RpcHandlerError::SerializationError(format!("{method} returned a non-array result: {other}"))),
+    }
+}
@@ -0,0 +1,193 @@
+//! Coarse regions read off endpoint hostnames, so nearby endpoints can be preferred.
+//!
+//! Many providers put the region in the hostname: `eu.`, `us-east-1.`, `ap-southeast.`,
+//! `singapore.`. `region_of` looks the host up in `HandlerSettings::region_hints` first and
+//! falls back to reading it with `region_of_host`. Under `HandlerSettings::region_affinity` the
+//! region breaks near-ties and damps switches between continents; it never leaves an endpoint
+//! out.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::IpAddr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{performance::LatencyMap, RegionAffinityPolicy};
+
+/// Where an endpoint's servers are, to the continent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Region {
+    NorthAmerica,
+    SouthAmerica,
+    Europe,
+    /// Asia and Oceania
+    AsiaPacific,
+    MiddleEast,
+    Africa,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Region::NorthAmerica => "north_america",
+            Region::SouthAmerica => "south_america",
+            Region::Europe => "europe",
+            Region::AsiaPacific => "asia_pacific",
+            Region::MiddleEast => "middle_east",
+            Region::Africa => "africa",
+        })
+    }
+}
+
+/// Words that name a region on their own as a hostname label or part of one.
+const PLACES: &[(&str, Region)] = &[
+    ("us", Region::NorthAmerica),
+    ("usa", Region::NorthAmerica),
+    ("america", Region::NorthAmerica),
+    ("virginia", Region::NorthAmerica),
+    ("ohio", Region::NorthAmerica),
+    ("oregon", Region::NorthAmerica),
+    ("california", Region::NorthAmerica),
+    ("nyc", Region::NorthAmerica),
+    ("newyork", Region::NorthAmerica),
+    ("chicago", Region::NorthAmerica),
+    ("dallas", Region::NorthAmerica),
+    ("canada", Region::NorthAmerica),
+    ("toronto", Region::NorthAmerica),
+    ("eu", Region::Europe),
+    ("europe", Region::Europe),
+    ("uk", Region::Europe),
+    ("london", Region::Europe),
+    ("frankfurt", Region::Europe),
+    ("germany", Region::Europe),
+    ("amsterdam", Region::Europe),
+    ("paris", Region::Europe),
+    ("dublin", Region::Europe),
+    ("ireland", Region::Europe),
+    ("stockholm", Region::Europe),
+    ("warsaw", Region::Europe),
+    ("asia", Region::AsiaPacific),
+    ("apac", Region::AsiaPacific),
+    ("singapore", Region::AsiaPacific),
+    ("sg", Region::AsiaPacific),
+    ("tokyo", Region::AsiaPacific),
+    ("japan", Region::AsiaPacific),
+    ("jp", Region::AsiaPacific),
+    ("hongkong", Region::AsiaPacific),
+    ("hk", Region::AsiaPacific),
+    ("seoul", Region::AsiaPacific),
+    ("korea", Region::AsiaPacific),
+    ("mumbai", Region::AsiaPacific),
+    ("india", Region::AsiaPacific),
+    ("sydney", Region::AsiaPacific),
+    ("australia", Region::AsiaPacific),
+    ("au", Region::AsiaPacific),
+    ("latam", Region::SouthAmerica),
+    ("brazil", Region::SouthAmerica),
+    ("saopaulo", Region::SouthAmerica),
+    ("bahrain", Region::MiddleEast),
+    ("dubai", Region::MiddleEast),
+    ("uae", Region::MiddleEast),
+    ("africa", Region::Africa),
+    ("johannesburg", Region::Africa),
+];
+
+/// Cloud region prefixes, which only name a region followed by a direction, as in `ap-south-1`
+/// or `useast2`: `sa` or `me` alone are too likely to mean something else.
+const CODES: &[(&str, Region)] = &[
+    ("us", Region::NorthAmerica),
+    ("na", Region::NorthAmerica),
+    ("ca", Region::NorthAmerica),
+    ("eu", Region::Europe),
+    ("ap", Region::AsiaPacific),
+    ("sa", Region::SouthAmerica),
+    ("me", Region::MiddleEast),
+    ("af", Region::Africa),
+];
+
+const DIRECTIONS: &[&str] = &["east", "west", "north", "south", "central", "northeast", "northwest", "southeast", "southwest"];
+
+/// The region of the endpoint at `url`: from `hints` when its `host:port`, its host or a domain
+/// above it is listed there, read off the hostname otherwise.
+pub fn region_of(url: &str, hints: &BTreeMap<String, Region>) -> Option<Region> {
+    let url = url::Url::parse(url).ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    let hinted = |key: &str| hints.iter().find(|(hint, _)| hint.eq_ignore_ascii_case(key)).map(|(_, region)| *region);
+    if let Some(region) = url.port().and_then(|port| hinted(&format!("{host}:{port}"))) {
+        return Some(region);
+    }
+    let mut domain = host.as_str();
+    loop {
+        if let Some(region) = hinted(domain) {
+            return Some(region);
+        }
+        match domain.split_once('.') {
+            Some((_, parent)) => domain = parent,
+            None => return region_of_host(&host),
+        }
+    }
+}
+
+/// The region a hostname names, such as `eu.rpc.example.com` or `rpc.us-east-1.example.com`.
+/// `None` for an IP address and for a hostname that names none.
+///
+/// Labels are split on `-` and `_`, trailing digits dropped, and the first word naming a place
+/// or a cloud region wins. The top-level domain is ignored, since it names a registry rather
+/// than where the servers are.
+pub fn region_of_host(host: &str) -> Option<Region> {
+    if host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let host = host.to_ascii_lowercase();
+    let labels: Vec<&str> = host.trim_end_matches('.').split('.').collect();
+    labels[..labels.len() - 1].iter().find_map(|label| region_of_label(label))
+}
+
+fn region_of_label(label: &str) -> Option<Region> {
+    let words: Vec<&str> = label
+        .split(['-', '_'])
+        .map(|word| word.trim_end_matches(|c: char| c.is_ascii_digit()))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let code = |word: &str| CODES.iter().find(|(code, _)| *code == word).map(|(_, region)| *region);
+    let place = |word: &str| PLACES.iter().find(|(place, _)| *place == word).map(|(_, region)| *region);
+    let glued = |word: &str| {
+        CODES.iter().find_map(|(code, region)| word.strip_prefix(code).filter(|rest| DIRECTIONS.contains(rest)).map(|_| *region))
+    };
+
+    let spelled_out = words.windows(2).find_map(|pair| code(pair[0]).filter(|_| DIRECTIONS.contains(&pair[1])));
+    spelled_out.or_else(|| words.iter().find_map(|word| place(word).or_else(|| glued(word))))
+}
+
+/// The region `policy` prefers: its `preferred`, or else the region of the fastest endpoint in
+/// `latencies` whose region is known.
+pub fn preferred_region(policy: &RegionAffinityPolicy, latencies: &LatencyMap, regions: &HashMap<String, Region>) -> Option<Region> {
+    policy.preferred.or_else(|| {
+        latencies
+            .iter()
+            .filter_map(|(url, latency)| regions.get(url).map(|region| (latency, url, *region)))
+            .min()
+            .map(|(_, _, region)| region)
+    })
+}
+
+/// Milliseconds `policy` adds to the latency of the endpoint at `url` when ordering: its
+/// `penalty_ms` when the endpoint is known to be outside `preferred`, nothing otherwise.
+pub fn region_penalty(policy: &RegionAffinityPolicy, url: &str, regions: &HashMap<String, Region>, preferred: Option<Region>) -> u64 {
+    match (regions.get(url), preferred) {
+        (Some(region), Some(preferred)) if *region != preferred => policy.penalty_ms,
+        _ => 0,
+    }
+}
+
+/// How much faster than the active provider an endpoint must measure to replace it: the
+/// cross-region margin when both regions are known and differ, the other one otherwise.
+pub fn switch_margin(policy: &RegionAffinityPolicy, active: Option<Region>, candidate: Option<Region>) -> u64 {
+    match (active, candidate) {
+        (Some(active), Some(candidate)) if active != candidate => policy.cross_region_switch_margin_ms,
+        _ => policy.switch_margin_ms,
+    }
+}
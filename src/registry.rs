@@ -0,0 +1,269 @@
+//! Handlers shared by every part of a process that asks for the same config.
+//!
+//! Building an `RpcHandler` probes every endpoint, so a service that builds one per request, as a
+//! framework extractor easily does, pays a full sweep each time. `HandlerRegistry::get_or_create`
+//! hands out one initialized handler per distinct config instead: concurrent callers with the same
+//! config share a single initialization, and a handler nobody has asked for in `max_idle` is shut
+//! down and dropped. `HandlerRegistry::global()` is one registry for the whole process.
+//!
+//! Configs are told apart by `config_key`, which covers the network, the configured endpoints and
+//! the effective policy hash, so settings that don't change behavior, such as the log level, map
+//! to the same handler.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, Weak,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use sha3::{Digest, Keccak256};
+use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+use web_time::Instant;
+
+use crate::{
+    clock::{system_clock, Clock},
+    config::resolve_config_with,
+    hex::hex,
+    runtime::{self, JoinHandle},
+    secrets::EnvSecretResolver,
+    HandlerComponents, HandlerConfig, Result, RpcHandler,
+};
+
+/// How long a handler may go unrequested before `HandlerRegistry::default()` shuts it down.
+pub const DEFAULT_MAX_IDLE: Duration = Duration::from_secs(15 * 60);
+
+static GLOBAL: LazyLock<HandlerRegistry> = LazyLock::new(HandlerRegistry::default);
+
+/// Options for `HandlerRegistry::new`.
+#[derive(Clone)]
+pub struct RegistryOptions {
+    /// Time a handler may go unrequested before it is shut down, never when `None`
+    pub max_idle: Option<Duration>,
+    /// Time source for idle tracking, passed on to the handlers; defaults to the system clock
+    pub clock: Option<Arc<dyn Clock>>,
+}
+
+impl Default for RegistryOptions {
+    fn default() -> Self {
+        Self { max_idle: Some(DEFAULT_MAX_IDLE), clock: None }
+    }
+}
+
+/// Counters for `HandlerRegistry::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RegistryStats {
+    /// Initialized handlers currently held
+    pub alive: usize,
+    /// Calls answered with a handler that existed, or was being created, already
+    pub hits: u64,
+    /// Calls that created a handler
+    pub misses: u64,
+    /// Handlers shut down for going unrequested
+    pub idle_evictions: u64,
+}
+
+struct Entry {
+    handler: OnceCell<Arc<RpcHandler>>,
+    last_used: Mutex<Instant>,
+}
+
+struct Inner {
+    clock: Arc<dyn Clock>,
+    max_idle: Option<Duration>,
+    entries: Mutex<HashMap<String, Arc<Entry>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    idle_evictions: AtomicU64,
+    reaper: Mutex<Option<JoinHandle<()>>>,
+    shutdown: CancellationToken,
+}
+
+/// Initialized handlers keyed by `config_key`.
+pub struct HandlerRegistry {
+    inner: Arc<Inner>,
+}
+
+impl Default for HandlerRegistry {
+    fn default() -> Self {
+        Self::new(RegistryOptions::default())
+    }
+}
+
+impl Drop for HandlerRegistry {
+    fn drop(&mut self) {
+        self.inner.shutdown.cancel();
+    }
+}
+
+impl HandlerRegistry {
+    pub fn new(options: RegistryOptions) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                clock: options.clock.unwrap_or_else(system_clock),
+                max_idle: options.max_idle,
+                entries: Mutex::new(HashMap::new()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                idle_evictions: AtomicU64::new(0),
+                reaper: Mutex::new(None),
+                shutdown: CancellationToken::new(),
+            }),
+        }
+    }
+
+    /// The process-wide registry, with `RegistryOptions::default()`.
+    pub fn global() -> &'static Self {
+        &GLOBAL
+    }
+
+    /// The initialized handler for `config`, created and initialized on first request.
+    ///
+    /// Concurrent calls for the same key wait on one initialization. If it fails, every waiting
+    /// call gets the error and the next call tries again.
+    pub async fn get_or_create(&self, config: HandlerConfig) -> Result<Arc<RpcHandler>> {
+        let key = config_key(&config)?;
+        self.start_reaper();
+        let entry = {
+            let mut entries = self.inner.entries.lock();
+            let entry = entries.entry(key.clone()).or_insert_with(|| {
+                Arc::new(Entry { handler: OnceCell::new(), last_used: Mutex::new(self.inner.clock.now_instant()) })
+            });
+            Arc::clone(entry)
+        };
+
+        let mut created = false;
+        let handler = entry
+            .handler
+            .get_or_try_init(|| async {
+                created = true;
+                let components = HandlerComponents { clock: Some(Arc::clone(&self.inner.clock)), ..HandlerComponents::default() };
+                let handler = RpcHandler::with_components(config, None, components).await?;
+                handler.init().await?;
+                Ok(handler)
+            })
+            .await;
+        *entry.last_used.lock() = self.inner.clock.now_instant();
+
+        match handler {
+            Ok(handler) => {
+                let counter = if created { &self.inner.misses } else { &self.inner.hits };
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(Arc::clone(handler))
+            }
+            Err(err) => {
+                let mut entries = self.inner.entries.lock();
+                if entries.get(&key).is_some_and(|held| Arc::ptr_eq(held, &entry) && !held.handler.initialized()) {
+                    entries.remove(&key);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Shut down and drop the handler for `config`. Returns whether there was one.
+    ///
+    /// Holders of the handler can keep using it for requests, but its background work stops.
+    pub fn evict(&self, config: &HandlerConfig) -> Result<bool> {
+        let key = config_key(config)?;
+        Ok(self.evict_key(&key))
+    }
+
+    /// Like `evict`, by `config_key`.
+    pub fn evict_key(&self, key: &str) -> bool {
+        let entry = self.inner.entries.lock().remove(key);
+        let handler = entry.and_then(|entry| entry.handler.get().cloned());
+        if let Some(handler) = &handler {
+            handler.shutdown();
+        }
+        handler.is_some()
+    }
+
+    /// Shut down every handler nobody has asked for in `max_idle`. Returns how many there were.
+    ///
+    /// Runs on its own every half `max_idle`; call it to evict at a moment of your choosing.
+    pub fn evict_idle(&self) -> usize {
+        self.inner.evict_idle()
+    }
+
+    pub fn stats(&self) -> RegistryStats {
+        let alive = self.inner.entries.lock().values().filter(|entry| entry.handler.initialized()).count();
+        RegistryStats {
+            alive,
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            idle_evictions: self.inner.idle_evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Start the idle reaper, once, from within the runtime the handlers run on.
+    fn start_reaper(&self) {
+        let Some(max_idle) = self.inner.max_idle else { return };
+        let mut reaper = self.inner.reaper.lock();
+        if reaper.is_some() {
+            return;
+        }
+        *reaper = Some(spawn_idle_reaper(Arc::downgrade(&self.inner), max_idle / 2, self.inner.shutdown.clone()));
+    }
+}
+
+impl Inner {
+    fn evict_idle(&self) -> usize {
+        let Some(max_idle) = self.max_idle else { return 0 };
+        let idle: Vec<Arc<RpcHandler>> = {
+            let mut entries = self.entries.lock();
+            let keys: Vec<String> = entries
+                .iter()
+                .filter(|(_, entry)| entry.handler.initialized() && self.clock.elapsed_since(*entry.last_used.lock()) >= max_idle)
+                .map(|(key, _)| key.clone())
+                .collect();
+            keys.iter().filter_map(|key| entries.remove(key)).filter_map(|entry| entry.handler.get().cloned()).collect()
+        };
+        for handler in &idle {
+            handler.shutdown();
+        }
+        self.idle_evictions.fetch_add(idle.len() as u64, Ordering::Relaxed);
+        idle.len()
+    }
+}
+
+/// Evict idle handlers every `interval` until `shutdown` or the registry is dropped.
+fn spawn_idle_reaper(inner: Weak<Inner>, interval: Duration, shutdown: CancellationToken) -> JoinHandle<()> {
+    runtime::spawn(async move {
+        loop {
+            let Some(clock) = inner.upgrade().map(|inner| Arc::clone(&inner.clock)) else { return };
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = clock.sleep(interval) => {}
+            }
+            let Some(inner) = inner.upgrade() else { return };
+            inner.evict_idle();
+        }
+    })
+}
+
+/// What `HandlerRegistry` tells configs apart by: a Keccak-256 over the network, the configured
+/// endpoints and the effective policy hash.
+///
+/// Endpoint URLs go in with their secrets filled in from the environment, so templates resolving
+/// to different keys don't share a handler, but only the hash is kept.
+pub fn config_key(config: &HandlerConfig) -> Result<String> {
+    let normalized = resolve_config_with(config.clone(), &EnvSecretResolver)?;
+    let settings = &normalized.settings;
+    let identity = format!(
+        "{}|{:?}|{:?}|{:?}|{:?}|{}|{}",
+        normalized.network_id,
+        normalized.tracking,
+        normalized.data_scope,
+        normalized.injected_rpcs,
+        settings.user_agent,
+        settings.pin_resolved_ips,
+        normalized.effective_policy().hash(),
+    );
+    Ok(format!("0x{}", hex(&Keccak256::digest(identity.as_bytes()))))
+}
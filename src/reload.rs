@@ -0,0 +1,240 @@
+//! Applying a changed config to a running handler.
+//!
+//! Most settings are read per request or per sweep, so they take effect as soon as the new
+//! config is swapped in, and latencies, cooldowns and everything else learned carry over. A few
+//! are built into the HTTP client, the head tracker or a background task when the handler starts;
+//! a config changing any of those is rejected whole.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{resolve_config_with, NormalizedConfig},
+    rpc::{select_aliased_rpc_set, TrackedRpc},
+    HandlerConfig, Result, Rpc, RpcHandler, RpcHandlerError,
+};
+
+/// Fields of `NormalizedConfig` only a new handler can change.
+pub const RESTART_FIELDS: &[&str] = &[
+    "network_id",
+    "data_scope",
+    "chain_aliases",
+    "settings.connect_timeout",
+    "settings.user_agent",
+    "settings.pin_resolved_ips",
+    "settings.browser_local_storage",
+    "settings.keepalive",
+    "settings.host_limits",
+    "settings.monotonic_head",
+    "settings.auto_refresh",
+    "settings.retry_tuning",
+    "settings.maintenance_lead",
+    "settings.constrained_mode",
+];
+
+/// What `apply_config` changed, for audit logs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConfigDiff {
+    /// Every setting whose value changed, in `NormalizedConfig` order
+    pub changes: Vec<FieldChange>,
+    /// Endpoint URLs, redacted
+    pub added_rpcs: Vec<String>,
+    pub removed_rpcs: Vec<String>,
+    /// Endpoints kept with a different tier, tracking, maintenance windows, headers or cost profile
+    pub updated_rpcs: Vec<String>,
+    /// The active provider was selected again
+    pub reselected: bool,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.added_rpcs.is_empty() && self.removed_rpcs.is_empty() && self.updated_rpcs.is_empty()
+    }
+}
+
+/// One setting's old and new value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    /// Path within `NormalizedConfig`, e.g. `settings.rpc_call_timeout`
+    pub field: &'static str,
+    /// `Debug` renderings, maps sorted and URLs redacted
+    pub from: String,
+    pub to: String,
+}
+
+impl RpcHandler {
+    /// Resolve `config` and apply it in place.
+    ///
+    /// Retry, timeout, routing and validation changes reach the active provider at once;
+    /// requests already under way finish with the options they started with. Endpoints added
+    /// or dropped from the configured set go through `add_rpc` and `remove_rpc`, so new ones are
+    /// probed from the next refresh. A changed failover policy, or dropping the active provider,
+    /// selects the provider again right away.
+    ///
+    /// Fails with `ConfigNotReloadable`, changing nothing, if any of `RESTART_FIELDS` differ.
+    pub async fn apply_config(self: &Arc<Self>, config: HandlerConfig) -> Result<ConfigDiff> {
+        let new = resolve_config_with(config, self.secret_resolver())?;
+        let old = self.config();
+        let changes = field_changes(&old, &new);
+        let fixed: Vec<String> = changes
+            .iter()
+            .filter(|change| RESTART_FIELDS.contains(&change.field))
+            .map(|change| change.field.to_string())
+            .collect();
+        if !fixed.is_empty() {
+            return Err(RpcHandlerError::ConfigNotReloadable { fields: fixed });
+        }
+
+        let (old_rpcs, new_rpcs) = (self.base_rpcs(&old), self.base_rpcs(&new));
+        let policy_changed = old.failover_policy != new.failover_policy;
+        let redactor = new.redactor.clone();
+        self.set_config(new);
+        self.tuner.forget_baseline();
+
+        let mut diff = ConfigDiff { changes, ..ConfigDiff::default() };
+        let previous: HashMap<&str, &Rpc> = old_rpcs.iter().map(|tracked| (tracked.rpc.url.as_str(), &tracked.rpc)).collect();
+        let kept: Vec<&str> = new_rpcs.iter().map(|tracked| tracked.rpc.url.as_str()).collect();
+        for rpc in old_rpcs.iter().map(|tracked| &tracked.rpc).filter(|rpc| !kept.contains(&rpc.url.as_str())) {
+            if self.remove_rpc(rpc.url.as_str()).await {
+                diff.removed_rpcs.push(old.redactor.redact(rpc.url.as_str()));
+            }
+        }
+        let current = self.config();
+        for tracked in new_rpcs {
+            let url = redactor.redact(tracked.rpc.url.as_str());
+            match previous.get(tracked.rpc.url.as_str()) {
+                None => {
+                    if self.add_tracked_rpc(tracked) {
+                        diff.added_rpcs.push(url);
+                    }
+                }
+                Some(before) => {
+                    let reconfigured = !same_endpoint_settings(&old, &current, tracked.rpc.url.as_str());
+                    if (!same_rpc(before, &tracked.rpc) && self.replace_rpc(tracked.rpc)) || reconfigured {
+                        diff.updated_rpcs.push(url);
+                    }
+                }
+            }
+        }
+
+        self.reload_provider_options().await;
+        let active_removed = match self.get_provider_url().await {
+            Ok(active) => !self.rpcs().iter().any(|rpc| rpc.url.as_str() == active),
+            Err(_) => false,
+        };
+        if policy_changed || active_removed {
+            match self.refresh().await {
+                Ok(()) => diff.reselected = true,
+                Err(e) => self.log("warn", "Re-selection after config change failed", Some(serde_json::json!({ "error": e.to_string() }))).await,
+            }
+        }
+
+        self.log("info", "Applied config", serde_json::to_value(&diff).ok()).await;
+        Ok(diff)
+    }
+
+    /// Swap in `new`, derived from the running config rather than resolved from a `HandlerConfig`,
+    /// and hand it to the active provider. The endpoints and `RESTART_FIELDS` are the caller's
+    /// to leave alone.
+    pub(crate) async fn apply_derived_config(&self, new: NormalizedConfig) -> Vec<FieldChange> {
+        let changes = field_changes(&self.config(), &new);
+        self.set_config(new);
+        self.reload_provider_options().await;
+        changes
+    }
+
+    /// The endpoints `config` configures, before any added at runtime.
+    fn base_rpcs(&self, config: &NormalizedConfig) -> Vec<TrackedRpc> {
+        select_aliased_rpc_set(
+            self.rpc_source(),
+            config.network_id,
+            &config.chain_aliases,
+            config.tracking.clone(),
+            config.injected_rpcs.clone(),
+            config.settings.egress,
+        )
+    }
+}
+
+fn same_rpc(a: &Rpc, b: &Rpc) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Whether `old` and `new` configure the endpoint at `url` alike beyond its `Rpc`.
+fn same_endpoint_settings(old: &NormalizedConfig, new: &NormalizedConfig, url: &str) -> bool {
+    old.tiers.get(url) == new.tiers.get(url)
+        && old.maintenance_windows.get(url) == new.maintenance_windows.get(url)
+        && old.headers.get(url) == new.headers.get(url)
+        && old.cost_profiles.get(url) == new.cost_profiles.get(url)
+}
+
+/// The settings that differ between `old` and `new`. The endpoint set is diffed separately.
+fn field_changes(old: &NormalizedConfig, new: &NormalizedConfig) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let mut compare = |field: &'static str, render: &dyn Fn(&NormalizedConfig) -> String| {
+        let (from, to) = (old.redactor.redact(&render(old)), new.redactor.redact(&render(new)));
+        if from != to {
+            changes.push(FieldChange { field, from, to });
+        }
+    };
+    compare("network_id", &|config| format!("{:?}", config.network_id));
+    compare("tracking", &|config| format!("{:?}", config.tracking));
+    compare("retry.retry_count", &|config| format!("{:?}", config.retry.retry_count));
+    compare("retry.retry_delay", &|config| format!("{:?}", config.retry.retry_delay));
+    compare("retry.batch_size", &|config| format!("{:?}", config.retry.batch_size));
+    compare("failover_policy", &|config| format!("{:?}", config.failover_policy));
+    compare("routes", &|config| format!("{:?}", config.routes));
+    compare("validation_mode", &|config| format!("{:?}", config.validation_mode));
+    compare("data_scope", &|config| format!("{:?}", config.data_scope));
+    compare("chain_aliases", &|config| format!("{:?}", config.chain_aliases));
+    compare("settings.rpc_timeout", &|config| format!("{:?}", config.settings.rpc_timeout));
+    compare("settings.rpc_call_timeout", &|config| format!("{:?}", config.settings.rpc_call_timeout));
+    compare("settings.connect_timeout", &|config| format!("{:?}", config.settings.connect_timeout));
+    compare("settings.browser_local_storage", &|config| format!("{:?}", config.settings.browser_local_storage));
+    compare("settings.log_level", &|config| format!("{:?}", config.settings.log_level));
+    compare("settings.prune_unused_data", &|config| format!("{:?}", config.settings.prune_unused_data));
+    compare("settings.pin_resolved_ips", &|config| format!("{:?}", config.settings.pin_resolved_ips));
+    compare("settings.keepalive", &|config| format!("{:?}", config.settings.keepalive));
+    compare("settings.follow_post_redirects", &|config| format!("{:?}", config.settings.follow_post_redirects));
+    compare("settings.memory_limits", &|config| format!("{:?}", config.settings.memory_limits));
+    compare("settings.adaptive_probe_timeout", &|config| format!("{:?}", config.settings.adaptive_probe_timeout));
+    compare("settings.fast_start_margin", &|config| format!("{:?}", config.settings.fast_start_margin));
+    compare("settings.host_limits", &|config| format!("{:?}", config.settings.host_limits.describe()));
+    compare("settings.monotonic_head", &|config| format!("{:?}", config.settings.monotonic_head));
+    compare("settings.auto_refresh", &|config| format!("{:?}", config.settings.auto_refresh));
+    compare("settings.init_policy", &|config| format!("{:?}", config.settings.init_policy.as_ref().map(|policy| policy.describe())));
+    compare("settings.retry_tuning", &|config| format!("{:?}", config.settings.retry_tuning));
+    compare("settings.maintenance_windows", &|config| {
+        format!("{:?}", config.settings.maintenance_windows.iter().collect::<BTreeMap<_, _>>())
+    });
+    compare("settings.maintenance_lead", &|config| format!("{:?}", config.settings.maintenance_lead));
+    compare("settings.max_concurrent_probes", &|config| format!("{:?}", config.settings.max_concurrent_probes));
+    compare("settings.probe_sweep_deadline", &|config| format!("{:?}", config.settings.probe_sweep_deadline));
+    compare("settings.max_probe_lag_blocks", &|config| format!("{:?}", config.settings.max_probe_lag_blocks));
+    compare("settings.head_lag_penalty_ms", &|config| format!("{:?}", config.settings.head_lag_penalty_ms));
+    compare("settings.region_hints", &|config| format!("{:?}", config.settings.region_hints));
+    compare("settings.region_affinity", &|config| format!("{:?}", config.settings.region_affinity));
+    compare("settings.user_agent", &|config| format!("{:?}", config.settings.user_agent));
+    compare("settings.latency_slo", &|config| format!("{:?}", config.settings.latency_slo.as_ref().map(|slo| slo.describe())));
+    compare("settings.daily_spend_budget", &|config| format!("{:?}", config.settings.daily_spend_budget));
+    compare("settings.timestamp_sanity", &|config| format!("{:?}", config.settings.timestamp_sanity.as_ref().map(|sanity| sanity.describe())));
+    compare("settings.agreement_sampling", &|config| format!("{:?}", config.settings.agreement_sampling.as_ref().map(|sampling| sampling.describe())));
+    compare("settings.response_cache_entries", &|config| format!("{:?}", config.settings.response_cache_entries));
+    compare("settings.negative_cache_entries", &|config| format!("{:?}", config.settings.negative_cache_entries));
+    compare("settings.rotate_secrets_on_auth_error", &|config| format!("{:?}", config.settings.rotate_secrets_on_auth_error));
+    compare("settings.localnet", &|config| format!("{:?}", config.settings.localnet));
+    compare("settings.egress", &|config| format!("{:?}", config.settings.egress));
+    compare("settings.idempotent_methods", &|config| format!("{:?}", config.settings.idempotent_methods));
+    compare("settings.metric_tag_keys", &|config| format!("{:?}", config.settings.metric_tag_keys));
+    compare("settings.error_mappings", &|config| format!("{:?}", config.settings.error_mappings));
+    compare("settings.staleness", &|config| format!("{:?}", config.settings.staleness.as_ref().map(|staleness| staleness.describe())));
+    compare("settings.custom_probes", &|config| format!("{:?}", config.settings.custom_probes));
+    compare("settings.custom_probe_policy", &|config| format!("{:?}", config.settings.custom_probe_policy));
+    compare("settings.constrained_mode", &|config| format!("{:?}", config.settings.constrained_mode.as_ref().map(|mode| mode.describe())));
+    compare("settings.readiness", &|config| format!("{:?}", config.settings.readiness));
+    changes
+}
@@ -0,0 +1,138 @@
+//! Receipts and blocks checked against the canonical chain before they are returned.
+//!
+//! An endpoint that hasn't caught up with a reorg can serve a receipt or a block from an orphaned
+//! block. Under `ConsistencyCheck::VerifyCanonical` the typed helpers look up the canonical hash
+//! at the height the data claims, from another endpoint where there is one, and re-send the
+//! query with backoff while the two disagree; a reorg usually settles within a block or two. Data
+//! that never agrees fails with `NonCanonicalData` instead of being returned.
+//!
+//! Canonical hashes of blocks at least `TIMESTAMP_FINALITY_DEPTH` below the head watermark are
+//! kept on the `RpcCalls`, so checks against old blocks cost nothing after the first.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use serde_json::{json, Value};
+
+use crate::{
+    block_search::TIMESTAMP_FINALITY_DEPTH,
+    calls::RpcCalls,
+    namespaces::parse_quantity,
+    provider::CallOptions,
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// Canonical block hashes an `RpcCalls` keeps at most.
+pub const MAX_CACHED_CANONICAL_HASHES: usize = 4096;
+
+/// Whether a typed receipt or block read is checked against the canonical chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConsistencyCheck {
+    /// Return what the endpoint answered
+    #[default]
+    Skip,
+    /// Compare the block hash the data carries with the canonical one at its height
+    VerifyCanonical(CanonicalRetry),
+}
+
+impl ConsistencyCheck {
+    /// `VerifyCanonical` with the default retries.
+    pub fn verify_canonical() -> Self {
+        ConsistencyCheck::VerifyCanonical(CanonicalRetry::default())
+    }
+}
+
+/// How `ConsistencyCheck::VerifyCanonical` retries data from a block that isn't canonical.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanonicalRetry {
+    /// Times the query is re-sent after the first mismatch
+    pub max_retries: u32,
+    /// Wait before the first retry, doubling for each one after it
+    pub backoff: Duration,
+}
+
+impl Default for CanonicalRetry {
+    fn default() -> Self {
+        Self { max_retries: 3, backoff: Duration::from_millis(500) }
+    }
+}
+
+/// Canonical block hashes by height, shared by the checks of one `RpcCalls`.
+pub(crate) type CanonicalHashCache = std::sync::Arc<parking_lot::Mutex<BTreeMap<u64, String>>>;
+
+
+/// The height and block hash `result` claims, `None` when it carries neither, as a pending block.
+fn anchor(result: &Value, height_field: &str, hash_field: &str) -> Option<(u64, String)> {
+    let height = result.get(height_field).and_then(parse_quantity)?;
+    let hash = result.get(hash_field).and_then(Value::as_str)?;
+    Some((height, hash.to_string()))
+}
+
+impl RpcCalls {
+    /// The receipt of transaction `hash`, `None` while the transaction is unknown or pending.
+    pub async fn get_transaction_receipt(&self, hash: &str, check: ConsistencyCheck) -> Result<Option<Value>> {
+        self.read_checked(JsonRpcRequest::new("eth_getTransactionReceipt", json!([hash])), ("blockNumber", "blockHash"), check).await
+    }
+
+    /// Block `block`, a number (`"0x10"`) or a tag, with full transactions when `hydrated`.
+    /// `None` for a block past the head. A pending block carries no hash and is never checked.
+    pub async fn get_block_by_number(&self, block: &str, hydrated: bool, check: ConsistencyCheck) -> Result<Option<Value>> {
+        self.read_checked(JsonRpcRequest::new("eth_getBlockByNumber", json!([block, hydrated])), ("number", "hash"), check).await
+    }
+
+    /// Send `request` and, under `VerifyCanonical`, re-send it until the block its result names
+    /// through `fields` (height, hash) is the canonical one.
+    async fn read_checked(&self, request: JsonRpcRequest, fields: (&str, &str), check: ConsistencyCheck) -> Result<Option<Value>> {
+        let ConsistencyCheck::VerifyCanonical(retry) = check else {
+            let result = self.try_rpc_call(&request).await?.into_result()?;
+            return Ok(Some(result).filter(|result| !result.is_null()));
+        };
+        let mut retries = 0;
+        loop {
+            let (response, provider) = self.try_rpc_call_attributed(request.clone()).await?;
+            let result = response.into_result()?;
+            if result.is_null() {
+                return Ok(None);
+            }
+            let Some((height, got_hash)) = anchor(&result, fields.0, fields.1) else {
+                return Ok(Some(result));
+            };
+            let expected_hash = self.canonical_hash(height, &provider).await?;
+            if expected_hash.as_deref().is_some_and(|expected| expected.eq_ignore_ascii_case(&got_hash)) {
+                return Ok(Some(result));
+            }
+            tracing::warn!(method = %request.method, %provider, height, got = %got_hash, expected = ?expected_hash, "data from a non-canonical block");
+            if retries >= retry.max_retries {
+                return Err(RpcHandlerError::NonCanonicalData { height, expected_hash, got_hash, provider });
+            }
+            self.clock.sleep(retry.backoff.saturating_mul(1 << retries.min(16))).await;
+            retries += 1;
+        }
+    }
+
+    /// The hash of the canonical block at `height`, asked of an endpoint other than `served_by`
+    /// when one answers, else of any. `None` when the block wasn't found.
+    async fn canonical_hash(&self, height: u64, served_by: &str) -> Result<Option<String>> {
+        if let Some(hash) = self.canonical_hashes.lock().get(&height) {
+            return Ok(Some(hash.clone()));
+        }
+        let block_request = JsonRpcRequest::new("eth_getBlockByNumber", json!([format!("{height:#x}"), false]));
+        let others = CallOptions { exclude: vec![served_by.to_string()], ..CallOptions::default() };
+        let from_others = self.handler.try_proxy_request_with(block_request.clone(), others).await.and_then(|(response, _)| response.into_result());
+        let block = match from_others {
+            Ok(block) if !block.is_null() => block,
+            // No other endpoint, or none with the block yet
+            _ => self.handler.try_proxy_request(block_request).await?.into_result()?,
+        };
+        let Some(hash) = block.get("hash").and_then(Value::as_str).map(str::to_string) else {
+            return Ok(None);
+        };
+        if self.handler.head_watermark().is_some_and(|head| height.saturating_add(TIMESTAMP_FINALITY_DEPTH) <= head) {
+            let mut cache = self.canonical_hashes.lock();
+            cache.insert(height, hash.clone());
+            while cache.len() > MAX_CACHED_CANONICAL_HASHES {
+                cache.pop_first();
+            }
+        }
+        Ok(Some(hash))
+    }
+}
@@ -0,0 +1,164 @@
+//! Rotating the secrets in templated endpoint URLs without restarting.
+//!
+//! `RpcHandler::rotate_secret` asks the `SecretResolver` for a placeholder again and moves every
+//! endpoint whose `url_template` names it to the URL the new value fills in. It is the same
+//! endpoint under a new URL, so its latency, cooldown, uptime history and the day's spend move
+//! with it. Requests already under way keep the URL they started with and finish there.
+//!
+//! Under `HandlerSettings::rotate_secrets_on_auth_error`, an endpoint answering `401` or `403`
+//! has its placeholders resolved again in the background, once until its URL changes.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Weak},
+};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{events::HandlerEvent, readiness::Heartbeat, runtime::{self, JoinHandle}, secrets::TemplateRenderer, Result, RpcHandler};
+
+/// Endpoints that answered `401` or `403`, reported once each. Cloning shares them.
+#[derive(Debug, Clone)]
+pub struct AuthFailures {
+    reported: Arc<parking_lot::Mutex<HashSet<String>>>,
+    sender: UnboundedSender<String>,
+}
+
+impl AuthFailures {
+    /// A tracker, and the receiving end of the endpoints it reports.
+    pub(crate) fn new() -> (Self, UnboundedReceiver<String>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { reported: Arc::default(), sender }, receiver)
+    }
+
+    /// Report an authentication failure at `url`, unless it was reported already.
+    pub(crate) fn report(&self, url: &str) {
+        if self.reported.lock().insert(url.to_string()) {
+            // The handler may be gone, with nobody left to tell
+            let _ = self.sender.send(url.to_string());
+        }
+    }
+
+    /// Let `urls` be reported again.
+    fn clear(&self, urls: &[String]) {
+        let mut reported = self.reported.lock();
+        for url in urls {
+            reported.remove(url);
+        }
+    }
+}
+
+impl RpcHandler {
+    /// Resolve `{name}` again and swap every endpoint templated with it over to its new URL.
+    ///
+    /// Each endpoint keeps what was learned about it, and the active provider follows it to
+    /// the new URL; requests already sent finish on the old one. Returns the endpoints whose
+    /// URL changed, redacted, which is none when the value didn't change or no template names
+    /// the placeholder. If the value can't be resolved, every endpoint stays as it was.
+    pub async fn rotate_secret(self: &Arc<Self>, name: &str) -> Result<Vec<String>> {
+        let placeholder = format!("{{{name}}}");
+        let templated: Vec<String> =
+            self.config().url_templates.iter().filter(|(_, template)| template.contains(&placeholder)).map(|(url, _)| url.clone()).collect();
+        self.auth_failures().clear(&templated);
+        let rotated = self.rotate_urls(&templated).await?;
+        if !rotated.is_empty() {
+            self.emit(HandlerEvent::SecretRotated { urls: rotated.clone() });
+        }
+        Ok(rotated)
+    }
+
+    /// Resolve every placeholder in `url`'s template again after it failed authentication.
+    async fn rotate_after_auth_failure(self: &Arc<Self>, url: String) {
+        let Some(template) = self.config().url_templates.get(&url).cloned() else { return };
+        match self.rotate_urls(std::slice::from_ref(&url)).await {
+            Ok(rotated) if rotated.is_empty() => {
+                self.log("warn", "Endpoint failed authentication and its secrets are unchanged", Some(serde_json::json!({ "url": template }))).await;
+            }
+            Ok(rotated) => {
+                self.log("info", "Rotated secrets after an authentication failure", Some(serde_json::json!({ "url": template }))).await;
+                self.emit(HandlerEvent::SecretRotated { urls: rotated });
+            }
+            Err(e) => {
+                self.log("warn", "Could not rotate secrets after an authentication failure", Some(serde_json::json!({ "url": template, "error": e.to_string() }))).await;
+            }
+        }
+    }
+
+    /// Render the templates behind `urls` again and move each endpoint whose URL changed.
+    /// Nothing moves unless every template renders.
+    async fn rotate_urls(self: &Arc<Self>, urls: &[String]) -> Result<Vec<String>> {
+        let config = self.config();
+        let mut renderer = TemplateRenderer::new(self.secret_resolver());
+        let mut moves: Vec<(String, Url)> = Vec::new();
+        for url in urls {
+            let Some(template) = config.url_templates.get(url) else { continue };
+            let rendered = renderer.render(template)?;
+            if rendered.as_str() != url {
+                moves.push((url.clone(), rendered));
+            }
+        }
+        if moves.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Old secrets stay redacted, since requests under way still log their URLs
+        let mut updated = (*config).clone();
+        updated.redactor = config.redactor.merged(&renderer.into_redactor());
+        let mut headers = (*config.headers).clone();
+        for (from, to) in &moves {
+            if let Some(template) = updated.url_templates.remove(from) {
+                updated.url_templates.insert(to.to_string(), template);
+            }
+            // The endpoint's own settings follow it to the new URL
+            if let Some(tier) = updated.tiers.remove(from) {
+                updated.tiers.insert(to.to_string(), tier);
+            }
+            if let Some(windows) = updated.maintenance_windows.remove(from) {
+                updated.maintenance_windows.insert(to.to_string(), windows);
+            }
+            if let Some(extra) = headers.remove(from) {
+                headers.insert(to.to_string(), extra);
+            }
+            if let Some(profile) = updated.cost_profiles.remove(from) {
+                updated.cost_profiles.insert(to.to_string(), profile);
+            }
+            for rpc in updated.injected_rpcs.iter_mut().filter(|rpc| rpc.url.as_str() == from) {
+                rpc.url = to.clone();
+            }
+            self.move_endpoint(from, to.clone()).await;
+        }
+        updated.headers = Arc::new(headers);
+        let rotated = moves.iter().map(|(_, to)| updated.redactor.redact(to.as_str())).collect();
+        self.set_config(updated);
+        self.reload_provider_options().await;
+
+        let active = self.get_provider_url().await.ok();
+        if let Some((_, to)) = moves.iter().find(|(from, _)| Some(from) == active.as_ref()) {
+            self.install_provider(to.to_string()).await?;
+        }
+        Ok(rotated)
+    }
+}
+
+/// Rotate the secrets of each endpoint reported failing authentication, until `shutdown` or
+/// the handler is dropped.
+pub(crate) fn spawn_rotation_watch(handler: &Arc<RpcHandler>, mut failures: UnboundedReceiver<String>, heartbeat: Heartbeat, shutdown: CancellationToken) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+
+    runtime::spawn(async move {
+        loop {
+            heartbeat.beat();
+            let url = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                url = failures.recv() => match url {
+                    Some(url) => url,
+                    None => return,
+                },
+            };
+            let Some(handler) = weak.upgrade() else { return };
+            handler.rotate_after_auth_failure(url).await;
+        }
+    })
+}
@@ -0,0 +1,22 @@
+use crate::RouteRule;
+
+impl RouteRule {
+    pub fn matches(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m == method)
+    }
+
+    /// The designated URLs in the same form the handler uses for its own keys.
+    pub fn normalized_urls(&self) -> Vec<String> {
+        self.urls.iter().map(|url| normalize_url(url)).collect()
+    }
+}
+
+/// The first rule that claims `method`, if any.
+pub fn route_for<'a>(rules: &'a [RouteRule], method: &str) -> Option<&'a RouteRule> {
+    rules.iter().find(|rule| rule.matches(method))
+}
+
+/// Parse and re-serialize a URL so `http://host:8545` and `http://host:8545/` compare equal.
+pub fn normalize_url(url: &str) -> String {
+    url::Url::parse(url).map(|parsed| parsed.to_string()).unwrap_or_else(|_| url.to_string())
+}
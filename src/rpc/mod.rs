@@ -1,3 +1,5 @@
 pub mod select_base_rpc_set;
+pub mod source;
 
-pub use select_base_rpc_set::select_base_rpc_set;
+pub use select_base_rpc_set::{select_aliased_rpc_set, select_base_rpc_set, select_tracked_rpc_set, TrackedRpc};
+pub use source::{RpcOrigin, RpcSource};
@@ -1,27 +1,62 @@
-use crate::{chainlist, NetworkId, Rpc, Tracking};
+use crate::{rpc::{RpcOrigin, RpcSource}, Egress, NetworkId, Rpc, Tracking};
 
-pub fn select_base_rpc_set(network_id: NetworkId, tracking: Tracking, injected_rpcs: Vec<Rpc>) -> Vec<Rpc> {
-    let mut rpcs = injected_rpcs;
-    
-    // Add RPCs from chainlist based on tracking preference
-    let chainlist_rpcs = chainlist::get_extra_rpcs(network_id);
-    
-    for rpc in chainlist_rpcs {
-        // Filter based on tracking preference
-        let should_include = match tracking {
-            Tracking::Yes => true,
-            Tracking::Limited => {
-                rpc.tracking.as_ref().map_or(true, |t| matches!(t, Tracking::Limited | Tracking::None))
-            }
-            Tracking::None => {
-                rpc.tracking.as_ref().map_or(false, |t| matches!(t, Tracking::None))
+/// An endpoint and how it came to be in the set.
+#[derive(Debug, Clone)]
+pub struct TrackedRpc {
+    pub rpc: Rpc,
+    pub origin: RpcOrigin,
+    /// The alias of the handler's network the source listed it under, `None` when listed under
+    /// the network's own id or configured
+    pub listed_under: Option<NetworkId>,
+}
+
+pub fn select_base_rpc_set(source: &dyn RpcSource, network_id: NetworkId, tracking: Tracking, injected_rpcs: Vec<Rpc>) -> Vec<Rpc> {
+    select_tracked_rpc_set(source, network_id, tracking, injected_rpcs).into_iter().map(|tracked| tracked.rpc).collect()
+}
+
+/// Like `select_base_rpc_set`, with the injected endpoints marked `Injected` and the rest with
+/// the source's origin.
+pub fn select_tracked_rpc_set(source: &dyn RpcSource, network_id: NetworkId, tracking: Tracking, injected_rpcs: Vec<Rpc>) -> Vec<TrackedRpc> {
+    select_aliased_rpc_set(source, network_id, &[], tracking, injected_rpcs, Egress::Open)
+}
+
+/// Like `select_tracked_rpc_set`, merging in the source's endpoints for each of `aliases`,
+/// tagged with the alias they were listed under. A URL listed under several ids is kept once.
+/// Under `Egress::InjectedOnly` the source isn't consulted, and only the injected endpoints are.
+pub fn select_aliased_rpc_set(
+    source: &dyn RpcSource,
+    network_id: NetworkId,
+    aliases: &[NetworkId],
+    tracking: Tracking,
+    injected_rpcs: Vec<Rpc>,
+    egress: Egress,
+) -> Vec<TrackedRpc> {
+    let mut rpcs: Vec<TrackedRpc> = injected_rpcs.into_iter().map(|rpc| TrackedRpc { rpc, origin: RpcOrigin::Injected, listed_under: None }).collect();
+    if egress == Egress::InjectedOnly {
+        return rpcs;
+    }
+    let origin = source.origin();
+
+    let listings = std::iter::once((network_id, None)).chain(aliases.iter().filter(|alias| **alias != network_id).map(|alias| (*alias, Some(*alias))));
+    for (id, listed_under) in listings {
+        // Add RPCs from the source based on tracking preference
+        for rpc in source.rpcs(id) {
+            // Filter based on tracking preference
+            let should_include = match tracking {
+                Tracking::Yes => true,
+                Tracking::Limited => {
+                    rpc.tracking.as_ref().is_none_or(|t| matches!(t, Tracking::Limited | Tracking::None))
+                }
+                Tracking::None => {
+                    rpc.tracking.as_ref().is_some_and(|t| matches!(t, Tracking::None))
+                }
+            };
+
+            if should_include && (listed_under.is_none() || !rpcs.iter().any(|tracked| tracked.rpc.url == rpc.url)) {
+                rpcs.push(TrackedRpc { rpc, origin, listed_under });
             }
-        };
-        
-        if should_include {
-            rpcs.push(rpc);
         }
     }
-    
+
     rpcs
 }
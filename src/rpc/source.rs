@@ -0,0 +1,43 @@
+//! Where the endpoints beyond the configured ones come from.
+//!
+//! By default that's the chainlist data in the handler's `DataScope`, which is empty without the
+//! `chainlist` feature. `HandlerComponents::rpc_source` swaps in another source.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chainlist::ChainView, NetworkId, Rpc};
+
+/// How an endpoint came to be in a handler's set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcOrigin {
+    /// From the chainlist data
+    Chainlist,
+    /// Configured in `HandlerConfig::network_rpcs`
+    Injected,
+    /// Added with `RpcHandler::add_rpc`
+    RuntimeAdded,
+    /// From an `RpcSource` passed in `HandlerComponents::rpc_source`
+    Registered,
+}
+
+/// Public endpoints to add to a handler's configured ones.
+pub trait RpcSource: Send + Sync {
+    /// Endpoints for `network_id`, before the tracking filter is applied.
+    fn rpcs(&self, network_id: NetworkId) -> Vec<Rpc>;
+
+    /// The origin the endpoints from this source are reported with.
+    fn origin(&self) -> RpcOrigin {
+        RpcOrigin::Registered
+    }
+}
+
+impl RpcSource for ChainView {
+    fn rpcs(&self, network_id: NetworkId) -> Vec<Rpc> {
+        self.extra_rpcs(network_id)
+    }
+
+    fn origin(&self) -> RpcOrigin {
+        RpcOrigin::Chainlist
+    }
+}
@@ -1,13 +1,47 @@
-use std::time::{Duration, Instant};
+//! The original endpoint tester, kept for code that still calls it.
+//!
+//! It probes through the same transports as `performance::measure_rpcs`, so there is one probing
+//! implementation. By default it keeps its old rules: a single `eth_blockNumber`, any success
+//! status counts as up whatever the body says, and every failure is reported as
+//! `RpcHandlerError::Timeout`. `with_granular_errors(true)` reports what actually went wrong
+//! instead. `ProbeMode::Measured` runs the handler's probes, with the block, Permit2 bytecode and
+//! head sync checks, and always reports granular errors.
+//!
+//! # Deprecated
+//!
+//! New code should call `performance::measure_rpcs_with_transport`, or let a handler probe its
+//! endpoints with `init` and `refresh`.
+
+use std::{sync::Arc, time::Duration};
 
 use futures::future::join_all;
-use tokio::time::timeout;
+use web_time::Instant;
+
+use crate::{
+    clock::{system_clock, Clock},
+    performance::{measure_rpcs_with_transport, MeasureOptions, RpcCheckResult, TimeoutPolicy},
+    runtime::timeout,
+    transport::{HttpTransportFactory, TransportFactory},
+    JsonRpcRequest, LatencyRecord, Result, Rpc, RpcHandlerError,
+};
 
-use crate::{JsonRpcRequest, LatencyRecord, Result, Rpc, RpcHandlerError};
+/// Which rules `RpcTestingService` probes by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProbeMode {
+    /// The service's original rules: one `eth_blockNumber`, up on any success status, even
+    /// with a JSON-RPC error or no JSON at all
+    #[default]
+    Legacy,
+    /// The handler's probes. An endpoint that answers but fails a check is `ProbeFailed`
+    Measured,
+}
 
 pub struct RpcTestingService {
     timeout_duration: Duration,
     pub client: reqwest::Client,
+    clock: Arc<dyn Clock>,
+    mode: ProbeMode,
+    granular_errors: bool,
 }
 
 impl RpcTestingService {
@@ -15,47 +49,116 @@ impl RpcTestingService {
         Self {
             timeout_duration: Duration::from_millis(timeout_ms),
             client: reqwest::Client::new(),
+            clock: system_clock(),
+            mode: ProbeMode::default(),
+            granular_errors: false,
         }
     }
 
+    /// Use `clock` for the `last_tested` timestamps.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Probe by `mode`'s rules instead of the legacy ones.
+    pub fn with_mode(mut self, mode: ProbeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Report legacy probe failures by what went wrong, such as `HttpStatus`, `RequestTimeout` or
+    /// `ConnectFailure`, rather than all as `Timeout` with the configured duration.
+    pub fn with_granular_errors(mut self, granular: bool) -> Self {
+        self.granular_errors = granular;
+        self
+    }
+
     pub async fn test_rpc_latency(&self, rpc: &Rpc) -> Result<LatencyRecord> {
+        match self.mode {
+            ProbeMode::Legacy => self.legacy_probe(rpc).await,
+            ProbeMode::Measured => self.measured(std::slice::from_ref(rpc)).await.remove(0).1,
+        }
+    }
+
+    pub async fn race_rpcs(&self, rpcs: &[Rpc]) -> Vec<(usize, Result<LatencyRecord>)> {
+        match self.mode {
+            ProbeMode::Legacy => join_all(rpcs.iter().enumerate().map(|(idx, rpc)| async move { (idx, self.legacy_probe(rpc).await) })).await,
+            ProbeMode::Measured => self.measured(rpcs).await,
+        }
+    }
+
+    fn transports(&self) -> HttpTransportFactory {
+        HttpTransportFactory::with_client(self.client.clone(), self.timeout_duration)
+    }
+
+    fn record(&self, latency_ms: u64) -> LatencyRecord {
+        LatencyRecord { latency_ms, last_tested: self.clock.now_system(), failure_count: 0 }
+    }
+
+    async fn legacy_probe(&self, rpc: &Rpc) -> Result<LatencyRecord> {
+        match self.legacy_exchange(rpc).await {
+            Err(_) if !self.granular_errors => Err(RpcHandlerError::Timeout { duration_ms: self.timeout_duration.as_millis() as u64 }),
+            outcome => outcome,
+        }
+    }
+
+    async fn legacy_exchange(&self, rpc: &Rpc) -> Result<LatencyRecord> {
+        let test_req = JsonRpcRequest::new("eth_blockNumber", serde_json::json!([]));
+
+        let url = rpc.url.as_str();
+        let transport = self.transports().transport(url);
         let start = Instant::now();
+        let exchange = timeout(self.timeout_duration, transport.exchange(&test_req))
+            .await
+            .map_err(|_| RpcHandlerError::request_timeout(url, self.timeout_duration))?;
 
-        let test_req = JsonRpcRequest {
-            id: Some(1),
-            jsonrpc: "2.0".to_string(),
-            method: "eth_blockNumber".to_string(),
-            params: serde_json::Value::Array(vec![]),
-        };
-
-        let response = timeout(
-            self.timeout_duration,
-            self.client.post(rpc.url.clone()).json(&test_req).send(),
-        )
-        .await;
-
-        match response {
-            Ok(Ok(resp)) if resp.status().is_success() => {
-                let latency = start.elapsed().as_millis() as u64;
-                Ok(LatencyRecord {
-                    latency_ms: latency,
-                    last_tested: std::time::SystemTime::now(),
-                    failure_count: 0,
-                })
-            }
-            _ => Err(RpcHandlerError::Timeout {
-                duration_ms: self.timeout_duration.as_millis() as u64,
-            }),
+        match exchange.body {
+            // The legacy probe never read the body, so neither a JSON-RPC error nor a body that
+            // isn't JSON-RPC made the endpoint down
+            Ok(_) | Err(RpcHandlerError::BodyDecode { .. }) => {}
+            Err(RpcHandlerError::NotAJsonRpcEndpoint { status, .. }) if (200..300).contains(&status) => {}
+            // A redirect the client didn't follow was reported by its status
+            Err(RpcHandlerError::NotAJsonRpcEndpoint { url, status, .. }) => return Err(RpcHandlerError::HttpStatus { url, status }),
+            Err(e) => return Err(e),
         }
+        Ok(self.record(start.elapsed().as_millis() as u64))
     }
 
-    pub async fn race_rpcs(&self, rpcs: &[Rpc]) -> Vec<(usize, Result<LatencyRecord>)> {
-        let futures: Vec<_> = rpcs
-            .iter()
+    async fn measured(&self, rpcs: &[Rpc]) -> Vec<(usize, Result<LatencyRecord>)> {
+        let options = MeasureOptions::new(TimeoutPolicy::Fixed(self.timeout_duration));
+        let (latencies, results) = measure_rpcs_with_transport(&self.transports(), rpcs, &options).await.unwrap_or_default();
+
+        rpcs.iter()
             .enumerate()
-            .map(|(idx, rpc)| async move { (idx, self.test_rpc_latency(rpc).await) })
-            .collect();
+            .map(|(idx, rpc)| {
+                let outcome = match results.get(idx) {
+                    Some(result) => match latencies.get(&result.url) {
+                        Some(latency) => Ok(self.record(*latency)),
+                        None => Err(self.measured_failure(result)),
+                    },
+                    None => Err(RpcHandlerError::request_timeout(rpc.url.as_str(), self.timeout_duration)),
+                };
+                (idx, outcome)
+            })
+            .collect()
+    }
 
-        join_all(futures).await
+    /// Why `result` didn't make it into the latency map.
+    fn measured_failure(&self, result: &RpcCheckResult) -> RpcHandlerError {
+        let url = result.url.clone();
+        let failed = |reason: &str| RpcHandlerError::ProbeFailed { url: url.clone(), reason: reason.to_string() };
+        if let Some(non_json_rpc) = &result.non_json_rpc {
+            return non_json_rpc.clone().into_error(&url);
+        }
+        if result.duration >= self.timeout_duration.as_millis() as u64 {
+            return RpcHandlerError::request_timeout(&url, self.timeout_duration);
+        }
+        match (result.success, &result.block_number) {
+            (true, _) => failed("out of sync with the most common head"),
+            (false, None) => failed("no block in answer to eth_getBlockByNumber"),
+            (false, Some(_)) if !result.bytecode_ok => failed("Permit2 bytecode missing"),
+            (false, Some(_)) => failed("the block or bytecode probe was refused"),
+        }
     }
 }
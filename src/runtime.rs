@@ -0,0 +1,327 @@
+//! The task and timer primitives background work and request timeouts are built on.
+//!
+//! Natively these are tokio's. On `wasm32` there is no tokio runtime: tasks run on the page's
+//! event loop through `wasm_bindgen_futures::spawn_local`, timers are `setTimeout`s, and
+//! `spawn_blocking` runs its closure in place since there is only the one thread. Every type
+//! keeps the tokio API the crate uses, so callers don't tell the two apart.
+//!
+//! The browser's fetch futures aren't `Send`. The page is single-threaded, so `SendResponse`
+//! and the sleeps here wrap them in `SendWrapper` to keep the crate's `Send` bounds as they are.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::{
+    task::{spawn, spawn_blocking, AbortHandle, JoinError, JoinHandle},
+    time::{sleep, timeout},
+};
+#[cfg(all(feature = "test-util", not(target_arch = "wasm32")))]
+pub(crate) use tokio::task::yield_now;
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) use wasm::{spawn, spawn_blocking, sleep, timeout, AbortHandle, JoinError, JoinHandle};
+#[cfg(all(feature = "test-util", target_arch = "wasm32"))]
+pub(crate) use wasm::yield_now;
+#[cfg(target_arch = "wasm32")]
+pub use wasm::SendResponse;
+
+/// Whether there is a runtime to spawn on: code run from a `Drop` may be outside any natively.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn available() -> bool {
+    tokio::runtime::Handle::try_current().is_ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn available() -> bool {
+    true
+}
+
+/// The runtime the caller is on and its worker count, for diagnostics.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn flavor() -> Option<(String, usize)> {
+    let runtime = tokio::runtime::Handle::try_current().ok()?;
+    let flavor = match runtime.runtime_flavor() {
+        tokio::runtime::RuntimeFlavor::CurrentThread => "current_thread".to_string(),
+        tokio::runtime::RuntimeFlavor::MultiThread => "multi_thread".to_string(),
+        other => format!("{other:?}"),
+    };
+    Some((flavor, runtime.metrics().num_workers()))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn flavor() -> Option<(String, usize)> {
+    Some(("wasm_event_loop".to_string(), 1))
+}
+
+/// Fails with `ErrorKind::Unsupported` on `wasm32`, where there is no filesystem for `what` to
+/// use, rather than with whatever std's stubs report once a file is touched.
+pub(crate) fn require_filesystem(what: &str) -> std::io::Result<()> {
+    if cfg!(target_arch = "wasm32") {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("{what} needs a filesystem, which a browser doesn't have")));
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use std::{
+        any::Any,
+        fmt,
+        future::Future,
+        panic::AssertUnwindSafe,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use futures::{
+        channel::oneshot,
+        future::{self, Either},
+        FutureExt, Stream, StreamExt,
+    };
+    use reqwest::{header::HeaderMap, StatusCode};
+    use send_wrapper::SendWrapper;
+    use serde::de::DeserializeOwned;
+
+    /// `setTimeout` takes a signed 32-bit delay and fires at once on anything longer.
+    const MAX_TIMER_MS: u64 = i32::MAX as u64;
+
+    /// A task spawned on the page's event loop, awaited for its output like tokio's.
+    pub(crate) struct JoinHandle<T> {
+        output: oneshot::Receiver<std::thread::Result<T>>,
+        abort: AbortHandle,
+    }
+
+    impl<T> JoinHandle<T> {
+        pub(crate) fn abort(&self) {
+            self.abort.abort();
+        }
+
+        pub(crate) fn is_finished(&self) -> bool {
+            self.abort.is_finished()
+        }
+
+        pub(crate) fn abort_handle(&self) -> AbortHandle {
+            self.abort.clone()
+        }
+    }
+
+    impl<T> fmt::Debug for JoinHandle<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("JoinHandle").field("finished", &self.is_finished()).finish()
+        }
+    }
+
+    impl<T> Future for JoinHandle<T> {
+        type Output = Result<T, JoinError>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            self.output.poll_unpin(cx).map(|output| match output {
+                Ok(Ok(value)) => Ok(value),
+                Ok(Err(payload)) => Err(JoinError { panic: Some(Mutex::new(payload)) }),
+                Err(oneshot::Canceled) => Err(JoinError { panic: None }),
+            })
+        }
+    }
+
+    /// Cancels a spawned task without owning its output.
+    #[derive(Debug, Clone)]
+    pub(crate) struct AbortHandle {
+        handle: future::AbortHandle,
+        finished: Arc<AtomicBool>,
+    }
+
+    impl AbortHandle {
+        pub(crate) fn abort(&self) {
+            self.handle.abort();
+        }
+
+        pub(crate) fn is_finished(&self) -> bool {
+            self.finished.load(Ordering::Acquire) || self.handle.is_aborted()
+        }
+    }
+
+    /// Why a task ended without output: it panicked, or it was aborted.
+    pub(crate) struct JoinError {
+        panic: Option<Mutex<Box<dyn Any + Send>>>,
+    }
+
+    impl JoinError {
+        pub(crate) fn try_into_panic(self) -> Result<Box<dyn Any + Send>, JoinError> {
+            match self.panic {
+                Some(payload) => Ok(payload.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())),
+                None => Err(self),
+            }
+        }
+    }
+
+    impl fmt::Debug for JoinError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Display::fmt(self, f)
+        }
+    }
+
+    impl fmt::Display for JoinError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(if self.panic.is_some() { "task panicked" } else { "task was cancelled" })
+        }
+    }
+
+    impl std::error::Error for JoinError {}
+
+    pub(crate) fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (sender, output) = oneshot::channel();
+        let (task, handle) = future::abortable(AssertUnwindSafe(future).catch_unwind());
+        let finished = Arc::new(AtomicBool::new(false));
+        let done = Arc::clone(&finished);
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Ok(result) = task.await {
+                let _ = sender.send(result);
+            }
+            done.store(true, Ordering::Release);
+        });
+        JoinHandle { output, abort: AbortHandle { handle, finished } }
+    }
+
+    /// Runs `work` right away: there is no other thread to hand it to.
+    pub(crate) fn spawn_blocking<F, R>(work: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + 'static,
+        R: 'static,
+    {
+        spawn(future::ready(()).map(move |()| work()))
+    }
+
+    pub(crate) async fn sleep(duration: Duration) {
+        let mut remaining = duration.as_millis();
+        loop {
+            let step = remaining.min(MAX_TIMER_MS as u128);
+            SendWrapper::new(gloo_timers::future::TimeoutFuture::new(step as u32)).await;
+            remaining -= step;
+            if remaining == 0 {
+                break;
+            }
+        }
+    }
+
+    /// `future` gave up on after `duration`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct Elapsed;
+
+    impl fmt::Display for Elapsed {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("deadline has elapsed")
+        }
+    }
+
+    impl std::error::Error for Elapsed {}
+
+    pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+        let (future, timer) = (std::pin::pin!(future), std::pin::pin!(sleep(duration)));
+        match future::select(future, timer).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(((), _)) => Err(Elapsed),
+        }
+    }
+
+    #[cfg(feature = "test-util")]
+    pub(crate) async fn yield_now() {
+        let mut yielded = false;
+        future::poll_fn(|cx| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await;
+    }
+
+    type BodyStream = Pin<Box<dyn Stream<Item = reqwest::Result<bytes::Bytes>>>>;
+
+    /// A fetch response that can be held across `.await`s in `Send` futures, with the methods of
+    /// `reqwest::Response` the crate reads bodies through.
+    pub struct SendResponse {
+        status: StatusCode,
+        headers: HeaderMap,
+        url: url::Url,
+        response: Option<SendWrapper<reqwest::Response>>,
+        stream: Option<SendWrapper<BodyStream>>,
+    }
+
+    impl fmt::Debug for SendResponse {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("SendResponse").field("status", &self.status).field("url", &self.url.as_str()).finish()
+        }
+    }
+
+    impl From<reqwest::Response> for SendResponse {
+        fn from(response: reqwest::Response) -> Self {
+            Self {
+                status: response.status(),
+                headers: response.headers().clone(),
+                url: response.url().clone(),
+                response: Some(SendWrapper::new(response)),
+                stream: None,
+            }
+        }
+    }
+
+    impl SendResponse {
+        pub fn status(&self) -> StatusCode {
+            self.status
+        }
+
+        pub fn headers(&self) -> &HeaderMap {
+            &self.headers
+        }
+
+        pub fn url(&self) -> &url::Url {
+            &self.url
+        }
+
+        /// Always `None`: the browser doesn't say which address it connected to.
+        pub fn remote_addr(&self) -> Option<std::net::SocketAddr> {
+            None
+        }
+
+        pub fn error_for_status(self) -> reqwest::Result<Self> {
+            match self.response.as_deref().map(reqwest::Response::error_for_status_ref) {
+                Some(Err(e)) => Err(e),
+                _ => Ok(self),
+            }
+        }
+
+        pub async fn json<T: DeserializeOwned>(self) -> reqwest::Result<T> {
+            SendWrapper::new(self.into_inner().json::<T>()).await
+        }
+
+        pub async fn text(self) -> reqwest::Result<String> {
+            SendWrapper::new(self.into_inner().text()).await
+        }
+
+        pub async fn bytes(self) -> reqwest::Result<bytes::Bytes> {
+            SendWrapper::new(self.into_inner().bytes()).await
+        }
+
+        /// The next piece of the body as the browser hands it over, `None` at its end.
+        pub async fn chunk(&mut self) -> reqwest::Result<Option<bytes::Bytes>> {
+            if let Some(response) = self.response.take() {
+                self.stream = Some(SendWrapper::new(Box::pin(response.take().bytes_stream())));
+            }
+            let Some(stream) = self.stream.as_mut() else { return Ok(None) };
+            SendWrapper::new(stream.next()).await.transpose()
+        }
+
+        fn into_inner(mut self) -> reqwest::Response {
+            self.response.take().expect("the body is read once").take()
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Scripted fake endpoints in this process, for watching the handler meet failures without a
+//! network. Needs the `scenarios` feature.
+//!
+//! Each endpoint of a `Scenario` follows a `Script`: a behavior to start with, such as answering
+//! like a healthy node, and later ones taking over after so many requests or so much time. The
+//! endpoints answer like nodes of one made-up chain whose head the scenario moves with `advance`.
+//! They serve HTTP on loopback addresses, so a handler reaches them as it would any endpoint, and
+//! `transports` reaches them without HTTP for the code that takes a `TransportFactory`. A
+//! `Narrator` tells what the handler made of them.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use ez_web3_rpc::{scenario::{Scenario, Script}, RpcHandler};
+//!
+//! let mut scenario = Scenario::new();
+//! scenario.endpoint("primary", Script::dies_after(5)).await?;
+//! scenario.endpoint("backup", Script::healthy()).await?;
+//! let handler = RpcHandler::new(scenario.config(), None).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod narrate;
+mod script;
+mod server;
+
+use std::{
+    io,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, task::AbortHandle};
+use web_time::Instant;
+
+pub use narrate::Narrator;
+pub use script::{block_hash, reply, Behavior, ChainView, Reply, Script, Seen, Step, Trigger, PERMIT2_CODE};
+
+use crate::{
+    DataScope, HandlerConfig, HandlerSettings, JsonRpcRequest, JsonRpcResponse, JsonRpcTransport, Result, Rpc, RpcConfig, RpcHandlerError,
+    TransportFactory,
+};
+
+/// The network id scenarios run on, which has no chainlist endpoints.
+pub const SCENARIO_NETWORK_ID: u64 = 424242;
+
+/// The head a scenario's chain starts at.
+pub const DEFAULT_HEAD: u64 = 0x100;
+
+/// A request a scripted endpoint was sent, and the behavior that answered it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Served {
+    /// Since the scenario started
+    pub at: Duration,
+    pub endpoint: String,
+    pub method: String,
+    pub behavior: Behavior,
+}
+
+/// What every endpoint of a scenario shares.
+struct Shared {
+    started: Instant,
+    head: AtomicU64,
+    timeline: Mutex<Vec<Served>>,
+}
+
+impl Shared {
+    fn view(&self) -> ChainView {
+        ChainView { chain_id: SCENARIO_NETWORK_ID, head: self.head.load(Ordering::Relaxed) }
+    }
+}
+
+struct EndpointState {
+    name: String,
+    url: String,
+    script: Script,
+    seen: Mutex<Seen>,
+    shared: Arc<Shared>,
+}
+
+impl EndpointState {
+    /// The reply to a request body, single or batch, and how long to wait before sending it.
+    /// A batch is answered as a whole, by the first of its requests that isn't answered with JSON.
+    fn answer(&self, body: &[u8]) -> (Reply, Duration) {
+        let Ok(request) = serde_json::from_slice::<Value>(body) else {
+            return (Reply::Json(json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": "parse error" } })), Duration::ZERO);
+        };
+        let Value::Array(requests) = request else {
+            return self.answer_one(&request);
+        };
+        let (mut answers, mut slowest) = (Vec::with_capacity(requests.len()), Duration::ZERO);
+        for request in &requests {
+            let (reply, delay) = self.answer_one(request);
+            slowest = slowest.max(delay);
+            match reply {
+                Reply::Json(answer) => answers.push(answer),
+                other => return (other, slowest),
+            }
+        }
+        (Reply::Json(Value::Array(answers)), slowest)
+    }
+
+    fn answer_one(&self, request: &Value) -> (Reply, Duration) {
+        let method = request.get("method").and_then(Value::as_str).unwrap_or_default();
+        let at = self.shared.started.elapsed();
+        let (behavior, delay) = {
+            let mut seen = self.seen.lock();
+            let (behavior, delay) = self.script.behavior(method, &seen, at);
+            let behavior = behavior.clone();
+            seen.record(method);
+            (behavior, delay)
+        };
+        let reply = reply(&behavior, request, self.shared.view());
+        self.shared.timeline.lock().push(Served { at, endpoint: self.name.clone(), method: method.to_string(), behavior });
+        (reply, delay)
+    }
+}
+
+/// One scripted endpoint, to point a handler at and ask what it was sent.
+#[derive(Clone)]
+pub struct ScenarioEndpoint {
+    state: Arc<EndpointState>,
+}
+
+impl ScenarioEndpoint {
+    pub fn name(&self) -> &str {
+        &self.state.name
+    }
+
+    /// The endpoint's URL, as the handler keys it in latency maps and provider URLs.
+    pub fn url(&self) -> &str {
+        &self.state.url
+    }
+
+    /// The endpoint as an open-source `Rpc`.
+    pub fn rpc(&self) -> Rpc {
+        Rpc {
+            url: self.state.url.parse().expect("loopback URLs parse"),
+            tracking: None,
+            tracking_details: None,
+            is_open_source: Some(true),
+        }
+    }
+
+    /// Requests sent to it, a batch counting each of its requests.
+    pub fn requests(&self) -> u64 {
+        self.state.seen.lock().requests
+    }
+
+    pub fn requests_for(&self, method: &str) -> u64 {
+        self.state.seen.lock().by_method.get(method).copied().unwrap_or(0)
+    }
+}
+
+/// Scripted endpoints answering about one chain. Dropping it stops them.
+pub struct Scenario {
+    shared: Arc<Shared>,
+    endpoints: Vec<ScenarioEndpoint>,
+    servers: Vec<AbortHandle>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scenario {
+    /// A scenario whose chain is at `DEFAULT_HEAD`, its clock starting now.
+    pub fn new() -> Self {
+        Self::at_head(DEFAULT_HEAD)
+    }
+
+    pub fn at_head(head: u64) -> Self {
+        Self {
+            shared: Arc::new(Shared { started: Instant::now(), head: AtomicU64::new(head), timeline: Mutex::new(Vec::new()) }),
+            endpoints: Vec::new(),
+            servers: Vec::new(),
+        }
+    }
+
+    /// Serve an endpoint following `script` on a loopback port. Must be called in a tokio runtime.
+    ///
+    /// Each endpoint gets its own loopback address, `127.0.0.2` for the second and so on, so
+    /// per-host limits and cooldowns tell them apart as they would real providers. Where only
+    /// `127.0.0.1` is configured, as on macOS, they share it.
+    pub async fn endpoint(&mut self, name: impl Into<String>, script: Script) -> io::Result<ScenarioEndpoint> {
+        let own = Ipv4Addr::new(127, 0, 0, (self.endpoints.len() % 254 + 1) as u8);
+        let listener = match TcpListener::bind((own, 0)).await {
+            Ok(listener) => listener,
+            Err(_) => TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?,
+        };
+        let url = format!("http://{}/", listener.local_addr()?);
+        let state = Arc::new(EndpointState { name: name.into(), url, script, seen: Mutex::new(Seen::default()), shared: Arc::clone(&self.shared) });
+        self.servers.push(tokio::spawn(server::serve(listener, Arc::clone(&state))).abort_handle());
+        let endpoint = ScenarioEndpoint { state };
+        self.endpoints.push(endpoint.clone());
+        Ok(endpoint)
+    }
+
+    pub fn endpoints(&self) -> &[ScenarioEndpoint] {
+        &self.endpoints
+    }
+
+    /// The name of the endpoint at `url`.
+    pub fn name_of(&self, url: &str) -> Option<&str> {
+        self.endpoints.iter().find(|endpoint| endpoint.url() == url).map(ScenarioEndpoint::name)
+    }
+
+    pub fn head(&self) -> u64 {
+        self.shared.head.load(Ordering::Relaxed)
+    }
+
+    /// Move the chain's head on by `blocks`, for every endpoint at once.
+    pub fn advance(&self, blocks: u64) {
+        self.shared.head.fetch_add(blocks, Ordering::Relaxed);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.shared.started.elapsed()
+    }
+
+    /// Every request the endpoints were sent so far, in the order they arrived.
+    pub fn timeline(&self) -> Vec<Served> {
+        self.shared.timeline.lock().clone()
+    }
+
+    /// Settings serving only the scenario's endpoints, in the order they were added, with a
+    /// quick retry and probe timeout.
+    pub fn settings(&self) -> HandlerSettings {
+        HandlerSettings {
+            network_rpcs: self.endpoints.iter().map(|endpoint| RpcConfig::from(endpoint.rpc())).collect(),
+            network_name: "scenario".to_string(),
+            data_scope: DataScope::OnlyThisNetwork,
+            rpc_probe_timeout_ms: 2000,
+            ..HandlerSettings::default()
+        }
+    }
+
+    /// A config on `SCENARIO_NETWORK_ID` with `settings`.
+    pub fn config(&self) -> HandlerConfig {
+        self.config_with(self.settings())
+    }
+
+    pub fn config_with(&self, settings: HandlerSettings) -> HandlerConfig {
+        HandlerConfig { network_id: SCENARIO_NETWORK_ID, settings: Some(settings) }
+    }
+
+    /// Transports reaching the endpoints without HTTP, for `fanout` and
+    /// `measure_rpcs_with_transport`. A URL that isn't one of them fails to connect.
+    pub fn transports(&self) -> ScenarioTransports {
+        ScenarioTransports { endpoints: self.endpoints.clone() }
+    }
+}
+
+impl Drop for Scenario {
+    fn drop(&mut self) {
+        for server in &self.servers {
+            server.abort();
+        }
+    }
+}
+
+/// Hands out transports to a scenario's endpoints that skip HTTP.
+#[derive(Clone)]
+pub struct ScenarioTransports {
+    endpoints: Vec<ScenarioEndpoint>,
+}
+
+impl TransportFactory for ScenarioTransports {
+    fn transport(&self, url: &str) -> Arc<dyn JsonRpcTransport> {
+        let state = self.endpoints.iter().find(|endpoint| endpoint.url() == url).map(|endpoint| Arc::clone(&endpoint.state));
+        Arc::new(ScriptedTransport { url: url.to_string(), state })
+    }
+
+    fn supports(&self, url: &str) -> bool {
+        self.endpoints.iter().any(|endpoint| endpoint.url() == url)
+    }
+}
+
+struct ScriptedTransport {
+    url: String,
+    state: Option<Arc<EndpointState>>,
+}
+
+#[async_trait]
+impl JsonRpcTransport for ScriptedTransport {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn request(&self, request: &JsonRpcRequest) -> Result<JsonRpcResponse<Value>> {
+        let url = self.url.clone();
+        let Some(state) = &self.state else { return Err(RpcHandlerError::ConnectFailure { url }) };
+        let body = serde_json::to_vec(request).map_err(|e| RpcHandlerError::SerializationError(e.to_string()))?;
+        let (reply, delay) = state.answer(&body);
+        tokio::time::sleep(delay).await;
+        match reply {
+            Reply::Json(answer) => serde_json::from_value(answer).map_err(|e| RpcHandlerError::BodyDecode { url, detail: e.to_string() }),
+            Reply::Status { status, .. } => Err(RpcHandlerError::HttpStatus { url, status }),
+            Reply::NotJson(body) => Err(RpcHandlerError::BodyDecode { url, detail: format!("not JSON: {body}") }),
+            Reply::Hangup => Err(RpcHandlerError::ConnectFailure { url }),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! A running account of what a handler did about a scenario's endpoints, by their names.
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::{sync::broadcast::error::RecvError, task::AbortHandle};
+use web_time::Instant;
+
+#[cfg(feature = "consensus")]
+use crate::consensus::{ConsensusReport, EndpointOutcome};
+use crate::{MetricsSnapshot, RpcHandler};
+
+use super::Scenario;
+
+/// Tells what a handler did about a scenario: its events as they happen, and at each
+/// `checkpoint` the provider switches, failovers, cooldowns and failures since the last one.
+/// URLs are replaced with the endpoints' names throughout.
+pub struct Narrator {
+    handler: Arc<RpcHandler>,
+    /// `(url, name)` of each endpoint
+    names: Vec<(String, String)>,
+    started: Instant,
+    echo: bool,
+    lines: Arc<Mutex<Vec<String>>>,
+    last: Mutex<(Option<String>, MetricsSnapshot)>,
+    events: AbortHandle,
+}
+
+impl Narrator {
+    /// Start narrating `handler`, printing each line to stdout as well when `echo`.
+    pub async fn new(scenario: &Scenario, handler: &Arc<RpcHandler>, echo: bool) -> Self {
+        let names: Vec<(String, String)> = scenario.endpoints().iter().map(|endpoint| (endpoint.url().to_string(), endpoint.name().to_string())).collect();
+        let (started, lines) = (scenario.shared.started, Arc::new(Mutex::new(Vec::new())));
+        let mut events = handler.subscribe();
+        let task = {
+            let (names, lines) = (names.clone(), Arc::clone(&lines));
+            tokio::spawn(async move {
+                loop {
+                    match events.recv().await {
+                        Ok(event) => append(&lines, started, echo, &named(&names, &format!("event: {event:?}"))),
+                        Err(RecvError::Lagged(skipped)) => append(&lines, started, echo, &format!("event: {skipped} skipped")),
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            })
+        };
+        let provider = handler.get_provider_url().await.ok();
+        Self {
+            handler: Arc::clone(handler),
+            names,
+            started,
+            echo,
+            lines,
+            last: Mutex::new((provider, handler.metrics_snapshot())),
+            events: task.abort_handle(),
+        }
+    }
+
+    /// Add a line of narration.
+    pub fn say(&self, line: impl AsRef<str>) {
+        append(&self.lines, self.started, self.echo, &named(&self.names, line.as_ref()));
+    }
+
+    /// Tell what changed since the last checkpoint, or since the narrator started, under `heading`.
+    pub async fn checkpoint(&self, heading: &str) {
+        let provider = self.handler.get_provider_url().await.ok();
+        let metrics = self.handler.metrics_snapshot();
+        let (previous, delta) = {
+            let mut last = self.last.lock();
+            let delta = metrics.diff(&last.1);
+            (std::mem::replace(&mut *last, (provider.clone(), metrics)).0, delta)
+        };
+
+        self.say(heading);
+        let none = || "none".to_string();
+        match provider == previous {
+            true => self.say(format!("  provider: {}", provider.unwrap_or_else(none))),
+            false => self.say(format!("  provider switched: {} -> {}", previous.unwrap_or_else(none), provider.unwrap_or_else(none))),
+        }
+        let totals = &delta.totals;
+        if totals.requests > 0 {
+            self.say(format!("  {} request(s), {} answered, {} after failing over", totals.requests, totals.successes, totals.failovers));
+        }
+        if totals.cooldowns_applied > 0 {
+            self.say(format!("  {} cooldown(s) applied", totals.cooldowns_applied));
+        }
+        for (url, endpoint) in &delta.endpoints {
+            if !endpoint.counts.failures.is_empty() {
+                let failures: Vec<String> = endpoint.counts.failures.iter().map(|(class, count)| format!("{count} {class:?}")).collect();
+                self.say(format!("  {url}: {} attempt(s), failed {}", endpoint.counts.attempts, failures.join(", ")));
+            }
+        }
+    }
+
+    /// Tell how the endpoints voted in a consensus call, and what cooldowns it applied.
+    #[cfg(feature = "consensus")]
+    pub fn consensus(&self, report: &ConsensusReport) {
+        let votes: Vec<String> = report.votes.iter().map(|(key, count)| format!("{key} x{count}")).collect();
+        let quorum = report.quorum.map(|quorum| format!(", {quorum} needed")).unwrap_or_default();
+        self.say(format!("  votes: {}{quorum}", votes.join(", ")));
+        for (url, outcome) in &report.outcomes {
+            let outcome = match outcome {
+                EndpointOutcome::Majority { key } => format!("voted {key} with the majority"),
+                EndpointOutcome::Minority { key, .. } => format!("voted {key}, outvoted"),
+                EndpointOutcome::Failed { error } => format!("failed: {error}"),
+                other => format!("{other:?}"),
+            };
+            self.say(format!("  {url}: {outcome}"));
+        }
+        for cooldown in &report.cooldowns {
+            let cause = if cooldown.rate_limited { ", rate limited" } else { "" };
+            self.say(format!("  {} cooled down for {}ms, strike {}{cause}", cooldown.url, cooldown.delay_ms, cooldown.strikes));
+        }
+        for url in &report.paroled {
+            self.say(format!("  {url} paroled from its cooldown"));
+        }
+    }
+
+    /// Everything narrated so far.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().clone()
+    }
+}
+
+impl Drop for Narrator {
+    fn drop(&mut self) {
+        self.events.abort();
+    }
+}
+
+fn append(lines: &Mutex<Vec<String>>, started: Instant, echo: bool, line: &str) {
+    let line = format!("[{:>7.3}s] {line}", started.elapsed().as_secs_f64());
+    if echo {
+        println!("{line}");
+    }
+    lines.lock().push(line);
+}
+
+/// `text` with each endpoint URL replaced by its name.
+fn named(names: &[(String, String)], text: &str) -> String {
+    names.iter().fold(text.to_string(), |text, (url, name)| text.replace(url.as_str(), name))
+}
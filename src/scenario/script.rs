@@ -0,0 +1,238 @@
+//! What a scripted endpoint does, and when, and the answers that follow from it.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+/// Enough of the Permit2 runtime bytecode for the `eth_getCode` probe to accept it.
+pub const PERMIT2_CODE: &str = "0x6040608081526004908136101561001557600080fd5b600090813560e01c";
+
+/// Seconds between the blocks of a scenario's chain, for their timestamps.
+const BLOCK_TIME_SECS: u64 = 12;
+
+/// How a scripted endpoint answers while a step is in force.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Behavior {
+    /// Answers as a node at the scenario's head
+    Healthy,
+    /// Answers as a node this many blocks behind the head
+    Stale(u64),
+    /// This result to every request
+    Answer(Value),
+    /// This JSON-RPC error to every request
+    RpcError { code: i64, message: String },
+    /// HTTP 429, with a `Retry-After` in seconds when set
+    RateLimited { retry_after_secs: Option<u64> },
+    /// This HTTP status with an empty body
+    Status(u16),
+    /// A 200 whose body isn't JSON
+    Malformed,
+    /// The connection closed without an answer, as from a node that died
+    Down,
+}
+
+impl Behavior {
+    /// Whether the endpoint answers JSON-RPC like a node, so per-method behaviors apply.
+    fn answers(&self) -> bool {
+        matches!(self, Behavior::Healthy | Behavior::Stale(_))
+    }
+}
+
+/// When a step of a script takes over from the one before.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// Once this many requests have reached the endpoint, of any method
+    AfterRequests(u64),
+    /// Once this many requests for the method have reached it
+    AfterMethod(String, u64),
+    /// This long after the scenario started
+    After(Duration),
+}
+
+/// One step of a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// `None` for the first step, in force from the start
+    pub trigger: Option<Trigger>,
+    pub behavior: Behavior,
+    /// How long each answer takes
+    pub delay: Duration,
+}
+
+/// Requests an endpoint had been sent before the current one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Seen {
+    pub requests: u64,
+    pub by_method: BTreeMap<String, u64>,
+}
+
+impl Seen {
+    pub fn record(&mut self, method: &str) {
+        self.requests += 1;
+        *self.by_method.entry(method.to_string()).or_default() += 1;
+    }
+}
+
+/// An endpoint's behavior over a scenario: a first step, then each later one taking over once
+/// its trigger fires. Triggers only ever fire for good, so the step in force is the last one
+/// whose trigger has fired.
+///
+/// ```
+/// # use std::time::Duration;
+/// use ez_web3_rpc::scenario::{Behavior, Script, Trigger};
+///
+/// // Answers for three requests, slowly, then drops every connection
+/// let script = Script::healthy().delay(Duration::from_millis(40)).then(Trigger::AfterRequests(3), Behavior::Down);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    steps: Vec<Step>,
+    /// Behaviors for single methods while the step in force answers like a node
+    methods: BTreeMap<String, Behavior>,
+}
+
+impl Script {
+    pub fn new(behavior: Behavior) -> Self {
+        Self { steps: vec![Step { trigger: None, behavior, delay: Duration::ZERO }], methods: BTreeMap::new() }
+    }
+
+    /// A node at the head throughout.
+    pub fn healthy() -> Self {
+        Self::new(Behavior::Healthy)
+    }
+
+    /// A node at the head that dies once it has been sent `requests` requests.
+    pub fn dies_after(requests: u64) -> Self {
+        Self::healthy().then(Trigger::AfterRequests(requests), Behavior::Down)
+    }
+
+    /// Switch to `behavior` once `trigger` fires. Steps are added in the order they happen.
+    pub fn then(mut self, trigger: Trigger, behavior: Behavior) -> Self {
+        self.steps.push(Step { trigger: Some(trigger), behavior, delay: Duration::ZERO });
+        self
+    }
+
+    /// Answer after `delay` during the step added last.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.delay = delay;
+        }
+        self
+    }
+
+    /// Answer `method` with `behavior` instead, while the step in force is `Healthy` or `Stale`.
+    /// An endpoint that is down or rate limited is so for every method.
+    pub fn method(mut self, method: impl Into<String>, behavior: Behavior) -> Self {
+        self.methods.insert(method.into(), behavior);
+        self
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// The step in force for a request arriving `elapsed` into the scenario after `seen`.
+    pub fn step(&self, seen: &Seen, elapsed: Duration) -> &Step {
+        let fired = |trigger: &Trigger| match trigger {
+            Trigger::AfterRequests(requests) => seen.requests >= *requests,
+            Trigger::AfterMethod(method, requests) => seen.by_method.get(method).copied().unwrap_or(0) >= *requests,
+            Trigger::After(after) => elapsed >= *after,
+        };
+        self.steps.iter().rev().find(|step| step.trigger.as_ref().is_none_or(fired)).unwrap_or(&self.steps[0])
+    }
+
+    /// How a request for `method` is answered, and after how long.
+    pub fn behavior(&self, method: &str, seen: &Seen, elapsed: Duration) -> (&Behavior, Duration) {
+        let step = self.step(seen, elapsed);
+        match self.methods.get(method) {
+            Some(behavior) if step.behavior.answers() => (behavior, step.delay),
+            _ => (&step.behavior, step.delay),
+        }
+    }
+}
+
+/// What the chain looks like to an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainView {
+    pub chain_id: u64,
+    pub head: u64,
+}
+
+/// What an endpoint sends back for a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Json(Value),
+    Status { status: u16, retry_after_secs: Option<u64> },
+    /// A 200 with this body, which isn't JSON
+    NotJson(String),
+    /// The connection closed without an answer
+    Hangup,
+}
+
+/// The reply to the single JSON-RPC `request` from an endpoint behaving as `behavior`.
+pub fn reply(behavior: &Behavior, request: &Value, chain: ChainView) -> Reply {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let error = |code: i64, message: &str| json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } });
+    let head = match behavior {
+        Behavior::Healthy => chain.head,
+        Behavior::Stale(blocks) => chain.head.saturating_sub(*blocks),
+        Behavior::Answer(result) => return Reply::Json(json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+        Behavior::RpcError { code, message } => return Reply::Json(error(*code, message)),
+        Behavior::RateLimited { retry_after_secs } => return Reply::Status { status: 429, retry_after_secs: *retry_after_secs },
+        Behavior::Status(status) => return Reply::Status { status: *status, retry_after_secs: None },
+        Behavior::Malformed => return Reply::NotJson("{\"jsonrpc\":\"2.0\",\"result\":".to_string()),
+        Behavior::Down => return Reply::Hangup,
+    };
+    let method = request.get("method").and_then(Value::as_str).unwrap_or_default();
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    match node_result(method, &params, head, chain.chain_id) {
+        Some(result) => Reply::Json(json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+        None => Reply::Json(error(-32601, &format!("the method {method} does not exist/is not available"))),
+    }
+}
+
+/// What a node whose latest block is `head` answers to `method`, `None` for methods it lacks.
+fn node_result(method: &str, params: &Value, head: u64, chain_id: u64) -> Option<Value> {
+    Some(match method {
+        "eth_chainId" => json!(format!("{chain_id:#x}")),
+        "net_version" => json!(chain_id.to_string()),
+        "eth_blockNumber" => json!(format!("{head:#x}")),
+        "eth_getBlockByNumber" => {
+            let number = match params.get(0).and_then(Value::as_str) {
+                Some(tag) if tag.starts_with("0x") => u64::from_str_radix(&tag[2..], 16).ok()?,
+                Some("earliest") => 0,
+                _ => head,
+            };
+            match number <= head {
+                true => block(number, head),
+                false => Value::Null,
+            }
+        }
+        "eth_getCode" => json!(PERMIT2_CODE),
+        "eth_syncing" => json!(false),
+        "eth_gasPrice" => json!("0x3b9aca00"),
+        "web3_clientVersion" => json!("scenario/v1"),
+        _ => return None,
+    })
+}
+
+/// Block `number` of a chain at `head`, its timestamp as far behind now as the block is behind
+/// the head. Hashes follow from the number, so every endpoint agrees on them.
+fn block(number: u64, head: u64) -> Value {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let timestamp = now.saturating_sub((head - number) * BLOCK_TIME_SECS);
+    json!({
+        "number": format!("{number:#x}"),
+        "hash": block_hash(number),
+        "parentHash": block_hash(number.saturating_sub(1)),
+        "timestamp": format!("{timestamp:#x}"),
+        "transactions": [],
+    })
+}
+
+pub fn block_hash(number: u64) -> String {
+    format!("0x{:064x}", number.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ 0x5ce0_a210)
+}
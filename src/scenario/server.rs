@@ -0,0 +1,72 @@
+//! Just enough HTTP/1.1 for a scripted endpoint: one POST per connection, then close.
+
+use std::{io, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{EndpointState, Reply};
+
+/// Request bodies larger than this are refused.
+const MAX_REQUEST_BYTES: usize = 1 << 20;
+
+pub(super) async fn serve(listener: TcpListener, state: Arc<EndpointState>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let _ = exchange(stream, &state).await;
+        });
+    }
+}
+
+async fn exchange(mut stream: TcpStream, state: &EndpointState) -> io::Result<()> {
+    let Some(body) = read_body(&mut stream).await? else { return Ok(()) };
+    let (reply, delay) = state.answer(&body);
+    tokio::time::sleep(delay).await;
+    let (status, retry_after, body) = match reply {
+        Reply::Json(answer) => (200, None, answer.to_string()),
+        Reply::Status { status, retry_after_secs } => (status, retry_after_secs, String::new()),
+        Reply::NotJson(body) => (200, None, body),
+        Reply::Hangup => return Ok(()),
+    };
+    let reason = reqwest::StatusCode::from_u16(status).ok().and_then(|status| status.canonical_reason()).unwrap_or("Unknown");
+    let retry_after = retry_after.map(|secs| format!("retry-after: {secs}\r\n")).unwrap_or_default();
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n{retry_after}connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// The body of the request on `stream`, `None` if the client went away before sending one whole.
+async fn read_body(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        if let Some(header_end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            let length = content_length(&buffer[..header_end]).unwrap_or(0);
+            let start = header_end + 4;
+            if length > MAX_REQUEST_BYTES {
+                return Ok(None);
+            }
+            if buffer.len() >= start + length {
+                return Ok(Some(buffer[start..start + length].to_vec()));
+            }
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 || buffer.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+fn content_length(headers: &[u8]) -> Option<usize> {
+    String::from_utf8_lossy(headers).lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("content-length").then(|| value.trim().parse().ok()).flatten()
+    })
+}
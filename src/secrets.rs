@@ -0,0 +1,134 @@
+//! Endpoint URL templates with secrets filled in at runtime.
+//!
+//! A config can name an endpoint by `url_template`, e.g.
+//! `https://eth-mainnet.g.alchemy.com/v2/{ALCHEMY_KEY}`, so it can be committed without the key.
+//! `resolve_config` fills each `{PLACEHOLDER}` from a `SecretResolver`, and every value it fills
+//! in is registered with the config's `Redactor`, which puts the placeholder back wherever the
+//! handler logs or reports a URL.
+
+use std::{fmt, sync::Arc};
+
+use serde_json::Value;
+use url::Url;
+
+use crate::{Result, RpcHandlerError};
+
+/// Looks up the value for a URL template placeholder.
+pub trait SecretResolver: Send + Sync {
+    /// The value for `name`, or `None` if there isn't one.
+    fn resolve(&self, name: &str) -> Option<String>;
+}
+
+/// Resolves each placeholder from the environment variable of the same name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvSecretResolver;
+
+impl SecretResolver for EnvSecretResolver {
+    fn resolve(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+}
+
+/// Replaces resolved secrets with their placeholders. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct Redactor {
+    secrets: Arc<Vec<(String, String)>>,
+}
+
+impl Redactor {
+    /// `text` with every resolved secret replaced by its `{PLACEHOLDER}`.
+    pub fn redact(&self, text: &str) -> String {
+        self.secrets.iter().fold(text.to_string(), |text, (value, placeholder)| text.replace(value, placeholder))
+    }
+
+    /// `value` with `redact` applied to every string in it, object keys included.
+    pub fn redact_value(&self, value: &Value) -> Value {
+        match value {
+            Value::String(text) => Value::String(self.redact(text)),
+            Value::Array(items) => Value::Array(items.iter().map(|item| self.redact_value(item)).collect()),
+            Value::Object(fields) => Value::Object(fields.iter().map(|(key, item)| (self.redact(key), self.redact_value(item))).collect()),
+            other => other.clone(),
+        }
+    }
+
+    /// How many secrets `redact_value` would replace in `value`.
+    pub(crate) fn occurrences(&self, value: &Value) -> usize {
+        let in_text = |text: &str| self.secrets.iter().map(|(secret, _)| text.matches(secret.as_str()).count()).sum::<usize>();
+        match value {
+            Value::String(text) => in_text(text),
+            Value::Array(items) => items.iter().map(|item| self.occurrences(item)).sum(),
+            Value::Object(fields) => fields.iter().map(|(key, item)| in_text(key) + self.occurrences(item)).sum(),
+            _ => 0,
+        }
+    }
+
+    /// The placeholders secrets are replaced with, in the order they were resolved.
+    pub(crate) fn placeholders(&self) -> Vec<String> {
+        self.secrets.iter().map(|(_, placeholder)| placeholder.clone()).collect()
+    }
+
+    /// A redactor replacing both this one's secrets and `other`'s.
+    pub(crate) fn merged(&self, other: &Redactor) -> Redactor {
+        Redactor::from_secrets(self.secrets.iter().chain(other.secrets.iter()).cloned().collect())
+    }
+
+    fn from_secrets(mut secrets: Vec<(String, String)>) -> Self {
+        // Longest first, so a secret that contains another is replaced whole
+        secrets.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.cmp(b)));
+        secrets.dedup();
+        Redactor { secrets: Arc::new(secrets) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+}
+
+impl fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let placeholders: Vec<&str> = self.secrets.iter().map(|(_, placeholder)| placeholder.as_str()).collect();
+        f.debug_struct("Redactor").field("placeholders", &placeholders).finish()
+    }
+}
+
+/// Fills in a set of templates against one resolver, collecting what it resolved for redaction.
+pub(crate) struct TemplateRenderer<'a> {
+    resolver: &'a dyn SecretResolver,
+    secrets: Vec<(String, String)>,
+}
+
+impl<'a> TemplateRenderer<'a> {
+    pub(crate) fn new(resolver: &'a dyn SecretResolver) -> Self {
+        Self { resolver, secrets: Vec::new() }
+    }
+
+    /// The URL `template` names. Errors name the template or placeholder, never a secret.
+    pub(crate) fn render(&mut self, template: &str) -> Result<Url> {
+        let invalid = |detail: &str| RpcHandlerError::InvalidUrlTemplate { template: template.to_string(), detail: detail.to_string() };
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            let close = rest[open..].find('}').ok_or_else(|| invalid("unclosed `{`"))? + open;
+            let name = &rest[open + 1..close];
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(invalid("placeholders are `{NAME}` with letters, digits and `_`"));
+            }
+            let value = self.resolver.resolve(name).filter(|value| !value.is_empty()).ok_or_else(|| {
+                RpcHandlerError::UnresolvedPlaceholder { placeholder: name.to_string(), template: template.to_string() }
+            })?;
+            rendered.push_str(&value);
+            self.secrets.push((value, format!("{{{name}}}")));
+            rest = &rest[close + 1..];
+        }
+        if rest.contains('}') {
+            return Err(invalid("unopened `}`"));
+        }
+        rendered.push_str(rest);
+        Url::parse(&rendered).map_err(|_| invalid("the filled-in template is not a valid URL"))
+    }
+
+    pub(crate) fn into_redactor(self) -> Redactor {
+        Redactor::from_secrets(self.secrets)
+    }
+}
@@ -0,0 +1,145 @@
+//! Consistency sessions: a sequence of reads answered from one block.
+//!
+//! Reads that are compared with each other, say a token's `totalSupply` and then a balance, only
+//! make sense at the same block, while the proxy may serve each from a different endpoint at a
+//! different head. `RpcHandler::session` resolves `"latest"` once, on the endpoint the proxy
+//! picks, and every read through the session goes to that endpoint with the block substituted
+//! for `"latest"`. When it fails, the read fails over only to endpoints that return the same hash
+//! for that block, each checked once per session, and fails with `SnapshotUnavailable` if none
+//! does.
+//!
+//! A session holds nothing but the snapshot and the checks, so it costs one request to open and
+//! is closed by dropping it.
+
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::{json, Value};
+
+use crate::{
+    calls::RpcCalls,
+    namespaces::parse_quantity,
+    provider::{
+        pinned::{latest_block_param, pin},
+        CallOptions, TrafficClass,
+    },
+    JsonRpcRequest, JsonRpcResponse, Result, RpcHandler, RpcHandlerError,
+};
+
+/// The block a session reads at, and which endpoints were found to hold it.
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    block: u64,
+    hash: String,
+    /// The endpoint `"latest"` was resolved on, asked first
+    url: String,
+    /// Whether each endpoint checked returned `hash` for `block`
+    checked: parking_lot::Mutex<HashMap<String, bool>>,
+}
+
+/// A handle whose reads all see the chain at one block.
+pub struct ConsistencySession {
+    snapshot: Arc<Snapshot>,
+    calls: RpcCalls,
+}
+
+impl RpcHandler {
+    /// Open a consistency session at the head of the endpoint the proxy picks.
+    pub async fn session(self: &Arc<Self>) -> Result<ConsistencySession> {
+        let request = JsonRpcRequest::new("eth_getBlockByNumber", json!(["latest", false]));
+        let (response, url) = self.try_proxy_request_attributed(request).await?;
+        let block = response.into_result()?;
+        let malformed = |violation: &str| RpcHandlerError::MalformedResponse { url: url.clone(), violation: violation.to_string() };
+        let number = block.get("number").and_then(parse_quantity).ok_or_else(|| malformed("latest block has no number"))?;
+        let hash = block.get("hash").and_then(Value::as_str).ok_or_else(|| malformed("latest block has no hash"))?;
+
+        let snapshot = Arc::new(Snapshot {
+            block: number,
+            hash: hash.to_lowercase(),
+            checked: parking_lot::Mutex::new(HashMap::from([(url.clone(), true)])),
+            url,
+        });
+        Ok(ConsistencySession { calls: RpcCalls { snapshot: Some(Arc::clone(&snapshot)), ..RpcCalls::new(Arc::clone(self)) }, snapshot })
+    }
+}
+
+impl ConsistencySession {
+    /// The block every read is answered at.
+    pub fn block_number(&self) -> u64 {
+        self.snapshot.block
+    }
+
+    pub fn block_hash(&self) -> &str {
+        &self.snapshot.hash
+    }
+
+    /// The endpoint the snapshot was taken on.
+    pub fn url(&self) -> &str {
+        &self.snapshot.url
+    }
+
+    /// The typed calls, sent through this session.
+    pub fn calls(&self) -> &RpcCalls {
+        &self.calls
+    }
+
+    pub async fn try_proxy_request(&self, request: JsonRpcRequest) -> Result<JsonRpcResponse<Value>> {
+        self.try_proxy_request_attributed(request).await.map(|(response, _)| response)
+    }
+
+    /// Like `try_proxy_request`, also returning the endpoint that answered.
+    pub async fn try_proxy_request_attributed(&self, request: JsonRpcRequest) -> Result<(JsonRpcResponse<Value>, String)> {
+        self.snapshot.send(self.calls.handler(), request).await
+    }
+
+    /// End the session. Dropping it does the same.
+    pub fn close(self) {}
+}
+
+impl Snapshot {
+    /// Send `request`, reading at the snapshot block if it reads state at `"latest"`, to the
+    /// snapshot's endpoint, failing over to the endpoints that hold the block in plan order.
+    pub(crate) async fn send(&self, handler: &Arc<RpcHandler>, request: JsonRpcRequest) -> Result<(JsonRpcResponse<Value>, String)> {
+        let request = match latest_block_param(&request) {
+            Some(index) => pin(&request, index, self.block),
+            None => request,
+        };
+        let planned: Vec<String> = handler.plan_request(&request, None).await.map(|plan| plan.urls.into_iter().map(|planned| planned.url).collect()).unwrap_or_default();
+        let mut known: Vec<String> = handler.rpcs().iter().map(|rpc| rpc.url.to_string()).chain(planned.iter().cloned()).collect();
+        known.push(self.url.clone());
+
+        let candidates = std::iter::once(self.url.clone()).chain(planned.into_iter().filter(|url| *url != self.url));
+        for url in candidates {
+            if !self.held_by(handler, &url).await {
+                continue;
+            }
+            let options = CallOptions {
+                exclude: known.iter().filter(|other| **other != url).cloned().collect(),
+                retry_count: Some(1),
+                ..CallOptions::default()
+            };
+            match handler.try_proxy_request_with(request.clone(), options).await {
+                Ok(answered) => return Ok(answered),
+                Err(e) => tracing::debug!(url = %handler.redact(&url), error = %e, "Session read failed, trying the next endpoint holding its block"),
+            }
+        }
+        Err(RpcHandlerError::SnapshotUnavailable { block: self.block, hash: self.hash.clone() })
+    }
+
+    /// Whether `url` returns the snapshot's hash for its block. A hash, matching or not, is kept
+    /// for the session; no answer, or no block yet, is asked again next time.
+    async fn held_by(&self, handler: &RpcHandler, url: &str) -> bool {
+        if let Some(held) = self.checked.lock().get(url).copied() {
+            return held;
+        }
+        let Ok(client) = handler.http_client() else { return false };
+        let Some(block) = handler.side_call(&client, url, &JsonRpcRequest::new("eth_getBlockByNumber", json!([format!("{:#x}", self.block), false])), TrafficClass::Request).await else {
+            return false;
+        };
+        // No block there yet: an endpoint catching up may still get it
+        let Some(hash) = block.get("hash").and_then(Value::as_str) else { return false };
+        let held = hash.eq_ignore_ascii_case(&self.hash);
+        self.checked.lock().insert(url.to_string(), held);
+        held
+    }
+}
+
@@ -0,0 +1,282 @@
+//! Shadow endpoints: a candidate endpoint that replays a sample of production reads so its
+//! answers can be compared with what production returned, before it serves anything.
+//!
+//! A replay starts only after the production response is in hand, on its own task, so it never
+//! adds latency to production or changes its result. Only methods the registry marks idempotent
+//! are replayed; anything unregistered is left alone. Replays are capped in flight, and samples
+//! over the cap are dropped rather than queued.
+//!
+//! A `"latest"` read can legitimately differ when a block lands between the production answer
+//! and the replay, so a handful of mismatches on head-tracking methods is expected.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use tokio::sync::Semaphore;
+use web_time::Instant;
+
+use crate::{comparator::{stable_string, MISSING_KEY}, hex::hex, jsonrpc::{diff_values, Difference, DiffOptions}, methods, provider::{classify::post_json_rpc, TrafficClass, WeightedSemaphore}, runtime, spend::{CostProfile, SpendMeter}, JsonRpcRequest, JsonRpcResponse};
+
+/// Mismatch samples kept per shadow endpoint; later mismatches are only counted.
+pub const MAX_MISMATCH_SAMPLES: usize = 32;
+/// Differences kept per mismatch sample, the most significant first.
+pub const MAX_SAMPLE_DIFFERENCES: usize = 5;
+/// Latency deltas kept per shadow endpoint for the percentiles, newest last.
+const LATENCY_WINDOW: usize = 1024;
+/// Replays in flight at once across all shadow endpoints.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// One request the shadow answered differently from production.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MismatchSample {
+    pub method: String,
+    /// keccak256 of the params, so samples can be told apart without keeping addresses or calldata
+    pub params_hash: String,
+    /// keccak256 of the normalized production result
+    pub production_digest: String,
+    /// keccak256 of the normalized shadow result, or of its JSON-RPC error
+    pub shadow_digest: String,
+    /// Where the shadow's result departs from production's, production on the left, truncated.
+    /// Empty when the shadow answered with an error or the two differ only in hex case or
+    /// ignored fields
+    pub differences: Vec<Difference>,
+}
+
+/// Shadow latency minus production latency over the compared requests, in milliseconds.
+///
+/// Positive values mean the shadow was slower. The percentiles cover the most recent deltas only.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct LatencyDeltaStats {
+    pub samples: u64,
+    pub mean_ms: f64,
+    pub min_ms: i64,
+    pub max_ms: i64,
+    pub p50_ms: i64,
+    pub p95_ms: i64,
+}
+
+/// How a shadow endpoint compared with production so far, from `RpcHandler::shadow_reports`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShadowReport {
+    pub url: String,
+    pub sample_rate: f64,
+    /// Replays that got a JSON-RPC response to compare
+    pub compared: u64,
+    pub matched: u64,
+    /// The first `MAX_MISMATCH_SAMPLES` mismatches
+    pub mismatched: Vec<MismatchSample>,
+    /// Every mismatch, sampled or not
+    pub mismatch_count: u64,
+    /// Replays that got no JSON-RPC response at all: timeouts, transport and HTTP errors
+    pub failed: u64,
+    pub latency_delta_stats: LatencyDeltaStats,
+}
+
+#[derive(Default)]
+struct Tally {
+    compared: u64,
+    matched: u64,
+    mismatched: Vec<MismatchSample>,
+    mismatch_count: u64,
+    failed: u64,
+    delta_sum_ms: i64,
+    delta_min_ms: Option<i64>,
+    delta_max_ms: Option<i64>,
+    deltas: VecDeque<i64>,
+}
+
+impl Tally {
+    fn record_delta(&mut self, delta_ms: i64) {
+        self.delta_sum_ms += delta_ms;
+        self.delta_min_ms = Some(self.delta_min_ms.map_or(delta_ms, |min| min.min(delta_ms)));
+        self.delta_max_ms = Some(self.delta_max_ms.map_or(delta_ms, |max| max.max(delta_ms)));
+        if self.deltas.len() == LATENCY_WINDOW {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta_ms);
+    }
+
+    fn latency_stats(&self) -> LatencyDeltaStats {
+        if self.compared == 0 {
+            return LatencyDeltaStats::default();
+        }
+        let mut sorted: Vec<i64> = self.deltas.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+        LatencyDeltaStats {
+            samples: self.compared,
+            mean_ms: self.delta_sum_ms as f64 / self.compared as f64,
+            min_ms: self.delta_min_ms.unwrap_or_default(),
+            max_ms: self.delta_max_ms.unwrap_or_default(),
+            p50_ms: percentile(0.5),
+            p95_ms: percentile(0.95),
+        }
+    }
+}
+
+struct Shadow {
+    url: String,
+    headers: Option<HeaderMap>,
+    sample_rate: f64,
+    client: reqwest::Client,
+    /// Charged for each replay before it is sent, by the shadow's own cost profile
+    spend: SpendMeter,
+    cost_profile: Option<CostProfile>,
+    tally: parking_lot::Mutex<Tally>,
+}
+
+impl Shadow {
+    fn report(&self) -> ShadowReport {
+        let tally = self.tally.lock();
+        ShadowReport {
+            url: self.url.clone(),
+            sample_rate: self.sample_rate,
+            compared: tally.compared,
+            matched: tally.matched,
+            mismatched: tally.mismatched.clone(),
+            mismatch_count: tally.mismatch_count,
+            failed: tally.failed,
+            latency_delta_stats: tally.latency_stats(),
+        }
+    }
+
+    async fn replay(&self, request: &JsonRpcRequest, production: &Value, production_latency: Duration, timeout: Duration, follow_redirects: bool) {
+        let started = Instant::now();
+        let sent = runtime::timeout(timeout, async {
+            let response = post_json_rpc(&self.client, &self.url, request, follow_redirects, self.headers.as_ref()).await.ok()?;
+            response.json::<JsonRpcResponse<Value>>().await.ok()
+        })
+        .await;
+        let shadow_latency = started.elapsed();
+
+        let mut tally = self.tally.lock();
+        let Ok(Some(response)) = sent else {
+            tally.failed += 1;
+            return;
+        };
+        let shadow = match (&response.result, &response.error) {
+            (_, Some(error)) => stable_string(&json!({ "error": { "code": error.code, "message": error.message } })),
+            (Some(result), None) => stable_string(result),
+            (None, None) => MISSING_KEY.to_string(),
+        };
+        let production_key = stable_string(production);
+
+        tally.compared += 1;
+        tally.record_delta(shadow_latency.as_millis() as i64 - production_latency.as_millis() as i64);
+        if shadow == production_key {
+            tally.matched += 1;
+            return;
+        }
+        tally.mismatch_count += 1;
+        if tally.mismatched.len() < MAX_MISMATCH_SAMPLES {
+            let differences = match (&response.result, &response.error) {
+                (Some(result), None) => diff_values(production, result, &DiffOptions::default()).most_significant(MAX_SAMPLE_DIFFERENCES),
+                _ => Vec::new(),
+            };
+            tally.mismatched.push(MismatchSample {
+                method: request.method.clone(),
+                params_hash: digest(&stable_string(&request.params)),
+                production_digest: digest(&production_key),
+                shadow_digest: digest(&shadow),
+                differences,
+            });
+        }
+    }
+}
+
+fn digest(normalized: &str) -> String {
+    format!("0x{}", hex(&Keccak256::digest(normalized.as_bytes())))
+}
+
+/// The handler's shadow endpoints by URL. Cloning shares them.
+#[derive(Clone)]
+pub struct Shadows {
+    endpoints: Arc<parking_lot::RwLock<HashMap<String, Arc<Shadow>>>>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Default for Shadows {
+    fn default() -> Self {
+        Self { endpoints: Arc::default(), in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)) }
+    }
+}
+
+impl std::fmt::Debug for Shadows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.endpoints.read().keys()).finish()
+    }
+}
+
+impl Shadows {
+    /// Start shadowing with `url`, replacing any earlier shadow at that URL and its tallies.
+    pub(crate) fn insert(&self, url: String, headers: Option<HeaderMap>, sample_rate: f64, client: reqwest::Client, spend: SpendMeter, cost_profile: Option<CostProfile>) {
+        let shadow = Shadow {
+            url: url.clone(),
+            headers,
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+            client,
+            spend,
+            cost_profile,
+            tally: parking_lot::Mutex::default(),
+        };
+        self.endpoints.write().insert(url, Arc::new(shadow));
+    }
+
+    pub(crate) fn remove(&self, url: &str) -> Option<ShadowReport> {
+        self.endpoints.write().remove(url).map(|shadow| shadow.report())
+    }
+
+    pub(crate) fn reports(&self) -> Vec<ShadowReport> {
+        let mut reports: Vec<ShadowReport> = self.endpoints.read().values().map(|shadow| shadow.report()).collect();
+        reports.sort_by(|a, b| a.url.cmp(&b.url));
+        reports
+    }
+
+    /// Hand a successful production response to each shadow whose sample takes it.
+    ///
+    /// Returns straight away; replays run on their own tasks.
+    pub(crate) fn observe(&self, request: &JsonRpcRequest, response: &JsonRpcResponse<Value>, latency: Duration, timeout: Duration, follow_redirects: bool, budget: Option<&WeightedSemaphore>) {
+        let (Some(result), None) = (&response.result, &response.error) else {
+            return;
+        };
+        if !methods::descriptor(&request.method).is_some_and(|descriptor| descriptor.idempotent) {
+            return;
+        }
+        let sampled: Vec<Arc<Shadow>> = self
+            .endpoints
+            .read()
+            .values()
+            .filter(|shadow| shadow.sample_rate >= 1.0 || rand::random::<f64>() < shadow.sample_rate)
+            .cloned()
+            .collect();
+        for shadow in sampled {
+            let Ok(permit) = Arc::clone(&self.in_flight).try_acquire_owned() else {
+                return;
+            };
+            // Under `ConstrainedMode` a replay counts as background traffic
+            let slot = match budget {
+                Some(budget) => match budget.try_acquire(TrafficClass::Probe) {
+                    Some(slot) => Some(slot),
+                    None => return,
+                },
+                None => None,
+            };
+            // A shadow out of budget sits the sample out rather than counting it as failed
+            if shadow.spend.charge_with(&shadow.url, &[&request.method], shadow.cost_profile.as_ref()).is_err() {
+                continue;
+            }
+            let (request, result) = (request.clone(), result.clone());
+            runtime::spawn(async move {
+                shadow.replay(&request, &result, latency, timeout, follow_redirects).await;
+                drop((permit, slot));
+            });
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! The latency-SLO guard: moves the handler off a provider that keeps answering, but too slowly.
+//!
+//! Retries only react to failures, so an endpoint that degrades from tens to hundreds of
+//! milliseconds keeps serving. Under `HandlerSettings::latency_slo` every successful proxied
+//! request slower than the target counts against the endpoint that served it. Enough of them
+//! within the window breach the budget: the endpoint goes behind the others in every plan for
+//! the penalty, and if it is the active provider the fastest endpoint not under a penalty
+//! replaces it.
+//!
+//! Methods that are slow by nature, `methods::is_heavy` and the configured ignore list, never
+//! count, so one wide `eth_getLogs` doesn't trip the guard.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use web_time::Instant;
+
+use crate::{config::LatencySloConfig, methods, readiness::Heartbeat, runtime::{self, JoinHandle}, RpcHandler};
+
+/// An endpoint that exceeded the latency budget `max_violations` times within the window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SloBreach {
+    pub url: String,
+    pub violations: usize,
+}
+
+#[derive(Debug, Default)]
+struct SloState {
+    /// When each endpoint's recent violations happened, oldest first
+    violations: HashMap<String, VecDeque<Instant>>,
+    /// When each breaching endpoint's penalty runs out
+    penalized: HashMap<String, Instant>,
+}
+
+/// Violation windows and penalties per endpoint. Cloning shares them.
+#[derive(Debug, Clone)]
+pub struct SloGuard {
+    state: Arc<parking_lot::Mutex<SloState>>,
+    breaches: UnboundedSender<SloBreach>,
+}
+
+impl SloGuard {
+    /// A guard, and the receiving end of the breaches it reports.
+    pub(crate) fn new() -> (Self, UnboundedReceiver<SloBreach>) {
+        let (breaches, receiver) = mpsc::unbounded_channel();
+        (Self { state: Arc::default(), breaches }, receiver)
+    }
+
+    /// Count a request `url` served in `latency` against `slo`, reporting a breach when it is
+    /// the violation that crosses the threshold.
+    ///
+    /// An endpoint already serving a penalty isn't counted again until the penalty is over.
+    pub(crate) fn record(&self, slo: &LatencySloConfig, url: &str, method: &str, latency: Duration, now: Instant) {
+        if latency <= slo.target || methods::is_heavy(method) || slo.ignore_methods.iter().any(|ignored| ignored == method) {
+            return;
+        }
+        let mut state = self.state.lock();
+        if state.penalized.get(url).is_some_and(|until| *until > now) {
+            return;
+        }
+
+        let violations = state.violations.entry(url.to_string()).or_default();
+        while violations.front().is_some_and(|at| now.saturating_duration_since(*at) > slo.window) {
+            violations.pop_front();
+        }
+        violations.push_back(now);
+        if violations.len() < slo.max_violations {
+            return;
+        }
+
+        let count = violations.len();
+        state.violations.remove(url);
+        state.penalized.insert(url.to_string(), now + slo.penalty);
+        // The handler may be gone, with nobody left to re-select
+        let _ = self.breaches.send(SloBreach { url: url.to_string(), violations: count });
+    }
+
+    /// Endpoints still serving a penalty at `now`, with the time left on it.
+    pub fn penalized(&self, now: Instant) -> HashMap<String, Duration> {
+        let mut state = self.state.lock();
+        state.penalized.retain(|_, until| *until > now);
+        state.penalized.iter().map(|(url, until)| (url.clone(), *until - now)).collect()
+    }
+}
+
+/// Re-select the provider on each breach until `shutdown` or the handler is dropped.
+pub(crate) fn spawn_slo_watch(
+    handler: &Arc<RpcHandler>,
+    mut breaches: UnboundedReceiver<SloBreach>,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+
+    runtime::spawn(async move {
+        loop {
+            heartbeat.beat();
+            let breach = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                breach = breaches.recv() => match breach {
+                    Some(breach) => breach,
+                    None => return,
+                },
+            };
+            let Some(handler) = weak.upgrade() else { return };
+            handler.step_off_slow_provider(breach).await;
+        }
+    })
+}
@@ -0,0 +1,439 @@
+//! Spend accounting for metered endpoints.
+//!
+//! An endpoint with a `CostProfile` costs `unit_cost` per request, times the method's
+//! multiplier, and may spend up to its `daily_budget` per UTC day. `HandlerSettings::daily_spend_budget`
+//! caps the day's spend across all metered endpoints the same way. Every request counts: proxied
+//! ones and their retries, batches, streams, probes, keepalive pings, consensus fan-outs,
+//! broadcasts, managed filter calls and shadow replays. A request is charged before it is sent, and one that would take an endpoint
+//! past a budget isn't sent at all, so the spend never overshoots.
+//!
+//! An endpoint that can't afford another request is left out like a cooling-down one, with
+//! unmetered endpoints taking its traffic, until the day rolls over. The day's spend is kept
+//! in a `SpendStore`, so a restart doesn't hand out the budget again. Saves happen off the
+//! request path, on a task of their own, with URLs redacted.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, io,
+    sync::{Arc, Weak},
+    time::SystemTime,
+};
+#[cfg(feature = "persistence")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{clock::Clock, readiness::Heartbeat, runtime::{self, JoinHandle}, secrets::Redactor, NetworkId, Result, RpcHandler, RpcHandlerError};
+
+/// What requests to a metered endpoint cost, e.g.
+/// `{"unit_cost": 1, "method_multipliers": {"eth_getLogs": 10}, "daily_budget": 100000}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CostProfile {
+    /// Cost of one request
+    pub unit_cost: u64,
+    /// Factor `unit_cost` is multiplied by for the listed methods
+    #[serde(default)]
+    pub method_multipliers: BTreeMap<String, u64>,
+    /// Spend allowed per UTC day, unlimited when `None`
+    #[serde(default)]
+    pub daily_budget: Option<u64>,
+}
+
+impl CostProfile {
+    /// What one `method` request costs.
+    pub fn cost_of(&self, method: &str) -> u64 {
+        self.unit_cost.saturating_mul(self.method_multipliers.get(method).copied().unwrap_or(1))
+    }
+}
+
+/// Which budget ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetScope {
+    /// The endpoint's own `CostProfile::daily_budget`
+    Endpoint,
+    /// `HandlerSettings::daily_spend_budget`, shared by every metered endpoint
+    Handler,
+}
+
+impl fmt::Display for BudgetScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BudgetScope::Endpoint => "endpoint",
+            BudgetScope::Handler => "handler",
+        })
+    }
+}
+
+/// One UTC day's spend, as kept in a `SpendStore`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailySpend {
+    pub day: NaiveDate,
+    /// Spend per metered endpoint URL, redacted as the handler reports it
+    pub endpoints: BTreeMap<String, u64>,
+    /// Spend across every metered endpoint
+    pub total: u64,
+}
+
+impl DailySpend {
+    fn new(day: NaiveDate) -> Self {
+        Self { day, endpoints: BTreeMap::new(), total: 0 }
+    }
+}
+
+/// Store of each network's spend for the current day.
+pub trait SpendStore: Send + Sync {
+    /// The last spend saved for `network_id`, whatever day it was for.
+    fn load(&self, network_id: NetworkId) -> Option<DailySpend>;
+
+    /// Store `spend`, replacing the one for the same network.
+    fn save(&self, network_id: NetworkId, spend: &DailySpend) -> io::Result<()>;
+}
+
+/// Spend held in memory, for sharing between handlers in one process.
+#[derive(Debug, Default)]
+pub struct MemorySpendStore {
+    spend: parking_lot::Mutex<HashMap<NetworkId, DailySpend>>,
+}
+
+impl SpendStore for MemorySpendStore {
+    fn load(&self, network_id: NetworkId) -> Option<DailySpend> {
+        self.spend.lock().get(&network_id).cloned()
+    }
+
+    fn save(&self, network_id: NetworkId, spend: &DailySpend) -> io::Result<()> {
+        self.spend.lock().insert(network_id, spend.clone());
+        Ok(())
+    }
+}
+
+/// Spend kept in a JSON file, so a restart doesn't reset the day's budgets.
+///
+/// Like `FileLatencyStore`, the file is read once on `open` and rewritten through a temporary
+/// file on every `save`. The meter saves from a task of its own, skipping to the latest spend
+/// when requests come in faster than the file is written.
+#[cfg(feature = "persistence")]
+#[derive(Debug)]
+pub struct FileSpendStore {
+    path: PathBuf,
+    spend: parking_lot::Mutex<BTreeMap<NetworkId, DailySpend>>,
+}
+
+#[cfg(feature = "persistence")]
+impl FileSpendStore {
+    /// Open the store at `path`, starting empty if the file doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        runtime::require_filesystem("FileSpendStore")?;
+        let path = path.as_ref().to_path_buf();
+        let spend = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, spend: parking_lot::Mutex::new(spend) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl SpendStore for FileSpendStore {
+    fn load(&self, network_id: NetworkId) -> Option<DailySpend> {
+        self.spend.lock().get(&network_id).cloned()
+    }
+
+    fn save(&self, network_id: NetworkId, spend: &DailySpend) -> io::Result<()> {
+        let mut stored = self.spend.lock();
+        stored.insert(network_id, spend.clone());
+
+        let bytes = serde_json::to_vec(&*stored).map_err(io::Error::other)?;
+        let staging = self.path.with_extension("tmp");
+        fs::write(&staging, bytes)?;
+        fs::rename(&staging, &self.path)
+    }
+}
+
+/// One metered endpoint's spend today.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointSpend {
+    pub spent: u64,
+    pub daily_budget: Option<u64>,
+    /// It can't afford another request today, under its own budget or the handler's
+    pub exhausted: bool,
+}
+
+/// The day's spend, from `RpcHandler::spend_report` and in metrics snapshots.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendReport {
+    /// UTC day the spend is for, `None` before anything was metered
+    pub day: Option<NaiveDate>,
+    pub total: u64,
+    pub daily_budget: Option<u64>,
+    /// Each endpoint with a cost profile, by URL
+    pub endpoints: BTreeMap<String, EndpointSpend>,
+}
+
+/// A budget that ran out: an endpoint's, or the handler's when `url` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetExhaustion {
+    pub url: Option<String>,
+    pub spent: u64,
+    pub budget: u64,
+}
+
+#[derive(Debug)]
+struct SpendState {
+    spend: DailySpend,
+    /// Budgets reported exhausted today, `None` standing for the handler's
+    reported: HashSet<Option<String>>,
+}
+
+struct MeterShared {
+    clock: Arc<dyn Clock>,
+    /// Hands each new spend to the task saving it, when there is a store
+    saves: Option<watch::Sender<DailySpend>>,
+    redactor: parking_lot::RwLock<Redactor>,
+    profiles: parking_lot::RwLock<HashMap<String, CostProfile>>,
+    daily_budget: parking_lot::RwLock<Option<u64>>,
+    state: parking_lot::Mutex<SpendState>,
+    exhaustions: UnboundedSender<BudgetExhaustion>,
+}
+
+/// The handler's spend accounting. Cloning shares it.
+#[derive(Clone)]
+pub struct SpendMeter {
+    shared: Arc<MeterShared>,
+}
+
+impl fmt::Debug for SpendMeter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpendMeter")
+            .field("profiles", &self.shared.profiles.read().keys().collect::<Vec<_>>())
+            .field("daily_budget", &*self.shared.daily_budget.read())
+            .field("has_store", &self.shared.saves.is_some())
+            .finish()
+    }
+}
+
+fn utc_day(now: SystemTime) -> NaiveDate {
+    DateTime::<Utc>::from(now).date_naive()
+}
+
+impl SpendMeter {
+    /// A meter picking up today's spend from `store`, and the receiving end of the budgets it
+    /// reports exhausted. Must be called in a tokio runtime when there is a store.
+    pub(crate) fn new(network_id: NetworkId, clock: Arc<dyn Clock>, store: Option<Arc<dyn SpendStore>>) -> (Self, UnboundedReceiver<BudgetExhaustion>) {
+        let today = utc_day(clock.now_system());
+        let spend = store
+            .as_ref()
+            .and_then(|store| store.load(network_id))
+            .filter(|spend| spend.day == today)
+            .unwrap_or_else(|| DailySpend::new(today));
+        let saves = store.map(|store| {
+            let (saves, pending) = watch::channel(spend.clone());
+            runtime::spawn(save_spend(network_id, store, pending));
+            saves
+        });
+        let (exhaustions, receiver) = mpsc::unbounded_channel();
+        let shared = MeterShared {
+            clock,
+            saves,
+            redactor: parking_lot::RwLock::default(),
+            profiles: parking_lot::RwLock::default(),
+            daily_budget: parking_lot::RwLock::default(),
+            state: parking_lot::Mutex::new(SpendState { spend, reported: HashSet::new() }),
+            exhaustions,
+        };
+        (Self { shared: Arc::new(shared) }, receiver)
+    }
+
+    /// Take the cost `profiles` by URL and the handler-wide `daily_budget`. Endpoints without
+    /// a profile are free. Spend loaded under an endpoint's redacted URL moves to the URL itself.
+    pub(crate) fn configure(&self, profiles: HashMap<String, CostProfile>, daily_budget: Option<u64>, redactor: &Redactor) {
+        *self.shared.profiles.write() = profiles;
+        *self.shared.daily_budget.write() = daily_budget;
+        *self.shared.redactor.write() = redactor.clone();
+
+        let urls: Vec<String> = self.shared.profiles.read().keys().cloned().collect();
+        let mut state = self.shared.state.lock();
+        for url in urls {
+            let redacted = redactor.redact(&url);
+            if redacted != url
+                && let Some(spent) = state.spend.endpoints.remove(&redacted)
+            {
+                *state.spend.endpoints.entry(url).or_default() += spent;
+            }
+        }
+    }
+
+    /// The profile `url` is charged by, if it is metered.
+    pub fn profile(&self, url: &str) -> Option<CostProfile> {
+        self.shared.profiles.read().get(url).cloned()
+    }
+
+    /// Charge a `method` request to `url` by its configured profile, before it is sent.
+    pub(crate) fn charge(&self, url: &str, method: &str) -> Result<()> {
+        self.charge_all(url, &[method])
+    }
+
+    /// Charge a batch of requests to `url` at once: all of them, or none when the batch doesn't
+    /// fit the budget.
+    pub(crate) fn charge_all(&self, url: &str, methods: &[&str]) -> Result<()> {
+        let profile = self.profile(url);
+        self.charge_with(url, methods, profile.as_ref())
+    }
+
+    /// Charge `methods` to `url` by `profile`, for endpoints outside the configured set such
+    /// as shadows. Free without a profile.
+    pub(crate) fn charge_with(&self, url: &str, methods: &[&str], profile: Option<&CostProfile>) -> Result<()> {
+        let Some(profile) = profile else { return Ok(()) };
+        let cost = methods.iter().fold(0u64, |cost, method| cost.saturating_add(profile.cost_of(method)));
+        let daily_budget = *self.shared.daily_budget.read();
+        let mut state = self.shared.state.lock();
+        self.roll(&mut state);
+
+        let spent = state.spend.endpoints.get(url).copied().unwrap_or(0);
+        let over = match (profile.daily_budget, daily_budget) {
+            (Some(budget), _) if spent.saturating_add(cost) > budget => Some((BudgetScope::Endpoint, Some(url), spent, budget)),
+            (_, Some(budget)) if state.spend.total.saturating_add(cost) > budget => Some((BudgetScope::Handler, None, state.spend.total, budget)),
+            _ => None,
+        };
+        if let Some((scope, reported_url, spent, budget)) = over {
+            self.report(&mut state, reported_url, spent, budget);
+            return Err(RpcHandlerError::BudgetExhausted { url: url.to_string(), scope });
+        }
+
+        let spent = spent + cost;
+        state.spend.endpoints.insert(url.to_string(), spent);
+        state.spend.total += cost;
+        // Report the budget that just ran out, not only the request it turns away
+        if let Some(budget) = profile.daily_budget.filter(|&budget| spent.saturating_add(profile.unit_cost) > budget) {
+            self.report(&mut state, Some(url), spent, budget);
+        }
+        if let Some(budget) = daily_budget.filter(|&budget| state.spend.total.saturating_add(profile.unit_cost) > budget) {
+            let total = state.spend.total;
+            self.report(&mut state, None, total, budget);
+        }
+        // Handed over under the lock, so saves can't go out of order, but written elsewhere
+        if let Some(saves) = &self.shared.saves {
+            let redactor = self.shared.redactor.read();
+            let endpoints = state.spend.endpoints.iter().map(|(url, spent)| (redactor.redact(url), *spent)).collect();
+            saves.send_replace(DailySpend { day: state.spend.day, endpoints, total: state.spend.total });
+        }
+        Ok(())
+    }
+
+    /// The error for a request to `url` that was never charged because `url` can't afford it.
+    pub(crate) fn refusal(&self, url: &str) -> RpcHandlerError {
+        let endpoint_exhausted = self.report_spend().endpoints.get(url).is_some_and(|spend| {
+            let unit_cost = self.profile(url).map_or(0, |profile| profile.unit_cost);
+            spend.daily_budget.is_some_and(|budget| spend.spent.saturating_add(unit_cost) > budget)
+        });
+        let scope = if endpoint_exhausted { BudgetScope::Endpoint } else { BudgetScope::Handler };
+        RpcHandlerError::BudgetExhausted { url: url.to_string(), scope }
+    }
+
+    /// Metered endpoints that can't afford another request today.
+    pub fn exhausted(&self) -> HashSet<String> {
+        self.report_spend().endpoints.into_iter().filter(|(_, spend)| spend.exhausted).map(|(url, _)| url).collect()
+    }
+
+    /// The day's spend against each budget.
+    pub fn report_spend(&self) -> SpendReport {
+        let profiles = self.shared.profiles.read().clone();
+        let daily_budget = *self.shared.daily_budget.read();
+        let mut state = self.shared.state.lock();
+        self.roll(&mut state);
+        let total = state.spend.total;
+        let endpoints = profiles
+            .into_iter()
+            .map(|(url, profile)| {
+                let spent = state.spend.endpoints.get(&url).copied().unwrap_or(0);
+                let exhausted = profile.daily_budget.is_some_and(|budget| spent.saturating_add(profile.unit_cost) > budget)
+                    || daily_budget.is_some_and(|budget| total.saturating_add(profile.unit_cost) > budget);
+                (url, EndpointSpend { spent, daily_budget: profile.daily_budget, exhausted })
+            })
+            .collect();
+        SpendReport { day: Some(state.spend.day), total, daily_budget, endpoints }
+    }
+
+    /// Move the day's spend at `from` over to `to`, the same endpoint under a new URL.
+    pub(crate) fn rename(&self, from: &str, to: &str) {
+        let mut state = self.shared.state.lock();
+        self.roll(&mut state);
+        if let Some(spent) = state.spend.endpoints.remove(from) {
+            *state.spend.endpoints.entry(to.to_string()).or_default() += spent;
+        }
+        if state.reported.remove(&Some(from.to_string())) {
+            state.reported.insert(Some(to.to_string()));
+        }
+    }
+
+    /// Start a new day's spend once the UTC date has moved on.
+    fn roll(&self, state: &mut SpendState) {
+        let today = utc_day(self.shared.clock.now_system());
+        if state.spend.day != today {
+            *state = SpendState { spend: DailySpend::new(today), reported: HashSet::new() };
+        }
+    }
+
+    /// Report a budget exhausted, once a day.
+    fn report(&self, state: &mut SpendState, url: Option<&str>, spent: u64, budget: u64) {
+        let url = url.map(str::to_string);
+        if state.reported.insert(url.clone()) {
+            // The handler may be gone, with nobody left to tell
+            let _ = self.shared.exhaustions.send(BudgetExhaustion { url, spent, budget });
+        }
+    }
+}
+
+/// Save each spend handed over on `pending` to `store`, on the blocking pool. Spend that changes
+/// during a save is saved once afterwards, as it then is. Ends when the meter is dropped, after
+/// saving the last spend it handed over.
+async fn save_spend(network_id: NetworkId, store: Arc<dyn SpendStore>, mut pending: watch::Receiver<DailySpend>) {
+    while pending.changed().await.is_ok() {
+        let spend = pending.borrow_and_update().clone();
+        let store = Arc::clone(&store);
+        match runtime::spawn_blocking(move || store.save(network_id, &spend)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!(error = %e, "Failed to save spend"),
+            Err(e) => tracing::warn!(error = %e, "Spend save panicked"),
+        }
+    }
+}
+
+/// Log and announce each exhausted budget until `shutdown` or the handler is dropped.
+pub(crate) fn spawn_budget_watch(
+    handler: &Arc<RpcHandler>,
+    mut exhaustions: UnboundedReceiver<BudgetExhaustion>,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+
+    runtime::spawn(async move {
+        loop {
+            heartbeat.beat();
+            let exhaustion = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                exhaustion = exhaustions.recv() => match exhaustion {
+                    Some(exhaustion) => exhaustion,
+                    None => return,
+                },
+            };
+            let Some(handler) = weak.upgrade() else { return };
+            handler.report_budget_exhausted(exhaustion).await;
+        }
+    })
+}
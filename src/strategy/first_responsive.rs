@@ -0,0 +1,24 @@
+use futures::{stream::FuturesUnordered, StreamExt};
+use crate::{performance::{measure_rpcs_with_transport, MeasureOptions}, transport::TransportFactory, Rpc};
+
+/// The first endpoint to pass its probe, racing all of them at once.
+///
+/// Unlike `get_first_healthy` this neither shuffles nor filters by scheme, and returns as soon
+/// as any probe succeeds; the rest are dropped. `None` if every probe failed.
+pub async fn first_responsive(transport: &dyn TransportFactory, rpcs: &[Rpc], options: &MeasureOptions) -> Option<String> {
+    let mut probes: FuturesUnordered<_> = rpcs
+        .iter()
+        .map(|rpc| async move {
+            let single = std::slice::from_ref(rpc);
+            let (latencies, _) = measure_rpcs_with_transport(transport, single, options).await.ok()?;
+            latencies.into_keys().next()
+        })
+        .collect();
+
+    while let Some(result) = probes.next().await {
+        if result.is_some() {
+            return result;
+        }
+    }
+    None
+}
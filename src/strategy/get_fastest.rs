@@ -1,8 +1,8 @@
 use std::time::Duration;
-use crate::{performance::measure_rpcs, Rpc, Result};
+use crate::{performance::{measure_rpcs, pick_fastest_in_lowest_tier, TierMap}, transport::TransportFactory, Rpc, Result};
 
-pub async fn get_fastest(rpcs: &[Rpc], timeout: Duration) -> Result<(Option<String>, std::collections::HashMap<String, u64>)> {
-    let (latencies, _check_results) = measure_rpcs(rpcs, timeout).await?;
+pub async fn get_fastest(transport: &dyn TransportFactory, rpcs: &[Rpc], timeout: Duration) -> Result<(Option<String>, std::collections::HashMap<String, u64>)> {
+    let (latencies, _check_results) = measure_rpcs(transport, rpcs, timeout).await?;
     
     let fastest = latencies
         .iter()
@@ -11,3 +11,15 @@ pub async fn get_fastest(rpcs: &[Rpc], timeout: Duration) -> Result<(Option<Stri
     
     Ok((fastest, latencies))
 }
+
+/// Fastest healthy RPC within the lowest tier that has any healthy endpoint.
+///
+/// A slow tier-0 endpoint is preferred over a fast tier-1 endpoint; the full latency map is
+/// still returned so lower tiers remain available for failover. Endpoints missing from `tiers`
+/// are in the lowest priority tier.
+pub async fn get_fastest_in_lowest_tier(transport: &dyn TransportFactory, rpcs: &[Rpc], tiers: &TierMap, timeout: Duration) -> Result<(Option<String>, std::collections::HashMap<String, u64>)> {
+    let (latencies, _check_results) = measure_rpcs(transport, rpcs, timeout).await?;
+    let fastest = pick_fastest_in_lowest_tier(&latencies, tiers);
+
+    Ok((fastest, latencies))
+}
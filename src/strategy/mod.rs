@@ -1,11 +1,21 @@
+pub mod first_responsive;
 pub mod get_fastest;
 pub mod get_first_healthy;
 
-pub use get_fastest::get_fastest;
+pub use first_responsive::first_responsive;
+pub use get_fastest::{get_fastest, get_fastest_in_lowest_tier};
 pub use get_first_healthy::get_first_healthy;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum Strategy {
+    #[default]
     Fastest,
     FirstHealthy,
+    /// Serve through the first endpoint to pass a probe, then switch to the fastest once the
+    /// full sweep completes if it beats the provisional one by more than `fast_start_margin_ms`
+    FastStart,
+    /// Serve through the listed endpoints in the order given, without probing any of them:
+    /// the first is the active provider from `init()` on and failover walks the rest in turn.
+    /// Every URL must be one of the handler's endpoints
+    StaticOrder(Vec<String>),
 }
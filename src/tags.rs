@@ -0,0 +1,76 @@
+//! Caller tags: which part of an application a call came from.
+//!
+//! A call carries tags in `CallOptions::tags`, `BatchOptions::tags` or `ConsensusOptions::tags`,
+//! say `component=indexer`. They travel with it: its tracing span, the sub-requests of a batch or
+//! consensus read, the `RequestHeld`, `HoldReleased` and `RequestFailed` events, and the failure
+//! journal entry a call that fails for good leaves, attempts included.
+//!
+//! Metrics are where tags cost something, since every distinct value is another series. Only the
+//! keys in `HandlerSettings::metric_tag_keys` become dimensions of `MetricsSnapshot::tagged`, and
+//! past `MAX_TAG_VALUES` values of one key the rest are counted as `OVERFLOW_TAG_VALUE`. The other
+//! keys are for logs only.
+
+use std::collections::BTreeMap;
+
+use crate::{events::HandlerEvent, FailureClass, Result, RpcHandler, RpcHandlerError};
+
+/// Tags on one call, by key.
+pub type CallTags = BTreeMap<String, String>;
+
+/// Tags one call may carry.
+pub const MAX_CALL_TAGS: usize = 8;
+
+/// Bytes in a tag key or value.
+pub const MAX_TAG_LEN: usize = 64;
+
+/// Distinct values of one allowlisted key the metrics count apart.
+pub const MAX_TAG_VALUES: usize = 32;
+
+/// What values past `MAX_TAG_VALUES` are counted as.
+pub const OVERFLOW_TAG_VALUE: &str = "_other";
+
+/// Fails with `InvalidCallTags` if `tags` has more than `MAX_CALL_TAGS` entries, an empty key, or
+/// a key or value longer than `MAX_TAG_LEN`.
+pub fn check_tags(tags: &CallTags) -> Result<()> {
+    let invalid = |detail: String| Err(RpcHandlerError::InvalidCallTags { detail });
+    if tags.len() > MAX_CALL_TAGS {
+        return invalid(format!("{} tags, at most {MAX_CALL_TAGS} are allowed", tags.len()));
+    }
+    for (key, value) in tags {
+        if key.is_empty() {
+            return invalid("empty tag key".to_string());
+        }
+        if key.len() > MAX_TAG_LEN || value.len() > MAX_TAG_LEN {
+            return invalid(format!("tag {key:?} has a key or value longer than {MAX_TAG_LEN} bytes"));
+        }
+    }
+    Ok(())
+}
+
+/// `tags` as `key=value` pairs joined by commas, for logs.
+pub fn render_tags(tags: &CallTags) -> String {
+    tags.iter().map(|(key, value)| format!("{key}={value}")).collect::<Vec<_>>().join(",")
+}
+
+/// The span a tagged call runs in, so everything it logs names its tags. Disabled for an
+/// untagged call, which logs as it always has.
+pub(crate) fn call_span(method: &str, tags: Option<&CallTags>) -> tracing::Span {
+    match tags.filter(|tags| !tags.is_empty()) {
+        Some(tags) => tracing::info_span!("rpc_call", method = %method, tags = %render_tags(tags)),
+        None => tracing::Span::none(),
+    }
+}
+
+impl RpcHandler {
+    /// Emit `RequestFailed` if a call tagged `tags` ended in an error.
+    pub(crate) fn report_tagged<T>(&self, method: &str, tags: Option<&CallTags>, result: &Result<T>) {
+        if let (Some(tags), Err(error)) = (tags.filter(|tags| !tags.is_empty()), result) {
+            self.emit(HandlerEvent::RequestFailed {
+                method: method.to_string(),
+                class: FailureClass::of(error),
+                error: self.redact(&error.to_string()),
+                tags: tags.clone(),
+            });
+        }
+    }
+}
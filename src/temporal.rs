@@ -0,0 +1,159 @@
+//! Consensus over time: the same consensus read repeated after a gap, answered only once two
+//! rounds in a row agree.
+//!
+//! Agreement across endpoints says nothing about whether the chain they agree on is about to
+//! reorganize. For checks that must not be fooled by a transient state, such as whether a deposit
+//! really confirmed, `temporal_consensus` runs a consensus round, waits out `recheck_after`, and
+//! runs it again. Needs the `consensus` feature.
+
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    calls::{ConsensusOptions, RpcCalls},
+    consensus::ConsensusReport,
+    namespaces::parse_quantity,
+    JsonRpcRequest, Result, RpcHandlerError,
+};
+
+/// How long to wait between two rounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recheck {
+    Duration(Duration),
+    /// Until the handler's head watermark is this many blocks past where it was
+    Blocks(u64),
+}
+
+/// Options for `temporal_consensus`.
+#[derive(Debug, Clone)]
+pub struct TemporalOptions {
+    pub recheck_after: Recheck,
+    /// Rounds after the first before giving up on two in a row agreeing, at least one
+    pub max_rechecks: usize,
+    /// How often the head is read while waiting out `Recheck::Blocks`
+    pub head_poll_interval: Duration,
+    /// Passed to every round
+    pub consensus: Option<ConsensusOptions>,
+    /// Ends a wait between rounds early, failing the call with `TemporalConsensusCancelled`
+    pub cancel: Option<CancellationToken>,
+}
+
+impl TemporalOptions {
+    pub fn new(recheck_after: Recheck) -> Self {
+        Self { recheck_after, max_rechecks: 1, head_poll_interval: Duration::from_secs(2), consensus: None, cancel: None }
+    }
+}
+
+/// What one round of a temporal consensus read came to.
+#[derive(Debug, Clone)]
+pub struct RoundSummary {
+    /// The value the round's quorum agreed on, `None` if it reached none
+    pub value: Option<Value>,
+    /// The head watermark when the round started
+    pub head: Option<u64>,
+    pub report: ConsensusReport,
+}
+
+/// Every round of a temporal consensus read, first to last.
+#[derive(Debug, Clone, Default)]
+pub struct TemporalReport {
+    pub rounds: Vec<RoundSummary>,
+}
+
+impl RpcCalls {
+    /// The value a `quorum` of endpoints agrees on in two consecutive consensus rounds,
+    /// `options.recheck_after` apart.
+    ///
+    /// A round without a quorum fails the call with its error. Rounds that keep disagreeing fail
+    /// it with `TemporalConsensus` once `max_rechecks` rounds have followed the first. Handler
+    /// shutdown, or `options.cancel`, ends a wait with `TemporalConsensusCancelled`.
+    pub async fn temporal_consensus<T>(&self, req: &JsonRpcRequest, quorum: f64, options: TemporalOptions) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.temporal_consensus_with_report(req, quorum, options).await.0
+    }
+
+    /// Like `temporal_consensus`, but also returns each round's summary.
+    pub async fn temporal_consensus_with_report<T>(&self, req: &JsonRpcRequest, quorum: f64, options: TemporalOptions) -> (Result<T>, TemporalReport)
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut report = TemporalReport::default();
+        let result = self.temporal_rounds(req, quorum, &options, &mut report).await;
+        let result = result.and_then(|value| serde_json::from_value(value).map_err(|e| RpcHandlerError::SerializationError(e.to_string())));
+        (result, report)
+    }
+
+    async fn temporal_rounds(&self, req: &JsonRpcRequest, quorum: f64, options: &TemporalOptions, report: &mut TemporalReport) -> Result<Value> {
+        let mut previous = self.temporal_round(req, quorum, options, report).await?;
+        for _ in 0..options.max_rechecks.max(1) {
+            self.wait_to_recheck(options, report.rounds.len()).await?;
+            let latest = self.temporal_round(req, quorum, options, report).await?;
+            if latest.1 == previous.1 {
+                return Ok(latest.0);
+            }
+            previous = latest;
+        }
+        let keys: Vec<&str> = report.rounds.iter().rev().take(2).filter_map(|round| round.report.most_common.as_deref()).collect();
+        Err(RpcHandlerError::TemporalConsensus {
+            previous: keys.get(1).unwrap_or(&"n/a").to_string(),
+            latest: keys.first().unwrap_or(&"n/a").to_string(),
+            rounds: report.rounds.len(),
+        })
+    }
+
+    /// One consensus round, recorded in `report`: the agreed value and its comparator key.
+    async fn temporal_round(&self, req: &JsonRpcRequest, quorum: f64, options: &TemporalOptions, report: &mut TemporalReport) -> Result<(Value, String)> {
+        let head = self.handler.head_watermark();
+        let (result, round) = self.consensus_with_report::<Value>(req, quorum, options.consensus.clone()).await;
+        let key = round.most_common.clone();
+        report.rounds.push(RoundSummary { value: result.as_ref().ok().cloned(), head, report: round });
+        let value = result?;
+        // A round that returned a value had a most common key
+        Ok((value, key.unwrap_or_default()))
+    }
+
+    /// Wait out `options.recheck_after`, ended early by cancellation or handler shutdown.
+    async fn wait_to_recheck(&self, options: &TemporalOptions, rounds: usize) -> Result<()> {
+        let cancel = options.cancel.clone().unwrap_or_default();
+        let wait = async {
+            match options.recheck_after {
+                Recheck::Duration(gap) => {
+                    self.clock.sleep(gap).await;
+                    Ok(())
+                }
+                Recheck::Blocks(blocks) => self.wait_for_blocks(blocks, options.head_poll_interval).await,
+            }
+        };
+        tokio::select! {
+            waited = wait => waited,
+            _ = cancel.cancelled() => Err(RpcHandlerError::TemporalConsensusCancelled { rounds }),
+            _ = self.handler.shutdown_token().cancelled() => Err(RpcHandlerError::TemporalConsensusCancelled { rounds }),
+        }
+    }
+
+    /// Poll the head through the proxy, which moves the watermark, until it is `blocks` past
+    /// where it started.
+    async fn wait_for_blocks(&self, blocks: u64, poll_interval: Duration) -> Result<()> {
+        let start = match self.handler.head_watermark() {
+            Some(head) => head,
+            None => self.read_head().await?,
+        };
+        loop {
+            self.clock.sleep(poll_interval).await;
+            self.read_head().await?;
+            if self.handler.head_watermark().is_some_and(|head| head >= start + blocks) {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn read_head(&self) -> Result<u64> {
+        let request = JsonRpcRequest::new("eth_blockNumber", serde_json::json!([]));
+        let head = self.try_rpc_call(&request).await?.into_result()?;
+        parse_quantity(&head).ok_or_else(|| RpcHandlerError::SerializationError(format!("eth_blockNumber returned {head}")))
+    }
+}
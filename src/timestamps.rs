@@ -0,0 +1,133 @@
+//! Block timestamp sanity checks: catching endpoints whose node clock is off, or whose head is
+//! old by the wall clock.
+//!
+//! Under `HandlerSettings::timestamp_sanity`, every block that passes through the handler, from
+//! probes and from proxied `eth_getBlockBy*` calls, is checked against the injectable clock. A
+//! block further ahead than `max_future_drift` flags its endpoint `ClockSkewSuspected`; a head
+//! block further behind than `max_past_lag` flags it `BlocksLagging`, which the plan treats like
+//! an endpoint probed behind the head. Flagged endpoints go behind the others until
+//! `sane_to_clear` sane blocks in a row clear the flag. In strict mode a skewed block also fails
+//! its attempt, so the request fails over instead of returning it.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{config::TimestampSanityConfig, head::reported_head, namespaces::parse_quantity, JsonRpcRequest, RpcHandlerError};
+
+/// Something wrong with an endpoint that its blocks gave away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum HealthFlag {
+    /// Served a block dated too far in the future
+    ClockSkewSuspected,
+    /// Served a head block dated too far in the past
+    BlocksLagging,
+    /// Kept serving block hashes its peers disagree with, found by agreement sampling
+    SuspectedDishonest,
+}
+
+#[derive(Debug, Default)]
+struct Streaks {
+    skewed: bool,
+    lagging: bool,
+    /// Sane blocks in a row since the skew flag was set
+    sane_blocks: u32,
+    /// Sane head blocks in a row since the lag flag was set
+    sane_heads: u32,
+}
+
+/// Flags per endpoint. Cloning shares them.
+#[derive(Debug, Clone, Default)]
+pub struct TimestampGuard {
+    endpoints: Arc<parking_lot::Mutex<HashMap<String, Streaks>>>,
+}
+
+impl TimestampGuard {
+    /// Check a block `url` served, stamped `timestamp` seconds since the Unix epoch. Only a head
+    /// block is held to `max_past_lag`.
+    ///
+    /// Returns how far ahead of `now` the block is, in milliseconds, when that's beyond
+    /// `max_future_drift`.
+    pub(crate) fn check(&self, config: &TimestampSanityConfig, url: &str, timestamp: u64, is_head: bool, now: SystemTime) -> Option<u64> {
+        let now_ms = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let block_ms = timestamp.saturating_mul(1000);
+        let drift_ms = block_ms.saturating_sub(now_ms);
+        let skewed = drift_ms > config.max_future_drift.as_millis() as u64;
+        let lagging = config.max_past_lag.is_some_and(|max| now_ms.saturating_sub(block_ms) > max.as_millis() as u64);
+
+        let mut endpoints = self.endpoints.lock();
+        let streaks = endpoints.entry(url.to_string()).or_default();
+        if skewed {
+            streaks.skewed = true;
+            streaks.sane_blocks = 0;
+        } else if streaks.skewed {
+            streaks.sane_blocks += 1;
+            streaks.skewed = streaks.sane_blocks < config.sane_to_clear;
+        }
+        if is_head && lagging {
+            streaks.lagging = true;
+            streaks.sane_heads = 0;
+        } else if is_head && streaks.lagging {
+            streaks.sane_heads += 1;
+            streaks.lagging = streaks.sane_heads < config.sane_to_clear;
+        }
+        if !streaks.skewed && !streaks.lagging {
+            endpoints.remove(url);
+        }
+        skewed.then_some(drift_ms)
+    }
+
+    /// Check the block in a proxied `result`, if it holds one. In strict mode a skewed block is
+    /// an error: the response must not be returned.
+    pub(crate) fn observe(
+        &self,
+        config: Option<&TimestampSanityConfig>,
+        url: &str,
+        request: &JsonRpcRequest,
+        result: &Value,
+        now: SystemTime,
+    ) -> Result<(), RpcHandlerError> {
+        let Some(config) = config else { return Ok(()) };
+        if !matches!(request.method.as_str(), "eth_getBlockByNumber" | "eth_getBlockByHash") {
+            return Ok(());
+        }
+        let Some(timestamp) = result.get("timestamp").and_then(parse_quantity) else {
+            return Ok(());
+        };
+        match self.check(config, url, timestamp, reported_head(request, result).is_some(), now) {
+            Some(drift_ms) if config.strict => Err(RpcHandlerError::ClockSkew { url: url.to_string(), drift_ms }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Flags currently set, by endpoint.
+    pub fn flags(&self) -> HashMap<String, BTreeSet<HealthFlag>> {
+        self.endpoints
+            .lock()
+            .iter()
+            .map(|(url, streaks)| {
+                let flags = [(streaks.skewed, HealthFlag::ClockSkewSuspected), (streaks.lagging, HealthFlag::BlocksLagging)];
+                (url.clone(), flags.into_iter().filter_map(|(set, flag)| set.then_some(flag)).collect())
+            })
+            .collect()
+    }
+
+    /// Endpoints flagged with `flag`.
+    pub(crate) fn flagged(&self, flag: HealthFlag) -> HashSet<String> {
+        self.endpoints
+            .lock()
+            .iter()
+            .filter(|(_, streaks)| match flag {
+                HealthFlag::ClockSkewSuspected => streaks.skewed,
+                HealthFlag::BlocksLagging => streaks.lagging,
+                HealthFlag::SuspectedDishonest => false,
+            })
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,196 @@
+//! How requests reach an endpoint, for the code that doesn't go through a handler's provider:
+//! health probes, the strategies built on them and the `fanout` engines.
+//!
+//! An endpoint reachable only some other way, such as an in-process node or a gateway that needs
+//! requests signed, gets probed and measured like any other once its `TransportFactory` hands
+//! out a transport for it.
+
+use std::{collections::HashSet, net::IpAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use serde_json::{json, Value};
+
+use crate::{provider::{headers::HeaderOverrides, post_json_rpc, rpc_client}, runtime, JsonRpcRequest, JsonRpcResponse, Result, RpcHandlerError};
+
+/// Sends JSON-RPC requests to one endpoint.
+#[async_trait]
+pub trait JsonRpcTransport: Send + Sync {
+    /// The endpoint every request goes to.
+    fn url(&self) -> &str;
+
+    /// Send `request` and return the decoded response, JSON-RPC errors included.
+    async fn request(&self, request: &JsonRpcRequest) -> Result<JsonRpcResponse<Value>>;
+
+    /// Send `request` for a health probe, keeping the body as it came and the address that
+    /// answered. The caller bounds how long it takes.
+    ///
+    /// Defaults to `request`, with the response re-encoded and no address. A rate-limited
+    /// endpoint should fail with an `HttpStatus` of 429 so probing slows down for it.
+    async fn exchange(&self, request: &JsonRpcRequest) -> ProbeExchange {
+        ProbeExchange { body: self.request(request).await.map(response_body), remote_ip: None }
+    }
+}
+
+/// Hands out the transport for each endpoint URL.
+pub trait TransportFactory: Send + Sync {
+    fn transport(&self, url: &str) -> Arc<dyn JsonRpcTransport>;
+
+    /// Whether `url` can be reached at all, e.g. by its scheme. `get_first_healthy` passes over
+    /// endpoints that can't. Defaults to every URL.
+    fn supports(&self, url: &str) -> bool {
+        let _ = url;
+        true
+    }
+}
+
+/// One probe request as the transport saw it.
+#[derive(Debug)]
+pub struct ProbeExchange {
+    /// The response body, JSON-RPC errors included, or why there was none
+    pub body: Result<Value>,
+    /// The address that answered, when the transport knows it
+    pub remote_ip: Option<IpAddr>,
+}
+
+/// `response` as JSON, leaving out a `result` or `error` it doesn't have.
+fn response_body(response: JsonRpcResponse<Value>) -> Value {
+    let mut body = json!({ "jsonrpc": response.jsonrpc, "id": response.id });
+    if let Some(result) = response.result {
+        body["result"] = result;
+    }
+    if let Some(error) = response.error {
+        body["error"] = json!(error);
+    }
+    body
+}
+
+/// JSON-RPC over HTTP POST to one URL.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    client: reqwest::Client,
+    url: String,
+    timeout: Duration,
+    follow_redirects: bool,
+    headers: Option<HeaderMap>,
+}
+
+impl HttpTransport {
+    pub fn new(client: reqwest::Client, url: impl Into<String>, timeout: Duration) -> Self {
+        Self { client, url: url.into(), timeout, follow_redirects: false, headers: None }
+    }
+}
+
+#[async_trait]
+impl JsonRpcTransport for HttpTransport {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn request(&self, request: &JsonRpcRequest) -> Result<JsonRpcResponse<Value>> {
+        let url = self.url.as_str();
+        let send = async {
+            let response = post_json_rpc(&self.client, url, request, self.follow_redirects, self.headers.as_ref()).await?;
+            if !response.status().is_success() {
+                return Err(RpcHandlerError::HttpStatus { url: url.to_string(), status: response.status().as_u16() });
+            }
+            response.json::<JsonRpcResponse<Value>>().await.map_err(|e| RpcHandlerError::from_reqwest(e, url))
+        };
+        runtime::timeout(self.timeout, send).await.map_err(|_| RpcHandlerError::request_timeout(url, self.timeout))?
+    }
+
+    /// Not bounded by the transport's timeout, which probes replace with their own.
+    async fn exchange(&self, request: &JsonRpcRequest) -> ProbeExchange {
+        let url = self.url.as_str();
+        let response = match post_json_rpc(&self.client, url, request, self.follow_redirects, self.headers.as_ref()).await {
+            Ok(response) => response,
+            Err(error) => return ProbeExchange { body: Err(error), remote_ip: None },
+        };
+        let remote_ip = response.remote_addr().map(|addr| addr.ip());
+        let status = response.status();
+        let body = match status.is_success() {
+            true => response.json::<Value>().await.map_err(|e| RpcHandlerError::BodyDecode { url: url.to_string(), detail: e.to_string() }),
+            false => Err(RpcHandlerError::HttpStatus { url: url.to_string(), status: status.as_u16() }),
+        };
+        ProbeExchange { body, remote_ip }
+    }
+}
+
+/// `HttpTransport`s sharing one client and timeout.
+#[derive(Debug, Clone)]
+pub struct HttpTransportFactory {
+    client: reqwest::Client,
+    timeout: Duration,
+    follow_redirects: bool,
+    headers: HeaderOverrides,
+    /// The only URLs transports send to, any when `None`
+    allowed: Option<Arc<HashSet<String>>>,
+}
+
+impl HttpTransportFactory {
+    /// Transports over the default `rpc_client()`, each request bounded by `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_client(rpc_client(), timeout)
+    }
+
+    pub fn with_client(client: reqwest::Client, timeout: Duration) -> Self {
+        Self { client, timeout, follow_redirects: false, headers: HeaderOverrides::default(), allowed: None }
+    }
+
+    /// Re-send a request once to a same-host redirect target.
+    pub fn follow_redirects(mut self, follow: bool) -> Self {
+        self.follow_redirects = follow;
+        self
+    }
+
+    /// Extra headers for the endpoints listed, by URL, sent on top of the client's.
+    pub fn headers(mut self, headers: HeaderOverrides) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Send to `urls` only. The transport for any other URL fails with `EgressDenied` without
+    /// sending, and `supports` is false for it.
+    pub fn only(mut self, urls: impl IntoIterator<Item = String>) -> Self {
+        self.allowed = Some(Arc::new(urls.into_iter().collect()));
+        self
+    }
+
+    fn allows(&self, url: &str) -> bool {
+        self.allowed.as_ref().is_none_or(|allowed| allowed.contains(url))
+    }
+}
+
+impl TransportFactory for HttpTransportFactory {
+    fn transport(&self, url: &str) -> Arc<dyn JsonRpcTransport> {
+        if !self.allows(url) {
+            return Arc::new(DeniedTransport { url: url.to_string() });
+        }
+        Arc::new(HttpTransport {
+            follow_redirects: self.follow_redirects,
+            headers: self.headers.get(url).cloned(),
+            ..HttpTransport::new(self.client.clone(), url, self.timeout)
+        })
+    }
+
+    /// `http` and `https` URLs, and of those only the ones given to `only` if it was called.
+    fn supports(&self, url: &str) -> bool {
+        (url.starts_with("https://") || url.starts_with("http://")) && self.allows(url)
+    }
+}
+
+/// Stands in for the transport to a URL an `HttpTransportFactory` may not send to.
+struct DeniedTransport {
+    url: String,
+}
+
+#[async_trait]
+impl JsonRpcTransport for DeniedTransport {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn request(&self, _request: &JsonRpcRequest) -> Result<JsonRpcResponse<Value>> {
+        Err(RpcHandlerError::EgressDenied { url: self.url.clone() })
+    }
+}
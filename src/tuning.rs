@@ -0,0 +1,308 @@
+//! Retry parameters that follow how calls actually go.
+//!
+//! Under `HandlerSettings::retry_tuning`, every proxied call that gets as far as an endpoint is
+//! added to an `OutcomeSummary`: whether it needed a retry and the first one answered, whether a
+//! later round of the plan answered it, and whether an attempt timed out before another endpoint
+//! answered. Every `window` the tuner hands the window's summary to `decide` and applies the one
+//! change it returns, if any, the way `apply_config` applies a config, then logs it with the
+//! summary behind it. `decide` is a pure function, so its rules can be checked on made-up
+//! summaries, and it never leaves the configured bounds.
+//!
+//! `freeze_retry_tuning` stops further changes, keeping the parameters as tuned so far, and
+//! `reset_retry_tuning` puts back the ones from before the first change. `apply_config` puts the
+//! configured parameters back too, and tuning goes on from those.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::{resolve_config::RetryConfig, RetryTuningConfig},
+    fanout::Position,
+    readiness::Heartbeat,
+    reload::FieldChange,
+    runtime::{self, JoinHandle},
+    RpcHandler,
+};
+
+/// Changes kept in `TuningStatus::history`.
+pub const TUNING_HISTORY_LEN: usize = 32;
+
+/// Calls that reached the point a rule looks at, before the rule may apply.
+pub const MIN_RULE_EVIDENCE: u64 = 5;
+
+/// Share of the calls reaching a later round that one answered, below which `FewerRounds` drops a round.
+pub const WASTED_ROUND_SHARE: f64 = 0.05;
+
+/// Share of calls with a timed-out attempt another endpoint then answered, from which
+/// `WiderBatches` races one more endpoint at once.
+pub const TIMEOUT_RESCUE_SHARE: f64 = 0.1;
+
+/// Share of retried calls the first retry answered, from which `ShorterDelay` halves the delay.
+pub const FIRST_RETRY_SHARE: f64 = 0.8;
+
+/// What the calls that finished in one window went through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutcomeSummary {
+    /// Calls sent to at least one endpoint, answered or not
+    pub calls: u64,
+    /// Calls whose first batch didn't answer
+    pub retried: u64,
+    /// Retried calls the next batch answered
+    pub first_retry_answered: u64,
+    /// Calls that went on past the first round of their plan
+    pub extra_rounds: u64,
+    /// Of those, calls a later round answered
+    pub extra_rounds_answered: u64,
+    /// Calls with an attempt that timed out in a batch that failed, answered by a later batch
+    pub timed_out_then_answered: u64,
+    /// Calls no endpoint answered in any round
+    pub exhausted: u64,
+}
+
+/// The retry parameters the tuner moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryParameters {
+    /// Rounds over the plan, `ProxySettings::retry_count`
+    pub retry_count: u32,
+    pub retry_delay_ms: u64,
+    /// Endpoints raced at once
+    pub batch_size: usize,
+}
+
+impl From<&RetryConfig> for RetryParameters {
+    fn from(retry: &RetryConfig) -> Self {
+        Self { retry_count: retry.retry_count, retry_delay_ms: retry.retry_delay.as_millis() as u64, batch_size: retry.batch_size }
+    }
+}
+
+impl From<RetryParameters> for RetryConfig {
+    fn from(parameters: RetryParameters) -> Self {
+        Self { retry_count: parameters.retry_count, retry_delay: Duration::from_millis(parameters.retry_delay_ms), batch_size: parameters.batch_size }
+    }
+}
+
+/// Why `decide` changed the parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TuningRule {
+    /// They were outside the bounds, e.g. after `apply_config`
+    IntoBounds,
+    /// Rounds after the first almost never answered, so they only added latency
+    FewerRounds,
+    /// Timed-out attempts were often answered by a peer in a later batch, which a wider batch
+    /// would have raced from the start
+    WiderBatches,
+    /// The first retry mostly answered, so waiting less before it costs nothing
+    ShorterDelay,
+}
+
+/// A change `decide` made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub rule: TuningRule,
+    pub parameters: RetryParameters,
+}
+
+/// The change `summary` calls for to `current`, if any, within `bounds`.
+///
+/// Parameters outside the bounds are brought into them first. Otherwise, once the window has
+/// `min_samples` calls, the rules are tried in the order of `TuningRule` and the first that
+/// applies changes one parameter by one step: a round less, one endpoint more per batch, or
+/// half the delay.
+pub fn decide(summary: &OutcomeSummary, current: RetryParameters, bounds: &RetryTuningConfig) -> Option<Decision> {
+    let within = within_bounds(current, bounds);
+    if within != current {
+        return Some(Decision { rule: TuningRule::IntoBounds, parameters: within });
+    }
+    if summary.calls < bounds.min_samples {
+        return None;
+    }
+    let share = |part: u64, whole: u64| part as f64 / whole as f64;
+
+    if summary.extra_rounds >= MIN_RULE_EVIDENCE
+        && share(summary.extra_rounds_answered, summary.extra_rounds) < WASTED_ROUND_SHARE
+        && current.retry_count > bounds.min_retry_count
+    {
+        return Some(Decision { rule: TuningRule::FewerRounds, parameters: RetryParameters { retry_count: current.retry_count - 1, ..current } });
+    }
+    if share(summary.timed_out_then_answered, summary.calls) >= TIMEOUT_RESCUE_SHARE && current.batch_size < bounds.max_batch_size {
+        return Some(Decision { rule: TuningRule::WiderBatches, parameters: RetryParameters { batch_size: current.batch_size + 1, ..current } });
+    }
+    let min_delay_ms = bounds.min_retry_delay.as_millis() as u64;
+    if summary.retried >= MIN_RULE_EVIDENCE && share(summary.first_retry_answered, summary.retried) >= FIRST_RETRY_SHARE && current.retry_delay_ms > min_delay_ms {
+        let retry_delay_ms = (current.retry_delay_ms / 2).max(min_delay_ms);
+        return Some(Decision { rule: TuningRule::ShorterDelay, parameters: RetryParameters { retry_delay_ms, ..current } });
+    }
+    None
+}
+
+fn within_bounds(parameters: RetryParameters, bounds: &RetryTuningConfig) -> RetryParameters {
+    RetryParameters {
+        retry_count: parameters.retry_count.clamp(bounds.min_retry_count, bounds.max_retry_count),
+        retry_delay_ms: parameters.retry_delay_ms.clamp(bounds.min_retry_delay.as_millis() as u64, bounds.max_retry_delay.as_millis() as u64),
+        batch_size: parameters.batch_size.clamp(bounds.min_batch_size, bounds.max_batch_size),
+    }
+}
+
+/// One change the tuner applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TuningRecord {
+    pub at: SystemTime,
+    pub rule: TuningRule,
+    pub from: RetryParameters,
+    pub to: RetryParameters,
+    /// The window's summary the rule applied to
+    pub evidence: OutcomeSummary,
+}
+
+/// Where tuning stands.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TuningStatus {
+    pub frozen: bool,
+    /// The parameters requests are sent with now
+    pub parameters: RetryParameters,
+    /// The parameters from before the first change, `None` while nothing has changed
+    pub baseline: Option<RetryParameters>,
+    /// The last `TUNING_HISTORY_LEN` changes, oldest first
+    pub history: Vec<TuningRecord>,
+    /// The current window's calls so far
+    pub window: OutcomeSummary,
+}
+
+#[derive(Debug, Default)]
+struct TunerState {
+    frozen: bool,
+    baseline: Option<RetryParameters>,
+    history: VecDeque<TuningRecord>,
+}
+
+/// The current window's outcomes and the tuner's state. Cloning shares them.
+#[derive(Debug, Clone, Default)]
+pub struct RetryTuner {
+    window: Arc<parking_lot::Mutex<OutcomeSummary>>,
+    state: Arc<parking_lot::Mutex<TunerState>>,
+}
+
+impl RetryTuner {
+    /// Add a call to the window: answered at `answered_at`, or by no batch of its `batches`
+    /// over `rounds`, and whether an attempt in a failed batch timed out before it was answered.
+    pub(crate) fn record(&self, answered_at: Option<Position>, batches: usize, rounds: usize, timed_out_before: bool) {
+        let Some(last) = (batches * rounds).checked_sub(1) else { return };
+        let reached = answered_at.map_or(last, |position| position.round * batches + position.batch);
+        let mut window = self.window.lock();
+        window.calls += 1;
+        window.retried += u64::from(reached >= 1);
+        window.extra_rounds += u64::from(reached >= batches);
+        match answered_at {
+            Some(position) => {
+                window.first_retry_answered += u64::from(reached == 1);
+                window.extra_rounds_answered += u64::from(position.round >= 1);
+                window.timed_out_then_answered += u64::from(timed_out_before);
+            }
+            None => window.exhausted += 1,
+        }
+    }
+
+    /// The window so far, starting a new one.
+    fn take_window(&self) -> OutcomeSummary {
+        std::mem::take(&mut *self.window.lock())
+    }
+
+    /// Forget the baseline once the configured parameters are back in place.
+    pub(crate) fn forget_baseline(&self) {
+        self.state.lock().baseline = None;
+    }
+}
+
+impl RpcHandler {
+    /// Close the current window and apply what `decide` makes of it, as the background loop
+    /// does every `window`. Returns the change, or `None` when tuning is off or frozen, or the
+    /// window called for none.
+    pub async fn tune_retries(&self) -> Option<TuningRecord> {
+        let bounds = self.config().settings.retry_tuning?;
+        let evidence = self.tuner.take_window();
+        if self.tuner.state.lock().frozen {
+            return None;
+        }
+        let from = RetryParameters::from(&self.config().retry);
+        let decision = decide(&evidence, from, &bounds)?;
+        let record = TuningRecord { at: self.clock().now_system(), rule: decision.rule, from, to: decision.parameters, evidence };
+        let changes = self.apply_retry_parameters(decision.parameters).await;
+        {
+            let mut state = self.tuner.state.lock();
+            state.baseline.get_or_insert(from);
+            state.history.push_back(record.clone());
+            while state.history.len() > TUNING_HISTORY_LEN {
+                state.history.pop_front();
+            }
+        }
+        self.log("info", "Tuned retry parameters", Some(serde_json::json!({ "rule": record.rule, "from": from, "to": record.to, "evidence": evidence, "changes": changes }))).await;
+        Some(record)
+    }
+
+    /// Where tuning stands, and the changes it made.
+    pub fn retry_tuning(&self) -> TuningStatus {
+        let state = self.tuner.state.lock();
+        TuningStatus {
+            frozen: state.frozen,
+            parameters: RetryParameters::from(&self.config().retry),
+            baseline: state.baseline,
+            history: state.history.iter().cloned().collect(),
+            window: *self.tuner.window.lock(),
+        }
+    }
+
+    /// Stop changing the retry parameters, keeping them as they are.
+    pub fn freeze_retry_tuning(&self) {
+        self.tuner.state.lock().frozen = true;
+    }
+
+    /// Let tuning change the retry parameters again, from a fresh window.
+    pub fn unfreeze_retry_tuning(&self) {
+        self.tuner.take_window();
+        self.tuner.state.lock().frozen = false;
+    }
+
+    /// Put back the parameters from before the first change and start a fresh window. Returns
+    /// `false` if nothing had changed. The history is kept.
+    pub async fn reset_retry_tuning(&self) -> bool {
+        let Some(baseline) = self.tuner.state.lock().baseline.take() else { return false };
+        self.tuner.take_window();
+        let changes = self.apply_retry_parameters(baseline).await;
+        self.log("info", "Reset tuned retry parameters", Some(serde_json::json!({ "to": baseline, "changes": changes }))).await;
+        true
+    }
+
+    /// The running config with `parameters` swapped in, applied as `apply_config` applies one.
+    async fn apply_retry_parameters(&self, parameters: RetryParameters) -> Vec<FieldChange> {
+        let mut config = (*self.config()).clone();
+        config.retry = parameters.into();
+        self.apply_derived_config(config).await
+    }
+}
+
+/// Spawn the loop that tunes `handler`'s retry parameters every `config.window`. Like the other
+/// loops it holds only a weak reference, and ends once the handler is dropped or `shutdown` is
+/// cancelled.
+pub(crate) fn spawn_retry_tuner(handler: &Arc<RpcHandler>, config: RetryTuningConfig, heartbeat: Heartbeat, shutdown: CancellationToken) -> JoinHandle<()> {
+    let weak: Weak<RpcHandler> = Arc::downgrade(handler);
+    let clock = Arc::clone(handler.clock());
+
+    runtime::spawn(async move {
+        loop {
+            heartbeat.beat();
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = clock.sleep(config.window) => {}
+            }
+            let Some(handler) = weak.upgrade() else { return };
+            handler.tune_retries().await;
+        }
+    })
+}
@@ -1,11 +1,20 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
+
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 use crate::chainlist::{get_chain_info};
+use crate::error::{kb::ErrorMapping, Result, RpcHandlerError};
+use crate::maintenance::MaintenanceWindow;
+use crate::performance::EndpointProbe;
+use crate::region::Region;
+use crate::spend::CostProfile;
 
 pub type NetworkId = u64;
 pub type NetworkName = String;
 
+/// A resolved endpoint. Settings that only some endpoints carry, such as tiers, headers and
+/// cost profiles, are set on its `RpcConfig` and resolved into `NormalizedConfig` by URL.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Rpc {
     pub url: Url,
@@ -14,16 +23,125 @@ pub struct Rpc {
     pub is_open_source: Option<bool>
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// An injected endpoint as configured: a literal `url`, or a `url_template` whose placeholders
+/// are filled in from the handler's `SecretResolver` (see `secrets`).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RpcConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+    /// e.g. `https://eth-mainnet.g.alchemy.com/v2/{ALCHEMY_KEY}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_template: Option<String>,
+    pub tracking: Option<Tracking>,
+    pub tracking_details: Option<String>,
+    pub is_open_source: Option<bool>,
+    /// Failover priority tier, lower is preferred. Untiered endpoints sort last.
+    #[serde(default)]
+    pub tier: Option<u8>,
+    /// Announced downtime, during which the endpoint is left out
+    #[serde(default)]
+    pub maintenance_windows: Option<Vec<MaintenanceWindow>>,
+    /// Extra headers sent to this endpoint only, replacing the handler's where both set one
+    #[serde(default)]
+    pub headers: Option<BTreeMap<String, String>>,
+    /// What requests to it cost, unmetered when `None`
+    #[serde(default)]
+    pub cost_profile: Option<CostProfile>
+}
+
+impl RpcConfig {
+    pub fn template(url_template: impl Into<String>) -> Self {
+        Self { url_template: Some(url_template.into()), ..Self::default() }
+    }
+
+    /// The endpoint at `url`, with the rest of this config.
+    pub(crate) fn into_rpc(self, url: Url) -> Rpc {
+        Rpc {
+            url,
+            tracking: self.tracking,
+            tracking_details: self.tracking_details,
+            is_open_source: self.is_open_source,
+        }
+    }
+}
+
+impl From<Rpc> for RpcConfig {
+    fn from(rpc: Rpc) -> Self {
+        Self {
+            url: Some(rpc.url),
+            url_template: None,
+            tracking: rpc.tracking,
+            tracking_details: rpc.tracking_details,
+            is_open_source: rpc.is_open_source,
+            tier: None,
+            maintenance_windows: None,
+            headers: None,
+            cost_profile: None,
+        }
+    }
+}
+
+impl Rpc {
+    /// An endpoint at `url` with nothing else known about it. Only `http` and `https` URLs are
+    /// accepted; the error leaves the URL out, as it may carry a key.
+    pub fn from_url(url: &str) -> Result<Self> {
+        let url = Url::parse(url).map_err(|err| RpcHandlerError::InvalidRpcConfig { detail: format!("invalid RPC URL: {err}") })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(RpcHandlerError::InvalidRpcConfig { detail: format!("RPC URLs must be http or https, not {}", url.scheme()) });
+        }
+        Ok(Self {
+            url,
+            tracking: None,
+            tracking_details: None,
+            is_open_source: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub enum Tracking {
     Yes,
+    #[default]
     Limited,
     None
 }
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// How the retry proxy orders endpoints when failing over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FailoverPolicy {
+    /// Order purely by measured latency.
+    #[default]
+    Latency,
+    /// Order by tier first and by latency within a tier; a tier is exhausted before the next is tried.
+    TierStrict,
+}
+
+/// How strictly JSON-RPC responses are checked before they are handed back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ValidationMode {
+    /// Accept anything that deserializes.
+    #[default]
+    Lenient,
+    /// Reject structurally invalid responses (see `validation::STRICT_VALIDATORS`) and fail over.
+    Strict,
+}
+
+/// Which endpoints a handler may send requests to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Egress {
+    /// Its configured endpoints and those its `RpcSource` lists
+    #[default]
+    Open,
+    /// Only the endpoints in `network_rpcs` and those added with `add_rpc`. No source endpoints
+    /// are selected, and probes and side calls to any other URL are refused without sending.
+    InjectedOnly,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub enum LogLevel {
     Error,
     Warn,
+    #[default]
     Info,
     Debug,
     Trace
@@ -62,11 +180,594 @@ pub struct HandlerConfig {
 pub struct HandlerSettings {
         pub log_level: LogLevel,
         pub tracking: Tracking,
-        pub network_rpcs: Vec<Rpc>,
+        /// Endpoints added to the chainlist ones, by URL or URL template
+        pub network_rpcs: Vec<RpcConfig>,
         pub network_name: NetworkName,
         pub rpc_probe_timeout_ms: u64,
         pub proxy_settings: Option<ProxySettings>,
-        pub wipe_chain_data: WipeChainData
+        /// Which networks' chain data the handler keeps in view. Legacy `wipe_chain_data`
+        /// settings are still read and mapped onto it.
+        #[serde(default, alias = "wipe_chain_data")]
+        pub data_scope: DataScope,
+        /// Other ids the same chain goes by, e.g. from before a rebrand. Their registry endpoints
+        /// join the handler's, and endpoints may report any of them; the registry's own alias
+        /// hints are followed as well
+        #[serde(default)]
+        pub chain_aliases: Vec<NetworkId>,
+        #[serde(default)]
+        pub failover_policy: FailoverPolicy,
+        /// Pin each endpoint's hostname to the IP the probe measured until a failure or TTL expiry
+        #[serde(default)]
+        pub pin_resolved_ips: bool,
+        /// Keep latency snapshots in the page's `localStorage` when no `latency_store` is given.
+        /// Only on `wasm32`; native handlers ignore it
+        #[serde(default)]
+        pub browser_local_storage: bool,
+        /// Keep the active provider's connection warm while idle, off when `None`
+        #[serde(default)]
+        pub keepalive: Option<KeepaliveSettings>,
+        /// Dedicated endpoints for state-changing submissions; empty `methods` means `methods::write_methods()`
+        #[serde(default)]
+        pub write_endpoint: Option<RouteRule>,
+        /// Method routing rules, consulted in order after `write_endpoint`
+        #[serde(default)]
+        pub routes: Vec<RouteRule>,
+        #[serde(default)]
+        pub validation_mode: ValidationMode,
+        /// Re-send a POST once to a same-host redirect target instead of treating the redirect as a non-JSON-RPC answer
+        #[serde(default)]
+        pub follow_post_redirects: bool,
+        /// Entry caps for the handler's per-endpoint maps
+        #[serde(default)]
+        pub memory_limits: MemoryLimits,
+        /// Derive the probe timeout from recent healthy latencies, `rpc_probe_timeout_ms` only when `None`
+        #[serde(default)]
+        pub adaptive_probe_timeout: Option<AdaptiveProbeTimeout>,
+        /// Under `Strategy::FastStart`, how much faster the swept fastest endpoint must be to replace the provisional one
+        #[serde(default)]
+        pub fast_start_margin_ms: u64,
+        /// Concurrent request caps per hostname, shared by the proxy, consensus and probes
+        #[serde(default)]
+        pub host_limits: HostLimits,
+        /// Never return a head block more than `max_head_lag` behind the highest one already returned
+        #[serde(default)]
+        pub monotonic_head: bool,
+        /// Blocks an endpoint may trail the highest returned head under `monotonic_head`
+        #[serde(default)]
+        pub max_head_lag: u64,
+        /// How far the highest returned head may drop, as a reorg, when no endpoint can reach it any more
+        #[serde(default)]
+        pub head_reorg_tolerance: u64,
+        /// Re-probe endpoints in the background a few at a time, yielding to traffic, off when `None`
+        #[serde(default)]
+        pub auto_refresh: Option<AutoRefreshSettings>,
+        /// Retry `init` when no endpoint passes the probes, once only when `None`
+        #[serde(default)]
+        pub init_policy: Option<InitPolicy>,
+        /// Move the retry parameters within these bounds as outcomes go, fixed when `None`
+        #[serde(default)]
+        pub retry_tuning: Option<RetryTuning>,
+        /// Announced downtime per endpoint URL, on top of each `RpcConfig::maintenance_windows`
+        #[serde(default)]
+        pub maintenance_windows: HashMap<String, Vec<MaintenanceWindow>>,
+        /// How long before a window opens the active provider is moved off its endpoint
+        #[serde(default = "default_maintenance_lead_ms")]
+        pub maintenance_lead_ms: u64,
+        /// Endpoints a probe sweep measures at once, halved while they answer 429
+        #[serde(default = "default_max_concurrent_probes")]
+        pub max_concurrent_probes: usize,
+        /// Cut a probe sweep short after this long, keeping what it measured, unbounded when `None`
+        #[serde(default)]
+        pub probe_sweep_deadline_ms: Option<u64>,
+        /// Blocks a probed head may be off the sweep's most common head and still count as in sync
+        #[serde(default)]
+        pub max_probe_lag_blocks: u64,
+        /// Milliseconds added to an endpoint's latency per block it trailed at its last probe when
+        /// ordering endpoints, latency alone when `0`
+        #[serde(default)]
+        pub head_lag_penalty_ms: u64,
+        /// Regions of endpoints by `host:port`, hostname or parent domain, consulted before the
+        /// hostname heuristic, see `region`
+        #[serde(default)]
+        pub region_hints: BTreeMap<String, Region>,
+        /// Prefer endpoints in one region and damp switches between regions, off when `None`
+        #[serde(default)]
+        pub region_affinity: Option<RegionAffinityPolicy>,
+        /// `User-Agent` sent on every request, `DEFAULT_USER_AGENT` when `None`
+        #[serde(default)]
+        pub user_agent: Option<String>,
+        /// Send no `User-Agent` unless `user_agent` sets one; `Accept: */*` is always sent
+        #[serde(default)]
+        pub minimal_headers: bool,
+        /// Re-select the provider when served requests keep exceeding a latency target, off when `None`
+        #[serde(default)]
+        pub latency_slo: Option<LatencySlo>,
+        /// Spend ceiling for all metered endpoints together per UTC day, unlimited when `None`
+        #[serde(default)]
+        pub daily_spend_budget: Option<u64>,
+        /// Checks block timestamps against the local clock and flags endpoints that fail them,
+        /// off when `None`
+        #[serde(default)]
+        pub timestamp_sanity: Option<TimestampSanity>,
+        /// Answers to registry-`cacheable` methods kept for calls with equivalent params, none when `0`
+        #[serde(default)]
+        pub response_cache_entries: usize,
+        /// `null` answers to lookups by a concrete transaction or block hash kept until the head
+        /// watermark moves, none when `0`; usually far fewer than `response_cache_entries`
+        #[serde(default)]
+        pub negative_cache_entries: usize,
+        /// Samples recent block hashes across endpoints in the background and flags those that
+        /// keep disagreeing with their peers, off when `None`
+        #[serde(default)]
+        pub agreement_sampling: Option<AgreementSampling>,
+        /// Resolve a templated endpoint's secrets again when it answers `401` or `403`, and swap
+        /// in its new URL if they changed
+        #[serde(default)]
+        pub rotate_secrets_on_auth_error: bool,
+        /// Serve a node on this machine: no registry endpoints, and no Permit2 or sync checks
+        /// when probing. Set by `HandlerConfig::localnet`
+        #[serde(default)]
+        pub localnet: bool,
+        /// Where requests may go, anywhere by default
+        #[serde(default)]
+        pub egress: Egress,
+        /// Methods outside `methods::registry()` known to be free of side effects, so consensus
+        /// reads may send them to several endpoints
+        #[serde(default)]
+        pub idempotent_methods: Vec<String>,
+        /// Caller tag keys counted apart in the metrics; other keys are only logged, see `tags`
+        #[serde(default)]
+        pub metric_tag_keys: Vec<String>,
+        /// Error mappings consulted before the built-in ones, e.g. for one provider's own codes
+        #[serde(default)]
+        pub error_mappings: Vec<ErrorMapping>,
+        /// Warn, or with `strict` refuse to build, when the chain data is older than allowed,
+        /// unchecked when `None`
+        #[serde(default)]
+        pub staleness_policy: Option<DataStaleness>,
+        /// Application-specific checks run on each endpoint after the built-in probes. Not
+        /// serialized; set them in code
+        #[serde(skip)]
+        pub custom_probes: Vec<Arc<dyn EndpointProbe>>,
+        /// Whether an endpoint failing a custom probe is left out of selection or only reported
+        #[serde(default)]
+        pub custom_probe_policy: CustomProbePolicy,
+        /// One in-flight ceiling across all of the handler's traffic, probes held to a share of
+        /// it, off when `None`
+        #[serde(default)]
+        pub constrained_mode: Option<ConstrainedMode>,
+        /// When `RpcHandler::readiness` and `liveness` stop reporting ready and live
+        #[serde(default)]
+        pub readiness: ReadinessThresholds,
+}
+
+fn default_maintenance_lead_ms() -> u64 {
+    60_000
+}
+
+fn default_tuning_min_samples() -> u64 {
+    50
+}
+
+fn default_max_concurrent_probes() -> usize {
+    crate::performance::DEFAULT_MAX_CONCURRENT_PROBES
+}
+
+fn default_slo_penalty_ms() -> u64 {
+    60_000
+}
+
+fn default_sane_to_clear() -> u32 {
+    3
+}
+
+fn default_agreement_sample_size() -> usize {
+    3
+}
+
+fn default_agreement_min_depth() -> u64 {
+    3
+}
+
+fn default_agreement_max_depth() -> u64 {
+    16
+}
+
+fn default_agreement_window() -> usize {
+    20
+}
+
+fn default_agreement_min_samples() -> usize {
+    5
+}
+
+fn default_max_disagreement_rate() -> f64 {
+    0.2
+}
+
+fn default_probe_share() -> f64 {
+    0.25
+}
+
+/// Sends the listed methods to specific endpoints instead of the general pool.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RouteRule {
+    pub methods: Vec<String>,
+    pub urls: Vec<String>,
+    /// Whether the general pool may be used once the designated endpoints have all failed
+    #[serde(default)]
+    pub allow_failover: bool,
+}
+
+/// Idle keepalive for the active provider.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct KeepaliveSettings {
+    /// Idle time without requests before the first ping is sent
+    pub keepalive_after_ms: u64,
+    /// Time between pings while the provider stays idle
+    pub keepalive_interval_ms: u64,
+}
+
+/// Background re-probing of the endpoints, spread over ticks and deferred while the handler is busy.
+///
+/// A sweep probes `endpoints_per_tick` endpoints per tick, merging each tick's results into the
+/// latencies as it goes. A tick that finds `busy_in_flight` or more proxied requests in flight
+/// probes nothing and is retried after a jittered `tick_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AutoRefreshSettings {
+    /// Time from the end of one complete sweep to the start of the next
+    pub interval_ms: u64,
+    /// Time between the ticks of a sweep, and before retrying a deferred tick
+    pub tick_ms: u64,
+    pub endpoints_per_tick: usize,
+    /// Proxied requests in flight at which the handler counts as too busy to probe
+    pub busy_in_flight: usize,
+    /// Spread each sweep evenly over part of the interval instead, see `ProbeSmearing`
+    #[serde(default)]
+    pub smearing: Option<ProbeSmearing>,
+}
+
+/// Auto-refresh sweeps spread over part of the interval, so a fleet doesn't probe in bursts.
+///
+/// Sweeps start every `interval_ms`. Each one's probes, `endpoints_per_tick` at a time, get
+/// evenly spaced slots across `spread_percent` of the interval and go out at a random moment
+/// within their slot. Where in the interval the sweep falls, and which endpoint comes first,
+/// follow from `instance_seed`, so instances started together probe at different moments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProbeSmearing {
+    /// Share of `interval_ms` a sweep is spread over, 1 to 100
+    pub spread_percent: u8,
+    /// Seed of this instance's phase, derived from the host name when `None`
+    #[serde(default)]
+    pub instance_seed: Option<u64>,
+}
+
+/// How hard `RpcHandler::init` tries before giving up on finding a healthy endpoint.
+///
+/// Each attempt runs the strategy's selection again, the pause doubling from `attempt_backoff_ms`.
+/// When the last attempt finds nothing, `allow_unprobed_fallback` serves through the endpoints
+/// unprobed, in tier and then listing order, as `InitState::Degraded` until a refresh finds one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct InitPolicy {
+    /// Selections tried, the first included
+    pub max_attempts: u32,
+    /// Pause before the second attempt, doubled before each one after
+    pub attempt_backoff_ms: u64,
+    /// Serve unprobed rather than fail once the attempts run out
+    #[serde(default)]
+    pub allow_unprobed_fallback: bool,
+}
+
+/// Bounds within which `tuning` moves the retry parameters as outcomes go.
+///
+/// Every `window_ms` the outcomes of the calls in that window are summarized, and at most one of
+/// `retry_count`, `retry_delay_ms` and the batch size is changed by one step, never past these
+/// bounds. The configured `ProxySettings`, racing three endpoints at once, are the starting point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RetryTuning {
+    /// Time between changes, and the span each one looks back over
+    pub window_ms: u64,
+    /// Calls a window needs before any rule applies
+    #[serde(default = "default_tuning_min_samples")]
+    pub min_samples: u64,
+    /// Rounds over the failover plan, at least one
+    pub min_retry_count: u32,
+    pub max_retry_count: u32,
+    pub min_retry_delay_ms: u64,
+    pub max_retry_delay_ms: u64,
+    /// Endpoints raced at once, at least one
+    pub min_batch_size: usize,
+    pub max_batch_size: usize,
+}
+
+/// Maximum entry counts for the handler's per-endpoint maps.
+///
+/// See `memory` for what gets evicted first and what is never evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MemoryLimits {
+    /// Latency and lagging-endpoint maps
+    pub max_latency_entries: usize,
+    /// Client versions, capabilities, keepalive failures and malformed-response counts
+    pub max_endpoint_entries: usize,
+    /// Consensus cooldowns
+    pub max_cooldown_entries: usize,
+    /// Probe skips, DNS pins and dead hosts
+    pub max_negative_entries: usize,
+}
+
+/// A latency budget for proxied requests that a degraded provider is moved away from.
+///
+/// Every successful request slower than `target_ms` counts as a violation against the endpoint
+/// that served it. `max_violations` within `violation_window_ms` re-select the active provider
+/// and push the endpoint behind the others for `penalty_ms`, although none of its requests failed.
+/// Methods `methods::is_heavy` lists, and those in `ignore_methods`, never count.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LatencySlo {
+    pub target_ms: u64,
+    pub violation_window_ms: u64,
+    pub max_violations: usize,
+    /// How long an endpoint that breached the budget stays behind the others
+    #[serde(default = "default_slo_penalty_ms")]
+    pub penalty_ms: u64,
+    /// Further methods whose latency is never held against an endpoint
+    #[serde(default)]
+    pub ignore_methods: Vec<String>,
+    /// Which latency is held to `target_ms`
+    #[serde(default)]
+    pub measure: SloMeasure,
+}
+
+/// The latency `LatencySlo` holds to its target, see `metrics::RequestTiming`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SloMeasure {
+    /// From the first attempt going out to the answer
+    #[default]
+    Service,
+    /// From the call to the answer, time spent waiting for a host slot included
+    Total,
+}
+
+/// What a failed `HandlerSettings::custom_probes` check does to its endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomProbePolicy {
+    /// Treat the endpoint as unhealthy until a later probe passes
+    #[default]
+    Exclude,
+    /// Keep it selectable and only report the failure
+    Annotate,
+}
+
+/// How much endpoint regions count when picking and ordering endpoints, see `region`.
+///
+/// Endpoints known to be outside the preferred region are ordered as if `penalty_ms` slower.
+/// After a probe sweep the active provider is only replaced by an endpoint faster than it by
+/// more than `switch_margin_ms`, or by more than `cross_region_switch_margin_ms` when the two
+/// are known to be in different regions, so two continents measuring alike in a quiet moment
+/// don't trade places on every refresh. Endpoints whose region isn't known are never penalized,
+/// and no endpoint is left out for its region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RegionAffinityPolicy {
+    /// Region to prefer, that of the fastest endpoint whose region is known when `None`
+    #[serde(default)]
+    pub preferred: Option<Region>,
+    #[serde(default)]
+    pub penalty_ms: u64,
+    #[serde(default)]
+    pub switch_margin_ms: u64,
+    pub cross_region_switch_margin_ms: u64,
+}
+
+/// Bounds on how far block timestamps may stray from the local clock.
+///
+/// A block dated more than `max_future_drift_ms` ahead flags its endpoint `ClockSkewSuspected`,
+/// and a head block dated more than `max_past_lag_ms` behind flags it `BlocksLagging`. Flagged
+/// endpoints go behind the others, lagging ones are left out of state reads, and a flag clears
+/// after `sane_to_clear` sane blocks in a row. With `strict`, a skewed block fails its attempt
+/// and the request fails over.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TimestampSanity {
+    pub max_future_drift_ms: u64,
+    /// Off when `None`; block times vary, so leave a few blocks' worth of slack
+    #[serde(default)]
+    pub max_past_lag_ms: Option<u64>,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default = "default_sane_to_clear")]
+    pub sane_to_clear: u32,
+}
+
+/// How old the chain data may be when a handler is built.
+///
+/// The age is taken from `chainlist::data_provenance()`: the embedded data ages from the build,
+/// and `chainlist::refresh_from_network` resets it. Data that was never fetched, as in an offline
+/// build, counts as older than any limit. Older data emits `HandlerEvent::ChainDataStale`, or
+/// with `strict` fails construction with `RpcHandlerError::ChainDataStale`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DataStaleness {
+    pub max_age_ms: u64,
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// Background checks that the endpoints agree on the chain they serve.
+///
+/// Every `interval_ms`, one block between `min_depth` and `max_depth` blocks behind the head is
+/// picked at random and its hash fetched from `sample_size` randomly chosen endpoints. Each one
+/// that answered is recorded as agreeing or disagreeing with the majority hash; rounds without
+/// a strict majority record nothing. Once an endpoint has `min_samples` of its last `window`
+/// samples and disagreed in more than `max_disagreement_rate` of them, it is flagged
+/// `SuspectedDishonest`: consensus calls stop asking it, and with `exclude_from_reads` so do
+/// proxied reads. The flag clears when its rate drops back to the limit.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AgreementSampling {
+    pub interval_ms: u64,
+    /// Endpoints asked per round, at least two
+    #[serde(default = "default_agreement_sample_size")]
+    pub sample_size: usize,
+    /// Blocks behind the head the sampled block is at least, clear of propagation noise
+    #[serde(default = "default_agreement_min_depth")]
+    pub min_depth: u64,
+    #[serde(default = "default_agreement_max_depth")]
+    pub max_depth: u64,
+    /// Recent samples per endpoint the rate is taken over
+    #[serde(default = "default_agreement_window")]
+    pub window: usize,
+    /// Samples an endpoint needs before it can be flagged
+    #[serde(default = "default_agreement_min_samples")]
+    pub min_samples: usize,
+    #[serde(default = "default_max_disagreement_rate")]
+    pub max_disagreement_rate: f64,
+    /// Also leave flagged endpoints out of proxied reads, not only out of consensus
+    #[serde(default)]
+    pub exclude_from_reads: bool,
+}
+
+/// Caps on concurrent requests per hostname, unlimited by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HostLimits {
+    /// Cap for hostnames without an entry in `per_host`, unlimited when `None`
+    #[serde(default)]
+    pub default_per_host: Option<usize>,
+    /// Caps for specific hostnames
+    #[serde(default)]
+    pub per_host: HashMap<String, usize>,
+    /// Read the quota hosts advertise in response headers and pace requests to those running low,
+    /// off when `None`
+    #[serde(default)]
+    pub rate_limit_headers: Option<RateLimitHeaders>,
+}
+
+/// A single budget for everything the handler sends, for hosts short on CPU and sockets.
+///
+/// At most `max_total_inflight` requests are in flight at once, whatever sent them: proxied,
+/// batch, streamed and consensus requests on one side, probes, keepalive pings, shadow replays
+/// and background sampling on the other. The latter never hold more than `probe_share` of the
+/// slots, at least one, and whenever a slot frees up a waiting request gets it before a waiting
+/// probe. Host caps still apply on top.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ConstrainedMode {
+    pub max_total_inflight: usize,
+    /// Fraction of `max_total_inflight` background traffic may hold
+    #[serde(default = "default_probe_share")]
+    pub probe_share: f64,
+}
+
+/// A way providers advertise their remaining quota in response headers.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitScheme {
+    /// `x-ratelimit-remaining` and `x-ratelimit-reset`
+    XRateLimit,
+    /// `ratelimit-remaining` and `ratelimit-reset`, or a combined `ratelimit` header, from the
+    /// IETF draft
+    RateLimit,
+    /// `Retry-After`, on any status: nothing left until it passes
+    RetryAfter,
+    /// Other header names, read like `XRateLimit`
+    Custom { remaining: String, reset: String },
+}
+
+impl RateLimitScheme {
+    /// The schemes read when none are configured.
+    pub const PRESETS: [RateLimitScheme; 3] = [RateLimitScheme::XRateLimit, RateLimitScheme::RateLimit, RateLimitScheme::RetryAfter];
+}
+
+/// Pacing by advertised quota, under `HostLimits::rate_limit_headers`.
+///
+/// A reset header holds seconds until the reset, or a Unix timestamp once it is past
+/// 1,000,000,000. Headers that are missing or don't parse are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateLimitHeaders {
+    /// Schemes read from every host, the first one a response carries wins
+    #[serde(default = "rate_limit_presets")]
+    pub schemes: Vec<RateLimitScheme>,
+    /// Replacements for `schemes` for specific hostnames
+    #[serde(default)]
+    pub per_host: HashMap<String, Vec<RateLimitScheme>>,
+    /// Below this many requests left, a host gets its remaining quota spread evenly over the
+    /// time to its reset, and sits out races while others can take its requests
+    pub remaining_floor: u64,
+}
+
+fn rate_limit_presets() -> Vec<RateLimitScheme> {
+    RateLimitScheme::PRESETS.to_vec()
+}
+
+impl Default for RateLimitHeaders {
+    fn default() -> Self {
+        Self { schemes: rate_limit_presets(), per_host: HashMap::new(), remaining_floor: 10 }
+    }
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        Self {
+            max_latency_entries: 1024,
+            max_endpoint_entries: 1024,
+            max_cooldown_entries: 1024,
+            max_negative_entries: 1024,
+        }
+    }
+}
+
+/// Limits for `RpcHandler::readiness` and `liveness`.
+///
+/// Readiness goes by the last outcome that found any endpoint up, so an idle handler stays ready
+/// only while keepalive or auto-refresh keeps probing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReadinessThresholds {
+    /// Longest since an endpoint last answered before the handler is not ready
+    #[serde(default = "default_max_success_age_ms")]
+    pub max_success_age_ms: u64,
+    /// Held calls above which the handler is not ready, unlimited when `None`
+    #[serde(default)]
+    pub max_pending: Option<usize>,
+    /// Time a periodic background loop may overrun its period, e.g. on a slow sweep, before the
+    /// handler is not live
+    #[serde(default = "default_heartbeat_grace_ms")]
+    pub heartbeat_grace_ms: u64,
+}
+
+impl Default for ReadinessThresholds {
+    fn default() -> Self {
+        Self {
+            max_success_age_ms: default_max_success_age_ms(),
+            max_pending: None,
+            heartbeat_grace_ms: default_heartbeat_grace_ms(),
+        }
+    }
+}
+
+fn default_max_success_age_ms() -> u64 {
+    5 * 60_000
+}
+
+fn default_heartbeat_grace_ms() -> u64 {
+    30_000
+}
+
+/// Probe timeout derived from the network's recent healthy probe latencies.
+///
+/// Endpoints that were healthy at the previous probe get `multiplier` times the p95 of recent
+/// healthy latencies, clamped to `[floor_ms, ceiling_ms]`. Endpoints without a healthy probe
+/// yet, and every endpoint before any history exists, get `rpc_probe_timeout_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct AdaptiveProbeTimeout {
+    pub multiplier: f64,
+    pub floor_ms: u64,
+    pub ceiling_ms: u64,
+}
+
+impl Default for AdaptiveProbeTimeout {
+    fn default() -> Self {
+        Self {
+            multiplier: 3.0,
+            floor_ms: 500,
+            ceiling_ms: 10_000,
+        }
+    }
 }
 
 impl Default for HandlerSettings {
@@ -78,16 +779,53 @@ impl Default for HandlerSettings {
             network_name: "Unknown".to_string(),
             rpc_probe_timeout_ms: 3000,
             proxy_settings: Some(ProxySettings::default()),
-            wipe_chain_data: WipeChainData::default(),
-        }
-    }
-}
-
-impl Default for WipeChainData {
-    fn default() -> Self {
-        Self {
-            clear_data: false,
-            retain_these_chains: Vec::new(),
+            data_scope: DataScope::default(),
+            chain_aliases: Vec::new(),
+            failover_policy: FailoverPolicy::default(),
+            pin_resolved_ips: false,
+            browser_local_storage: false,
+            keepalive: None,
+            write_endpoint: None,
+            routes: Vec::new(),
+            validation_mode: ValidationMode::default(),
+            follow_post_redirects: false,
+            memory_limits: MemoryLimits::default(),
+            adaptive_probe_timeout: None,
+            fast_start_margin_ms: 0,
+            host_limits: HostLimits::default(),
+            monotonic_head: false,
+            max_head_lag: 0,
+            head_reorg_tolerance: 0,
+            auto_refresh: None,
+            init_policy: None,
+            retry_tuning: None,
+            maintenance_windows: HashMap::new(),
+            maintenance_lead_ms: default_maintenance_lead_ms(),
+            max_concurrent_probes: default_max_concurrent_probes(),
+            probe_sweep_deadline_ms: None,
+            max_probe_lag_blocks: 0,
+            head_lag_penalty_ms: 0,
+            region_hints: BTreeMap::new(),
+            region_affinity: None,
+            user_agent: None,
+            minimal_headers: false,
+            latency_slo: None,
+            daily_spend_budget: None,
+            timestamp_sanity: None,
+            response_cache_entries: 0,
+            negative_cache_entries: 0,
+            agreement_sampling: None,
+            rotate_secrets_on_auth_error: false,
+            localnet: false,
+            egress: Egress::default(),
+            idempotent_methods: Vec::new(),
+            metric_tag_keys: Vec::new(),
+            error_mappings: Vec::new(),
+            staleness_policy: None,
+            custom_probes: Vec::new(),
+            custom_probe_policy: CustomProbePolicy::default(),
+            constrained_mode: None,
+            readiness: ReadinessThresholds::default(),
         }
     }
 }
@@ -107,24 +845,143 @@ impl HandlerConfig {
                 log_level: LogLevel::Error,
                 tracking: Tracking::Limited,
                 network_rpcs: Vec::new(), 
-                network_name: get_chain_info(network_id).unwrap().name,
+                network_name: get_chain_info(network_id).map(|chain| chain.name).unwrap_or_else(|| format!("chain-{network_id}")),
                 rpc_probe_timeout_ms: 3000,
                 proxy_settings: Some(ProxySettings::default()),
-                wipe_chain_data: WipeChainData::new(network_id)
+                data_scope: DataScope::OnlyThisNetwork,
+                chain_aliases: Vec::new(),
+                failover_policy: FailoverPolicy::default(),
+                pin_resolved_ips: false,
+                browser_local_storage: false,
+                keepalive: None,
+                write_endpoint: None,
+                routes: Vec::new(),
+                validation_mode: ValidationMode::default(),
+                follow_post_redirects: false,
+                memory_limits: MemoryLimits::default(),
+                adaptive_probe_timeout: None,
+                fast_start_margin_ms: 0,
+                host_limits: HostLimits::default(),
+                monotonic_head: false,
+                max_head_lag: 0,
+                head_reorg_tolerance: 0,
+                auto_refresh: None,
+                init_policy: None,
+                retry_tuning: None,
+                maintenance_windows: HashMap::new(),
+                maintenance_lead_ms: default_maintenance_lead_ms(),
+                max_concurrent_probes: default_max_concurrent_probes(),
+                probe_sweep_deadline_ms: None,
+                max_probe_lag_blocks: 0,
+                head_lag_penalty_ms: 0,
+                region_hints: BTreeMap::new(),
+                region_affinity: None,
+                user_agent: None,
+                minimal_headers: false,
+                latency_slo: None,
+                daily_spend_budget: None,
+                timestamp_sanity: None,
+                response_cache_entries: 0,
+                negative_cache_entries: 0,
+                agreement_sampling: None,
+                rotate_secrets_on_auth_error: false,
+                localnet: false,
+                egress: Egress::default(),
+                idempotent_methods: Vec::new(),
+                metric_tag_keys: Vec::new(),
+                error_mappings: Vec::new(),
+                staleness_policy: None,
+                custom_probes: Vec::new(),
+                custom_probe_policy: CustomProbePolicy::default(),
+                constrained_mode: None,
+                readiness: ReadinessThresholds::default(),
             })
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct WipeChainData {
-    pub clear_data: bool,
-    pub retain_these_chains: Vec<NetworkId>
+/// Which networks' chain data a handler keeps in view. The shared chain data itself is never
+/// pruned, so handlers with different scopes don't affect each other.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", from = "legacy::DataScopeRepr")]
+pub enum DataScope {
+    /// Everything, as it is updated
+    #[default]
+    Global,
+    /// The handler's own network
+    OnlyThisNetwork,
+    /// These networks, which must include the handler's own
+    Networks(Vec<NetworkId>),
 }
 
-impl WipeChainData {
-    fn new(network_id: NetworkId) -> Self {
-        Self { clear_data: true, retain_these_chains: [network_id].to_vec() }
+impl DataScope {
+    /// The networks kept for a handler on `network_id`, or `None` for all of them.
+    pub fn retained(&self, network_id: NetworkId) -> Option<Vec<NetworkId>> {
+        match self {
+            Self::Global => None,
+            Self::OnlyThisNetwork => Some(vec![network_id]),
+            Self::Networks(networks) => Some(networks.clone()),
+        }
+    }
+}
+
+#[allow(deprecated)]
+pub use legacy::WipeChainData;
+
+/// The `wipe_chain_data` settings `DataScope` replaced, still read from older configs.
+#[allow(deprecated)]
+mod legacy {
+    use serde::{Deserialize, Serialize};
+
+    use super::{DataScope, NetworkId};
+
+    #[deprecated(note = "use `DataScope`")]
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct WipeChainData {
+        pub clear_data: bool,
+        pub retain_these_chains: Vec<NetworkId>
+    }
+
+    impl From<WipeChainData> for DataScope {
+        fn from(legacy: WipeChainData) -> Self {
+            match (legacy.clear_data, legacy.retain_these_chains.is_empty()) {
+                (false, _) => Self::Global,
+                // This used to wipe every chain, the handler's own included
+                (true, true) => Self::OnlyThisNetwork,
+                (true, false) => Self::Networks(legacy.retain_these_chains),
+            }
+        }
+    }
+
+    /// Either spelling of a data scope in a config.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub(super) enum DataScopeRepr {
+        Current(DataScopeDef),
+        Legacy(WipeChainData),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub(super) enum DataScopeDef {
+        Global,
+        OnlyThisNetwork,
+        Networks(Vec<NetworkId>),
+    }
+
+    impl From<DataScopeRepr> for DataScope {
+        fn from(repr: DataScopeRepr) -> Self {
+            match repr {
+                DataScopeRepr::Current(DataScopeDef::Global) => Self::Global,
+                DataScopeRepr::Current(DataScopeDef::OnlyThisNetwork) => Self::OnlyThisNetwork,
+                DataScopeRepr::Current(DataScopeDef::Networks(networks)) => Self::Networks(networks),
+                DataScopeRepr::Legacy(legacy) => {
+                    let scope = Self::from(legacy);
+                    tracing::warn!(?scope, "`wipe_chain_data` is deprecated, set `data_scope` instead");
+                    scope
+                }
+            }
+        }
     }
 }
 
@@ -132,7 +989,13 @@ impl WipeChainData {
 pub struct ProxySettings {
     pub retry_count: u32,
     pub retry_delay_ms: u64,
-    pub rpc_call_timeout_ms: u64
+    /// Total budget for one call, connecting included
+    pub rpc_call_timeout_ms: u64,
+    /// Budget for establishing the connection alone. A call that can't connect in time fails
+    /// with `ConnectTimeout` and the endpoint is skipped for the rest of the request, while a
+    /// slow answer only ever runs into the total budget. `None` leaves connecting unbounded.
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>
 }
 
 /**
@@ -145,7 +1008,8 @@ impl Default for ProxySettings {
         Self {
             retry_count: 3,
             retry_delay_ms: 1000,
-            rpc_call_timeout_ms: 5000
+            rpc_call_timeout_ms: 5000,
+            connect_timeout_ms: None
         }
     }
 }
@@ -0,0 +1,119 @@
+//! Structural checks applied to raw JSON-RPC responses in `ValidationMode::Strict`.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{methods, JsonRpcId, JsonRpcRequest};
+
+/// `jsonrpc` versions accepted from providers.
+pub const ACCEPTED_JSONRPC_VERSIONS: &[&str] = &["2.0"];
+
+/// Expected shape of `result` for a known method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultShape {
+    /// `0x`-prefixed hex quantity without leading zeros, e.g. `0x0`, `0x1a`
+    HexQuantity,
+    /// `0x`-prefixed hex byte string, e.g. `0x`, `0x6080`
+    HexData,
+    /// A JSON object, or `null` when the requested item doesn't exist
+    ObjectOrNull,
+}
+
+/// Result shapes for methods whose responses are checked beyond the envelope.
+pub const RESULT_SHAPES: &[(&str, ResultShape)] = &[
+    ("eth_blockNumber", ResultShape::HexQuantity),
+    ("eth_chainId", ResultShape::HexQuantity),
+    ("eth_gasPrice", ResultShape::HexQuantity),
+    ("eth_maxPriorityFeePerGas", ResultShape::HexQuantity),
+    ("eth_getBalance", ResultShape::HexQuantity),
+    ("eth_getTransactionCount", ResultShape::HexQuantity),
+    ("eth_estimateGas", ResultShape::HexQuantity),
+    ("eth_call", ResultShape::HexData),
+    ("eth_getCode", ResultShape::HexData),
+    ("eth_getBlockByNumber", ResultShape::ObjectOrNull),
+    ("eth_getBlockByHash", ResultShape::ObjectOrNull),
+];
+
+/// A named check over the request and the raw response body, returning a description of the
+/// violation if it fails.
+pub struct Validator {
+    pub name: &'static str,
+    pub check: fn(&JsonRpcRequest, &Value) -> Option<String>,
+}
+
+/// Checks run, in order, on every response in strict mode.
+pub const STRICT_VALIDATORS: &[Validator] = &[
+    Validator { name: "jsonrpc_version", check: check_jsonrpc_version },
+    Validator { name: "result_xor_error", check: check_result_xor_error },
+    Validator { name: "id_matches", check: check_id_matches },
+    Validator { name: "result_shape", check: check_result_shape },
+];
+
+/// Run every strict validator and report the first violation as `"<name>: <detail>"`.
+pub fn validate_response(request: &JsonRpcRequest, response: &Value) -> Option<String> {
+    STRICT_VALIDATORS
+        .iter()
+        .find_map(|validator| (validator.check)(request, response).map(|detail| format!("{}: {detail}", validator.name)))
+}
+
+pub fn check_jsonrpc_version(_request: &JsonRpcRequest, response: &Value) -> Option<String> {
+    match response.get("jsonrpc").and_then(Value::as_str) {
+        Some(version) if ACCEPTED_JSONRPC_VERSIONS.contains(&version) => None,
+        Some(version) => Some(format!("unsupported version {version:?}")),
+        None => Some("missing jsonrpc version".to_string()),
+    }
+}
+
+/// Exactly one of `result` and `error` must be present, and a present `result` must not be
+/// `null` unless `methods::null_result_valid` allows it.
+pub fn check_result_xor_error(request: &JsonRpcRequest, response: &Value) -> Option<String> {
+    let result = response.get("result");
+    let has_error = response.get("error").is_some_and(|error| !error.is_null());
+
+    match (result, has_error) {
+        (Some(_), true) => Some("both result and error present".to_string()),
+        (None, false) => Some("neither result nor error present".to_string()),
+        (Some(Value::Null), false) if !methods::null_result_valid(&request.method) => Some("result is null without an error".to_string()),
+        _ => None,
+    }
+}
+
+/// The response id must echo the request id with the same JSON type.
+pub fn check_id_matches(request: &JsonRpcRequest, response: &Value) -> Option<String> {
+    let id = response.get("id").unwrap_or(&Value::Null);
+    let expected = request.id.as_ref().unwrap_or(&JsonRpcId::Null);
+    match JsonRpcId::deserialize(id) {
+        Ok(ref got) if got == expected => None,
+        _ => Some(format!("expected id {expected}, got {id}")),
+    }
+}
+
+pub fn check_result_shape(request: &JsonRpcRequest, response: &Value) -> Option<String> {
+    let (shape, result) = (result_shape(&request.method)?, response.get("result")?);
+    let ok = match shape {
+        ResultShape::HexQuantity => result.as_str().is_some_and(is_hex_quantity),
+        ResultShape::HexData => result.as_str().is_some_and(is_hex_data),
+        ResultShape::ObjectOrNull => result.is_object() || result.is_null(),
+    };
+    (!ok).then(|| format!("expected {shape:?}, got {result}"))
+}
+
+pub fn result_shape(method: &str) -> Option<ResultShape> {
+    RESULT_SHAPES.iter().find(|(m, _)| *m == method).map(|(_, shape)| *shape)
+}
+
+pub fn is_hex_quantity(value: &str) -> bool {
+    let Some(digits) = value.strip_prefix("0x") else { return false };
+    match digits.as_bytes() {
+        [] => false,
+        [b'0'] => true,
+        [b'0', ..] => false,
+        bytes => bytes.iter().all(u8::is_ascii_hexdigit),
+    }
+}
+
+pub fn is_hex_data(value: &str) -> bool {
+    value
+        .strip_prefix("0x")
+        .is_some_and(|digits| digits.len() % 2 == 0 && digits.bytes().all(|b| b.is_ascii_hexdigit()))
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "abi")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::{abi::{encode, selector}, *};
+use serde_json::json;
+use wiremock::{matchers::{body_partial_json, method}, Mock, MockServer, ResponseTemplate};
+
+const TOKEN: &str = "0x6b175474e89094c44da98b954eedeac495271d0f";
+const HOLDER: &str = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045";
+
+/// Concatenated words, without whitespace.
+fn words(words: &[&str]) -> String {
+    words.concat()
+}
+
+#[test]
+fn test_selectors() {
+    assert_eq!(selector("balanceOf(address)").unwrap(), [0x70, 0xa0, 0x82, 0x31]);
+    assert_eq!(selector("transfer(address,uint256)").unwrap(), [0xa9, 0x05, 0x9c, 0xbb]);
+    // Aliases and parameter names are canonicalized away
+    assert_eq!(selector("transfer(address to, uint amount)").unwrap(), selector("transfer(address,uint256)").unwrap());
+    assert_eq!(selector("baz(uint32,bool)").unwrap(), [0xcd, 0xcd, 0x77, 0xc0]);
+}
+
+#[test]
+fn test_static_encoding() {
+    let data = CallDataBuilder::function("baz(uint32,bool)").arg_uint(69).arg_bool(true).build();
+    assert_eq!(data, format!("0xcdcd77c0{}", words(&[
+        "0000000000000000000000000000000000000000000000000000000000000045",
+        "0000000000000000000000000000000000000000000000000000000000000001",
+    ])));
+
+    let data = CallDataBuilder::function("balanceOf(address)").arg_address(&HOLDER.to_uppercase().replace("0X", "0x")).build();
+    assert_eq!(data, format!("0x70a08231000000000000000000000000{}", &HOLDER[2..]));
+
+    let negative = encode(&[AbiType::Int(256), AbiType::Int(8)], &[AbiValue::int(-1), AbiValue::int(-128)]).unwrap();
+    assert_eq!(negative[..32], [0xff; 32]);
+    assert_eq!((negative[32..63].iter().all(|byte| *byte == 0xff), negative[63]), (true, 0x80));
+}
+
+#[test]
+fn test_dynamic_encoding_uses_the_offset_layout() {
+    // The examples from the Solidity ABI specification
+    let data = CallDataBuilder::function("sam(bytes,bool,uint256[])")
+        .arg_bytes(b"dave")
+        .arg_bool(true)
+        .arg_array(vec![AbiValue::uint(1), AbiValue::uint(2), AbiValue::uint(3)])
+        .build();
+    assert_eq!(data, format!("0xa5643bf2{}", words(&[
+        "0000000000000000000000000000000000000000000000000000000000000060",
+        "0000000000000000000000000000000000000000000000000000000000000001",
+        "00000000000000000000000000000000000000000000000000000000000000a0",
+        "0000000000000000000000000000000000000000000000000000000000000004",
+        "6461766500000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000003",
+        "0000000000000000000000000000000000000000000000000000000000000001",
+        "0000000000000000000000000000000000000000000000000000000000000002",
+        "0000000000000000000000000000000000000000000000000000000000000003",
+    ])));
+
+    let data = CallDataBuilder::function("f(uint256,uint32[],bytes10,bytes)")
+        .arg_uint(0x123)
+        .arg_array(vec![AbiValue::uint(0x456), AbiValue::uint(0x789)])
+        .arg_fixed_bytes(b"1234567890")
+        .arg_bytes(b"Hello, world!")
+        .build();
+    assert_eq!(data, format!("0x8be65246{}", words(&[
+        "0000000000000000000000000000000000000000000000000000000000000123",
+        "0000000000000000000000000000000000000000000000000000000000000080",
+        "3132333435363738393000000000000000000000000000000000000000000000",
+        "00000000000000000000000000000000000000000000000000000000000000e0",
+        "0000000000000000000000000000000000000000000000000000000000000002",
+        "0000000000000000000000000000000000000000000000000000000000000456",
+        "0000000000000000000000000000000000000000000000000000000000000789",
+        "000000000000000000000000000000000000000000000000000000000000000d",
+        "48656c6c6f2c20776f726c642100000000000000000000000000000000000000",
+    ])));
+}
+
+#[test]
+fn test_decoding_round_trips() {
+    let types = ["string", "address", "int8", "string[]", "bytes32", "bool"];
+    let values = vec![
+        AbiValue::String("Dai Stablecoin".to_string()),
+        AbiValue::Address(HOLDER.to_string()),
+        AbiValue::int(-5),
+        AbiValue::Array(["one", "two", "three"].iter().map(|s| AbiValue::String(s.to_string())).collect()),
+        AbiValue::FixedBytes(vec![0xab; 32]),
+        AbiValue::Bool(false),
+    ];
+    let parsed: Vec<AbiType> = types.iter().map(|name| AbiType::parse(name).unwrap()).collect();
+    let encoded: String = encode(&parsed, &values).unwrap().iter().map(|byte| format!("{byte:02x}")).collect();
+    let decoded = decode_return(&types, &format!("0x{encoded}")).unwrap();
+    assert_eq!(decoded, values);
+    assert_eq!((decoded[0].as_str(), decoded[2].as_i128(), decoded[3].as_array().map(<[_]>::len)), (Some("Dai Stablecoin"), Some(-5), Some(3)));
+
+    let name = decode_return(&["string"], &format!("0x{}", words(&[
+        "0000000000000000000000000000000000000000000000000000000000000020",
+        "0000000000000000000000000000000000000000000000000000000000000003",
+        "4441490000000000000000000000000000000000000000000000000000000000",
+    ]))).unwrap();
+    assert_eq!(name, [AbiValue::String("DAI".to_string())]);
+}
+
+#[test]
+fn test_invalid_input_is_rejected() {
+    let invalid = |result: Result<String>| matches!(result, Err(RpcHandlerError::InvalidAbi { .. }));
+    assert!(invalid(CallDataBuilder::function("balanceOf(address)").try_build()));
+    assert!(invalid(CallDataBuilder::function("balanceOf(address)").arg_uint(1).try_build()));
+    assert!(invalid(CallDataBuilder::function("balanceOf(address)").arg_address("0x1234").try_build()));
+    assert!(invalid(CallDataBuilder::function("set(uint8)").arg_uint(256).try_build()));
+    assert!(invalid(CallDataBuilder::function("set(int8)").arg_int(-129).try_build()));
+    assert!(invalid(CallDataBuilder::function("set(uint7)").arg_uint(1).try_build()));
+    assert!(invalid(CallDataBuilder::function("set(uint256[][])").arg_array(Vec::new()).try_build()));
+    assert!(invalid(CallDataBuilder::function("set(bytes4)").arg_fixed_bytes(&[1, 2]).try_build()));
+    assert!(invalid(CallDataBuilder::function("set(address").try_build()));
+    assert!(CallDataBuilder::function("set(uint8)").arg_uint(255).try_build().is_ok());
+
+    assert!(matches!(decode_return(&["uint256"], "0x01"), Err(RpcHandlerError::InvalidAbi { .. })));
+    assert!(matches!(decode_return(&["bool"], &format!("0x{:064x}", 2)), Err(RpcHandlerError::InvalidAbi { .. })));
+    // A length far past the data doesn't allocate for it
+    assert!(matches!(decode_return(&["uint256[]"], &format!("0x{:064x}{:064x}", 32, u64::MAX)), Err(RpcHandlerError::InvalidAbi { .. })));
+}
+
+#[tokio::test]
+async fn test_view_call_sends_exact_calldata_and_decodes() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let calldata = format!("0x70a08231000000000000000000000000{}", &HOLDER[2..]);
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_call", "params": [{ "to": TOKEN, "data": calldata }, "0x10"] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(format!("0x{:064x}", 1_500_000_000_000_000_000u128)))))
+        .mount(&server)
+        .await;
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&server)])), None).await.unwrap();
+    handler.init().await.unwrap();
+    let calls = RpcCalls::new(handler);
+
+    let balance = calls.view_call(TOKEN, "balanceOf(address)(uint256)", vec![AbiValue::Address(HOLDER.to_string())], "0x10").await.unwrap();
+    assert_eq!(balance.iter().map(AbiValue::as_u128).collect::<Vec<_>>(), [Some(1_500_000_000_000_000_000)]);
+    assert_eq!(count_method(&server, "eth_call").await, 1);
+
+    let untyped = calls.view_call(TOKEN, "balanceOf(address)", vec![AbiValue::Address(HOLDER.to_string())], "0x10").await;
+    assert!(matches!(untyped, Err(RpcHandlerError::InvalidAbi { .. })));
+}
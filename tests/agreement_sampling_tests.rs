@@ -0,0 +1,166 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// Two endpoints serving the `0xabc` chain and one serving `0xdef` at every height.
+struct Endpoints {
+    honest: Vec<MockServer>,
+    dishonest: MockServer,
+}
+
+impl Endpoints {
+    async fn start() -> Self {
+        let mut honest = Vec::new();
+        for _ in 0..2 {
+            let server = MockServer::start().await;
+            mount_probe(&server, "0x100", Duration::ZERO).await;
+            honest.push(server);
+        }
+        let dishonest = MockServer::start().await;
+        mount_method(&dishonest, "eth_getBlockByNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x100", "hash": "0xdef" })))).await;
+        mount_method(&dishonest, "eth_getCode", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(PERMIT2_CODE)))).await;
+        for server in honest.iter().chain([&dishonest]) {
+            mount_method(server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x100")))).await;
+        }
+        Self { honest, dishonest }
+    }
+
+    async fn handler(&self, sampling: AgreementSampling) -> Arc<RpcHandler> {
+        let rpcs = self.honest.iter().chain([&self.dishonest]).map(mk_rpc).collect();
+        let settings = HandlerSettings { agreement_sampling: Some(sampling), ..settings(rpcs) };
+        RpcHandler::new(config(settings), None).await.unwrap()
+    }
+}
+
+fn sampling(interval_ms: u64) -> AgreementSampling {
+    AgreementSampling {
+        interval_ms,
+        sample_size: 3,
+        min_depth: 3,
+        max_depth: 16,
+        window: 20,
+        min_samples: 5,
+        max_disagreement_rate: 0.2,
+        exclude_from_reads: false,
+    }
+}
+
+#[tokio::test]
+async fn test_the_odd_endpoint_out_loses_agreement_and_is_flagged_after_min_samples() {
+    let endpoints = Endpoints::start().await;
+    let handler = endpoints.handler(sampling(60_000)).await;
+    let mut events = handler.subscribe();
+    let dishonest = url_key(&endpoints.dishonest);
+
+    for round in 1..=4 {
+        let sampled = handler.sample_agreement().await.unwrap();
+        assert_eq!(sampled.majority.as_deref(), Some("0xabc"));
+        assert_eq!(sampled.hashes[&dishonest], "0xdef");
+        assert!((0x100 - 16..=0x100 - 3).contains(&sampled.block));
+
+        let stats = handler.agreement_stats()[&dishonest];
+        assert_eq!((stats.samples, stats.agreements, stats.agreement_rate), (round, 0, 0.0));
+        assert!(!stats.suspected_dishonest, "flagged after only {round} samples");
+    }
+    let report = handler.health_report().await;
+    let entry = report.endpoints.iter().find(|endpoint| endpoint.url == dishonest).unwrap();
+    assert!(entry.flags.is_empty());
+
+    handler.sample_agreement().await.unwrap();
+    assert!(handler.agreement_stats()[&dishonest].suspected_dishonest);
+    assert_eq!(events.try_recv().unwrap(), HandlerEvent::SuspectedDishonest { url: dishonest.clone(), samples: 5, agreements: 0 });
+
+    let report = handler.health_report().await;
+    for endpoint in &report.endpoints {
+        let agreement = endpoint.agreement.unwrap();
+        if endpoint.url == dishonest {
+            assert_eq!(endpoint.flags, [HealthFlag::SuspectedDishonest].into());
+        } else {
+            assert_eq!((agreement.agreement_rate, agreement.suspected_dishonest), (1.0, false));
+            assert!(endpoint.flags.is_empty());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_a_round_without_a_majority_records_nothing() {
+    let honest = MockServer::start().await;
+    mount_probe(&honest, "0x100", Duration::ZERO).await;
+    mount_method(&honest, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x100")))).await;
+    let other = Endpoints::start().await.dishonest;
+    let rpcs = vec![mk_rpc(&honest), mk_rpc(&other)];
+    let handler = RpcHandler::new(config(HandlerSettings { agreement_sampling: Some(sampling(60_000)), ..settings(rpcs) }), None).await.unwrap();
+
+    let sampled = handler.sample_agreement().await.unwrap();
+    assert_eq!(sampled.hashes.len(), 2);
+    assert_eq!(sampled.majority, None);
+    assert!(handler.agreement_stats().is_empty());
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_consensus_stops_counting_a_flagged_endpoint() {
+    let endpoints = Endpoints::start().await;
+    let handler = endpoints.handler(sampling(60_000)).await;
+    let calls = RpcCalls::new(Arc::clone(&handler));
+    let dishonest = url_key(&endpoints.dishonest);
+    let options = || Some(ConsensusOptions { per_host_concurrency: Some(3), ..ConsensusOptions::default() });
+
+    // A unanimous quorum, so the attempt never stops before every endpoint has voted
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 1.0, options()).await;
+    assert_eq!(result.unwrap(), "0x100");
+    assert_eq!(report.votes.values().sum::<usize>(), 3);
+    assert!(report.distrusted.is_empty());
+
+    for _ in 0..5 {
+        handler.sample_agreement().await.unwrap();
+    }
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 1.0, options()).await;
+    assert_eq!(result.unwrap(), "0x100");
+    assert_eq!(report.votes.values().sum::<usize>(), 2);
+    assert!(!report.outcomes.contains_key(&dishonest));
+    assert_eq!(report.distrusted, vec![dishonest]);
+}
+
+#[tokio::test]
+async fn test_exclude_from_reads_leaves_a_flagged_endpoint_out_of_the_plan() {
+    let endpoints = Endpoints::start().await;
+    let handler = endpoints.handler(AgreementSampling { exclude_from_reads: true, ..sampling(60_000) }).await;
+    handler.init().await.unwrap();
+    let dishonest = url_key(&endpoints.dishonest);
+    let excluded = |plan: &RequestPlan| plan.excluded.iter().any(|e| e.url == dishonest && e.reason == Exclusion::SuspectedDishonest);
+
+    assert!(!excluded(&handler.plan_request(&block_number(), None).await.unwrap()));
+    for _ in 0..5 {
+        handler.sample_agreement().await.unwrap();
+    }
+    let plan = handler.plan_request(&block_number(), None).await.unwrap();
+    assert!(excluded(&plan));
+    assert!(!plan.urls.iter().any(|planned| planned.url == dishonest));
+}
+
+#[tokio::test]
+async fn test_sampler_runs_in_the_background_until_shutdown() {
+    let endpoints = Endpoints::start().await;
+    let handler = endpoints.handler(sampling(20)).await;
+    handler.init().await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while handler.agreement_stats().get(&url_key(&endpoints.dishonest)).is_none_or(|stats| !stats.suspected_dishonest) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the sampler flags the dishonest endpoint on its own");
+
+    handler.shutdown();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let samples = count_method(&endpoints.dishonest, "eth_getBlockByNumber").await;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(count_method(&endpoints.dishonest, "eth_getBlockByNumber").await, samples);
+}
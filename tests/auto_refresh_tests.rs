@@ -0,0 +1,188 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::auto_refresh::{deferral_delay, instance_phase, smeared_offsets};
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// How long each proxied `eth_blockNumber` is held by the endpoints.
+const CALL_DELAY: Duration = Duration::from_millis(80);
+
+async fn probe_counts(servers: &[MockServer]) -> Vec<usize> {
+    let mut counts = Vec::new();
+    for server in servers {
+        counts.push(count_method(server, "eth_getCode").await);
+    }
+    counts
+}
+
+#[tokio::test]
+async fn test_sweep_waits_for_traffic_then_probes_a_few_per_tick() {
+    let mut servers = Vec::new();
+    for _ in 0..4 {
+        let server = MockServer::start().await;
+        mount_probe(&server, "0x10", Duration::ZERO).await;
+        mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10"))).set_delay(CALL_DELAY)).await;
+        servers.push(server);
+    }
+    let settings = HandlerSettings {
+        auto_refresh: Some(AutoRefreshSettings { interval_ms: 200, tick_ms: 60, endpoints_per_tick: 1, busy_in_flight: 1, smearing: None }),
+        ..settings(servers.iter().map(mk_rpc).collect())
+    };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+    let initial_sweep = handler.health_report().await.last_full_sweep.expect("init is a full sweep");
+    let after_init = probe_counts(&servers).await;
+
+    // Three callers back to back keep a request in flight well past the first scheduled tick
+    let traffic: Vec<_> = (0..3)
+        .map(|_| {
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                let until = tokio::time::Instant::now() + Duration::from_millis(700);
+                while tokio::time::Instant::now() < until {
+                    handler.try_proxy_request(block_number()).await.unwrap();
+                }
+            })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(handler.requests_in_flight() > 0);
+    for caller in traffic {
+        caller.await.unwrap();
+    }
+
+    let report = handler.health_report().await;
+    assert_eq!(probe_counts(&servers).await, after_init, "nothing was probed under load");
+    assert!(report.refresh_deferrals >= 3, "{} deferrals", report.refresh_deferrals);
+    assert_eq!(report.last_full_sweep, Some(initial_sweep));
+
+    // Once idle, one endpoint is probed per tick until the sweep completes
+    let mut partial_states = Vec::new();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+    let completed = loop {
+        let reprobed = probe_counts(&servers).await.iter().zip(&after_init).filter(|(now, before)| now > before).count();
+        let last_full_sweep = handler.health_report().await.last_full_sweep;
+        if last_full_sweep != Some(initial_sweep) {
+            break last_full_sweep.unwrap();
+        }
+        // The last tick's probes land a moment before the sweep is marked complete
+        if reprobed > 0 && reprobed < servers.len() {
+            partial_states.push(reprobed);
+        }
+        assert!(tokio::time::Instant::now() < deadline, "sweep never completed, saw {partial_states:?}");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert!(completed > initial_sweep);
+    partial_states.dedup();
+    assert!(partial_states.len() >= 2, "sweep should span several ticks, saw {partial_states:?}");
+    assert!(partial_states.is_sorted(), "{partial_states:?}");
+    assert!(probe_counts(&servers).await.iter().zip(&after_init).all(|(now, before)| now > before));
+    assert_eq!(handler.get_latencies().await.len(), servers.len());
+
+    // A manual refresh still probes everything at once
+    let before_refresh = probe_counts(&servers).await;
+    handler.refresh().await.unwrap();
+    let after_refresh = probe_counts(&servers).await;
+    assert!(after_refresh.iter().zip(&before_refresh).all(|(now, before)| now > before));
+    assert!(handler.health_report().await.last_full_sweep.unwrap() > completed);
+    handler.shutdown();
+}
+
+#[test]
+fn test_deferral_delay_adds_up_to_half_a_tick() {
+    let tick = Duration::from_millis(100);
+    assert_eq!(deferral_delay(tick, 0.0), tick);
+    assert_eq!(deferral_delay(tick, 0.5), Duration::from_millis(125));
+    assert!(deferral_delay(tick, 0.999) < Duration::from_millis(150));
+    assert_eq!(deferral_delay(tick, 7.0), Duration::from_millis(150), "out-of-range jitter is clamped");
+}
+
+#[test]
+fn test_smeared_offsets_split_the_window_at_the_instance_phase() {
+    let (interval, window) = (Duration::from_secs(60), Duration::from_secs(30));
+    let offsets = smeared_offsets(6, interval, window, 0.5, || 0.0);
+    let expected: Vec<Duration> = (0..6).map(|slot| Duration::from_secs(15 + 5 * slot)).collect();
+    assert_eq!(offsets, expected);
+
+    let jittered = smeared_offsets(6, interval, window, 1.0, || 0.999);
+    assert!(jittered.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(jittered[0] >= Duration::from_secs(30) && jittered[5] < interval);
+
+    // A window wider than the interval is the interval
+    assert_eq!(smeared_offsets(2, interval, interval * 2, 0.7, || 0.0), vec![Duration::ZERO, Duration::from_secs(30)]);
+
+    // Instances phase apart
+    let phases: Vec<f64> = (0..8).map(instance_phase).collect();
+    assert!(phases.iter().all(|phase| (0.0..1.0).contains(phase)));
+    assert!(phases.windows(2).all(|pair| pair[0] != pair[1]));
+    assert_eq!(instance_phase(7), instance_phase(7));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_smeared_sweep_probes_one_slot_at_a_time_across_its_window() {
+    const STEP: Duration = Duration::from_millis(100);
+    let mut servers = Vec::new();
+    for _ in 0..4 {
+        let server = MockServer::start().await;
+        mount_probe(&server, "0x10", Duration::ZERO).await;
+        servers.push(server);
+    }
+    let smearing = ProbeSmearing { spread_percent: 50, instance_seed: Some(3) };
+    let settings = HandlerSettings {
+        auto_refresh: Some(AutoRefreshSettings { interval_ms: 10_000, tick_ms: 500, endpoints_per_tick: 1, busy_in_flight: 8, smearing: Some(smearing) }),
+        ..settings(servers.iter().map(|server| mk_rpc(server)).collect())
+    };
+    let clock = MockClock::new();
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+    let started = clock.now_system();
+    let after_init = handler.probe_freshness();
+    assert_eq!(after_init.len(), servers.len());
+    assert!(after_init.values().all(|at| *at == started), "init probes everything at once");
+
+    // Step through three intervals, the first empty, noting which endpoints were probed at each step
+    clock.wait_for_sleepers(1).await;
+    let sleeping = clock.sleepers();
+    let mut previous = after_init;
+    let mut probed_at_step = Vec::new();
+    for step in 1..=300u32 {
+        clock.advance(STEP);
+        clock.wait_for_sleepers(sleeping).await;
+        let freshness = handler.probe_freshness();
+        let moved: Vec<&String> = freshness.iter().filter(|(url, at)| previous.get(*url) != Some(at)).map(|(url, _)| url).collect();
+        assert!(moved.len() <= 1, "step {step} probed {moved:?} together");
+        for url in moved {
+            assert_eq!(freshness[url], started + STEP * step);
+            probed_at_step.push(step);
+        }
+        previous = freshness;
+    }
+
+    // Each endpoint once per interval, in the 5s window the phase puts within each
+    let phase = instance_phase(3);
+    let window_start = 50.0 * phase;
+    assert_eq!(probed_at_step.len(), 2 * servers.len(), "{probed_at_step:?}");
+    for (sweep, steps) in probed_at_step.chunks(servers.len()).enumerate() {
+        let first = (100 * sweep + 100) as f64 + window_start;
+        assert!(steps.iter().all(|&step| f64::from(step) >= first.floor() && f64::from(step) <= first + 51.0), "sweep {sweep}: {steps:?}, phase {phase}");
+        assert!(steps.windows(2).all(|pair| pair[0] < pair[1]), "{steps:?}");
+        assert!(steps[servers.len() - 1] - steps[0] >= 20, "probes should spread over the window: {steps:?}");
+    }
+    assert!(handler.health_report().await.last_full_sweep.unwrap() > started);
+
+    // Shutting down ends the loop between slots
+    handler.shutdown();
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while clock.sleepers() >= sleeping {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("the smeared loop should stop sleeping on shutdown");
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::{backfill::ENDPOINT_FAILURE_LIMIT, *};
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+fn block_hash(number: u64) -> String {
+    format!("0x{number:064x}")
+}
+
+/// A chain of blocks at head `0x1000`, each with one transaction, that fails every request
+/// with a 500 once it has served `die_after` numbered blocks.
+async fn chain(die_after: Option<usize>, delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    let served = AtomicUsize::new(0);
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let param = body["params"][0].as_str().unwrap_or_default().to_string();
+            let result = match body["method"].as_str().unwrap() {
+                "eth_getCode" => json!(PERMIT2_CODE),
+                "eth_getBlockByNumber" if param == "latest" => json!({ "number": "0x1000", "hash": "0xabc" }),
+                "eth_getBlockByNumber" => {
+                    if die_after.is_some_and(|limit| served.fetch_add(1, Ordering::SeqCst) >= limit) {
+                        return ResponseTemplate::new(500);
+                    }
+                    let number = u64::from_str_radix(param.trim_start_matches("0x"), 16).unwrap();
+                    json!({ "number": param, "hash": block_hash(number), "transactions": [{ "hash": format!("0x{number:x}aa") }] })
+                }
+                "eth_getBlockReceipts" => {
+                    return ResponseTemplate::new(200).set_body_json(json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32601, "message": "the method eth_getBlockReceipts does not exist" } }));
+                }
+                "eth_getTransactionReceipt" => json!({ "transactionHash": param, "status": "0x1" }),
+                _ => Value::Null,
+            };
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, result)).set_delay(delay)
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn calls(servers: &[&MockServer]) -> Arc<RpcCalls> {
+    let handler = RpcHandler::new(config(settings(servers.iter().map(|server| mk_rpc(server)).collect())), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_latencies().await.len(), servers.len());
+    Arc::new(RpcCalls::new(handler))
+}
+
+fn options() -> BackfillOptions {
+    BackfillOptions { concurrency: 6, partition_size: 10, retry_backoff: Duration::from_millis(10), ..BackfillOptions::default() }
+}
+
+/// A sink collecting results by block number.
+fn collector() -> (Arc<parking_lot::Mutex<BTreeMap<u64, Value>>>, impl BlockSink) {
+    let results = Arc::new(parking_lot::Mutex::new(BTreeMap::new()));
+    let sink_results = Arc::clone(&results);
+    (results, move |number, result| {
+        assert!(sink_results.lock().insert(number, result).is_none(), "block {number} delivered twice");
+    })
+}
+
+#[tokio::test]
+async fn test_backfill_finishes_across_the_survivors_when_an_endpoint_dies() {
+    let (first, dying, second) = (chain(None, Duration::ZERO).await, chain(Some(15), Duration::ZERO).await, chain(None, Duration::ZERO).await);
+    let calls = calls(&[&first, &dying, &second]).await;
+    let runner = BackfillRunner::new(calls, 0..=199, BackfillTask::FetchBlock, options()).unwrap();
+    let progress = runner.progress();
+    let (results, sink) = collector();
+
+    let report = runner.run(&sink).await.unwrap();
+    assert_eq!((report.completed, report.resumed, report.failed.len(), report.stopped), (200, 0, 0, false));
+    assert_eq!(report.dropped_endpoints, [url_key(&dying)]);
+    let results = results.lock();
+    assert_eq!(results.len(), 200);
+    assert!(results.iter().all(|(number, block)| block["hash"] == block_hash(*number)));
+
+    // Both survivors carried a share, and the dying endpoint stopped getting work once it died
+    let requests = |server: &MockServer| report.requests_by_endpoint.get(&url_key(server)).copied().unwrap_or_default();
+    assert!(requests(&first) >= 50 && requests(&second) >= 50, "{:?}", report.requests_by_endpoint);
+    // Each partition under way can have one more attempt in flight when it's dropped
+    let in_flight = options().concurrency as u64;
+    assert!((15..=15 + ENDPOINT_FAILURE_LIMIT as u64 + in_flight).contains(&requests(&dying)), "{:?}", report.requests_by_endpoint);
+
+    let last = progress.borrow().clone();
+    assert_eq!((last.completed_blocks, last.failed_blocks, last.finished, last.eta), (200, 0, true, Some(Duration::ZERO)));
+}
+
+#[cfg(feature = "persistence")]
+#[tokio::test]
+async fn test_a_recreated_runner_resumes_from_the_checkpoint() {
+    let servers = [chain(None, Duration::from_millis(5)).await, chain(None, Duration::from_millis(5)).await];
+    let calls = calls(&[&servers[0], &servers[1]]).await;
+    let path = std::env::temp_dir().join(format!("ez-web3-rpc-backfill-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let options = BackfillOptions { concurrency: 2, run_id: Some("blocks".to_string()), ..options() };
+
+    let store = Arc::new(FileCheckpointStore::open(&path).unwrap());
+    let runner = BackfillRunner::with_checkpoints(Arc::clone(&calls), 0..=199, BackfillTask::FetchBlock, options.clone(), store).unwrap();
+    let mut progress = runner.progress();
+    let (first_results, sink) = collector();
+    let (first, _) = tokio::join!(runner.run(&sink), async {
+        progress.wait_for(|progress| progress.completed_blocks >= 50).await.unwrap();
+        runner.stop();
+    });
+    let first = first.unwrap();
+    assert!(first.stopped && first.completed < 200 && first.completed % 10 == 0, "{first:?}");
+    let sent_before: usize = futures::future::join_all(servers.iter().map(|server| count_method(server, "eth_getBlockByNumber"))).await.iter().sum();
+
+    // As after a restart: a new store over the same file and a new runner
+    let store = Arc::new(FileCheckpointStore::open(&path).unwrap());
+    let runner = BackfillRunner::with_checkpoints(calls, 0..=199, BackfillTask::FetchBlock, options, store).unwrap();
+    let (second_results, sink) = collector();
+    let second = runner.run(&sink).await.unwrap();
+    assert_eq!((second.resumed, second.completed, second.stopped), (first.completed, 200 - first.completed, false));
+    let sent_after: usize = futures::future::join_all(servers.iter().map(|server| count_method(server, "eth_getBlockByNumber"))).await.iter().sum();
+    assert_eq!((sent_after - sent_before) as u64, 200 - first.completed, "only the blocks not yet done are fetched");
+
+    let mut all = first_results.lock().clone();
+    for (number, block) in second_results.lock().iter() {
+        assert!(all.insert(*number, block.clone()).is_none(), "block {number} fetched by both runs");
+    }
+    assert_eq!(all.keys().copied().collect::<Vec<_>>(), (0..=199).collect::<Vec<_>>());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_receipts_fall_back_and_failures_are_reported_per_block() {
+    let server = chain(None, Duration::ZERO).await;
+    let calls = calls(&[&server]).await;
+    let runner = BackfillRunner::new(Arc::clone(&calls), 7..=8, BackfillTask::FetchBlockWithReceipts, options()).unwrap();
+    let (results, sink) = collector();
+    runner.run(&sink).await.unwrap();
+    assert_eq!(results.lock()[&7]["receipts"], json!([{ "transactionHash": "0x7aa", "status": "0x1" }]));
+
+    // A custom task that fails on one block reports just that block
+    let task = BackfillTask::custom(|context: BlockContext| async move {
+        if context.number == 13 {
+            return Err(RpcHandlerError::JsonRpc("unlucky".to_string()));
+        }
+        context.call("eth_getBlockByNumber", json!([format!("{:#x}", context.number), false])).await
+    });
+    let options = BackfillOptions { max_retries: 2, ..options() };
+    let runner = BackfillRunner::new(Arc::clone(&calls), 10..=19, task, options).unwrap();
+    let (results, sink) = collector();
+    let report = runner.run(&sink).await.unwrap();
+    assert_eq!((report.completed, report.failed.keys().copied().collect::<Vec<_>>()), (9, vec![13]));
+    assert!(!results.lock().contains_key(&13));
+    assert_eq!(runner.progress().borrow().failed_blocks, 1);
+
+    #[allow(clippy::reversed_empty_ranges)]
+    let empty = BackfillRunner::new(calls, 5..=4, BackfillTask::FetchBlock, BackfillOptions::default());
+    assert!(matches!(empty, Err(RpcHandlerError::InvalidBackfill { .. })));
+}
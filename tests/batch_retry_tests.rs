@@ -0,0 +1,321 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, Respond, ResponseTemplate};
+
+const SLOTS: u64 = 10;
+
+fn storage_read(slot: u64) -> JsonRpcRequest {
+    JsonRpcRequest::new("eth_getStorageAt", json!(["0x0000000000000000000000000000000000000001", format!("0x{slot:x}"), "latest"])).with_id(100 + slot)
+}
+
+fn slot_of(entry: &Value) -> u64 {
+    u64::from_str_radix(entry["params"][1].as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+}
+
+fn slot_value(slot: u64) -> Value {
+    json!(format!("0x{:064x}", slot * 7))
+}
+
+/// Answers batches of storage reads in reverse order, failing each slot in `failures` with its
+/// error for as many sends as listed.
+struct Scripted {
+    failures: HashMap<u64, (u32, i64, &'static str)>,
+    sends: Arc<parking_lot::Mutex<HashMap<u64, u32>>>,
+}
+
+impl Respond for Scripted {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let entries: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+        let mut sends = self.sends.lock();
+        let mut answers: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                let slot = slot_of(entry);
+                let sent = sends.entry(slot).or_default();
+                *sent += 1;
+                match self.failures.get(&slot) {
+                    Some(&(times, code, message)) if *sent <= times => {
+                        json!({ "jsonrpc": "2.0", "id": entry["id"], "error": { "code": code, "message": message } })
+                    }
+                    _ => json!({ "jsonrpc": "2.0", "id": entry["id"], "result": slot_value(slot) }),
+                }
+            })
+            .collect();
+        answers.reverse();
+        ResponseTemplate::new(200).set_body_json(answers)
+    }
+}
+
+/// A probe-passing endpoint answering batches per `failures`, and its per-slot send counts.
+async fn endpoint(probe_delay: Duration, failures: &[(u64, u32, i64, &'static str)]) -> (MockServer, Arc<parking_lot::Mutex<HashMap<u64, u32>>>) {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", probe_delay).await;
+    let sends = Arc::default();
+    let scripted = Scripted {
+        failures: failures.iter().map(|&(slot, times, code, message)| (slot, (times, code, message))).collect(),
+        sends: Arc::clone(&sends),
+    };
+    Mock::given(method("POST")).and(|request: &Request| request.body.starts_with(b"[")).respond_with(scripted).mount(&server).await;
+    (server, sends)
+}
+
+async fn handler(servers: &[&MockServer]) -> Arc<RpcHandler> {
+    handler_in(servers, ValidationMode::default()).await
+}
+
+async fn handler_in(servers: &[&MockServer], validation_mode: ValidationMode) -> Arc<RpcHandler> {
+    let settings = HandlerSettings {
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: 2000, connect_timeout_ms: None }),
+        validation_mode,
+        ..settings(servers.iter().map(|server| mk_rpc(server)).collect())
+    };
+    init_handler(settings).await
+}
+
+#[tokio::test]
+async fn test_only_retryable_failures_are_resent_and_merged_in_place() {
+    let (server, sends) = endpoint(Duration::ZERO, &[
+        (3, 1, -32603, "internal error"),
+        (5, 1, -32000, "header not found"),
+        (7, 2, -32603, "internal error"),
+        (9, 5, -32602, "invalid argument 1: hex number with leading zero digits"),
+    ])
+    .await;
+    let handler = handler(&[&server]).await;
+    let batch: JsonRpcBatch = (0..SLOTS).map(storage_read).collect();
+
+    let entries = handler.get_provider().await.unwrap().send_batch(&batch, None).await.unwrap();
+    assert_eq!(entries.len(), batch.len());
+    for (slot, entry) in (0..SLOTS).zip(&entries) {
+        let response = entry.response.as_ref().unwrap();
+        assert_eq!(response.id, Some(JsonRpcId::from(100 + slot)), "the caller's id comes back");
+        assert_eq!(entry.served_by.as_deref(), Some(url_key(&server).as_str()));
+        match slot {
+            9 => assert_eq!(response.error.as_ref().unwrap().code, -32602),
+            _ => assert_eq!(response.result, Some(slot_value(slot)), "slot {slot}"),
+        }
+    }
+    let attempts: Vec<u32> = entries.iter().map(|entry| entry.attempts).collect();
+    assert_eq!(attempts, vec![1, 1, 1, 2, 1, 2, 1, 3, 1, 1]);
+
+    // What came back fine the first time, or failed deterministically, was never sent again
+    let sends = sends.lock().clone();
+    assert_eq!(sends, (0..SLOTS).map(|slot| (slot, attempts[slot as usize])).collect());
+    let batches = server.received_requests().await.unwrap().into_iter().filter(|request| request.body.starts_with(b"[")).count();
+    assert_eq!(batches, 3);
+}
+
+/// Accepts every entry of a batch, as a transaction submission endpoint would.
+struct Accepting;
+
+impl Respond for Accepting {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let entries: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+        ResponseTemplate::new(200).set_body_json(entries.iter().map(|entry| json!({ "jsonrpc": "2.0", "id": entry["id"], "result": "0xaccepted" })).collect::<Vec<_>>())
+    }
+}
+
+/// The methods of every batch entry `server` received, in order.
+async fn batched_methods(server: &MockServer) -> Vec<String> {
+    let batches = server.received_requests().await.unwrap().into_iter().filter(|request| request.body.starts_with(b"["));
+    batches
+        .flat_map(|request| serde_json::from_slice::<Vec<Value>>(&request.body).unwrap())
+        .map(|entry| entry["method"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_a_write_anywhere_in_a_batch_goes_to_the_write_endpoint() {
+    let (public, _) = endpoint(Duration::ZERO, &[]).await;
+    let private = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(Accepting).mount(&private).await;
+    let handler = init_handler(HandlerSettings {
+        write_endpoint: Some(RouteRule { methods: vec![], urls: vec![private.uri()], allow_failover: false }),
+        ..settings(vec![mk_rpc(&public)])
+    })
+    .await;
+    let submit = JsonRpcRequest::new("eth_sendRawTransaction", json!(["0x02f8"])).with_id("submit");
+    let batch = vec![storage_read(0), submit, storage_read(1)];
+
+    let entries = handler.try_proxy_batch(&batch, None).await.unwrap();
+    let served_by: Vec<String> = entries.iter().map(|entry| entry.served_by.clone().unwrap()).collect();
+    assert_eq!(served_by, vec![url_key(&public), url_key(&private), url_key(&public)]);
+    assert_eq!(entries[1].response.as_ref().unwrap().id, Some(JsonRpcId::from("submit")));
+    assert_eq!(entries[2].response.as_ref().unwrap().result, Some(slot_value(1)));
+    assert_eq!(batched_methods(&public).await, vec!["eth_getStorageAt", "eth_getStorageAt"]);
+    assert_eq!(batched_methods(&private).await, vec!["eth_sendRawTransaction"]);
+}
+
+#[tokio::test]
+async fn test_call_options_apply_to_a_batch() {
+    let (first, _) = endpoint(Duration::ZERO, &[]).await;
+    let (second, _) = endpoint(Duration::from_millis(30), &[]).await;
+    let handler = handler(&[&first, &second]).await;
+    let batch: JsonRpcBatch = (0..3).map(storage_read).collect();
+
+    let call = CallOptions { exclude: vec![first.uri()], ..CallOptions::default() };
+    let entries = handler.try_proxy_batch_with(&batch, None, call).await.unwrap();
+    assert!(entries.iter().all(|entry| entry.served_by.as_deref() == Some(url_key(&second).as_str())));
+    assert!(batched_methods(&first).await.is_empty());
+
+    // A failure is reported under the batch's name, with the call's tags
+    let mut events = handler.subscribe();
+    let tags: CallTags = [("component".to_string(), "indexer".to_string())].into();
+    let call = CallOptions { exclude: vec![first.uri(), second.uri()], tags: Some(tags.clone()), ..CallOptions::default() };
+    assert!(matches!(handler.try_proxy_batch_with(&batch, None, call).await, Err(RpcHandlerError::NoAvailableRpcs { .. })));
+    let failed = std::iter::from_fn(|| events.try_recv().ok()).find(|event| matches!(event, HandlerEvent::RequestFailed { .. }));
+    match failed {
+        Some(HandlerEvent::RequestFailed { method, tags: event_tags, .. }) => assert_eq!((method.as_str(), event_tags), ("batch[3]", tags)),
+        other => panic!("expected RequestFailed, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_strict_validation_checks_answers_against_the_ids_sent() {
+    let (server, sends) = endpoint(Duration::ZERO, &[]).await;
+    let handler = handler_in(&[&server], ValidationMode::Strict).await;
+    // The caller's ids are 100 and up, not the positions the entries are sent under
+    let batch: JsonRpcBatch = (0..SLOTS).map(storage_read).collect();
+
+    let entries = handler.try_proxy_batch(&batch, None).await.unwrap();
+    for (slot, entry) in (0..SLOTS).zip(&entries) {
+        let response = entry.response.as_ref().unwrap_or_else(|| panic!("slot {slot} was refused"));
+        assert_eq!((&response.id, &response.result, entry.attempts), (&Some(JsonRpcId::from(100 + slot)), &Some(slot_value(slot)), 1));
+    }
+    assert!(sends.lock().values().all(|&sent| sent == 1));
+}
+
+#[tokio::test]
+async fn test_failed_entries_move_to_the_next_endpoint_within_the_cap() {
+    let (flaky, flaky_sends) = endpoint(Duration::ZERO, &[(1, u32::MAX, -32603, "internal error"), (2, u32::MAX, -32005, "limit exceeded")]).await;
+    let (steady, steady_sends) = endpoint(Duration::from_millis(30), &[]).await;
+    let handler = handler(&[&flaky, &steady]).await;
+    let provider = handler.get_provider().await.unwrap();
+    assert_eq!(provider.base_url, url_key(&flaky));
+    let batch: JsonRpcBatch = (0..4).map(storage_read).collect();
+
+    let entries = provider.send_batch(&batch, None).await.unwrap();
+    let served_by: Vec<String> = entries.iter().map(|entry| entry.served_by.clone().unwrap()).collect();
+    assert_eq!(served_by, vec![url_key(&flaky), url_key(&steady), url_key(&steady), url_key(&flaky)]);
+    assert!(entries.iter().all(|entry| entry.response.as_ref().unwrap().error.is_none()));
+    assert_eq!(steady_sends.lock().keys().copied().collect::<std::collections::BTreeSet<_>>(), [1, 2].into());
+
+    // Without partial retries the first answers come back as they were
+    let entries = provider.send_batch(&batch, Some(BatchOptions { max_partial_retries: 0, ..BatchOptions::default() })).await.unwrap();
+    assert_eq!(entries[1].response.as_ref().unwrap().error.as_ref().unwrap().code, -32603);
+    assert!(entries.iter().all(|entry| entry.attempts == 1));
+    assert_eq!(flaky_sends.lock()[&1], 2);
+    assert_eq!(steady_sends.lock()[&1], 1);
+}
+
+/// Answers batches of storage reads in a shuffled order under ids of its own choosing, as the
+/// request's ids are only echoed, failing `rejected` with invalid params.
+struct Shuffled {
+    rejected: u64,
+}
+
+impl Respond for Shuffled {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let entries: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+        let mut answers: Vec<Value> = entries
+            .iter()
+            .map(|entry| match slot_of(entry) {
+                slot if slot == self.rejected => json!({ "jsonrpc": "2.0", "id": entry["id"], "error": { "code": -32602, "message": "invalid argument 1" } }),
+                slot => json!({ "jsonrpc": "2.0", "id": entry["id"], "result": slot_value(slot) }),
+            })
+            .collect();
+        answers.shuffle(&mut StdRng::seed_from_u64(entries.len() as u64));
+        ResponseTemplate::new(200).set_body_json(answers)
+    }
+}
+
+#[tokio::test]
+async fn test_the_handler_matches_shuffled_answers_to_their_requests() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST")).and(|request: &Request| request.body.starts_with(b"[")).respond_with(Shuffled { rejected: 4 }).mount(&server).await;
+    let handler = handler(&[&server]).await;
+    let batch: JsonRpcBatch = (0..SLOTS).map(storage_read).collect();
+
+    let entries = handler.try_proxy_batch(&batch, None).await.unwrap();
+    assert_eq!(entries.len(), batch.len());
+    for (slot, entry) in (0..SLOTS).zip(&entries) {
+        let response = entry.response.as_ref().unwrap();
+        assert_eq!(response.id, Some(JsonRpcId::from(100 + slot)));
+        match slot {
+            // Fails alone, and deterministically, so it isn't sent again
+            4 => assert_eq!((response.error.as_ref().unwrap().code, entry.attempts), (-32602, 1)),
+            _ => assert_eq!(response.result, Some(slot_value(slot)), "slot {slot}"),
+        }
+    }
+    assert!(handler.try_proxy_batch(&[], None).await.unwrap().is_empty());
+}
+
+#[test]
+fn test_json_rpc_errors_split_into_retryable_and_deterministic() {
+    let error = |code: i64, message: &str| JsonRpcError { code, message: message.into(), data: None };
+    assert!(error(-32603, "internal error").is_retryable());
+    assert!(error(-32005, "limit exceeded").is_retryable());
+    assert!(error(-32000, "Header not found").is_retryable());
+    assert!(error(-32000, "missing trie node 1a2b (path )").is_retryable());
+    assert!(!error(-32000, "nonce too low").is_retryable());
+    assert!(!error(-32602, "invalid params").is_retryable());
+    assert!(!error(-32601, "the method eth_foo does not exist").is_retryable());
+    assert!(!error(3, "execution reverted").is_retryable());
+}
+
+/// Answers the calls in a batch and nothing else, with an empty body when there are none, as
+/// the spec has servers treat notifications.
+struct SkippingNotifications;
+
+impl Respond for SkippingNotifications {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let entries: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+        let answers: Vec<Value> = entries
+            .iter()
+            .filter(|entry| entry.get("id").is_some())
+            .map(|entry| json!({ "jsonrpc": "2.0", "id": entry["id"], "result": slot_value(slot_of(entry)) }))
+            .collect();
+        match answers.is_empty() {
+            true => ResponseTemplate::new(200),
+            false => ResponseTemplate::new(200).set_body_json(answers),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_notifications_go_out_without_an_id_and_are_sent_once() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST")).and(|request: &Request| request.body.starts_with(b"[")).respond_with(SkippingNotifications).mount(&server).await;
+    let handler = handler(&[&server]).await;
+    let notification = |slot| JsonRpcRequest { id: None, ..storage_read(slot) };
+    let batch: JsonRpcBatch = vec![storage_read(0), notification(1), storage_read(2)];
+
+    let entries = handler.try_proxy_batch(&batch, None).await.unwrap();
+    assert_eq!(entries[0].response.as_ref().unwrap().result, Some(slot_value(0)));
+    assert_eq!(entries[2].response.as_ref().unwrap().id, Some(JsonRpcId::from(102)));
+    assert!(entries[1].response.is_none());
+    assert_eq!(entries.iter().map(|entry| entry.attempts).collect::<Vec<_>>(), vec![1, 1, 1], "the unanswered notification isn't re-sent");
+
+    let sent: Vec<Value> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.body.starts_with(b"["))
+        .flat_map(|request| serde_json::from_slice::<Vec<Value>>(&request.body).unwrap())
+        .collect();
+    assert_eq!(sent.len(), 3);
+    assert!(sent[1].get("id").is_none(), "sent as {}", sent[1]);
+
+    // A batch of only notifications is answered with nothing
+    let entries = handler.try_proxy_batch(&[notification(3), notification(4)], None).await.unwrap();
+    assert!(entries.iter().all(|entry| entry.response.is_none() && entry.attempts == 1));
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::matchers::{body_partial_json, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const TX_HASHES: [&str; 3] = [
+    "0x1111111111111111111111111111111111111111111111111111111111111111",
+    "0x2222222222222222222222222222222222222222222222222222222222222222",
+    "0x3333333333333333333333333333333333333333333333333333333333333333",
+];
+
+fn receipt(hash: &str) -> Value {
+    json!({ "transactionHash": hash, "status": "0x1" })
+}
+
+fn method_not_found() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "jsonrpc": "2.0", "id": 1, "error": { "code": -32601, "message": "the method does not exist/is not available" }
+    }))
+}
+
+/// Serve block 0x10 with `TX_HASHES`, answering the first receipts slowest so ordering is exercised.
+async fn mount_per_transaction_receipts(server: &MockServer) {
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getBlockByNumber", "params": ["0x10", false] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x10", "transactions": TX_HASHES }))))
+        .mount(server)
+        .await;
+
+    for (i, hash) in TX_HASHES.iter().enumerate() {
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "eth_getTransactionReceipt", "params": [hash] })))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(rpc_response(1, receipt(hash)))
+                .set_delay(Duration::from_millis(60 - 20 * i as u64)))
+            .mount(server)
+            .await;
+    }
+}
+
+async fn calls_for(server: &MockServer) -> RpcCalls {
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(server)])), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+    RpcCalls::new(handler)
+}
+
+fn hashes(receipts: &[Value]) -> Vec<&str> {
+    receipts.iter().map(|r| r["transactionHash"].as_str().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn test_block_receipts_served_in_one_call() {
+    let server = MockServer::start().await;
+    let all: Vec<Value> = TX_HASHES.iter().map(|h| receipt(h)).collect();
+    mount_method(&server, "eth_getBlockReceipts", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(all)))).await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+
+    let calls = calls_for(&server).await;
+    let receipts = calls.get_block_receipts("0x10").await.unwrap();
+
+    assert_eq!(hashes(&receipts), TX_HASHES);
+    assert_eq!(count_method(&server, "eth_getBlockReceipts").await, 1);
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 0);
+
+    let caps = calls.handler().capabilities_for(&url_key(&server)).await;
+    assert_eq!(caps.block_receipts, Some(BlockReceiptsMethod::Eth));
+}
+
+#[tokio::test]
+async fn test_unsupported_block_receipts_falls_back_and_is_remembered() {
+    let server = MockServer::start().await;
+    mount_method(&server, "eth_getBlockReceipts", method_not_found()).await;
+    mount_method(&server, "erigon_getBlockReceipts", method_not_found()).await;
+    mount_per_transaction_receipts(&server).await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+
+    let calls = calls_for(&server).await;
+    assert_eq!(hashes(&calls.get_block_receipts("0x10").await.unwrap()), TX_HASHES);
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 3);
+
+    // The second call goes straight to per-transaction fetches
+    assert_eq!(hashes(&calls.get_block_receipts("0x10").await.unwrap()), TX_HASHES);
+    assert_eq!(count_method(&server, "eth_getBlockReceipts").await, 1);
+    assert_eq!(count_method(&server, "erigon_getBlockReceipts").await, 1);
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 6);
+
+    let report = calls.handler().health_report().await;
+    assert_eq!(report.endpoints[0].capabilities.block_receipts, Some(BlockReceiptsMethod::Unsupported));
+}
+
+#[tokio::test]
+async fn test_erigon_variant_is_used_and_remembered() {
+    let server = MockServer::start().await;
+    let all: Vec<Value> = TX_HASHES.iter().map(|h| receipt(h)).collect();
+    mount_method(&server, "eth_getBlockReceipts", method_not_found()).await;
+    mount_method(&server, "erigon_getBlockReceipts", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(all)))).await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+
+    let calls = calls_for(&server).await;
+    calls.get_block_receipts("0x10").await.unwrap();
+    calls.get_block_receipts("0x10").await.unwrap();
+
+    assert_eq!(count_method(&server, "eth_getBlockReceipts").await, 1);
+    assert_eq!(count_method(&server, "erigon_getBlockReceipts").await, 2);
+}
+
+#[tokio::test]
+async fn test_restored_capabilities_skip_probing() {
+    let server = MockServer::start().await;
+    mount_per_transaction_receipts(&server).await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+
+    let calls = calls_for(&server).await;
+    let learned = EndpointCapabilities { block_receipts: Some(BlockReceiptsMethod::Unsupported) };
+    calls.handler().restore_capabilities([(url_key(&server), learned)].into()).await;
+
+    assert_eq!(hashes(&calls.get_block_receipts("0x10").await.unwrap()), TX_HASHES);
+    assert_eq!(count_method(&server, "eth_getBlockReceipts").await, 0);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+const HEAD: u64 = 1000;
+const GENESIS_TIME: u64 = 1_600_000_000;
+
+/// Block times of 4 to 14 seconds, except block 98, which shares block 97's timestamp.
+fn timestamp(number: u64) -> u64 {
+    match number {
+        98 => timestamp(97),
+        _ => GENESIS_TIME + 12 * number + (number % 5) * 2,
+    }
+}
+
+/// A chain of `HEAD + 1` blocks answering after `delay`, which fails requests for numbered
+/// blocks once it has answered `answers` of them. Returns how many numbered blocks were asked for.
+async fn chain(answers: usize, delay: Duration) -> (MockServer, Arc<AtomicUsize>) {
+    let asked = Arc::new(AtomicUsize::new(0));
+    let server = MockServer::start().await;
+    let counter = Arc::clone(&asked);
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let number = match (body["method"].as_str().unwrap(), body["params"][0].as_str().unwrap_or_default()) {
+                ("eth_getCode", _) => return respond(json!(PERMIT2_CODE), delay),
+                ("eth_getBlockByNumber", "latest") => HEAD,
+                ("eth_getBlockByNumber", block) => {
+                    if counter.fetch_add(1, Ordering::SeqCst) >= answers {
+                        return ResponseTemplate::new(503);
+                    }
+                    u64::from_str_radix(block.trim_start_matches("0x"), 16).unwrap()
+                }
+                _ => return respond(json!(null), delay),
+            };
+            let block = (number <= HEAD).then(|| json!({ "number": format!("0x{number:x}"), "timestamp": format!("0x{:x}", timestamp(number)), "hash": "0xabc" }));
+            respond(json!(block), delay)
+        })
+        .mount(&server)
+        .await;
+    (server, asked)
+}
+
+fn respond(result: Value, delay: Duration) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, result)).set_delay(delay)
+}
+
+async fn calls(servers: &[&MockServer]) -> RpcCalls {
+    let handler = RpcHandler::new(config(settings(servers.iter().map(|server| mk_rpc(server)).collect())), None).await.unwrap();
+    handler.init().await.unwrap();
+    RpcCalls::new(handler)
+}
+
+fn stamp(number: u64) -> BlockStamp {
+    BlockStamp { number, timestamp: timestamp(number) }
+}
+
+async fn search(calls: &RpcCalls, at: u64) -> BlockMatch {
+    calls.block_by_timestamp(at, SearchHint::default()).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_boundaries_round_down_to_the_last_block_at_or_before() {
+    let (server, _) = chain(usize::MAX, Duration::ZERO).await;
+    let calls = calls(&[&server]).await;
+
+    let exact = search(&calls, timestamp(500)).await;
+    assert_eq!((exact.at_or_before, exact.after), (Some(stamp(500)), Some(stamp(501))));
+    assert!(exact.fetched <= 2 + 10, "about log2(1000) requests, took {}", exact.fetched);
+
+    let between = search(&calls, timestamp(501) - 1).await;
+    assert_eq!((between.at_or_before, between.after), (Some(stamp(500)), Some(stamp(501))));
+
+    // Blocks 97 and 98 share a timestamp; the later one counts
+    assert_eq!(timestamp(97), timestamp(98));
+    assert_eq!(search(&calls, timestamp(97)).await.at_or_before, Some(stamp(98)));
+
+    let genesis = search(&calls, GENESIS_TIME).await;
+    assert_eq!((genesis.at_or_before, genesis.after), (Some(stamp(0)), Some(stamp(1))));
+
+    let before_genesis = search(&calls, GENESIS_TIME - 1).await;
+    assert_eq!((before_genesis.at_or_before, before_genesis.after), (None, Some(stamp(0))));
+
+    let future = search(&calls, timestamp(HEAD) + 3600).await;
+    assert_eq!((future.at_or_before, future.after), (Some(stamp(HEAD)), None));
+    assert_eq!(search(&calls, timestamp(HEAD)).await.at_or_before, Some(stamp(HEAD)));
+}
+
+#[tokio::test]
+async fn test_repeated_lookups_reuse_cached_timestamps() {
+    let (server, _) = chain(usize::MAX, Duration::ZERO).await;
+    let calls = calls(&[&server]).await;
+
+    let first = search(&calls, timestamp(300) + 1).await;
+    let again = search(&calls, timestamp(300) + 1).await;
+    assert_eq!(again.at_or_before, first.at_or_before);
+    assert_eq!(again.fetched, 1, "only the head is asked for again");
+
+    // A lower bound narrows a fresh search
+    let hinted = calls.block_by_timestamp(timestamp(900), SearchHint { lower_bound: Some(880) }).await.unwrap();
+    assert_eq!(hinted.at_or_before, Some(stamp(900)));
+    assert!(hinted.fetched <= 2 + 7);
+    let past_hint = calls.block_by_timestamp(timestamp(10), SearchHint { lower_bound: Some(880) }).await.unwrap();
+    assert_eq!((past_hint.at_or_before, past_hint.after), (None, Some(stamp(880))));
+}
+
+#[tokio::test]
+async fn test_failover_resumes_from_verified_bounds() {
+    // The faster endpoint is picked, answers four block requests, then fails
+    let (flaky, flaky_asked) = chain(4, Duration::ZERO).await;
+    let (steady, steady_asked) = chain(usize::MAX, Duration::from_millis(30)).await;
+    let calls = calls(&[&flaky, &steady]).await;
+    assert_eq!(calls.handler().get_provider_url().await.unwrap(), url_key(&flaky));
+
+    let found = search(&calls, timestamp(700)).await;
+    assert_eq!((found.at_or_before, found.after), (Some(stamp(700)), Some(stamp(701))));
+    assert_eq!((found.url, found.failovers), (url_key(&steady), 1));
+
+    // A search from scratch takes about ten numbered blocks; the rest of this one took fewer
+    let resumed = steady_asked.load(Ordering::SeqCst);
+    assert!(flaky_asked.load(Ordering::SeqCst) >= 5);
+    assert!(resumed <= 10 - 3, "picked up from the verified bounds, took {resumed} more requests");
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+#[cfg(feature = "persistence")]
+use std::{path::PathBuf, time::SystemTime};
+
+use common::*;
+use ez_web3_rpc::broadcast::transaction_hash;
+#[cfg(feature = "persistence")]
+use ez_web3_rpc::broadcast::{LedgerEntry, DEFAULT_LEDGER_TTL};
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const RAW_TX: &str = "0x02f86c0180843b9aca00850df8475800825208940000000000000000000000000000000000000000808080c0";
+
+#[cfg(feature = "persistence")]
+fn scratch_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ez_web3_rpc-{name}-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// A probe-passing endpoint that answers `eth_sendRawTransaction` with `response`.
+async fn endpoint(response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_sendRawTransaction", response).await;
+    server
+}
+
+#[cfg(feature = "persistence")]
+fn accepts() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(transaction_hash(RAW_TX).unwrap())))
+}
+
+async fn handler(servers: &[&MockServer]) -> Arc<RpcHandler> {
+    init_handler(settings(servers.iter().map(|server| mk_rpc(server)).collect())).await
+}
+
+async fn submissions(server: &MockServer) -> usize {
+    count_method(server, "eth_sendRawTransaction").await
+}
+
+#[cfg(feature = "persistence")]
+#[tokio::test]
+async fn test_rebroadcast_after_restart_skips_endpoints_that_accepted() {
+    let path = scratch_file("broadcast-ledger");
+    let (first, second) = (endpoint(accepts()).await, endpoint(accepts()).await);
+    let flaky = endpoint(ResponseTemplate::new(503)).await;
+    let servers = [&first, &second, &flaky];
+
+    let calls = RpcCalls::with_ledger(handler(&servers).await, Arc::new(FileLedger::open(&path, DEFAULT_LEDGER_TTL).unwrap()));
+    let report = calls.broadcast_raw_transaction(RAW_TX, None).await.unwrap();
+    assert_eq!(report.idempotency_key, transaction_hash(RAW_TX).unwrap(), "keyed by the tx hash by default");
+    assert_eq!(report.accepted, BTreeSet::from([url_key(&first), url_key(&second)]));
+    assert!(report.rejected.contains_key(&url_key(&flaky)));
+    assert!(!report.replayed);
+
+    // The process dies; after a restart only the ledger file is left
+    drop(calls);
+    flaky.reset().await;
+    mount_probe(&flaky, "0x10", Duration::ZERO).await;
+    mount_method(&flaky, "eth_sendRawTransaction", accepts()).await;
+
+    let calls = RpcCalls::with_ledger(handler(&servers).await, Arc::new(FileLedger::open(&path, DEFAULT_LEDGER_TTL).unwrap()));
+    let report = calls.broadcast_raw_transaction(RAW_TX, None).await.unwrap();
+    assert_eq!((submissions(&first).await, submissions(&second).await, submissions(&flaky).await), (1, 1, 1));
+    assert_eq!(report.accepted.len(), 3);
+    assert!(report.rejected.is_empty() && !report.replayed);
+
+    // Every endpoint has it now: the recorded report comes back and nothing is sent
+    let replay = calls.broadcast_raw_transaction(RAW_TX, None).await.unwrap();
+    assert_eq!(replay, BroadcastReport { replayed: true, ..report });
+    assert_eq!((submissions(&first).await, submissions(&second).await, submissions(&flaky).await), (1, 1, 1));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_already_known_counts_as_accepted_and_keys_are_separate() {
+    let known = endpoint(ResponseTemplate::new(200).set_body_json(json!({
+        "jsonrpc": "2.0", "id": 1, "error": { "code": -32000, "message": "already known" }
+    })))
+    .await;
+    let underpriced = endpoint(ResponseTemplate::new(200).set_body_json(json!({
+        "jsonrpc": "2.0", "id": 1, "error": { "code": -32000, "message": "transaction underpriced" }
+    })))
+    .await;
+    let calls = RpcCalls::new(handler(&[&known, &underpriced]).await);
+
+    let options = || Some(BroadcastOptions { idempotency_key: Some("order-42".into()), ..BroadcastOptions::default() });
+    let report = calls.broadcast_raw_transaction(RAW_TX, options()).await.unwrap();
+    assert_eq!(report.idempotency_key, "order-42");
+    assert_eq!(report.accepted, BTreeSet::from([url_key(&known)]));
+    assert!(report.rejected[&url_key(&underpriced)].contains("underpriced"));
+
+    // Only the rejecting endpoint is retried under the same key; a different key starts over
+    calls.broadcast_raw_transaction(RAW_TX, options()).await.unwrap();
+    assert_eq!((submissions(&known).await, submissions(&underpriced).await), (1, 2));
+    calls.broadcast_raw_transaction(RAW_TX, None).await.unwrap();
+    assert_eq!((submissions(&known).await, submissions(&underpriced).await), (2, 3));
+
+    assert!(matches!(calls.broadcast_raw_transaction("0x123", None).await, Err(RpcHandlerError::InvalidRawTransaction { .. })));
+}
+
+#[cfg(feature = "persistence")]
+#[test]
+fn test_ledger_entries_expire_after_the_ttl() {
+    let recorded_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+    let entry = |key: &str| LedgerEntry {
+        report: BroadcastReport {
+            idempotency_key: key.into(),
+            tx_hash: transaction_hash(RAW_TX).unwrap(),
+            accepted: ["https://a.example/".to_string()].into(),
+            rejected: Default::default(),
+            replayed: false,
+        },
+        recorded_at,
+    };
+    let ttl = Duration::from_secs(60);
+
+    let path = scratch_file("broadcast-ttl");
+    let ledgers: [Box<dyn BroadcastLedger>; 2] = [Box::new(MemoryLedger::new(ttl)), Box::new(FileLedger::open(&path, ttl).unwrap())];
+    for ledger in ledgers {
+        ledger.record("a", entry("a"), recorded_at).unwrap();
+        assert_eq!(ledger.get("a", recorded_at + Duration::from_secs(59)), Some(entry("a")));
+        assert_eq!(ledger.get("a", recorded_at + ttl), None);
+
+        // Recording another key past the TTL drops the expired one for good
+        ledger.record("b", LedgerEntry { recorded_at: recorded_at + ttl, ..entry("b") }, recorded_at + ttl).unwrap();
+        assert_eq!(ledger.get("a", recorded_at), None);
+    }
+    let reopened = FileLedger::open(&path, ttl).unwrap();
+    assert!(reopened.get("b", recorded_at + ttl).is_some());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_transaction_hash() {
+    assert_eq!(transaction_hash(RAW_TX).unwrap(), "0x392ef484070555ae0b4242a8cac46236a799ef27d8bfc2a6cff224e2438e3723");
+    assert_eq!(transaction_hash("0x").unwrap_err().to_string(), "Invalid raw transaction: expected a non-empty, even number of hex digits");
+    assert!(matches!(transaction_hash("0xaé0"), Err(RpcHandlerError::InvalidRawTransaction { .. })));
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::{
+    io::Write,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use common::*;
+use ez_web3_rpc::{tags::*, *};
+use wiremock::{MockServer, ResponseTemplate};
+
+#[derive(Clone, Default)]
+struct Captured(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn tags(pairs: &[(&str, &str)]) -> CallTags {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+fn tagged(pairs: &[(&str, &str)]) -> CallOptions {
+    CallOptions { tags: Some(tags(pairs)), ..CallOptions::default() }
+}
+
+async fn failing_handler() -> (MockServer, Arc<RpcHandler>, Arc<MemoryJournal>) {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_blockNumber", ResponseTemplate::new(503)).await;
+
+    let journal = Arc::new(MemoryJournal::default());
+    let handler_settings = HandlerSettings {
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 1, rpc_call_timeout_ms: 1_000, connect_timeout_ms: None }),
+        metric_tag_keys: vec!["component".into()],
+        ..settings(vec![mk_rpc(&server)])
+    };
+    let components = HandlerComponents { failure_journal: Some(journal.clone()), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(handler_settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+    (server, handler, journal)
+}
+
+#[tokio::test]
+async fn test_a_failed_tagged_call_carries_its_tags_everywhere() {
+    let logs = Captured::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (_server, handler, journal) = failing_handler().await;
+    let mut events = handler.subscribe();
+    let since = SystemTime::now() - Duration::from_secs(1);
+
+    let result = handler.try_proxy_request_with(block_number(), tagged(&[("component", "indexer"), ("request", "abc-123")])).await;
+    assert!(result.is_err());
+
+    let failed = std::iter::from_fn(|| events.try_recv().ok()).find(|event| matches!(event, HandlerEvent::RequestFailed { .. }));
+    match failed {
+        Some(HandlerEvent::RequestFailed { method, tags: event_tags, .. }) => {
+            assert_eq!(method, "eth_blockNumber");
+            assert_eq!(event_tags, tags(&[("component", "indexer"), ("request", "abc-123")]));
+        }
+        other => panic!("expected RequestFailed, got {other:?}"),
+    }
+
+    let summary = handler.failure_summary(since).await.unwrap();
+    assert_eq!(summary.by_tag.get("request=abc-123"), Some(&1));
+    let entries = journal.entries().unwrap();
+    assert_eq!(entries.last().unwrap().tags.get("component").map(String::as_str), Some("indexer"));
+
+    // Only the allowlisted key becomes a metric dimension
+    let snapshot = handler.metrics_snapshot();
+    let indexer = &snapshot.tagged["component"]["indexer"];
+    assert_eq!((indexer.requests, indexer.successes, indexer.failures.values().sum::<u64>()), (1, 0, 1));
+    assert!(!snapshot.tagged.contains_key("request"));
+
+    let logs = String::from_utf8(logs.0.lock().clone()).unwrap();
+    assert!(logs.contains("request=abc-123"), "the call's logs should name its tags: {logs}");
+
+    // Untagged calls fail as before, without the event
+    let mut events = handler.subscribe();
+    assert!(handler.try_proxy_request(block_number()).await.is_err());
+    assert!(!std::iter::from_fn(|| events.try_recv().ok()).any(|event| matches!(event, HandlerEvent::RequestFailed { .. })));
+}
+
+#[tokio::test]
+async fn test_values_of_a_key_past_the_cap_are_counted_together() {
+    let (_server, handler, _journal) = failing_handler().await;
+    for i in 0..MAX_TAG_VALUES + 3 {
+        let component = format!("worker-{i}");
+        let _ = handler.try_proxy_request_with(block_number(), tagged(&[("component", &component)])).await;
+    }
+    let component = &handler.metrics_snapshot().tagged["component"];
+    assert_eq!(component.len(), MAX_TAG_VALUES + 1);
+    assert_eq!(component[OVERFLOW_TAG_VALUE].requests, 3);
+
+    // A value already counted keeps its own series
+    let _ = handler.try_proxy_request_with(block_number(), tagged(&[("component", "worker-0")])).await;
+    assert_eq!(handler.metrics_snapshot().tagged["component"]["worker-0"].requests, 2);
+}
+
+#[tokio::test]
+async fn test_malformed_tags_are_rejected_before_sending() {
+    let (server, handler, _journal) = failing_handler().await;
+    let too_many: Vec<(String, String)> = (0..=MAX_CALL_TAGS).map(|i| (format!("k{i}"), "v".to_string())).collect();
+    let too_many = CallOptions { tags: Some(too_many.into_iter().collect()), ..CallOptions::default() };
+    let long = "x".repeat(MAX_TAG_LEN + 1);
+
+    let sent = count_method(&server, "eth_blockNumber").await;
+    for options in [too_many, tagged(&[("", "v")]), tagged(&[("component", &long)])] {
+        let err = handler.try_proxy_request_with(block_number(), options).await.unwrap_err();
+        assert!(matches!(err, RpcHandlerError::InvalidCallTags { .. }), "got {err:?}");
+    }
+    assert_eq!(count_method(&server, "eth_blockNumber").await, sent);
+
+    let provider = handler.get_provider().await.unwrap();
+    let batch = BatchOptions { tags: Some(tags(&[("", "v")])), ..BatchOptions::default() };
+    assert!(matches!(provider.send_batch(&[block_number()], Some(batch)).await, Err(RpcHandlerError::InvalidCallTags { .. })));
+}
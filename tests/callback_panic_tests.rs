@@ -0,0 +1,134 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use common::*;
+use ez_web3_rpc::{fanout, *};
+use serde_json::{json, Value};
+use wiremock::{MockServer, ResponseTemplate};
+
+/// An in-sync endpoint answering `eth_getBalance` with `result`.
+async fn answering(result: Value) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_getBalance", ResponseTemplate::new(200).set_body_json(rpc_response(1, result))).await;
+    server
+}
+
+fn balance() -> JsonRpcRequest {
+    request("eth_getBalance", json!(["0x0000000000000000000000000000000000000001", "latest"]))
+}
+
+/// Keys answers exactly, but panics on `"0xbad"`, and on merging when `merge_panics`.
+#[cfg(feature = "consensus")]
+#[derive(Debug)]
+struct Brittle {
+    merge_panics: bool,
+}
+
+#[cfg(feature = "consensus")]
+impl ResultComparator for Brittle {
+    fn key(&self, value: &Value) -> String {
+        match value == &json!("0xbad") {
+            true => panic!("comparator bug"),
+            false => value.to_string(),
+        }
+    }
+
+    fn merge(&self, values: &[&Value]) -> Value {
+        match self.merge_panics {
+            true => panic!("merge bug"),
+            false => values[0].clone(),
+        }
+    }
+}
+
+#[cfg(feature = "consensus")]
+fn with(comparator: Brittle) -> Option<ConsensusOptions> {
+    Some(ConsensusOptions { comparator: Some(Arc::new(comparator)), ..ConsensusOptions::default() })
+}
+
+#[cfg(feature = "consensus")]
+async fn calls_for(servers: &[&MockServer]) -> RpcCalls {
+    let rpcs = servers.iter().map(|server| mk_rpc(server)).collect();
+    RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap())
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_a_comparator_panic_fails_only_that_endpoints_vote() {
+    let (good, also_good, bad) = (answering(json!("0x10")).await, answering(json!("0x10")).await, answering(json!("0xbad")).await);
+    let calls = calls_for(&[&good, &also_good, &bad]).await;
+    let mut events = calls.handler().subscribe();
+
+    // A quorum of all three can't be reached early, so every answer is tallied
+    let (result, report) = calls.consensus_with_report::<String>(&balance(), 1.0, with(Brittle { merge_panics: false })).await;
+    assert_eq!(result.unwrap(), "0x10");
+    match &report.outcomes[&url_key(&bad)] {
+        EndpointOutcome::Failed { error } => assert!(error.contains("comparator panicked: comparator bug"), "{error}"),
+        other => panic!("expected the panicking vote to fail, got {other:?}"),
+    }
+    assert_eq!(report.votes.values().sum::<usize>(), 2);
+
+    assert_eq!(calls.handler().metrics_snapshot().panics.get(panics::COMPARATOR), Some(&1));
+    let panicked = std::iter::from_fn(|| events.try_recv().ok()).find(|event| matches!(event, HandlerEvent::CallbackPanicked { .. }));
+    assert!(matches!(panicked, Some(HandlerEvent::CallbackPanicked { ref component, .. }) if component == panics::COMPARATOR), "{panicked:?}");
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_a_merge_panic_fails_the_call_and_leaves_the_handler_usable() {
+    let (first, second) = (answering(json!("0x10")).await, answering(json!("0x10")).await);
+    let calls = calls_for(&[&first, &second]).await;
+
+    let (result, _) = calls.consensus_with_report::<String>(&balance(), 0.66, with(Brittle { merge_panics: true })).await;
+    assert!(matches!(result, Err(RpcHandlerError::ConsensusFailure { .. })), "{result:?}");
+    assert!(calls.handler().metrics_snapshot().panics[panics::COMPARATOR] >= 1);
+
+    let (result, _) = calls.consensus_with_report::<String>(&balance(), 0.66, None).await;
+    assert_eq!(result.unwrap(), "0x10");
+}
+
+/// HTTP, except that requests to `broken` panic.
+struct PanicsFor {
+    broken: String,
+    http: HttpTransportFactory,
+}
+
+struct Panicking(String);
+
+#[async_trait]
+impl JsonRpcTransport for Panicking {
+    fn url(&self) -> &str {
+        &self.0
+    }
+
+    async fn request(&self, _: &JsonRpcRequest) -> Result<JsonRpcResponse<Value>> {
+        panic!("transport bug")
+    }
+}
+
+impl TransportFactory for PanicsFor {
+    fn transport(&self, url: &str) -> Arc<dyn JsonRpcTransport> {
+        match url == self.broken {
+            true => Arc::new(Panicking(url.to_string())),
+            false => self.http.transport(url),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_a_panicking_transport_fails_its_quorum_vote() {
+    let (good, also_good, broken) = (answering(json!(7)).await, answering(json!(7)).await, answering(json!(7)).await);
+    let factory = PanicsFor { broken: broken.uri(), http: HttpTransportFactory::new(Duration::from_secs(2)) };
+    let config = QuorumConfig { stop_at_quorum: false, ..QuorumConfig::new(Arc::new(factory), 0.66) };
+    let endpoints = [Endpoint::new(good.uri()), Endpoint::new(also_good.uri()), Endpoint::new(broken.uri())];
+
+    let outcome = fanout::quorum(&endpoints, &balance(), &config).await.unwrap();
+    assert_eq!(outcome.value, Some(json!(7)));
+    assert_eq!(outcome.voters.len(), 2);
+    assert_eq!(outcome.failures.len(), 1);
+    assert_eq!(outcome.failures[0].url, broken.uri());
+    assert!(outcome.failures[0].error.contains("fan_out_task panicked: transport bug"), "{}", outcome.failures[0].error);
+}
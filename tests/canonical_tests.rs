@@ -0,0 +1,142 @@
+mod common;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const CHECKSUMMED: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+const LOWER: &str = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+/// `CHECKSUMMED` with one letter's case flipped
+const BAD_CHECKSUM: &str = "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+const HASH: &str = "0xB903239F8543D04B5DC1BA6579132B143087C68DB1B2168786408FCBCE568238";
+
+fn canonical(method: &str, params: serde_json::Value) -> serde_json::Value {
+    canonicalize_params(method, &params, false).params
+}
+
+#[test]
+fn test_addresses_are_lowercased_in_address_slots() {
+    assert_eq!(canonical("eth_getBalance", json!([CHECKSUMMED, "latest"])), json!([LOWER, "latest"]));
+    assert_eq!(canonical("eth_getTransactionCount", json!([CHECKSUMMED.to_uppercase().replace("0X", "0x"), "pending"])), json!([LOWER, "pending"]));
+    assert_eq!(canonical("eth_getCode", json!([CHECKSUMMED, "0x10"])), json!([LOWER, "0x10"]));
+    assert_eq!(request_key("eth_getBalance", &json!([CHECKSUMMED, "latest"])), request_key("eth_getBalance", &json!([LOWER, "latest"])));
+}
+
+#[test]
+fn test_hashes_are_lowercased() {
+    let lower = HASH.to_lowercase();
+    assert_eq!(canonical("eth_getBlockByHash", json!([HASH, false])), json!([lower, false]));
+    assert_eq!(canonical("eth_getTransactionReceipt", json!([HASH])), json!([lower]));
+}
+
+#[test]
+fn test_block_quantities_become_minimal_hex_and_tags_are_kept() {
+    for block in [json!("0x10"), json!("0x010"), json!("0x0010"), json!(16)] {
+        assert_eq!(canonical("eth_getBlockByNumber", json!([block, false])), json!(["0x10", false]));
+    }
+    assert_eq!(canonical("eth_getBlockByNumber", json!(["0x0", true])), json!(["0x0", true]));
+    assert_eq!(canonical("eth_getBlockByNumber", json!(["0x000", true])), json!(["0x0", true]));
+    assert_eq!(canonical("eth_getBlockByNumber", json!(["0xAB", true])), json!(["0xab", true]));
+    for tag in ["latest", "pending", "safe", "finalized", "earliest"] {
+        assert_eq!(canonical("eth_getBlockByNumber", json!([tag, false])), json!([tag, false]));
+    }
+    assert_eq!(canonical("eth_getBlockReceipts", json!(["0x00ff"])), json!(["0xff"]));
+}
+
+#[test]
+fn test_storage_slots_are_quantities_but_storage_keys_stay_data() {
+    assert_eq!(canonical("eth_getStorageAt", json!([CHECKSUMMED, "0x00", "latest"])), json!([LOWER, "0x0", "latest"]));
+    let keys = json!(["0x00000000000000000000000000000000000000000000000000000000000000AB"]);
+    assert_eq!(canonical("eth_getProof", json!([CHECKSUMMED, keys, "0x1"])), json!([LOWER, keys, "0x1"]));
+}
+
+#[test]
+fn test_calldata_keeps_its_case_while_call_fields_are_normalized() {
+    let call = json!({ "to": CHECKSUMMED, "from": CHECKSUMMED, "data": "0xA9059CBB00AbCd", "value": "0x00de0b6b3a7640000", "gas": 21000 });
+    let expected = json!({ "data": "0xA9059CBB00AbCd", "from": LOWER, "gas": "0x5208", "to": LOWER, "value": "0xde0b6b3a7640000" });
+    assert_eq!(canonical("eth_call", json!([call, "latest"])), json!([expected, "latest"]));
+    assert_eq!(canonical("eth_estimateGas", json!([{ "to": CHECKSUMMED, "input": "0xABCD" }])), json!([{ "input": "0xABCD", "to": LOWER }]));
+}
+
+#[test]
+fn test_log_filters_normalize_addresses_blocks_and_hashes_but_not_topics() {
+    let topic = "0xDDF252AD1BE2C89B69C2B068FC378DAA952BA7F163C4A11628F55A4DF523B3EF";
+    let filter = json!({ "toBlock": "latest", "fromBlock": "0x0100", "address": [CHECKSUMMED], "topics": [topic] });
+    assert_eq!(
+        canonical("eth_getLogs", json!([filter])),
+        json!([{ "address": [LOWER], "fromBlock": "0x100", "toBlock": "latest", "topics": [topic] }])
+    );
+    assert_eq!(canonical("eth_getLogs", json!([{ "address": CHECKSUMMED, "blockHash": HASH }])), json!([{ "address": LOWER, "blockHash": HASH.to_lowercase() }]));
+    assert_eq!(canonical("eth_newFilter", json!([{ "address": CHECKSUMMED }])), json!([{ "address": LOWER }]));
+}
+
+#[test]
+fn test_unregistered_methods_and_unknown_shapes_are_left_alone_apart_from_key_order() {
+    let params = json!([{ "b": CHECKSUMMED, "a": "0x0010" }, "0xABC"]);
+    assert_eq!(canonical("debug_traceCall", params), json!([{ "a": "0x0010", "b": CHECKSUMMED }, "0xABC"]));
+    // Strings that don't fit their slot aren't guessed at
+    assert_eq!(canonical("eth_getBalance", json!(["0xABC", "latest"])), json!(["0xABC", "latest"]));
+    assert_eq!(canonical("eth_getBlockByNumber", json!(["16", false])), json!(["16", false]));
+    // Params past the registered ones, and non-array params
+    assert_eq!(canonical("eth_chainId", json!(["0xAB"])), json!(["0xAB"]));
+    assert_eq!(canonical("eth_getBalance", json!({ "address": CHECKSUMMED })), json!({ "address": CHECKSUMMED }));
+    // Sending raw transactions is data throughout
+    assert_eq!(canonical("eth_sendRawTransaction", json!(["0xF86C"])), json!(["0xF86C"]));
+}
+
+#[test]
+fn test_eip55_checksums_are_validated_on_request() {
+    assert!(is_valid_checksum(CHECKSUMMED));
+    assert!(is_valid_checksum(LOWER));
+    assert!(is_valid_checksum(&format!("0x{}", LOWER[2..].to_uppercase())));
+    assert!(!is_valid_checksum(BAD_CHECKSUM));
+    assert!(!is_valid_checksum("0x1234"));
+
+    let checked = canonicalize_params("eth_getBalance", &json!([BAD_CHECKSUM, "latest"]), true);
+    assert_eq!(checked.params, json!([LOWER, "latest"]));
+    assert_eq!(checked.warnings.len(), 1);
+    assert!(checked.warnings[0].contains(BAD_CHECKSUM));
+    assert!(canonicalize_params("eth_getBalance", &json!([CHECKSUMMED, "latest"]), true).warnings.is_empty());
+    assert!(canonicalize_params("eth_getBalance", &json!([BAD_CHECKSUM, "latest"]), false).warnings.is_empty());
+}
+
+#[tokio::test]
+async fn test_differently_cased_block_hashes_hit_the_same_cache_entry() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", std::time::Duration::ZERO).await;
+    let block = json!({ "hash": HASH.to_lowercase(), "number": "0x10" });
+    mount_method(&server, "eth_getBlockByHash", ResponseTemplate::new(200).set_body_json(rpc_response(1, block.clone()))).await;
+    let handler = RpcHandler::new(config(HandlerSettings { response_cache_entries: 8, ..settings(vec![mk_rpc(&server)]) }), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let by_hash = |hash: String| JsonRpcRequest::new("eth_getBlockByHash", json!([hash, false])).with_id(7);
+    let first = handler.try_proxy_request(by_hash(HASH.to_string())).await.unwrap();
+    let (second, url) = handler.try_proxy_request_attributed(by_hash(HASH.to_lowercase())).await.unwrap();
+
+    assert_eq!((first.result, second.result.clone()), (Some(block.clone()), Some(block)));
+    assert_eq!((second.id, url), (Some(JsonRpcId::from(7)), url_key(&server)));
+    assert_eq!(count_method(&server, "eth_getBlockByHash").await, 1);
+}
+
+#[tokio::test]
+async fn test_nothing_is_cached_unless_enabled_or_for_changing_results() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", std::time::Duration::ZERO).await;
+    mount_method(&server, "eth_getBlockByHash", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x10" })))).await;
+    mount_method(&server, "eth_getBalance", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x5")))).await;
+
+    let uncached = RpcHandler::new(config(settings(vec![mk_rpc(&server)])), None).await.unwrap();
+    uncached.init().await.unwrap();
+    for _ in 0..2 {
+        uncached.try_proxy_request(request("eth_getBlockByHash", json!([HASH, false]))).await.unwrap();
+    }
+    assert_eq!(count_method(&server, "eth_getBlockByHash").await, 2);
+
+    let cached = RpcHandler::new(config(HandlerSettings { response_cache_entries: 8, ..settings(vec![mk_rpc(&server)]) }), None).await.unwrap();
+    cached.init().await.unwrap();
+    for _ in 0..2 {
+        cached.try_proxy_request(request("eth_getBalance", json!([CHECKSUMMED, "latest"]))).await.unwrap();
+    }
+    assert_eq!(count_method(&server, "eth_getBalance").await, 2, "balances change, so they are never cached");
+}
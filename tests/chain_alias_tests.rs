@@ -0,0 +1,77 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const ALIAS: NetworkId = TEST_NETWORK_ID + 1;
+
+/// Lists endpoints under the network ids they were registered for.
+struct Listings(HashMap<NetworkId, Vec<Rpc>>);
+
+impl RpcSource for Listings {
+    fn rpcs(&self, network_id: NetworkId) -> Vec<Rpc> {
+        self.0.get(&network_id).cloned().unwrap_or_default()
+    }
+}
+
+async fn serving(chain_id: NetworkId) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(format!("{chain_id:#x}"))))).await;
+    server
+}
+
+/// A handler for `TEST_NETWORK_ID`, which the source lists nothing under, with `listed` under `ALIAS`.
+async fn handler(listed: &[&MockServer], chain_aliases: Vec<NetworkId>) -> Arc<RpcHandler> {
+    let listings = Listings(HashMap::from([(ALIAS, listed.iter().map(|server| mk_rpc(server)).collect())]));
+    let components = HandlerComponents { rpc_source: Some(Arc::new(listings)), ..HandlerComponents::default() };
+    let settings = HandlerSettings { chain_aliases, ..settings(Vec::<Rpc>::new()) };
+    RpcHandler::with_components(config(settings), None, components).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_aliased_ids_merge_into_one_working_set() {
+    let (own, sibling) = (serving(TEST_NETWORK_ID).await, serving(ALIAS).await);
+
+    let unaliased = handler(&[&own, &sibling], Vec::new()).await;
+    assert!(unaliased.rpcs().is_empty());
+    assert!(matches!(unaliased.init().await, Err(RpcHandlerError::NoAvailableRpcs { .. })));
+
+    let handler = handler(&[&own, &sibling], vec![ALIAS]).await;
+    handler.init().await.unwrap();
+    let provenance = handler.rpc_provenance();
+    assert_eq!(provenance.len(), 2);
+    assert!(provenance.iter().all(|endpoint| endpoint.listed_under == Some(ALIAS) && endpoint.origin == RpcOrigin::Registered), "{provenance:?}");
+
+    // Each endpoint is credited with the id it actually reports
+    let reported = handler.reported_chain_ids();
+    assert_eq!(reported[&url_key(&own)], TEST_NETWORK_ID);
+    assert_eq!(reported[&url_key(&sibling)], ALIAS);
+    let by_url: HashMap<String, Option<NetworkId>> = provenance.into_iter().map(|endpoint| (endpoint.url, endpoint.reported_chain_id)).collect();
+    assert_eq!(by_url[&url_key(&sibling)], Some(ALIAS));
+
+    let calls = RpcCalls::new(handler.clone());
+    let chain_id = request("eth_chainId", json!([]));
+    let answered = calls.try_rpc_call(&chain_id).await.unwrap().into_result().unwrap();
+    assert!([json!(format!("{TEST_NETWORK_ID:#x}")), json!(format!("{ALIAS:#x}"))].contains(&answered), "{answered}");
+}
+
+#[tokio::test]
+async fn test_an_id_outside_the_alias_group_fails_init() {
+    let (own, sibling, other) = (serving(TEST_NETWORK_ID).await, serving(ALIAS).await, serving(1).await);
+    let handler = handler(&[&own, &sibling, &other], vec![ALIAS]).await;
+
+    match handler.init().await {
+        Err(RpcHandlerError::ChainAliasMismatch { url, reported, accepted }) => {
+            assert_eq!(url, url_key(&other));
+            assert_eq!(reported, 1);
+            assert_eq!(accepted, [TEST_NETWORK_ID, ALIAS]);
+        }
+        other => panic!("expected ChainAliasMismatch, got {other:?}"),
+    }
+    assert_eq!(handler.reported_chain_ids()[&url_key(&sibling)], ALIAS);
+}
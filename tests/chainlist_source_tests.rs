@@ -0,0 +1,160 @@
+#![cfg(feature = "chainlist")]
+
+use std::path::PathBuf;
+
+use ez_web3_rpc::chainlist::{self, source::*};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const CHAINS_FIXTURE: &str = r#"[
+    {"chainId": 1, "name": "Ethereum Mainnet", "rpc": [
+        "https://eth.example/", "https://mainnet.infura.io/v3/${INFURA_API_KEY}", "https://a.example", "https://eth.example"
+    ]},
+    {"chainId": 5, "name": "Goerli", "status": "deprecated", "rpc": ["https://goerli.example"]},
+    {"chainId": 10, "name": "OP Mainnet", "rpc": ["https://op.example"], "parent": {"type": "L2", "chain": "eip155-1"}},
+    {"chainId": 61, "name": "Mainnet Before Rebrand", "status": "deprecated", "redirectChain": "eip155-1", "rpc": []},
+    {"chainId": 99, "name": "Keyed Only", "rpc": ["https://keyed.example/${INFURA_API_KEY}"]},
+    {"chainId": 100, "name": "Gnosis", "rpc": []}
+]"#;
+
+const TVL_FIXTURE: &str = r#"[
+    {"name": "op mainnet", "tvl": 900.5, "tokenSymbol": "OP"},
+    {"name": "Ethereum Mainnet", "tvl": 50000.0}
+]"#;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ez_web3_rpc-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+async fn registry_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/tvl"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(TVL_FIXTURE))
+        .mount(&server)
+        .await;
+    server
+}
+
+fn options_for(server: &MockServer, cache_dir: Option<PathBuf>) -> SourceOptions {
+    SourceOptions {
+        chains_url: format!("{}/chains", server.uri()),
+        tvl_url: format!("{}/tvl", server.uri()),
+        cache_dir,
+        ..SourceOptions::default()
+    }
+}
+
+#[test]
+fn test_registry_filters_and_normalizes_fixture() {
+    let registry = ChainRegistry::from_json(CHAINS_FIXTURE, TVL_FIXTURE).unwrap();
+
+    let ids: Vec<u64> = registry.chains.iter().map(|chain| chain.chain_id).collect();
+    assert_eq!(ids, vec![1, 10], "deprecated, key-only and RPC-less chains are dropped; sorted by TVL");
+
+    let eth = registry.get(1).unwrap();
+    assert_eq!(eth.name, "ethereum_mainnet");
+    assert_eq!(eth.tvl, 50000.0);
+    assert_eq!(eth.rpcs, vec!["https://a.example", "https://eth.example"]);
+
+    assert_eq!(registry.get(10).unwrap().tvl, 900.5, "TVL names match case-insensitively");
+    assert_eq!(registry.aliases, vec![(61, 1)], "deprecated entries still alias; an L2 isn't its parent");
+}
+
+#[test]
+fn test_remove_trailing_slash_basic() {
+    assert_eq!(remove_trailing_slash("http://foo.com/"), "http://foo.com");
+    assert_eq!(remove_trailing_slash("http://foo.com"), "http://foo.com");
+    assert_eq!(remove_trailing_slash("/"), "");
+    assert_eq!(remove_trailing_slash(""), "");
+}
+
+#[test]
+fn test_render_emits_statics_and_escapes_names() {
+    let mut registry = ChainRegistry::from_json(CHAINS_FIXTURE, TVL_FIXTURE).unwrap();
+    registry.chains[0].name = "quote\"chain".to_string();
+    let source = registry.render();
+
+    for item in ["pub struct ChainInfo", "CHAIN_DATA", "CHAIN_IDS", "EXTRA_RPCS_DATA", "CHAIN_ALIASES"] {
+        assert!(source.contains(item), "missing {item}");
+    }
+    assert!(source.contains(r#""quote\"chain".to_string()"#));
+    assert!(ChainRegistry::default().render().contains("EXTRA_RPCS_DATA"), "empty registries still render every static");
+}
+
+#[tokio::test]
+async fn test_fetch_revalidates_with_etag() {
+    let server = registry_server().await;
+    Mock::given(method("GET"))
+        .and(path("/chains"))
+        .and(header("If-None-Match", "\"v1\""))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/chains"))
+        .respond_with(ResponseTemplate::new(200).insert_header("ETag", "\"v1\"").set_body_string(CHAINS_FIXTURE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = options_for(&server, Some(scratch_dir("etag")));
+    let first = fetch_chain_registry(&options).await.unwrap();
+    let second = fetch_chain_registry(&options).await.unwrap();
+    assert_eq!(first, second, "a 304 is served from the cached body");
+    assert_eq!(first.chains.len(), 2);
+
+    // The cached copy keeps the ETag it was served with
+    let snapshot = fetch_registry_snapshot(&options).await.unwrap();
+    let etags: Vec<Option<&str>> = snapshot.sources.iter().map(|source| source.etag.as_deref()).collect();
+    assert_eq!(etags, vec![Some("\"v1\""), None]);
+    assert!(snapshot.generated_at.is_some());
+}
+
+#[tokio::test]
+async fn test_fetch_falls_back_to_cache_on_server_error() {
+    let server = registry_server().await;
+    Mock::given(method("GET"))
+        .and(path("/chains"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(CHAINS_FIXTURE))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/chains"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+
+    let cache_dir = scratch_dir("stale");
+    let fresh = fetch_chain_registry(&options_for(&server, Some(cache_dir.clone()))).await.unwrap();
+    let stale = fetch_chain_registry(&options_for(&server, Some(cache_dir))).await.unwrap();
+    assert_eq!(fresh, stale);
+
+    let err = fetch_chain_registry(&options_for(&server, None)).await.unwrap_err();
+    assert!(matches!(err, SourceError::Status { status: 503, .. }), "got {err}");
+}
+
+#[tokio::test]
+async fn test_refresh_from_network_replaces_chain_data() {
+    let server = registry_server().await;
+    Mock::given(method("GET"))
+        .and(path("/chains"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(CHAINS_FIXTURE))
+        .mount(&server)
+        .await;
+
+    let loaded = chainlist::refresh_from_network(&options_for(&server, None)).await.unwrap();
+    assert_eq!(loaded, 2);
+    assert_eq!(chainlist::get_chain_ids(), vec![(1, "ethereum_mainnet".to_string()), (10, "op_mainnet".to_string())]);
+    let extra: Vec<String> = chainlist::get_extra_rpcs(10).iter().map(|rpc| rpc.url.to_string()).collect();
+    assert_eq!(extra, vec!["https://op.example/"]);
+
+    // Looking up the retired id lands on the chain it redirects to
+    assert_eq!(chainlist::alias_group(61), vec![1, 61]);
+    assert_eq!(chainlist::get_chain_info(61).unwrap().chain_id, 1);
+    assert!(chainlist::get_chain_info(5).is_none());
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::{process::Output, time::Duration};
+
+use common::*;
+use serde_json::{json, Value};
+use wiremock::{MockServer, ResponseTemplate};
+
+/// Run the `ez-web3-rpc` binary with `args`, without blocking the runtime the mocks serve on.
+async fn cli(args: &[&str]) -> Output {
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_ez-web3-rpc")).args(args).output().await.unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+async fn endpoint() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x20", Duration::ZERO).await;
+    server
+}
+
+#[tokio::test]
+async fn test_probe_reports_injected_endpoints() {
+    let server = endpoint().await;
+    let network_id = TEST_NETWORK_ID.to_string();
+
+    let output = cli(&["--json", "probe", &network_id, "--rpc-url", &server.uri()]).await;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    let report: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(report["network_id"], json!(TEST_NETWORK_ID));
+    assert_eq!(report["endpoints"][0]["url"], json!(url_key(&server)));
+    assert!(report["endpoints"][0]["latency_ms"].is_u64());
+
+    // Text mode marks the active endpoint
+    let output = cli(&["probe", &network_id, "--rpc-url", &server.uri()]).await;
+    assert!(stdout(&output).contains("* tier   -"), "{}", stdout(&output));
+}
+
+#[tokio::test]
+async fn test_call_prints_the_result_and_exits_on_json_rpc_errors() {
+    let server = endpoint().await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x67932")))).await;
+    mount_method(
+        &server,
+        "eth_call",
+        ResponseTemplate::new(200).set_body_json(json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": 3, "message": "execution reverted" } })),
+    )
+    .await;
+    let network_id = TEST_NETWORK_ID.to_string();
+
+    let output = cli(&["call", &network_id, "eth_chainId", "--rpc-url", &server.uri()]).await;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert_eq!(stdout(&output).trim(), "\"0x67932\"");
+    assert_eq!(count_method(&server, "eth_chainId").await, 1);
+
+    let output = cli(&["--json", "call", &network_id, "eth_call", r#"[{"to":"0x0000000000000000000000000000000000000001"},"latest"]"#, "--rpc-url", &server.uri()]).await;
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let response: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(response["error"]["message"], "execution reverted");
+}
+
+#[tokio::test]
+async fn test_failing_doctor_run_exits_with_problems() {
+    // Nothing listens on the discard port
+    let output = cli(&["doctor", &TEST_NETWORK_ID.to_string(), "--rpc-url", "http://127.0.0.1:9/"]).await;
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let text = stdout(&output);
+    assert!(text.contains("[FAIL] live probe"), "{text}");
+    assert!(text.trim_end().ends_with("problems found"), "{text}");
+}
+
+#[tokio::test]
+async fn test_bad_usage_exits_with_usage() {
+    for args in [&["call", "not-a-network", "eth_chainId"][..], &["call", "1", "eth_chainId", "{}"], &["teleport", "1"], &["probe", "1", "--rpc-url", "ws://localhost:1"]] {
+        let output = cli(args).await;
+        assert_eq!(output.status.code(), Some(2), "{args:?}: {output:?}");
+    }
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::matchers::{body_partial_json, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn handler_with_clock(settings: HandlerSettings, clock: &MockClock) -> Arc<RpcHandler> {
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), Some(Strategy::Fastest), components).await.unwrap();
+    handler.init().await.unwrap();
+    handler
+}
+
+#[tokio::test]
+async fn test_mock_clock_sleep_completes_only_after_advance() {
+    let clock = MockClock::new();
+    let (start_instant, start_system) = (clock.now_instant(), clock.now_system());
+
+    let sleeper = {
+        let clock = clock.clone();
+        tokio::spawn(async move { clock.sleep(Duration::from_secs(10)).await })
+    };
+    clock.wait_for_sleepers(1).await;
+
+    clock.advance(Duration::from_secs(9));
+    tokio::task::yield_now().await;
+    assert!(!sleeper.is_finished());
+
+    clock.advance(Duration::from_secs(1));
+    sleeper.await.unwrap();
+    assert_eq!(clock.sleepers(), 0);
+    assert_eq!(clock.now_instant() - start_instant, Duration::from_secs(10));
+    assert_eq!(clock.now_system().duration_since(start_system).unwrap(), Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn test_retry_backoff_waits_on_clock() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_chainId" })))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+
+    let mut settings = settings(vec![mk_rpc(&server)]);
+    settings.proxy_settings = Some(ProxySettings { retry_count: 2, retry_delay_ms: 60_000, rpc_call_timeout_ms: 1000, connect_timeout_ms: None });
+    let clock = MockClock::new();
+    let handler = handler_with_clock(settings, &clock).await;
+
+    let request_task = {
+        let handler = handler.clone();
+        tokio::spawn(async move { handler.try_proxy_request(request("eth_chainId", json!([]))).await })
+    };
+
+    // The first attempt failed and the provider is now backing off for a minute of mock time
+    clock.wait_for_sleepers(1).await;
+    assert_eq!(count_method(&server, "eth_chainId").await, 1);
+    assert!(!request_task.is_finished());
+
+    clock.advance(Duration::from_secs(60));
+    let response = request_task.await.unwrap().unwrap();
+    assert_eq!(response.result, Some(json!("0x1")));
+    assert_eq!(count_method(&server, "eth_chainId").await, 2);
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_consensus_cooldown_expires_on_clock() {
+    let good = MockServer::start().await;
+    let bad = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10"))).set_delay(Duration::from_millis(50)))
+        .mount(&good)
+        .await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(500)).mount(&bad).await;
+
+    let bad_url = format!("http://localhost:{}/", bad.address().port());
+    let rpcs: Vec<Rpc> = [format!("{}/a", good.uri()), format!("{}/b", good.uri()), bad_url]
+        .iter()
+        .map(|url| Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) })
+        .collect();
+    let clock = MockClock::new();
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings(rpcs)), None, components).await.unwrap();
+    let calls = RpcCalls::new(handler);
+
+    let options = ConsensusOptions { cooldown_ms: Some(30_000), per_host_concurrency: Some(3), ..ConsensusOptions::default() };
+    let (result, _) = calls.consensus_with_report::<String>(&block_number(), 0.5, Some(options.clone())).await;
+    assert_eq!(result.unwrap(), "0x10");
+    assert_eq!(bad.received_requests().await.unwrap().len(), 1);
+
+    // Still cooling down one second before expiry, so the bad endpoint isn't contacted
+    clock.advance(Duration::from_secs(29));
+    calls.consensus_with_report::<String>(&block_number(), 0.5, Some(options.clone())).await.0.unwrap();
+    assert_eq!(bad.received_requests().await.unwrap().len(), 1);
+
+    clock.advance(Duration::from_secs(1));
+    calls.consensus_with_report::<String>(&block_number(), 0.5, Some(options)).await.0.unwrap();
+    assert_eq!(bad.received_requests().await.unwrap().len(), 2);
+}
@@ -0,0 +1,158 @@
+//! Shared wiremock helpers for integration tests that need a probe-passing JSON-RPC endpoint.
+#![allow(dead_code)]
+
+use std::{sync::Arc, time::Duration};
+
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::matchers::{body_partial_json, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Runtime bytecode prefix the probe expects for Permit2.
+pub const PERMIT2_CODE: &str = "0x6040608081526004908136101561001557600080fd5b600090813560e01c";
+
+pub fn rpc_response(id: u64, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// A request under id 1, the id `rpc_response` answers with.
+pub fn request(rpc_method: &str, params: Value) -> JsonRpcRequest {
+    JsonRpcRequest::new(rpc_method, params).with_id(1)
+}
+
+pub fn block_number() -> JsonRpcRequest {
+    request("eth_blockNumber", json!([]))
+}
+
+pub fn mk_rpc(server: &MockServer) -> Rpc {
+    Rpc { url: server.uri().parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+/// `server` configured in failover tier `tier`.
+pub fn tiered(server: &MockServer, tier: u8) -> RpcConfig {
+    RpcConfig { tier: Some(tier), ..mk_rpc(server).into() }
+}
+
+/// The key the handler uses for a mock server in latency maps and provider URLs.
+pub fn url_key(server: &MockServer) -> String {
+    server.uri().parse::<url::Url>().unwrap().to_string()
+}
+
+/// Mount responses for the two probe calls (`eth_getBlockByNumber`, `eth_getCode`) with an optional delay.
+pub async fn mount_probe(server: &MockServer, block_number: &str, delay: Duration) {
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getBlockByNumber" })))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_json(rpc_response(1, json!({ "number": block_number, "hash": "0xabc" })))
+            .set_delay(delay))
+        .mount(server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getCode" })))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_json(rpc_response(1, json!(PERMIT2_CODE)))
+            .set_delay(delay))
+        .mount(server)
+        .await;
+}
+
+/// Mount a fixed response for a single JSON-RPC method.
+pub async fn mount_method(server: &MockServer, rpc_method: &str, response: ResponseTemplate) {
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": rpc_method })))
+        .respond_with(response)
+        .mount(server)
+        .await;
+}
+
+/// Count requests received by a mock for the given JSON-RPC method.
+pub async fn count_method(server: &MockServer, rpc_method: &str) -> usize {
+    server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter(|req| {
+            serde_json::from_slice::<Value>(&req.body)
+                .ok()
+                .and_then(|body| body.get("method").and_then(|m| m.as_str()).map(|m| m == rpc_method))
+                .unwrap_or(false)
+        })
+        .count()
+}
+
+pub fn settings(rpcs: Vec<impl Into<RpcConfig>>) -> HandlerSettings {
+    HandlerSettings {
+        log_level: LogLevel::Error,
+        network_rpcs: rpcs.into_iter().map(Into::into).collect(),
+        network_name: "local_testnet".to_string(),
+        rpc_probe_timeout_ms: 2000,
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: 1000, connect_timeout_ms: None }),
+        data_scope: DataScope::OnlyThisNetwork,
+        ..HandlerSettings::default()
+    }
+}
+
+/// A network id that won't exist in the generated chainlist data so tests stay hermetic.
+pub const TEST_NETWORK_ID: u64 = 424242;
+
+pub fn config(settings: HandlerSettings) -> HandlerConfig {
+    HandlerConfig { network_id: TEST_NETWORK_ID, settings: Some(settings) }
+}
+
+/// A handler over `settings` on the test network, past `init`.
+pub async fn init_handler(settings: HandlerSettings) -> Arc<RpcHandler> {
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+    handler
+}
+
+/// A bare-bones HTTP endpoint that answers every POST with `body` after `delay`, without wiremock.
+///
+/// Returns the endpoint's URL. The server lives until the test's runtime shuts down.
+pub async fn serve_fixed(body: Value, delay: Duration) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let payload = body.to_string();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let payload = payload.clone();
+            tokio::spawn(async move {
+                // Read the headers and as much body as Content-Length announces
+                let mut request = Vec::new();
+                let mut chunk = [0u8; 4096];
+                loop {
+                    let Ok(read) = stream.read(&mut chunk).await else { return };
+                    if read == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&chunk[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(header_end) = text.find("\r\n\r\n") {
+                        let content_length = text[..header_end]
+                            .lines()
+                            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                            .and_then(|v| v.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= header_end + 4 + content_length {
+                            break;
+                        }
+                    }
+                }
+
+                tokio::time::sleep(delay).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{payload}",
+                    payload.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+
+    url
+}
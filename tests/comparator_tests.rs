@@ -0,0 +1,99 @@
+mod common;
+
+#[cfg(feature = "consensus")]
+use std::sync::Arc;
+
+#[cfg(feature = "consensus")]
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+#[cfg(feature = "consensus")]
+use wiremock::matchers::method;
+#[cfg(feature = "consensus")]
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const BLOCK_HASH: &str = "0xAbCd000000000000000000000000000000000000000000000000000000000001";
+
+#[test]
+fn test_stable_string_ignores_key_order() {
+    let comparator = StableStringComparator;
+    assert_eq!(comparator.key(&json!({ "a": 1, "b": 2 })), comparator.key(&json!({ "b": 2, "a": 1 })));
+    assert_ne!(comparator.key(&json!({ "a": 1 })), comparator.key(&json!({ "a": 2 })));
+    assert_eq!(comparator.key(&json!("0x10")), "0x10");
+}
+
+#[test]
+fn test_hash_only_block_comparator() {
+    let comparator = HashOnlyBlockComparator;
+    let a = json!({ "hash": BLOCK_HASH, "number": "0x10", "totalDifficulty": "0x0" });
+    let b = json!({ "hash": BLOCK_HASH.to_lowercase(), "number": "0x10", "l1BlockNumber": "0x5" });
+    let c = json!({ "hash": "0x02", "number": "0x10" });
+
+    assert_eq!(comparator.key(&a), comparator.key(&b));
+    assert_ne!(comparator.key(&a), comparator.key(&c));
+    assert_eq!(comparator.key(&Value::Null), StableStringComparator.key(&Value::Null));
+    assert_eq!(comparator.merge(&[&a, &b]), a);
+}
+
+#[test]
+fn test_numeric_tolerance_comparator() {
+    let comparator = NumericToleranceComparator::new(["baseFeePerGas", "gasUsedRatio"], 0.05);
+    let fee_history = |base_fee: &[&str], ratio: f64| json!({
+        "oldestBlock": "0x10",
+        "baseFeePerGas": base_fee,
+        "gasUsedRatio": [ratio],
+    });
+
+    let a = fee_history(&["0x3a699d00", "0x3b9aca00"], 0.52); // 0.98 gwei
+    let b = fee_history(&["0x3b9aca00", "0x3b9aca00"], 0.525); // 1.00 gwei
+    let c = fee_history(&["0x3c14dc00", "0x3b9aca00"], 0.53); // 1.008 gwei
+    let far = fee_history(&["0x4a817c80", "0x3b9aca00"], 0.52); // 1.25 gwei
+
+    assert_eq!(comparator.key(&a), comparator.key(&b));
+    assert_eq!(comparator.key(&b), comparator.key(&c));
+    assert_ne!(comparator.key(&a), comparator.key(&far));
+
+    // Fields outside the tolerance list still have to match exactly
+    let mut other_block = a.clone();
+    other_block["oldestBlock"] = json!("0x11");
+    assert_ne!(comparator.key(&a), comparator.key(&other_block));
+
+    let merged = comparator.merge(&[&a, &c, &b]);
+    assert_eq!(merged["baseFeePerGas"], json!(["0x3b9aca00", "0x3b9aca00"]));
+    assert_eq!(merged["gasUsedRatio"], json!([0.525]));
+    assert_eq!(merged["oldestBlock"], json!("0x10"));
+}
+
+#[cfg(feature = "consensus")]
+async fn block_backend(extra_field: &str) -> MockServer {
+    let server = MockServer::start().await;
+    let mut block = json!({ "hash": BLOCK_HASH, "number": "0x10" });
+    block[extra_field] = json!("0x1");
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, block)))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[cfg(feature = "consensus")]
+async fn calls_for(servers: &[&MockServer]) -> RpcCalls {
+    let rpcs = servers.iter().map(|server| mk_rpc(server)).collect();
+    let handler = RpcHandler::new(config(settings(rpcs)), None).await.unwrap();
+    RpcCalls::new(handler)
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_hash_comparator_reaches_quorum_on_differing_extras() {
+    let servers = [block_backend("l1BlockNumber").await, block_backend("totalDifficulty").await, block_backend("mixHash").await];
+    let calls = calls_for(&servers.iter().collect::<Vec<_>>()).await;
+    let request = request("eth_getBlockByNumber", json!(["0x10", false]));
+
+    let exact = calls.bft_consensus::<Value>(&request, 0.66, 0.66, None).await;
+    assert!(matches!(exact, Err(RpcHandlerError::ConsensusFailure { .. })));
+
+    let options = ConsensusOptions { comparator: Some(Arc::new(HashOnlyBlockComparator)), ..ConsensusOptions::default() };
+    let block = calls.bft_consensus::<Value>(&request, 0.66, 0.66, Some(options)).await.unwrap();
+    assert_eq!(block["hash"], json!(BLOCK_HASH));
+}
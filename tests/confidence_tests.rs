@@ -0,0 +1,140 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::*;
+use ez_web3_rpc::confidence::reputation;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+fn stats(samples: usize, agreements: usize) -> AgreementStats {
+    AgreementStats { samples, agreements, agreement_rate: agreements as f64 / samples as f64, suspected_dishonest: false }
+}
+
+fn answer(url: &str, reputation: f64, agrees: bool) -> Corroboration {
+    Corroboration { url: url.to_string(), reputation, agrees }
+}
+
+fn evidence(responses: Vec<Corroboration>, finality: Finality) -> Evidence {
+    Evidence { primary: responses[0].url.clone(), responses, unanswered: Vec::new(), block: None, finality }
+}
+
+#[test]
+fn test_lone_unreliable_provider_scores_low() {
+    let options = ConfidenceOptions::default();
+    let unreliable = reputation(Some(&stats(10, 2)), options.unknown_reputation);
+    assert_eq!(unreliable, 0.25);
+
+    let score = evidence(vec![answer("a", unreliable, true)], Finality::NearHead).score(&options);
+    assert!((score - 0.225).abs() < 1e-9, "{score}");
+    // A flagged endpoint vouches for nothing
+    let flagged = AgreementStats { suspected_dishonest: true, ..stats(10, 2) };
+    assert_eq!(evidence(vec![answer("a", reputation(Some(&flagged), 0.5), true)], Finality::Finalized).score(&options), 0.0);
+}
+
+#[test]
+fn test_three_reputable_agreeing_providers_on_finalized_data_score_near_one() {
+    let options = ConfidenceOptions::default();
+    let reputable = reputation(Some(&stats(20, 20)), options.unknown_reputation);
+    let responses = vec![answer("a", reputable, true), answer("b", reputable, true), answer("c", reputable, true)];
+
+    let finalized = evidence(responses.clone(), Finality::Finalized).score(&options);
+    assert!(finalized > 0.999, "{finalized}");
+    assert_eq!(finalized, evidence(responses.clone(), Finality::Finalized).score(&options), "the same evidence scores the same");
+    let near_head = evidence(responses, Finality::NearHead).score(&options);
+    assert!((near_head - finalized * 0.9).abs() < 1e-9);
+}
+
+#[test]
+fn test_disagreement_and_unknown_reputation_lower_the_score() {
+    let options = ConfidenceOptions::default();
+    let unknown = reputation(None, options.unknown_reputation);
+    let score = |responses: Vec<Corroboration>| evidence(responses, Finality::Finalized).score(&options);
+
+    let lone = score(vec![answer("a", unknown, true)]);
+    let corroborated = score(vec![answer("a", unknown, true), answer("b", unknown, true), answer("c", unknown, true)]);
+    let split = score(vec![answer("a", unknown, true), answer("b", unknown, true), answer("c", unknown, false)]);
+    let contradicted = score(vec![answer("a", unknown, true), answer("b", unknown, false), answer("c", unknown, false)]);
+    assert_eq!(lone, 0.5);
+    assert_eq!(corroborated, 0.875);
+    assert!(corroborated > split && split > contradicted, "{corroborated} {split} {contradicted}");
+    // Disagreeing with a flagged endpoint costs nothing
+    assert_eq!(score(vec![answer("a", unknown, true), answer("b", 0.0, false)]), lone);
+}
+
+const ADDRESS: &str = "0x00000000219ab540356cbb839cbe05303d7705fa";
+
+fn balance_at_16() -> JsonRpcRequest {
+    request("eth_getBalance", json!([ADDRESS, "0x10"]))
+}
+
+fn balance(value: &str, delay: Duration) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(value))).set_delay(delay)
+}
+
+/// A primary answering `0x64` and secondaries answering `secondaries` after `delay`, with the
+/// head at block 256 so block 16 is finalized. The primary's tier keeps the proxy from racing
+/// the read to the secondaries, so only the confidence check waits on them.
+async fn scripted(secondaries: &[&str], delay: Duration) -> (RpcCalls, Vec<MockServer>) {
+    let mut servers = Vec::new();
+    for (index, answer) in ["0x64"].iter().chain(secondaries).enumerate() {
+        let server = MockServer::start().await;
+        mount_probe(&server, "0x100", Duration::from_millis(if index == 0 { 0 } else { 50 })).await;
+        mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x100")))).await;
+        mount_method(&server, "eth_getBalance", balance(answer, if index == 0 { Duration::ZERO } else { delay })).await;
+        servers.push(server);
+    }
+    let rpcs = servers.iter().enumerate().map(|(index, server)| RpcConfig { tier: (index == 0).then_some(1), ..mk_rpc(server).into() }).collect();
+    let settings = HandlerSettings { failover_policy: FailoverPolicy::TierStrict, ..settings(rpcs) };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&servers[0]));
+    let calls = RpcCalls::new(handler);
+    let head = block_number();
+    calls.try_rpc_call(&head).await.unwrap();
+    (calls, servers)
+}
+
+fn options(max_overhead_ms: u64) -> ConfidenceOptions {
+    ConfidenceOptions { max_overhead: Duration::from_millis(max_overhead_ms), ..ConfidenceOptions::default() }
+}
+
+#[tokio::test]
+async fn test_scores_order_by_how_many_endpoints_corroborate() {
+    let mut scores = Vec::new();
+    for secondaries in [&["0x64", "0x64"][..], &[], &["0x65", "0x66"]] {
+        let (calls, servers) = scripted(secondaries, Duration::from_millis(30)).await;
+        let read: ConfidentValue<String> = calls.call_with_confidence(&balance_at_16(), options(1000)).await.unwrap();
+
+        assert_eq!(read.value, "0x64");
+        assert_eq!(read.evidence.primary, url_key(&servers[0]));
+        assert_eq!((read.evidence.block, read.evidence.finality), (Some(16), Finality::Finalized));
+        assert_eq!(read.evidence.responses.len(), servers.len(), "{:?}", read.evidence);
+        assert_eq!(read.evidence.responses.iter().filter(|response| response.agrees).count(), 1 + secondaries.iter().filter(|answer| **answer == "0x64").count());
+        scores.push(read.confidence);
+    }
+    let [corroborated, lone, contradicted] = scores[..] else { unreachable!() };
+    assert!(corroborated > lone && lone > contradicted, "{scores:?}");
+    assert_eq!(corroborated, 0.875);
+}
+
+#[tokio::test]
+async fn test_slow_secondaries_cost_at_most_the_overhead() {
+    let (calls, servers) = scripted(&["0x64", "0x64"], Duration::from_secs(3)).await;
+
+    let started = Instant::now();
+    let read: ConfidentValue<String> = calls.call_with_confidence(&balance_at_16(), options(100)).await.unwrap();
+    let elapsed = started.elapsed();
+
+    assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_millis(1000), "{elapsed:?}");
+    assert_eq!(read.value, "0x64");
+    assert_eq!(read.evidence.responses.len(), 1);
+    let mut unanswered = read.evidence.unanswered.clone();
+    unanswered.sort();
+    let mut expected = vec![url_key(&servers[1]), url_key(&servers[2])];
+    expected.sort();
+    assert_eq!(unanswered, expected);
+    // Scored as the lone primary it effectively was
+    assert_eq!(read.confidence, 0.5);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::{config::PartialProxySettings, *};
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// Lists the same endpoints for every network, as a registry would.
+struct Listed(Vec<Rpc>);
+
+impl RpcSource for Listed {
+    fn rpcs(&self, _network_id: NetworkId) -> Vec<Rpc> {
+        self.0.clone()
+    }
+}
+
+async fn healthy() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x20", Duration::ZERO).await;
+    mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x20")))).await;
+    server
+}
+
+fn ci_config(injected: &MockServer, partial: PartialHandlerSettings) -> HandlerConfig {
+    HandlerConfig::profile(Profile::Ci, TEST_NETWORK_ID)
+        .overlay(PartialHandlerSettings { network_rpcs: Some(vec![mk_rpc(injected).into()]), ..Default::default() })
+        .overlay(partial)
+}
+
+#[tokio::test]
+async fn test_ci_profile_sends_nothing_beyond_the_injected_endpoint() {
+    let injected = healthy().await;
+    let listed = healthy().await;
+    let components = || HandlerComponents { rpc_source: Some(Arc::new(Listed(vec![mk_rpc(&listed)]))), ..Default::default() };
+
+    let handler = RpcHandler::with_components(ci_config(&injected, PartialHandlerSettings::default()), None, components()).await.unwrap();
+    assert_eq!(handler.rpcs().iter().map(|rpc| rpc.url.to_string()).collect::<Vec<_>>(), [url_key(&injected)]);
+    handler.init().await.unwrap();
+    handler.refresh().await.unwrap();
+    let (response, served_by) = handler.try_proxy_request_attributed(block_number()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x20")));
+    assert_eq!(served_by, url_key(&injected));
+    assert_eq!(handler.effective_policy().egress, Egress::InjectedOnly);
+    assert!(listed.received_requests().await.unwrap().is_empty());
+
+    // The same handler with egress open probes the listed endpoint too
+    let open = PartialHandlerSettings { egress: Some(Egress::Open), ..Default::default() };
+    let handler = RpcHandler::with_components(ci_config(&injected, open), None, components()).await.unwrap();
+    handler.init().await.unwrap();
+    assert!(count_method(&listed, "eth_getBlockByNumber").await > 0);
+
+    // Probe transports refuse anything outside the allowed set without sending
+    listed.reset().await;
+    let transports = HttpTransportFactory::new(Duration::from_secs(1)).only([url_key(&injected)]);
+    assert!(transports.supports(&url_key(&injected)));
+    assert!(!transports.supports(&url_key(&listed)));
+    match transports.transport(&url_key(&listed)).request(&block_number()).await {
+        Err(RpcHandlerError::EgressDenied { url }) => assert_eq!(url, url_key(&listed)),
+        other => panic!("expected EgressDenied, got {other:?}"),
+    }
+    assert!(listed.received_requests().await.unwrap().is_empty());
+}
+
+#[test]
+fn test_overlay_overrides_only_what_it_names() {
+    let overlay: PartialHandlerSettings = serde_json::from_value(json!({
+        "log_level": "Debug",
+        "proxy_settings": { "rpc_call_timeout_ms": 20000, "connect_timeout_ms": null },
+        "readiness": { "max_pending": 8 },
+        "init_policy": null,
+        "keepalive": { "keepalive_after_ms": 5000, "keepalive_interval_ms": 5000 }
+    }))
+    .unwrap();
+    let settings = HandlerConfig::profile(Profile::Production, 1).overlay(overlay).settings.unwrap();
+
+    assert!(matches!(settings.log_level, LogLevel::Debug));
+    let proxy = settings.proxy_settings.unwrap();
+    assert_eq!((proxy.retry_count, proxy.retry_delay_ms, proxy.rpc_call_timeout_ms, proxy.connect_timeout_ms), (3, 250, 20_000, None));
+    assert_eq!(settings.readiness.max_pending, Some(8));
+    assert_eq!(settings.readiness.max_success_age_ms, ReadinessThresholds::default().max_success_age_ms);
+    assert_eq!(settings.init_policy, None);
+    assert_eq!(settings.keepalive.map(|keepalive| keepalive.keepalive_after_ms), Some(5_000));
+    // Left alone
+    assert_eq!(settings.failover_policy, FailoverPolicy::TierStrict);
+    assert!(settings.auto_refresh.is_some());
+
+    // A nested partial over settings that have none is merged onto the defaults, and later
+    // overlays win over earlier ones
+    let bare = HandlerConfig { network_id: 1, settings: Some(HandlerSettings { proxy_settings: None, ..HandlerSettings::default() }) };
+    let proxy = |partial: PartialProxySettings| PartialHandlerSettings { proxy_settings: Some(partial), ..Default::default() };
+    let settings = bare
+        .overlay(proxy(PartialProxySettings { connect_timeout_ms: Some(Some(700)), retry_count: Some(5), ..Default::default() }))
+        .overlay(proxy(PartialProxySettings { retry_count: Some(1), ..Default::default() }))
+        .settings
+        .unwrap();
+    let merged = settings.proxy_settings.unwrap();
+    let defaults = ProxySettings::default();
+    assert_eq!((merged.retry_count, merged.connect_timeout_ms, merged.rpc_call_timeout_ms), (1, Some(700), defaults.rpc_call_timeout_ms));
+
+    // An unset field is left out when serialized, a cleared one is `null`
+    let partial = PartialHandlerSettings { init_policy: Some(None), rpc_probe_timeout_ms: Some(900), ..Default::default() };
+    assert_eq!(serde_json::to_value(&partial).unwrap(), json!({ "rpc_probe_timeout_ms": 900, "init_policy": null }));
+    assert!(serde_json::from_value::<PartialHandlerSettings>(json!({ "rpc_probe_timout_ms": 900 })).is_err());
+}
+
+#[test]
+fn test_profiles_populate_their_documented_settings() {
+    let development = HandlerConfig::profile(Profile::Development, 31337);
+    let settings = development.settings.as_ref().unwrap();
+    assert!(settings.localnet);
+    assert!(matches!(settings.log_level, LogLevel::Debug));
+    assert_eq!(settings.network_rpcs.iter().filter_map(|rpc| rpc.url.as_ref().map(|url| url.as_str())).collect::<Vec<_>>(), ["http://127.0.0.1:8545/"]);
+
+    let ci = HandlerConfig::profile(Profile::Ci, 1).settings.unwrap();
+    assert_eq!(ci.egress, Egress::InjectedOnly);
+    assert!(ci.network_rpcs.is_empty() && ci.auto_refresh.is_none() && ci.keepalive.is_none());
+
+    let production = resolve_config(HandlerConfig::profile(Profile::Production, 1)).unwrap();
+    assert_eq!(production.failover_policy, FailoverPolicy::TierStrict);
+    assert_eq!(production.settings.egress, Egress::Open);
+    assert!(production.settings.init_policy.is_some_and(|policy| policy.allow_unprobed_fallback));
+    assert!(production.settings.agreement_sampling.is_some_and(|sampling| sampling.exclude_from_reads));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+fn balance_request() -> JsonRpcRequest {
+    request("eth_getBalance", json!(["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "latest"]))
+}
+
+fn with_call_timeout(settings: HandlerSettings, rpc_call_timeout_ms: u64) -> HandlerSettings {
+    let proxy = ProxySettings { rpc_call_timeout_ms, ..settings.proxy_settings.clone().unwrap() };
+    HandlerSettings { proxy_settings: Some(proxy), ..settings }
+}
+
+#[tokio::test]
+async fn test_timeout_change_spares_the_call_in_flight() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let slow = ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1"))).set_delay(Duration::from_millis(600));
+    mount_method(&server, "eth_getBalance", slow).await;
+    let base = settings(vec![mk_rpc(&server)]);
+    let handler = init_handler(with_call_timeout(base.clone(), 1000)).await;
+
+    let in_flight = tokio::spawn({
+        let handler = Arc::clone(&handler);
+        async move { handler.try_proxy_request(balance_request()).await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let diff = handler.apply_config(config(with_call_timeout(base, 300))).await.unwrap();
+    assert_eq!(diff.changes, [FieldChange { field: "settings.rpc_call_timeout", from: "1s".into(), to: "300ms".into() }]);
+    assert!(diff.added_rpcs.is_empty() && diff.removed_rpcs.is_empty() && !diff.reselected);
+    assert_eq!(handler.config().settings.rpc_call_timeout, Duration::from_millis(300));
+
+    // Sent under the old 1s budget, so the 600ms answer still makes it
+    let response = in_flight.await.unwrap().unwrap();
+    assert_eq!(response.into_result().unwrap(), json!("0x1"));
+
+    let started_at = Instant::now();
+    assert!(handler.try_proxy_request(balance_request()).await.is_err(), "the new 300ms budget applies");
+    assert!(started_at.elapsed() < Duration::from_millis(600));
+    assert_eq!(handler.get_latencies().await.len(), 1, "probe results carry over");
+}
+
+#[tokio::test]
+async fn test_endpoint_set_and_failover_policy_changes() {
+    let (a, b) = (MockServer::start().await, MockServer::start().await);
+    mount_probe(&a, "0x10", Duration::from_millis(30)).await;
+    mount_probe(&b, "0x10", Duration::ZERO).await;
+    let handler = init_handler(settings(vec![mk_rpc(&a)])).await;
+
+    // Adding an endpoint, tiering the existing one and switching policy re-selects
+    let tiered = HandlerSettings { failover_policy: FailoverPolicy::TierStrict, ..settings(vec![tiered(&a, 1), mk_rpc(&b).into()]) };
+    let diff = handler.apply_config(config(tiered.clone())).await.unwrap();
+    assert_eq!(diff.changes.iter().map(|change| change.field).collect::<Vec<_>>(), ["failover_policy"]);
+    assert_eq!((diff.added_rpcs, diff.updated_rpcs, diff.reselected), (vec![url_key(&b)], vec![url_key(&a)], true));
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&a), "tier 1 wins despite being slower");
+
+    // Settings fixed at construction are refused, and nothing is applied
+    let restart = HandlerConfig { network_id: TEST_NETWORK_ID + 1, settings: Some(HandlerSettings { pin_resolved_ips: true, ..tiered.clone() }) };
+    match handler.apply_config(restart).await {
+        Err(RpcHandlerError::ConfigNotReloadable { fields }) => assert_eq!(fields, ["network_id", "settings.pin_resolved_ips"]),
+        other => panic!("expected ConfigNotReloadable, got {other:?}"),
+    }
+    assert_eq!((handler.network_id, handler.config().settings.pin_resolved_ips), (TEST_NETWORK_ID, false));
+
+    // Dropping the active provider moves off it at once
+    let diff = handler.apply_config(config(HandlerSettings { failover_policy: FailoverPolicy::TierStrict, ..settings(vec![mk_rpc(&b)]) })).await.unwrap();
+    assert!(diff.changes.is_empty());
+    assert_eq!((diff.removed_rpcs, diff.reselected), (vec![url_key(&a)], true));
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&b));
+    assert_eq!(handler.rpcs().len(), 1);
+
+    // Applying the same config again is a no-op
+    let again = handler.apply_config(config(HandlerSettings { failover_policy: FailoverPolicy::TierStrict, ..settings(vec![mk_rpc(&b)]) })).await.unwrap();
+    assert!(again.is_empty() && !again.reselected);
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn rpc_at(url: &str) -> Rpc {
+    Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+async fn calls_for(urls: &[String]) -> RpcCalls {
+    let rpcs = urls.iter().map(|u| rpc_at(u)).collect();
+    RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap())
+}
+
+/// Same server on two paths: two URLs sharing the `127.0.0.1` hostname.
+fn same_host_urls(server: &MockServer) -> Vec<String> {
+    vec![format!("{}/a", server.uri()), format!("{}/b", server.uri())]
+}
+
+#[tokio::test]
+async fn test_requests_to_same_host_are_serialized() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_json(rpc_response(1, json!("0x10")))
+            .set_delay(Duration::from_millis(150)))
+        .mount(&server)
+        .await;
+
+    let calls = calls_for(&same_host_urls(&server)).await;
+
+    let start = Instant::now();
+    let block: String = calls.bft_consensus(&block_number(), 1.0, 1.0, None).await.unwrap();
+    assert_eq!(block, "0x10");
+    assert!(start.elapsed() >= Duration::from_millis(300), "per-host limit of 1 must serialize requests");
+
+    let parallel = ConsensusOptions { per_host_concurrency: Some(2), ..ConsensusOptions::default() };
+    let start = Instant::now();
+    let _: String = calls.bft_consensus(&block_number(), 1.0, 1.0, Some(parallel)).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(280), "per-host limit of 2 should allow both in flight");
+}
+
+#[tokio::test]
+async fn test_rate_limited_host_skips_queued_requests() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429))
+        .mount(&server)
+        .await;
+
+    let calls = calls_for(&same_host_urls(&server)).await;
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 0.5, None).await;
+
+    assert!(result.is_err());
+    assert_eq!(server.received_requests().await.unwrap().len(), 1, "second URL on the cooled host must not be sent");
+    assert_eq!(report.skipped_urls.len(), 1);
+}
+
+#[tokio::test]
+async fn test_concurrency_scales_down_with_cooldown_density() {
+    let good = MockServer::start().await;
+    let bad = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10"))))
+        .mount(&good)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&bad)
+        .await;
+
+    // The failing endpoints are addressed via `localhost` so they live on a different hostname
+    let bad_port = bad.address().port();
+    let mut urls: Vec<String> = (0..4).map(|i| format!("{}/{i}", good.uri())).collect();
+    urls.extend((0..4).map(|i| format!("http://localhost:{bad_port}/{i}")));
+    let calls = calls_for(&urls).await;
+
+    // Let every bad endpoint fail concurrently so all four end up cooled down
+    let warmup = ConsensusOptions { concurrency: Some(8), per_host_concurrency: Some(8), ..ConsensusOptions::default() };
+    let (_, first) = calls.consensus_with_report::<String>(&block_number(), 0.5, Some(warmup)).await;
+    assert_eq!(first.effective_concurrency, 8);
+    assert!(first.concurrency_note.is_none());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let options = ConsensusOptions { concurrency: Some(4), per_host_concurrency: Some(4), ..ConsensusOptions::default() };
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 0.5, Some(options)).await;
+    assert_eq!(result.unwrap(), "0x10");
+    assert_eq!(report.configured_concurrency, 4);
+    assert_eq!(report.effective_concurrency, 2);
+    assert_eq!(report.concurrency_note.as_deref(), Some("4 of 8 endpoints cooling down"));
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+
+/// Every stub answers after this long, so all four requests are in flight before the erroring
+/// one cools down the shared `127.0.0.1` host.
+const STUB_DELAY: Duration = Duration::from_millis(50);
+
+struct Providers {
+    agreeing: Vec<String>,
+    stale: String,
+    erroring: String,
+}
+
+impl Providers {
+    async fn start() -> Self {
+        let agreeing = vec![
+            serve_fixed(rpc_response(1, json!("0x10")), STUB_DELAY).await,
+            serve_fixed(rpc_response(1, json!("0x10")), STUB_DELAY).await,
+        ];
+        let stale = serve_fixed(rpc_response(1, json!("0xf")), STUB_DELAY).await;
+        let erroring = serve_fixed(
+            json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32000, "message": "header not found" } }),
+            STUB_DELAY,
+        )
+        .await;
+        Self { agreeing, stale, erroring }
+    }
+
+    /// A fresh handler, so one scenario's cooldowns don't leak into the next.
+    async fn calls(&self) -> RpcCalls {
+        let rpcs = self.agreeing.iter().chain([&self.stale, &self.erroring])
+            .map(|url| Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) })
+            .collect();
+        RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap())
+    }
+}
+
+fn options() -> Option<ConsensusOptions> {
+    Some(ConsensusOptions { per_host_concurrency: Some(4), ..ConsensusOptions::default() })
+}
+
+#[tokio::test]
+async fn test_report_classifies_each_provider() {
+    let providers = Providers::start().await;
+    let calls = providers.calls().await;
+
+    // Three of four must agree, which two agreeing providers can't deliver
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 0.75, options()).await;
+
+    assert!(matches!(result, Err(RpcHandlerError::ConsensusFailure { ref most_common }) if most_common == "0x10"));
+    assert_eq!(report.votes.get("0x10"), Some(&2));
+    assert_eq!(report.votes.get("0xf"), Some(&1));
+    assert_eq!(report.most_common.as_deref(), Some("0x10"));
+    assert_eq!(report.quorum, Some(3));
+
+    let mut majority = report.urls_where(|o| matches!(o, EndpointOutcome::Majority { .. }));
+    majority.sort();
+    let mut agreeing: Vec<&str> = providers.agreeing.iter().map(String::as_str).collect();
+    agreeing.sort();
+    assert_eq!(majority, agreeing);
+    assert_eq!(
+        report.outcomes[&providers.stale],
+        EndpointOutcome::Minority {
+            key: "0xf".to_string(),
+            differences: vec![Difference { path: "$".to_string(), kind: DifferenceKind::Changed { left: json!("0x10"), right: json!("0xf") } }],
+        }
+    );
+    assert!(matches!(&report.outcomes[&providers.erroring], EndpointOutcome::Failed { error } if error.contains("header not found")));
+
+    assert_eq!(report.cooldowns.len(), 1);
+    let cooldown = &report.cooldowns[0];
+    assert_eq!(cooldown.url, providers.erroring);
+    assert_eq!((cooldown.strikes, cooldown.delay_ms, cooldown.rate_limited), (1, 30_000, false));
+    assert!(calls.cooldown_remaining(&providers.erroring).await.is_some());
+    assert!(calls.cooldown_remaining(&providers.stale).await.is_none());
+
+    let rendered = report.to_string();
+    assert!(rendered.contains("quorum missed"), "{rendered}");
+    assert!(rendered.contains(&format!("minority  {}  0xf", providers.stale)), "{rendered}");
+    assert!(rendered.contains(r#"$: "0x10" != "0xf""#), "{rendered}");
+    assert!(rendered.contains("[cooldown 30000ms, strike 1]"), "{rendered}");
+}
+
+#[tokio::test]
+async fn test_consensus_and_bft_settle_on_the_agreeing_value() {
+    let providers = Providers::start().await;
+
+    let calls = providers.calls().await;
+    let block: String = calls.consensus(&block_number(), 0.5, options()).await.unwrap();
+    assert_eq!(block, "0x10");
+
+    // 2 of the 3 answers falls short of 75%, but meets a lowered threshold
+    let calls = providers.calls().await;
+    let block: String = calls.bft_consensus(&block_number(), 0.75, 0.5, options()).await.unwrap();
+    assert_eq!(block, "0x10");
+    assert!(calls.cooldown_remaining(&providers.erroring).await.is_some(), "the erroring provider is cooled down");
+    for url in providers.agreeing.iter().chain([&providers.stale]) {
+        assert!(calls.cooldown_remaining(url).await.is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_early_abort_waits_for_a_quorum_of_all_providers() {
+    let providers = Providers::start().await;
+    let calls = providers.calls().await;
+
+    // Half of four is two votes; a single answer must not settle it
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 0.5, options()).await;
+
+    assert_eq!(result.unwrap(), "0x10");
+    assert_eq!(report.votes.get("0x10"), Some(&2));
+}
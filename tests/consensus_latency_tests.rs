@@ -0,0 +1,80 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{MockServer, ResponseTemplate};
+
+fn options() -> ConsensusOptions {
+    ConsensusOptions { per_host_concurrency: Some(3), ..ConsensusOptions::default() }
+}
+
+/// Three endpoints: the first probes fastest but answers `eth_getBalance` and `eth_getLogs`
+/// slowly, the other two the other way round.
+async fn endpoints() -> (MockServer, [MockServer; 2]) {
+    let slow = MockServer::start().await;
+    mount_probe(&slow, "0x20", Duration::ZERO).await;
+    let fast = [MockServer::start().await, MockServer::start().await];
+    for server in &fast {
+        mount_probe(server, "0x20", Duration::from_millis(40)).await;
+    }
+    for (server, delay) in [(&slow, 150), (&fast[0], 0), (&fast[1], 0)] {
+        for (method, result) in [("eth_getBalance", json!("0x64")), ("eth_getLogs", json!([]))] {
+            mount_method(server, method, ResponseTemplate::new(200).set_body_json(rpc_response(1, result)).set_delay(Duration::from_millis(delay))).await;
+        }
+    }
+    (slow, fast)
+}
+
+async fn first_attempt(handler: &RpcHandler) -> String {
+    let plan = handler.plan_request(&request("eth_getBalance", json!(["0x00000000219ab540356cbb839cbe05303d7705fa", "latest"])), None).await.unwrap();
+    plan.urls[0].url.clone()
+}
+
+#[tokio::test]
+async fn test_consensus_answers_reorder_endpoints_without_a_probe() {
+    let (slow, fast) = endpoints().await;
+    let rpcs = [&slow, &fast[0], &fast[1]].map(mk_rpc).to_vec();
+    let handler = RpcHandler::new(config(settings(rpcs)), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(first_attempt(&handler).await, url_key(&slow), "{:?}", handler.get_latencies().await);
+
+    let calls = RpcCalls::new(handler.clone());
+    let balance = request("eth_getBalance", json!(["0x00000000219ab540356cbb839cbe05303d7705fa", "0x10"]));
+    for _ in 0..5 {
+        let (value, report) = calls.consensus_with_report::<String>(&balance, 1.0, Some(options())).await;
+        assert_eq!(value.unwrap(), "0x64");
+        assert_eq!(report.latency_observations, 3);
+    }
+
+    let latencies = handler.get_latencies().await;
+    assert!(fast.iter().all(|server| latencies[&url_key(server)] < latencies[&url_key(&slow)]), "{latencies:?}");
+    assert_ne!(first_attempt(&handler).await, url_key(&slow));
+    let plan = handler.plan_request(&balance, None).await.unwrap();
+    assert_eq!(plan.urls.last().unwrap().url, url_key(&slow));
+}
+
+#[tokio::test]
+async fn test_heavy_method_answers_are_kept_out_of_the_ordering() {
+    let (slow, fast) = endpoints().await;
+    let rpcs = [&slow, &fast[0], &fast[1]].map(mk_rpc).to_vec();
+    let handler = RpcHandler::new(config(settings(rpcs)), None).await.unwrap();
+    handler.init().await.unwrap();
+    let probed = handler.get_latencies().await;
+
+    let calls = RpcCalls::new(handler.clone());
+    let logs = request("eth_getLogs", json!([{ "fromBlock": "0x1", "toBlock": "0x10" }]));
+    let (value, report) = calls.consensus_with_report::<Value>(&logs, 1.0, Some(options())).await;
+    assert_eq!(value.unwrap(), json!([]));
+    assert_eq!(report.latency_observations, 3);
+
+    assert_eq!(handler.get_latencies().await, probed);
+    assert_eq!(first_attempt(&handler).await, url_key(&slow));
+    let heavy = handler.heavy_method_latencies();
+    assert_eq!(heavy.len(), 3);
+    assert!(heavy[&url_key(&slow)] >= 150, "{heavy:?}");
+}
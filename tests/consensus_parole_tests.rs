@@ -0,0 +1,100 @@
+#![cfg(all(feature = "consensus", feature = "test-util"))]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+/// An endpoint on its own loopback address, so each one is a separate host to cooldowns, that
+/// answers everything while `up` and fails everything with a 500 otherwise.
+async fn endpoint(host: u8, up: bool) -> (Rpc, MockServer, Arc<AtomicBool>) {
+    let listener = std::net::TcpListener::bind(format!("127.0.0.{host}:0")).unwrap();
+    let server = MockServer::builder().listener(listener).start().await;
+    let state = Arc::new(AtomicBool::new(up));
+    let flag = Arc::clone(&state);
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            match (flag.load(Ordering::SeqCst), body["method"].as_str().unwrap()) {
+                (false, _) => ResponseTemplate::new(500),
+                (true, "eth_blockNumber") => ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10"))),
+                (true, _) => ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x5"))),
+            }
+        })
+        .mount(&server)
+        .await;
+    let url = format!("http://127.0.0.{host}:{}/", server.address().port());
+    (Rpc { url: url.parse().unwrap(), ..mk_rpc(&server) }, server, state)
+}
+
+fn balance() -> JsonRpcRequest {
+    request("eth_getBalance", json!(["0x0000000000000000000000000000000000000001", "latest"]))
+}
+
+fn options() -> ConsensusOptions {
+    ConsensusOptions { cooldown_ms: Some(30_000), ..ConsensusOptions::default() }
+}
+
+async fn calls(rpcs: Vec<Rpc>, clock: &MockClock) -> RpcCalls {
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    RpcCalls::new(RpcHandler::with_components(config(settings(rpcs)), None, components).await.unwrap())
+}
+
+#[tokio::test]
+async fn test_a_recovered_endpoint_is_paroled_when_quorum_becomes_infeasible() {
+    let (recovering_rpc, recovering, recovering_up) = endpoint(2, false).await;
+    let (broken_rpc, broken, _) = endpoint(3, false).await;
+    let (steady_rpc, _steady, _) = endpoint(4, true).await;
+    let clock = MockClock::new();
+    let calls = calls(vec![recovering_rpc.clone(), broken_rpc.clone(), steady_rpc.clone()], &clock).await;
+
+    // Two of three fail and cool down, leaving one endpoint, which can't agree with anyone
+    let (_, first) = calls.consensus_with_report::<String>(&balance(), 1.0, Some(options())).await;
+    assert_eq!(first.cooldowns.len(), 2);
+    assert!(first.paroled.is_empty());
+
+    recovering_up.store(true, Ordering::SeqCst);
+    let (result, report) = calls.consensus_with_report::<String>(&balance(), 1.0, Some(options())).await;
+    assert_eq!(result.unwrap(), "0x5");
+    assert_eq!(report.paroled, [recovering_rpc.url.to_string()]);
+    assert!(matches!(report.outcomes.get(recovering_rpc.url.as_str()), Some(EndpointOutcome::Majority { .. })));
+    assert!(calls.cooldown_remaining(recovering_rpc.url.as_str()).await.is_none());
+    assert!(calls.cooldown_remaining(broken_rpc.url.as_str()).await.is_some(), "a failed check leaves the cooldown running");
+    assert_eq!((count_method(&recovering, "eth_blockNumber").await, count_method(&broken, "eth_blockNumber").await), (1, 1));
+    assert!(report.to_string().contains(&format!("paroled {}", recovering_rpc.url)));
+}
+
+#[tokio::test]
+async fn test_a_broken_endpoint_is_checked_at_most_once_per_interval() {
+    let (broken_rpc, broken, _) = endpoint(2, false).await;
+    let (steady_rpc, _steady, _) = endpoint(3, true).await;
+    let clock = MockClock::new();
+    let calls = calls(vec![broken_rpc, steady_rpc], &clock).await;
+    let interval = Duration::from_millis(options().describe().cooldown.parole_interval_ms);
+
+    let _ = calls.consensus_with_report::<String>(&balance(), 0.5, Some(options())).await;
+    for _ in 0..3 {
+        let (result, report) = calls.consensus_with_report::<String>(&balance(), 0.5, Some(options())).await;
+        assert!(matches!(result, Err(RpcHandlerError::ConsensusFailure { .. })));
+        assert!(report.paroled.is_empty());
+    }
+    assert_eq!(count_method(&broken, "eth_blockNumber").await, 1);
+
+    clock.advance(interval - Duration::from_secs(1));
+    let _ = calls.consensus_with_report::<String>(&balance(), 0.5, Some(options())).await;
+    assert_eq!(count_method(&broken, "eth_blockNumber").await, 1);
+
+    clock.advance(Duration::from_secs(1));
+    let _ = calls.consensus_with_report::<String>(&balance(), 0.5, Some(options())).await;
+    assert_eq!(count_method(&broken, "eth_blockNumber").await, 2, "checked again once the interval has passed");
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+const SLOW: Duration = Duration::from_millis(1000);
+
+async fn answering(block: &str, delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(block))).set_delay(delay))
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn calls_for(servers: &[&MockServer]) -> RpcCalls {
+    let rpcs = servers.iter().map(|server| mk_rpc(server)).collect();
+    RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap())
+}
+
+async fn received(server: &MockServer) -> usize {
+    server.received_requests().await.unwrap().len()
+}
+
+#[tokio::test]
+async fn test_three_identical_fast_answers_settle_without_the_slow_endpoints() {
+    let fast = [answering("0x10", Duration::ZERO).await, answering("0x10", Duration::ZERO).await, answering("0x10", Duration::ZERO).await];
+    let slow = [answering("0x10", SLOW).await, answering("0x10", SLOW).await];
+    let calls = calls_for(&[&fast[0], &fast[1], &fast[2], &slow[0], &slow[1]]).await;
+    let options = |unanimous_prefix| Some(ConsensusOptions { concurrency: Some(3), per_host_concurrency: Some(5), unanimous_prefix, ..ConsensusOptions::default() });
+
+    let start = Instant::now();
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 0.66, options(Some(3))).await;
+    assert_eq!(result.unwrap(), "0x10");
+    assert!(start.elapsed() < SLOW / 2, "{:?}", start.elapsed());
+    assert!(report.short_circuited);
+    assert_eq!(report.votes.get("0x10"), Some(&3));
+    // A slow endpoint is either never asked or cancelled once three answers agree
+    for server in &slow {
+        assert!(report.outcomes.get(&url_key(server)).is_none_or(|outcome| *outcome == EndpointOutcome::Cancelled), "{report}");
+    }
+    let mut issued = 0;
+    for server in fast.iter().chain(&slow) {
+        issued += received(server).await;
+    }
+    for server in &fast {
+        assert_eq!(received(server).await, 1);
+    }
+    assert!(issued <= 5, "{issued}");
+    assert!(report.to_string().contains("short-circuited on 3 identical answers"), "{report}");
+
+    // Without the prefix, a two-thirds quorum of five needs a slow answer
+    let start = Instant::now();
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 0.66, options(None)).await;
+    assert_eq!(result.unwrap(), "0x10");
+    assert!(start.elapsed() >= SLOW);
+    assert!(!report.short_circuited);
+}
+
+#[tokio::test]
+async fn test_a_split_prefix_falls_back_to_the_quorum() {
+    let delay = Duration::from_millis(300);
+    let (agreeing, also_agreeing, stale) = (answering("0x10", Duration::ZERO).await, answering("0x10", Duration::ZERO).await, answering("0xf", Duration::ZERO).await);
+    let (slow, also_slow) = (answering("0x10", delay).await, answering("0x10", delay).await);
+    let calls = calls_for(&[&agreeing, &also_agreeing, &stale, &slow, &also_slow]).await;
+    let options = ConsensusOptions { concurrency: Some(5), per_host_concurrency: Some(5), unanimous_prefix: Some(3), ..ConsensusOptions::default() };
+
+    let start = Instant::now();
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 0.66, Some(options)).await;
+    assert_eq!(result.unwrap(), "0x10");
+    assert!(start.elapsed() >= delay);
+    assert!(!report.short_circuited);
+    assert_eq!(report.votes.get("0xf"), Some(&1));
+    assert!(report.outcomes.values().all(|outcome| *outcome != EndpointOutcome::Cancelled));
+}
+
+#[test]
+fn test_a_prefix_below_two_is_ignored() {
+    let describe = |unanimous_prefix| ConsensusOptions { unanimous_prefix, ..ConsensusOptions::default() }.describe().unanimous_prefix;
+    assert_eq!(describe(Some(0)), None);
+    assert_eq!(describe(Some(1)), None);
+    assert_eq!(describe(Some(2)), Some(2));
+}
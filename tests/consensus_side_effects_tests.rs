@@ -0,0 +1,99 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const TX_HASH: &str = "0x4b8e1c8d2a0e4e9f3f6a2f0c3b1d5e7a9c0b2d4f6e8a1c3e5b7d9f0a2c4e6b8d";
+
+fn send_raw() -> JsonRpcRequest {
+    request("eth_sendRawTransaction", json!(["0x02f86b0180843b9aca00"]))
+}
+
+async fn answering(method: &str, response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+    mount_method(&server, method, response).await;
+    server
+}
+
+fn answer(result: serde_json::Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, result))
+}
+
+async fn calls_for(servers: &[&MockServer], idempotent_methods: &[&str]) -> RpcCalls {
+    let mut settings = settings(servers.iter().map(|server| mk_rpc(server)).collect());
+    settings.idempotent_methods = idempotent_methods.iter().map(|method| method.to_string()).collect();
+    RpcCalls::new(RpcHandler::new(config(settings), None).await.unwrap())
+}
+
+#[tokio::test]
+async fn test_methods_with_side_effects_are_refused_without_sending() {
+    let (a, b) = (answering("eth_sendRawTransaction", answer(json!(TX_HASH))).await, answering("eth_sendRawTransaction", answer(json!(TX_HASH))).await);
+    let calls = calls_for(&[&a, &b], &[]).await;
+
+    let err = calls.consensus::<String>(&send_raw(), 0.5, None).await.unwrap_err();
+    assert!(matches!(&err, RpcHandlerError::NonIdempotentMethodInConsensus { method, registered: true } if method == "eth_sendRawTransaction"), "{err:?}");
+    assert!(err.to_string().contains("allow_side_effects"), "{err}");
+    assert!(calls.bft_consensus::<String>(&send_raw(), 0.66, 0.5, None).await.is_err());
+    assert_eq!(count_method(&a, "eth_sendRawTransaction").await + count_method(&b, "eth_sendRawTransaction").await, 0);
+
+    // An unregistered method is refused too, with a hint to declare it
+    let err = calls.consensus::<String>(&request("custom_pendingRoot", json!([])), 0.5, None).await.unwrap_err();
+    assert!(matches!(&err, RpcHandlerError::NonIdempotentMethodInConsensus { registered: false, .. }), "{err:?}");
+    assert!(err.to_string().contains("HandlerSettings::idempotent_methods"), "{err}");
+    assert!(a.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_allowed_side_effects_settle_on_any_acceptance() {
+    let rejected = ResponseTemplate::new(200).set_body_json(json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32000, "message": "nonce too low" } }));
+    let accepting = answering("eth_sendRawTransaction", answer(json!(TX_HASH))).await;
+    let (rejecting, also_rejecting) = (answering("eth_sendRawTransaction", rejected.clone()).await, answering("eth_sendRawTransaction", rejected).await);
+    let calls = calls_for(&[&rejecting, &accepting, &also_rejecting], &[]).await;
+    let options = ConsensusOptions { allow_side_effects: true, unanimous_prefix: Some(2), ..ConsensusOptions::default() };
+
+    // One acceptance out of three is no quorum, but a broadcast only needs one
+    let (result, report) = calls.consensus_with_report::<String>(&send_raw(), 0.66, Some(options.clone())).await;
+    assert_eq!(result.unwrap(), TX_HASH);
+    assert_eq!(report.quorum, Some(1));
+    assert!(!report.short_circuited);
+    // Every endpoint was sent the transaction exactly once
+    for server in [&rejecting, &accepting, &also_rejecting] {
+        assert_eq!(count_method(server, "eth_sendRawTransaction").await, 1);
+    }
+    assert_eq!(report.outcomes[&url_key(&accepting)], EndpointOutcome::Majority { key: TX_HASH.to_string() });
+    assert!(matches!(report.outcomes[&url_key(&rejecting)], EndpointOutcome::Failed { .. }));
+    // A rejected transaction says nothing about the endpoint, so nothing is cooled down
+    assert!(report.cooldowns.is_empty(), "{:?}", report.cooldowns);
+
+    // With a single endpoint left there is still something to broadcast to
+    let lone = answering("eth_sendRawTransaction", answer(json!(TX_HASH))).await;
+    let calls = calls_for(&[&lone], &[]).await;
+    assert_eq!(calls.consensus::<String>(&send_raw(), 0.66, Some(options)).await.unwrap(), TX_HASH);
+}
+
+#[tokio::test]
+async fn test_reads_keep_value_agreement() {
+    let (a, b, c) = (
+        answering("eth_blockNumber", answer(json!("0x10"))).await,
+        answering("eth_blockNumber", answer(json!("0x10"))).await,
+        answering("eth_blockNumber", answer(json!("0xf"))).await,
+    );
+    let calls = calls_for(&[&a, &b, &c], &["custom_head"]).await;
+    let block: String = calls.consensus(&block_number(), 0.66, None).await.unwrap();
+    assert_eq!(block, "0x10");
+    // Allowing side effects changes nothing for a read: a split still needs its quorum
+    let options = ConsensusOptions { allow_side_effects: true, ..ConsensusOptions::default() };
+    let (result, report) = calls.consensus_with_report::<String>(&block_number(), 1.0, Some(options)).await;
+    assert!(matches!(result, Err(RpcHandlerError::ConsensusFailure { .. })));
+    assert_eq!(report.quorum, Some(3));
+
+    // A declared method is a read like any other
+    for server in [&a, &b, &c] {
+        mount_method(server, "custom_head", answer(json!("0x10"))).await;
+    }
+    assert_eq!(calls.consensus::<String>(&request("custom_head", json!([])), 1.0, None).await.unwrap(), "0x10");
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+/// An endpoint whose chain ends at `head`, with block hashes tagged by `fork`. `eth_call`
+/// answers with the block param it was sent, and fails while `failing` is set.
+struct Chain {
+    server: MockServer,
+    head: Arc<AtomicU64>,
+    failing: Arc<AtomicBool>,
+}
+
+impl Chain {
+    async fn start(head: u64, fork: &'static str, probe_delay: Duration) -> Self {
+        let server = MockServer::start().await;
+        let head = Arc::new(AtomicU64::new(head));
+        let failing = Arc::new(AtomicBool::new(false));
+        let (chain_head, chain_failing) = (Arc::clone(&head), Arc::clone(&failing));
+        Mock::given(method("POST"))
+            .respond_with(move |request: &Request| {
+                let body: Value = request.body_json().unwrap();
+                let params = &body["params"];
+                let head = chain_head.load(Ordering::SeqCst);
+                let result = match body["method"].as_str().unwrap() {
+                    "eth_getBlockByNumber" => {
+                        let number = match params[0].as_str().unwrap() {
+                            "latest" => Some(head),
+                            hex => Some(u64::from_str_radix(hex.trim_start_matches("0x"), 16).unwrap()).filter(|number| *number <= head),
+                        };
+                        let delay = if params[0] == "latest" { probe_delay } else { Duration::ZERO };
+                        let block = number.map_or(Value::Null, |number| json!({ "number": format!("{number:#x}"), "hash": format!("0x{fork}{number:x}") }));
+                        return ResponseTemplate::new(200).set_body_json(rpc_response(1, block)).set_delay(delay);
+                    }
+                    "eth_getCode" => json!(PERMIT2_CODE),
+                    "eth_call" if chain_failing.load(Ordering::SeqCst) => return ResponseTemplate::new(500),
+                    "eth_call" => params[1].clone(),
+                    "eth_chainId" => json!("0x1"),
+                    other => panic!("unexpected {other}"),
+                };
+                ResponseTemplate::new(200).set_body_json(rpc_response(1, result))
+            })
+            .mount(&server)
+            .await;
+        Self { server, head, failing }
+    }
+
+    fn url(&self) -> String {
+        url_key(&self.server)
+    }
+
+    async fn sent(&self, rpc_method: &str) -> Vec<Value> {
+        self.server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|request| serde_json::from_slice::<Value>(&request.body).ok())
+            .filter(|body| body["method"] == rpc_method)
+            .map(|body| body["params"].clone())
+            .collect()
+    }
+}
+
+/// Two endpoints at block 0x1f during init, the faster one the active provider.
+async fn handler(fresh: &Chain, stale: &Chain) -> Arc<RpcHandler> {
+    let handler = init_handler(settings(vec![mk_rpc(&fresh.server), mk_rpc(&stale.server)])).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), fresh.url());
+    handler
+}
+
+fn eth_call(block: Option<&str>) -> JsonRpcRequest {
+    let call = json!({ "to": "0x000000000022d473030f116ddee9f6b43ac78ba3", "data": "0x18160ddd" });
+    let params = match block {
+        Some(block) => json!([call, block]),
+        None => json!([call]),
+    };
+    request("eth_call", params)
+}
+
+#[tokio::test]
+async fn test_session_reads_are_pinned_to_the_snapshot_block() {
+    let fresh = Chain::start(0x1f, "a", Duration::ZERO).await;
+    let stale = Chain::start(0x1f, "a", Duration::from_millis(50)).await;
+    let handler = handler(&fresh, &stale).await;
+    fresh.head.store(0x20, Ordering::SeqCst);
+
+    let session = handler.session().await.unwrap();
+    assert_eq!((session.block_number(), session.block_hash(), session.url()), (0x20, "0xa20", fresh.url().as_str()));
+
+    // The head moves on, the session doesn't
+    fresh.head.store(0x21, Ordering::SeqCst);
+    for block in [Some("latest"), None] {
+        let (response, url) = session.try_proxy_request_attributed(eth_call(block)).await.unwrap();
+        assert_eq!((response.result, url), (Some(json!("0x20")), fresh.url()));
+    }
+    // An explicit block is left alone, and so are methods without a block param
+    let response = session.try_proxy_request(eth_call(Some("0x10"))).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x10")));
+    let request = request("eth_chainId", json!([]));
+    assert_eq!(session.try_proxy_request(request).await.unwrap().result, Some(json!("0x1")));
+    assert!(stale.sent("eth_call").await.is_empty());
+    session.close();
+}
+
+#[tokio::test]
+async fn test_failover_goes_to_an_endpoint_holding_the_same_block() {
+    let fresh = Chain::start(0x1f, "a", Duration::ZERO).await;
+    let stale = Chain::start(0x1f, "a", Duration::from_millis(50)).await;
+    let handler = handler(&fresh, &stale).await;
+    fresh.head.store(0x20, Ordering::SeqCst);
+    let session = handler.session().await.unwrap();
+
+    stale.head.store(0x20, Ordering::SeqCst);
+    fresh.failing.store(true, Ordering::SeqCst);
+    let (response, url) = session.try_proxy_request_attributed(eth_call(Some("latest"))).await.unwrap();
+    assert_eq!((response.result, url), (Some(json!("0x20")), stale.url()));
+    assert_eq!(stale.sent("eth_getBlockByNumber").await.last(), Some(&json!(["0x20", false])));
+}
+
+#[tokio::test]
+async fn test_failover_never_mixes_heads() {
+    let fresh = Chain::start(0x1f, "a", Duration::ZERO).await;
+    let stale = Chain::start(0x1f, "a", Duration::from_millis(50)).await;
+    let handler = handler(&fresh, &stale).await;
+    fresh.head.store(0x20, Ordering::SeqCst);
+    let session = handler.session().await.unwrap();
+    fresh.failing.store(true, Ordering::SeqCst);
+
+    // The other endpoint doesn't have block 0x20 yet
+    let result = session.try_proxy_request(eth_call(Some("latest"))).await;
+    assert!(matches!(result, Err(RpcHandlerError::SnapshotUnavailable { block: 0x20, ref hash }) if hash == "0xa20"), "{result:?}");
+    assert!(stale.sent("eth_call").await.is_empty());
+
+    // Once it has it, the session can fail over after all
+    stale.head.store(0x20, Ordering::SeqCst);
+    let (_, url) = session.try_proxy_request_attributed(eth_call(Some("latest"))).await.unwrap();
+    assert_eq!(url, stale.url());
+}
+
+#[tokio::test]
+async fn test_failover_skips_an_endpoint_on_another_fork() {
+    let fresh = Chain::start(0x1f, "a", Duration::ZERO).await;
+    let forked = Chain::start(0x1f, "b", Duration::from_millis(50)).await;
+    let handler = handler(&fresh, &forked).await;
+    fresh.head.store(0x20, Ordering::SeqCst);
+    forked.head.store(0x20, Ordering::SeqCst);
+    let session = handler.session().await.unwrap();
+    fresh.failing.store(true, Ordering::SeqCst);
+
+    let result = session.calls().try_rpc_call(&eth_call(Some("latest"))).await;
+    assert!(matches!(result, Err(RpcHandlerError::SnapshotUnavailable { block: 0x20, .. })), "{result:?}");
+    assert!(forked.sent("eth_call").await.is_empty());
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use common::*;
+use ez_web3_rpc::provider::{HostLimiter, TrafficClass, WeightedSemaphore};
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// A small machine every stub runs on: each request takes `BASE` plus `PER_REQUEST` for every
+/// request it shares the machine with.
+#[derive(Default)]
+struct Device {
+    in_flight: AtomicUsize,
+}
+
+const BASE: Duration = Duration::from_millis(5);
+const PER_REQUEST: Duration = Duration::from_millis(8);
+
+/// A probe-passing endpoint on `device`.
+async fn serve_on(device: Arc<Device>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let device = Arc::clone(&device);
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let body = loop {
+                    let Ok(read) = stream.read(&mut chunk).await else { return };
+                    if read == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&chunk[..read]);
+                    if let Some(header_end) = request.windows(4).position(|w| w == b"\r\n\r\n")
+                        && let Ok(body) = serde_json::from_slice::<Value>(&request[header_end + 4..])
+                    {
+                        break body;
+                    }
+                };
+
+                let sharing = device.in_flight.fetch_add(1, Ordering::SeqCst) as u32;
+                let result = match body["method"].as_str() {
+                    Some("eth_getBlockByNumber") => json!({ "number": "0x10", "hash": "0xabc" }),
+                    Some("eth_getCode") => json!(PERMIT2_CODE),
+                    _ => json!("0x10"),
+                };
+                tokio::time::sleep(BASE + PER_REQUEST * sharing).await;
+                device.in_flight.fetch_sub(1, Ordering::SeqCst);
+                let payload = rpc_response(1, result).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{payload}",
+                    payload.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+
+    url
+}
+
+fn rpc_at(url: &str) -> Rpc {
+    Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+/// The p95 latency of `eth_blockNumber` requests sent one after another while a refresh of 40
+/// endpoints runs.
+async fn p95_during_refresh(constrained_mode: Option<ConstrainedMode>) -> Duration {
+    let device = Arc::new(Device::default());
+    let mut rpcs = Vec::new();
+    for _ in 0..40 {
+        rpcs.push(rpc_at(&serve_on(Arc::clone(&device)).await));
+    }
+    let settings = HandlerSettings { constrained_mode, ..settings(rpcs) };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let refresh = tokio::spawn({
+        let handler = Arc::clone(&handler);
+        async move { handler.refresh().await }
+    });
+    let mut latencies = Vec::new();
+    while !refresh.is_finished() {
+        let started = Instant::now();
+        let request = block_number();
+        handler.try_proxy_request(request).await.unwrap();
+        latencies.push(started.elapsed());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    refresh.await.unwrap().unwrap();
+
+    assert!(latencies.len() >= 3, "only {} requests ran during the refresh", latencies.len());
+    latencies.sort();
+    latencies[(latencies.len() - 1) * 95 / 100]
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_constrained_mode_keeps_requests_fast_during_a_refresh() {
+    let bound = Duration::from_millis(100);
+
+    let baseline = p95_during_refresh(None).await;
+    assert!(baseline > bound, "unbounded p95 {baseline:?} stayed within {bound:?}");
+
+    let constrained = p95_during_refresh(Some(ConstrainedMode { max_total_inflight: 4, probe_share: 0.5 })).await;
+    assert!(constrained <= bound, "constrained p95 {constrained:?} exceeded {bound:?}");
+}
+
+#[test]
+fn test_constrained_mode_probe_share_is_normalized() {
+    let mut settings = settings(Vec::<Rpc>::new());
+    settings.constrained_mode = Some(ConstrainedMode { max_total_inflight: 10, probe_share: 0.25 });
+    let normalized = resolve_config(config(settings.clone())).unwrap();
+    let mode = normalized.settings.constrained_mode.unwrap();
+    assert_eq!((mode.max_total_inflight, mode.probe_slots), (10, 2));
+
+    // Probes always keep one slot, and never more than the ceiling
+    settings.constrained_mode = Some(ConstrainedMode { max_total_inflight: 0, probe_share: 0.0 });
+    let mode = resolve_config(config(settings.clone())).unwrap().settings.constrained_mode.unwrap();
+    assert_eq!((mode.max_total_inflight, mode.probe_slots), (1, 1));
+    settings.constrained_mode = Some(ConstrainedMode { max_total_inflight: 3, probe_share: 4.0 });
+    assert_eq!(resolve_config(config(settings)).unwrap().settings.constrained_mode.unwrap().probe_slots, 3);
+}
+
+#[test]
+fn test_weighted_semaphore_holds_probes_to_their_cap() {
+    let semaphore = WeightedSemaphore::new(4, 1);
+
+    let probe = semaphore.try_acquire(TrafficClass::Probe).unwrap();
+    assert!(semaphore.try_acquire(TrafficClass::Probe).is_none(), "probes are held to one slot");
+    let requests: Vec<_> = (0..3).map(|_| semaphore.try_acquire(TrafficClass::Request).unwrap()).collect();
+    assert!(semaphore.try_acquire(TrafficClass::Request).is_none(), "the ceiling covers every class");
+    assert_eq!((semaphore.in_use(), semaphore.in_use_by(TrafficClass::Request)), (4, 3));
+
+    drop(probe);
+    assert!(semaphore.try_acquire(TrafficClass::Probe).is_some());
+    drop(requests);
+    assert_eq!(semaphore.in_use(), 0);
+}
+
+#[tokio::test]
+async fn test_weighted_semaphore_serves_waiting_requests_before_probes() {
+    let semaphore = WeightedSemaphore::new(2, 2);
+    let held = [semaphore.try_acquire(TrafficClass::Probe).unwrap(), semaphore.try_acquire(TrafficClass::Probe).unwrap()];
+
+    // The probe queues first, but a freed slot still goes to the request
+    let probe = tokio::spawn({
+        let semaphore = semaphore.clone();
+        async move { semaphore.acquire(TrafficClass::Probe).await }
+    });
+    let request = tokio::spawn({
+        let semaphore = semaphore.clone();
+        async move { semaphore.acquire(TrafficClass::Request).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let [first, second] = held;
+    drop(first);
+    let request = tokio::time::timeout(Duration::from_secs(1), request).await.unwrap().unwrap();
+    assert!(!probe.is_finished());
+    assert_eq!(semaphore.in_use_by(TrafficClass::Request), 1);
+
+    drop(second);
+    let probe = tokio::time::timeout(Duration::from_secs(1), probe).await.unwrap().unwrap();
+    drop((request, probe));
+    assert_eq!(semaphore.in_use(), 0);
+}
+
+#[tokio::test]
+async fn test_weighted_semaphore_abandoned_wait_leaks_no_slot() {
+    let semaphore = WeightedSemaphore::new(1, 1);
+    let held = semaphore.try_acquire(TrafficClass::Request).unwrap();
+
+    let waited = tokio::time::timeout(Duration::from_millis(20), semaphore.acquire(TrafficClass::Probe)).await;
+    assert!(waited.is_err());
+    drop(held);
+    assert_eq!(semaphore.in_use(), 0);
+    assert!(semaphore.try_acquire(TrafficClass::Probe).is_some());
+}
+
+#[tokio::test]
+async fn test_host_limiter_takes_budget_slots_by_class() {
+    let limiter = HostLimiter::default().with_budget(WeightedSemaphore::new(2, 1));
+    let url = "http://127.0.0.1:8545/";
+
+    let probe = limiter.try_acquire_as(url, TrafficClass::Probe).unwrap();
+    assert!(limiter.try_acquire_as(url, TrafficClass::Probe).is_none());
+    let request = limiter.try_acquire(url).unwrap();
+    assert!(limiter.try_acquire(url).is_none(), "requests and probes share the ceiling");
+    assert_eq!(limiter.budget().unwrap().in_use(), 2);
+
+    drop((probe, request));
+    let reserved = limiter.reserve(TrafficClass::Probe).await;
+    assert_eq!(limiter.budget().unwrap().in_use_by(TrafficClass::Probe), 1);
+    drop(reserved);
+    assert!(HostLimiter::default().reserve(TrafficClass::Probe).await.is_none(), "no budget without constrained mode");
+}
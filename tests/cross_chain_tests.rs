@@ -0,0 +1,138 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+const CHAIN_A: NetworkId = 424_243;
+const CHAIN_B: NetworkId = 424_244;
+
+/// What a chain's endpoints answer, shared by both of them.
+#[derive(Clone)]
+struct Script {
+    /// `(number, timestamp)` of the head block, by whether the chain has caught up yet
+    behind: (u64, u64),
+    caught_up: (u64, u64),
+    /// The chain stays behind until its first `eth_call` has been answered
+    stale: Arc<AtomicBool>,
+    /// Block params `eth_call` was asked at
+    calls: Arc<parking_lot::Mutex<Vec<String>>>,
+    calls_fail: bool,
+}
+
+impl Script {
+    fn steady(head: (u64, u64)) -> Self {
+        Self::delayed(head, head)
+    }
+
+    fn delayed(behind: (u64, u64), caught_up: (u64, u64)) -> Self {
+        Self { behind, caught_up, stale: Arc::new(AtomicBool::new(true)), calls: Arc::default(), calls_fail: false }
+    }
+
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let body: Value = serde_json::from_slice(&request.body).unwrap();
+        let result = match body["method"].as_str().unwrap() {
+            "eth_getCode" => json!(PERMIT2_CODE),
+            "eth_getBlockByNumber" => {
+                let (number, timestamp) = if self.stale.load(Ordering::SeqCst) { self.behind } else { self.caught_up };
+                json!({ "number": format!("0x{number:x}"), "hash": format!("0x{number:064x}"), "timestamp": format!("0x{timestamp:x}") })
+            }
+            "eth_call" if self.calls_fail => return ResponseTemplate::new(503),
+            "eth_call" => {
+                let block = body["params"][1].as_str().unwrap().to_string();
+                self.calls.lock().push(block.clone());
+                self.stale.store(false, Ordering::SeqCst);
+                json!(block)
+            }
+            other => panic!("unexpected {other}"),
+        };
+        ResponseTemplate::new(200).set_body_json(rpc_response(1, result))
+    }
+}
+
+/// A handler for `network_id` over two endpoints on different hostnames following `script`.
+async fn chain(network_id: NetworkId, script: &Script) -> (Arc<RpcHandler>, Vec<MockServer>) {
+    let mut servers = Vec::new();
+    for _ in 0..2 {
+        let server = MockServer::start().await;
+        let script = script.clone();
+        Mock::given(method("POST")).respond_with(move |request: &Request| script.respond(request)).mount(&server).await;
+        servers.push(server);
+    }
+    let localhost = Rpc { url: format!("http://localhost:{}", servers[1].address().port()).parse().unwrap(), ..mk_rpc(&servers[1]) };
+    let settings = settings(vec![mk_rpc(&servers[0]), localhost]);
+    let handler = RpcHandler::new(HandlerConfig { network_id, settings: Some(settings) }, None).await.unwrap();
+    handler.init().await.unwrap();
+    (handler, servers)
+}
+
+fn balance_of() -> JsonRpcRequest {
+    request("eth_call", json!([{ "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "data": "0x70a08231" }, "latest"]))
+}
+
+fn agreed(read: &ChainRead) -> &AgreedRead {
+    read.outcome.as_ref().unwrap_or_else(|e| panic!("chain {} failed: {e}", read.network_id))
+}
+
+#[tokio::test]
+async fn test_skew_is_reported_and_the_laggard_read_again() {
+    let (a, b) = (Script::steady((0x200, 1_100)), Script::delayed((0x64, 1_000), (0x65, 1_096)));
+    let ((handler_a, _a), (handler_b, _b)) = (chain(CHAIN_A, &a).await, chain(CHAIN_B, &b).await);
+    let manager = MultiChainHandler::new([handler_a, handler_b]);
+    assert_eq!(manager.network_ids(), [CHAIN_A, CHAIN_B]);
+
+    // Without a limit the spread is only reported
+    let requests = || vec![(CHAIN_A, balance_of(), 1.0), (CHAIN_B, balance_of(), 1.0)];
+    let result = manager.cross_chain_consensus(requests()).await.unwrap();
+    let (read_a, read_b) = (agreed(&result.reads[0]), agreed(&result.reads[1]));
+    assert_eq!((read_a.value.clone(), read_a.block_number, read_a.pinned), (json!("0x200"), 0x200, true));
+    assert_eq!((read_b.value.clone(), read_b.block_timestamp, read_b.reread), (json!("0x64"), 1_000, false));
+    assert_eq!(result.skew.spread, Duration::from_secs(100));
+    assert_eq!((result.skew.within_max_skew, result.skew.reread), (None, None));
+    assert_eq!(result.skew.block_timestamps.into_iter().collect::<Vec<_>>(), [(CHAIN_A, 1_100), (CHAIN_B, 1_000)]);
+
+    // Over the limit, the chain behind is read once more and has caught up by then
+    b.stale.store(true, Ordering::SeqCst);
+    b.calls.lock().clear();
+    let options = CrossChainOptions { max_skew: Some(Duration::from_secs(30)), consensus: None };
+    let result = manager.cross_chain_consensus_with(requests(), &options).await.unwrap();
+    let read_b = agreed(&result.reads[1]);
+    assert_eq!((read_b.value.clone(), read_b.block_number, read_b.reread), (json!("0x65"), 0x65, true));
+    assert_eq!(*b.calls.lock(), ["0x64", "0x64", "0x65", "0x65"], "pinned to the head, once per endpoint per read");
+    assert_eq!(result.skew.spread, Duration::from_secs(4));
+    assert_eq!((result.skew.within_max_skew, result.skew.reread), (Some(true), Some(CHAIN_B)));
+    assert!(!agreed(&result.reads[0]).reread);
+}
+
+#[tokio::test]
+async fn test_a_failing_chain_keeps_the_other_result() {
+    let a = Script::steady((0x200, 1_100));
+    let b = Script { calls_fail: true, ..Script::steady((0x64, 1_000)) };
+    let ((handler_a, _a), (handler_b, _b)) = (chain(CHAIN_A, &a).await, chain(CHAIN_B, &b).await);
+    let manager = MultiChainHandler::new([handler_a, handler_b]);
+
+    let options = CrossChainOptions { max_skew: Some(Duration::from_secs(1)), consensus: None };
+    let result = manager.cross_chain_consensus_with(vec![(CHAIN_A, balance_of(), 1.0), (CHAIN_B, balance_of(), 1.0)], &options).await.unwrap();
+    assert_eq!(agreed(&result.reads[0]).value, json!("0x200"));
+    assert_eq!(result.reads[1].network_id, CHAIN_B);
+    assert!(result.reads[1].outcome.is_err());
+    // One successful chain has nothing to be skewed against
+    assert_eq!((result.skew.spread, result.skew.within_max_skew, result.skew.reread), (Duration::ZERO, Some(true), None));
+
+    // A network without a handler is a caller error
+    assert!(matches!(
+        manager.cross_chain_consensus(vec![(1, balance_of(), 1.0)]).await,
+        Err(RpcHandlerError::NoAvailableRpcs { network_id: 1 })
+    ));
+}
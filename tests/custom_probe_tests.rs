@@ -0,0 +1,102 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// Healthy only where `balanceOf` on the app's contract comes back nonzero.
+struct NonzeroBalance;
+
+#[async_trait]
+impl EndpointProbe for NonzeroBalance {
+    fn name(&self) -> &str {
+        "nonzero-balance"
+    }
+
+    async fn probe(&self, _url: &str, transport: &dyn JsonRpcTransport) -> ProbeOutcome {
+        let call = request("eth_call", json!([{ "to": "0x01", "data": "0x70a08231" }, "latest"]));
+        match transport.request(&call).await.and_then(JsonRpcResponse::into_result) {
+            Ok(result) if result.as_str().is_some_and(|result| result.trim_start_matches("0x").trim_start_matches('0').is_empty()) => {
+                ProbeOutcome::Fail("zero balance".to_string())
+            }
+            Ok(_) => ProbeOutcome::Pass,
+            Err(e) => ProbeOutcome::Fail(e.to_string()),
+        }
+    }
+}
+
+struct Panicking;
+
+#[async_trait]
+impl EndpointProbe for Panicking {
+    fn name(&self) -> &str {
+        "panicking"
+    }
+
+    async fn probe(&self, _url: &str, _transport: &dyn JsonRpcTransport) -> ProbeOutcome {
+        panic!("probe bug")
+    }
+}
+
+async fn endpoint(balance: &str) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_call", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(balance)))).await;
+    server
+}
+
+async fn handler(servers: &[&MockServer], probes: Vec<Arc<dyn EndpointProbe>>, policy: CustomProbePolicy) -> Arc<RpcHandler> {
+    let mut settings = settings(servers.iter().map(|server| mk_rpc(server)).collect());
+    settings.custom_probes = probes;
+    settings.custom_probe_policy = policy;
+    init_handler(settings).await
+}
+
+fn probe_outcomes(report: &HealthReport, server: &MockServer) -> Vec<NamedProbeOutcome> {
+    let url = url_key(server);
+    report.endpoints.iter().find(|endpoint| endpoint.url == url).unwrap().custom_probes.clone()
+}
+
+#[tokio::test]
+async fn test_failing_custom_probe_excludes_endpoint() {
+    let (funded, empty) = (endpoint("0x01").await, endpoint("0x00").await);
+    let handler = handler(&[&funded, &empty], vec![Arc::new(NonzeroBalance)], CustomProbePolicy::Exclude).await;
+
+    let latencies = handler.get_latencies().await;
+    assert!(latencies.contains_key(&url_key(&funded)));
+    assert!(!latencies.contains_key(&url_key(&empty)));
+
+    let report = handler.health_report().await;
+    let failed = NamedProbeOutcome { probe: "nonzero-balance".to_string(), outcome: ProbeOutcome::Fail("zero balance".to_string()) };
+    assert_eq!(probe_outcomes(&report, &empty), vec![failed]);
+    assert_eq!(probe_outcomes(&report, &funded)[0].outcome, ProbeOutcome::Pass);
+    assert!(report.to_string().contains("[nonzero-balance failed: zero balance]"), "{report}");
+}
+
+#[tokio::test]
+async fn test_annotate_policy_keeps_failing_endpoint() {
+    let (funded, empty) = (endpoint("0x01").await, endpoint("0x00").await);
+    let handler = handler(&[&funded, &empty], vec![Arc::new(NonzeroBalance)], CustomProbePolicy::Annotate).await;
+
+    let latencies = handler.get_latencies().await;
+    assert!(latencies.contains_key(&url_key(&funded)));
+    assert!(latencies.contains_key(&url_key(&empty)));
+    assert!(probe_outcomes(&handler.health_report().await, &empty)[0].outcome.is_fail());
+}
+
+#[tokio::test]
+async fn test_panicking_probe_fails_without_breaking_refresh() {
+    let server = endpoint("0x01").await;
+    let handler = handler(&[&server], vec![Arc::new(Panicking), Arc::new(NonzeroBalance)], CustomProbePolicy::Annotate).await;
+    handler.refresh().await.unwrap();
+
+    let outcomes = probe_outcomes(&handler.health_report().await, &server);
+    assert_eq!(outcomes[0].outcome, ProbeOutcome::Fail("probe panicked: probe bug".to_string()));
+    assert_eq!(outcomes[1].outcome, ProbeOutcome::Pass);
+    assert!(handler.get_latencies().await.contains_key(&url_key(&server)));
+    assert!(handler.metrics_snapshot().panics[panics::CUSTOM_PROBE] >= 2, "init and refresh each count a panic");
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "chainlist")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::{chainlist::source::{ChainRegistry, RegistryChain}, *};
+use serde_json::{json, Value};
+use wiremock::{MockServer, ResponseTemplate};
+
+const NETWORK_A: NetworkId = 424243;
+const NETWORK_B: NetworkId = 424244;
+
+fn scoped(network_id: NetworkId, data_scope: DataScope) -> HandlerConfig {
+    HandlerConfig { network_id, settings: Some(HandlerSettings { data_scope, ..settings(Vec::<Rpc>::new()) }) }
+}
+
+/// Settings as JSON with `data_scope` swapped for a legacy `wipe_chain_data` value.
+fn legacy_settings(wipe_chain_data: Value) -> HandlerSettings {
+    let mut value = serde_json::to_value(settings(Vec::<Rpc>::new())).unwrap();
+    let object = value.as_object_mut().unwrap();
+    object.remove("data_scope");
+    object.insert("wipe_chain_data".into(), wipe_chain_data);
+    serde_json::from_value(value).unwrap()
+}
+
+async fn chain_endpoint(network_id: NetworkId) -> (MockServer, RegistryChain) {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(format!("{network_id:#x}"))))).await;
+    let chain = RegistryChain { chain_id: network_id, name: format!("chain {network_id}"), tvl: 0.0, rpcs: vec![server.uri()] };
+    (server, chain)
+}
+
+fn chain_id_request() -> JsonRpcRequest {
+    request("eth_chainId", json!([]))
+}
+
+#[tokio::test]
+async fn test_scope_leaving_out_the_own_network_is_rejected() {
+    let err = resolve_config(scoped(TEST_NETWORK_ID, DataScope::Networks(vec![1, 10]))).unwrap_err();
+    assert!(matches!(err, RpcHandlerError::InvalidDataScope { network_id: TEST_NETWORK_ID, ref retained } if *retained == [1, 10]), "got {err:?}");
+    assert!(matches!(
+        RpcHandler::new(scoped(TEST_NETWORK_ID, DataScope::Networks(Vec::new())), None).await.err(),
+        Some(RpcHandlerError::InvalidDataScope { .. })
+    ));
+
+    for scope in [DataScope::Global, DataScope::OnlyThisNetwork, DataScope::Networks(vec![1, TEST_NETWORK_ID])] {
+        assert_eq!(resolve_config(scoped(TEST_NETWORK_ID, scope.clone())).unwrap().data_scope, scope);
+    }
+}
+
+#[test]
+fn test_legacy_wipe_chain_data_maps_onto_a_scope() {
+    let cases = [
+        (json!({ "clear_data": false, "retain_these_chains": [1] }), DataScope::Global),
+        (json!({ "clear_data": true, "retain_these_chains": [] }), DataScope::OnlyThisNetwork),
+        (json!({ "clear_data": true, "retain_these_chains": [1, 10] }), DataScope::Networks(vec![1, 10])),
+    ];
+    for (legacy, scope) in cases {
+        assert_eq!(legacy_settings(legacy.clone()).data_scope, scope, "{legacy}");
+    }
+
+    // The current spelling round-trips, and a missing scope is global
+    for scope in [DataScope::Global, DataScope::OnlyThisNetwork, DataScope::Networks(vec![1])] {
+        let value = serde_json::to_value(&scope).unwrap();
+        assert_eq!(serde_json::from_value::<DataScope>(value).unwrap(), scope);
+    }
+    assert_eq!(serde_json::to_value(DataScope::Networks(vec![1])).unwrap(), json!({ "networks": [1] }));
+    let mut value = serde_json::to_value(settings(Vec::<Rpc>::new())).unwrap();
+    value.as_object_mut().unwrap().remove("data_scope");
+    assert_eq!(serde_json::from_value::<HandlerSettings>(value).unwrap().data_scope, DataScope::Global);
+}
+
+#[tokio::test]
+async fn test_handlers_with_different_scopes_do_not_interfere() {
+    let (server_a, chain_a) = chain_endpoint(NETWORK_A).await;
+    let (server_b, chain_b) = chain_endpoint(NETWORK_B).await;
+    chainlist::apply_registry(&ChainRegistry { chains: vec![chain_a, chain_b.clone()], ..ChainRegistry::default() });
+
+    let (only_a, global_b, legacy_a) = tokio::join!(
+        RpcHandler::new(scoped(NETWORK_A, DataScope::OnlyThisNetwork), None),
+        RpcHandler::new(scoped(NETWORK_B, DataScope::Global), None),
+        // Used to wipe every chain for every handler
+        RpcHandler::new(HandlerConfig { network_id: NETWORK_A, settings: Some(legacy_settings(json!({ "clear_data": true, "retain_these_chains": [] }))) }, None),
+    );
+    let (only_a, global_b, legacy_a) = (only_a.unwrap(), global_b.unwrap(), legacy_a.unwrap());
+
+    let ids = |view: &chainlist::ChainView| view.chain_ids().into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+    assert_eq!(ids(only_a.chain_data()), [NETWORK_A]);
+    assert_eq!(ids(legacy_a.chain_data()), [NETWORK_A]);
+    assert!(global_b.chain_data().is_global());
+    assert_eq!(ids(global_b.chain_data()), [NETWORK_A, NETWORK_B], "scoped handlers leave the shared data alone");
+
+    // The shared data changing under them doesn't reach into a scoped handler's snapshot
+    chainlist::apply_registry(&ChainRegistry { chains: vec![chain_b], ..ChainRegistry::default() });
+    assert_eq!(ids(only_a.chain_data()), [NETWORK_A]);
+    assert_eq!(only_a.chain_data().extra_rpcs(NETWORK_A).len(), 1);
+    assert_eq!(ids(global_b.chain_data()), [NETWORK_B]);
+
+    only_a.init().await.unwrap();
+    global_b.init().await.unwrap();
+    let (_, served_a) = only_a.try_proxy_request_with(chain_id_request(), CallOptions::default()).await.unwrap();
+    let (_, served_b) = global_b.try_proxy_request_with(chain_id_request(), CallOptions::default()).await.unwrap();
+    assert_eq!(served_a, url_key(&server_a));
+    assert_eq!(served_b, url_key(&server_b));
+}
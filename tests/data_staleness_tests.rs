@@ -0,0 +1,84 @@
+#![cfg(all(feature = "chainlist", feature = "test-util"))]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::{
+    chainlist::{self, source::RegistrySnapshot},
+    *,
+};
+use tokio::sync::Mutex;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+// The chain data and its provenance are process-wide
+static CHAIN_DATA: Mutex<()> = Mutex::const_new(());
+
+/// Chain data fetched at `clock`'s current time.
+fn apply_fetched_now(clock: &MockClock) {
+    chainlist::apply_snapshot(&RegistrySnapshot { generated_at: Some(clock.now_system()), ..RegistrySnapshot::default() });
+}
+
+async fn build(staleness: DataStaleness, clock: &MockClock) -> Result<Arc<RpcHandler>> {
+    let rpcs = vec![Rpc::from_url("https://rpc.example").unwrap()];
+    let settings = HandlerSettings { staleness_policy: Some(staleness), ..settings(rpcs) };
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    RpcHandler::with_components(config(settings), None, components).await
+}
+
+fn stale_events(handler: &RpcHandler) -> Vec<HandlerEvent> {
+    let events = handler.recent_events().into_iter().map(|recorded| recorded.event);
+    events.filter(|event| matches!(event, HandlerEvent::ChainDataStale { .. })).collect()
+}
+
+#[tokio::test]
+async fn test_data_age_follows_the_clock() {
+    let _guard = CHAIN_DATA.lock().await;
+    let clock = MockClock::new();
+    apply_fetched_now(&clock);
+
+    let provenance = chainlist::data_provenance();
+    assert!(!provenance.is_offline());
+    assert_eq!(provenance.age_at(clock.now_system()), Duration::ZERO);
+    clock.advance(3 * HOUR);
+    assert_eq!(provenance.age_at(clock.now_system()), 3 * HOUR);
+    assert_eq!(chainlist::get_chains_by_tvl_with_provenance().provenance, provenance);
+}
+
+#[tokio::test]
+async fn test_stale_data_warns_or_fails_construction() {
+    let _guard = CHAIN_DATA.lock().await;
+    let clock = MockClock::new();
+    apply_fetched_now(&clock);
+
+    let fresh = build(DataStaleness { max_age_ms: HOUR.as_millis() as u64, strict: false }, &clock).await.unwrap();
+    assert!(stale_events(&fresh).is_empty());
+
+    clock.advance(2 * HOUR);
+    let stale = build(DataStaleness { max_age_ms: HOUR.as_millis() as u64, strict: false }, &clock).await.unwrap();
+    assert_eq!(stale_events(&stale), vec![HandlerEvent::ChainDataStale { age_ms: Some(7_200_000), max_age_ms: 3_600_000 }]);
+
+    let err = build(DataStaleness { max_age_ms: HOUR.as_millis() as u64, strict: true }, &clock).await.err().unwrap();
+    assert!(matches!(err, RpcHandlerError::ChainDataStale { age_ms: Some(7_200_000), max_age_ms: 3_600_000 }), "{err}");
+    assert!(err.to_string().contains("refresh_from_network"), "{err}");
+}
+
+#[tokio::test]
+async fn test_offline_sentinel_counts_as_stale() {
+    let _guard = CHAIN_DATA.lock().await;
+    let offline = RegistrySnapshot::offline();
+    let rendered = offline.render();
+    assert!(rendered.contains("pub const DATA_GENERATED_AT: Option<u64> = None;"), "{rendered}");
+    assert!(rendered.contains("pub const DATA_SOURCES: &[(&str, Option<&str>)] = &[];"), "{rendered}");
+
+    chainlist::apply_snapshot(&offline);
+    let provenance = chainlist::data_provenance();
+    assert!(provenance.is_offline());
+    assert_eq!((provenance.chains_count, provenance.rpcs_count), (0, 0));
+    assert_eq!(chainlist::data_age(), Duration::MAX);
+
+    let err = build(DataStaleness { max_age_ms: u64::MAX, strict: true }, &MockClock::new()).await.err().unwrap();
+    assert!(matches!(err, RpcHandlerError::ChainDataStale { age_ms: None, .. }), "{err}");
+}
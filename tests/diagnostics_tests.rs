@@ -0,0 +1,120 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const SECRET: &str = "s3cr3t-5d0e7a1f";
+
+struct Secrets(HashMap<String, String>);
+
+impl SecretResolver for Secrets {
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.0.get(name).cloned()
+    }
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ez_web3_rpc-{}-{name}", std::process::id()))
+}
+
+/// A handler over a templated endpoint holding `SECRET` and a plain one, both passing probes and
+/// failing every `eth_call` with a 500, after one failed call.
+async fn failing_handler() -> (Arc<RpcHandler>, MockServer, MockServer) {
+    let (templated, plain) = (MockServer::start().await, MockServer::start().await);
+    for server in [&templated, &plain] {
+        mount_probe(server, "0x10", Duration::ZERO).await;
+        mount_method(server, "eth_call", ResponseTemplate::new(500)).await;
+    }
+    let mut settings = settings(vec![mk_rpc(&plain)]);
+    settings.network_rpcs.push(RpcConfig::template(format!("{}/v2/{{PROVIDER_KEY}}", templated.uri())));
+    let resolver = Secrets(HashMap::from([("PROVIDER_KEY".to_string(), SECRET.to_string())]));
+    let components = HandlerComponents { secret_resolver: Some(Arc::new(resolver)), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+
+    let request = request("eth_call", json!([{ "to": "0x0", "data": "0x" }, "latest"]));
+    assert!(handler.try_proxy_request(request).await.is_err());
+    (handler, templated, plain)
+}
+
+#[tokio::test]
+async fn test_bundle_carries_the_failures_with_every_secret_redacted() {
+    let (handler, _templated, _plain) = failing_handler().await;
+    assert!(handler.recent_failures().iter().any(|failure| failure.url.contains(SECRET)));
+
+    let bundle = handler.diagnostics_bundle().await;
+    assert!(!bundle.recent_failures.is_empty());
+    assert!(bundle.recent_failures.iter().all(|failure| failure.class == FailureClass::HttpStatus), "{:?}", bundle.recent_failures);
+    assert!(bundle.recent_failures.iter().any(|failure| failure.url.contains("/v2/{PROVIDER_KEY}")));
+    assert!(bundle.recent_events.iter().any(|recorded| matches!(recorded.event, HandlerEvent::InitStateChanged(InitState::Final { .. }))));
+    assert_eq!(bundle.latencies.len(), 2);
+    assert_eq!((bundle.metrics.totals.requests, bundle.metrics.totals.successes), (1, 0));
+    assert_eq!(bundle.crate_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(bundle.chain_data.endpoints, 2);
+    assert!(!bundle.redaction.raw_urls);
+    assert_eq!(bundle.redaction.placeholders, ["{PROVIDER_KEY}"]);
+    assert!(bundle.redaction.secrets_redacted > 0);
+
+    let json = serde_json::to_string(&bundle).unwrap();
+    assert!(!json.contains(SECRET));
+    let summary = bundle.summary();
+    assert!(summary.contains("HttpStatus") && summary.contains("endpoints (2)"), "{summary}");
+    assert!(!summary.contains(SECRET));
+
+    // Written out and read back, the bundle is the same
+    let path = temp_path("diagnostics.json");
+    bundle.write_to(&path).unwrap();
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(!written.contains(SECRET));
+    let read: DiagnosticsBundle = serde_json::from_str(&written).unwrap();
+    assert_eq!(read.recent_failures, bundle.recent_failures);
+    assert_eq!(read.recent_events, bundle.recent_events);
+    assert_eq!(read.health, bundle.health);
+    assert_eq!(read.redaction, bundle.redaction);
+}
+
+#[tokio::test]
+async fn test_raw_urls_skip_the_redaction_pass() {
+    let (handler, _templated, _plain) = failing_handler().await;
+    let bundle = handler.diagnostics_bundle_with(DiagnosticsOptions { include_raw_urls: true }).await;
+    assert!(bundle.redaction.raw_urls);
+    assert_eq!(bundle.redaction.secrets_redacted, 0);
+    assert!(bundle.recent_failures.iter().any(|failure| failure.url.contains(SECRET)));
+    assert!(bundle.summary().contains("raw URLs"));
+    // The health report is redacted at the source either way
+    assert!(!bundle.health.to_string().contains(SECRET));
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn test_bundle_written_gzipped() {
+    use std::io::Read;
+
+    let (handler, _templated, _plain) = failing_handler().await;
+    let bundle = handler.diagnostics_bundle().await;
+    let path = temp_path("diagnostics.json.gz");
+    bundle.write_to(&path).unwrap();
+    let written = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let json = serde_json::to_vec_pretty(&bundle).unwrap();
+    assert!(written.len() * 3 < json.len(), "{} of {}", written.len(), json.len());
+    let mut unzipped = Vec::new();
+    flate2::read::GzDecoder::new(written.as_slice()).read_to_end(&mut unzipped).unwrap();
+    assert_eq!(unzipped, json);
+}
+
+#[cfg(not(feature = "gzip"))]
+#[tokio::test]
+async fn test_gz_path_refused_without_the_gzip_feature() {
+    let (handler, _templated, _plain) = failing_handler().await;
+    let path = temp_path("diagnostics.json.gz");
+    let error = handler.diagnostics_bundle().await.write_to(&path).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    assert!(!path.exists());
+}
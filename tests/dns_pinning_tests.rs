@@ -0,0 +1,159 @@
+mod common;
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use common::*;
+use ez_web3_rpc::provider::{HostResolver, PinningResolver};
+use ez_web3_rpc::*;
+use reqwest::dns::{Name, Resolve};
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const HOST: &str = "rpc.pinning.test";
+const SLOW_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+const FAST_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+
+/// Answers with the slow backend first for the first `slow_first` lookups, then flips the order.
+struct StubResolver {
+    lookups: AtomicUsize,
+    slow_first: usize,
+    fail: bool,
+}
+
+impl StubResolver {
+    fn new(slow_first: usize) -> Self {
+        Self { lookups: AtomicUsize::new(0), slow_first, fail: false }
+    }
+
+    fn failing() -> Self {
+        Self { lookups: AtomicUsize::new(0), slow_first: 0, fail: true }
+    }
+}
+
+#[async_trait]
+impl HostResolver for StubResolver {
+    async fn lookup(&self, _host: &str) -> io::Result<Vec<IpAddr>> {
+        let n = self.lookups.fetch_add(1, Ordering::SeqCst);
+        if self.fail {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such host"));
+        }
+        Ok(if n < self.slow_first { vec![SLOW_IP, FAST_IP] } else { vec![FAST_IP, SLOW_IP] })
+    }
+}
+
+async fn resolve(resolver: &PinningResolver, host: &str) -> io::Result<Vec<IpAddr>> {
+    let name: Name = host.parse().unwrap();
+    match resolver.resolve(name).await {
+        Ok(addrs) => Ok(addrs.map(|addr| addr.ip()).collect()),
+        Err(e) => Err(io::Error::other(e.to_string())),
+    }
+}
+
+/// Two mock servers on the same port behind different loopback IPs, like one round-robin hostname.
+async fn start_backends() -> (MockServer, MockServer, u16) {
+    let slow_listener = TcpListener::bind((SLOW_IP, 0)).unwrap();
+    let port = slow_listener.local_addr().unwrap().port();
+    let fast_listener = TcpListener::bind((FAST_IP, port)).unwrap();
+
+    let slow = MockServer::builder().listener(slow_listener).start().await;
+    let fast = MockServer::builder().listener(fast_listener).start().await;
+    (slow, fast, port)
+}
+
+#[tokio::test]
+async fn test_pinned_ip_overrides_lookup_until_unpinned() {
+    let stub = Arc::new(StubResolver::new(0));
+    let resolver = PinningResolver::new(stub.clone());
+    let url = format!("http://{HOST}:8545/");
+
+    assert_eq!(resolve(&resolver, HOST).await.unwrap(), vec![FAST_IP, SLOW_IP]);
+
+    resolver.pin(&url, SLOW_IP);
+    for _ in 0..3 {
+        assert_eq!(resolve(&resolver, HOST).await.unwrap(), vec![SLOW_IP]);
+    }
+    assert_eq!(stub.lookups.load(Ordering::SeqCst), 1, "pinned hosts must not hit the inner resolver");
+    assert_eq!(resolver.pinned_ip(&url), Some(SLOW_IP));
+
+    assert!(resolver.unpin(&url));
+    assert_eq!(resolve(&resolver, HOST).await.unwrap(), vec![FAST_IP, SLOW_IP]);
+    assert_eq!(stub.lookups.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_pin_expires_after_ttl() {
+    let clock = MockClock::new();
+    let resolver = PinningResolver::new(Arc::new(StubResolver::new(0)))
+        .with_pin_ttl(Duration::from_secs(300))
+        .with_clock(Arc::new(clock.clone()));
+    let url = format!("http://{HOST}/");
+
+    resolver.pin(&url, SLOW_IP);
+    clock.advance(Duration::from_secs(299));
+    assert_eq!(resolver.pinned_ip(&url), Some(SLOW_IP));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(resolver.pinned_ip(&url), None);
+    assert_eq!(resolve(&resolver, HOST).await.unwrap(), vec![FAST_IP, SLOW_IP]);
+}
+
+#[tokio::test]
+async fn test_dead_host_fails_fast_from_negative_cache() {
+    let stub = Arc::new(StubResolver::failing());
+    let resolver = PinningResolver::new(stub.clone());
+
+    assert!(resolve(&resolver, HOST).await.is_err());
+    assert!(resolver.is_dead_host(HOST));
+    assert!(resolve(&resolver, HOST).await.is_err());
+    assert_eq!(stub.lookups.load(Ordering::SeqCst), 1, "dead host should not be looked up again");
+}
+
+#[tokio::test]
+async fn test_handler_sticks_to_measured_ip() {
+    let (slow, fast, port) = start_backends().await;
+    mount_probe(&slow, "0x10", Duration::from_millis(60)).await;
+    mount_probe(&fast, "0x10", Duration::ZERO).await;
+    for server in [&slow, &fast] {
+        mount_method(server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    }
+
+    // The probe's connections see the slow backend first; afterwards DNS prefers the fast one
+    let stub = Arc::new(StubResolver::new(2));
+    let url: url::Url = format!("http://{HOST}:{port}").parse().unwrap();
+    let rpc = Rpc { url: url.clone(), tracking: None, tracking_details: None, is_open_source: Some(true) };
+    let mut settings = settings(vec![rpc]);
+    settings.pin_resolved_ips = true;
+
+    let components = HandlerComponents { host_resolver: Some(stub.clone()), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), Some(Strategy::Fastest), components).await.unwrap();
+    handler.init().await.unwrap();
+
+    let key = url.to_string();
+    assert_eq!(handler.pinned_ip(&key), Some(SLOW_IP));
+
+    for _ in 0..3 {
+        let request = request("eth_chainId", json!([]));
+        handler.try_proxy_request(request).await.unwrap();
+    }
+    assert_eq!(count_method(&slow, "eth_chainId").await, 3);
+    assert_eq!(count_method(&fast, "eth_chainId").await, 0, "requests must stay on the measured IP");
+
+    let report = handler.health_report().await;
+    assert_eq!(report.endpoints[0].pinned_ip, Some(SLOW_IP));
+    assert!(report.to_string().contains("-> 127.0.0.1"));
+
+    // Unpinning lets the next probe re-resolve and pin whatever it measures now
+    assert!(handler.unpin(&key));
+    handler.refresh().await.unwrap();
+    assert_eq!(handler.pinned_ip(&key), Some(FAST_IP));
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use std::time::Duration;
+#[cfg(feature = "test-util")]
+use std::sync::Arc;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// An endpoint that passes the probe and reports `chain_id`.
+async fn endpoint(chain_id: u64) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(format!("0x{chain_id:x}"))))).await;
+    server
+}
+
+fn assert_fails(report: &DoctorReport, check: DoctorCheck, containing: &str) {
+    match report.outcome(check) {
+        Some(CheckOutcome::Fail(message, suggestion)) => {
+            assert!(message.contains(containing), "{check}: {message}");
+            assert!(!suggestion.is_empty());
+        }
+        other => panic!("{check} should fail, got {other:?}\n{report}"),
+    }
+}
+
+#[tokio::test]
+async fn test_empty_rpc_set_fails_and_skips_the_live_checks() {
+    let config = HandlerConfig { network_id: 987_654_321, settings: Some(settings(Vec::<Rpc>::new())) };
+    let handler = RpcHandler::new(config, None).await.unwrap();
+
+    let report = handler.doctor().await;
+    assert!(!report.passed());
+    assert_fails(&report, DoctorCheck::RpcCount, "no endpoints for network 987654321");
+    assert_ne!(report.outcome(DoctorCheck::EmbeddedData), Some(&CheckOutcome::Pass), "the network isn't in any registry");
+    for check in [DoctorCheck::Dns, DoctorCheck::LiveProbe, DoctorCheck::ChainId] {
+        assert!(matches!(report.outcome(check), Some(CheckOutcome::Warn(message)) if message.starts_with("skipped")), "{check}");
+    }
+    assert_eq!(report.target, None);
+    assert!(report.to_string().contains("[FAIL] endpoints after filters"), "{report}");
+
+    let serialized = serde_json::to_value(&report).unwrap();
+    assert_eq!(serialized["checks"][1]["check"], "rpc_count");
+    assert_eq!(serialized["checks"][1]["outcome"]["status"], "fail");
+    assert_eq!(serialized["checks"][5], json!({ "check": "clock", "outcome": { "status": "pass" } }));
+}
+
+#[tokio::test]
+async fn test_wrong_chain_id_fails_before_init_without_touching_state() {
+    let (wrong, other) = (endpoint(2).await, endpoint(2).await);
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&wrong), mk_rpc(&other)])), None).await.unwrap();
+
+    let report = handler.doctor().await;
+    let target = report.target.clone().unwrap();
+    assert_eq!(report.outcome(DoctorCheck::LiveProbe), Some(&CheckOutcome::Pass));
+    assert_eq!(report.outcome(DoctorCheck::Dns), Some(&CheckOutcome::Pass), "IP hosts need no lookup");
+    assert_fails(&report, DoctorCheck::ChainId, &format!("serves chain 2, not {TEST_NETWORK_ID}"));
+    assert!(!report.passed());
+
+    // One probe and one chain id request, to one endpoint, every run
+    handler.doctor().await;
+    let targeted = if target == url_key(&wrong) { &wrong } else { &other };
+    let untouched = if target == url_key(&wrong) { &other } else { &wrong };
+    assert_eq!((count_method(targeted, "eth_getCode").await, count_method(targeted, "eth_chainId").await), (2, 2));
+    assert_eq!(untouched.received_requests().await.unwrap().len(), 0);
+    assert!(handler.get_latencies().await.is_empty());
+    assert_eq!(handler.init_state(), InitState::Starting);
+}
+
+#[tokio::test]
+async fn test_unreachable_host_fails_dns_and_the_live_checks() {
+    let unreachable = Rpc {
+        url: "http://ez-web3-rpc-test.invalid/".parse().unwrap(),
+        tracking: None,
+        tracking_details: None,
+        is_open_source: Some(true),
+    };
+    let handler = RpcHandler::new(config(HandlerSettings { rpc_probe_timeout_ms: 3000, ..settings(vec![unreachable]) }), None).await.unwrap();
+
+    let report = handler.doctor().await;
+    assert_fails(&report, DoctorCheck::Dns, "ez-web3-rpc-test.invalid");
+    assert_fails(&report, DoctorCheck::LiveProbe, "did not answer");
+    assert_fails(&report, DoctorCheck::ChainId, "eth_chainId failed");
+    assert!(matches!(report.outcome(DoctorCheck::RpcCount), Some(CheckOutcome::Warn(_))), "a single endpoint");
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_healthy_endpoint_passes_and_clock_skew_warns() {
+    let server = endpoint(TEST_NETWORK_ID).await;
+    let clock = MockClock::new();
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings(vec![mk_rpc(&server)])), None, components).await.unwrap();
+    handler.init().await.unwrap();
+
+    let report = handler.doctor().await;
+    assert_eq!(report.target, Some(url_key(&server)));
+    for check in [DoctorCheck::Dns, DoctorCheck::LiveProbe, DoctorCheck::ChainId, DoctorCheck::Clock] {
+        assert_eq!(report.outcome(check), Some(&CheckOutcome::Pass), "{check}\n{report}");
+    }
+
+    clock.advance(Duration::from_secs(60 * 60));
+    let report = handler.doctor().await;
+    assert!(matches!(report.outcome(DoctorCheck::Clock), Some(CheckOutcome::Warn(message)) if message.contains("off the endpoint's")), "{report}");
+    assert!(report.outcome(DoctorCheck::ChainId) == Some(&CheckOutcome::Pass));
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use std::collections::{HashMap, HashSet};
+
+use common::*;
+#[cfg(feature = "chainlist")]
+use ez_web3_rpc::chainlist::{self, source::ChainRegistry};
+use ez_web3_rpc::*;
+use serde_json::Value;
+
+const GOLDEN: &str = include_str!("fixtures/effective_policy_default.json");
+
+/// `HandlerConfig::new` looks its network up in the chain data, which an offline build lacks.
+fn mainnet_config() -> HandlerConfig {
+    #[cfg(feature = "chainlist")]
+    if chainlist::get_chain_info(1).is_none() {
+        let registry = ChainRegistry::from_json(r#"[{"chainId": 1, "name": "Ethereum Mainnet", "rpc": ["https://eth.example"]}]"#, "[]").unwrap();
+        chainlist::apply_registry(&registry);
+    }
+    HandlerConfig::new(1)
+}
+
+fn policy(settings: HandlerSettings) -> EffectivePolicy {
+    resolve_config(config(settings)).unwrap().effective_policy()
+}
+
+#[test]
+fn test_default_policy_matches_the_golden_file() {
+    let policy = resolve_config(mainnet_config()).unwrap().effective_policy();
+    let serialized = serde_json::to_value(&policy).unwrap();
+    let golden: Value = serde_json::from_str(GOLDEN).unwrap();
+    assert_eq!(serialized, golden, "defaults changed:\n{}", serde_json::to_string_pretty(&serialized).unwrap());
+    assert_eq!(policy.hash(), resolve_config(mainnet_config()).unwrap().effective_policy().hash(), "the hash is stable");
+}
+
+#[test]
+fn test_hash_changes_with_every_knob() {
+    let base = || HandlerSettings { network_rpcs: Vec::new(), ..settings(Vec::<Rpc>::new()) };
+    let proxy = |edit: fn(&mut ProxySettings)| {
+        let mut proxy = ProxySettings::default();
+        edit(&mut proxy);
+        HandlerSettings { proxy_settings: Some(proxy), ..base() }
+    };
+    let variants = vec![
+        base(),
+        proxy(|p| p.retry_count += 1),
+        proxy(|p| p.retry_delay_ms += 1),
+        proxy(|p| p.rpc_call_timeout_ms += 1),
+        proxy(|p| p.connect_timeout_ms = Some(500)),
+        HandlerSettings { rpc_probe_timeout_ms: 2500, ..base() },
+        HandlerSettings { failover_policy: FailoverPolicy::TierStrict, ..base() },
+        HandlerSettings { validation_mode: ValidationMode::Strict, ..base() },
+        HandlerSettings { routes: vec![RouteRule { methods: vec!["eth_call".into()], urls: vec!["http://node:8545".into()], allow_failover: true }], ..base() },
+        HandlerSettings { keepalive: Some(KeepaliveSettings { keepalive_after_ms: 1000, keepalive_interval_ms: 1000 }), ..base() },
+        HandlerSettings { monotonic_head: true, ..base() },
+        HandlerSettings { monotonic_head: true, max_head_lag: 2, ..base() },
+        HandlerSettings { adaptive_probe_timeout: Some(AdaptiveProbeTimeout::default()), ..base() },
+        HandlerSettings { host_limits: HostLimits { default_per_host: Some(2), per_host: HashMap::new(), rate_limit_headers: None }, ..base() },
+        HandlerSettings { max_concurrent_probes: 4, ..base() },
+        HandlerSettings { probe_sweep_deadline_ms: Some(5000), ..base() },
+        HandlerSettings { maintenance_lead_ms: 1, ..base() },
+        HandlerSettings { follow_post_redirects: true, ..base() },
+        HandlerSettings { pin_resolved_ips: true, ..base() },
+    ];
+    let count = variants.len();
+    let hashes: HashSet<String> = variants.into_iter().map(|settings| policy(settings).hash()).collect();
+    assert_eq!(hashes.len(), count, "every knob moves the hash");
+
+    // Settings that don't change behavior leave it alone
+    let renamed = HandlerSettings { network_name: "renamed".into(), log_level: LogLevel::Trace, ..base() };
+    assert_eq!(policy(renamed).hash(), policy(base()).hash());
+}
+
+#[tokio::test]
+async fn test_handler_reports_its_strategy() {
+    let server = wiremock::MockServer::start().await;
+    let settings = HandlerSettings { fast_start_margin_ms: 250, ..settings(vec![mk_rpc(&server)]) };
+    let handler = RpcHandler::new(config(settings.clone()), Some(Strategy::FastStart)).await.unwrap();
+
+    let policy = handler.effective_policy();
+    assert_eq!(serde_json::to_value(&policy.strategy).unwrap(), serde_json::json!({ "name": "fast_start", "margin_ms": 250 }));
+    assert_ne!(policy.hash(), resolve_config(config(settings)).unwrap().effective_policy().hash());
+}
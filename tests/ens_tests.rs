@@ -0,0 +1,174 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use std::{collections::HashMap, time::Duration};
+
+use common::*;
+use ez_web3_rpc::{ens::{encode_node_call, reverse_name}, *};
+use serde_json::{json, Value};
+use wiremock::{matchers::{body_partial_json, method}, Mock, MockServer, Request, ResponseTemplate};
+
+const REGISTRY: &str = "0x1111111111111111111111111111111111111111";
+const RESOLVER: &str = "0x2222222222222222222222222222222222222222";
+const OWNER: &str = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045";
+const ATTACKER: &str = "0x000000000000000000000000000000000000dead";
+
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", bytes.iter().map(|byte| format!("{byte:02x}")).collect::<String>())
+}
+
+fn word(address: &str) -> String {
+    format!("0x{:0>64}", address.trim_start_matches("0x"))
+}
+
+fn abi_string(value: &str) -> String {
+    let data: String = value.bytes().map(|byte| format!("{byte:02x}")).collect();
+    format!("0x{:064x}{:064x}{:0<64}", 32, value.len(), data)
+}
+
+/// An endpoint with `vitalik.eth` set to `address` and `OWNER`'s reverse record to `vitalik.eth`;
+/// `unset.eth` has no resolver. Endpoints claiming `ATTACKER` for it lie about its resolver too.
+async fn ens_endpoint(address: &'static str) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_call" })))
+        .respond_with(move |request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let call = &body["params"][0];
+            let (to, data) = (call["to"].as_str().unwrap(), call["data"].as_str().unwrap());
+            let vitalik = namehash("vitalik.eth");
+            let reverse = namehash(&reverse_name(OWNER));
+            let result = match to {
+                REGISTRY if data == encode_node_call("resolver(bytes32)", &vitalik) && address == ATTACKER => word(ATTACKER),
+                REGISTRY if data == encode_node_call("resolver(bytes32)", &vitalik) || data == encode_node_call("resolver(bytes32)", &reverse) => word(RESOLVER),
+                REGISTRY => word("0x0"),
+                RESOLVER if data == encode_node_call("addr(bytes32)", &vitalik) => word(address),
+                RESOLVER if data == encode_node_call("name(bytes32)", &reverse) => abi_string("vitalik.eth"),
+                _ => word("0x0"),
+            };
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(result)))
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn calls(servers: &[&MockServer]) -> RpcCalls {
+    let handler = RpcHandler::new(config(settings(servers.iter().map(|server| mk_rpc(server)).collect())), None).await.unwrap();
+    handler.init().await.unwrap();
+    RpcCalls::new(handler)
+}
+
+fn options() -> EnsOptions {
+    EnsOptions {
+        consensus: Some(ConsensusOptions { per_host_concurrency: Some(4), ..ConsensusOptions::default() }),
+        registries: HashMap::from([(TEST_NETWORK_ID, REGISTRY.to_string())]),
+        ..EnsOptions::default()
+    }
+}
+
+fn sorted(mut urls: Vec<String>) -> Vec<String> {
+    urls.sort();
+    urls
+}
+
+#[test]
+fn test_namehash_vectors() {
+    assert_eq!(namehash(""), [0u8; 32]);
+    assert_eq!(hex(&namehash("eth")), "0x93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae");
+    assert_eq!(hex(&namehash("foo.eth")), "0xde9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f");
+    assert_eq!(hex(&namehash("vitalik.eth")), "0xee6c4522aab0003e8d14cd40a6af439055fd2577951148c14b6cea9a53475835");
+    assert_eq!(hex(&namehash("addr.reverse")), "0x91d1777781884d03a6757a803996e38de2a42967fb37eeaca72729271025a9e2");
+    assert_eq!(namehash("Vitalik.ETH"), namehash("vitalik.eth"));
+}
+
+#[test]
+fn test_calldata_encoding() {
+    let node = namehash("vitalik.eth");
+    let node_hex = "ee6c4522aab0003e8d14cd40a6af439055fd2577951148c14b6cea9a53475835";
+    assert_eq!(encode_node_call("resolver(bytes32)", &node), format!("0x0178b8bf{node_hex}"));
+    assert_eq!(encode_node_call("addr(bytes32)", &node), format!("0x3b3b57de{node_hex}"));
+    assert_eq!(encode_node_call("name(bytes32)", &node), format!("0x691f3431{node_hex}"));
+    assert_eq!(reverse_name("0xD8dA6BF26964aF9D7eEd9e03E53415D37aA96045"), "d8da6bf26964af9d7eed9e03e53415d37aa96045.addr.reverse");
+}
+
+#[tokio::test]
+async fn test_resolution_agreed_by_every_endpoint() {
+    let servers = [ens_endpoint(OWNER).await, ens_endpoint(OWNER).await, ens_endpoint(OWNER).await];
+    let calls = calls(&servers.iter().collect::<Vec<_>>()).await;
+
+    let resolved = calls.resolve_ens("vitalik.eth", &options()).await.unwrap();
+    assert_eq!((resolved.address.as_str(), resolved.resolver.as_str(), resolved.consensus), (OWNER, RESOLVER, true));
+    assert!(resolved.agreed_by.len() >= 2, "a quorum of two stood behind it");
+    assert!(resolved.agreed_by.iter().all(|url| servers.iter().any(|server| &url_key(server) == url)));
+
+    let reversed = calls.lookup_address(&OWNER.to_uppercase().replace("0X", "0x"), &options()).await.unwrap();
+    assert_eq!((reversed.name.as_str(), reversed.address.as_str(), reversed.resolver.as_str()), ("vitalik.eth", OWNER, RESOLVER));
+    assert!(reversed.agreed_by.len() >= 2);
+}
+
+#[tokio::test]
+async fn test_a_lying_endpoint_is_outvoted_or_blocks_quorum() {
+    let servers = [ens_endpoint(OWNER).await, ens_endpoint(OWNER).await, ens_endpoint(ATTACKER).await];
+    let calls = calls(&servers.iter().collect::<Vec<_>>()).await;
+
+    // The two honest endpoints outvote the liar, which isn't reported as agreeing
+    let resolved = calls.resolve_ens("vitalik.eth", &options()).await.unwrap();
+    assert_eq!((resolved.address.as_str(), resolved.resolver.as_str()), (OWNER, RESOLVER));
+    assert_eq!(resolved.agreed_by, sorted(vec![url_key(&servers[0]), url_key(&servers[1])]));
+
+    // Unanimity can't be reached
+    let strict = EnsOptions { quorum: 1.0, ..options() };
+    assert!(matches!(calls.resolve_ens("vitalik.eth", &strict).await, Err(RpcHandlerError::ConsensusFailure { .. })));
+}
+
+#[tokio::test]
+async fn test_unset_names_and_unsupported_networks() {
+    let servers = [ens_endpoint(OWNER).await, ens_endpoint(OWNER).await];
+    let calls = calls(&servers.iter().collect::<Vec<_>>()).await;
+
+    match calls.resolve_ens("unset.eth", &options()).await {
+        Err(RpcHandlerError::EnsNotFound { name }) => assert_eq!(name, "unset.eth"),
+        other => panic!("expected EnsNotFound, got {other:?}"),
+    }
+    assert!(matches!(calls.lookup_address(ATTACKER, &options()).await, Err(RpcHandlerError::EnsNotFound { .. })));
+
+    match calls.resolve_ens("vitalik.eth", &EnsOptions::default()).await {
+        Err(RpcHandlerError::EnsUnsupportedOnNetwork { network_id }) => assert_eq!(network_id, TEST_NETWORK_ID),
+        other => panic!("expected EnsUnsupportedOnNetwork, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_single_endpoint_resolves_without_consensus() {
+    let server = ens_endpoint(OWNER).await;
+    let calls = calls(&[&server]).await;
+
+    let resolved = calls.resolve_ens("vitalik.eth", &options()).await.unwrap();
+    assert_eq!((resolved.address.as_str(), resolved.consensus), (OWNER, false));
+    assert_eq!(resolved.agreed_by, [url_key(&server)]);
+}
+
+#[tokio::test]
+async fn test_malformed_resolver_answers_are_errors() {
+    // A string offset that overflows once the length word is added, and a non-hex answer
+    let huge_offset = format!("0x{:0>64}", "ffffffffffffffff");
+    for name_result in [huge_offset, format!("0x{}é0", "00".repeat(31))] {
+        let server = MockServer::start().await;
+        mount_probe(&server, "0x10", Duration::ZERO).await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "eth_call" })))
+            .respond_with(move |request: &Request| {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                let result = if body["params"][0]["to"] == REGISTRY { word(RESOLVER) } else { name_result.clone() };
+                ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(result)))
+            })
+            .mount(&server)
+            .await;
+
+        let err = calls(&[&server]).await.lookup_address(OWNER, &options()).await.unwrap_err();
+        assert!(matches!(err, RpcHandlerError::SerializationError(_)), "got {err:?}");
+    }
+}
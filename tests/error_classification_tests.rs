@@ -0,0 +1,116 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use ez_web3_rpc::rpc_service::RpcTestingService;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+fn rpc_at(url: &str) -> Rpc {
+    Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+fn chain_id() -> JsonRpcRequest {
+    request("eth_chainId", json!([]))
+}
+
+async fn latency_error(url: &str, timeout_ms: u64) -> RpcHandlerError {
+    RpcTestingService::new(timeout_ms).with_granular_errors(true).test_rpc_latency(&rpc_at(url)).await.unwrap_err()
+}
+
+/// A handler whose only endpoint passes its probe but answers `eth_chainId` with `response`.
+async fn single_endpoint_handler(server: &MockServer, response: ResponseTemplate, call_timeout_ms: u64) -> std::sync::Arc<RpcHandler> {
+    mount_probe(server, "0x10", Duration::ZERO).await;
+    mount_method(server, "eth_chainId", response).await;
+    let settings = HandlerSettings {
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: call_timeout_ms, connect_timeout_ms: None }),
+        ..settings(vec![mk_rpc(server)])
+    };
+    init_handler(settings).await
+}
+
+#[tokio::test]
+async fn test_refused_port_is_connect_failure() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let url = format!("http://127.0.0.1:{port}/");
+
+    match latency_error(&url, 1000).await {
+        RpcHandlerError::ConnectFailure { url: failed } => assert_eq!(failed, url),
+        other => panic!("expected ConnectFailure, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_unresolvable_host_is_dns_failure() {
+    let url = "http://ez-web3-rpc-test.invalid/";
+    match latency_error(url, 3000).await {
+        RpcHandlerError::DnsFailure { url: failed } => assert_eq!(failed, url),
+        other => panic!("expected DnsFailure, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_unroutable_ip_fails_with_url_attached() {
+    // TEST-NET-1 is never routed: depending on the network this is refused outright or hangs
+    let url = "http://192.0.2.1/";
+    match latency_error(url, 300).await {
+        RpcHandlerError::ConnectFailure { url: failed } => assert_eq!(failed, url),
+        RpcHandlerError::RequestTimeout { url: failed, configured_ms } => {
+            assert_eq!(failed, url);
+            assert_eq!(configured_ms, 300);
+        }
+        other => panic!("expected ConnectFailure or RequestTimeout, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_tls_to_plain_http_is_tls_failure() {
+    let server = MockServer::start().await;
+    let url = format!("{}/", server.uri().replace("http://", "https://"));
+    match latency_error(&url, 1000).await {
+        RpcHandlerError::TlsFailure { url: failed } => assert_eq!(failed, url),
+        other => panic!("expected TlsFailure, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_slow_endpoint_is_request_timeout() {
+    let slow = MockServer::start().await;
+    let response = ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1"))).set_delay(Duration::from_millis(500));
+
+    let handler = single_endpoint_handler(&slow, response, 100).await;
+    match handler.try_proxy_request(chain_id()).await.unwrap_err() {
+        RpcHandlerError::RequestTimeout { url, configured_ms } => {
+            assert_eq!(url, url_key(&slow));
+            assert_eq!(configured_ms, 100);
+        }
+        other => panic!("expected RequestTimeout, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_json_body_is_body_decode() {
+    let broken = MockServer::start().await;
+    let response = ResponseTemplate::new(200).set_body_raw("{\"jsonrpc\": \"2.0\", \"result\": ", "application/json");
+
+    let handler = single_endpoint_handler(&broken, response, 1000).await;
+    match handler.try_proxy_request(chain_id()).await.unwrap_err() {
+        RpcHandlerError::BodyDecode { url, detail } => {
+            assert_eq!(url, url_key(&broken));
+            assert!(!detail.is_empty());
+        }
+        other => panic!("expected BodyDecode, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_error_status_keeps_status_and_url() {
+    let limited = MockServer::start().await;
+    let handler = single_endpoint_handler(&limited, ResponseTemplate::new(429), 1000).await;
+    let err = handler.try_proxy_request(chain_id()).await.unwrap_err();
+    assert!(err.is_rate_limited(), "got {err:?}");
+    assert!(!err.is_transport());
+    assert!(matches!(err, RpcHandlerError::HttpStatus { ref url, status: 429 } if *url == url_key(&limited)));
+}
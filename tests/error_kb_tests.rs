@@ -0,0 +1,83 @@
+mod common;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde::Deserialize;
+
+const CORPUS: &str = include_str!("fixtures/provider_errors.json");
+
+#[derive(Deserialize)]
+struct Case {
+    client: String,
+    url: String,
+    error: JsonRpcError,
+    condition: ErrorCondition,
+}
+
+fn error(code: i64, message: &str) -> JsonRpcError {
+    JsonRpcError { code, message: message.into(), data: None }
+}
+
+#[test]
+fn test_provider_errors_map_to_their_condition() {
+    let cases: Vec<Case> = serde_json::from_str(CORPUS).unwrap();
+    for case in &cases {
+        let condition = classify_jsonrpc_error(&case.error, &case.url);
+        assert_eq!(condition, case.condition, "{} says {:?}", case.client, case.error.message);
+    }
+    // Provider-specific rows apply to that provider only
+    let full = error(-32000, "queue is full");
+    assert_eq!(classify_jsonrpc_error(&full, "https://rpc.ankr.com/eth"), ErrorCondition::RateLimited);
+    assert_eq!(classify_jsonrpc_error(&full, "https://geth.example.org"), ErrorCondition::Unclassified);
+}
+
+#[test]
+fn test_mappings_are_consulted_before_the_built_in_table() {
+    let nonce = error(-32000, "nonce too low");
+    let mapping: ErrorMapping = serde_json::from_value(serde_json::json!({
+        "message": "NONCE TOO (LOW|HIGH)",
+        "host": "*.example.org",
+        "condition": "node_syncing",
+    }))
+    .unwrap();
+    assert_eq!(error::kb::classify_with(std::slice::from_ref(&mapping), &nonce, "https://lagging.example.org"), ErrorCondition::NodeSyncing);
+    assert_eq!(error::kb::classify_with(std::slice::from_ref(&mapping), &nonce, "https://rpc.example.com"), ErrorCondition::Unclassified);
+
+    // A mapping overrides a built-in row too
+    let reverted = ErrorMapping { codes: vec![3], message: None, host: None, condition: ErrorCondition::Internal };
+    assert_eq!(error::kb::classify_with(&[reverted], &error(3, "execution reverted"), ""), ErrorCondition::Internal);
+    assert!(!ErrorCondition::Unclassified.is_retryable() && ErrorCondition::NodeSyncing.is_retryable());
+}
+
+#[test]
+fn test_message_patterns_are_regular_expressions() {
+    let mapping = ErrorMapping {
+        codes: vec![],
+        message: Some(MessagePattern::new("header not found|unknown block").unwrap()),
+        host: Some("*.example.org".into()),
+        condition: ErrorCondition::Internal,
+    };
+    for message in ["header not found", "Unknown Block 0x10"] {
+        assert_eq!(error::kb::classify_with(std::slice::from_ref(&mapping), &error(-32000, message), "https://rpc.example.org"), ErrorCondition::Internal);
+    }
+    assert_eq!(error::kb::classify_with(std::slice::from_ref(&mapping), &error(-32000, "nonce too low"), "https://rpc.example.org"), ErrorCondition::Unclassified);
+
+    let invalid = MessagePattern::new("header (not found");
+    assert!(matches!(invalid, Err(RpcHandlerError::InvalidErrorMapping { ref pattern, .. }) if pattern == "header (not found"), "{invalid:?}");
+    let parsed = serde_json::from_value::<ErrorMapping>(serde_json::json!({ "message": "header (not found", "condition": "internal" }));
+    assert!(parsed.unwrap_err().to_string().contains("header (not found"));
+}
+
+#[tokio::test]
+async fn test_handler_classifies_with_its_settings() {
+    let mut settings = settings(Vec::<Rpc>::new());
+    settings.error_mappings.push(ErrorMapping { codes: vec![-32099], message: None, host: Some("127.0.0.1".into()), condition: ErrorCondition::RateLimited });
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+
+    let custom = error(-32099, "slow down");
+    assert_eq!(handler.classify_error(&custom, "http://127.0.0.1:8545"), ErrorCondition::RateLimited);
+    assert_eq!(handler.classify_error(&custom, "http://localhost:8545"), ErrorCondition::Unclassified);
+    assert_eq!(classify_jsonrpc_error(&custom, "http://127.0.0.1:8545"), ErrorCondition::Unclassified);
+    // Everything else still goes by the built-in table
+    assert_eq!(handler.classify_error(&error(-32601, "method not found"), "http://127.0.0.1:8545"), ErrorCondition::MethodNotFound);
+}
@@ -0,0 +1,151 @@
+#![cfg(any(feature = "consensus", feature = "persistence"))]
+
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::{Duration, SystemTime}};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+#[cfg(feature = "persistence")]
+const SECRET: &str = "s3cr3t-9b41c0de";
+
+struct Secrets(HashMap<String, String>);
+
+impl SecretResolver for Secrets {
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.0.get(name).cloned()
+    }
+}
+
+#[cfg(feature = "consensus")]
+fn answer(result: serde_json::Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, result))
+}
+
+async fn probed() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    server
+}
+
+#[cfg(feature = "consensus")]
+fn settings_for(servers: &[&MockServer]) -> HandlerSettings {
+    settings(servers.iter().map(|server| mk_rpc(server)).collect())
+}
+
+async fn journaled(settings: HandlerSettings, journal: Arc<dyn JournalStore>, secrets: Option<Secrets>) -> Arc<RpcHandler> {
+    let components = HandlerComponents {
+        failure_journal: Some(journal),
+        secret_resolver: secrets.map(|secrets| Arc::new(secrets) as Arc<dyn SecretResolver>),
+        ..HandlerComponents::default()
+    };
+    let handler = RpcHandler::with_components(config(settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+    handler
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_a_mix_of_failures_is_summarized() {
+    let store = Arc::new(MemoryJournal::default());
+    let since = SystemTime::now() - Duration::from_secs(1);
+
+    // One endpoint under strict validation: a 500, a malformed answer, another network's id
+    let lone = probed().await;
+    mount_method(&lone, "eth_call", ResponseTemplate::new(500)).await;
+    mount_method(&lone, "eth_getBalance", answer(json!("lots"))).await;
+    mount_method(&lone, "eth_chainId", answer(json!("0x1"))).await;
+    mount_method(&lone, "eth_blockNumber", answer(json!("0x10"))).await;
+    let settings = HandlerSettings { validation_mode: ValidationMode::Strict, ..settings(vec![mk_rpc(&lone)]) };
+    let lone_handler = journaled(settings, store.clone(), None).await;
+
+    // Answered requests leave nothing
+    lone_handler.try_proxy_request(block_number()).await.unwrap();
+    assert_eq!(lone_handler.failure_summary(since).await.unwrap().total, 0);
+
+    let call = request("eth_call", json!([{ "to": "0x0", "data": "0x" }, "latest"]));
+    assert!(lone_handler.try_proxy_request(call.clone()).await.is_err());
+    assert!(lone_handler.try_proxy_request(request("eth_getBalance", json!(["0x0000000000000000000000000000000000000001", "latest"]))).await.is_err());
+    // The wrong chain is journaled, but the answer still returned
+    assert_eq!(lone_handler.try_proxy_request(request("eth_chainId", json!([]))).await.unwrap().result, Some(json!("0x1")));
+
+    // Two endpoints sharing the journal: both failing a call, then splitting a consensus read
+    let (a, b) = (probed().await, probed().await);
+    mount_method(&a, "eth_call", ResponseTemplate::new(500)).await;
+    mount_method(&b, "eth_call", ResponseTemplate::new(503)).await;
+    mount_method(&a, "eth_blockNumber", answer(json!("0x10"))).await;
+    mount_method(&b, "eth_blockNumber", answer(json!("0x11"))).await;
+    let pair = journaled(settings_for(&[&a, &b]), store.clone(), None).await;
+    assert!(pair.try_proxy_request(call.clone()).await.is_err());
+    let calls = RpcCalls::new(Arc::clone(&pair));
+    assert!(calls.consensus::<String>(&block_number(), 0.66, None).await.is_err());
+
+    let summary = pair.failure_summary(since).await.unwrap();
+    assert_eq!(summary.total, 5, "{summary:#?}");
+    assert_eq!(summary.by_kind[&FailureKind::RequestFailed], 3);
+    assert_eq!(summary.by_kind[&FailureKind::ConsensusFailed], 1);
+    assert_eq!(summary.by_kind[&FailureKind::ChainIdMismatch], 1);
+    assert_eq!(summary.by_class[&FailureClass::HttpStatus], 1);
+    assert_eq!(summary.by_class[&FailureClass::Malformed], 2);
+    assert_eq!(summary.by_class[&FailureClass::Exhausted], 1);
+    assert_eq!(summary.by_method["eth_call"], 2);
+    assert_eq!(summary.by_endpoint[&url_key(&lone)], 3);
+    assert_eq!((summary.by_endpoint[&url_key(&a)], summary.by_endpoint[&url_key(&b)]), (1, 1));
+
+    let entries = store.entries().unwrap();
+    let exhausted = entries.iter().find(|entry| entry.class == FailureClass::Exhausted).unwrap();
+    assert_eq!(exhausted.attempts.len(), 2);
+    assert_eq!(exhausted.endpoint, None);
+    let consensus = entries.iter().find(|entry| entry.kind == FailureKind::ConsensusFailed).unwrap();
+    assert_eq!(consensus.votes.values().sum::<usize>(), 2);
+    // Equivalent calls share a digest
+    let digests: Vec<&str> = entries.iter().filter(|entry| entry.method == "eth_call").map(|entry| entry.payload_digest.as_str()).collect();
+    let digest = journal::payload_digest(&call);
+    assert_eq!(digests, [digest.as_str(); 2]);
+
+    let markdown = summary.to_markdown();
+    assert!(markdown.starts_with("## Failure summary\n\n5 failures recorded since "), "{markdown}");
+    for heading in ["### By kind", "### By error class", "### By method", "### By endpoint", "### Examples"] {
+        assert!(markdown.contains(heading), "{markdown}");
+    }
+    assert!(markdown.contains("| RequestFailed | 3 |") && markdown.contains("| Malformed | 2 |"), "{markdown}");
+    assert!(markdown.contains("| RequestFailed | Exhausted | `eth_call` | 1 | "), "{markdown}");
+    assert!(markdown.contains(&format!("`{digest}`")), "{markdown}");
+
+    // Only what is recorded after `since` counts
+    assert_eq!(pair.failure_summary(SystemTime::now() + Duration::from_secs(1)).await.unwrap().total, 0);
+}
+
+#[cfg(feature = "persistence")]
+#[tokio::test]
+async fn test_file_journal_rotates_and_keeps_secrets_out() {
+    let path = std::env::temp_dir().join(format!("ez_web3_rpc-{}-journal.jsonl", std::process::id()));
+    let store = Arc::new(FileJournal::open(&path, 2048).unwrap());
+    let rotated = store.rotated_path();
+
+    let server = probed().await;
+    mount_method(&server, "eth_call", ResponseTemplate::new(500)).await;
+    let mut settings = settings(Vec::<Rpc>::new());
+    settings.network_rpcs.push(RpcConfig::template(format!("{}/v2/{{PROVIDER_KEY}}", server.uri())));
+    let secrets = Secrets(HashMap::from([("PROVIDER_KEY".to_string(), SECRET.to_string())]));
+    let handler = journaled(settings, store.clone(), Some(secrets)).await;
+
+    for _ in 0..12 {
+        assert!(handler.try_proxy_request(request("eth_call", json!([{ "to": "0x0", "data": "0x" }, "latest"]))).await.is_err());
+    }
+    let summary = handler.failure_summary(SystemTime::UNIX_EPOCH).await.unwrap();
+
+    let (current, previous) = (std::fs::read_to_string(&path).unwrap(), std::fs::read_to_string(&rotated).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&rotated).unwrap();
+    assert!(current.len() <= 2048 && previous.len() <= 2048);
+    // The oldest entries were rotated out; what is kept is whole JSON Lines
+    let kept = current.lines().count() + previous.lines().count();
+    assert!(kept < 12 && summary.total == kept, "{kept} kept, {} summarized", summary.total);
+    assert!(!current.contains(SECRET) && !previous.contains(SECRET));
+    assert!(current.contains("/v2/{PROVIDER_KEY}"));
+    assert!(summary.by_endpoint.keys().all(|url| url.ends_with("/v2/{PROVIDER_KEY}")), "{:?}", summary.by_endpoint);
+}
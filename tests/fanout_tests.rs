@@ -0,0 +1,100 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::{fanout, *};
+use serde_json::{json, Value};
+use wiremock::{MockServer, ResponseTemplate};
+
+fn transport() -> Arc<dyn TransportFactory> {
+    Arc::new(HttpTransportFactory::new(Duration::from_secs(2)))
+}
+
+fn indexer_status() -> JsonRpcRequest {
+    request("indexer_status", json!({ "shard": 7 }))
+}
+
+/// An endpoint answering `indexer_status` with `result`.
+async fn serving(result: Value) -> MockServer {
+    let server = MockServer::start().await;
+    mount_method(&server, "indexer_status", ResponseTemplate::new(200).set_body_json(rpc_response(1, result))).await;
+    server
+}
+
+async fn failing(status: u16) -> MockServer {
+    let server = MockServer::start().await;
+    mount_method(&server, "indexer_status", ResponseTemplate::new(status)).await;
+    server
+}
+
+#[tokio::test]
+async fn test_race_fails_over_to_the_next_batch() {
+    let (down, up) = (failing(503).await, serving(json!({ "height": 42 })).await);
+    let endpoints = vec![Endpoint::new(down.uri()), Endpoint::new(up.uri())];
+    let config = RaceConfig { batch_size: 1, ..RaceConfig::new(transport()) };
+
+    let (value, attribution) = fanout::race(&endpoints, &indexer_status(), &config).await.unwrap();
+    assert_eq!(value, json!({ "height": 42 }));
+    assert_eq!((attribution.url.as_str(), attribution.round, attribution.batch), (up.uri().as_str(), 0, 1));
+    assert_eq!(attribution.failures.len(), 1);
+    assert_eq!(attribution.failures[0].url, down.uri());
+}
+
+#[tokio::test]
+async fn test_race_tries_heavier_endpoints_first() {
+    let (light, heavy) = (serving(json!("light")).await, serving(json!("heavy")).await);
+    let endpoints = vec![Endpoint::new(light.uri()), Endpoint { weight: 5, ..Endpoint::new(heavy.uri()) }];
+    let config = RaceConfig { batch_size: 1, ..RaceConfig::new(transport()) };
+
+    let (value, _) = fanout::race(&endpoints, &indexer_status(), &config).await.unwrap();
+    assert_eq!(value, json!("heavy"));
+    assert_eq!(count_method(&light, "indexer_status").await, 0);
+}
+
+#[tokio::test]
+async fn test_race_retries_rounds_and_reports_a_lone_endpoints_error() {
+    let down = failing(502).await;
+    let config = RaceConfig { rounds: 3, ..RaceConfig::new(transport()) };
+
+    let err = fanout::race(&[Endpoint::new(down.uri())], &indexer_status(), &config).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::HttpStatus { status: 502, .. }), "{err}");
+    assert_eq!(count_method(&down, "indexer_status").await, 3);
+}
+
+#[tokio::test]
+async fn test_quorum_agrees_on_the_majority_answer() {
+    let servers = [serving(json!("abc")).await, serving(json!("abc")).await, serving(json!("xyz")).await];
+    let endpoints: Vec<Endpoint> = servers.iter().map(|server| Endpoint::new(server.uri())).collect();
+    let config = QuorumConfig { stop_at_quorum: false, ..QuorumConfig::new(transport(), 0.66) };
+
+    let outcome = fanout::quorum(&endpoints, &indexer_status(), &config).await.unwrap();
+    assert_eq!(outcome.value, Some(json!("abc")));
+    assert_eq!(outcome.quorum, 2);
+    assert_eq!(outcome.votes.values().copied().collect::<Vec<_>>(), vec![2, 1]);
+    assert_eq!(outcome.voters[&servers[2].uri()], "xyz");
+}
+
+#[tokio::test]
+async fn test_quorum_disagreement_reaches_no_value() {
+    let servers = [serving(json!("abc")).await, serving(json!("xyz")).await, failing(500).await];
+    let endpoints: Vec<Endpoint> = servers.iter().map(|server| Endpoint::new(server.uri())).collect();
+
+    let outcome = fanout::quorum(&endpoints, &indexer_status(), &QuorumConfig::new(transport(), 0.66)).await.unwrap();
+    assert_eq!(outcome.value, None);
+    assert_eq!(outcome.votes.len(), 2);
+    assert_eq!(outcome.failures.len(), 1);
+    assert_eq!(outcome.failures[0].url, servers[2].uri());
+}
+
+#[tokio::test]
+async fn test_quorum_counts_votes_by_weight() {
+    let (minority, majority) = (serving(json!("abc")).await, serving(json!("xyz")).await);
+    let extra = serving(json!("abc")).await;
+    let endpoints = vec![Endpoint::new(minority.uri()), Endpoint::new(extra.uri()), Endpoint { weight: 3, ..Endpoint::new(majority.uri()) }];
+    let config = QuorumConfig { stop_at_quorum: false, ..QuorumConfig::new(transport(), 0.6) };
+
+    let outcome = fanout::quorum(&endpoints, &indexer_status(), &config).await.unwrap();
+    assert_eq!(outcome.value, Some(json!("xyz")));
+    assert_eq!(outcome.votes[outcome.most_common.as_deref().unwrap()], 3);
+}
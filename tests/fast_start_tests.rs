@@ -0,0 +1,103 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use tokio::sync::broadcast;
+use wiremock::{MockServer, ResponseTemplate};
+
+async fn backend(block: &str, probe_delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, block, probe_delay).await;
+    mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(block)))).await;
+    server
+}
+
+/// The next event `matches` accepts, skipping others, within two seconds.
+async fn next_event(events: &mut broadcast::Receiver<HandlerEvent>, matches: impl Fn(&HandlerEvent) -> bool) -> HandlerEvent {
+    tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let event = events.recv().await.unwrap();
+            if matches(&event) {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("event not emitted in time")
+}
+
+fn is_final(event: &HandlerEvent) -> bool {
+    matches!(event, HandlerEvent::InitStateChanged(InitState::Final { .. }))
+}
+
+#[tokio::test]
+async fn test_serves_provisionally_then_upgrades_to_fastest() {
+    // Answers first, but a block behind the other two
+    let quick = backend("0xf", Duration::ZERO).await;
+    let fast = backend("0x10", Duration::from_millis(150)).await;
+    let slow = backend("0x10", Duration::from_millis(300)).await;
+    let rpcs = vec![mk_rpc(&quick), mk_rpc(&fast), mk_rpc(&slow)];
+
+    let handler = RpcHandler::new(config(settings(rpcs)), Some(Strategy::FastStart)).await.unwrap();
+    let mut events = handler.subscribe();
+    assert_eq!(handler.init_state(), InitState::Starting);
+
+    let started = Instant::now();
+    handler.init().await.unwrap();
+    assert_eq!(handler.init_state(), InitState::Provisional { url: url_key(&quick) });
+
+    // Requests are served straight away, without waiting for the sweep
+    let (response, served_by) = handler.try_proxy_request_attributed(block_number()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0xf")));
+    assert_eq!(served_by, url_key(&quick));
+    assert!(started.elapsed() < Duration::from_millis(150), "init and the first request took {:?}", started.elapsed());
+
+    let upgraded = next_event(&mut events, |e| matches!(e, HandlerEvent::ProviderUpgraded { .. })).await;
+    assert_eq!(upgraded, HandlerEvent::ProviderUpgraded {
+        from: url_key(&quick),
+        to: url_key(&fast),
+        from_latency_ms: None,
+        to_latency_ms: handler.get_latencies().await[&url_key(&fast)],
+    });
+    next_event(&mut events, is_final).await;
+    assert_eq!(handler.init_state(), InitState::Final { url: url_key(&fast) });
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&fast));
+}
+
+#[tokio::test]
+async fn test_keeps_provisional_provider_when_it_is_the_fastest() {
+    let fast = backend("0x10", Duration::ZERO).await;
+    let slow = backend("0x10", Duration::from_millis(200)).await;
+    let settings = HandlerSettings { fast_start_margin_ms: 50, ..settings(vec![mk_rpc(&fast), mk_rpc(&slow)]) };
+
+    let handler = RpcHandler::new(config(settings), Some(Strategy::FastStart)).await.unwrap();
+    let mut events = handler.subscribe();
+    handler.init().await.unwrap();
+
+    let event = next_event(&mut events, |e| is_final(e) || matches!(e, HandlerEvent::ProviderUpgraded { .. })).await;
+    assert_eq!(event, HandlerEvent::InitStateChanged(InitState::Final { url: url_key(&fast) }));
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&fast));
+    assert_eq!(handler.get_latencies().await.len(), 2, "the sweep's latencies are kept");
+}
+
+#[tokio::test]
+async fn test_tier_strict_upgrades_to_lower_tier_even_when_slower() {
+    let backup = backend("0x10", Duration::ZERO).await;
+    let primary = backend("0x10", Duration::from_millis(100)).await;
+    let settings = HandlerSettings {
+        failover_policy: FailoverPolicy::TierStrict,
+        fast_start_margin_ms: 1000,
+        ..settings(vec![tiered(&backup, 1), tiered(&primary, 0)])
+    };
+
+    let handler = RpcHandler::new(config(settings), Some(Strategy::FastStart)).await.unwrap();
+    let mut events = handler.subscribe();
+    handler.init().await.unwrap();
+    assert_eq!(handler.init_state(), InitState::Provisional { url: url_key(&backup) });
+
+    next_event(&mut events, is_final).await;
+    assert_eq!(handler.init_state(), InitState::Final { url: url_key(&primary) });
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::{path::Path, process::Command, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// The feature combinations the README documents, each of which must compile on its own.
+const COMBINATIONS: &[&str] = &["", "chainlist", "ws", "consensus", "persistence", "bench-bin", "cli", "consensus,persistence", "scenarios", "full"];
+
+#[tokio::test]
+async fn test_injected_rpcs_serve_requests_whatever_the_features() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&server)])), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let request = request("eth_chainId", json!([]));
+    let (response, url) = handler.try_proxy_request_attributed(request).await.unwrap();
+    assert_eq!((response.result, url), (Some(json!("0x1")), url_key(&server)));
+    if !cfg!(feature = "chainlist") {
+        assert!(chainlist::get_chain_info(1).is_none());
+        assert!(handler.rpc_source().rpcs(1).is_empty());
+    }
+}
+
+#[test]
+fn test_handler_config_new_needs_no_chain_data() {
+    let config = HandlerConfig::new(TEST_NETWORK_ID);
+    assert_eq!(config.settings.unwrap().network_name, format!("chain-{TEST_NETWORK_ID}"));
+}
+
+/// Runs `cargo check` once per combination, in its own target directory so it doesn't wait on the
+/// lock of the build running the tests. Offline, so the registry isn't downloaded for each one.
+/// Too slow for every `cargo test`; run with `--ignored`.
+#[test]
+#[ignore = "builds the crate once per combination"]
+fn test_every_documented_feature_combination_compiles() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = manifest_dir.join("target").join("feature-matrix");
+    for features in COMBINATIONS {
+        let status = Command::new(env!("CARGO"))
+            .args(["check", "--offline", "--lib", "--bins", "--no-default-features", "--features", features])
+            .current_dir(manifest_dir)
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .env("CARGO_NET_OFFLINE", "true")
+            .status()
+            .unwrap();
+        assert!(status.success(), "`--no-default-features --features {features:?}` doesn't compile");
+    }
+}
+
+/// The default features build for the browser. Skipped where the `wasm32-unknown-unknown` target
+/// isn't installed.
+#[test]
+#[ignore = "builds the crate for wasm32"]
+fn test_default_features_compile_for_wasm32() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let libdir = Command::new("rustc").args(["--print", "target-libdir", "--target", "wasm32-unknown-unknown"]).output().unwrap();
+    if !Path::new(String::from_utf8_lossy(&libdir.stdout).trim()).exists() {
+        eprintln!("wasm32-unknown-unknown isn't installed, skipping");
+        return;
+    }
+    let status = Command::new(env!("CARGO"))
+        .args(["check", "--offline", "--lib", "--target", "wasm32-unknown-unknown"])
+        .current_dir(manifest_dir)
+        .env("CARGO_TARGET_DIR", manifest_dir.join("target").join("feature-matrix"))
+        .env("CARGO_NET_OFFLINE", "true")
+        .status()
+        .unwrap();
+    assert!(status.success(), "the default features don't compile for wasm32-unknown-unknown");
+}
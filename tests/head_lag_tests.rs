@@ -0,0 +1,95 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use common::*;
+#[cfg(feature = "scenarios")]
+use ez_web3_rpc::scenario::*;
+use ez_web3_rpc::*;
+use wiremock::MockServer;
+
+async fn at_block(block: &str, probe_delay_ms: u64) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, block, Duration::from_millis(probe_delay_ms)).await;
+    server
+}
+
+async fn handler(servers: &[&MockServer], customize: impl FnOnce(&mut HandlerSettings)) -> Arc<RpcHandler> {
+    let mut settings = settings(servers.iter().map(|server| mk_rpc(server)).collect());
+    customize(&mut settings);
+    init_handler(settings).await
+}
+
+/// `(head_lag, latency measured)` per endpoint in the health report.
+async fn reported(handler: &RpcHandler) -> HashMap<String, (Option<u64>, bool)> {
+    let report = handler.health_report().await;
+    report.endpoints.into_iter().map(|endpoint| (endpoint.url, (endpoint.head_lag, endpoint.latency_ms.is_some()))).collect()
+}
+
+fn ordered_urls(ordered: &[OrderedRpc]) -> Vec<String> {
+    ordered.iter().map(|ordered| ordered.rpc.url.to_string()).collect()
+}
+
+#[cfg(feature = "scenarios")]
+#[tokio::test]
+async fn test_lag_behind_the_common_head_is_recorded_per_endpoint() {
+    // The head is one block past the common one, so that one endpoint can be ahead of it
+    let mut scenario = Scenario::at_head(0x11);
+    let first = scenario.endpoint("first", Script::new(Behavior::Stale(1))).await.unwrap();
+    let second = scenario.endpoint("second", Script::new(Behavior::Stale(1))).await.unwrap();
+    let behind = scenario.endpoint("behind", Script::new(Behavior::Stale(3))).await.unwrap();
+    let ahead = scenario.endpoint("ahead", Script::healthy()).await.unwrap();
+    let handler = RpcHandler::new(scenario.config_with(settings(scenario.endpoints().iter().map(ScenarioEndpoint::rpc).collect())), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let lags = handler.head_lags();
+    assert_eq!(lags[first.url()], 0);
+    assert_eq!(lags[second.url()], 0);
+    assert_eq!(lags[behind.url()], 2);
+    // Ahead of the common head is not behind it
+    assert_eq!(lags[ahead.url()], 0);
+
+    // By default only endpoints exactly at the common head are measured
+    let reported = reported(&handler).await;
+    assert_eq!(reported[first.url()], (Some(0), true));
+    assert_eq!(reported[behind.url()], (Some(2), false));
+    assert_eq!(reported[ahead.url()], (Some(0), false));
+    assert!(handler.health_report().await.to_string().contains("[2 blocks behind]"));
+}
+
+#[tokio::test]
+async fn test_lag_penalty_puts_an_endpoint_at_the_tip_ahead_of_a_faster_one_behind() {
+    let (tip, also_tip, fast_behind) = (at_block("0x10", 40).await, at_block("0x10", 60).await, at_block("0xe", 0).await);
+    let servers = [&tip, &also_tip, &fast_behind];
+
+    let latency_only = handler(&servers, |settings| settings.max_probe_lag_blocks = 2).await;
+    assert_eq!(latency_only.get_provider_url().await.unwrap(), url_key(&fast_behind));
+    assert_eq!(ordered_urls(&latency_only.ordered_rpcs().await), [url_key(&fast_behind), url_key(&tip), url_key(&also_tip)]);
+
+    // 100ms per block outweighs the 40ms the endpoint two blocks behind saves
+    let penalized = handler(&servers, |settings| {
+        settings.max_probe_lag_blocks = 2;
+        settings.head_lag_penalty_ms = 100;
+    })
+    .await;
+    assert_eq!(penalized.get_provider_url().await.unwrap(), url_key(&tip));
+    assert_eq!(ordered_urls(&penalized.ordered_rpcs().await), [url_key(&tip), url_key(&also_tip), url_key(&fast_behind)]);
+    // The plan still reports the measured latency, not the score
+    let request = request("eth_call", serde_json::json!([]));
+    let plan = penalized.plan_request(&request, None).await.unwrap();
+    let behind = plan.urls.iter().find(|planned| planned.url == url_key(&fast_behind)).unwrap();
+    assert!(behind.latency_ms.unwrap() < 100, "{:?}", behind.latency_ms);
+}
+
+#[tokio::test]
+async fn test_exclusion_threshold_is_configurable() {
+    let (first, second, one_behind, two_behind) = (at_block("0x10", 0).await, at_block("0x10", 0).await, at_block("0xf", 0).await, at_block("0xe", 0).await);
+    let servers = [&first, &second, &one_behind, &two_behind];
+
+    let strict = reported(&*handler(&servers, |_| {}).await).await;
+    assert_eq!((strict[&url_key(&one_behind)].1, strict[&url_key(&two_behind)].1), (false, false));
+
+    let lenient = reported(&*handler(&servers, |settings| settings.max_probe_lag_blocks = 1).await).await;
+    assert_eq!(lenient[&url_key(&one_behind)], (Some(1), true));
+    assert_eq!(lenient[&url_key(&two_behind)], (Some(2), false));
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use tokio::sync::broadcast;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+async fn revive(server: &MockServer) {
+    server.reset().await;
+    mount_probe(server, "0x10", Duration::ZERO).await;
+    mount_method(server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10")))).await;
+}
+
+async fn kill(server: &MockServer) {
+    server.reset().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(503)).mount(server).await;
+}
+
+/// Two healthy endpoints behind an initialized handler, then both taken down.
+async fn outage() -> (std::sync::Arc<RpcHandler>, MockServer, MockServer) {
+    let (a, b) = (MockServer::start().await, MockServer::start().await);
+    revive(&a).await;
+    revive(&b).await;
+    let handler = init_handler(settings(vec![mk_rpc(&a), mk_rpc(&b)])).await;
+    kill(&a).await;
+    kill(&b).await;
+    (handler, a, b)
+}
+
+fn held(max_wait_ms: u64) -> CallOptions {
+    let policy = HoldPolicy { max_wait: Duration::from_millis(max_wait_ms), retry_interval: Duration::from_millis(100) };
+    CallOptions { hold_on_total_failure: Some(policy), ..CallOptions::default() }
+}
+
+/// The next hold event, skipping others.
+async fn next_hold_event(events: &mut broadcast::Receiver<HandlerEvent>) -> HandlerEvent {
+    tokio::time::timeout(Duration::from_secs(3), async {
+        loop {
+            match events.recv().await.unwrap() {
+                event @ (HandlerEvent::RequestHeld { .. } | HandlerEvent::HoldReleased { .. }) => return event,
+                _ => continue,
+            }
+        }
+    })
+    .await
+    .expect("hold event not emitted in time")
+}
+
+#[tokio::test]
+async fn test_held_call_succeeds_once_an_endpoint_recovers() {
+    let (handler, _a, b) = outage().await;
+    let mut events = handler.subscribe();
+
+    // Without a hold policy the outage fails the call straight away
+    assert!(handler.try_proxy_request_with(block_number(), CallOptions::default()).await.is_err());
+
+    let call = tokio::spawn({
+        let handler = handler.clone();
+        async move { handler.try_proxy_request_with(block_number(), held(5000)).await }
+    });
+    assert_eq!(next_hold_event(&mut events).await, HandlerEvent::RequestHeld { method: "eth_blockNumber".into(), holding: 1, tags: CallTags::new() });
+    assert_eq!(handler.holding_requests(), 1);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    revive(&b).await;
+    let (response, served_by) = tokio::time::timeout(Duration::from_secs(3), call).await.unwrap().unwrap().unwrap();
+    assert_eq!(response.result, Some(json!("0x10")));
+    assert_eq!(served_by, url_key(&b));
+    assert_eq!(next_hold_event(&mut events).await, HandlerEvent::HoldReleased { method: "eth_blockNumber".into(), holding: 0, recovered: true, tags: CallTags::new() });
+    assert_eq!(handler.holding_requests(), 0);
+}
+
+#[tokio::test]
+async fn test_expired_hold_returns_every_round() {
+    let (handler, _a, _b) = outage().await;
+    let mut events = handler.subscribe();
+
+    let started = Instant::now();
+    let err = handler.try_proxy_request_with(block_number(), held(400)).await.unwrap_err();
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(400) && elapsed < Duration::from_secs(3), "held for {elapsed:?}");
+    match err {
+        RpcHandlerError::HoldExpired { held_ms, attempts, last } => {
+            assert!(held_ms >= 400, "{held_ms}");
+            assert!(attempts.len() >= 3, "{attempts:?}");
+            assert!(matches!(*last, RpcHandlerError::AllEndpointsFailed), "got {last:?}");
+        }
+        other => panic!("expected HoldExpired, got {other:?}"),
+    }
+    assert!(matches!(next_hold_event(&mut events).await, HandlerEvent::RequestHeld { holding: 1, .. }));
+    assert!(matches!(next_hold_event(&mut events).await, HandlerEvent::HoldReleased { holding: 0, recovered: false, .. }));
+
+    // A held call that is dropped stops counting as held
+    let call = tokio::spawn({
+        let handler = handler.clone();
+        async move { handler.try_proxy_request_with(block_number(), held(5000)).await }
+    });
+    assert!(matches!(next_hold_event(&mut events).await, HandlerEvent::RequestHeld { holding: 1, .. }));
+    call.abort();
+    assert!(matches!(next_hold_event(&mut events).await, HandlerEvent::HoldReleased { holding: 0, recovered: false, .. }));
+    assert_eq!(handler.holding_requests(), 0);
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use std::collections::HashMap;
+#[cfg(feature = "consensus")]
+use std::sync::Arc;
+#[cfg(feature = "consensus")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "consensus")]
+use common::*;
+use ez_web3_rpc::provider::HostLimiter;
+use ez_web3_rpc::*;
+#[cfg(feature = "consensus")]
+use serde_json::{json, Value};
+#[cfg(feature = "consensus")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Concurrent requests seen across every stub sharing it, and the most seen at once.
+#[cfg(feature = "consensus")]
+#[derive(Default)]
+struct Concurrency {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+#[cfg(feature = "consensus")]
+impl Concurrency {
+    fn enter(&self) {
+        let now = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+    }
+
+    fn leave(&self) {
+        self.current.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+/// A probe-passing endpoint on `127.0.0.1` that answers after `delay` and counts requests in flight.
+#[cfg(feature = "consensus")]
+async fn serve_counting(concurrency: Arc<Concurrency>, delay: Duration) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let concurrency = Arc::clone(&concurrency);
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let body = loop {
+                    let Ok(read) = stream.read(&mut chunk).await else { return };
+                    if read == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&chunk[..read]);
+                    if let Some(header_end) = request.windows(4).position(|w| w == b"\r\n\r\n")
+                        && let Ok(body) = serde_json::from_slice::<Value>(&request[header_end + 4..])
+                    {
+                        break body;
+                    }
+                };
+
+                concurrency.enter();
+                let result = match body["method"].as_str() {
+                    Some("eth_getBlockByNumber") => json!({ "number": "0x10", "hash": "0xabc" }),
+                    Some("eth_getCode") => json!(PERMIT2_CODE),
+                    _ => json!("0x10"),
+                };
+                tokio::time::sleep(delay).await;
+                let payload = rpc_response(1, result).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{payload}",
+                    payload.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                concurrency.leave();
+            });
+        }
+    });
+
+    url
+}
+
+#[cfg(feature = "consensus")]
+fn rpc_at(url: &str) -> Rpc {
+    Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_consensus_proxy_and_refresh_share_the_host_cap() {
+    let concurrency = Arc::new(Concurrency::default());
+    let first = serve_counting(Arc::clone(&concurrency), Duration::from_millis(100)).await;
+    let second = serve_counting(Arc::clone(&concurrency), Duration::from_millis(100)).await;
+
+    let settings = HandlerSettings {
+        host_limits: HostLimits { default_per_host: Some(2), per_host: HashMap::new(), rate_limit_headers: None },
+        ..settings(vec![rpc_at(&first), rpc_at(&second)])
+    };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+    let calls = RpcCalls::new(Arc::clone(&handler));
+    let options = Some(ConsensusOptions { per_host_concurrency: Some(2), ..ConsensusOptions::default() });
+
+    let request = block_number();
+    let (consensus, proxied, refreshed) = tokio::join!(
+        calls.consensus_with_report::<String>(&request, 0.5, options),
+        handler.try_proxy_request(block_number()),
+        handler.refresh(),
+    );
+
+    assert!(concurrency.peak() <= 2, "{} requests reached the host at once", concurrency.peak());
+    assert_eq!(proxied.unwrap().result, Some(json!("0x10")));
+    refreshed.unwrap();
+    // Whatever the proxy and probes left free, consensus either answered or sat the host out
+    let (result, report) = consensus;
+    assert!(
+        result.is_ok() || report.outcomes.values().any(|o| *o == EndpointOutcome::Saturated),
+        "{report}"
+    );
+    assert!(handler.health_report().await.host_in_flight.values().all(|n| *n == 0));
+}
+
+#[tokio::test]
+async fn test_saturated_host_is_reported_in_flight() {
+    let limiter = HostLimiter::new(HostLimits {
+        default_per_host: Some(2),
+        per_host: HashMap::from([("tiny.example".to_string(), 0)]),
+        rate_limit_headers: None,
+    });
+    let url = "http://127.0.0.1:8545/";
+
+    let held = [limiter.try_acquire(url).unwrap(), limiter.try_acquire("http://127.0.0.1:8546/").unwrap()];
+    assert!(limiter.try_acquire(url).is_none(), "a third request to the host is refused");
+    assert_eq!(limiter.in_flight().get("127.0.0.1"), Some(&2));
+    assert!(limiter.try_acquire("http://other.example/").is_some(), "other hosts have their own cap");
+    assert_eq!(limiter.cap_for("tiny.example"), Some(1), "a zero cap still lets one request through");
+
+    drop(held);
+    assert_eq!(limiter.in_flight().get("127.0.0.1"), Some(&0));
+    let waited = tokio::time::timeout(Duration::from_millis(100), limiter.acquire(url)).await;
+    assert!(waited.is_ok());
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use tokio::sync::broadcast;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+fn policy(max_attempts: u32, allow_unprobed_fallback: bool) -> InitPolicy {
+    InitPolicy { max_attempts, attempt_backoff_ms: 50, allow_unprobed_fallback }
+}
+
+/// Answers every request with `503`.
+async fn dead() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(503)).mount(&server).await;
+    server
+}
+
+async fn healthy(server: &MockServer) {
+    mount_probe(server, "0x20", Duration::ZERO).await;
+    mount_method(server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x20")))).await;
+}
+
+async fn next_event(events: &mut broadcast::Receiver<HandlerEvent>, matches: impl Fn(&HandlerEvent) -> bool) -> HandlerEvent {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let event = events.recv().await.unwrap();
+            if matches(&event) {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("event not emitted in time")
+}
+
+#[tokio::test]
+async fn test_init_retries_a_failed_probe_round() {
+    let flaky = MockServer::start().await;
+    // The first round's block and code probes fail, everything after succeeds
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(503)).up_to_n_times(2).mount(&flaky).await;
+    healthy(&flaky).await;
+
+    let once = RpcHandler::new(config(settings(vec![mk_rpc(&flaky)])), None).await.unwrap();
+    assert!(matches!(once.init().await, Err(RpcHandlerError::NoAvailableRpcs { .. })));
+    assert_eq!(once.init_state(), InitState::Starting);
+
+    flaky.reset().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(503)).up_to_n_times(2).mount(&flaky).await;
+    healthy(&flaky).await;
+    let handler_settings = HandlerSettings { init_policy: Some(policy(3, false)), ..settings(vec![mk_rpc(&flaky)]) };
+    let handler = RpcHandler::new(config(handler_settings), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.init_state(), InitState::Final { url: url_key(&flaky) });
+    assert_eq!(count_method(&flaky, "eth_getBlockByNumber").await, 2);
+    assert!(!handler.readiness().degraded);
+
+    let described = handler.effective_policy().init.unwrap();
+    assert_eq!(described.backoff_ms, [50, 100]);
+}
+
+#[tokio::test]
+async fn test_unprobed_fallback_degrades_then_recovers() {
+    let primary = dead().await;
+    // Answers calls, but its probes fail the Permit2 check
+    let backup = MockServer::start().await;
+    mount_method(&backup, "eth_getBlockByNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x20", "hash": "0xabc" })))).await;
+    mount_method(&backup, "eth_getCode", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x")))).await;
+    mount_method(&backup, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x20")))).await;
+    let rpcs = vec![tiered(&backup, 2), tiered(&primary, 1)];
+
+    let without_fallback = RpcHandler::new(config(HandlerSettings { init_policy: Some(policy(2, false)), ..settings(rpcs.clone()) }), None).await.unwrap();
+    assert!(matches!(without_fallback.init().await, Err(RpcHandlerError::NoAvailableRpcs { .. })));
+
+    let handler = RpcHandler::new(config(HandlerSettings { init_policy: Some(policy(2, true)), ..settings(rpcs) }), None).await.unwrap();
+    let mut events = handler.subscribe();
+    handler.init().await.unwrap();
+    assert_eq!(handler.init_state(), InitState::Degraded { url: url_key(&primary) });
+    assert_eq!(next_event(&mut events, |event| matches!(event, HandlerEvent::Degraded { .. })).await, HandlerEvent::Degraded { url: url_key(&primary), attempts: 2 });
+    let readiness = handler.readiness();
+    assert!(readiness.degraded);
+    assert_eq!(readiness.active_url, Some(url_key(&primary)));
+
+    // The unprobed order is walked past the dead primary
+    let (response, served_by) = handler.try_proxy_request_attributed(block_number()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x20")));
+    assert_eq!(served_by, url_key(&backup));
+
+    primary.reset().await;
+    healthy(&primary).await;
+    let recovered = next_event(&mut events, |event| matches!(event, HandlerEvent::Recovered { .. })).await;
+    assert_eq!(recovered, HandlerEvent::Recovered { from: url_key(&primary), to: url_key(&primary) });
+    assert_eq!(handler.init_state(), InitState::Final { url: url_key(&primary) });
+    assert!(!handler.readiness().degraded);
+
+    // The recovery loop retires once it's done, rather than showing as stopped
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let liveness = handler.liveness();
+    assert!(liveness.live, "{liveness:?}");
+    assert!(liveness.tasks.iter().all(|task| task.task != BackgroundTask::Recovery));
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Number, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, Respond, ResponseTemplate};
+
+fn block_number(id: Option<JsonRpcId>) -> JsonRpcRequest {
+    JsonRpcRequest { id, ..JsonRpcRequest::new("eth_blockNumber", json!([])) }
+}
+
+/// Answers `eth_blockNumber`, single or batched, echoing each request's id as it was sent.
+struct Echo;
+
+impl Respond for Echo {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let answer = |entry: &Value| json!({ "jsonrpc": "2.0", "id": entry["id"], "result": "0x20" });
+        match serde_json::from_slice::<Value>(&request.body).unwrap() {
+            Value::Array(entries) => ResponseTemplate::new(200).set_body_json(entries.iter().map(answer).collect::<Vec<_>>()),
+            entry => ResponseTemplate::new(200).set_body_json(answer(&entry)),
+        }
+    }
+}
+
+async fn echoing() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x20", Duration::ZERO).await;
+    Mock::given(method("POST")).respond_with(Echo).mount(&server).await;
+    server
+}
+
+async fn handler(servers: &[&MockServer]) -> std::sync::Arc<RpcHandler> {
+    init_handler(settings(servers.iter().map(|server| mk_rpc(server)).collect())).await
+}
+
+#[test]
+fn test_ids_keep_their_json_type() {
+    for (id, wire) in [(JsonRpcId::from(7), json!(7)), (JsonRpcId::Number((-1).into()), json!(-1)), (JsonRpcId::Number(Number::from_f64(1.5).unwrap()), json!(1.5)), (JsonRpcId::from("req-7"), json!("req-7")), (JsonRpcId::Null, Value::Null)] {
+        let request = serde_json::to_value(block_number(Some(id.clone()))).unwrap();
+        assert_eq!(request["id"], wire);
+        assert_eq!(serde_json::from_value::<JsonRpcRequest>(request).unwrap().id, Some(id));
+    }
+    // Left out, the request is a notification, and isn't read back as a null id
+    let notification = serde_json::to_value(block_number(None)).unwrap();
+    assert!(notification.get("id").is_none());
+    assert_eq!(serde_json::from_value::<JsonRpcRequest>(notification).unwrap().id, None);
+
+    let response: JsonRpcResponse<Value> = serde_json::from_value(json!({ "jsonrpc": "2.0", "id": "abc", "result": "0x1" })).unwrap();
+    assert_eq!(response.id, Some(JsonRpcId::String("abc".into())));
+    assert_eq!(JsonRpcId::from("1").to_string(), "\"1\"");
+    assert_ne!(JsonRpcId::from("1"), JsonRpcId::from(1));
+}
+
+#[test]
+fn test_numeric_ids_read_back_as_u64() {
+    assert_eq!(block_number(Some(7.into())).numeric_id(), Some(7));
+    for id in [Some(JsonRpcId::from("7")), Some(JsonRpcId::Null), Some(JsonRpcId::Number((-1).into())), Some(JsonRpcId::Number(Number::from_f64(1.0).unwrap())), None] {
+        assert_eq!(block_number(id.clone()).numeric_id(), None, "{id:?}");
+    }
+    let response: JsonRpcResponse<Value> = serde_json::from_value(json!({ "jsonrpc": "2.0", "id": 7, "result": "0x1" })).unwrap();
+    assert_eq!(response.numeric_id(), Some(7));
+}
+
+#[test]
+fn test_ids_that_are_not_numbers_strings_or_null_are_refused() {
+    for id in [json!(true), json!({ "id": 1 }), json!([1])] {
+        let response = json!({ "jsonrpc": "2.0", "id": id, "result": "0x1" });
+        assert!(serde_json::from_value::<JsonRpcResponse<Value>>(response).is_err(), "{id}");
+    }
+}
+
+#[tokio::test]
+async fn test_string_and_null_ids_come_back_through_the_handler() {
+    let server = echoing().await;
+    let handler = handler(&[&server]).await;
+
+    for id in [JsonRpcId::from("dapp-42"), JsonRpcId::Null] {
+        let response = handler.try_proxy_request(block_number(Some(id.clone()))).await.unwrap();
+        assert_eq!((response.id, response.result), (Some(id), Some(json!("0x20"))));
+    }
+
+    let batch = vec![block_number(Some("first".into())), block_number(Some(2.into())), block_number(Some(JsonRpcId::Null))];
+    let entries = handler.try_proxy_batch(&batch, None).await.unwrap();
+    let ids: Vec<Option<JsonRpcId>> = entries.into_iter().map(|entry| entry.response.unwrap().id).collect();
+    assert_eq!(ids, batch.iter().map(|request| request.id.clone()).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_consensus_accepts_string_ids() {
+    let (a, b) = (echoing().await, echoing().await);
+    let calls = RpcCalls::new(handler(&[&a, &b]).await);
+    let head: String = calls.consensus(&block_number(Some("consensus-1".into())), 0.66, None).await.unwrap();
+    assert_eq!(head, "0x20");
+}
+
+#[test]
+fn test_new_requests_get_unique_ids_across_threads() {
+    let threads: Vec<_> = (0..8)
+        .map(|_| std::thread::spawn(|| (0..500).map(|_| JsonRpcRequest::new("eth_blockNumber", json!([]))).collect::<Vec<_>>()))
+        .collect();
+    let requests: Vec<JsonRpcRequest> = threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect();
+    let ids: std::collections::HashSet<JsonRpcId> = requests.iter().map(|request| request.id.clone().unwrap()).collect();
+    assert_eq!(ids.len(), requests.len());
+    assert!(ids.iter().all(|id| matches!(id, JsonRpcId::Number(_))));
+    assert!(requests.iter().all(|request| request.jsonrpc == "2.0" && request.method == "eth_blockNumber"));
+
+    let named = JsonRpcRequest::new("eth_chainId", json!([])).with_id("chain");
+    assert_eq!(named.id, Some(JsonRpcId::String("chain".into())));
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::config::KeepaliveConfig;
+use ez_web3_rpc::keepalive::next_ping_delay;
+use ez_web3_rpc::*;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use wiremock::{MockServer, ResponseTemplate};
+
+/// Connections idle longer than this are closed by the proxy, like a NAT or load balancer would.
+const PROXY_IDLE_CUTOFF: Duration = Duration::from_millis(400);
+
+/// A TCP proxy in front of a mock server that counts accepted connections and drops idle ones.
+struct ConnectionCountingProxy {
+    url: url::Url,
+    connections: Arc<AtomicUsize>,
+}
+
+impl ConnectionCountingProxy {
+    async fn start(upstream: &MockServer) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap()).parse().unwrap();
+        let upstream_addr = *upstream.address();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&connections);
+        tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let server = TcpStream::connect(upstream_addr).await.unwrap();
+                tokio::spawn(pipe_until_idle(client, server));
+            }
+        });
+
+        Self { url, connections }
+    }
+
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+async fn pipe_until_idle(mut client: TcpStream, mut server: TcpStream) {
+    let (mut client_buf, mut server_buf) = (vec![0u8; 16 * 1024], vec![0u8; 16 * 1024]);
+    loop {
+        tokio::select! {
+            read = client.read(&mut client_buf) => match read {
+                Ok(0) | Err(_) => return,
+                Ok(n) => if server.write_all(&client_buf[..n]).await.is_err() { return },
+            },
+            read = server.read(&mut server_buf) => match read {
+                Ok(0) | Err(_) => return,
+                Ok(n) => if client.write_all(&server_buf[..n]).await.is_err() { return },
+            },
+            _ = tokio::time::sleep(PROXY_IDLE_CUTOFF) => return,
+        }
+    }
+}
+
+fn chain_id_request() -> JsonRpcRequest {
+    request("eth_chainId", json!([]))
+}
+
+fn rpc_at(url: &url::Url) -> Rpc {
+    Rpc { url: url.clone(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+fn keepalive(after_ms: u64, interval_ms: u64) -> Option<KeepaliveSettings> {
+    Some(KeepaliveSettings { keepalive_after_ms: after_ms, keepalive_interval_ms: interval_ms })
+}
+
+async fn chain_id_backend(delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", delay).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    server
+}
+
+#[test]
+fn test_next_ping_delay() {
+    let config = KeepaliveConfig { after: Duration::from_secs(60), interval: Duration::from_secs(20) };
+
+    assert_eq!(next_ping_delay(Duration::from_secs(10), None, &config), Some(Duration::from_secs(50)));
+    assert_eq!(next_ping_delay(Duration::from_secs(60), None, &config), None);
+    assert_eq!(next_ping_delay(Duration::from_secs(75), Some(Duration::from_secs(5)), &config), Some(Duration::from_secs(15)));
+    assert_eq!(next_ping_delay(Duration::from_secs(90), Some(Duration::from_secs(20)), &config), None);
+}
+
+#[tokio::test]
+async fn test_idle_keepalive_avoids_reconnect() {
+    let active = chain_id_backend(Duration::ZERO).await;
+    let standby = chain_id_backend(Duration::from_millis(100)).await;
+    let proxy = ConnectionCountingProxy::start(&active).await;
+
+    let mut settings = settings(vec![rpc_at(&proxy.url), mk_rpc(&standby)]);
+    settings.keepalive = keepalive(150, 150);
+    let handler = RpcHandler::new(config(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), proxy.url.to_string());
+
+    handler.try_proxy_request(chain_id_request()).await.unwrap();
+    let connections_before_idle = proxy.connections();
+    let calls_before_idle = count_method(&active, "eth_chainId").await;
+    let standby_before_idle = count_method(&standby, "eth_chainId").await;
+
+    // Idle for well past the proxy's cutoff
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    let pings = count_method(&active, "eth_chainId").await - calls_before_idle;
+    assert!((4..=9).contains(&pings), "expected a ping roughly every 150ms after 150ms idle, got {pings}");
+    assert_eq!(count_method(&standby, "eth_chainId").await, standby_before_idle, "only the active provider is pinged");
+
+    handler.try_proxy_request(chain_id_request()).await.unwrap();
+    assert_eq!(proxy.connections(), connections_before_idle, "keepalive should have kept the connection open");
+
+    handler.shutdown();
+    let calls_at_shutdown = count_method(&active, "eth_chainId").await;
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(count_method(&active, "eth_chainId").await, calls_at_shutdown);
+}
+
+#[tokio::test]
+async fn test_idle_without_keepalive_reconnects() {
+    let active = chain_id_backend(Duration::ZERO).await;
+    let proxy = ConnectionCountingProxy::start(&active).await;
+
+    let handler = RpcHandler::new(config(settings(vec![rpc_at(&proxy.url)])), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+
+    handler.try_proxy_request(chain_id_request()).await.unwrap();
+    let connections_before_idle = proxy.connections();
+
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    handler.try_proxy_request(chain_id_request()).await.unwrap();
+    assert!(proxy.connections() > connections_before_idle);
+    assert_eq!(count_method(&active, "eth_chainId").await, 2, "no pings when keepalive is off");
+}
+
+#[tokio::test]
+async fn test_busy_provider_is_not_pinged() {
+    let active = chain_id_backend(Duration::ZERO).await;
+    let mut settings = settings(vec![mk_rpc(&active)]);
+    settings.keepalive = keepalive(200, 100);
+    let handler = RpcHandler::new(config(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+
+    for _ in 0..12 {
+        handler.try_proxy_request(chain_id_request()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(count_method(&active, "eth_chainId").await, 12);
+    handler.shutdown();
+}
+
+#[tokio::test]
+async fn test_keepalive_failures_feed_failure_count() {
+    let active = MockServer::start().await;
+    mount_probe(&active, "0x10", Duration::ZERO).await;
+    mount_method(&active, "eth_chainId", ResponseTemplate::new(503)).await;
+
+    let mut settings = settings(vec![mk_rpc(&active)]);
+    settings.keepalive = keepalive(50, 50);
+    let handler = RpcHandler::new(config(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    handler.shutdown();
+
+    let report = handler.health_report().await;
+    assert!(report.endpoints[0].consecutive_failures >= 2);
+    assert!(report.to_string().contains("failed pings"));
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+const SLOW: Duration = Duration::from_millis(250);
+
+/// Probes after `probe_delay`, and answers everything else with `0x5`: slowly once it has
+/// answered `fast_for` requests, and always slowly for the methods in `slow_methods`.
+async fn endpoint(probe_delay: Duration, fast_for: usize, slow_methods: &'static [&'static str]) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", probe_delay).await;
+    let answered = AtomicUsize::new(0);
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let slow = slow_methods.contains(&body["method"].as_str().unwrap()) || answered.fetch_add(1, Ordering::SeqCst) >= fast_for;
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x5"))).set_delay(if slow { SLOW } else { Duration::ZERO })
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+fn slo() -> LatencySlo {
+    LatencySlo { target_ms: 100, violation_window_ms: 5_000, max_violations: 3, penalty_ms: 60_000, ignore_methods: vec!["eth_call".to_string()], measure: SloMeasure::Service }
+}
+
+/// The primary probes faster, so it starts as the active provider.
+async fn handler(primary: &MockServer, fallback: &MockServer) -> Arc<RpcHandler> {
+    let settings = HandlerSettings { latency_slo: Some(slo()), ..settings(vec![mk_rpc(primary), mk_rpc(fallback)]) };
+    let handler = init_handler(settings).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(primary));
+    handler
+}
+
+fn breaches(events: &mut broadcast::Receiver<HandlerEvent>) -> Vec<(String, usize, Option<String>)> {
+    std::iter::from_fn(|| events.try_recv().ok())
+        .filter_map(|event| match event {
+            HandlerEvent::LatencySloBreached { url, violations, replacement } => Some((url, violations, replacement)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_a_provider_that_turns_slow_is_replaced_without_failing_a_request() {
+    let primary = endpoint(Duration::ZERO, 5, &[]).await;
+    let fallback = endpoint(Duration::from_millis(60), usize::MAX, &[]).await;
+    let handler = handler(&primary, &fallback).await;
+    let mut events = handler.subscribe();
+
+    let mut served = Vec::new();
+    for _ in 0..12 {
+        let started = Instant::now();
+        let (response, url) = handler.try_proxy_request_attributed(request("eth_getBalance", json!([]))).await.unwrap();
+        assert_eq!(response.result, Some(json!("0x5")));
+        served.push((url, started.elapsed()));
+    }
+
+    // Five fast answers, then three over the budget breach it, and the rest go to the fallback
+    assert!(served[..8].iter().all(|(url, _)| *url == url_key(&primary)), "{served:?}");
+    assert!(served[8..].iter().all(|(url, latency)| *url == url_key(&fallback) && *latency < SLOW), "{served:?}");
+    assert_eq!(count_method(&primary, "eth_getBalance").await, 8, "the penalized endpoint sits out the race");
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while handler.get_provider_url().await.unwrap() != url_key(&fallback) && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&fallback));
+    assert_eq!(breaches(&mut events), [(url_key(&primary), 3, Some(url_key(&fallback)))]);
+
+    // Still a fallback of last resort, in a batch of its own
+    let plan = handler.plan_request(&request("eth_getBalance", json!([])), None).await.unwrap();
+    assert_eq!(plan.batches(), [vec![url_key(&fallback)], vec![url_key(&primary)]]);
+    assert!(matches!(plan.urls[1].placement, Placement::SloPenalized { remaining_ms } if remaining_ms > 0));
+}
+
+#[tokio::test]
+async fn test_heavy_and_ignored_methods_never_count() {
+    let primary = endpoint(Duration::ZERO, usize::MAX, &["eth_getLogs", "eth_call"]).await;
+    let fallback = endpoint(Duration::from_millis(60), usize::MAX, &[]).await;
+    let handler = handler(&primary, &fallback).await;
+    let mut events = handler.subscribe();
+
+    for rpc_method in ["eth_getLogs", "eth_call"] {
+        for _ in 0..4 {
+            let started = Instant::now();
+            let (_, url) = handler.try_proxy_request_attributed(request(rpc_method, json!([]))).await.unwrap();
+            assert!(url == url_key(&primary) && started.elapsed() >= SLOW);
+        }
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert!(breaches(&mut events).is_empty());
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&primary));
+    let plan = handler.plan_request(&request("eth_getBalance", json!([])), None).await.unwrap();
+    assert!(plan.urls.iter().all(|planned| !matches!(planned.placement, Placement::SloPenalized { .. })));
+    assert_eq!(handler.effective_policy().latency_slo.unwrap().ignore_methods, ["eth_call"]);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::{net::TcpListener, time::Duration};
+
+use common::*;
+use ez_web3_rpc::{localnet::parse_local_nodes, *};
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// A loopback address nothing listens on.
+fn dead_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+/// A mock standing in for anvil: `chain_id`, a head, and no Permit2 deployed.
+async fn local_node(chain_id: &str) -> MockServer {
+    let server = MockServer::start().await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(chain_id)))).await;
+    mount_method(&server, "eth_getBlockByNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x3", "hash": "0xabc" })))).await;
+    mount_method(&server, "eth_getCode", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x")))).await;
+    server
+}
+
+#[tokio::test]
+async fn test_the_first_answering_node_is_adopted_with_its_chain_id() {
+    let (anvil, other) = (local_node("0x7a69").await, local_node("0x1").await);
+    let options = LocalnetOptions { urls: vec![dead_address(), anvil.uri(), other.uri()], probe_timeout_ms: 300 };
+
+    let config = HandlerConfig::localnet_with(&options).await.unwrap();
+    assert_eq!(config.network_id, 31337);
+    let settings = config.settings.clone().unwrap();
+    assert!(settings.localnet);
+    assert_eq!(settings.network_rpcs.len(), 1);
+
+    let handler = RpcHandler::new(config, None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&anvil));
+    assert_eq!(handler.health_report().await.endpoints.len(), 1);
+    // Probing skips the Permit2 bytecode a local chain doesn't have
+    assert_eq!(count_method(&anvil, "eth_getCode").await, 0);
+    let response = handler.try_proxy_request(request("eth_chainId", json!([]))).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x7a69")));
+}
+
+#[tokio::test]
+async fn test_nothing_listening_names_every_address_tried() {
+    let tried = vec![dead_address(), dead_address()];
+    let options = LocalnetOptions { urls: tried.clone(), probe_timeout_ms: 300 };
+
+    let err = HandlerConfig::localnet_with(&options).await.unwrap_err();
+    assert!(matches!(&err, RpcHandlerError::NoLocalNodeFound { tried: named } if *named == tried), "{err:?}");
+    assert!(tried.iter().all(|url| err.to_string().contains(url.as_str())), "{err}");
+}
+
+#[tokio::test]
+async fn test_a_slow_node_is_not_waited_for() {
+    let slow = MockServer::start().await;
+    mount_method(&slow, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x7a69"))).set_delay(Duration::from_millis(500))).await;
+    let options = LocalnetOptions { urls: vec![slow.uri()], probe_timeout_ms: 100 };
+    assert!(matches!(HandlerConfig::localnet_with(&options).await, Err(RpcHandlerError::NoLocalNodeFound { .. })));
+}
+
+#[test]
+fn test_override_list_comes_before_the_defaults() {
+    assert_eq!(parse_local_nodes(" http://127.0.0.1:9545, ,http://devnet:8545 "), ["http://127.0.0.1:9545", "http://devnet:8545"]);
+    let defaults = LocalnetOptions::default().urls;
+    assert!(defaults.ends_with(&localnet::DEFAULT_LOCAL_NODES.iter().map(|url| url.to_string()).collect::<Vec<_>>()));
+}
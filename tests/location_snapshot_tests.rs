@@ -0,0 +1,152 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+#[cfg(feature = "persistence")]
+use std::path::PathBuf;
+
+use common::*;
+#[cfg(feature = "persistence")]
+use ez_web3_rpc::location::DEFAULT_SNAPSHOT_TTL;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+const OFFICE: &str = "10.20.30.1";
+#[cfg(feature = "persistence")]
+const HOME: &str = "192.168.1.1";
+
+/// A location the test moves between.
+struct StubLocation(parking_lot::Mutex<&'static str>);
+
+#[cfg(feature = "persistence")]
+impl StubLocation {
+    fn move_to(&self, fingerprint: &'static str) {
+        *self.0.lock() = fingerprint;
+    }
+}
+
+impl LocationProvider for StubLocation {
+    fn fingerprint(&self) -> Option<String> {
+        Some(self.0.lock().to_string())
+    }
+}
+
+#[cfg(feature = "persistence")]
+fn scratch_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ez_web3_rpc-{name}-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// A probe-passing endpoint answering after `delay` milliseconds, which the test changes as it moves.
+async fn endpoint(delay: Arc<AtomicU64>) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let result = match body["method"].as_str().unwrap() {
+                "eth_getCode" => json!(PERMIT2_CODE),
+                _ => json!({ "number": "0x10", "hash": "0xabc" }),
+            };
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, result)).set_delay(Duration::from_millis(delay.load(Ordering::SeqCst)))
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+#[cfg(feature = "persistence")]
+async fn probes(servers: &[&MockServer]) -> usize {
+    let mut total = 0;
+    for server in servers {
+        total += count_method(server, "eth_getCode").await;
+    }
+    total
+}
+
+#[cfg(feature = "persistence")]
+#[tokio::test]
+async fn test_snapshots_are_only_restored_where_they_were_taken() {
+    let (delay_a, delay_b) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(80)));
+    let (a, b) = (endpoint(Arc::clone(&delay_a)).await, endpoint(Arc::clone(&delay_b)).await);
+    let path = scratch_file("latency-snapshots");
+    let store = Arc::new(FileLatencyStore::open(&path, DEFAULT_SNAPSHOT_TTL).unwrap());
+    let location = Arc::new(StubLocation(parking_lot::Mutex::new(OFFICE)));
+    let components = HandlerComponents {
+        latency_store: Some(store.clone()),
+        location_provider: Some(location.clone()),
+        ..HandlerComponents::default()
+    };
+    let handler = || async {
+        let handler = RpcHandler::with_components(config(settings(vec![mk_rpc(&a), mk_rpc(&b)])), None, components.clone()).await.unwrap();
+        handler.init().await.unwrap();
+        handler
+    };
+
+    // The first start at the office probes and records what it measured
+    let first = handler().await;
+    assert_eq!(first.get_provider_url().await.unwrap(), url_key(&a));
+    let office_key = first.location_key().unwrap();
+
+    // A restart there serves from the snapshot without probing
+    let probed = probes(&[&a, &b]).await;
+    let restarted = handler().await;
+    assert_eq!(restarted.get_provider_url().await.unwrap(), url_key(&a));
+    assert_eq!(restarted.get_latencies().await, first.get_latencies().await);
+    assert_eq!(probes(&[&a, &b]).await, probed, "restored, not probed");
+    let mut events = restarted.subscribe();
+
+    // At home the other endpoint is nearer, and the office snapshot is ignored
+    location.move_to(HOME);
+    delay_a.store(80, Ordering::SeqCst);
+    delay_b.store(0, Ordering::SeqCst);
+    assert!(restarted.location_changed().await.unwrap());
+    assert!(probes(&[&a, &b]).await > probed, "probed afresh");
+    assert_eq!(restarted.get_provider_url().await.unwrap(), url_key(&b));
+    let home_key = restarted.location_key().unwrap();
+    assert_ne!(home_key, office_key);
+    assert!(!restarted.location_changed().await.unwrap(), "same place, nothing to do");
+
+    // Back at the office, its snapshot wins even though a probe from here would disagree
+    location.move_to(OFFICE);
+    let probed = probes(&[&a, &b]).await;
+    assert!(restarted.location_changed().await.unwrap());
+    assert_eq!(restarted.get_provider_url().await.unwrap(), url_key(&a));
+    assert_eq!(probes(&[&a, &b]).await, probed);
+
+    let changes: Vec<HandlerEvent> = std::iter::from_fn(|| events.try_recv().ok()).filter(|event| matches!(event, HandlerEvent::LocationChanged { .. })).collect();
+    assert_eq!(changes, [
+        HandlerEvent::LocationChanged { from: Some(office_key.clone()), to: Some(home_key.clone()), restored: false },
+        HandlerEvent::LocationChanged { from: Some(home_key), to: Some(office_key), restored: true },
+    ]);
+
+    // Only hashes reach the file
+    let persisted = std::fs::read_to_string(&path).unwrap();
+    assert!(!persisted.contains(OFFICE) && !persisted.contains(HOME));
+    let reopened = FileLatencyStore::open(&path, DEFAULT_SNAPSHOT_TTL).unwrap();
+    let home_snapshot = reopened.load(TEST_NETWORK_ID, &restarted.location_key().unwrap(), std::time::SystemTime::now()).unwrap();
+    assert_eq!(home_snapshot.latencies.len(), 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_expired_snapshots_are_probed_over() {
+    let server = endpoint(Arc::new(AtomicU64::new(0))).await;
+    let store = Arc::new(MemoryLatencyStore::new(Duration::ZERO));
+    let components = HandlerComponents {
+        latency_store: Some(store.clone()),
+        location_provider: Some(Arc::new(StubLocation(parking_lot::Mutex::new(OFFICE)))),
+        ..HandlerComponents::default()
+    };
+    for _ in 0..2 {
+        let handler = RpcHandler::with_components(config(settings(vec![mk_rpc(&server)])), None, components.clone()).await.unwrap();
+        handler.init().await.unwrap();
+    }
+    assert_eq!(count_method(&server, "eth_getCode").await, 2, "every start probed");
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveTime, Weekday};
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+use common::*;
+use ez_web3_rpc::maintenance::MaintenanceSchedule;
+use ez_web3_rpc::*;
+use serde_json::json;
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+use wiremock::{MockServer, ResponseTemplate};
+
+/// `YYYY-MM-DD HH:MM` UTC.
+fn utc(date: &str, time: &str) -> SystemTime {
+    let date: NaiveDate = date.parse().unwrap();
+    date.and_time(time.parse().unwrap()).and_utc().into()
+}
+
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+async fn endpoint(probe_delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", probe_delay).await;
+    mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10")))).await;
+    server
+}
+
+#[test]
+fn test_windows_resolve_to_the_occurrence_in_progress_or_next() {
+    // 2024-01-07 is a Sunday
+    let weekly: MaintenanceWindow =
+        serde_json::from_value(json!({ "repeat": "weekly", "weekday": "Sun", "start": "02:00", "duration_minutes": 60 })).unwrap();
+    assert_eq!(weekly, MaintenanceWindow::Weekly { weekday: Weekday::Sun, start: NaiveTime::from_hms_opt(2, 0, 0).unwrap(), duration_minutes: 60 });
+    assert_eq!(weekly.occurrence(utc("2024-01-06", "23:00")), Some((utc("2024-01-07", "02:00"), utc("2024-01-07", "03:00"))));
+    assert!(weekly.active_at(utc("2024-01-07", "02:30")));
+    assert_eq!(weekly.occurrence(utc("2024-01-07", "03:00")), Some((utc("2024-01-14", "02:00"), utc("2024-01-14", "03:00"))));
+
+    // A daily window running over midnight is still in progress the next morning
+    let daily = MaintenanceWindow::Daily { start: NaiveTime::from_hms_opt(23, 30, 0).unwrap(), duration_minutes: 90 };
+    assert_eq!(daily.occurrence(utc("2024-01-08", "00:30")), Some((utc("2024-01-07", "23:30"), utc("2024-01-08", "01:00"))));
+    assert!(!daily.active_at(utc("2024-01-08", "01:00")));
+
+    let start = utc("2024-01-07", "12:00");
+    let once = MaintenanceWindow::Once { start_unix_secs: start.duration_since(UNIX_EPOCH).unwrap().as_secs(), duration_minutes: 30 };
+    assert!(once.active_at(start));
+    assert_eq!(once.occurrence(start + Duration::from_secs(30 * 60)), None, "a one-off window doesn't come back");
+
+    // Windows from settings are keyed like the endpoints they name
+    let schedule = MaintenanceSchedule::new(&HashMap::new(), &HashMap::from([("https://node.example".to_string(), vec![weekly.clone(), daily])]));
+    let url = "https://node.example/";
+    let now = utc("2024-01-07", "02:30");
+    assert_eq!(schedule.in_window_until(url, now), Some(utc("2024-01-07", "03:00")));
+    assert_eq!(schedule.next_start(url, now), Some(utc("2024-01-07", "23:30")), "looks past the window in progress");
+    assert!(!schedule.unavailable_within(url, utc("2024-01-07", "22:00"), Duration::from_secs(60 * 60)));
+    assert!(schedule.unavailable_within(url, utc("2024-01-07", "22:31"), Duration::from_secs(60 * 60)));
+    assert_eq!(schedule.in_window(now).into_iter().collect::<Vec<_>>(), vec![url.to_string()]);
+}
+
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+#[tokio::test]
+async fn test_endpoint_is_stepped_around_for_its_window() {
+    let (fast, slow, slowest) = (endpoint(Duration::ZERO).await, endpoint(Duration::from_millis(30)).await, endpoint(Duration::from_millis(60)).await);
+    let clock = MockClock::new();
+    let window_start = clock.now_system().duration_since(UNIX_EPOCH).unwrap().as_secs() + 300;
+
+    let window = MaintenanceWindow::Once { start_unix_secs: window_start, duration_minutes: 10 };
+    let rpcs = vec![RpcConfig { maintenance_windows: Some(vec![window]), ..mk_rpc(&fast).into() }, mk_rpc(&slow).into(), mk_rpc(&slowest).into()];
+    let settings = HandlerSettings { maintenance_lead_ms: 60_000, ..settings(rpcs) };
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), Some(Strategy::Fastest), components).await.unwrap();
+    handler.init().await.unwrap();
+    let mut events = handler.subscribe();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&fast));
+
+    // Well before the lead the fast endpoint stays active
+    for _ in 0..3 {
+        clock.wait_for_sleepers(1).await;
+        clock.advance(Duration::from_secs(60));
+    }
+    clock.wait_for_sleepers(1).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&fast));
+
+    // A minute before the window opens the active provider moves off it
+    clock.advance(Duration::from_secs(60));
+    clock.wait_for_sleepers(1).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&slow));
+    let failovers: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+        .filter_map(|event| match event {
+            HandlerEvent::MaintenanceFailover { from, to } => Some((from, to)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(failovers, vec![(url_key(&fast), url_key(&slow))]);
+
+    // Inside the window it is left out of proxying, probes and fan-outs
+    clock.advance(Duration::from_secs(70));
+    let plan = handler.plan_request(&block_number(), None).await.unwrap();
+    assert_eq!(plan.batches().concat(), vec![url_key(&slow), url_key(&slowest)]);
+    assert!(plan.excluded.contains(&ExcludedUrl { url: url_key(&fast), reason: Exclusion::Maintenance }));
+    let report = handler.health_report().await;
+    let fast_health = report.endpoints.iter().find(|e| e.url == url_key(&fast)).unwrap();
+    assert_eq!(fast_health.maintenance_until, Some(UNIX_EPOCH + Duration::from_secs(window_start + 600)));
+    assert!(report.to_string().contains("[scheduled maintenance]"));
+
+    let probes_before = count_method(&fast, "eth_getCode").await;
+    handler.refresh().await.unwrap();
+    assert_eq!(count_method(&fast, "eth_getCode").await, probes_before);
+    let block: String = RpcCalls::new(Arc::clone(&handler)).consensus(&block_number(), 1.0, None).await.unwrap();
+    assert_eq!(block, "0x10");
+    assert_eq!(count_method(&fast, "eth_blockNumber").await, 0);
+    assert_eq!(count_method(&slowest, "eth_blockNumber").await, 1);
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&slow));
+
+    // Once the window closes it is back in every pool
+    clock.advance(Duration::from_secs(600));
+    handler.refresh().await.unwrap();
+    assert!(count_method(&fast, "eth_getCode").await > probes_before);
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&fast));
+    let plan = handler.plan_request(&block_number(), None).await.unwrap();
+    assert!(plan.excluded.is_empty(), "{:?}", plan.excluded);
+    handler.shutdown();
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::{filters::BACKFILL_SPAN_BLOCKS, *};
+use serde_json::{json, Value};
+use wiremock::{matchers::{body_partial_json, method}, Mock, MockServer, Request, Respond, ResponseTemplate};
+
+/// The chain both endpoints agree on, as `(block, logIndex)`.
+const CHAIN: &[(u64, u64)] = &[(0x11, 0), (0x12, 0), (0x12, 1), (0x13, 0), (0x14, 0), (0x14, 1), (0x15, 0)];
+
+fn log(block: u64, index: u64) -> Value {
+    json!({
+        "address": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+        "blockNumber": format!("0x{block:x}"),
+        "blockHash": format!("0x{block:064x}"),
+        "logIndex": format!("0x{index:x}"),
+        "removed": false
+    })
+}
+
+fn logs(entries: &[(u64, u64)]) -> Value {
+    Value::Array(entries.iter().map(|&(block, index)| log(block, index)).collect())
+}
+
+fn ok(result: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, result))
+}
+
+fn quantity(value: &Value) -> u64 {
+    u64::from_str_radix(value.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+}
+
+/// `eth_getFilterChanges` answering with each batch in turn, then nothing.
+fn changes(batches: Vec<Value>) -> impl Respond + 'static {
+    let polls = AtomicUsize::new(0);
+    move |_: &Request| ok(batches.get(polls.fetch_add(1, Ordering::SeqCst)).cloned().unwrap_or(json!([])))
+}
+
+/// `eth_getLogs` over `CHAIN`, recording each queried range.
+fn get_logs(ranges: Arc<parking_lot::Mutex<Vec<(u64, u64)>>>) -> impl Respond + 'static {
+    move |request: &Request| {
+        let body: Value = serde_json::from_slice(&request.body).unwrap();
+        let (from, to) = (quantity(&body["params"][0]["fromBlock"]), quantity(&body["params"][0]["toBlock"]));
+        ranges.lock().push((from, to));
+        let matching: Vec<(u64, u64)> = CHAIN.iter().copied().filter(|(block, _)| (from..=to).contains(block)).collect();
+        ok(logs(&matching))
+    }
+}
+
+async fn mount_responder(server: &MockServer, rpc_method: &str, respond: impl Respond + 'static) {
+    Mock::given(method("POST")).and(body_partial_json(json!({ "method": rpc_method }))).respond_with(respond).mount(server).await;
+}
+
+async fn handler(primary: &MockServer, fallback: &MockServer) -> Arc<RpcHandler> {
+    mount_probe(primary, "0x20", Duration::ZERO).await;
+    mount_probe(fallback, "0x20", Duration::from_millis(40)).await;
+    let handler = init_handler(settings(vec![mk_rpc(primary), mk_rpc(fallback)])).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(primary));
+    handler
+}
+
+fn spec() -> Value {
+    json!({ "address": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48" })
+}
+
+#[tokio::test]
+async fn test_no_logs_are_lost_when_the_primary_dies() {
+    // A standalone server, so dropping it really closes the port
+    let primary = MockServer::builder().start().await;
+    let fallback = MockServer::start().await;
+    let handler = handler(&primary, &fallback).await;
+    let mut events = handler.subscribe();
+
+    mount_method(&primary, "eth_newFilter", ok(json!("0x1"))).await;
+    mount_method(&primary, "eth_blockNumber", ok(json!("0x10"))).await;
+    mount_responder(&primary, "eth_getFilterChanges", changes(vec![logs(&CHAIN[..2]), logs(&CHAIN[2..3])])).await;
+    let ranges = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    mount_method(&fallback, "eth_newFilter", ok(json!("0xb"))).await;
+    mount_method(&fallback, "eth_blockNumber", ok(json!("0x14"))).await;
+    mount_responder(&fallback, "eth_getLogs", get_logs(Arc::clone(&ranges))).await;
+    // The new filter overlaps the backfill by a log
+    mount_responder(&fallback, "eth_getFilterChanges", changes(vec![logs(&CHAIN[5..])])).await;
+
+    let calls = RpcCalls::new(Arc::clone(&handler));
+    let mut filter = calls.managed_log_filter(spec()).await.unwrap();
+    assert_eq!((filter.url(), filter.filter_id()), (url_key(&primary).as_str(), "0x1"));
+
+    let mut delivered = Vec::new();
+    delivered.extend(filter.poll().await.unwrap());
+    delivered.extend(filter.poll().await.unwrap());
+    let primary_url = url_key(&primary);
+    drop(primary);
+
+    let backfilled = filter.poll().await.unwrap();
+    assert_eq!(backfilled, logs(&CHAIN[3..6]).as_array().unwrap().clone(), "the gap, without what was already delivered");
+    assert_eq!((filter.url(), filter.filter_id()), (url_key(&fallback).as_str(), "0xb"));
+    assert_eq!(*ranges.lock(), [(0x12, 0x14)], "backfilled from the last delivered block");
+    delivered.extend(backfilled);
+    delivered.extend(filter.poll().await.unwrap());
+    assert_eq!(Value::Array(delivered), logs(CHAIN), "every log once, in order");
+
+    let reinstalled = std::iter::from_fn(|| events.try_recv().ok()).find(|event| matches!(event, HandlerEvent::FilterReinstalled { .. }));
+    assert_eq!(reinstalled, Some(HandlerEvent::FilterReinstalled { from: primary_url, to: url_key(&fallback), backfilled: 3 }));
+
+    // Dropping it uninstalls the filter where it lives now
+    drop(filter);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(count_method(&fallback, "eth_uninstallFilter").await, 1);
+}
+
+#[tokio::test]
+async fn test_long_gaps_are_backfilled_in_bounded_ranges() {
+    let (primary, fallback) = (MockServer::start().await, MockServer::start().await);
+    let handler = handler(&primary, &fallback).await;
+
+    mount_method(&primary, "eth_newFilter", ok(json!("0x1"))).await;
+    mount_method(&primary, "eth_blockNumber", ok(json!("0x10"))).await;
+    mount_method(&primary, "eth_getFilterChanges", ResponseTemplate::new(503)).await;
+    let head = 0x10 + 2 * BACKFILL_SPAN_BLOCKS + 500;
+    let ranges = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    mount_method(&fallback, "eth_newFilter", ok(json!("0xb"))).await;
+    mount_method(&fallback, "eth_blockNumber", ok(json!(format!("0x{head:x}")))).await;
+    mount_responder(&fallback, "eth_getLogs", get_logs(Arc::clone(&ranges))).await;
+
+    let calls = RpcCalls::new(handler);
+    let mut filter = calls.managed_log_filter(spec()).await.unwrap();
+    assert_eq!(Value::Array(filter.poll().await.unwrap()), logs(CHAIN));
+    assert_eq!(filter.url(), url_key(&fallback));
+
+    // Nothing was delivered before the move, so the backfill starts after the installation head
+    let span = BACKFILL_SPAN_BLOCKS;
+    assert_eq!(*ranges.lock(), [(0x11, 0x10 + span), (0x11 + span, 0x10 + 2 * span), (0x11 + 2 * span, head)]);
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+#[cfg(feature = "consensus")]
+use serde_json::json;
+#[cfg(feature = "consensus")]
+use wiremock::matchers::method;
+use wiremock::MockServer;
+#[cfg(feature = "consensus")]
+use wiremock::{Mock, ResponseTemplate};
+
+#[cfg(feature = "consensus")]
+const LIMIT: usize = 16;
+/// Synthetic endpoints kept configured at any one time while the soak test churns through them.
+#[cfg(feature = "consensus")]
+const WINDOW: usize = 40;
+
+#[cfg(feature = "consensus")]
+fn rpc_at(url: &str) -> Rpc {
+    Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+#[cfg(feature = "consensus")]
+fn assert_within_limits(report: &MemoryReport) {
+    for (name, count) in [
+        ("latencies", report.latencies),
+        ("lagging", report.lagging),
+        ("client_versions", report.client_versions),
+        ("capabilities", report.capabilities),
+        ("failure_counts", report.failure_counts),
+        ("malformed_counts", report.malformed_counts),
+        ("last_updated", report.last_updated),
+        ("cooldowns", report.cooldowns),
+        ("probe_skips", report.probe_skips),
+    ] {
+        assert!(count <= LIMIT, "{name} holds {count} entries, over the limit of {LIMIT}: {report:?}");
+    }
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_maps_stay_bounded_under_endpoint_churn() {
+    let good = MockServer::start().await;
+    mount_probe(&good, "0x10", Duration::ZERO).await;
+    mount_method(&good, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10")))).await;
+    let bad = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(500)).mount(&bad).await;
+
+    let limits = MemoryLimits {
+        max_latency_entries: LIMIT,
+        max_endpoint_entries: LIMIT,
+        max_cooldown_entries: LIMIT,
+        max_negative_entries: LIMIT,
+    };
+    let settings = HandlerSettings { memory_limits: limits, ..settings(vec![mk_rpc(&good)]) };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+    let calls = RpcCalls::new(handler.clone());
+    // Short cooldowns so failed endpoints become evictable between rounds
+    let consensus = ConsensusOptions { cooldown_ms: Some(1), per_host_concurrency: Some(8), concurrency: Some(8), ..ConsensusOptions::default() };
+
+    let bad_port = bad.address().port();
+    let synthetic = |i: usize| match i % 5 {
+        0 => format!("http://localhost:{bad_port}/synthetic/{i}"),
+        _ => format!("{}/synthetic/{i}", good.uri()),
+    };
+
+    for i in 0..1000 {
+        assert!(handler.add_rpc(rpc_at(&synthetic(i))));
+        if i >= WINDOW {
+            assert!(handler.remove_rpc(&synthetic(i - WINDOW)).await);
+        }
+
+        if i % 25 == 24 {
+            handler.refresh().await.unwrap();
+            handler.try_proxy_request(block_number()).await.unwrap();
+            let _ = calls.consensus::<String>(&block_number(), 0.5, Some(consensus.clone())).await;
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            handler.refresh().await.unwrap();
+
+            let report = handler.memory_report().await;
+            assert_within_limits(&report);
+            assert!(report.endpoints <= WINDOW + 1, "removed endpoints linger: {report:?}");
+
+            let active = handler.get_provider_url().await.unwrap();
+            assert!(handler.get_latencies().await.contains_key(&active), "the active provider's latency must survive eviction");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_removing_an_endpoint_forgets_its_state() {
+    let first = MockServer::start().await;
+    let second = MockServer::start().await;
+    mount_probe(&first, "0x10", Duration::ZERO).await;
+    mount_probe(&second, "0x10", Duration::from_millis(50)).await;
+
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&first), mk_rpc(&second)])), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.memory_report().await.latencies, 2);
+
+    assert!(handler.remove_rpc(&second.uri()).await);
+    assert!(!handler.remove_rpc(&second.uri()).await);
+    assert!(!handler.get_latencies().await.contains_key(&url_key(&second)));
+    assert_eq!(handler.memory_report().await.endpoints, 1);
+    assert!(!handler.add_rpc(mk_rpc(&first)), "duplicate URLs are rejected");
+}
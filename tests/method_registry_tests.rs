@@ -0,0 +1,134 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::*;
+use ez_web3_rpc::methods::{self, MethodDescriptor};
+use ez_web3_rpc::validation::{ResultShape, RESULT_SHAPES};
+use ez_web3_rpc::*;
+use serde_json::Value;
+
+const JSON_SCHEMA_TYPES: &[&str] = &["null", "boolean", "object", "array", "number", "integer", "string"];
+
+/// Check `schema` is a JSON Schema object using only the keywords the registry relies on.
+fn check_schema(schema: &Value, at: &str) {
+    let object = schema.as_object().unwrap_or_else(|| panic!("{at}: schema must be an object"));
+    if let Some(ty) = object.get("type") {
+        assert!(JSON_SCHEMA_TYPES.contains(&ty.as_str().unwrap_or_default()), "{at}: unknown type {ty}");
+    }
+    if let Some(pattern) = object.get("pattern") {
+        let pattern = pattern.as_str().unwrap_or_else(|| panic!("{at}: pattern must be a string"));
+        assert!(pattern.starts_with('^') && pattern.ends_with('$'), "{at}: pattern {pattern} must be anchored");
+    }
+    if let Some(one_of) = object.get("oneOf") {
+        let variants = one_of.as_array().unwrap_or_else(|| panic!("{at}: oneOf must be an array"));
+        assert!(variants.len() >= 2, "{at}: oneOf needs alternatives");
+        for (i, variant) in variants.iter().enumerate() {
+            check_schema(variant, &format!("{at}.oneOf[{i}]"));
+        }
+    }
+    if let Some(properties) = object.get("properties") {
+        for (name, property) in properties.as_object().unwrap_or_else(|| panic!("{at}: properties must be an object")) {
+            check_schema(property, &format!("{at}.{name}"));
+        }
+    }
+    if let Some(required) = object.get("required") {
+        let properties = object.get("properties").and_then(Value::as_object).unwrap_or_else(|| panic!("{at}: required without properties"));
+        for name in required.as_array().unwrap_or_else(|| panic!("{at}: required must be an array")) {
+            assert!(properties.contains_key(name.as_str().unwrap_or_default()), "{at}: required {name} isn't a property");
+        }
+    }
+}
+
+/// Check an OpenRPC content descriptor: a name, a schema and an optional `required` flag.
+fn check_content_descriptor(descriptor: &Value, at: &str) {
+    assert!(descriptor["name"].as_str().is_some_and(|name| !name.is_empty()), "{at}: content descriptor needs a name");
+    if let Some(required) = descriptor.get("required") {
+        assert!(required.is_boolean(), "{at}: required must be a boolean");
+    }
+    check_schema(&descriptor["schema"], &format!("{at}.schema"));
+}
+
+#[test]
+fn test_openrpc_document_follows_the_schema() {
+    let document = methods::to_openrpc();
+
+    let version = document["openrpc"].as_str().expect("openrpc version");
+    assert_eq!(version.split('.').filter(|part| part.parse::<u32>().is_ok()).count(), 3, "semver: {version}");
+    assert_eq!(document["info"]["title"], "ez_web3_rpc");
+    assert!(document["info"]["version"].is_string());
+
+    let methods = document["methods"].as_array().expect("methods array");
+    assert_eq!(methods.len(), methods::registry().len());
+    let mut names = HashSet::new();
+    for method in methods {
+        let name = method["name"].as_str().expect("method name");
+        assert!(names.insert(name), "{name} is registered twice");
+
+        let params = method["params"].as_array().unwrap_or_else(|| panic!("{name}: params must be an array"));
+        let mut optional_seen = false;
+        for (i, param) in params.iter().enumerate() {
+            check_content_descriptor(param, &format!("{name}.params[{i}]"));
+            // Positional params can only leave off a suffix
+            let required = param["required"].as_bool().unwrap_or(false);
+            assert!(!(optional_seen && required), "{name}: required param after an optional one");
+            optional_seen |= !required;
+        }
+        check_content_descriptor(&method["result"], &format!("{name}.result"));
+        for flag in ["x-idempotent", "x-cacheable", "x-archive-sensitive", "x-null-result"] {
+            assert!(method[flag].is_boolean(), "{name}: {flag}");
+        }
+    }
+
+    // Round-trips through text unchanged, for tooling that stores it
+    let text = serde_json::to_string_pretty(&document).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&text).unwrap(), document);
+}
+
+#[test]
+fn test_registry_covers_validated_methods_with_matching_results() {
+    for (method, shape) in RESULT_SHAPES {
+        let descriptor = methods::descriptor(method).unwrap_or_else(|| panic!("{method} is validated but not registered"));
+        let title = descriptor.result_schema["title"].as_str();
+        match shape {
+            ResultShape::HexQuantity => assert_eq!(title, Some("hex quantity"), "{method}"),
+            ResultShape::HexData => assert_eq!(title, Some("hex data"), "{method}"),
+            ResultShape::ObjectOrNull => assert!(descriptor.result_schema["oneOf"].is_array(), "{method}"),
+        }
+    }
+
+    let flagged = |pick: fn(&MethodDescriptor) -> bool| -> Vec<&str> {
+        methods::registry().iter().filter(|m| pick(m)).map(|m| m.name).collect()
+    };
+    assert_eq!(flagged(|m| !m.idempotent), vec!["eth_sendRawTransaction", "eth_sendTransaction"]);
+    assert!(flagged(|m| m.archive_sensitive).contains(&"eth_call"));
+    assert!(flagged(|m| m.cacheable).contains(&"eth_chainId"));
+    assert!(!flagged(|m| m.cacheable).contains(&"eth_blockNumber"));
+}
+
+#[test]
+fn test_write_endpoint_default_follows_the_registry() {
+    let settings = HandlerSettings {
+        write_endpoint: Some(RouteRule { methods: vec![], urls: vec!["http://node:8545".into()], allow_failover: false }),
+        ..HandlerSettings::default()
+    };
+    let normalized = resolve_config(HandlerConfig { network_id: TEST_NETWORK_ID, settings: Some(settings) }).unwrap();
+
+    let side_effects: Vec<String> = methods::registry().iter().filter(|m| !m.idempotent).map(|m| m.name.to_string()).collect();
+    assert_eq!(normalized.routes[0].methods, side_effects);
+    assert_eq!(methods::write_methods().collect::<Vec<_>>(), side_effects);
+}
+
+#[test]
+fn test_null_results_are_declared_exactly_where_the_schema_allows_them() {
+    for descriptor in methods::registry() {
+        let schema_allows_null = descriptor.result_schema["oneOf"]
+            .as_array()
+            .is_some_and(|variants| variants.iter().any(|variant| variant["type"] == "null"));
+        assert_eq!(descriptor.null_result, schema_allows_null, "{}", descriptor.name);
+        assert_eq!(methods::null_result_valid(descriptor.name), descriptor.null_result, "{}", descriptor.name);
+    }
+    assert!(methods::null_result_valid("eth_getTransactionReceipt"));
+    assert!(!methods::null_result_valid("eth_getCode"));
+    assert!(methods::null_result_valid("eth_someUnregisteredMethod"));
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::time::Duration;
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+use std::{collections::BTreeMap, sync::Arc};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+fn ok(result: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(result)))
+}
+
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+fn classes(counts: &[(FailureClass, u64)]) -> BTreeMap<FailureClass, u64> {
+    counts.iter().copied().collect()
+}
+
+/// A tier 0 primary and a tier 1 backup, the backup on another hostname so consensus doesn't
+/// treat them as one provider.
+async fn endpoints() -> (MockServer, MockServer, Vec<RpcConfig>) {
+    let (primary, backup) = (MockServer::start().await, MockServer::start().await);
+    mount_probe(&primary, "0x10", Duration::ZERO).await;
+    mount_probe(&backup, "0x10", Duration::from_millis(30)).await;
+    let backup_rpc = RpcConfig { url: Some(format!("http://localhost:{}", backup.address().port()).parse().unwrap()), ..tiered(&backup, 1) };
+    let rpcs = vec![tiered(&primary, 0), backup_rpc];
+    (primary, backup, rpcs)
+}
+
+#[cfg(all(feature = "consensus", feature = "test-util"))]
+#[tokio::test]
+async fn test_diff_matches_the_scripted_traffic() {
+    let (primary, backup, rpcs) = endpoints().await;
+    let backup_url = rpcs[1].url.as_ref().unwrap().to_string();
+    let clock = MockClock::new();
+    let settings = HandlerSettings {
+        failover_policy: FailoverPolicy::TierStrict,
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 0, rpc_call_timeout_ms: 2000, connect_timeout_ms: None }),
+        ..settings(rpcs)
+    };
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+
+    mount_method(&primary, "eth_chainId", ok("0x1")).await;
+    mount_method(&primary, "eth_gasPrice", ResponseTemplate::new(503)).await;
+    mount_method(&backup, "eth_gasPrice", ok("0x2")).await;
+    for server in [&primary, &backup] {
+        mount_method(server, "eth_getBalance", ResponseTemplate::new(429)).await;
+        mount_method(server, "net_version", ok("1")).await;
+    }
+    mount_method(&primary, "web3_clientVersion", ok("geth")).await;
+    mount_method(&backup, "web3_clientVersion", ResponseTemplate::new(503)).await;
+
+    let before = handler.metrics_snapshot();
+    for _ in 0..3 {
+        handler.try_proxy_request(request("eth_chainId", json!([]))).await.unwrap();
+    }
+    for _ in 0..2 {
+        handler.try_proxy_request(request("eth_gasPrice", json!([]))).await.unwrap();
+    }
+    assert!(handler.try_proxy_request(request("eth_getBalance", json!([]))).await.is_err());
+    let calls = RpcCalls::new(Arc::clone(&handler));
+    calls.consensus::<String>(&request("net_version", json!([])), 1.0, None).await.unwrap();
+    // The backup's failure cools it down, which leaves the next run a single endpoint
+    assert_eq!(calls.consensus::<String>(&request("web3_clientVersion", json!([])), 1.0, None).await.unwrap(), "geth");
+    assert!(calls.consensus::<String>(&request("net_version", json!([])), 1.0, None).await.is_err());
+    clock.advance(Duration::from_secs(60));
+    let after = handler.metrics_snapshot();
+
+    let delta = after.diff(&before);
+    assert_eq!(delta.elapsed_ms, 60_000);
+    assert!(!delta.reset_between);
+    assert_eq!(delta.totals, CounterValues {
+        requests: 6,
+        successes: 5,
+        failures: classes(&[(FailureClass::RateLimited, 1)]),
+        failovers: 2,
+        cooldowns_applied: 1,
+        consensus_runs: 3,
+        consensus_failures: 1,
+    });
+    assert_eq!(delta.requests_per_minute, 6.0);
+    assert_eq!(delta.failovers_per_minute, 2.0);
+    assert_eq!(delta.error_rate, Some(1.0 / 6.0));
+    assert_eq!(delta.consensus_failure_rate, Some(1.0 / 3.0));
+
+    let primary_delta = &delta.endpoints[&url_key(&primary)];
+    assert_eq!(primary_delta.counts, EndpointValues {
+        attempts: 8,
+        successes: 5,
+        failures: classes(&[(FailureClass::HttpStatus, 2), (FailureClass::RateLimited, 1)]),
+    });
+    assert_eq!(primary_delta.error_rate, Some(3.0 / 8.0));
+    assert_eq!(delta.endpoints[&backup_url].counts, EndpointValues {
+        attempts: 5,
+        successes: 3,
+        failures: classes(&[(FailureClass::HttpStatus, 1), (FailureClass::RateLimited, 1)]),
+    });
+
+    // Both serialize, and the snapshot comes back as it was
+    let exported = serde_json::to_value(&after).unwrap();
+    assert_eq!(exported["totals"]["failures"], json!({ "rate_limited": 1 }));
+    assert_eq!(serde_json::from_value::<MetricsSnapshot>(exported).unwrap(), after);
+    assert_eq!(serde_json::to_value(&delta).unwrap()["endpoints"][url_key(&primary)]["counts"]["attempts"], json!(8));
+}
+
+#[tokio::test]
+async fn test_counters_only_reset_when_asked() {
+    let (primary, _backup, rpcs) = endpoints().await;
+    let handler = RpcHandler::new(config(settings(rpcs)), None).await.unwrap();
+    handler.init().await.unwrap();
+    mount_method(&primary, "eth_chainId", ok("0x1")).await;
+
+    let first = handler.metrics_snapshot();
+    for _ in 0..4 {
+        handler.try_proxy_request(request("eth_chainId", json!([]))).await.unwrap();
+    }
+    let second = handler.metrics_snapshot();
+    assert_eq!(second.totals.requests, 4);
+    assert_eq!(handler.metrics_snapshot().totals.requests, 4, "reading doesn't reset");
+
+    handler.reset_metrics();
+    handler.try_proxy_request(request("eth_chainId", json!([]))).await.unwrap();
+    let third = handler.metrics_snapshot();
+    assert_eq!((third.epoch, third.totals.requests), (second.epoch + 1, 1));
+    let delta = third.diff(&second);
+    assert!(delta.reset_between);
+    assert_eq!((delta.totals.requests, delta.endpoints[&url_key(&primary)].counts.attempts), (1, 1), "counts since the reset");
+    assert_eq!(third.diff(&first).totals.requests, 1);
+    assert!(!second.diff(&first).reset_between);
+}
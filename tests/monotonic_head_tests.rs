@@ -0,0 +1,118 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::head::HeadTracker;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+/// A probe-passing endpoint whose `eth_blockNumber` reports `head`; a later probe delay orders it later.
+async fn endpoint(head: u64, probe_delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", probe_delay).await;
+    mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(format!("{head:#x}"))))).await;
+    server
+}
+
+/// Leave `server` passing probes but failing every other call.
+async fn fail_calls(server: &MockServer) {
+    server.reset().await;
+    mount_probe(server, "0x10", Duration::ZERO).await;
+    mount_method(server, "eth_blockNumber", ResponseTemplate::new(500)).await;
+}
+
+async fn handler(servers: &[&MockServer], monotonic_head: bool) -> std::sync::Arc<RpcHandler> {
+    let settings = HandlerSettings {
+        monotonic_head,
+        max_head_lag: 2,
+        ..settings(servers.iter().map(|server| mk_rpc(server)).collect())
+    };
+    init_handler(settings).await
+}
+
+#[tokio::test]
+async fn test_lagging_fallback_is_skipped_for_a_fresh_one() {
+    let primary = endpoint(1000, Duration::ZERO).await;
+    let lagging = endpoint(995, Duration::from_millis(40)).await;
+    let fresh = endpoint(1000, Duration::from_millis(80)).await;
+    let handler = handler(&[&primary, &lagging, &fresh], true).await;
+
+    let (response, served_by) = handler.try_proxy_request_attributed(block_number()).await.unwrap();
+    assert_eq!((response.result, served_by), (Some(json!("0x3e8")), url_key(&primary)));
+    assert_eq!(handler.head_watermark(), Some(1000));
+
+    fail_calls(&primary).await;
+    let asked = count_method(&lagging, "eth_blockNumber").await;
+    let (response, served_by) = handler.try_proxy_request_attributed(block_number()).await.unwrap();
+    assert_eq!((response.result, served_by), (Some(json!("0x3e8")), url_key(&fresh)));
+    assert_eq!(count_method(&lagging, "eth_blockNumber").await, asked + 1, "the lagging fallback was asked, and its answer dropped");
+    assert_eq!(handler.observed_heads()[&url_key(&lagging)], 995);
+
+    // Now that its head is known, it is left out before anything is sent
+    let plan = handler.plan_request(&block_number(), None).await.unwrap();
+    assert!(plan.excluded.contains(&ExcludedUrl { url: url_key(&lagging), reason: Exclusion::BehindHead }));
+    assert_eq!(plan.head_guard.map(|guard| guard.floor()), Some(998));
+    handler.try_proxy_request(block_number()).await.unwrap();
+    assert_eq!(count_method(&lagging, "eth_blockNumber").await, asked + 1);
+}
+
+#[tokio::test]
+async fn test_without_the_guard_the_lagging_fallback_answers() {
+    let primary = endpoint(1000, Duration::ZERO).await;
+    let lagging = endpoint(995, Duration::from_millis(40)).await;
+    let handler = handler(&[&primary, &lagging], false).await;
+
+    handler.try_proxy_request(block_number()).await.unwrap();
+    fail_calls(&primary).await;
+    let response = handler.try_proxy_request(block_number()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x3e3")));
+    assert_eq!(handler.head_watermark(), Some(1000), "the watermark is tracked either way");
+}
+
+#[tokio::test]
+async fn test_fails_when_no_endpoint_is_synced_until_refresh() {
+    let primary = endpoint(1000, Duration::ZERO).await;
+    let lagging = endpoint(995, Duration::from_millis(40)).await;
+    let handler = handler(&[&primary, &lagging], true).await;
+
+    handler.try_proxy_request(block_number()).await.unwrap();
+    fail_calls(&primary).await;
+    let err = handler.try_proxy_request(block_number()).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::NoSufficientlySyncedProvider { watermark: 1000, max_head_lag: 2 }), "{err:?}");
+
+    // A refresh starts over, so the lagging endpoint's head becomes the new watermark
+    handler.refresh().await.unwrap();
+    assert_eq!(handler.head_watermark(), None);
+    let response = handler.try_proxy_request(block_number()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x3e3")));
+    assert_eq!(handler.head_watermark(), Some(995));
+}
+
+#[test]
+fn test_reorg_within_tolerance_lowers_the_watermark() {
+    let (a, b) = ("https://a.example/".to_string(), "https://b.example/".to_string());
+    let head = |n: u64| json!(format!("{n:#x}"));
+
+    let tracker = HeadTracker::new(true, 0, 3);
+    tracker.observe(&a, &block_number(), &head(1000)).unwrap();
+    assert!(tracker.observe(&b, &block_number(), &head(998)).is_err());
+    assert!(tracker.observe(&a, &block_number(), &head(998)).is_err(), "a's own drop is rejected too");
+
+    // Nobody reaches 1000 any more, and 998 is within the tolerance: the chain reorganized
+    let guard = tracker.guard_for([&a, &b]).unwrap();
+    assert_eq!((guard.watermark, guard.floor()), (998, 998));
+    tracker.observe(&b, &block_number(), &head(998)).unwrap();
+
+    // A deeper drop is not taken for a reorg
+    let tracker = HeadTracker::new(true, 0, 1);
+    tracker.observe(&a, &block_number(), &head(1000)).unwrap();
+    assert!(tracker.observe(&b, &block_number(), &head(997)).is_err());
+    assert_eq!(tracker.guard_for([&b]).unwrap().watermark, 1000);
+
+    // Only requests for the current head teach it anything
+    let historical = JsonRpcRequest { method: "eth_getBlockByNumber".into(), params: json!(["0x1", false]), ..block_number() };
+    tracker.observe(&b, &historical, &json!({ "number": "0x1" })).unwrap();
+    assert_eq!(tracker.heads()[&b], 997);
+}
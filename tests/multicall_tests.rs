@@ -0,0 +1,230 @@
+#![cfg(feature = "abi")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::{
+    multicall::{decode_aggregate3, encode_aggregate3, MULTICALL3_ADDRESS},
+    *,
+};
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+const TOKEN: &str = "0x6b175474e89094c44da98b954eedeac495271d0f";
+const HOLDER: &str = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045";
+
+fn word(value: usize) -> String {
+    format!("{value:064x}")
+}
+
+fn unhex(value: &str) -> Vec<u8> {
+    let digits = value.trim_start_matches("0x");
+    (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()).collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn read_word(data: &[u8], at: usize) -> usize {
+    data[at + 24..at + 32].iter().fold(0, |value, byte| value << 8 | *byte as usize)
+}
+
+/// Answers `aggregate3` call data the way Multicall3 would if every call returned its own call data.
+fn echo_aggregate3(call_data: &str) -> String {
+    let data = unhex(call_data);
+    let array = &data[4 + read_word(&data, 4)..];
+    let calls = read_word(array, 0);
+    let body = &array[32..];
+    let echoed: Vec<Vec<u8>> = (0..calls)
+        .map(|index| {
+            let tuple = &body[read_word(body, 32 * index)..];
+            let at = read_word(tuple, 64);
+            tuple[at + 32..at + 32 + read_word(tuple, at)].to_vec()
+        })
+        .collect();
+
+    let mut head = vec![word(32), word(calls)];
+    let mut tail = Vec::new();
+    let mut offset = 32 * calls;
+    for bytes in &echoed {
+        head.push(word(offset));
+        let mut padded = bytes.clone();
+        padded.resize(bytes.len().div_ceil(32) * 32, 0);
+        tail.extend([word(1), word(64), word(bytes.len()), hex(&padded)]);
+        offset += 96 + padded.len();
+    }
+    format!("0x{}{}", head.concat(), tail.concat())
+}
+
+fn balance_of(holder: usize) -> MulticallItem {
+    let holder = format!("0x{holder:040x}");
+    MulticallItem::new(TOKEN, CallDataBuilder::function("balanceOf(address)").arg_address(&holder).build())
+}
+
+/// An endpoint at block 0x10 with Multicall3 deployed, echoing every `aggregate3` call.
+async fn with_multicall3() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST"))
+        .respond_with(|request: &Request| {
+            let body: Value = request.body_json().unwrap();
+            assert_eq!(body["method"], "eth_call");
+            assert_eq!(body["params"][0]["to"], MULTICALL3_ADDRESS);
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(echo_aggregate3(body["params"][0]["data"].as_str().unwrap()))))
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn calls_for(server: &MockServer) -> RpcCalls {
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(server)])), None).await.unwrap();
+    handler.init().await.unwrap();
+    RpcCalls::new(handler)
+}
+
+async fn sent_calls(server: &MockServer) -> Vec<Value> {
+    let requests = server.received_requests().await.unwrap_or_default();
+    requests
+        .iter()
+        .filter_map(|request| serde_json::from_slice::<Value>(&request.body).ok())
+        .filter(|body| body["method"] == "eth_call")
+        .map(|body| body["params"].clone())
+        .collect()
+}
+
+#[test]
+fn test_aggregate3_encoding() {
+    let call_data = format!("0x70a08231000000000000000000000000{}", &HOLDER[2..]);
+    let item = MulticallItem::new(TOKEN.to_uppercase().replace("0X", "0x"), call_data.clone());
+    let padded_call = format!("{}{}", &call_data[2..], "0".repeat(56));
+    assert_eq!(encode_aggregate3(std::slice::from_ref(&item)).unwrap(), format!("0x82ad56cb{}", [
+        word(0x20),
+        word(1),
+        word(0x20),
+        format!("000000000000000000000000{}", &TOKEN[2..]),
+        word(1),
+        word(0x60),
+        word(0x24),
+        padded_call,
+    ].concat()));
+
+    // Two calls, the second required to succeed: the offsets step over the first tuple
+    let required = item.clone().require_success();
+    let encoded = encode_aggregate3(&[item, required]).unwrap();
+    let words: Vec<&str> = (0..(encoded.len() - 10) / 64).map(|i| &encoded[10 + 64 * i..10 + 64 * (i + 1)]).collect();
+    assert_eq!(words[..5], [word(0x20), word(2), word(0x40), word(0x40 + 0xc0), format!("000000000000000000000000{}", &TOKEN[2..])]);
+    assert_eq!(words[5 + 6], word(0));
+
+    let invalid = encode_aggregate3(&[MulticallItem::new(TOKEN, "not hex")]);
+    assert!(matches!(invalid, Err(RpcHandlerError::InvalidAbi { .. })));
+}
+
+#[test]
+fn test_aggregate3_decoding() {
+    let returned = format!("0x{}", [
+        word(0x20),
+        word(2),
+        word(0x40),
+        word(0xc0),
+        // (true, uint256 7)
+        word(1), word(0x40), word(0x20), word(7),
+        // (false, 0x)
+        word(0), word(0x40), word(0),
+    ].concat());
+    let results = decode_aggregate3(&returned).unwrap();
+    assert_eq!(results, [
+        MulticallResult { success: true, return_data: format!("0x{}", word(7)) },
+        MulticallResult { success: false, return_data: "0x".to_string() },
+    ]);
+    assert_eq!(results[0].decode(&["uint256"]).unwrap()[0].as_u128(), Some(7));
+    assert!(matches!(results[1].decode(&["uint256"]), Err(RpcHandlerError::InvalidAbi { .. })));
+
+    // A length the data can't hold
+    let bogus = format!("0x{}", [word(0x20), word(1_000_000)].concat());
+    assert!(matches!(decode_aggregate3(&bogus), Err(RpcHandlerError::InvalidAbi { .. })));
+}
+
+#[tokio::test]
+async fn test_twenty_reads_go_out_as_one_aggregate_call_at_the_pinned_block() {
+    let server = with_multicall3().await;
+    let calls = calls_for(&server).await;
+    let items: Vec<MulticallItem> = (1..=20).map(balance_of).collect();
+
+    let results = calls.multicall(items.clone(), MulticallOptions::default()).await.unwrap();
+    assert_eq!(results.len(), 20);
+    for (item, result) in items.iter().zip(&results) {
+        assert_eq!((result.success, &result.return_data), (true, &item.call_data));
+    }
+    let sent = sent_calls(&server).await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0][1], "0x10");
+}
+
+#[tokio::test]
+async fn test_large_batches_are_split_at_the_calldata_threshold() {
+    let server = with_multicall3().await;
+    let calls = calls_for(&server).await;
+    let items: Vec<MulticallItem> = (1..=5).map(balance_of).collect();
+
+    // Each call takes 224 bytes, so two fit under 600 along with the fixed 68
+    let options = MulticallOptions { max_calldata_bytes: 600, block: Some(0xf), ..MulticallOptions::default() };
+    let results = calls.multicall(items.clone(), options).await.unwrap();
+    let returned: Vec<&str> = results.iter().map(|result| result.return_data.as_str()).collect();
+    assert_eq!(returned, items.iter().map(|item| item.call_data.as_str()).collect::<Vec<_>>());
+    let sent = sent_calls(&server).await;
+    assert_eq!(sent.len(), 3);
+    assert!(sent.iter().all(|params| params[1] == "0xf"));
+}
+
+#[tokio::test]
+async fn test_without_multicall3_each_call_is_sent_at_the_same_block() {
+    let server = MockServer::start().await;
+    let reverting = "0x000000000000000000000000000000000000dead";
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            let body: Value = request.body_json().unwrap();
+            let params = &body["params"];
+            match body["method"].as_str().unwrap() {
+                "eth_getBlockByNumber" => ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x10", "hash": "0xabc" }))),
+                "eth_getCode" if params[0] == MULTICALL3_ADDRESS => ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x"))),
+                "eth_getCode" => ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(PERMIT2_CODE))),
+                "eth_call" if params[0]["to"] == reverting => ResponseTemplate::new(200).set_body_json(json!({
+                    "jsonrpc": "2.0", "id": 1, "error": { "code": 3, "message": "execution reverted" }
+                })),
+                "eth_call" => ResponseTemplate::new(200).set_body_json(rpc_response(1, params[0]["data"].clone())),
+                other => panic!("unexpected {other}"),
+            }
+        })
+        .mount(&server)
+        .await;
+    let calls = calls_for(&server).await;
+    let items = vec![balance_of(1), MulticallItem::new(reverting, "0x12345678"), balance_of(2)];
+
+    let results = calls.multicall(items.clone(), MulticallOptions::default()).await.unwrap();
+    assert_eq!(results, [
+        MulticallResult { success: true, return_data: items[0].call_data.clone() },
+        MulticallResult { success: false, return_data: "0x".to_string() },
+        MulticallResult { success: true, return_data: items[2].call_data.clone() },
+    ]);
+    let sent = sent_calls(&server).await;
+    assert_eq!(sent.len(), 3);
+    assert!(sent.iter().all(|params| params[1] == "0x10"));
+
+    // The missing deployment is remembered, and a required call that reverts fails the whole multicall
+    let required = vec![balance_of(3), MulticallItem::new(reverting, "0x12345678").require_success()];
+    let result = calls.multicall(required, MulticallOptions::default()).await;
+    assert!(matches!(result, Err(RpcHandlerError::JsonRpcCode { code: 3, .. })), "{result:?}");
+    let code_checks = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter_map(|request| serde_json::from_slice::<Value>(&request.body).ok())
+        .filter(|body| body["method"] == "eth_getCode" && body["params"][0] == MULTICALL3_ADDRESS)
+        .count();
+    assert_eq!(code_checks, 1);
+}
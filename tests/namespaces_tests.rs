@@ -0,0 +1,114 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::namespaces::{parse_quantity, requires_block_sync};
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+// Divergent encodings observed from real providers for the same answers.
+const NET_VERSION_FIXTURES: &[(&str, u64)] = &[
+    (r#""1""#, 1),          // geth, spec-compliant decimal string
+    (r#""0x1""#, 1),        // some gateways hex-encode it
+    (r#"1"#, 1),            // bare number
+    (r#""100""#, 100),      // gnosis via nethermind
+    (r#""0x89""#, 137),     // polygon gateway
+];
+
+const TXPOOL_FIXTURES: &[&str] = &[
+    r#"{"pending":"0x10","queued":"0x2"}"#,   // geth
+    r#"{"pending":16,"queued":2}"#,           // erigon rpcdaemon
+    r#"{"pending":"16","queued":"2"}"#,       // nethermind
+];
+
+#[test]
+fn test_parse_quantity_fixtures() {
+    for (raw, expected) in NET_VERSION_FIXTURES {
+        let value: serde_json::Value = serde_json::from_str(raw).unwrap();
+        assert_eq!(parse_quantity(&value), Some(*expected), "fixture {raw}");
+    }
+    assert_eq!(parse_quantity(&json!("0x")), None);
+    assert_eq!(parse_quantity(&json!("peers")), None);
+    assert_eq!(parse_quantity(&json!(null)), None);
+}
+
+#[test]
+fn test_txpool_status_fixtures() {
+    for raw in TXPOOL_FIXTURES {
+        let status: TxPoolStatus = serde_json::from_str(raw).unwrap();
+        assert_eq!(status, TxPoolStatus { pending: 16, queued: 2 }, "fixture {raw}");
+    }
+}
+
+#[test]
+fn test_requires_block_sync() {
+    assert!(requires_block_sync("eth_blockNumber"));
+    assert!(requires_block_sync("debug_traceTransaction"));
+    assert!(!requires_block_sync("net_version"));
+    assert!(!requires_block_sync("web3_clientVersion"));
+    assert!(!requires_block_sync("txpool_status"));
+}
+
+async fn handler_for(servers: &[&MockServer]) -> RpcCalls {
+    let rpcs = servers.iter().map(|s| mk_rpc(s)).collect();
+    let handler = RpcHandler::new(config(settings(rpcs)), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+    RpcCalls::new(handler)
+}
+
+#[tokio::test]
+async fn test_typed_helpers_tolerate_mixed_encodings() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "net_version", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x64")))).await;
+    mount_method(&server, "net_peerCount", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("25")))).await;
+    mount_method(&server, "txpool_status", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({"pending": "0x3", "queued": 0})))).await;
+    mount_method(&server, "web3_clientVersion", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("Geth/v1.13.5-stable/linux-amd64/go1.21.4")))).await;
+
+    let calls = handler_for(&[&server]).await;
+    assert_eq!(calls.net_version().await.unwrap(), 100);
+    assert_eq!(calls.net_peer_count().await.unwrap(), 25);
+    assert_eq!(calls.txpool_status().await.unwrap(), TxPoolStatus { pending: 3, queued: 0 });
+    assert!(calls.web3_client_version().await.unwrap().starts_with("Geth/"));
+}
+
+#[tokio::test]
+async fn test_client_version_feeds_health_report() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "web3_clientVersion", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("erigon/2.48.1/linux-amd64/go1.20.5")))).await;
+
+    let calls = handler_for(&[&server]).await;
+    calls.web3_client_version().await.unwrap();
+
+    let report = calls.handler().health_report().await;
+    assert_eq!(report.endpoints[0].client_version.as_deref(), Some("erigon/2.48.1/linux-amd64/go1.20.5"));
+    assert!(report.to_string().contains("erigon/2.48.1"));
+}
+
+#[tokio::test]
+async fn test_non_eth_methods_skip_block_sync_filter() {
+    let head_a = MockServer::start().await;
+    let head_b = MockServer::start().await;
+    let lagging = MockServer::start().await;
+
+    for server in [&head_a, &head_b] {
+        mount_method(server, "net_version", ResponseTemplate::new(500)).await;
+        mount_method(server, "eth_chainId", ResponseTemplate::new(500)).await;
+        mount_probe(server, "0x10", Duration::ZERO).await;
+    }
+    mount_probe(&lagging, "0xf", Duration::ZERO).await;
+    mount_method(&lagging, "net_version", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("1")))).await;
+    mount_method(&lagging, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+
+    let calls = handler_for(&[&head_a, &head_b, &lagging]).await;
+    assert!(!calls.handler().get_latencies().await.contains_key(&url_key(&lagging)));
+
+    assert_eq!(calls.net_version().await.unwrap(), 1);
+
+    let chain_id = request("eth_chainId", json!([]));
+    assert!(calls.try_rpc_call(&chain_id).await.is_err());
+    assert_eq!(count_method(&lagging, "eth_chainId").await, 0, "state-reading methods must stay on in-sync endpoints");
+}
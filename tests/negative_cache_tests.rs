@@ -0,0 +1,122 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::{body_partial_json, method}, Mock, MockServer, Request, ResponseTemplate};
+
+const TX: &str = "0x88df016429689c079f3b2f6ad39fa052532c56795b733da78a91ebe6a713944b";
+const OTHER_TX: &str = "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060";
+
+/// An endpoint whose head is `head`, where `TX` lands in block `0x12`.
+async fn chain(head: &Arc<AtomicU64>) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let current = Arc::clone(head);
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_blockNumber" })))
+        .respond_with(move |_: &Request| {
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(format!("{:#x}", current.load(Ordering::SeqCst)))))
+        })
+        .mount(&server)
+        .await;
+    let current = Arc::clone(head);
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getTransactionReceipt" })))
+        .respond_with(move |_: &Request| {
+            let receipt = (current.load(Ordering::SeqCst) >= 0x12).then(|| json!({ "transactionHash": TX, "blockNumber": "0x12" }));
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, receipt.unwrap_or(Value::Null)))
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn handler_for(server: &MockServer, negative_cache_entries: usize) -> Arc<RpcHandler> {
+    let settings = HandlerSettings { negative_cache_entries, ..settings(vec![mk_rpc(server)]) };
+    init_handler(settings).await
+}
+
+async fn advance_to(handler: &RpcHandler, head: &AtomicU64, block: u64) {
+    head.store(block, Ordering::SeqCst);
+    handler.try_proxy_request(block_number()).await.unwrap();
+    assert_eq!(handler.head_watermark(), Some(block));
+}
+
+#[tokio::test]
+async fn test_a_missing_receipt_is_queried_once_per_head() {
+    let head = Arc::new(AtomicU64::new(0x10));
+    let server = chain(&head).await;
+    let handler = handler_for(&server, 4).await;
+    advance_to(&handler, &head, 0x10).await;
+
+    for _ in 0..5 {
+        let (response, url) = handler.try_proxy_request_attributed(request("eth_getTransactionReceipt", json!([TX]))).await.unwrap();
+        assert_eq!((response.result, url), (Some(Value::Null), url_key(&server)));
+    }
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 1);
+
+    // Asked for in another case, it is the same lookup
+    handler.try_proxy_request(request("eth_getTransactionReceipt", json!([TX.to_uppercase().replace("0X", "0x")]))).await.unwrap();
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 1);
+
+    advance_to(&handler, &head, 0x11).await;
+    for _ in 0..5 {
+        handler.try_proxy_request(request("eth_getTransactionReceipt", json!([TX]))).await.unwrap();
+    }
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 2);
+
+    advance_to(&handler, &head, 0x12).await;
+    let landed = handler.try_proxy_request(request("eth_getTransactionReceipt", json!([TX]))).await.unwrap();
+    assert_eq!(landed.result.unwrap()["blockNumber"], "0x12");
+    handler.try_proxy_request(request("eth_getTransactionReceipt", json!([TX]))).await.unwrap();
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 4, "receipts aren't cacheable once found");
+
+    let stats = handler.cache_stats();
+    assert_eq!((stats.negative_hits, stats.hits), (9, 0));
+    assert_eq!(handler.metrics_snapshot().cache, stats);
+}
+
+#[tokio::test]
+async fn test_negatives_have_their_own_capacity_and_are_off_by_default() {
+    let head = Arc::new(AtomicU64::new(0x10));
+    let server = chain(&head).await;
+
+    let uncached = handler_for(&server, 0).await;
+    for _ in 0..2 {
+        uncached.try_proxy_request(request("eth_getTransactionReceipt", json!([TX]))).await.unwrap();
+    }
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 2);
+
+    // One negative at a time, so alternating lookups push each other out
+    let handler = handler_for(&server, 1).await;
+    for hash in [TX, OTHER_TX, TX, OTHER_TX, OTHER_TX] {
+        handler.try_proxy_request(request("eth_getTransactionReceipt", json!([hash]))).await.unwrap();
+    }
+    assert_eq!(count_method(&server, "eth_getTransactionReceipt").await, 2 + 4);
+    assert_eq!(handler.cache_stats(), CacheStats { entries: 0, hits: 0, negative_entries: 1, negative_hits: 1 });
+}
+
+#[tokio::test]
+async fn test_lookups_relative_to_a_block_tag_are_never_kept_negative() {
+    let head = Arc::new(AtomicU64::new(0x10));
+    let server = chain(&head).await;
+    mount_method(&server, "eth_getBlockReceipts", ResponseTemplate::new(200).set_body_json(rpc_response(1, Value::Null))).await;
+    let handler = handler_for(&server, 4).await;
+    advance_to(&handler, &head, 0x10).await;
+
+    for _ in 0..3 {
+        handler.try_proxy_request(request("eth_getBlockReceipts", json!(["latest"]))).await.unwrap();
+        handler.try_proxy_request(request("eth_getBlockReceipts", json!(["0x11"]))).await.unwrap();
+    }
+    assert_eq!(count_method(&server, "eth_getBlockReceipts").await, 6);
+    assert_eq!(handler.cache_stats().negative_entries, 0);
+}
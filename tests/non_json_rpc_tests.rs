@@ -0,0 +1,131 @@
+mod common;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn chain_id() -> JsonRpcRequest {
+    request("eth_chainId", json!([]))
+}
+
+async fn html_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw("<html><body>Welcome to our RPC docs</body></html>", "text/html; charset=utf-8"))
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn redirect_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/"))
+        .respond_with(ResponseTemplate::new(301).insert_header("Location", "/rpc"))
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn healthy_server() -> MockServer {
+    let server = MockServer::start().await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    mount_probe(&server, "0x10", std::time::Duration::ZERO).await;
+    server
+}
+
+/// Send `eth_chainId` only to `target`, so its error reaches the caller.
+fn routed_to(target: &MockServer, healthy: &MockServer) -> HandlerSettings {
+    HandlerSettings {
+        routes: vec![RouteRule { methods: vec!["eth_chainId".into()], urls: vec![target.uri()], allow_failover: false }],
+        ..settings(vec![mk_rpc(healthy), mk_rpc(target)])
+    }
+}
+
+#[tokio::test]
+async fn test_html_landing_page_is_classified_and_skipped_on_refresh() {
+    let healthy = healthy_server().await;
+    let html = html_server().await;
+
+    let handler = RpcHandler::new(config(routed_to(&html, &healthy)), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let err = handler.try_proxy_request(chain_id()).await.unwrap_err();
+    match err {
+        RpcHandlerError::NotAJsonRpcEndpoint { url, content_type, status } => {
+            assert_eq!(url, url_key(&html));
+            assert_eq!(content_type.as_deref(), Some("text/html; charset=utf-8"));
+            assert_eq!(status, 200);
+        }
+        other => panic!("expected NotAJsonRpcEndpoint, got {other:?}"),
+    }
+
+    let report = handler.health_report().await;
+    let endpoint = report.endpoints.iter().find(|e| e.url == url_key(&html)).unwrap();
+    assert_eq!(endpoint.non_json_rpc.as_ref().map(|c| c.status), Some(200));
+    assert!(report.to_string().contains("not-rpc"), "listed apart from transient failures:\n{report}");
+
+    let seen = html.received_requests().await.unwrap().len();
+    handler.refresh().await.unwrap();
+    assert_eq!(html.received_requests().await.unwrap().len(), seen, "classified endpoint must not be re-probed");
+}
+
+#[tokio::test]
+async fn test_redirect_is_classified_without_json_parse_error() {
+    let healthy = healthy_server().await;
+    let redirect = redirect_server().await;
+
+    let handler = RpcHandler::new(config(routed_to(&redirect, &healthy)), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let err = handler.try_proxy_request(chain_id()).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::NotAJsonRpcEndpoint { status: 301, .. }), "got {err:?}");
+    assert!(!err.to_string().contains("decoding"), "no parse error should leak: {err}");
+
+    // The default policy never turns the POST into a GET against the docs page
+    let methods: Vec<String> = redirect.received_requests().await.unwrap().iter().map(|r| r.method.to_string()).collect();
+    assert!(methods.iter().all(|m| m == "POST"), "got {methods:?}");
+}
+
+#[tokio::test]
+async fn test_same_host_redirect_is_followed_as_post_when_enabled() {
+    let healthy = healthy_server().await;
+    let redirect = redirect_server().await;
+    Mock::given(method("POST"))
+        .and(path("/rpc"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x2a"))))
+        .mount(&redirect)
+        .await;
+
+    let settings = HandlerSettings { follow_post_redirects: true, ..routed_to(&redirect, &healthy) };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let response = handler.try_proxy_request(chain_id()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x2a")));
+    assert!(handler.probe_schedule().classification(&url_key(&redirect)).is_none());
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_consensus_classifies_html_endpoint() {
+    let first = healthy_server().await;
+    let second = healthy_server().await;
+    let html = html_server().await;
+
+    // A different hostname so the HTML endpoint's cooldown doesn't hold back the others
+    let html_url = format!("http://localhost:{}/", html.address().port());
+    let html_rpc = Rpc { url: html_url.parse().unwrap(), ..mk_rpc(&html) };
+    let rpcs = vec![mk_rpc(&first), mk_rpc(&second), html_rpc];
+    let calls = RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap());
+
+    let options = ConsensusOptions { per_host_concurrency: Some(3), ..ConsensusOptions::default() };
+    let result: String = calls.bft_consensus(&chain_id(), 0.66, 0.5, Some(options)).await.unwrap();
+    assert_eq!(result, "0x1");
+
+    let classification = calls.handler().probe_schedule().classification(&html_url).unwrap();
+    assert_eq!(classification.content_type.as_deref(), Some("text/html; charset=utf-8"));
+}
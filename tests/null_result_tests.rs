@@ -0,0 +1,101 @@
+mod common;
+
+#[cfg(feature = "consensus")]
+use std::time::Duration;
+
+#[cfg(feature = "consensus")]
+use common::*;
+use ez_web3_rpc::{comparator::NULL_KEY, *};
+use serde_json::{json, Value};
+
+/// Every stub answers after this long, so all requests are in flight before a failing one
+/// cools down the shared `127.0.0.1` host.
+#[cfg(feature = "consensus")]
+const STUB_DELAY: Duration = Duration::from_millis(50);
+
+#[cfg(feature = "consensus")]
+const TX_HASH: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+
+#[cfg(feature = "consensus")]
+async fn calls(urls: &[String]) -> RpcCalls {
+    let rpcs = urls
+        .iter()
+        .map(|url| Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) })
+        .collect();
+    RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap())
+}
+
+#[cfg(feature = "consensus")]
+fn options(providers: usize) -> Option<ConsensusOptions> {
+    Some(ConsensusOptions { concurrency: Some(providers), per_host_concurrency: Some(providers), ..ConsensusOptions::default() })
+}
+
+#[cfg(feature = "consensus")]
+async fn null_results(count: usize) -> Vec<String> {
+    let mut urls = Vec::new();
+    for _ in 0..count {
+        urls.push(serve_fixed(json!({ "jsonrpc": "2.0", "id": 1, "result": null }), STUB_DELAY).await);
+    }
+    urls
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_providers_agreeing_a_transaction_is_unknown_reach_consensus_on_none() {
+    let urls = null_results(5).await;
+    let calls = calls(&urls).await;
+
+    let receipt = request("eth_getTransactionReceipt", json!([TX_HASH]));
+    let (result, report) = calls.consensus_with_report::<Option<Value>>(&receipt, 1.0, options(5)).await;
+
+    assert_eq!(result.unwrap(), None);
+    assert_eq!(report.votes, [(NULL_KEY.to_string(), 5)].into_iter().collect());
+    assert_eq!(report.most_common.as_deref(), Some(NULL_KEY));
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_a_response_without_a_result_is_malformed_not_a_vote_for_null() {
+    let mut urls = null_results(4).await;
+    let omitting = serve_fixed(json!({ "jsonrpc": "2.0", "id": 1 }), STUB_DELAY).await;
+    urls.push(omitting.clone());
+    let calls = calls(&urls).await;
+
+    let receipt = request("eth_getTransactionReceipt", json!([TX_HASH]));
+    let (result, report) = calls.consensus_with_report::<Option<Value>>(&receipt, 1.0, options(5)).await;
+
+    // Quorum is over the endpoints that answered, and the omission isn't one of them
+    assert_eq!(result.unwrap(), None);
+    assert_eq!(report.votes, [(NULL_KEY.to_string(), 4)].into_iter().collect());
+    match &report.outcomes[&omitting] {
+        EndpointOutcome::Failed { error } => assert!(error.contains("neither result nor error"), "{error}"),
+        other => panic!("expected a malformed failure, got {other:?}"),
+    }
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_null_for_a_method_that_never_returns_it_is_malformed() {
+    let urls = null_results(3).await;
+    let calls = calls(&urls).await;
+
+    let (result, report) = calls.consensus_with_report::<Option<String>>(&request("eth_getCode", json!([])), 0.5, options(3)).await;
+
+    assert!(result.is_err());
+    assert!(report.votes.is_empty(), "{:?}", report.votes);
+    assert!(report.outcomes.values().all(|outcome| matches!(outcome, EndpointOutcome::Failed { error } if error.contains("result is null"))));
+}
+
+#[test]
+fn test_null_and_missing_results_parse_apart() {
+    let null: JsonRpcResponse<Value> = serde_json::from_value(json!({ "jsonrpc": "2.0", "id": 1, "result": null })).unwrap();
+    let missing: JsonRpcResponse<Value> = serde_json::from_value(json!({ "jsonrpc": "2.0", "id": 1 })).unwrap();
+    assert_eq!((null.result, missing.result), (Some(Value::Null), None));
+
+    // A type that can't hold `null` still parses, as before
+    let typed: JsonRpcResponse<String> = serde_json::from_value(json!({ "jsonrpc": "2.0", "id": 1, "result": null })).unwrap();
+    assert_eq!(typed.result, None);
+
+    assert_ne!(comparator::stable_string(&Value::Null), comparator::stable_string(&json!("null")));
+    assert_eq!(comparator::stable_string(&Value::Null), NULL_KEY);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::time::Duration;
+#[cfg(feature = "consensus")]
+use std::{
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use common::*;
+#[cfg(feature = "consensus")]
+use ez_web3_rpc::provider::plan::BATCH_SIZE;
+use ez_web3_rpc::*;
+use futures::StreamExt;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+#[cfg(feature = "consensus")]
+use wiremock::Request;
+
+async fn endpoint(probe_delay_ms: u64) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::from_millis(probe_delay_ms)).await;
+    server
+}
+
+fn urls(ordered: &[OrderedRpc]) -> Vec<String> {
+    ordered.iter().map(|ordered| ordered.rpc.url.to_string()).collect()
+}
+
+/// The batches the proxy races, in sequence: tiers are never mixed, and each is chunked.
+#[cfg(feature = "consensus")]
+fn batches(ordered: &[OrderedRpc]) -> Vec<Vec<String>> {
+    let mut tiers: Vec<(Option<u8>, Vec<String>)> = Vec::new();
+    for entry in ordered {
+        match tiers.last_mut() {
+            Some((tier, urls)) if *tier == entry.tier => urls.push(entry.rpc.url.to_string()),
+            _ => tiers.push((entry.tier, vec![entry.rpc.url.to_string()])),
+        }
+    }
+    tiers.iter().flat_map(|(_, urls)| urls.chunks(BATCH_SIZE).map(|chunk| { let mut chunk = chunk.to_vec(); chunk.sort(); chunk })).collect()
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_ordered_rpcs_matches_the_attempt_order() {
+    // Tier 0 by latency, with `cooling` demoted to the end of it; tier 1 with one endpoint in maintenance
+    let (fast, medium, slow, cooling) = (endpoint(0).await, endpoint(40).await, endpoint(80).await, endpoint(0).await);
+    let (tier_one, maintained) = (endpoint(0).await, endpoint(0).await);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let window = MaintenanceWindow::Once { start_unix_secs: now - 60, duration_minutes: 10 };
+    let rpcs = vec![
+        tiered(&slow, 0),
+        tiered(&cooling, 0),
+        tiered(&fast, 0),
+        tiered(&medium, 0),
+        tiered(&tier_one, 1),
+        RpcConfig { maintenance_windows: Some(vec![window]), ..tiered(&maintained, 1) },
+    ];
+    let settings = HandlerSettings {
+        failover_policy: FailoverPolicy::TierStrict,
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 250, rpc_call_timeout_ms: 1000, connect_timeout_ms: None }),
+        ..settings(rpcs)
+    };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    assert!(handler.ordered_rpcs().await.is_empty(), "nothing is planned before init");
+    handler.init().await.unwrap();
+
+    // A consensus timeout puts `cooling` in a cooldown
+    for server in [&fast, &medium, &slow, &tier_one] {
+        mount_method(server, "eth_gasPrice", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    }
+    let late = ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1"))).set_delay(Duration::from_millis(500));
+    mount_method(&cooling, "eth_gasPrice", late).await;
+    let options = ConsensusOptions { timeout_ms: Some(150), cooldown_ms: Some(30_000), per_host_concurrency: Some(8), concurrency: Some(8), ..ConsensusOptions::default() };
+    let _ = RpcCalls::new(Arc::clone(&handler)).consensus_with_report::<String>(&request("eth_gasPrice", json!([])), 1.0, Some(options)).await;
+
+    let ordered = handler.ordered_rpcs().await;
+    assert_eq!(urls(&ordered), [url_key(&fast), url_key(&medium), url_key(&slow), url_key(&cooling), url_key(&tier_one)]);
+    assert_eq!(ordered.iter().map(|o| o.cooled_down).collect::<Vec<_>>(), [false, false, false, true, false]);
+    assert_eq!(ordered.iter().map(|o| o.tier).collect::<Vec<_>>(), [Some(0), Some(0), Some(0), Some(0), Some(1)]);
+    assert!(ordered.windows(2).all(|pair| pair[0].score > pair[1].score));
+    assert!(ordered.iter().all(|o| o.latency.is_some()));
+    let streamed: Vec<OrderedRpc> = handler.healthy_rpcs_stream(HealthCheckLevel::Cached).await.collect().await;
+    assert_eq!(urls(&streamed), urls(&ordered));
+
+    // Every endpoint fails, so the proxy walks the whole plan, pausing between batches
+    let attempts = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    for server in [&fast, &medium, &slow, &cooling, &tier_one, &maintained] {
+        let (attempts, url) = (Arc::clone(&attempts), url_key(server));
+        let failing = move |_: &Request| {
+            attempts.lock().push((url.clone(), Instant::now()));
+            ResponseTemplate::new(503)
+        };
+        wiremock::Mock::given(wiremock::matchers::body_partial_json(json!({ "method": "eth_chainId" }))).respond_with(failing).mount(server).await;
+    }
+    assert!(handler.try_proxy_request_with(request("eth_chainId", json!([])), CallOptions::default()).await.is_err());
+
+    let mut observed: Vec<Vec<String>> = Vec::new();
+    let mut last_at: Option<Instant> = None;
+    for (url, at) in attempts.lock().clone() {
+        match last_at {
+            Some(last) if at.duration_since(last) < Duration::from_millis(150) => observed.last_mut().unwrap().push(url),
+            _ => observed.push(vec![url]),
+        }
+        last_at = Some(at);
+    }
+    observed.iter_mut().for_each(|batch| batch.sort());
+    assert_eq!(observed, batches(&ordered));
+}
+
+#[tokio::test]
+async fn test_live_stream_probes_lazily_and_skips_dead_endpoints() {
+    let (first, second, third) = (endpoint(0).await, endpoint(40).await, endpoint(80).await);
+    mount_method(&first, "eth_blockNumber", ResponseTemplate::new(503)).await;
+    for server in [&second, &third] {
+        mount_method(server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10")))).await;
+    }
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&third), mk_rpc(&first), mk_rpc(&second)])), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(urls(&handler.ordered_rpcs().await), [url_key(&first), url_key(&second), url_key(&third)]);
+
+    let stream = handler.healthy_rpcs_stream(HealthCheckLevel::Live).await;
+    let taken: Vec<OrderedRpc> = stream.take(1).collect().await;
+    assert_eq!(urls(&taken), [url_key(&second)], "the failing first choice is skipped");
+    assert_eq!(taken[0].latency.as_ref().unwrap().failure_count, 0);
+    assert_eq!(count_method(&first, "eth_blockNumber").await, 1);
+    assert_eq!(count_method(&second, "eth_blockNumber").await, 1);
+    assert_eq!(count_method(&third, "eth_blockNumber").await, 0, "nothing past the item taken is probed");
+}
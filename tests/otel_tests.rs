@@ -0,0 +1,143 @@
+#![cfg(feature = "otel")]
+
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use opentelemetry::{
+    context::FutureExt,
+    propagation::TextMapPropagator,
+    trace::{SpanKind, Status, TraceContextExt},
+    KeyValue,
+};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{InMemorySpanExporter, SdkTracerProvider, SpanData},
+};
+use serde_json::json;
+use wiremock::{
+    matchers::{body_partial_json, header_exists, method},
+    Mock, MockServer, ResponseTemplate,
+};
+
+async fn handler(rpcs: Vec<impl Into<RpcConfig>>, settings_override: impl FnOnce(HandlerSettings) -> HandlerSettings) -> (Arc<RpcHandler>, InMemorySpanExporter) {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    let components = HandlerComponents { tracer_provider: Some(Arc::new(provider)), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings_override(settings(rpcs))), None, components).await.unwrap();
+    handler.init().await.unwrap();
+    exporter.reset();
+    (handler, exporter)
+}
+
+/// The value of `key` among `attributes`, as a string.
+fn attribute(attributes: &[KeyValue], key: &str) -> Option<String> {
+    attributes.iter().find(|attribute| attribute.key.as_str() == key).map(|attribute| attribute.value.to_string())
+}
+
+fn traceparent(span: &SpanData) -> String {
+    format!("00-{}-{}-01", span.span_context.trace_id(), span.span_context.span_id())
+}
+
+#[tokio::test]
+async fn test_a_call_that_fails_over_once_nests_its_attempts_and_propagates_traceparent() {
+    let primary = MockServer::start().await;
+    mount_probe(&primary, "0x10", Duration::ZERO).await;
+    mount_method(&primary, "eth_getBalance", ResponseTemplate::new(500)).await;
+    let fallback = MockServer::start().await;
+    mount_probe(&fallback, "0x10", Duration::ZERO).await;
+    // Answers only requests that carry trace context
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getBalance" })))
+        .and(header_exists("traceparent"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x5"))))
+        .mount(&fallback)
+        .await;
+    let rpcs = vec![tiered(&primary, 0), tiered(&fallback, 1)];
+    let (handler, exporter) = handler(rpcs, |settings| HandlerSettings { failover_policy: FailoverPolicy::TierStrict, ..settings }).await;
+
+    // The caller's context, as an incoming request would carry it
+    let incoming = HashMap::from([("traceparent".to_string(), "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string())]);
+    let parent = TraceContextPropagator::new().extract(&incoming);
+    let parent_span = parent.span().span_context().clone();
+    let response = handler.try_proxy_request(request("eth_getBalance", json!([]))).with_context(parent).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x5")));
+
+    let spans = exporter.get_finished_spans().unwrap();
+    assert_eq!(spans.len(), 3, "{spans:#?}");
+    let (failed, served, call) = (&spans[0], &spans[1], &spans[2]);
+    assert_eq!((call.name.as_ref(), &call.span_kind, call.parent_span_id), ("eth_getBalance", &SpanKind::Internal, parent_span.span_id()));
+    assert!(call.parent_span_is_remote);
+    assert_eq!(call.status, Status::Unset);
+    assert_eq!(call.instrumentation_scope.name(), "ez_web3_rpc");
+    assert!(spans.iter().all(|span| span.span_context.trace_id() == parent_span.trace_id()));
+    for (attempt, server) in [(failed, &primary), (served, &fallback)] {
+        assert_eq!((&attempt.span_kind, attempt.parent_span_id), (&SpanKind::Client, call.span_context.span_id()));
+        assert_eq!(attribute(&attempt.attributes, "rpc.system").as_deref(), Some("jsonrpc"));
+        assert_eq!(attribute(&attempt.attributes, "rpc.method").as_deref(), Some("eth_getBalance"));
+        assert_eq!(attribute(&attempt.attributes, "server.address").as_deref(), Some("127.0.0.1"));
+        assert_eq!(attribute(&attempt.attributes, "server.port"), Some(server.address().port().to_string()));
+    }
+    assert_eq!(attribute(&failed.attributes, "error.type").as_deref(), Some("http_status"));
+    assert!(matches!(failed.status, Status::Error { .. }));
+    assert_eq!(attribute(&served.attributes, "error.type"), None);
+
+    assert_eq!(call.events.len(), 1);
+    let failover = &call.events.events[0];
+    assert_eq!((failover.name.as_ref(), attribute(&failover.attributes, "error.type").as_deref()), ("failover", Some("http_status")));
+
+    // Each provider saw the context of its own attempt span
+    for (attempt, server) in [(failed, &primary), (served, &fallback)] {
+        let received = server.received_requests().await.unwrap();
+        let balance = received.iter().rev().find(|request| String::from_utf8_lossy(&request.body).contains("eth_getBalance")).unwrap();
+        assert_eq!(balance.headers["traceparent"].to_str().unwrap(), traceparent(attempt));
+    }
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_consensus_attempts_nest_under_the_consensus_span() {
+    let mut servers = Vec::new();
+    for _ in 0..3 {
+        let server = MockServer::start().await;
+        mount_probe(&server, "0x10", Duration::ZERO).await;
+        mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10")))).await;
+        servers.push(server);
+    }
+    let (handler, exporter) = handler(servers.iter().map(mk_rpc).collect(), |settings| settings).await;
+    let calls = RpcCalls::new(handler);
+
+    let options = ConsensusOptions { concurrency: Some(3), ..ConsensusOptions::default() };
+    let head: String = calls.consensus(&block_number(), 1.0, Some(options)).await.unwrap();
+    assert_eq!(head, "0x10");
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let consensus = spans.last().unwrap();
+    assert_eq!((consensus.name.as_ref(), consensus.parent_span_id), ("consensus eth_blockNumber", opentelemetry::trace::SpanId::INVALID), "a trace of its own without a parent");
+    let attempts: Vec<_> = spans.iter().filter(|span| span.span_kind == SpanKind::Client).collect();
+    assert_eq!(attempts.len(), 3, "{spans:#?}");
+    assert!(attempts.iter().all(|span| span.parent_span_id == consensus.span_context.span_id() && span.span_context.trace_id() == consensus.span_context.trace_id()));
+
+    let decision = consensus.events.iter().find(|event| event.name == "consensus.decision").unwrap();
+    assert_eq!((attribute(&decision.attributes, "responded").as_deref(), attribute(&decision.attributes, "majority_votes").as_deref()), (Some("3"), Some("3")));
+    for server in &servers {
+        let received = server.received_requests().await.unwrap();
+        let sent: Vec<_> = received.iter().filter_map(|request| request.headers.get("traceparent")).map(|value| value.to_str().unwrap().to_string()).collect();
+        assert!(attempts.iter().any(|attempt| sent.contains(&traceparent(attempt))));
+    }
+}
+
+#[tokio::test]
+async fn test_calls_are_not_traced_without_a_tracer_provider() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&server)])), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    handler.try_proxy_request(request("eth_chainId", json!([]))).await.unwrap();
+    let received = server.received_requests().await.unwrap();
+    assert!(received.iter().all(|request| !request.headers.contains_key("traceparent")));
+}
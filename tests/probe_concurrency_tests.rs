@@ -0,0 +1,145 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use common::*;
+use ez_web3_rpc::performance::{measure_rpcs_with_options, MeasureOptions, TimeoutPolicy};
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+const DELAY: Duration = Duration::from_millis(60);
+
+/// When each endpoint was first reached, shared by every mock in a test.
+#[derive(Clone, Default)]
+struct Arrivals(Arc<parking_lot::Mutex<Vec<(String, Instant)>>>);
+
+impl Arrivals {
+    /// The most endpoints ever being probed at once. An endpoint counts from its first request
+    /// until its answer is due, which is never after the prober lets go of its slot.
+    fn peak(&self) -> usize {
+        let mut first: Vec<(String, Instant)> = Vec::new();
+        for (url, at) in self.0.lock().iter() {
+            if !first.iter().any(|(seen, _)| seen == url) {
+                first.push((url.clone(), *at));
+            }
+        }
+        first.iter().map(|(_, start)| first.iter().filter(|(_, at)| at <= start && *start < *at + DELAY).count()).max().unwrap_or(0)
+    }
+}
+
+/// A probe-passing endpoint that answers after `DELAY`, or with 429 while `throttled` says so.
+async fn endpoint(arrivals: &Arrivals, throttled: impl Fn() -> bool + Send + Sync + 'static) -> MockServer {
+    let server = MockServer::start().await;
+    let (arrivals, url) = (arrivals.clone(), url_key(&server));
+    let respond = move |request: &Request| {
+        arrivals.0.lock().push((url.clone(), Instant::now()));
+        if throttled() {
+            return ResponseTemplate::new(429).set_delay(DELAY);
+        }
+        let body: Value = serde_json::from_slice(&request.body).unwrap();
+        let result = match body["method"].as_str() {
+            Some("eth_getCode") => json!(PERMIT2_CODE),
+            _ => json!({ "number": "0x10", "hash": "0xabc" }),
+        };
+        ResponseTemplate::new(200).set_body_json(rpc_response(1, result)).set_delay(DELAY)
+    };
+    Mock::given(method("POST")).respond_with(respond).mount(&server).await;
+    server
+}
+
+async fn endpoints(count: usize, arrivals: &Arrivals) -> Vec<MockServer> {
+    let mut servers = Vec::with_capacity(count);
+    for _ in 0..count {
+        servers.push(endpoint(arrivals, || false).await);
+    }
+    servers
+}
+
+fn options(max_concurrent_probes: usize, sweep_deadline: Duration) -> MeasureOptions {
+    MeasureOptions { max_concurrent_probes, sweep_deadline: Some(sweep_deadline), ..MeasureOptions::new(TimeoutPolicy::Fixed(Duration::from_secs(2))) }
+}
+
+#[tokio::test]
+async fn test_cap_is_never_exceeded_and_every_endpoint_is_probed() {
+    let arrivals = Arrivals::default();
+    let servers = endpoints(50, &arrivals).await;
+    let rpcs: Vec<Rpc> = servers.iter().map(mk_rpc).collect();
+    let progress = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let options = MeasureOptions {
+        on_progress: Some({
+            let progress = Arc::clone(&progress);
+            Arc::new(move |done, total| progress.lock().push((done, total)))
+        }),
+        ..options(5, Duration::from_secs(10))
+    };
+
+    let started = Instant::now();
+    let (latencies, results) = measure_rpcs_with_options(&reqwest::Client::new(), &rpcs, &options).await.unwrap();
+    assert!(started.elapsed() >= DELAY * 10, "50 endpoints five at a time take at least ten rounds");
+
+    assert_eq!(latencies.len(), 50);
+    assert_eq!(results.iter().map(|r| r.url.clone()).collect::<Vec<_>>(), servers.iter().map(url_key).collect::<Vec<_>>(), "results keep the input order");
+    assert!(results.iter().all(|r| r.success && r.bytecode_ok));
+    assert_eq!(arrivals.peak(), 5);
+    assert_eq!(arrivals.0.lock().len(), 100, "block and code once per endpoint");
+    assert_eq!(*progress.lock(), (1..=50).map(|done| (done, 50)).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_rate_limited_probes_halve_the_concurrency() {
+    // The first wave of eight endpoints is turned away, everything after that is answered
+    let arrivals = Arrivals::default();
+    let answered = Arc::new(AtomicUsize::new(0));
+    let mut servers = Vec::new();
+    for _ in 0..24 {
+        let answered = Arc::clone(&answered);
+        servers.push(endpoint(&arrivals, move || answered.fetch_add(1, Ordering::SeqCst) < 16).await);
+    }
+    let rpcs: Vec<Rpc> = servers.iter().map(mk_rpc).collect();
+
+    let (latencies, results) = measure_rpcs_with_options(&reqwest::Client::new(), &rpcs, &options(8, Duration::from_secs(10))).await.unwrap();
+    assert_eq!(results.len(), 24);
+    assert_eq!(latencies.len(), 16, "the throttled endpoints are reported failed");
+    assert!(results[..8].iter().all(|r| !r.success));
+
+    // One wave of 429s halves the limit once, so the rest go at most four at a time
+    let later = Arrivals(Arc::new(parking_lot::Mutex::new(arrivals.0.lock()[16..].to_vec())));
+    assert_eq!(arrivals.peak(), 8);
+    assert_eq!(later.peak(), 4);
+}
+
+#[tokio::test]
+async fn test_sweep_deadline_returns_partial_results() {
+    let arrivals = Arrivals::default();
+    let servers = endpoints(20, &arrivals).await;
+    let rpcs: Vec<Rpc> = servers.iter().map(mk_rpc).collect();
+    let progressed = Arc::new(AtomicUsize::new(0));
+    let options = MeasureOptions {
+        on_progress: Some({
+            let progressed = Arc::clone(&progressed);
+            Arc::new(move |done, _| progressed.store(done, Ordering::SeqCst))
+        }),
+        ..options(2, DELAY * 4)
+    };
+
+    let started = Instant::now();
+    let (latencies, results) = measure_rpcs_with_options(&reqwest::Client::new(), &rpcs, &options).await.unwrap();
+    assert!(started.elapsed() < DELAY * 8, "the sweep stops at its deadline");
+    assert!(!results.is_empty() && results.len() < 20, "got {} results", results.len());
+    assert_eq!(latencies.len(), results.len());
+    assert_eq!(progressed.load(Ordering::SeqCst), results.len());
+    let positions: Vec<usize> = results.iter().map(|r| servers.iter().position(|s| url_key(s) == r.url).unwrap()).collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "partial results keep the input order");
+
+    // Nothing cut off keeps running afterwards
+    let sent = arrivals.0.lock().len();
+    tokio::time::sleep(DELAY * 2).await;
+    assert_eq!(arrivals.0.lock().len(), sent);
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use common::*;
+use ez_web3_rpc::performance::{ProbeTimeouts, TimeoutPolicy};
+use ez_web3_rpc::*;
+use wiremock::MockServer;
+
+const KNOWN: &str = "https://known.example/";
+const NEW: &str = "https://new.example/";
+
+fn adaptive(history: Vec<u64>) -> TimeoutPolicy {
+    TimeoutPolicy::Adaptive {
+        fallback: Duration::from_millis(3000),
+        settings: AdaptiveProbeTimeout { multiplier: 3.0, floor_ms: 500, ceiling_ms: 8000 },
+        history,
+        known_healthy: HashSet::from([KNOWN.to_string()]),
+    }
+}
+
+#[test]
+fn test_fast_history_tightens_the_timeout_to_the_floor() {
+    let policy = adaptive((20..=120).step_by(5).collect());
+
+    assert_eq!(policy.timeout_for(KNOWN), Duration::from_millis(500));
+    assert_eq!(policy.timeout_for(NEW), Duration::from_millis(3000), "never-seen endpoints keep the static timeout");
+    assert_eq!(policy.timeouts(), ProbeTimeouts { fallback_ms: 3000, adaptive_ms: Some(500), p95_ms: Some(115) });
+}
+
+#[test]
+fn test_slow_but_healthy_history_relaxes_past_the_static_timeout() {
+    let mut history = vec![1200; 18];
+    history.extend([1500, 1500]);
+    let policy = adaptive(history);
+
+    assert_eq!(policy.timeout_for(KNOWN), Duration::from_millis(4500));
+    assert_eq!(policy.timeout_for(NEW), Duration::from_millis(3000));
+
+    let policy = adaptive(vec![5000; 10]);
+    assert_eq!(policy.timeout_for(KNOWN), Duration::from_millis(8000), "clamped to the ceiling");
+}
+
+#[test]
+fn test_no_history_falls_back_to_the_static_timeout() {
+    let policy = adaptive(Vec::new());
+
+    assert_eq!(policy.timeout_for(KNOWN), Duration::from_millis(3000));
+    assert_eq!(policy.timeouts(), ProbeTimeouts { fallback_ms: 3000, adaptive_ms: None, p95_ms: None });
+    assert_eq!(TimeoutPolicy::Fixed(Duration::from_millis(750)).timeout_for(KNOWN), Duration::from_millis(750));
+}
+
+fn adaptive_settings(rpcs: Vec<Rpc>, static_ms: u64, floor_ms: u64) -> HandlerSettings {
+    HandlerSettings {
+        rpc_probe_timeout_ms: static_ms,
+        adaptive_probe_timeout: Some(AdaptiveProbeTimeout { multiplier: 3.0, floor_ms, ceiling_ms: 5000 }),
+        ..settings(rpcs)
+    }
+}
+
+#[tokio::test]
+async fn test_fast_probes_drop_a_stalled_endpoint_sooner() {
+    let steady = MockServer::start().await;
+    let stalling = MockServer::start().await;
+    mount_probe(&steady, "0x10", Duration::ZERO).await;
+    mount_probe(&stalling, "0x10", Duration::ZERO).await;
+
+    let handler = RpcHandler::new(config(adaptive_settings(vec![mk_rpc(&steady), mk_rpc(&stalling)], 3000, 200)), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.probe_timeouts().unwrap().adaptive_ms, None, "no history before the first probe");
+
+    handler.refresh().await.unwrap();
+    assert_eq!(handler.probe_timeouts().unwrap().adaptive_ms, Some(200));
+
+    stalling.reset().await;
+    mount_probe(&stalling, "0x10", Duration::from_millis(1500)).await;
+    let started = Instant::now();
+    handler.refresh().await.unwrap();
+
+    assert!(started.elapsed() < Duration::from_millis(1000), "the stalled endpoint held refresh for {:?}", started.elapsed());
+    assert!(!handler.get_latencies().await.contains_key(&url_key(&stalling)));
+    let report = handler.health_report().await;
+    assert_eq!(report.probe_timeouts.and_then(|t| t.adaptive_ms), Some(200));
+    assert!(report.to_string().contains("probe timeout 200ms"), "{report}");
+}
+
+#[tokio::test]
+async fn test_slow_but_healthy_history_keeps_endpoint_past_static_timeout() {
+    let slow = MockServer::start().await;
+    mount_probe(&slow, "0x10", Duration::from_millis(300)).await;
+
+    let handler = RpcHandler::new(config(adaptive_settings(vec![mk_rpc(&slow)], 500, 100)), None).await.unwrap();
+    handler.init().await.unwrap();
+    handler.refresh().await.unwrap();
+    let timeouts = handler.probe_timeouts().unwrap();
+    assert!(timeouts.adaptive_ms.unwrap() >= 900, "{timeouts:?}");
+
+    // Slower than the static timeout, but within what its own history allows
+    slow.reset().await;
+    mount_probe(&slow, "0x10", Duration::from_millis(700)).await;
+    handler.refresh().await.unwrap();
+    assert!(handler.get_latencies().await.contains_key(&url_key(&slow)));
+}
+
+#[tokio::test]
+async fn test_restored_history_applies_only_to_previously_healthy_endpoints() {
+    let known = MockServer::start().await;
+    let newcomer = MockServer::start().await;
+    mount_probe(&known, "0x10", Duration::ZERO).await;
+    mount_probe(&newcomer, "0x10", Duration::from_millis(400)).await;
+
+    let handler = RpcHandler::new(config(adaptive_settings(vec![mk_rpc(&known)], 1000, 100)), None).await.unwrap();
+    handler.restore_probe_history([10, 12, 15]);
+    handler.init().await.unwrap();
+    assert_eq!(handler.probe_timeouts().unwrap().adaptive_ms, Some(100));
+
+    // The newcomer would miss the adaptive timeout, but it hasn't been seen healthy yet
+    assert!(handler.add_rpc(mk_rpc(&newcomer)));
+    handler.refresh().await.unwrap();
+    assert!(handler.get_latencies().await.contains_key(&url_key(&newcomer)));
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use wiremock::{
+    matchers::{body_partial_json, method},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// A proof over a small generated state: an account with 25 storage slots among 40 accounts,
+/// proving slots 1 and 2 and the absence of slot 3.
+const FIXTURE: &str = include_str!("fixtures/get_proof.json");
+
+/// A mainnet proof of a contract account and the absence of its slot 0, deep enough to go through
+/// every kind of node a production trie has.
+const MAINNET_FIXTURE: &str = include_str!("fixtures/get_proof_mainnet.json");
+
+fn fixture() -> (ProofResponse, String) {
+    load(FIXTURE)
+}
+
+fn load(fixture: &str) -> (ProofResponse, String) {
+    let fixture: Value = serde_json::from_str(fixture).unwrap();
+    let state_root = fixture["stateRoot"].as_str().unwrap().to_string();
+    (serde_json::from_value(fixture["proof"].clone()).unwrap(), state_root)
+}
+
+#[test]
+fn test_fixture_proof_verifies_against_its_state_root() {
+    let (proof, state_root) = fixture();
+    assert_eq!(proof.storage_proof.len(), 3);
+    assert_eq!(proof.storage_proof[2].value, "0x0", "slot 3 is proven empty");
+    assert!(verify_storage_value(&proof, &state_root).unwrap());
+
+    // Node order within a proof doesn't matter, nor do hex case and zero padding
+    let mut reformatted = proof.clone();
+    reformatted.account_proof.reverse();
+    reformatted.storage_proof[0].proof.reverse();
+    reformatted.storage_proof[0].value = format!("0x{:0>64}", "DEADBEEF");
+    reformatted.storage_hash = reformatted.storage_hash.to_uppercase().replace("0X", "0x");
+    assert!(verify_storage_value(&reformatted, &state_root.to_uppercase().replace("0X", "0x")).unwrap());
+}
+
+#[test]
+fn test_mainnet_proof_verifies_against_its_state_root() {
+    let (proof, state_root) = load(MAINNET_FIXTURE);
+    assert_eq!(proof.account_proof.len(), 8);
+    assert!(verify_storage_value(&proof, &state_root).unwrap());
+
+    let mut claimed = proof.clone();
+    claimed.storage_proof[0].value = "0x1".into();
+    assert!(!verify_storage_value(&claimed, &state_root).unwrap(), "slot 0 is proven empty");
+    let mut code = proof.clone();
+    code.code_hash = format!("0x{}", "00".repeat(32));
+    assert!(!verify_storage_value(&code, &state_root).unwrap());
+    let mut missing = proof.clone();
+    missing.account_proof.remove(3);
+    assert!(!verify_storage_value(&missing, &state_root).unwrap());
+}
+
+#[test]
+fn test_tampered_proofs_fail_verification() {
+    let (proof, state_root) = fixture();
+    let tampered = |tamper: fn(&mut ProofResponse)| {
+        let mut proof = proof.clone();
+        tamper(&mut proof);
+        verify_storage_value(&proof, &state_root).unwrap()
+    };
+
+    assert!(!tampered(|proof| proof.storage_proof[0].value = "0xdeadbeee".into()));
+    assert!(!tampered(|proof| proof.storage_proof[2].value = "0x1".into()), "an empty slot can't be claimed");
+    assert!(!tampered(|proof| proof.balance = "0x1".into()));
+    assert!(!tampered(|proof| proof.nonce = "0x2".into()));
+    assert!(!tampered(|proof| proof.storage_proof[1].key = "0x4".into()));
+    assert!(!tampered(|proof| proof.account_proof.truncate(1)));
+    assert!(!tampered(|proof| {
+        // Flip a byte inside the leaf: its hash no longer matches the reference to it
+        let leaf = proof.storage_proof[0].proof.last_mut().unwrap();
+        let flipped = if leaf.ends_with('0') { '1' } else { '0' };
+        leaf.pop();
+        leaf.push(flipped);
+    }));
+    assert!(!verify_storage_value(&proof, &format!("0x{}", "11".repeat(32))).unwrap(), "another block's root");
+}
+
+#[test]
+fn test_malformed_proofs_are_errors() {
+    let (proof, state_root) = fixture();
+    let mut bad_hex = proof.clone();
+    bad_hex.account_proof[0] = "0xzz".into();
+    assert!(matches!(verify_storage_value(&bad_hex, &state_root), Err(RpcHandlerError::InvalidProof { .. })));
+    assert!(matches!(verify_storage_value(&proof, "0x1234"), Err(RpcHandlerError::InvalidProof { detail }) if detail.contains("32 bytes")));
+    assert!(matches!(verify_storage_value(&proof, "0xé1"), Err(RpcHandlerError::InvalidProof { .. })));
+
+    // A root node claiming a payload of usize::MAX bytes
+    let mut overlong = proof.clone();
+    overlong.account_proof = vec![format!("0xbf{}", "ff".repeat(8))];
+    let root = Keccak256::digest([0xbf].into_iter().chain([0xff; 8]).collect::<Vec<u8>>());
+    let root = format!("0x{}", root.iter().map(|byte| format!("{byte:02x}")).collect::<String>());
+    assert!(matches!(verify_storage_value(&overlong, &root), Err(RpcHandlerError::InvalidProof { detail }) if detail.contains("truncated")));
+}
+
+#[tokio::test]
+async fn test_get_proof_sends_the_typed_request() {
+    let (proof, _) = fixture();
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getProof", "params": [proof.address, ["0x1", "0x2"], "0x10"] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, serde_json::to_value(&proof).unwrap())))
+        .mount(&server)
+        .await;
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&server)])), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let fetched = RpcCalls::new(handler).get_proof(&proof.address, &["0x1", "0x2"], "0x10").await.unwrap();
+    assert_eq!(fetched, proof);
+    assert!(methods::descriptor("eth_getProof").unwrap().archive_sensitive);
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_proof_consensus_ignores_node_differences() {
+    let (proof, state_root) = fixture();
+    let mut reordered = proof.clone();
+    reordered.account_proof.reverse();
+    reordered.storage_proof[1].proof.reverse();
+    let mut lying = proof.clone();
+    lying.storage_proof[0].value = "0x1".into();
+
+    let answer = |proof: &ProofResponse| rpc_response(1, serde_json::to_value(proof).unwrap());
+    let urls = [
+        serve_fixed(answer(&proof), Duration::ZERO).await,
+        serve_fixed(answer(&reordered), Duration::ZERO).await,
+        serve_fixed(answer(&lying), Duration::ZERO).await,
+    ];
+    let rpcs = urls
+        .iter()
+        .map(|url| Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) })
+        .collect();
+    let calls = RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap());
+    let options = ConsensusOptions { per_host_concurrency: Some(3), concurrency: Some(3), ..ConsensusOptions::default() };
+
+    let agreed = calls.get_proof_consensus(&proof.address, &["0x1", "0x2", "0x3"], "latest", 0.66, Some(options.clone())).await.unwrap();
+    assert_eq!(agreed.storage_proof[0].value, "0xdeadbeef");
+    assert!(verify_storage_value(&agreed, &state_root).unwrap());
+
+    // Exact comparison sees three different answers
+    let exact = ConsensusOptions { comparator: Some(std::sync::Arc::new(StableStringComparator)), ..options };
+    let err = calls.get_proof_consensus(&proof.address, &["0x1"], "latest", 0.66, Some(exact)).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::ConsensusFailure { .. }), "got {err:?}");
+
+    assert_eq!(ProofComparator.key(&json!(proof)), ProofComparator.key(&json!(reordered)));
+    assert_ne!(ProofComparator.key(&json!(proof)), ProofComparator.key(&json!(lying)));
+}
@@ -0,0 +1,153 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use common::*;
+use ez_web3_rpc::{clock::MockClock, provider::HostLimiter, *};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::json;
+use wiremock::{
+    matchers::{body_partial_json, method},
+    Mock, MockServer, Request, Respond, ResponseTemplate,
+};
+
+/// Answers `eth_call` advertising one request fewer left each time, and with a 429 once none are.
+struct Countdown {
+    remaining: AtomicU64,
+    refused: Arc<AtomicU64>,
+}
+
+impl Respond for Countdown {
+    fn respond(&self, _: &Request) -> ResponseTemplate {
+        match self.remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)) {
+            Ok(left) => ResponseTemplate::new(200)
+                .insert_header("x-ratelimit-remaining", (left - 1).to_string())
+                .insert_header("x-ratelimit-reset", "60")
+                .set_body_json(rpc_response(1, json!("0x1"))),
+            Err(_) => {
+                self.refused.fetch_add(1, Ordering::SeqCst);
+                ResponseTemplate::new(429)
+            }
+        }
+    }
+}
+
+fn eth_call() -> JsonRpcRequest {
+    request("eth_call", json!([{ "to": "0x0", "data": "0x" }, "latest"]))
+}
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    pairs.iter().map(|(name, value)| (reqwest::header::HeaderName::from_static(name), HeaderValue::from_str(value).unwrap())).collect()
+}
+
+fn limiter(clock: &MockClock, floor: u64) -> HostLimiter {
+    let rate_limit_headers = RateLimitHeaders { remaining_floor: floor, ..RateLimitHeaders::default() };
+    HostLimiter::new(HostLimits { rate_limit_headers: Some(rate_limit_headers), ..HostLimits::default() }).with_clock(Arc::new(clock.clone()))
+}
+
+#[tokio::test]
+async fn test_requests_move_to_the_other_host_before_any_429() {
+    let (limited, spare) = (MockServer::start().await, MockServer::start().await);
+    mount_probe(&limited, "0x10", Duration::ZERO).await;
+    mount_probe(&spare, "0x10", Duration::from_millis(50)).await;
+    let refused = Arc::new(AtomicU64::new(0));
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_call" })))
+        .respond_with(Countdown { remaining: AtomicU64::new(5), refused: Arc::clone(&refused) })
+        .mount(&limited)
+        .await;
+    mount_method(&spare, "eth_call", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+
+    // The same loopback server under a name of its own, so the two are different hosts
+    let limited_rpc = Rpc { url: limited.uri().replace("127.0.0.1", "localhost").parse().unwrap(), ..mk_rpc(&limited) };
+    let host_limits = HostLimits { rate_limit_headers: Some(RateLimitHeaders { remaining_floor: 3, ..RateLimitHeaders::default() }), ..HostLimits::default() };
+    let settings = HandlerSettings { host_limits, ..settings(vec![limited_rpc, mk_rpc(&spare)]) };
+    let clock = MockClock::new();
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+
+    for _ in 0..8 {
+        handler.try_proxy_request(eth_call()).await.unwrap();
+    }
+    // Three went out before the quota fell under the floor, and the rest only to the spare host
+    assert_eq!(count_method(&limited, "eth_call").await, 3);
+    assert_eq!(count_method(&spare, "eth_call").await, 8);
+    assert_eq!(refused.load(Ordering::SeqCst), 0);
+    let report = handler.health_report().await;
+    assert_eq!(report.host_quotas.get("localhost"), Some(&HostQuota { remaining: 2, reset_in_ms: 60_000, paced: true }));
+    assert!(report.to_string().contains("paced by quota: localhost 2 left, resets in 60s"), "{report}");
+    let plan = handler.plan_request(&eth_call(), None).await.unwrap();
+    assert_eq!(plan.urls.last().map(|planned| &planned.placement), Some(&Placement::QuotaPaced));
+
+    // Past the advertised reset the quota is forgotten and the host is raced again
+    clock.advance(Duration::from_secs(61));
+    assert!(handler.health_report().await.host_quotas.is_empty());
+    handler.try_proxy_request(eth_call()).await.unwrap();
+    assert_eq!(count_method(&limited, "eth_call").await, 4);
+}
+
+#[tokio::test]
+async fn test_quota_left_is_spread_over_the_reset_window() {
+    let clock = MockClock::new();
+    let limiter = limiter(&clock, 10);
+    let url = "https://rpc.example.com/";
+    limiter.observe(url, &headers(&[("x-ratelimit-remaining", "4"), ("x-ratelimit-reset", "40")]));
+
+    // Three left after this one, across 40s: one request every 10s
+    assert!(limiter.try_acquire(url).is_some());
+    assert!(limiter.try_acquire(url).is_none());
+    clock.advance(Duration::from_secs(9));
+    assert!(limiter.is_paced(url));
+    clock.advance(Duration::from_secs(1));
+    assert!(!limiter.is_paced(url));
+    assert_eq!(limiter.quotas()["rpc.example.com"].remaining, 3);
+    // Other hosts are untouched
+    assert!(limiter.try_acquire("https://other.example.com/").is_some());
+}
+
+#[tokio::test]
+async fn test_header_schemes_and_garbage() {
+    let clock = MockClock::new();
+    let limiter = limiter(&clock, 10);
+    let quota = |host: &str| limiter.quotas().get(host).map(|quota| (quota.remaining, quota.reset_in_ms));
+
+    limiter.observe("https://draft.example/", &headers(&[("ratelimit", "limit=100, remaining=7, reset=30")]));
+    assert_eq!(quota("draft.example"), Some((7, 30_000)));
+
+    let reset_at = clock.now_system().duration_since(UNIX_EPOCH).unwrap().as_secs() + 20;
+    limiter.observe("https://epoch.example/", &headers(&[("ratelimit-remaining", "50"), ("ratelimit-reset", &reset_at.to_string())]));
+    assert!(quota("epoch.example").is_some_and(|(remaining, reset_in_ms)| remaining == 50 && (19_000..=20_000).contains(&reset_in_ms)));
+
+    // A Retry-After on a success still means nothing is left until it passes
+    let date = chrono::DateTime::<chrono::Utc>::from(clock.now_system() + Duration::from_secs(90)).to_rfc2822();
+    limiter.observe("https://retry.example/", &headers(&[("retry-after", &date)]));
+    assert!(limiter.is_paced("https://retry.example/"));
+    limiter.observe("https://seconds.example/", &headers(&[("retry-after", "5")]));
+    assert_eq!(quota("seconds.example"), Some((0, 5_000)));
+
+    for garbage in [
+        headers(&[("x-ratelimit-remaining", "lots"), ("x-ratelimit-reset", "60")]),
+        headers(&[("x-ratelimit-remaining", "5")]),
+        headers(&[("x-ratelimit-remaining", "5"), ("x-ratelimit-reset", "-1")]),
+        headers(&[("ratelimit", "remaining; reset=")]),
+        headers(&[("retry-after", "tomorrow")]),
+        HeaderMap::new(),
+    ] {
+        limiter.observe("https://garbage.example/", &garbage);
+    }
+    assert_eq!(quota("garbage.example"), None);
+
+    // Without `rate_limit_headers` nothing is read
+    let off = HostLimiter::new(HostLimits::default());
+    off.observe("https://draft.example/", &headers(&[("retry-after", "5")]));
+    assert!(off.quotas().is_empty() && !off.is_paced("https://draft.example/"));
+}
@@ -0,0 +1,125 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+async fn handler_with_clock(servers: &[&MockServer], settings: HandlerSettings, clock: &MockClock) -> Arc<RpcHandler> {
+    let settings = HandlerSettings { network_rpcs: servers.iter().map(|server| mk_rpc(server).into()).collect(), ..settings };
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), Some(Strategy::Fastest), components).await.unwrap();
+    handler.init().await.unwrap();
+    handler
+}
+
+#[tokio::test]
+async fn freshly_initialized_handler_is_ready_and_live() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let clock = MockClock::new();
+    let handler = handler_with_clock(&[&server], settings(Vec::<Rpc>::new()), &clock).await;
+
+    let readiness = handler.readiness();
+    assert!(readiness.ready, "{readiness:?}");
+    assert_eq!(readiness.active_url.as_deref(), Some(url_key(&server).as_str()));
+    assert!(readiness.last_success.is_some());
+
+    let liveness = handler.liveness();
+    assert!(liveness.live, "{liveness:?}");
+    let tasks: Vec<BackgroundTask> = liveness.tasks.iter().map(|heartbeat| heartbeat.task).collect();
+    assert_eq!(tasks, vec![BackgroundTask::SloWatch, BackgroundTask::BudgetWatch, BackgroundTask::RotationWatch]);
+
+    let json = serde_json::to_value(&readiness).unwrap();
+    assert_eq!(json["ready"], true);
+    assert_eq!(json["reasons"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn stale_last_success_flips_readiness_but_not_liveness() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let clock = MockClock::new();
+    let thresholds = ReadinessThresholds { max_success_age_ms: 30_000, ..ReadinessThresholds::default() };
+    let handler = handler_with_clock(&[&server], HandlerSettings { readiness: thresholds, ..settings(Vec::<Rpc>::new()) }, &clock).await;
+    assert!(handler.readiness().ready);
+
+    drop(server);
+    clock.advance(Duration::from_secs(20));
+    assert!(handler.readiness().ready, "still within the threshold");
+
+    clock.advance(Duration::from_secs(20));
+    let readiness = handler.readiness();
+    assert!(!readiness.ready);
+    assert_eq!(readiness.reasons, vec![NotReady::NoRecentSuccess { since_success_ms: Some(40_000), max_age_ms: 30_000 }]);
+    assert_eq!(
+        serde_json::to_value(&readiness.reasons[0]).unwrap(),
+        serde_json::json!({ "reason": "no_recent_success", "since_success_ms": 40_000, "max_age_ms": 30_000 })
+    );
+
+    assert!(handler.liveness().live);
+}
+
+#[tokio::test]
+async fn aborted_background_task_flips_liveness() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let clock = MockClock::new();
+    let handler = handler_with_clock(&[&server], settings(Vec::<Rpc>::new()), &clock).await;
+    assert!(handler.liveness().live);
+
+    assert!(handler.abort_background_task(BackgroundTask::BudgetWatch).await);
+    let liveness = handler.liveness();
+    assert!(!liveness.live);
+    assert_eq!(liveness.reasons, vec![NotLive::TaskStopped { task: BackgroundTask::BudgetWatch }]);
+    assert!(handler.readiness().ready, "readiness doesn't depend on the background loops");
+}
+
+#[tokio::test]
+async fn stalled_periodic_task_flips_liveness() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    // The keepalive ping hangs in real time while the mock clock runs on
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1"))).set_delay(Duration::from_millis(500))).await;
+    let clock = MockClock::new();
+    let keepalive = KeepaliveSettings { keepalive_after_ms: 10_000, keepalive_interval_ms: 10_000 };
+    let thresholds = ReadinessThresholds { heartbeat_grace_ms: 5_000, ..ReadinessThresholds::default() };
+    let settings = HandlerSettings { keepalive: Some(keepalive), readiness: thresholds, ..settings(Vec::<Rpc>::new()) };
+    let handler = handler_with_clock(&[&server], settings, &clock).await;
+
+    clock.wait_for_sleepers(1).await;
+    clock.advance(Duration::from_secs(10));
+    while count_method(&server, "eth_chainId").await == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let keepalive_beat = handler.liveness().tasks.into_iter().find(|heartbeat| heartbeat.task == BackgroundTask::Keepalive).unwrap();
+    assert_eq!((keepalive_beat.since_beat_ms, keepalive_beat.period_ms), (10_000, Some(10_000)));
+    assert!(handler.liveness().live);
+
+    clock.advance(Duration::from_secs(20));
+    let liveness = handler.liveness();
+    assert_eq!(
+        liveness.reasons,
+        vec![NotLive::TaskStalled { task: BackgroundTask::Keepalive, since_beat_ms: 30_000, expected_within_ms: 15_000 }]
+    );
+
+    // Once the ping comes back the loop beats again
+    clock.wait_for_sleepers(1).await;
+    assert!(handler.liveness().live);
+}
+
+#[tokio::test]
+async fn shut_down_handler_is_neither_ready_nor_live() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let clock = MockClock::new();
+    let handler = handler_with_clock(&[&server], settings(Vec::<Rpc>::new()), &clock).await;
+
+    handler.shutdown();
+    assert!(handler.readiness().reasons.contains(&NotReady::ShutDown));
+    assert_eq!(handler.liveness().reasons, vec![NotLive::ShutDown]);
+}
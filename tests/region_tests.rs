@@ -0,0 +1,140 @@
+mod common;
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::{region::*, *};
+use wiremock::MockServer;
+
+#[test]
+fn test_chainlist_hostnames_map_to_the_region_they_name() {
+    let corpus = [
+        ("virginia.rpc.blxrbdn.com", Some(Region::NorthAmerica)),
+        ("uk.rpc.blxrbdn.com", Some(Region::Europe)),
+        ("singapore.rpc.blxrbdn.com", Some(Region::AsiaPacific)),
+        ("eth.llamarpc.com", None),
+        ("rpc.ankr.com", None),
+        ("ethereum-rpc.publicnode.com", None),
+        ("cloudflare-eth.com", None),
+        ("rpc.flashbots.net", None),
+        ("eth-mainnet.public.blastapi.io", None),
+        ("eth-mainnet.g.alchemy.com", None),
+        ("mainnet.infura.io", None),
+        ("rpc.mevblocker.io", None),
+        ("eth.drpc.org", None),
+        ("1rpc.io", None),
+        ("ethereum.blockpi.network", None),
+        ("rpc.payload.de", None),
+        ("eth.merkle.io", None),
+        ("rpc.builder0x69.io", None),
+        ("bsc-dataseed1.binance.org", None),
+        ("bsc-dataseed.bnbchain.org", None),
+        ("polygon-rpc.com", None),
+        ("arb1.arbitrum.io", None),
+        ("mainnet.optimism.io", None),
+        ("mainnet.base.org", None),
+        ("rpc.gnosischain.com", None),
+        ("api.avax.network", None),
+        ("nd-123-456-789.p2pify.com", None),
+    ];
+    for (host, region) in corpus {
+        assert_eq!(region_of_host(host), region, "{host}");
+    }
+}
+
+#[test]
+fn test_cloud_region_codes_need_a_direction() {
+    assert_eq!(region_of_host("rpc.us-east-1.example.com"), Some(Region::NorthAmerica));
+    assert_eq!(region_of_host("eu-central.rpc.example.com"), Some(Region::Europe));
+    assert_eq!(region_of_host("ap-southeast.rpc.example.com"), Some(Region::AsiaPacific));
+    assert_eq!(region_of_host("node-sa-east-1.example.com"), Some(Region::SouthAmerica));
+    assert_eq!(region_of_host("useast2.example.com"), Some(Region::NorthAmerica));
+    assert_eq!(region_of_host("eu1.rpc.example.com"), Some(Region::Europe));
+    // Without one, `sa` and `me` are just letters
+    assert_eq!(region_of_host("sa.rpc.example.com"), None);
+    assert_eq!(region_of_host("rpc.me.example.com"), None);
+    // Nor does a top-level domain or an address say where the servers are
+    assert_eq!(region_of_host("rpc.example.eu"), None);
+    assert_eq!(region_of_host("10.0.0.1"), None);
+}
+
+#[test]
+fn test_hints_come_before_the_hostname() {
+    let hints = BTreeMap::from([
+        ("mycorp.net".to_string(), Region::Europe),
+        ("eu.rpc.example.com".to_string(), Region::AsiaPacific),
+        ("127.0.0.1:8545".to_string(), Region::NorthAmerica),
+    ]);
+    assert_eq!(region_of("https://rpc.mainnet.mycorp.net/v1/key", &hints), Some(Region::Europe));
+    assert_eq!(region_of("https://eu.rpc.example.com", &hints), Some(Region::AsiaPacific));
+    assert_eq!(region_of("http://127.0.0.1:8545", &hints), Some(Region::NorthAmerica));
+    assert_eq!(region_of("http://127.0.0.1:8546", &hints), None);
+    assert_eq!(region_of("https://us-west.other.com", &hints), Some(Region::NorthAmerica));
+}
+
+async fn answering_in(probe_delay_ms: u64) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::from_millis(probe_delay_ms)).await;
+    server
+}
+
+async fn slow_down(server: &MockServer, probe_delay_ms: u64) {
+    server.reset().await;
+    mount_probe(server, "0x10", Duration::from_millis(probe_delay_ms)).await;
+}
+
+fn hint(server: &MockServer) -> String {
+    format!("127.0.0.1:{}", server.address().port())
+}
+
+async fn handler(servers: &[(&MockServer, Region)], affinity: Option<RegionAffinityPolicy>) -> Arc<RpcHandler> {
+    let handler_settings = HandlerSettings {
+        region_hints: servers.iter().map(|(server, region)| (hint(server), *region)).collect(),
+        region_affinity: affinity,
+        ..settings(servers.iter().map(|(server, _)| mk_rpc(server)).collect())
+    };
+    init_handler(handler_settings).await
+}
+
+fn ordered_urls(ordered: &[OrderedRpc]) -> Vec<String> {
+    ordered.iter().map(|ordered| ordered.rpc.url.to_string()).collect()
+}
+
+#[tokio::test]
+async fn test_affinity_penalty_breaks_a_near_tie_toward_the_preferred_region() {
+    let (us, eu) = (answering_in(30).await, answering_in(60).await);
+    let servers = [(&us, Region::NorthAmerica), (&eu, Region::Europe)];
+
+    let latency_only = handler(&servers, None).await;
+    assert_eq!(latency_only.get_provider_url().await.unwrap(), url_key(&us));
+
+    let affinity = RegionAffinityPolicy { preferred: Some(Region::Europe), penalty_ms: 200, switch_margin_ms: 0, cross_region_switch_margin_ms: 0 };
+    let preferring_eu = handler(&servers, Some(affinity)).await;
+    assert_eq!(preferring_eu.get_provider_url().await.unwrap(), url_key(&eu));
+    // A tiebreak only: the other region is still there to fail over to
+    assert_eq!(ordered_urls(&preferring_eu.ordered_rpcs().await), [url_key(&eu), url_key(&us)]);
+
+    let report = preferring_eu.health_report().await;
+    let regions: Vec<Option<Region>> = report.endpoints.iter().map(|endpoint| endpoint.region).collect();
+    assert!(regions.contains(&Some(Region::Europe)) && regions.contains(&Some(Region::NorthAmerica)), "{regions:?}");
+    assert!(report.to_string().contains("[europe]"), "{report}");
+}
+
+#[tokio::test]
+async fn test_switching_regions_takes_a_larger_improvement_than_staying() {
+    let (active, abroad, neighbor) = (answering_in(10).await, answering_in(150).await, answering_in(600).await);
+    let servers = [(&active, Region::Europe), (&abroad, Region::NorthAmerica), (&neighbor, Region::Europe)];
+    let affinity = RegionAffinityPolicy { preferred: None, penalty_ms: 0, switch_margin_ms: 30, cross_region_switch_margin_ms: 1_000 };
+    let handler = handler(&servers, Some(affinity)).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&active));
+
+    // The endpoint abroad is now faster, but by less than the cross-region margin
+    slow_down(&active, 350).await;
+    handler.refresh().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&active));
+
+    // One in the same region only needs to clear the smaller margin
+    slow_down(&neighbor, 10).await;
+    handler.refresh().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&neighbor));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use futures::future::join_all;
+use wiremock::MockServer;
+
+async fn probed() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    server
+}
+
+fn config_for(server: &MockServer) -> HandlerConfig {
+    config(settings(vec![mk_rpc(server)]))
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_share_one_initialization() {
+    let server = probed().await;
+    let registry = HandlerRegistry::new(RegistryOptions::default());
+
+    let handlers = join_all((0..100).map(|_| registry.get_or_create(config_for(&server)))).await;
+    let handlers: Vec<Arc<RpcHandler>> = handlers.into_iter().map(Result::unwrap).collect();
+    assert!(handlers.iter().all(|handler| Arc::ptr_eq(handler, &handlers[0])));
+    assert_eq!(count_method(&server, "eth_getBlockByNumber").await, 1);
+    assert_eq!(registry.stats(), RegistryStats { alive: 1, hits: 99, misses: 1, idle_evictions: 0 });
+
+    // The log level doesn't change what the handler does
+    let mut quiet = settings(vec![mk_rpc(&server)]);
+    quiet.log_level = LogLevel::Error;
+    assert!(Arc::ptr_eq(&registry.get_or_create(config(quiet)).await.unwrap(), &handlers[0]));
+    assert_eq!(count_method(&server, "eth_getBlockByNumber").await, 1);
+}
+
+#[tokio::test]
+async fn test_differing_configs_get_their_own_handler() {
+    let (a, b) = (probed().await, probed().await);
+    let registry = HandlerRegistry::new(RegistryOptions::default());
+
+    let first = registry.get_or_create(config_for(&a)).await.unwrap();
+    let second = registry.get_or_create(config_for(&b)).await.unwrap();
+    let mut strict = settings(vec![mk_rpc(&a)]);
+    strict.validation_mode = ValidationMode::Strict;
+    let third = registry.get_or_create(config(strict)).await.unwrap();
+    assert!(!Arc::ptr_eq(&first, &second) && !Arc::ptr_eq(&first, &third));
+    assert_eq!(registry.stats().alive, 3);
+
+    assert!(registry.evict(&config_for(&b)).unwrap());
+    assert!(!registry.evict(&config_for(&b)).unwrap());
+    assert_eq!(registry.stats().alive, 2);
+    // A failed creation isn't kept
+    assert!(registry.get_or_create(config(settings(Vec::<Rpc>::new()))).await.is_err());
+    assert_eq!(registry.stats(), RegistryStats { alive: 2, hits: 0, misses: 3, idle_evictions: 0 });
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_idle_handlers_are_shut_down() {
+    let (used, unused) = (probed().await, probed().await);
+    let clock = MockClock::new();
+    let max_idle = Duration::from_secs(60);
+    let registry = HandlerRegistry::new(RegistryOptions { max_idle: Some(max_idle), clock: Some(Arc::new(clock.clone())) });
+
+    registry.get_or_create(config_for(&used)).await.unwrap();
+    registry.get_or_create(config_for(&unused)).await.unwrap();
+    clock.wait_for_sleepers(1).await;
+
+    clock.advance(max_idle / 2);
+    registry.get_or_create(config_for(&used)).await.unwrap();
+    clock.wait_for_sleepers(1).await;
+    clock.advance(max_idle / 2);
+    while registry.stats().idle_evictions == 0 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(registry.stats().alive, 1);
+
+    // Asking again after eviction builds a new handler
+    registry.get_or_create(config_for(&unused)).await.unwrap();
+    assert_eq!(count_method(&unused, "eth_getBlockByNumber").await, 2);
+    assert_eq!(registry.stats().misses, 3);
+}
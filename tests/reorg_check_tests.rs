@@ -0,0 +1,112 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{body_partial_json, method},
+    Mock, MockServer, ResponseTemplate,
+};
+
+const TX: &str = "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060";
+const ORPHANED: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+const CANONICAL: &str = "0x2222222222222222222222222222222222222222222222222222222222222222";
+
+fn receipt(block_hash: &str) -> Value {
+    json!({ "transactionHash": TX, "blockNumber": "0x10", "blockHash": block_hash, "status": "0x1" })
+}
+
+/// Answer `eth_getBlockByNumber` for block 16 with `hash` after `delay`, ahead of the probe's mock.
+async fn mount_block_16(server: &MockServer, hash: &str, delay: Duration) {
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getBlockByNumber", "params": ["0x10", false] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x10", "hash": hash }))).set_delay(delay))
+        .with_priority(1)
+        .mount(server)
+        .await;
+}
+
+/// An endpoint on an orphaned fork that serves first, and one on the canonical chain that loses
+/// any race with it.
+async fn endpoints() -> (MockServer, MockServer) {
+    let (orphaned, canonical) = (MockServer::start().await, MockServer::start().await);
+    mount_probe(&orphaned, "0x20", Duration::ZERO).await;
+    mount_probe(&canonical, "0x20", Duration::from_millis(200)).await;
+    mount_block_16(&canonical, CANONICAL, Duration::from_millis(100)).await;
+    (orphaned, canonical)
+}
+
+async fn calls(orphaned: &MockServer, canonical: &MockServer) -> RpcCalls {
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(orphaned), mk_rpc(canonical)])), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(orphaned));
+    RpcCalls::new(handler)
+}
+
+fn verify(max_retries: u32) -> ConsistencyCheck {
+    ConsistencyCheck::VerifyCanonical(CanonicalRetry { max_retries, backoff: Duration::from_millis(10) })
+}
+
+/// Block 16 lookups `server` received, the probes' `latest` left out.
+async fn block_16_lookups(server: &MockServer) -> usize {
+    let requests = server.received_requests().await.unwrap_or_default();
+    requests.iter().filter(|request| serde_json::from_slice::<Value>(&request.body).is_ok_and(|body| body["params"][0] == "0x10")).count()
+}
+
+#[tokio::test]
+async fn test_orphaned_receipt_is_retried_until_canonical() {
+    let (orphaned, canonical) = endpoints().await;
+    // The endpoint serves the orphaned receipt once, then catches up with the reorg
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_getTransactionReceipt" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, receipt(ORPHANED))))
+        .up_to_n_times(1)
+        .mount(&orphaned)
+        .await;
+    mount_method(&orphaned, "eth_getTransactionReceipt", ResponseTemplate::new(200).set_body_json(rpc_response(1, receipt(CANONICAL)))).await;
+    let calls = calls(&orphaned, &canonical).await;
+
+    let found = calls.get_transaction_receipt(TX, verify(3)).await.unwrap();
+    assert_eq!(found, Some(receipt(CANONICAL)));
+    assert_eq!(count_method(&orphaned, "eth_getTransactionReceipt").await, 2);
+    // The canonical hash came from the other endpoint
+    assert_eq!(block_16_lookups(&orphaned).await, 0);
+    assert_eq!(block_16_lookups(&canonical).await, 2);
+}
+
+#[tokio::test]
+async fn test_receipt_that_stays_orphaned_fails_with_non_canonical_data() {
+    let (orphaned, canonical) = endpoints().await;
+    mount_method(&orphaned, "eth_getTransactionReceipt", ResponseTemplate::new(200).set_body_json(rpc_response(1, receipt(ORPHANED)))).await;
+    let calls = calls(&orphaned, &canonical).await;
+
+    let error = calls.get_transaction_receipt(TX, verify(2)).await.unwrap_err();
+    match error {
+        RpcHandlerError::NonCanonicalData { height, expected_hash, got_hash, provider } => {
+            assert_eq!(height, 16);
+            assert_eq!(expected_hash.as_deref(), Some(CANONICAL));
+            assert_eq!(got_hash, ORPHANED);
+            assert_eq!(provider, url_key(&orphaned));
+        }
+        other => panic!("expected NonCanonicalData, got {other:?}"),
+    }
+    assert_eq!(count_method(&orphaned, "eth_getTransactionReceipt").await, 3, "the first try and two retries");
+}
+
+#[tokio::test]
+async fn test_orphaned_block_is_caught_unless_the_check_is_skipped() {
+    let (orphaned, canonical) = endpoints().await;
+    mount_block_16(&orphaned, ORPHANED, Duration::ZERO).await;
+    mount_method(&orphaned, "eth_getTransactionReceipt", ResponseTemplate::new(200).set_body_json(rpc_response(1, receipt(ORPHANED)))).await;
+    let calls = calls(&orphaned, &canonical).await;
+
+    let error = calls.get_block_by_number("0x10", false, verify(0)).await.unwrap_err();
+    assert!(matches!(error, RpcHandlerError::NonCanonicalData { height: 16, ref got_hash, .. } if got_hash == ORPHANED), "{error:?}");
+
+    // Unchecked reads return what the endpoint served
+    let block = calls.get_block_by_number("0x10", false, ConsistencyCheck::Skip).await.unwrap().unwrap();
+    assert_eq!(block["hash"], ORPHANED);
+    assert_eq!(calls.get_transaction_receipt(TX, ConsistencyCheck::default()).await.unwrap(), Some(receipt(ORPHANED)));
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// `(url, method)` of every non-probe request, in arrival order.
+type Arrivals = Arc<Mutex<Vec<(String, String)>>>;
+
+/// A probe-passing endpoint that answers `ok_methods` with `"0x10"` and every other method with a 500.
+async fn serve_recording(arrivals: Arrivals, ok_methods: &'static [&'static str]) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let own_url = url.clone();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let (arrivals, url) = (Arc::clone(&arrivals), own_url.clone());
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let body = loop {
+                    let Ok(read) = stream.read(&mut chunk).await else { return };
+                    if read == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&chunk[..read]);
+                    if let Some(header_end) = request.windows(4).position(|w| w == b"\r\n\r\n")
+                        && let Ok(body) = serde_json::from_slice::<Value>(&request[header_end + 4..])
+                    {
+                        break body;
+                    }
+                };
+
+                let method = body["method"].as_str().unwrap_or_default().to_string();
+                let result = match method.as_str() {
+                    "eth_getBlockByNumber" => Some(json!({ "number": "0x10", "hash": "0xabc" })),
+                    "eth_getCode" => Some(json!(PERMIT2_CODE)),
+                    other => {
+                        arrivals.lock().unwrap().push((url, other.to_string()));
+                        ok_methods.contains(&other).then(|| json!("0x10"))
+                    }
+                };
+                let (status, payload) = match result {
+                    Some(result) => ("200 OK", rpc_response(1, result).to_string()),
+                    None => ("500 Internal Server Error", String::new()),
+                };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{payload}",
+                    payload.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+
+    url
+}
+
+fn rpc_at(url: &str, tier: Option<u8>) -> RpcConfig {
+    RpcConfig { url: Some(url.parse().unwrap()), tracking: None, tracking_details: None, is_open_source: Some(true), tier, ..RpcConfig::default() }
+}
+
+/// Check that `method`'s arrivals followed the plan: batch by batch, over every pass.
+///
+/// Endpoints within a batch are raced, so only batch membership is ordered.
+fn assert_followed(plan: &RequestPlan, arrivals: &Arrivals, method: &str) {
+    let observed: Vec<String> = arrivals.lock().unwrap().iter().filter(|(_, m)| m == method).map(|(url, _)| url.clone()).collect();
+    let batches = plan.batches();
+    let expected_len = batches.iter().map(Vec::len).sum::<usize>() * plan.retry_count as usize;
+    assert_eq!(observed.len(), expected_len, "observed {observed:?} for plan {batches:?}");
+
+    let mut rest = observed.as_slice();
+    for _ in 0..plan.retry_count {
+        for batch in &batches {
+            let (sent, after) = rest.split_at(batch.len());
+            assert_eq!(sent.iter().collect::<HashSet<_>>(), batch.iter().collect::<HashSet<_>>(), "observed {observed:?} for plan {batches:?}");
+            rest = after;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_tier_strict_plan_with_exclusion_and_retry_override() {
+    let arrivals = Arrivals::default();
+    let mut urls = Vec::new();
+    for _ in 0..4 {
+        urls.push(serve_recording(Arc::clone(&arrivals), &[]).await);
+    }
+    let settings = HandlerSettings {
+        failover_policy: FailoverPolicy::TierStrict,
+        ..settings(vec![rpc_at(&urls[0], Some(1)), rpc_at(&urls[1], Some(0)), rpc_at(&urls[2], Some(1)), rpc_at(&urls[3], Some(0))])
+    };
+    let handler = RpcHandler::new(config(settings), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let call = CallOptions { exclude: vec![urls[2].clone()], retry_count: Some(2), rpc_call_timeout_ms: Some(400), hold_on_total_failure: None, max_state_lag_blocks: None, tags: None };
+    let plan = handler.plan_request(&request("eth_chainId", json!([])), Some(call.clone())).await.unwrap();
+
+    assert_eq!(plan.batches().len(), 2, "tiers never share a batch");
+    assert_eq!(plan.batches()[0].iter().collect::<HashSet<_>>(), HashSet::from([&urls[1], &urls[3]]));
+    assert_eq!(plan.batches()[1], vec![urls[0].clone()]);
+    assert!(plan.urls.iter().all(|u| matches!(u.placement, Placement::Tier { tier } if Some(tier) == u.tier)));
+    assert_eq!(plan.excluded, vec![ExcludedUrl { url: urls[2].clone(), reason: Exclusion::Caller }]);
+    assert_eq!((plan.retry_count, plan.rpc_call_timeout_ms, plan.retry_delay_ms), (2, 400, 10));
+
+    assert!(handler.try_proxy_request_with(request("eth_chainId", json!([])), call).await.is_err());
+    assert_followed(&plan, &arrivals, "eth_chainId");
+}
+
+#[tokio::test]
+async fn test_routed_method_fails_over_to_latency_ordered_batches() {
+    let arrivals = Arrivals::default();
+    let designated = serve_recording(Arc::clone(&arrivals), &[]).await;
+    let mut pool = Vec::new();
+    for _ in 0..4 {
+        pool.push(serve_recording(Arc::clone(&arrivals), &[]).await);
+    }
+    let routed = |allow_failover| HandlerSettings {
+        routes: vec![RouteRule { methods: vec!["eth_call".into()], urls: vec![designated.clone()], allow_failover }],
+        ..settings(pool.iter().map(|url| rpc_at(url, None)).collect())
+    };
+    let handler = RpcHandler::new(config(routed(true)), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let plan = handler.plan_request(&request("eth_call", json!([])), None).await.unwrap();
+    assert_eq!(plan.urls[0].url, designated);
+    assert_eq!(plan.urls[0].placement, Placement::Routed);
+    assert_eq!(plan.batches().iter().map(Vec::len).collect::<Vec<_>>(), vec![1, 3, 1]);
+    assert!(plan.urls[1..].iter().all(|u| u.placement == Placement::Latency && u.latency_ms.is_some()));
+    let latencies: Vec<u64> = plan.urls[1..].iter().map(|u| u.latency_ms.unwrap()).collect();
+    assert!(latencies.is_sorted(), "{latencies:?}");
+
+    assert!(handler.try_proxy_request(request("eth_call", json!([]))).await.is_err());
+    assert_followed(&plan, &arrivals, "eth_call");
+
+    // Without failover the pool is listed as excluded, and nothing past the designated endpoint is planned
+    let handler = RpcHandler::new(config(routed(false)), None).await.unwrap();
+    handler.init().await.unwrap();
+    let plan = handler.plan_request(&request("eth_call", json!([])), None).await.unwrap();
+    assert_eq!(plan.batches(), vec![vec![designated.clone()]]);
+    assert!(plan.routed_only());
+    assert!(plan.excluded.iter().all(|e| e.reason == Exclusion::RouteWithoutFailover));
+    assert_eq!(plan.excluded.len(), pool.len());
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_cooling_down_endpoint_is_demoted_to_the_last_batch() {
+    let arrivals = Arrivals::default();
+    let mut healthy = Vec::new();
+    for _ in 0..3 {
+        healthy.push(serve_recording(Arc::clone(&arrivals), &["eth_blockNumber"]).await);
+    }
+    let failing = serve_recording(Arc::clone(&arrivals), &[]).await;
+    let rpcs = healthy.iter().chain([&failing]).map(|url| rpc_at(url, None)).collect();
+    let handler = RpcHandler::new(config(settings(rpcs)), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    // A failed consensus answer puts the endpoint in cooldown
+    let calls = RpcCalls::new(Arc::clone(&handler));
+    let options = Some(ConsensusOptions { per_host_concurrency: Some(4), ..ConsensusOptions::default() });
+    let _: String = calls.consensus(&block_number(), 0.5, options).await.unwrap();
+    assert!(calls.cooldown_remaining(&failing).await.is_some());
+
+    let plan = handler.plan_request(&request("eth_chainId", json!([])), None).await.unwrap();
+    let last = plan.urls.last().unwrap();
+    assert_eq!(last.url, failing);
+    assert!(matches!(last.placement, Placement::CoolingDown { remaining_ms } if remaining_ms > 0));
+    assert_eq!(plan.batches()[1], vec![failing.clone()]);
+
+    let encoded = serde_json::to_value(&plan).unwrap();
+    assert_eq!(encoded["urls"][3]["placement"]["rule"], "cooling_down");
+    assert_eq!(encoded["failover_policy"], "Latency");
+    assert_eq!(serde_json::from_value::<RequestPlan>(encoded).unwrap(), plan);
+
+    assert!(handler.try_proxy_request(request("eth_chainId", json!([]))).await.is_err());
+    assert_followed(&plan, &arrivals, "eth_chainId");
+}
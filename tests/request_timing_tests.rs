@@ -0,0 +1,72 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const SERVICE: Duration = Duration::from_millis(300);
+
+/// One endpoint taking `SERVICE` to answer, with a single slot for its host.
+async fn saturable(latency_slo: Option<LatencySlo>) -> (MockServer, Arc<RpcHandler>) {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_getBalance", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x5"))).set_delay(SERVICE)).await;
+    let mut settings = settings(vec![mk_rpc(&server)]);
+    settings.proxy_settings = Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: 2_000, connect_timeout_ms: None });
+    settings.host_limits = HostLimits { default_per_host: Some(1), per_host: HashMap::new(), rate_limit_headers: None };
+    settings.latency_slo = latency_slo;
+    let handler = init_handler(settings).await;
+    (server, handler)
+}
+
+/// Send a request that takes the host's only slot, then a second one that has to wait for it.
+async fn saturate(handler: &Arc<RpcHandler>) -> (AttributedResponse, AttributedResponse) {
+    let first = tokio::spawn({
+        let handler = Arc::clone(handler);
+        async move { handler.try_proxy_request_attributed_with(request("eth_getBalance", json!([])), CallOptions::default()).await }
+    });
+    while handler.health_report().await.host_in_flight.values().sum::<usize>() == 0 {
+        tokio::task::yield_now().await;
+    }
+    let second = handler.try_proxy_request_attributed_with(request("eth_getBalance", json!([])), CallOptions::default()).await.unwrap();
+    (first.await.unwrap().unwrap(), second)
+}
+
+#[tokio::test]
+async fn test_queued_request_reports_its_wait_apart_from_its_service() {
+    let (_server, handler) = saturable(None).await;
+    let (first, second) = saturate(&handler).await;
+
+    let service_ms = SERVICE.as_millis() as u64;
+    assert!(first.timing.queue_wait_ms < 50, "{:?}", first.timing);
+    assert!(first.timing.service_ms >= service_ms, "{:?}", first.timing);
+    // The second waited out the first's attempt, then took only as long as its own
+    assert!(second.timing.queue_wait_ms.abs_diff(first.timing.service_ms) < 100, "{:?} {:?}", first.timing, second.timing);
+    assert!((service_ms..service_ms + 150).contains(&second.timing.service_ms), "{:?}", second.timing);
+
+    let latency = handler.health_report().await.request_latency;
+    assert_eq!((latency.queue_wait.count, latency.service.count), (2, 2));
+    assert_eq!(latency.queue_wait.sum_ms, first.timing.queue_wait_ms + second.timing.queue_wait_ms);
+    assert!(latency.queue_wait.quantile_upper_bound_ms(1.0).unwrap() >= second.timing.queue_wait_ms);
+    assert_eq!(handler.metrics_snapshot().latency, latency);
+}
+
+#[tokio::test]
+async fn test_total_latency_slo_counts_queue_wait() {
+    for (measure, breaches) in [(SloMeasure::Service, 0), (SloMeasure::Total, 1)] {
+        // Each request is served within the target, but the queued one isn't answered within it
+        let slo = LatencySlo { target_ms: 450, violation_window_ms: 5_000, max_violations: 1, penalty_ms: 60_000, ignore_methods: Vec::new(), measure };
+        let (_server, handler) = saturable(Some(slo)).await;
+        let mut events = handler.subscribe();
+        saturate(&handler).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let breached = std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|event| matches!(event, HandlerEvent::LatencySloBreached { .. }))
+            .count();
+        assert_eq!(breached, breaches, "{measure:?}");
+    }
+}
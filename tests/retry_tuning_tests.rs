@@ -0,0 +1,135 @@
+mod common;
+
+use std::{collections::HashSet, time::Duration};
+
+use common::*;
+use ez_web3_rpc::{config::RetryTuningConfig, tuning::*, *};
+use wiremock::{MockServer, ResponseTemplate};
+
+fn bounds() -> RetryTuningConfig {
+    RetryTuningConfig {
+        window: Duration::from_secs(60),
+        min_samples: 20,
+        min_retry_count: 1,
+        max_retry_count: 5,
+        min_retry_delay: Duration::from_millis(10),
+        max_retry_delay: Duration::from_millis(1_000),
+        min_batch_size: 1,
+        max_batch_size: 4,
+    }
+}
+
+fn parameters(retry_count: u32, retry_delay_ms: u64, batch_size: usize) -> RetryParameters {
+    RetryParameters { retry_count, retry_delay_ms, batch_size }
+}
+
+#[test]
+fn test_each_rule_fires_on_its_evidence() {
+    let current = parameters(3, 400, 2);
+    let calm = OutcomeSummary { calls: 100, retried: 4, first_retry_answered: 4, ..Default::default() };
+    assert_eq!(decide(&calm, current, &bounds()), None);
+
+    // Later rounds that never answer are dropped one at a time
+    let wasted = OutcomeSummary { calls: 100, retried: 30, extra_rounds: 30, exhausted: 30, ..Default::default() };
+    assert_eq!(decide(&wasted, current, &bounds()), Some(Decision { rule: TuningRule::FewerRounds, parameters: parameters(2, 400, 2) }));
+    assert_eq!(decide(&wasted, parameters(1, 400, 2), &bounds()), None);
+
+    let rescued = OutcomeSummary { calls: 100, retried: 20, first_retry_answered: 10, timed_out_then_answered: 15, ..Default::default() };
+    assert_eq!(decide(&rescued, current, &bounds()), Some(Decision { rule: TuningRule::WiderBatches, parameters: parameters(3, 400, 3) }));
+    assert_eq!(decide(&rescued, parameters(3, 400, 4), &bounds()), None);
+
+    let quick = OutcomeSummary { calls: 100, retried: 10, first_retry_answered: 9, ..Default::default() };
+    assert_eq!(decide(&quick, current, &bounds()), Some(Decision { rule: TuningRule::ShorterDelay, parameters: parameters(3, 200, 2) }));
+    assert_eq!(decide(&quick, parameters(3, 15, 2), &bounds()).map(|decision| decision.parameters), Some(parameters(3, 10, 2)));
+
+    // Too few calls to go by, except for bringing parameters into bounds
+    let thin = OutcomeSummary { calls: 10, ..wasted };
+    assert_eq!(decide(&thin, current, &bounds()), None);
+    assert_eq!(decide(&thin, parameters(9, 5, 0), &bounds()), Some(Decision { rule: TuningRule::IntoBounds, parameters: parameters(5, 10, 1) }));
+}
+
+#[test]
+fn test_synthetic_outcome_streams_stay_within_bounds() {
+    let bounds = bounds();
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = |below: u64| {
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (seed >> 33) % below.max(1)
+    };
+
+    // Starting outside the bounds, as after a config change
+    let mut current = parameters(8, 5_000, 0);
+    let mut fired = HashSet::new();
+    for _ in 0..2_000 {
+        let calls = next(200);
+        let retried = next(calls + 1);
+        let extra_rounds = next(retried + 1);
+        let summary = OutcomeSummary {
+            calls,
+            retried,
+            first_retry_answered: next(retried + 1),
+            extra_rounds,
+            extra_rounds_answered: next(extra_rounds / 8 + 1),
+            timed_out_then_answered: next(calls / 6 + 1),
+            exhausted: next(extra_rounds + 1),
+        };
+        if let Some(decision) = decide(&summary, current, &bounds) {
+            let next = decision.parameters;
+            assert!((bounds.min_retry_count..=bounds.max_retry_count).contains(&next.retry_count), "{decision:?}");
+            assert!((10..=1_000).contains(&next.retry_delay_ms), "{decision:?}");
+            assert!((bounds.min_batch_size..=bounds.max_batch_size).contains(&next.batch_size), "{decision:?}");
+            // One step on one parameter per window, past the first
+            if decision.rule == TuningRule::IntoBounds {
+                current = next;
+                fired.insert(decision.rule);
+                continue;
+            }
+            let steps = usize::from(next.retry_count != current.retry_count) + usize::from(next.retry_delay_ms != current.retry_delay_ms) + usize::from(next.batch_size != current.batch_size);
+            assert_eq!(steps, 1, "{decision:?}");
+            current = next;
+            fired.insert(decision.rule);
+        }
+    }
+    assert_eq!(fired, HashSet::from([TuningRule::IntoBounds, TuningRule::FewerRounds, TuningRule::WiderBatches, TuningRule::ShorterDelay]));
+}
+
+#[tokio::test]
+async fn test_tuner_drops_rounds_that_never_answer_and_can_be_frozen_and_reset() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x20", Duration::ZERO).await;
+    mount_method(&server, "eth_blockNumber", ResponseTemplate::new(503)).await;
+
+    let tuning = RetryTuning { window_ms: 3_600_000, min_samples: 5, min_retry_count: 1, max_retry_count: 3, min_retry_delay_ms: 1, max_retry_delay_ms: 100, min_batch_size: 1, max_batch_size: 3 };
+    let handler_settings = HandlerSettings {
+        proxy_settings: Some(ProxySettings { retry_count: 3, retry_delay_ms: 1, rpc_call_timeout_ms: 1_000, connect_timeout_ms: None }),
+        retry_tuning: Some(tuning),
+        ..settings(vec![mk_rpc(&server)])
+    };
+    let handler = RpcHandler::new(config(handler_settings), None).await.unwrap();
+    handler.init().await.unwrap();
+    let block_number = || block_number();
+
+    for _ in 0..5 {
+        assert!(handler.try_proxy_request(block_number()).await.is_err());
+    }
+    assert_eq!(handler.retry_tuning().window, OutcomeSummary { calls: 5, retried: 5, extra_rounds: 5, exhausted: 5, ..Default::default() });
+    let record = handler.tune_retries().await.unwrap();
+    assert_eq!((record.rule, record.from.retry_count, record.to.retry_count), (TuningRule::FewerRounds, 3, 2));
+    assert_eq!(record.evidence.calls, 5);
+    assert_eq!(handler.effective_policy().retry.rounds, 2);
+    assert_eq!(handler.retry_tuning().window, OutcomeSummary::default());
+
+    // Nothing changes while frozen
+    handler.freeze_retry_tuning();
+    for _ in 0..5 {
+        assert!(handler.try_proxy_request(block_number()).await.is_err());
+    }
+    assert_eq!(handler.tune_retries().await, None);
+    assert_eq!(handler.effective_policy().retry.rounds, 2);
+
+    // Reset restores the parameters from before the first change
+    assert!(handler.reset_retry_tuning().await);
+    let status = handler.retry_tuning();
+    assert_eq!((status.parameters.retry_count, status.baseline, status.history.len()), (3, None, 1));
+    assert!(!handler.reset_retry_tuning().await);
+}
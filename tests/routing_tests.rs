@@ -0,0 +1,109 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const RAW_TX: &str = "0x02f86b0180843b9aca00850c92a69c0082520894";
+
+fn send_raw_request() -> JsonRpcRequest {
+    request("eth_sendRawTransaction", json!([RAW_TX]))
+}
+
+#[cfg(feature = "consensus")]
+fn chain_id_request() -> JsonRpcRequest {
+    request("eth_chainId", json!([]))
+}
+
+async fn public_backend() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    mount_method(&server, "eth_sendRawTransaction", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0xpublic")))).await;
+    server
+}
+
+fn write_rule(private: &MockServer, allow_failover: bool) -> Option<RouteRule> {
+    Some(RouteRule { methods: vec![], urls: vec![private.uri()], allow_failover })
+}
+
+async fn handler_with(public: &[&MockServer], write_endpoint: Option<RouteRule>) -> std::sync::Arc<RpcHandler> {
+    let mut settings = settings(public.iter().map(|server| mk_rpc(server)).collect());
+    settings.write_endpoint = write_endpoint;
+    let handler = RpcHandler::new(config(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+    handler
+}
+
+#[tokio::test]
+async fn test_submission_goes_only_to_private_endpoint() {
+    let (public_a, public_b) = (public_backend().await, public_backend().await);
+    let private = MockServer::start().await;
+    mount_method(&private, "eth_sendRawTransaction", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0xprivate")))).await;
+
+    let handler = handler_with(&[&public_a, &public_b], write_rule(&private, false)).await;
+
+    let response = handler.try_proxy_request(send_raw_request()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0xprivate")));
+    assert_eq!(count_method(&private, "eth_sendRawTransaction").await, 1);
+    assert_eq!(count_method(&public_a, "eth_sendRawTransaction").await, 0);
+    assert_eq!(count_method(&public_b, "eth_sendRawTransaction").await, 0);
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_submission_never_leaks_when_private_endpoint_is_down() {
+    let (public_a, public_b) = (public_backend().await, public_backend().await);
+    let private = MockServer::start().await;
+    mount_method(&private, "eth_sendRawTransaction", ResponseTemplate::new(503)).await;
+
+    let handler = handler_with(&[&public_a, &public_b], write_rule(&private, false)).await;
+
+    let err = handler.try_proxy_request(send_raw_request()).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::RoutedEndpointsFailed { ref method, ref urls }
+        if method == "eth_sendRawTransaction" && urls == &vec![url_key(&private)]));
+    assert_eq!(count_method(&public_a, "eth_sendRawTransaction").await, 0);
+    assert_eq!(count_method(&public_b, "eth_sendRawTransaction").await, 0);
+
+    // The consensus fan-out is held to the same rule
+    let calls = RpcCalls::new(handler.clone());
+    assert!(calls.consensus::<String>(&send_raw_request(), 0.5, None).await.is_err());
+    assert_eq!(count_method(&public_a, "eth_sendRawTransaction").await, 0);
+    assert_eq!(count_method(&public_b, "eth_sendRawTransaction").await, 0);
+
+    // Reads still route through the general pool
+    let response = handler.try_proxy_request(chain_id_request()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x1")));
+    assert_eq!(count_method(&private, "eth_chainId").await, 0);
+}
+
+#[tokio::test]
+async fn test_rule_with_failover_falls_back_to_pool() {
+    let public = public_backend().await;
+    let private = MockServer::start().await;
+    mount_method(&private, "eth_sendRawTransaction", ResponseTemplate::new(503)).await;
+
+    let handler = handler_with(&[&public], write_rule(&private, true)).await;
+
+    let response = handler.try_proxy_request(send_raw_request()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0xpublic")));
+    assert_eq!(count_method(&private, "eth_sendRawTransaction").await, 1, "designated endpoint is tried first");
+}
+
+#[test]
+fn test_write_endpoint_defaults_to_write_methods() {
+    let settings = HandlerSettings {
+        write_endpoint: Some(RouteRule { methods: vec![], urls: vec!["http://node:8545".into()], allow_failover: false }),
+        routes: vec![RouteRule { methods: vec!["eth_call".into()], urls: vec!["http://archive".into()], allow_failover: true }],
+        ..HandlerSettings::default()
+    };
+
+    let normalized = resolve_config(HandlerConfig { network_id: TEST_NETWORK_ID, settings: Some(settings) }).unwrap();
+    let rule = routing::route_for(&normalized.routes, "eth_sendRawTransaction").unwrap();
+    assert_eq!(rule.normalized_urls(), vec!["http://node:8545/".to_string()]);
+    assert!(routing::route_for(&normalized.routes, "eth_call").unwrap().allow_failover);
+    assert!(routing::route_for(&normalized.routes, "eth_chainId").is_none());
+}
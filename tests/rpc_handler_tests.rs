@@ -1,156 +1,91 @@
-use ez_web3_rpc::*;
-use serde_json::json;
-use wiremock::{Mock, MockServer, ResponseTemplate};
-use wiremock::matchers::{method, path};
+mod common;
+
+use std::time::Duration;
 
-// Use a network id that won't exist in the generated chainlist data so tests stay hermetic.
-const TEST_NETWORK_ID: u64 = 424242;
+use common::*;
+use ez_web3_rpc::prelude::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
 
-fn build_mock_jsonrpc_response(id: u64, result: serde_json::Value) -> serde_json::Value {
-    json!({
-        "jsonrpc": "2.0",
-        "id": id,
-        "result": result
-    })
+/// A config over `servers` built the short way, with fast retries.
+fn config_for(servers: &[&MockServer]) -> HandlerConfig {
+    let uris: Vec<String> = servers.iter().map(|server| server.uri()).collect();
+    HandlerConfig::builder(TEST_NETWORK_ID).rpc_urls(uris.iter().map(String::as_str)).retry_count(1).retry_delay_ms(5).rpc_call_timeout_ms(1000).build().unwrap()
 }
 
-fn normalize(url: &str) -> &str { url.trim_end_matches('/') }
+fn chain_id(id: u64) -> JsonRpcRequest {
+    JsonRpcRequest::new("eth_chainId", json!([])).with_id(id)
+}
 
 #[tokio::test]
 async fn test_handler_initializes_and_selects_fastest_rpc() {
-    // spin up two mock servers with slight latency differences
-    let server_fast = MockServer::start().await;
-    let server_slow = MockServer::start().await;
-
-    Mock::given(method("POST"))
-        .and(path("/"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(build_mock_jsonrpc_response(1, json!("0x1"))))
-        .mount(&server_fast)
-        .await;
+    let (fast, slow) = (MockServer::start().await, MockServer::start().await);
+    mount_probe(&fast, "0x10", Duration::ZERO).await;
+    mount_probe(&slow, "0x10", Duration::from_millis(100)).await;
 
-    // slower by sleeping inside response
-    Mock::given(method("POST"))
-        .and(path("/"))
-        .respond_with(ResponseTemplate::new(200)
-            .set_body_json(build_mock_jsonrpc_response(1, json!("0x1")))
-            .set_delay(std::time::Duration::from_millis(50)))
-        .mount(&server_slow)
-        .await;
-
-    let config = HandlerConfig {
-        network_id: TEST_NETWORK_ID,
-        settings: Some(HandlerSettings {
-            log_level: LogLevel::Error,
-            tracking: Tracking::Limited,
-            network_rpcs: vec![
-                Rpc { url: server_slow.uri().parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) },
-                Rpc { url: server_fast.uri().parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) },
-            ],
-            network_name: "local_testnet".to_string(),
-            rpc_probe_timeout_ms: 5000,
-            proxy_settings: Some(ProxySettings::default()),
-            // Ensure we wipe chain data so no external RPC URLs are added.
-            wipe_chain_data: WipeChainData { clear_data: true, retain_these_chains: vec![TEST_NETWORK_ID] }
-        })
-    };
-
-    let handler = RpcHandler::new(Some(config), TEST_NETWORK_ID).await.expect("handler init");
-    // Insert synthetic latency records to avoid relying on probe success
-    handler.get_latencies().insert(server_fast.uri(), LatencyRecord { latency_ms: 5, last_tested: std::time::SystemTime::now(), failure_count: 0 });
-    handler.get_latencies().insert(server_slow.uri(), LatencyRecord { latency_ms: 55, last_tested: std::time::SystemTime::now(), failure_count: 0 });
-    let fastest = handler.get_fastest_rpc(None).await.expect("fastest rpc");
-    assert_eq!(normalize(&fastest), normalize(&server_fast.uri()));
+    let handler = RpcHandler::new(config_for(&[&slow, &fast]), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&fast));
 }
 
 #[tokio::test]
 async fn test_try_proxy_request_success() {
     let server = MockServer::start().await;
-    // Always succeed for this test
-    Mock::given(method("POST"))
-        .and(path("/"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(build_mock_jsonrpc_response(42, json!("0xabc"))))
-        .mount(&server)
-        .await;
-
-    let config = HandlerConfig {
-        network_id: TEST_NETWORK_ID,
-        settings: Some(HandlerSettings {
-            log_level: LogLevel::Error,
-            tracking: Tracking::Limited,
-            network_rpcs: vec![
-                Rpc { url: server.uri().parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
-            ],
-            network_name: "local".to_string(),
-            rpc_probe_timeout_ms: 5000,
-            proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: 1000 }),
-            wipe_chain_data: WipeChainData { clear_data: true, retain_these_chains: vec![TEST_NETWORK_ID] }
-        })
-    };
-
-    let handler = RpcHandler::new(Some(config), TEST_NETWORK_ID).await.unwrap();
-    handler.get_latencies().insert(server.uri(), LatencyRecord { latency_ms: 10, last_tested: std::time::SystemTime::now(), failure_count: 0 });
-
-    let request = JsonRpcRequest { jsonrpc: "2.0".into(), method: "eth_chainId".into(), params: json!([]), id: Some(42) };
-
-    let resp = handler.try_proxy_request(request).await.expect("proxy request success");
-    assert!(resp.error.is_none());
-    assert_eq!(resp.result.unwrap(), json!("0xabc"));
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(42, json!("0xabc")))).await;
+
+    let handler = RpcHandler::new(config_for(&[&server]), None).await.unwrap();
+    handler.init().await.unwrap();
+    let response = handler.try_proxy_request(chain_id(42)).await.unwrap();
+    assert!(response.error.is_none());
+    assert_eq!(response.result, Some(json!("0xabc")));
 }
 
 #[tokio::test]
 async fn test_try_proxy_request_all_fail() {
     let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_chainId", ResponseTemplate::new(500)).await;
 
-    // All attempts fail
-    for _ in 0..3 {
-        Mock::given(method("POST"))
-            .and(path("/"))
-            .respond_with(ResponseTemplate::new(500))
-            .mount(&server)
-            .await;
-    }
-
-    let config = HandlerConfig {
-        network_id: TEST_NETWORK_ID,
-        settings: Some(HandlerSettings {
-            log_level: LogLevel::Error,
-            tracking: Tracking::Limited,
-            network_rpcs: vec![
-                Rpc { url: server.uri().parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
-            ],
-            network_name: "local".to_string(),
-            rpc_probe_timeout_ms: 5000,
-            proxy_settings: Some(ProxySettings { retry_count: 3, retry_delay_ms: 5, rpc_call_timeout_ms: 1000 }),
-            wipe_chain_data: WipeChainData { clear_data: true, retain_these_chains: vec![TEST_NETWORK_ID] }
-        })
-    };
-
-    let handler = RpcHandler::new(Some(config), TEST_NETWORK_ID).await.unwrap();
-    handler.get_latencies().insert(server.uri(), LatencyRecord { latency_ms: 10, last_tested: std::time::SystemTime::now(), failure_count: 0 });
+    let handler = RpcHandler::new(config_for(&[&server]), None).await.unwrap();
+    handler.init().await.unwrap();
+    let err = handler.try_proxy_request(chain_id(2)).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::HttpStatus { status: 500, .. }), "{err:?}");
+}
 
-    let request = JsonRpcRequest { jsonrpc: "2.0".into(), method: "eth_chainId".into(), params: json!([]), id: Some(2) };
+#[tokio::test]
+async fn test_init_without_rpcs_has_none_available() {
+    let handler = RpcHandler::new(config_for(&[]), None).await.unwrap();
+    let err = handler.init().await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::NoAvailableRpcs { .. }), "{err:?}");
+}
 
-    let err = handler.try_proxy_request(request).await.err().expect("should err");
-    assert!(matches!(err, RpcHandlerError::AllEndpointsFailed | RpcHandlerError::JsonRpc(_)));
+#[test]
+fn test_builder_defaults_and_overrides() {
+    let config = HandlerConfig::builder(TEST_NETWORK_ID).rpc_url("https://rpc.example.com").probe_timeout_ms(2000).retry_count(5).build().unwrap();
+    let settings = config.settings.unwrap();
+    let defaults = HandlerConfig::new(TEST_NETWORK_ID).settings.unwrap();
+    assert_eq!(settings.rpc_probe_timeout_ms, 2000);
+    let proxy = settings.proxy_settings.unwrap();
+    assert_eq!((proxy.retry_count, proxy.retry_delay_ms), (5, ProxySettings::default().retry_delay_ms));
+    assert_eq!(settings.network_name, defaults.network_name);
+    assert_eq!(settings.data_scope, DataScope::OnlyThisNetwork);
+    assert_eq!(settings.network_rpcs.len(), 1);
+    assert_eq!(settings.network_rpcs[0].url.as_ref().map(|url| url.as_str()), Some("https://rpc.example.com/"));
+
+    let settings = HandlerSettings::builder().with(|settings| settings.monotonic_head = true).rpc_template("https://rpc.example.com/{KEY}").build().unwrap();
+    assert!(settings.monotonic_head);
+    assert!(matches!(settings.log_level, LogLevel::Info));
+    assert_eq!(settings.network_rpcs[0].url_template.as_deref(), Some("https://rpc.example.com/{KEY}"));
 }
 
-#[tokio::test]
-async fn test_get_fastest_rpc_no_available() {
-    let config = HandlerConfig {
-        network_id: TEST_NETWORK_ID,
-        settings: Some(HandlerSettings {
-            log_level: LogLevel::Error,
-            tracking: Tracking::Limited,
-            network_rpcs: vec![],
-            network_name: "none".to_string(),
-            rpc_probe_timeout_ms: 100,
-            proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 1, rpc_call_timeout_ms: 50 }),
-            wipe_chain_data: WipeChainData { clear_data: true, retain_these_chains: vec![TEST_NETWORK_ID] }
-        })
-    };
+#[test]
+fn test_invalid_urls_fail_the_build() {
+    assert!(matches!(Rpc::from_url("not a url"), Err(RpcHandlerError::InvalidRpcConfig { .. })));
+    assert!(matches!(Rpc::from_url("wss://rpc.example.com"), Err(RpcHandlerError::InvalidRpcConfig { .. })));
+    assert!(Rpc::from_url("http://127.0.0.1:8545").is_ok_and(|rpc| rpc.tracking.is_none() && rpc.url.port() == Some(8545)));
 
-    let handler = RpcHandler::new(Some(config), TEST_NETWORK_ID).await.unwrap();
-    // Ensure no latencies inserted
-    let err = handler.get_fastest_rpc(None).await.err().expect("expected error");
-    assert!(matches!(err, RpcHandlerError::NoAvailableRpcs { .. }));
+    // The first bad URL is the one reported, and the secret in it isn't
+    let err = HandlerConfig::builder(1).rpc_url("https://rpc.example.com").rpc_url("ftp://s3cr3t@host").rpc_url("nope").build().unwrap_err();
+    assert!(matches!(&err, RpcHandlerError::InvalidRpcConfig { detail } if detail.contains("ftp") && !detail.contains("s3cr3t")), "{err:?}");
 }
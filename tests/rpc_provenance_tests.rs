@@ -0,0 +1,143 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+#[cfg(feature = "test-util")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use common::*;
+use ez_web3_rpc::{chainlist::ChainView, *};
+#[cfg(feature = "test-util")]
+use serde_json::{json, Value};
+use wiremock::MockServer;
+#[cfg(feature = "test-util")]
+use wiremock::{matchers::method, Mock, Request, ResponseTemplate};
+
+/// Offers a fixed set of endpoints for every network.
+struct FixedSource(Vec<Rpc>);
+
+impl RpcSource for FixedSource {
+    fn rpcs(&self, _network_id: NetworkId) -> Vec<Rpc> {
+        self.0.clone()
+    }
+}
+
+/// An endpoint that answers probes until `down` is set, then fails everything with a 500.
+#[cfg(feature = "test-util")]
+async fn flaky() -> (MockServer, Arc<AtomicBool>) {
+    let server = MockServer::start().await;
+    let down = Arc::new(AtomicBool::new(false));
+    let is_down = Arc::clone(&down);
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            if is_down.load(Ordering::SeqCst) {
+                return ResponseTemplate::new(500);
+            }
+            let body: Value = request.body_json().unwrap();
+            let result = match body["method"].as_str().unwrap() {
+                "eth_getBlockByNumber" => json!({ "number": "0x10", "hash": "0xabc" }),
+                "eth_getCode" => json!(PERMIT2_CODE),
+                other => panic!("unexpected {other}"),
+            };
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, result))
+        })
+        .mount(&server)
+        .await;
+    (server, down)
+}
+
+async fn probed() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    server
+}
+
+fn origins(handler: &RpcHandler) -> Vec<(String, RpcOrigin)> {
+    handler.rpc_provenance().into_iter().map(|provenance| (provenance.url, provenance.origin)).collect()
+}
+
+#[tokio::test]
+async fn test_each_endpoint_is_attributed_to_the_path_that_added_it() {
+    let (injected, registered, added) = (probed().await, probed().await, probed().await);
+    let components = HandlerComponents { rpc_source: Some(Arc::new(FixedSource(vec![mk_rpc(&registered)]))), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings(vec![mk_rpc(&injected)])), None, components).await.unwrap();
+    assert!(handler.add_rpc(mk_rpc(&added)));
+
+    assert_eq!(
+        origins(&handler),
+        [(url_key(&injected), RpcOrigin::Injected), (url_key(&registered), RpcOrigin::Registered), (url_key(&added), RpcOrigin::RuntimeAdded)]
+    );
+    assert_eq!(ChainView::global().origin(), RpcOrigin::Chainlist);
+
+    let report = handler.health_report().await;
+    let reported: Vec<(String, RpcOrigin)> = report.endpoints.into_iter().map(|endpoint| (endpoint.url, endpoint.origin)).collect();
+    assert_eq!(reported, origins(&handler));
+}
+
+#[tokio::test]
+async fn test_an_endpoint_a_reload_adds_is_injected_not_runtime_added() {
+    let (first, second) = (probed().await, probed().await);
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&first)])), None).await.unwrap();
+
+    let diff = handler.apply_config(config(settings(vec![mk_rpc(&first), mk_rpc(&second)]))).await.unwrap();
+    assert_eq!(diff.added_rpcs, [url_key(&second)]);
+    assert_eq!(origins(&handler), [(url_key(&first), RpcOrigin::Injected), (url_key(&second), RpcOrigin::Injected)]);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_uptime_is_weighted_by_how_long_each_outcome_held() {
+    let clock = MockClock::new();
+    let (server, down) = flaky().await;
+    let steady = probed().await;
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings(vec![mk_rpc(&server), mk_rpc(&steady)])), None, components).await.unwrap();
+    let url = url_key(&server);
+    assert_eq!(handler.uptime(&url, Duration::from_secs(100)), None);
+
+    // Up at 0s, down at 10s, up again at 40s, nothing since
+    handler.init().await.unwrap();
+    clock.advance(Duration::from_secs(10));
+    down.store(true, Ordering::SeqCst);
+    handler.refresh().await.unwrap();
+    clock.advance(Duration::from_secs(30));
+    down.store(false, Ordering::SeqCst);
+    handler.refresh().await.unwrap();
+    let back_up = clock.now_system();
+    clock.advance(Duration::from_secs(60));
+
+    let uptime = |window: u64| handler.uptime(&url, Duration::from_secs(window)).unwrap();
+    assert!((uptime(100) - 0.7).abs() < 1e-9, "{}", uptime(100));
+    assert!((uptime(75) - 0.8).abs() < 1e-9, "{}", uptime(75));
+    assert_eq!(uptime(50), 1.0);
+    // Time before the first outcome counts neither way
+    assert!((uptime(1_000) - 0.7).abs() < 1e-9, "{}", uptime(1_000));
+    assert_eq!(handler.uptime(&url_key(&steady), Duration::from_secs(100)), Some(1.0));
+
+    let provenance = handler.rpc_provenance().into_iter().find(|provenance| provenance.url == url).unwrap();
+    assert_eq!(provenance.last_healthy, Some(back_up));
+    let endpoint = handler.health_report().await.endpoints.into_iter().find(|endpoint| endpoint.url == url).unwrap();
+    assert!((endpoint.uptime.unwrap() - 0.7).abs() < 1e-9);
+    assert_eq!(handler.metrics_snapshot().provenance, handler.rpc_provenance());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_request_attempts_count_as_outcomes() {
+    let clock = MockClock::new();
+    let (server, down) = flaky().await;
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings(vec![mk_rpc(&server)])), None, components).await.unwrap();
+    handler.init().await.unwrap();
+    let url = url_key(&server);
+
+    clock.advance(Duration::from_secs(10));
+    down.store(true, Ordering::SeqCst);
+    let request = request("eth_getBlockByNumber", json!(["latest", false]));
+    assert!(handler.try_proxy_request(request).await.is_err());
+    clock.advance(Duration::from_secs(10));
+
+    // Up for the first 10s, down since the failed attempt
+    assert_eq!(handler.uptime(&url, Duration::from_secs(20)), Some(0.5));
+    let provenance = &handler.rpc_provenance()[0];
+    assert_eq!(provenance.last_healthy, Some(clock.now_system() - Duration::from_secs(20)));
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::{rpc_service::{ProbeMode, RpcTestingService}, *};
+use serde_json::json;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+async fn answering(response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(response).mount(&server).await;
+    server
+}
+
+/// A URL nothing listens on.
+fn refused() -> Rpc {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    Rpc::from_url(&format!("http://127.0.0.1:{port}")).unwrap()
+}
+
+#[tokio::test]
+async fn test_legacy_probe_counts_any_success_status_as_up() {
+    let servers = [
+        answering(ResponseTemplate::new(200).set_body_json(json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32601, "message": "method not found" } }))).await,
+        answering(ResponseTemplate::new(200).set_body_raw("<html>docs</html>", "text/html")).await,
+        answering(ResponseTemplate::new(204)).await,
+    ];
+    let rpcs: Vec<Rpc> = servers.iter().map(mk_rpc).collect();
+
+    let results = RpcTestingService::new(500).race_rpcs(&rpcs).await;
+    assert_eq!(results.iter().map(|(index, _)| *index).collect::<Vec<_>>(), [0, 1, 2]);
+    for (index, result) in results {
+        let record = result.unwrap_or_else(|e| panic!("endpoint {index}: {e:?}"));
+        assert_eq!(record.failure_count, 0);
+    }
+    for server in &servers {
+        assert_eq!(count_method(server, "eth_blockNumber").await, 1, "only a block number is asked for");
+    }
+}
+
+#[tokio::test]
+async fn test_legacy_probe_reports_every_failure_as_a_timeout() {
+    let unavailable = answering(ResponseTemplate::new(503)).await;
+    let slow = answering(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1"))).set_delay(Duration::from_millis(300))).await;
+    let service = RpcTestingService::new(50);
+
+    // As the original service did, whatever the failure
+    for rpc in [mk_rpc(&unavailable), mk_rpc(&slow), refused()] {
+        let result = service.test_rpc_latency(&rpc).await;
+        assert!(matches!(result, Err(RpcHandlerError::Timeout { duration_ms: 50 })), "{}: {result:?}", rpc.url);
+    }
+}
+
+#[tokio::test]
+async fn test_granular_errors_name_each_legacy_failure() {
+    let unavailable = answering(ResponseTemplate::new(503)).await;
+    let slow = answering(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1"))).set_delay(Duration::from_millis(300))).await;
+    let service = RpcTestingService::new(50).with_granular_errors(true);
+
+    match service.test_rpc_latency(&mk_rpc(&unavailable)).await {
+        Err(RpcHandlerError::HttpStatus { url, status }) => assert_eq!((url, status), (url_key(&unavailable), 503)),
+        other => panic!("expected HttpStatus, got {other:?}"),
+    }
+    match service.test_rpc_latency(&mk_rpc(&slow)).await {
+        Err(RpcHandlerError::RequestTimeout { url, configured_ms }) => assert_eq!((url, configured_ms), (url_key(&slow), 50)),
+        other => panic!("expected RequestTimeout, got {other:?}"),
+    }
+    let result = service.test_rpc_latency(&refused()).await;
+    assert!(matches!(result, Err(RpcHandlerError::ConnectFailure { .. })), "{result:?}");
+}
+
+#[tokio::test]
+async fn test_measured_probe_applies_the_handler_checks() {
+    let good = MockServer::start().await;
+    mount_probe(&good, "0x20", Duration::ZERO).await;
+    let lagging = MockServer::start().await;
+    mount_probe(&lagging, "0x1", Duration::ZERO).await;
+    let no_code = MockServer::start().await;
+    mount_method(&no_code, "eth_getBlockByNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!({ "number": "0x20", "hash": "0xabc" })))).await;
+    mount_method(&no_code, "eth_getCode", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x")))).await;
+    let html = answering(ResponseTemplate::new(200).set_body_raw("<html>docs</html>", "text/html")).await;
+    let rpcs: Vec<Rpc> = [&good, &no_code, &lagging, &html].iter().map(|server| mk_rpc(server)).collect();
+
+    let service = RpcTestingService::new(500).with_mode(ProbeMode::Measured);
+    let results = service.race_rpcs(&rpcs).await;
+    assert_eq!(results.iter().map(|(index, _)| *index).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    let mut results = results.into_iter().map(|(_, result)| result);
+
+    assert_eq!(results.next().unwrap().unwrap().failure_count, 0);
+    for expected in [&no_code, &lagging] {
+        match results.next().unwrap() {
+            Err(RpcHandlerError::ProbeFailed { url, .. }) => assert_eq!(url, url_key(expected)),
+            other => panic!("expected ProbeFailed, got {other:?}"),
+        }
+    }
+    let result = results.next().unwrap();
+    assert!(matches!(result, Err(RpcHandlerError::NotAJsonRpcEndpoint { status: 200, .. })), "{result:?}");
+    assert_eq!(count_method(&good, "eth_blockNumber").await, 0);
+
+    // One endpoint on its own has no head to lag behind
+    assert!(service.test_rpc_latency(&mk_rpc(&lagging)).await.is_ok());
+}
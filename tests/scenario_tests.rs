@@ -0,0 +1,151 @@
+#![cfg(feature = "scenarios")]
+
+use std::time::Duration;
+
+use ez_web3_rpc::{scenario::*, *};
+use serde_json::{json, Value};
+
+fn seen(requests: &[&str]) -> Seen {
+    let mut seen = Seen::default();
+    for method in requests {
+        seen.record(method);
+    }
+    seen
+}
+
+fn request(method: &str, params: Value) -> JsonRpcRequest {
+    JsonRpcRequest::new(method, params).with_id(7)
+}
+
+fn chain(head: u64) -> ChainView {
+    ChainView { chain_id: SCENARIO_NETWORK_ID, head }
+}
+
+#[test]
+fn test_a_step_takes_over_once_its_request_count_is_reached() {
+    let script = Script::dies_after(2);
+    assert_eq!(script.step(&seen(&[]), Duration::ZERO).behavior, Behavior::Healthy);
+    assert_eq!(script.step(&seen(&["eth_chainId"]), Duration::ZERO).behavior, Behavior::Healthy);
+    assert_eq!(script.step(&seen(&["eth_chainId", "eth_getCode"]), Duration::ZERO).behavior, Behavior::Down);
+}
+
+#[test]
+fn test_the_last_fired_trigger_wins() {
+    let script = Script::healthy()
+        .then(Trigger::AfterMethod("eth_call".into(), 1), Behavior::RateLimited { retry_after_secs: Some(2) })
+        .delay(Duration::from_millis(30))
+        .then(Trigger::After(Duration::from_secs(5)), Behavior::Stale(4));
+
+    // Other methods don't count toward a method's trigger
+    assert_eq!(script.step(&seen(&["eth_chainId", "eth_chainId"]), Duration::ZERO).behavior, Behavior::Healthy);
+    let limited = script.step(&seen(&["eth_call"]), Duration::from_secs(1));
+    assert_eq!((&limited.behavior, limited.delay), (&Behavior::RateLimited { retry_after_secs: Some(2) }, Duration::from_millis(30)));
+    assert_eq!(script.step(&seen(&["eth_call"]), Duration::from_secs(5)).behavior, Behavior::Stale(4));
+    assert_eq!(script.step(&seen(&[]), Duration::from_secs(6)).behavior, Behavior::Stale(4));
+}
+
+#[test]
+fn test_method_behaviors_apply_only_while_answering_like_a_node() {
+    let script = Script::healthy().method("eth_chainId", Behavior::Status(500)).then(Trigger::AfterRequests(1), Behavior::Malformed);
+    assert_eq!(script.behavior("eth_chainId", &seen(&[]), Duration::ZERO).0, &Behavior::Status(500));
+    assert_eq!(script.behavior("eth_blockNumber", &seen(&[]), Duration::ZERO).0, &Behavior::Healthy);
+    assert_eq!(script.behavior("eth_chainId", &seen(&["eth_blockNumber"]), Duration::ZERO).0, &Behavior::Malformed);
+}
+
+#[test]
+fn test_replies_follow_the_behavior() {
+    let block_number = json!({ "jsonrpc": "2.0", "id": 3, "method": "eth_blockNumber", "params": [] });
+    assert_eq!(reply(&Behavior::Healthy, &block_number, chain(0x20)), Reply::Json(json!({ "jsonrpc": "2.0", "id": 3, "result": "0x20" })));
+    assert_eq!(reply(&Behavior::Stale(3), &block_number, chain(0x20)), Reply::Json(json!({ "jsonrpc": "2.0", "id": 3, "result": "0x1d" })));
+    assert_eq!(reply(&Behavior::Answer(json!("0x2")), &block_number, chain(0x20)), Reply::Json(json!({ "jsonrpc": "2.0", "id": 3, "result": "0x2" })));
+    assert_eq!(
+        reply(&Behavior::RpcError { code: -32005, message: "limit exceeded".into() }, &block_number, chain(0x20)),
+        Reply::Json(json!({ "jsonrpc": "2.0", "id": 3, "error": { "code": -32005, "message": "limit exceeded" } }))
+    );
+    assert_eq!(reply(&Behavior::RateLimited { retry_after_secs: Some(1) }, &block_number, chain(0x20)), Reply::Status { status: 429, retry_after_secs: Some(1) });
+    assert_eq!(reply(&Behavior::Status(503), &block_number, chain(0x20)), Reply::Status { status: 503, retry_after_secs: None });
+    assert!(matches!(reply(&Behavior::Malformed, &block_number, chain(0x20)), Reply::NotJson(body) if serde_json::from_str::<Value>(&body).is_err()));
+    assert_eq!(reply(&Behavior::Down, &block_number, chain(0x20)), Reply::Hangup);
+
+    let unknown = json!({ "jsonrpc": "2.0", "id": 4, "method": "debug_traceCall", "params": [] });
+    assert!(matches!(reply(&Behavior::Healthy, &unknown, chain(0x20)), Reply::Json(answer) if answer["error"]["code"] == -32601));
+}
+
+#[test]
+fn test_a_stale_node_serves_the_chain_up_to_its_own_head() {
+    let block = |tag: &str, behavior: &Behavior| match reply(behavior, &json!({ "id": 1, "method": "eth_getBlockByNumber", "params": [tag, false] }), chain(0x20)) {
+        Reply::Json(answer) => answer["result"].clone(),
+        other => panic!("expected an answer, got {other:?}"),
+    };
+    let latest = block("latest", &Behavior::Stale(2));
+    assert_eq!(latest["number"], "0x1e");
+    assert_eq!(latest["hash"], block_hash(0x1e));
+    assert_eq!(latest["parentHash"], block_hash(0x1d));
+    // Hashes follow from the number, so a stale node agrees with a healthy one on the blocks it has
+    assert_eq!(block("0x1e", &Behavior::Healthy)["hash"], latest["hash"]);
+    assert_eq!(block("0x1f", &Behavior::Stale(2)), Value::Null);
+}
+
+#[tokio::test]
+async fn test_endpoints_count_what_they_are_sent_and_keep_a_timeline() {
+    let mut scenario = Scenario::at_head(0x40);
+    let primary = scenario.endpoint("primary", Script::dies_after(2)).await.unwrap();
+    let transports = scenario.transports();
+    let transport = transports.transport(primary.url());
+
+    assert_eq!(transport.request(&request("eth_blockNumber", json!([]))).await.unwrap().result, Some(json!("0x40")));
+    scenario.advance(2);
+    assert_eq!(transport.request(&request("eth_blockNumber", json!([]))).await.unwrap().result, Some(json!("0x42")));
+    let died = transport.request(&request("eth_chainId", json!([]))).await;
+    assert!(matches!(died, Err(RpcHandlerError::ConnectFailure { .. })), "{died:?}");
+    assert!(!transports.supports("http://127.0.0.1:1/"));
+
+    assert_eq!((primary.requests(), primary.requests_for("eth_blockNumber"), primary.requests_for("eth_chainId")), (3, 2, 1));
+    let timeline = scenario.timeline();
+    let behaviors: Vec<(&str, &Behavior)> = timeline.iter().map(|served| (served.method.as_str(), &served.behavior)).collect();
+    assert_eq!(behaviors, [("eth_blockNumber", &Behavior::Healthy), ("eth_blockNumber", &Behavior::Healthy), ("eth_chainId", &Behavior::Down)]);
+    assert!(timeline.iter().all(|served| served.endpoint == "primary"));
+}
+
+#[tokio::test]
+async fn test_endpoints_serve_http_single_and_batched() {
+    let mut scenario = Scenario::new();
+    let limited = scenario.endpoint("limited", Script::healthy().then(Trigger::AfterRequests(2), Behavior::RateLimited { retry_after_secs: Some(3) })).await.unwrap();
+    let client = reqwest::Client::new();
+    let post = |body: Value| client.post(limited.url()).json(&body).send();
+
+    let batch = json!([
+        { "jsonrpc": "2.0", "id": 1, "method": "eth_chainId", "params": [] },
+        { "jsonrpc": "2.0", "id": 2, "method": "eth_getCode", "params": ["0x000000000022D473030F116dDEE9F6B43aC78BA3", "latest"] },
+    ]);
+    let answers: Value = post(batch).await.unwrap().json().await.unwrap();
+    assert_eq!(answers[0]["result"], format!("{SCENARIO_NETWORK_ID:#x}"));
+    assert_eq!(answers[1]["result"], PERMIT2_CODE);
+
+    let response = post(json!({ "jsonrpc": "2.0", "id": 3, "method": "eth_chainId", "params": [] })).await.unwrap();
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()["retry-after"], "3");
+    assert_eq!(limited.requests(), 3);
+}
+
+#[tokio::test]
+async fn test_a_handler_probes_and_serves_through_scripted_endpoints() {
+    let mut scenario = Scenario::new();
+    let slow = scenario.endpoint("slow", Script::healthy().delay(Duration::from_millis(80))).await.unwrap();
+    let fast = scenario.endpoint("fast", Script::healthy()).await.unwrap();
+    let handler = RpcHandler::new(scenario.config(), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), fast.url());
+
+    let narrator = Narrator::new(&scenario, &handler, false).await;
+    let (answer, url) = handler.try_proxy_request_attributed(request("eth_blockNumber", json!([]))).await.unwrap();
+    assert_eq!(answer.result, Some(json!(format!("{DEFAULT_HEAD:#x}"))));
+    assert_eq!(url, fast.url());
+    // Both probes of each endpoint, and the read raced across them
+    assert_eq!((fast.requests(), slow.requests()), (3, 3));
+
+    narrator.checkpoint("after one read").await;
+    let lines = narrator.lines();
+    assert!(lines.iter().any(|line| line.ends_with("provider: fast")), "{lines:#?}");
+    assert!(lines.iter().any(|line| line.contains("1 request(s), 1 answered, 0 after failing over")), "{lines:#?}");
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+const TEMPLATE: &str = "{PROVIDER_HOST}/v2/{PROVIDER_KEY}";
+
+/// Secrets that can be changed after the handler resolved them.
+#[derive(Clone, Default)]
+struct Vault(Arc<parking_lot::Mutex<HashMap<String, String>>>);
+
+impl Vault {
+    /// Point the template at `server` with `key`. The two servers stand in for one provider
+    /// before and after its key changed.
+    fn set(&self, server: &MockServer, key: &str) {
+        let mut secrets = self.0.lock();
+        secrets.insert("PROVIDER_HOST".to_string(), server.uri());
+        secrets.insert("PROVIDER_KEY".to_string(), key.to_string());
+    }
+}
+
+impl SecretResolver for Vault {
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.0.lock().get(name).cloned()
+    }
+}
+
+fn keyed(server: &MockServer, key: &str) -> String {
+    format!("{}/v2/{key}", server.uri())
+}
+
+fn eth_call() -> JsonRpcRequest {
+    request("eth_call", json!([{ "to": "0x0", "data": "0x" }, "latest"]))
+}
+
+async fn answering(result: &str, delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    mount_method(&server, "eth_call", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(result))).set_delay(delay)).await;
+    server
+}
+
+/// A handler over the templated endpoint and a slower plain one, serving through the templated one.
+async fn handler(vault: &Vault, fallback: &MockServer, customize: impl FnOnce(&mut HandlerSettings)) -> Arc<RpcHandler> {
+    let mut settings = settings(vec![mk_rpc(fallback)]);
+    settings.network_rpcs.push(RpcConfig::template(TEMPLATE));
+    customize(&mut settings);
+    let components = HandlerComponents { secret_resolver: Some(Arc::new(vault.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+    handler
+}
+
+async fn fallback() -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::from_millis(80)).await;
+    mount_method(&server, "eth_call", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0xfallback")))).await;
+    server
+}
+
+#[tokio::test]
+async fn test_rotation_carries_state_over_and_lets_in_flight_requests_finish() {
+    let (old, new, fallback) = (answering("0xold", Duration::from_millis(300)).await, answering("0xnew", Duration::ZERO).await, fallback().await);
+    let vault = Vault::default();
+    vault.set(&old, "old-key");
+    let handler = handler(&vault, &fallback, |_| {}).await;
+    let (old_url, new_url) = (keyed(&old, "old-key"), keyed(&new, "new-key"));
+    assert_eq!(handler.get_provider_url().await.unwrap(), old_url);
+    let latency = handler.get_latencies().await[&old_url];
+    let mut events = handler.subscribe();
+
+    let in_flight = {
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move { handler.try_proxy_request(eth_call()).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    vault.set(&new, "new-key");
+    assert_eq!(handler.rotate_secret("PROVIDER_KEY").await.unwrap(), [TEMPLATE]);
+
+    // The request sent before the swap finishes on the old URL
+    assert_eq!(in_flight.await.unwrap().unwrap().result, Some(json!("0xold")));
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xnew")));
+    assert_eq!(handler.get_provider_url().await.unwrap(), new_url);
+
+    let urls: Vec<String> = handler.rpcs().iter().map(|rpc| rpc.url.to_string()).collect();
+    assert!(urls.contains(&new_url) && !urls.contains(&old_url), "{urls:?}");
+    let latencies = handler.get_latencies().await;
+    assert_eq!((latencies.get(&new_url), latencies.get(&old_url)), (Some(&latency), None));
+    assert!(handler.uptime(&new_url, Duration::from_secs(60)).is_some());
+    assert_eq!(handler.rpc_provenance().iter().filter(|provenance| provenance.url == TEMPLATE).count(), 1);
+    // Neither key shows up where URLs are reported
+    let report = handler.health_report().await.to_string();
+    assert!(!report.contains("old-key") && !report.contains("new-key"), "{report}");
+
+    let rotated = loop {
+        match events.recv().await.unwrap() {
+            HandlerEvent::SecretRotated { urls } => break urls,
+            _ => continue,
+        }
+    };
+    assert_eq!(rotated, [TEMPLATE]);
+    // Resolving the same values again changes nothing
+    assert!(handler.rotate_secret("PROVIDER_KEY").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_failed_resolution_keeps_the_old_url() {
+    let (old, fallback) = (answering("0xold", Duration::ZERO).await, fallback().await);
+    let vault = Vault::default();
+    vault.set(&old, "old-key");
+    let handler = handler(&vault, &fallback, |_| {}).await;
+
+    vault.0.lock().remove("PROVIDER_KEY");
+    let result = handler.rotate_secret("PROVIDER_KEY").await;
+    assert!(matches!(result, Err(RpcHandlerError::UnresolvedPlaceholder { ref placeholder, .. }) if placeholder == "PROVIDER_KEY"), "{result:?}");
+    assert_eq!(handler.get_provider_url().await.unwrap(), keyed(&old, "old-key"));
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xold")));
+    // A placeholder no template names rotates nothing
+    assert!(handler.rotate_secret("OTHER_KEY").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_rotation_carries_the_endpoint_settings_over() {
+    let (old, new, fallback) = (answering("0xold", Duration::ZERO).await, answering("0xnew", Duration::ZERO).await, fallback().await);
+    let vault = Vault::default();
+    vault.set(&old, "old-key");
+    let handler = handler(&vault, &fallback, |settings| {
+        let templated = settings.network_rpcs.last_mut().unwrap();
+        templated.tier = Some(0);
+        templated.headers = Some([("x-team".to_string(), "core".to_string())].into());
+    })
+    .await;
+
+    vault.set(&new, "new-key");
+    handler.rotate_secret("PROVIDER_KEY").await.unwrap();
+    let report = handler.health_report().await;
+    let endpoint = report.endpoints.iter().find(|endpoint| endpoint.url == TEMPLATE).unwrap();
+    assert_eq!(endpoint.tier, Some(0));
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xnew")));
+    let requests = new.received_requests().await.unwrap();
+    assert!(requests.iter().filter(|request| request.body_json::<serde_json::Value>().unwrap()["method"] == "eth_call").all(|request| request.headers.get("x-team").is_some_and(|value| value == "core")));
+}
+
+#[tokio::test]
+async fn test_an_auth_error_rotates_the_endpoint_automatically() {
+    let (old, new, fallback) = (MockServer::start().await, answering("0xnew", Duration::ZERO).await, fallback().await);
+    mount_probe(&old, "0x10", Duration::ZERO).await;
+    mount_method(&old, "eth_call", ResponseTemplate::new(401)).await;
+    let vault = Vault::default();
+    vault.set(&old, "old-key");
+    let handler = handler(&vault, &fallback, |settings| settings.rotate_secrets_on_auth_error = true).await;
+    let mut events = handler.subscribe();
+
+    // The key was revoked and replaced at the provider; the next call is refused with a 401
+    vault.set(&new, "new-key");
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xfallback")));
+    let rotated = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let HandlerEvent::SecretRotated { urls } = events.recv().await.unwrap() {
+                break urls;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(rotated, [TEMPLATE]);
+    assert_eq!(handler.get_provider_url().await.unwrap(), keyed(&new, "new-key"));
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xnew")));
+    assert_eq!(count_method(&old, "eth_call").await, 1);
+}
+
+#[tokio::test]
+async fn test_auth_errors_leave_urls_alone_by_default() {
+    let (old, new, fallback) = (MockServer::start().await, answering("0xnew", Duration::ZERO).await, fallback().await);
+    mount_probe(&old, "0x10", Duration::ZERO).await;
+    mount_method(&old, "eth_call", ResponseTemplate::new(403)).await;
+    let vault = Vault::default();
+    vault.set(&old, "old-key");
+    let handler = handler(&vault, &fallback, |_| {}).await;
+
+    vault.set(&new, "new-key");
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xfallback")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), keyed(&old, "old-key"));
+}
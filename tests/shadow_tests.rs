@@ -0,0 +1,130 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::*;
+use ez_web3_rpc::{comparator::stable_string, *};
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+const ODD_ONE_OUT: &str = "0x00000000000000000000000000000000000000ff";
+
+fn balance(address: &str) -> JsonRpcRequest {
+    request("eth_getBalance", json!([address, "latest"]))
+}
+
+fn address(n: u8) -> String {
+    format!("0x{n:040x}")
+}
+
+/// Answers every balance with `0x5`, except `ODD_ONE_OUT`'s, which gets `odd`.
+async fn balances(odd: &'static str, delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST"))
+        .respond_with(move |request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let result = if body["params"][0] == ODD_ONE_OUT { odd } else { "0x5" };
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(result))).set_delay(delay)
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn handler(production: &MockServer) -> Arc<RpcHandler> {
+    init_handler(settings(vec![mk_rpc(production)])).await
+}
+
+/// The shadow's report once `compared + failed` reaches `replays`.
+async fn settled(handler: &RpcHandler, replays: u64) -> ShadowReport {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let report = handler.shadow_reports().remove(0);
+        if report.compared + report.failed >= replays || Instant::now() > deadline {
+            return report;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+fn digest(value: &Value) -> String {
+    format!("0x{}", Keccak256::digest(stable_string(value).as_bytes()).iter().map(|byte| format!("{byte:02x}")).collect::<String>())
+}
+
+#[tokio::test]
+async fn test_one_diverging_answer_among_many_is_counted_and_sampled() {
+    let production = balances("0x5", Duration::ZERO).await;
+    mount_method(&production, "eth_sendRawTransaction", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0xabc")))).await;
+    let shadow = balances("0x6", Duration::ZERO).await;
+    let handler = handler(&production).await;
+    handler.add_shadow_rpc(mk_rpc(&shadow), 1.0).unwrap();
+
+    for n in 1..20 {
+        assert_eq!(handler.try_proxy_request(balance(&address(n))).await.unwrap().result, Some(json!("0x5")));
+    }
+    let odd = handler.try_proxy_request(balance(ODD_ONE_OUT)).await.unwrap();
+    assert_eq!(odd.result, Some(json!("0x5")), "production answers stand whatever the shadow says");
+    let write = request("eth_sendRawTransaction", json!(["0x01"]));
+    assert!(handler.try_proxy_request(write).await.is_ok());
+
+    let report = settled(&handler, 20).await;
+    assert_eq!((report.compared, report.matched, report.mismatch_count, report.failed), (20, 19, 1, 0));
+    assert_eq!(report.mismatched, [MismatchSample {
+        method: "eth_getBalance".to_string(),
+        params_hash: digest(&json!([ODD_ONE_OUT, "latest"])),
+        production_digest: digest(&json!("0x5")),
+        shadow_digest: digest(&json!("0x6")),
+        differences: vec![Difference { path: "$".to_string(), kind: DifferenceKind::Changed { left: json!("0x5"), right: json!("0x6") } }],
+    }]);
+    assert_eq!(report.latency_delta_stats.samples, 20);
+    assert_eq!(count_method(&shadow, "eth_sendRawTransaction").await, 0, "writes are never replayed");
+}
+
+#[tokio::test]
+async fn test_a_slow_shadow_leaves_production_latency_alone() {
+    let production = balances("0x5", Duration::ZERO).await;
+    let shadow = balances("0x5", Duration::from_millis(400)).await;
+    let handler = handler(&production).await;
+    handler.add_shadow_rpc(mk_rpc(&shadow), 1.0).unwrap();
+
+    for n in 1..=10 {
+        let started = Instant::now();
+        assert!(handler.try_proxy_request(balance(&address(n))).await.is_ok());
+        assert!(started.elapsed() < Duration::from_millis(200), "took {:?}", started.elapsed());
+    }
+
+    let report = settled(&handler, 10).await;
+    assert_eq!((report.compared, report.matched), (10, 10));
+    assert!(report.latency_delta_stats.min_ms >= 300, "{:?}", report.latency_delta_stats);
+    assert!(report.latency_delta_stats.p50_ms <= report.latency_delta_stats.p95_ms);
+}
+
+#[tokio::test]
+async fn test_unsampled_and_failed_replays() {
+    let production = balances("0x5", Duration::ZERO).await;
+    let idle = balances("0x5", Duration::ZERO).await;
+    let handler = handler(&production).await;
+    handler.add_shadow_rpc(mk_rpc(&idle), 0.0).unwrap();
+    for n in 1..=5 {
+        assert!(handler.try_proxy_request(balance(&address(n))).await.is_ok());
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(count_method(&idle, "eth_getBalance").await, 0);
+    let removed = handler.remove_shadow_rpc(&url_key(&idle)).unwrap();
+    assert_eq!((removed.compared, removed.sample_rate), (0, 0.0));
+    assert!(handler.shadow_reports().is_empty());
+
+    let broken = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(500)).mount(&broken).await;
+    handler.add_shadow_rpc(mk_rpc(&broken), 1.0).unwrap();
+    for n in 1..=3 {
+        assert!(handler.try_proxy_request(balance(&address(n))).await.is_ok(), "a failing shadow never fails production");
+    }
+    let report = settled(&handler, 3).await;
+    assert_eq!((report.compared, report.failed, report.latency_delta_stats), (0, 3, LatencyDeltaStats::default()));
+}
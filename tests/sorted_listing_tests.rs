@@ -0,0 +1,92 @@
+mod common;
+
+use std::{sync::Arc, time::{Duration, SystemTime}};
+
+use common::*;
+use ez_web3_rpc::*;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+/// Four healthy endpoints at increasing latencies and one that fails its probe.
+async fn handler() -> (Arc<RpcHandler>, Vec<MockServer>) {
+    let mut servers = Vec::new();
+    for delay_ms in [60, 0, 90, 30] {
+        let server = MockServer::start().await;
+        mount_probe(&server, "0x10", Duration::from_millis(delay_ms)).await;
+        servers.push(server);
+    }
+    let broken = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(500)).mount(&broken).await;
+    servers.push(broken);
+
+    let handler = RpcHandler::new(config(settings(servers.iter().map(mk_rpc).collect())), None).await.unwrap();
+    handler.init().await.unwrap();
+    (handler, servers)
+}
+
+fn urls<T>(entries: &[T], url: impl Fn(&T) -> &str) -> Vec<String> {
+    entries.iter().map(|entry| url(entry).to_string()).collect()
+}
+
+#[tokio::test]
+async fn test_sorted_latencies_are_stable_and_break_ties_by_url() {
+    let (handler, servers) = handler().await;
+
+    let by_latency = handler.latencies_sorted(SortBy::Latency, Order::Ascending).await;
+    assert_eq!(by_latency.len(), 4);
+    assert!(by_latency.windows(2).all(|pair| pair[0].1.latency_ms <= pair[1].1.latency_ms), "{by_latency:?}");
+    assert_eq!(by_latency[0].0, url_key(&servers[1]), "the undelayed endpoint is fastest");
+    let descending = handler.latencies_sorted(SortBy::Latency, Order::Descending).await;
+    assert_eq!(descending[0].0, url_key(&servers[2]));
+
+    // Every failure count is zero, so the order is the URL tie-break alone
+    let mut expected = urls(&by_latency, |(url, _)| url);
+    expected.sort();
+    for _ in 0..5 {
+        assert_eq!(urls(&handler.latencies_sorted(SortBy::FailureCount, Order::Descending).await, |(url, _)| url), expected);
+        assert_eq!(urls(&handler.latencies_sorted(SortBy::Url, Order::Ascending).await, |(url, _)| url), expected);
+    }
+    assert!(by_latency.iter().all(|(_, record)| record.failure_count == 0 && record.last_tested <= SystemTime::now()));
+}
+
+#[tokio::test]
+async fn test_health_pages_cover_every_endpoint_once() {
+    let (handler, servers) = handler().await;
+    let sort = (SortBy::Latency, Order::Descending);
+
+    let mut pages = Vec::new();
+    let mut offset = Some(0);
+    while let Some(next) = offset {
+        let page = handler.health_report_page(next, 2, sort).await;
+        assert_eq!(page.total_endpoints, 5);
+        offset = page.next_offset;
+        pages.push(page);
+    }
+    assert_eq!(pages.iter().map(|page| page.report.endpoints.len()).collect::<Vec<_>>(), [2, 2, 1]);
+    assert_eq!(pages.iter().map(|page| page.next_offset).collect::<Vec<_>>(), [Some(2), Some(4), None]);
+
+    let listed: Vec<EndpointHealth> = pages.into_iter().flat_map(|page| page.report.endpoints).collect();
+    assert_eq!(listed[0].url, url_key(&servers[2]));
+    assert_eq!((listed[4].url.as_str(), listed[4].latency_ms), (url_key(&servers[4]).as_str(), None), "unhealthy endpoints come last in either order");
+    let whole = handler.health_report_page(0, 10, sort).await;
+    assert_eq!(urls(&whole.report.endpoints, |endpoint| &endpoint.url), urls(&listed, |endpoint| &endpoint.url));
+    assert_eq!(whole.next_offset, None);
+
+    let past_the_end = handler.health_report_page(7, 2, sort).await;
+    assert!(past_the_end.report.endpoints.is_empty() && past_the_end.next_offset.is_none());
+}
+
+#[cfg(feature = "persistence")]
+#[test]
+fn test_file_latency_store_writes_in_key_order() {
+    let path = std::env::temp_dir().join(format!("ez-web3-rpc-sorted-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let store = FileLatencyStore::open(&path, Duration::from_secs(60)).unwrap();
+    for location in ["zz", "mm", "aa"] {
+        let snapshot = LatencySnapshot { latencies: [(format!("http://{location}/"), 1)].into_iter().collect(), recorded_at: SystemTime::now() };
+        store.save(TEST_NETWORK_ID, location, snapshot).unwrap();
+    }
+    let written = std::fs::read_to_string(&path).unwrap();
+    let positions: Vec<usize> = ["aa", "mm", "zz"].iter().map(|location| written.find(&format!("/{location}\"")).unwrap()).collect();
+    assert!(positions.is_sorted(), "{written}");
+    std::fs::remove_file(&path).unwrap();
+}
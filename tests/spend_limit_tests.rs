@@ -0,0 +1,181 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use tokio::sync::broadcast;
+use wiremock::{MockServer, ResponseTemplate};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn metered(server: &MockServer, daily_budget: Option<u64>) -> RpcConfig {
+    let cost_profile = CostProfile { unit_cost: 1, method_multipliers: BTreeMap::new(), daily_budget };
+    RpcConfig { cost_profile: Some(cost_profile), ..mk_rpc(server).into() }
+}
+
+fn answer(result: serde_json::Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(rpc_response(1, result))
+}
+
+async fn endpoint(probe_delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", probe_delay).await;
+    mount_method(&server, "eth_getBalance", answer(json!("0x5"))).await;
+    server
+}
+
+async fn received(server: &MockServer) -> usize {
+    server.received_requests().await.unwrap().len()
+}
+
+async fn handler_with(settings: HandlerSettings, clock: &MockClock, store: Option<Arc<dyn SpendStore>>) -> Arc<RpcHandler> {
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), spend_store: store, ..HandlerComponents::default() };
+    RpcHandler::with_components(config(settings), None, components).await.unwrap()
+}
+
+fn exhaustions(events: &mut broadcast::Receiver<HandlerEvent>) -> Vec<(Option<String>, u64, u64, Option<String>)> {
+    std::iter::from_fn(|| events.try_recv().ok())
+        .filter_map(|event| match event {
+            HandlerEvent::BudgetExhausted { url, spent, budget, replacement } => Some((url, spent, budget, replacement)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_a_metered_endpoint_receives_exactly_its_budget_and_resumes_the_next_day() {
+    let paid = endpoint(Duration::ZERO).await;
+    let free = endpoint(Duration::from_millis(80)).await;
+    let clock = MockClock::new();
+    let handler = handler_with(settings(vec![metered(&paid, Some(10)), mk_rpc(&free).into()]), &clock, None).await;
+    let mut events = handler.subscribe();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&paid));
+
+    for _ in 0..20 {
+        let response = handler.try_proxy_request(request("eth_getBalance", json!([]))).await.unwrap();
+        assert_eq!(response.result, Some(json!("0x5")));
+    }
+
+    // Both probes and every proxied request count, and nothing goes out past the budget
+    assert_eq!(received(&paid).await, 10);
+    let report = handler.spend_report();
+    assert_eq!(report.endpoints[&url_key(&paid)], EndpointSpend { spent: 10, daily_budget: Some(10), exhausted: true });
+    assert_eq!(report.total, 10);
+    assert!(!report.endpoints.contains_key(&url_key(&free)), "unmetered endpoints aren't listed");
+    assert!(handler.health_report().await.endpoints.iter().any(|endpoint| endpoint.spend.as_ref().is_some_and(|spend| spend.exhausted)));
+    assert_eq!(handler.metrics_snapshot().spend, report);
+
+    let plan = handler.plan_request(&request("eth_getBalance", json!([])), None).await.unwrap();
+    assert!(plan.excluded.iter().any(|excluded| excluded.url == url_key(&paid) && excluded.reason == Exclusion::BudgetExhausted));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(exhaustions(&mut events), vec![(Some(url_key(&paid)), 10, 10, Some(url_key(&free)))]);
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&free));
+
+    clock.advance(DAY);
+    handler.try_proxy_request(request("eth_getBalance", json!([]))).await.unwrap();
+    assert_eq!(received(&paid).await, 11, "a new UTC day starts the budget over");
+    assert_eq!(handler.spend_report().endpoints[&url_key(&paid)].spent, 1);
+}
+
+#[tokio::test]
+async fn test_the_handler_budget_caps_every_metered_endpoint_together() {
+    let first = endpoint(Duration::ZERO).await;
+    let second = endpoint(Duration::ZERO).await;
+    let clock = MockClock::new();
+    let store: Arc<dyn SpendStore> = Arc::new(MemorySpendStore::default());
+    let settings = HandlerSettings { daily_spend_budget: Some(6), ..settings(vec![metered(&first, None), metered(&second, None)]) };
+    let handler = handler_with(settings.clone(), &clock, Some(Arc::clone(&store))).await;
+    handler.init().await.unwrap();
+
+    // Two probes each, then one race across both spends the rest
+    handler.try_proxy_request(request("eth_getBalance", json!([]))).await.unwrap();
+    let err = handler.try_proxy_request(request("eth_getBalance", json!([]))).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::BudgetExhausted { scope: BudgetScope::Handler, .. }), "{err:?}");
+    assert_eq!(received(&first).await + received(&second).await, 6);
+    assert_eq!(handler.metrics_snapshot().totals.failures.get(&FailureClass::BudgetExhausted), Some(&1));
+
+    // A handler started later the same day picks up the spend instead of a fresh budget, once
+    // the last save, made off the request path, has landed
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let restarted = handler_with(settings, &clock, Some(store)).await;
+    let report = restarted.spend_report();
+    assert_eq!((report.total, report.daily_budget), (6, Some(6)));
+    assert!(report.endpoints.values().all(|spend| spend.exhausted));
+}
+
+#[tokio::test]
+async fn test_method_multipliers_price_heavy_calls() {
+    let paid = endpoint(Duration::ZERO).await;
+    mount_method(&paid, "eth_getLogs", answer(json!([]))).await;
+    let cost_profile = CostProfile {
+        unit_cost: 2,
+        method_multipliers: BTreeMap::from([("eth_getLogs".to_string(), 5)]),
+        daily_budget: Some(20),
+    };
+    let clock = MockClock::new();
+    let handler = handler_with(settings(vec![RpcConfig { cost_profile: Some(cost_profile), ..mk_rpc(&paid).into() }]), &clock, None).await;
+    handler.init().await.unwrap();
+
+    handler.try_proxy_request(request("eth_getLogs", json!([]))).await.unwrap();
+    assert_eq!(handler.spend_report().endpoints[&url_key(&paid)].spent, 4 + 10);
+
+    // A second `eth_getLogs` would cost 10 more than the 6 left, so it isn't sent
+    let err = handler.try_proxy_request(request("eth_getLogs", json!([]))).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::BudgetExhausted { scope: BudgetScope::Endpoint, .. }), "{err:?}");
+    handler.try_proxy_request(request("eth_getBalance", json!([]))).await.unwrap();
+    assert_eq!(handler.spend_report().endpoints[&url_key(&paid)].spent, 16);
+}
+
+/// A store whose saves wait until it is `open`, and count once they are through.
+#[derive(Default)]
+struct GatedStore {
+    open: AtomicBool,
+    inner: MemorySpendStore,
+    saves: AtomicUsize,
+}
+
+impl SpendStore for GatedStore {
+    fn load(&self, network_id: NetworkId) -> Option<DailySpend> {
+        self.inner.load(network_id)
+    }
+
+    fn save(&self, network_id: NetworkId, spend: &DailySpend) -> std::io::Result<()> {
+        while !self.open.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        self.saves.fetch_add(1, Ordering::Relaxed);
+        self.inner.save(network_id, spend)
+    }
+}
+
+#[tokio::test]
+async fn test_requests_do_not_wait_for_spend_to_be_saved() {
+    let paid = endpoint(Duration::ZERO).await;
+    let store = Arc::new(GatedStore::default());
+    let clock = MockClock::new();
+    let handler = handler_with(settings(vec![metered(&paid, None)]), &clock, Some(store.clone())).await;
+    handler.init().await.unwrap();
+
+    for _ in 0..5 {
+        handler.try_proxy_request(request("eth_getBalance", json!([]))).await.unwrap();
+    }
+    store.open.store(true, Ordering::Relaxed);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The saves held up meanwhile were folded into the latest spend
+    assert_eq!(store.load(TEST_NETWORK_ID).map(|spend| spend.total), Some(7));
+    assert!(store.saves.load(Ordering::Relaxed) < 7);
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "persistence")]
+
+mod common;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+async fn endpoint(probe_delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", probe_delay).await;
+    mount_method(&server, "eth_getBalance", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x5")))).await;
+    server
+}
+
+#[test]
+fn test_file_spend_store_survives_reopening() {
+    let path = std::env::temp_dir().join(format!("ez-web3-rpc-spend-{}.json", std::process::id()));
+    let spend = DailySpend {
+        day: chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+        endpoints: BTreeMap::from([("https://paid.example/".to_string(), 7)]),
+        total: 7,
+    };
+    FileSpendStore::open(&path).unwrap().save(1, &spend).unwrap();
+
+    let reopened = FileSpendStore::open(&path).unwrap();
+    assert_eq!(reopened.load(1), Some(spend));
+    assert_eq!(reopened.load(2), None);
+    std::fs::remove_file(path).unwrap();
+}
+
+struct Secrets(HashMap<String, String>);
+
+impl SecretResolver for Secrets {
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.0.get(name).cloned()
+    }
+}
+
+#[tokio::test]
+async fn test_saved_spend_is_keyed_by_redacted_urls() {
+    const SECRET: &str = "k3y-55e1a0";
+    let paid = endpoint(Duration::ZERO).await;
+    let path = std::env::temp_dir().join(format!("ez-web3-rpc-spend-redacted-{}.json", std::process::id()));
+    let cost_profile = CostProfile { unit_cost: 1, method_multipliers: BTreeMap::new(), daily_budget: Some(10) };
+    let settings = HandlerSettings {
+        network_rpcs: vec![RpcConfig { cost_profile: Some(cost_profile), ..RpcConfig::template(format!("{}/v2/{{PAID_KEY}}", paid.uri())) }],
+        ..settings(Vec::<Rpc>::new())
+    };
+    let start = |store: Arc<dyn SpendStore>| {
+        let components = HandlerComponents {
+            secret_resolver: Some(Arc::new(Secrets(HashMap::from([("PAID_KEY".to_string(), SECRET.to_string())])))),
+            spend_store: Some(store),
+            ..HandlerComponents::default()
+        };
+        RpcHandler::with_components(config(settings.clone()), None, components)
+    };
+
+    let handler = start(Arc::new(FileSpendStore::open(&path).unwrap())).await.unwrap();
+    handler.init().await.unwrap();
+    handler.try_proxy_request(request("eth_getBalance", json!([]))).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(!saved.contains(SECRET), "secret saved in {saved}");
+    assert!(saved.contains("{PAID_KEY}"), "{saved}");
+
+    // Loaded back, the spend is the endpoint's again
+    let restarted = start(Arc::new(FileSpendStore::open(&path).unwrap())).await.unwrap();
+    let spent: Vec<u64> = restarted.spend_report().endpoints.values().map(|spend| spend.spent).collect();
+    assert_eq!(spent, vec![3]);
+    std::fs::remove_file(path).unwrap();
+}
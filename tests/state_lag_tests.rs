@@ -0,0 +1,86 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{matchers::{body_partial_json, method}, Mock, MockServer, Request, ResponseTemplate};
+
+/// An endpoint whose head is `head`, answering `eth_call` with the block it was asked at.
+async fn endpoint(head: &str, delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", delay).await;
+    mount_method(&server, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(head)))).await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "method": "eth_call" })))
+        .respond_with(|request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            ResponseTemplate::new(200).set_body_json(rpc_response(1, body["params"][1].clone()))
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+fn call_at(block: Value) -> JsonRpcRequest {
+    request("eth_call", json!([{ "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "data": "0x18160ddd" }, block]))
+}
+
+fn guarded(max_state_lag_blocks: u64) -> CallOptions {
+    CallOptions { max_state_lag_blocks: Some(max_state_lag_blocks), ..CallOptions::default() }
+}
+
+#[tokio::test]
+async fn test_guarded_state_reads_skip_lagging_endpoints() {
+    let lagging = endpoint("0x10", Duration::ZERO).await;
+    let fresh = endpoint("0x20", Duration::from_millis(40)).await;
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&lagging), mk_rpc(&fresh)])), None).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&lagging));
+
+    // Unguarded, "latest" goes to the faster endpoint as is
+    let (response, url) = handler.try_proxy_request_with(call_at(json!("latest")), CallOptions::default()).await.unwrap();
+    assert_eq!((response.into_result().unwrap(), url), (json!("latest"), url_key(&lagging)));
+
+    // Guarded, it is pinned to the fresh endpoint's head and sent there
+    let attributed = handler.try_proxy_request_attributed_with(call_at(json!("latest")), guarded(2)).await.unwrap();
+    assert_eq!(attributed.url, url_key(&fresh));
+    assert_eq!(attributed.block_number, Some(0x20));
+    assert_eq!(attributed.response.into_result().unwrap(), json!("0x20"));
+
+    // A left-out block param reads at "latest" too
+    let mut implicit = call_at(Value::Null);
+    implicit.params.as_array_mut().unwrap().pop();
+    let attributed = handler.try_proxy_request_attributed_with(implicit, guarded(2)).await.unwrap();
+    assert_eq!((attributed.url, attributed.block_number), (url_key(&fresh), Some(0x20)));
+
+    // Heads were resolved once within the cache window
+    assert_eq!(count_method(&lagging, "eth_blockNumber").await, 1);
+    assert_eq!(count_method(&fresh, "eth_blockNumber").await, 1);
+
+    // A lag the lagging endpoint is within keeps both, pinned to the lower head
+    let attributed = handler.try_proxy_request_attributed_with(call_at(json!("latest")), guarded(0x10)).await.unwrap();
+    assert_eq!((attributed.url, attributed.block_number), (url_key(&lagging), Some(0x10)));
+
+    // Reads at a concrete block are left alone
+    let attributed = handler.try_proxy_request_attributed_with(call_at(json!("0x5")), guarded(2)).await.unwrap();
+    assert_eq!((attributed.url, attributed.block_number), (url_key(&lagging), None));
+    assert_eq!(attributed.response.into_result().unwrap(), json!("0x5"));
+}
+
+#[tokio::test]
+async fn test_guarded_read_fails_when_every_endpoint_lags() {
+    let lagging = endpoint("0x10", Duration::ZERO).await;
+    let fresh = endpoint("0x20", Duration::ZERO).await;
+    let handler = RpcHandler::new(config(settings(vec![mk_rpc(&lagging), mk_rpc(&fresh)])), None).await.unwrap();
+    handler.init().await.unwrap();
+    handler.try_proxy_request_with(call_at(json!("latest")), guarded(2)).await.unwrap();
+
+    // With the fresh endpoint ruled out, the head it reported still stands
+    let options = CallOptions { exclude: vec![url_key(&fresh)], ..guarded(2) };
+    match handler.try_proxy_request_with(call_at(json!("latest")), options).await {
+        Err(RpcHandlerError::StaleState { watermark, max_lag_blocks }) => assert_eq!((watermark, max_lag_blocks), (0x20, 2)),
+        other => panic!("expected StaleState, got {other:?}"),
+    }
+}
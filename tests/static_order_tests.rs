@@ -0,0 +1,77 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+fn eth_call() -> JsonRpcRequest {
+    request("eth_call", json!([{ "to": "0x0", "data": "0x" }, "latest"]))
+}
+
+async fn answering(result: &str) -> MockServer {
+    let server = MockServer::start().await;
+    mount_method(&server, "eth_call", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(result)))).await;
+    server
+}
+
+async fn received(server: &MockServer) -> usize {
+    server.received_requests().await.unwrap().len()
+}
+
+async fn handler(servers: &[&MockServer], order: Vec<String>) -> Result<Arc<RpcHandler>> {
+    let rpcs = servers.iter().map(|server| mk_rpc(server)).collect();
+    RpcHandler::new(config(settings(rpcs)), Some(Strategy::StaticOrder(order))).await
+}
+
+#[tokio::test]
+async fn test_init_sends_nothing_and_serves_through_the_first_url() {
+    let (a, b, c) = (answering("0xa").await, answering("0xb").await, answering("0xc").await);
+    let handler = handler(&[&a, &b, &c], vec![c.uri(), a.uri(), b.uri()]).await.unwrap();
+    handler.init().await.unwrap();
+    handler.refresh().await.unwrap();
+
+    for server in [&a, &b, &c] {
+        assert_eq!(received(server).await, 0);
+    }
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&c));
+    assert!(handler.get_latencies().await.is_empty());
+    let ordered: Vec<String> = handler.ordered_rpcs().await.iter().map(|ordered| ordered.rpc.url.to_string()).collect();
+    assert_eq!(ordered, [url_key(&c), url_key(&a), url_key(&b)]);
+
+    let plan = handler.plan_request(&eth_call(), None).await.unwrap();
+    assert_eq!(plan.batches(), [vec![url_key(&c)], vec![url_key(&a)], vec![url_key(&b)]]);
+    assert!(plan.urls.iter().all(|planned| planned.placement == Placement::Static));
+    let policy = handler.effective_policy();
+    assert_eq!(policy.racing.batch_size, 1);
+    assert_eq!(serde_json::to_value(&policy.strategy).unwrap()["name"], "static_order");
+}
+
+#[tokio::test]
+async fn test_failover_walks_the_list_in_order() {
+    let (a, b, c) = (answering("0xa").await, answering("0xb").await, answering("0xc").await);
+    let handler = handler(&[&a, &b, &c], vec![a.uri(), b.uri(), c.uri()]).await.unwrap();
+    handler.init().await.unwrap();
+
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xa")));
+    assert_eq!((received(&b).await, received(&c).await), (0, 0));
+
+    // A goes down; the next call moves on to B alone
+    a.reset().await;
+    mount_method(&a, "eth_call", ResponseTemplate::new(500)).await;
+    assert_eq!(handler.try_proxy_request(eth_call()).await.unwrap().result, Some(json!("0xb")));
+    assert_eq!((count_method(&a, "eth_call").await, count_method(&b, "eth_call").await, received(&c).await), (1, 1, 0));
+}
+
+#[tokio::test]
+async fn test_unknown_url_is_a_config_error() {
+    let a = answering("0xa").await;
+    let result = handler(&[&a], vec![a.uri(), "https://not-configured.example".to_string()]).await;
+    assert!(
+        matches!(result, Err(RpcHandlerError::UnknownStaticOrderUrl { ref url }) if url == "https://not-configured.example/"),
+        "{:?}",
+        result.err()
+    );
+}
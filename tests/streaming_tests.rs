@@ -0,0 +1,210 @@
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    future::Future,
+    ops::ControlFlow,
+    sync::Arc,
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{MockServer, ResponseTemplate};
+
+/// Tracks bytes allocated by the current thread while a measurement runs. The mock servers run on
+/// threads of their own, so only the client's allocations are counted.
+struct CountingAllocator;
+
+thread_local! {
+    /// `(current, peak)` while measuring
+    static ALLOCATED: Cell<Option<(isize, isize)>> = const { Cell::new(None) };
+}
+
+fn track(delta: isize) {
+    let _ = ALLOCATED.try_with(|allocated| {
+        if let Some((current, peak)) = allocated.get() {
+            allocated.set(Some((current + delta, peak.max(current + delta))));
+        }
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            track(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        track(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new.is_null() {
+            track(new_size as isize - layout.size() as isize);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Run `future` on this thread, returning its output and the most it had allocated at once.
+async fn peak_allocation<F: Future>(future: F) -> (F::Output, usize) {
+    ALLOCATED.with(|allocated| allocated.set(Some((0, 0))));
+    let output = future.await;
+    let (_, peak) = ALLOCATED.with(|allocated| allocated.take()).unwrap();
+    (output, peak as usize)
+}
+
+fn log(index: usize) -> Value {
+    json!({
+        "address": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+        "topics": ["0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef", format!("0x{:064x}", index)],
+        "data": format!("0x{:064x}", index * 1000),
+        "blockNumber": format!("0x{:x}", 18_000_000 + index / 50),
+        "transactionHash": format!("0x{:064x}", index / 3),
+        "logIndex": format!("0x{:x}", index),
+        "removed": false
+    })
+}
+
+/// A response body of `count` logs, written out by hand so it can be cut off anywhere.
+fn logs_body(count: usize) -> String {
+    let logs: Vec<String> = (0..count).map(|index| log(index).to_string()).collect();
+    format!(r#"{{"jsonrpc":"2.0","id":1,"result":[{}]}}"#, logs.join(","))
+}
+
+fn raw(body: impl Into<Vec<u8>>) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(body.into(), "application/json")
+}
+
+async fn endpoint(probe_delay_ms: u64) -> MockServer {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::from_millis(probe_delay_ms)).await;
+    server
+}
+
+async fn handler(servers: &[&MockServer]) -> Arc<RpcHandler> {
+    let settings = HandlerSettings {
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: 5000, connect_timeout_ms: None }),
+        ..settings(servers.iter().map(|server| mk_rpc(server)).collect())
+    };
+    init_handler(settings).await
+}
+
+#[tokio::test]
+async fn test_large_log_array_streams_in_bounded_memory() {
+    const LOGS: usize = 20_000;
+    let server = endpoint(0).await;
+    let body = logs_body(LOGS);
+    assert!(body.len() > 6 << 20, "body is {} bytes", body.len());
+    mount_method(&server, "eth_getLogs", raw(body.clone())).await;
+    let provider = handler(&[&server]).await.get_provider().await.unwrap();
+
+    let mut seen = 0;
+    let mut sink = |item: Value| {
+        assert_eq!(item, log(seen));
+        seen += 1;
+        ControlFlow::Continue(())
+    };
+    let (summary, peak) = peak_allocation(provider.send_request_streaming(&request("eth_getLogs", json!([{ "fromBlock": "0x1", "toBlock": "latest" }])), &mut sink)).await;
+    let summary = summary.unwrap();
+    assert_eq!(summary, StreamSummary { url: url_key(&server), items_emitted: LOGS as u64, stopped_early: false });
+    assert_eq!(seen, LOGS);
+    assert!(peak < 2 << 20, "streaming peaked at {peak} bytes");
+
+    // Buffering the same response needs more than the whole body at once
+    let (response, buffered_peak) = peak_allocation(provider.send_request(&request("eth_getLogs", json!([{ "fromBlock": "0x1", "toBlock": "latest" }])))).await;
+    assert_eq!(response.unwrap().result.unwrap().as_array().unwrap().len(), LOGS);
+    assert!(buffered_peak > body.len(), "buffering peaked at {buffered_peak} bytes");
+}
+
+#[tokio::test]
+async fn test_sink_can_stop_early_and_scalars_come_whole() {
+    let server = endpoint(0).await;
+    mount_method(&server, "eth_getLogs", raw(logs_body(5_000))).await;
+    mount_method(&server, "debug_traceTransaction", raw(r#"{"id":1,"result":{"gas":21000,"failed":false,"returnValue":"","structLogs":[]},"jsonrpc":"2.0"}"#)).await;
+    let provider = handler(&[&server]).await.get_provider().await.unwrap();
+
+    let mut items = Vec::new();
+    let mut sink = |item: Value| {
+        items.push(item);
+        if items.len() == 10 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    };
+    let summary = provider.send_request_streaming(&request("eth_getLogs", json!([{ "fromBlock": "0x1", "toBlock": "latest" }])), &mut sink).await.unwrap();
+    assert_eq!((summary.items_emitted, summary.stopped_early), (10, true));
+    assert_eq!(items, (0..10).map(log).collect::<Vec<_>>());
+
+    let mut trace = Vec::new();
+    let mut sink = |item: Value| {
+        trace.push(item);
+        ControlFlow::Continue(())
+    };
+    let summary = provider.send_request_streaming(&request("debug_traceTransaction", json!([{ "fromBlock": "0x1", "toBlock": "latest" }])), &mut sink).await.unwrap();
+    assert_eq!((summary.items_emitted, summary.stopped_early), (1, false));
+    assert_eq!(trace, [json!({ "gas": 21000, "failed": false, "returnValue": "", "structLogs": [] })]);
+}
+
+#[tokio::test]
+async fn test_fails_over_only_until_the_first_item() {
+    let (first, second) = (endpoint(0).await, endpoint(40).await);
+    // Cut off after three logs
+    let full = logs_body(50);
+    let cut = full.match_indices(r#"},{"#).nth(2).unwrap().0 + 2;
+    mount_method(&first, "eth_getLogs", raw(&full.as_bytes()[..cut])).await;
+    mount_method(&first, "debug_traceTransaction", ResponseTemplate::new(503)).await;
+    mount_method(&first, "trace_filter", raw(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"invalid params"}}"#)).await;
+    mount_method(&first, "trace_block", raw(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32603,"message":"internal error"}}"#)).await;
+    for method in ["eth_getLogs", "debug_traceTransaction", "trace_filter", "trace_block"] {
+        mount_method(&second, method, raw(logs_body(4))).await;
+    }
+    let provider = handler(&[&first, &second]).await.get_provider().await.unwrap();
+    assert_eq!(provider.base_url, url_key(&first));
+
+    let stream = |method: &'static str| {
+        let provider = provider.clone();
+        async move {
+            let mut items = 0;
+            let mut sink = |_: Value| {
+                items += 1;
+                ControlFlow::Continue(())
+            };
+            let result = provider.send_request_streaming(&request(method, json!([{ "fromBlock": "0x1", "toBlock": "latest" }])), &mut sink).await;
+            (result, items)
+        }
+    };
+
+    // Failures before anything reached the sink move on transparently
+    for method in ["debug_traceTransaction", "trace_block"] {
+        let (summary, items) = stream(method).await;
+        assert_eq!(summary.unwrap().url, url_key(&second), "{method}");
+        assert_eq!(items, 4);
+    }
+
+    // Once items went out, a failure is reported instead of restarting elsewhere
+    let (result, items) = stream("eth_getLogs").await;
+    assert_eq!(items, 3);
+    match result.unwrap_err() {
+        RpcHandlerError::PartialStream { url, items_emitted: 3, cause } => {
+            assert_eq!(url, url_key(&first));
+            assert!(matches!(*cause, RpcHandlerError::MalformedResponse { .. }), "got {cause:?}");
+        }
+        other => panic!("expected PartialStream, got {other:?}"),
+    }
+    assert_eq!(count_method(&second, "eth_getLogs").await, 0);
+
+    // An error embedded in the body is found, and a deterministic one isn't retried
+    let (result, items) = stream("trace_filter").await;
+    assert!(matches!(result, Err(RpcHandlerError::JsonRpcCode { code: -32602, .. })), "got {result:?}");
+    assert_eq!(items, 0);
+    assert_eq!(count_method(&second, "trace_filter").await, 0);
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "consensus")]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+use wiremock::{matchers::{body_partial_json, method}, Mock, MockServer, Request, ResponseTemplate};
+
+fn balance() -> JsonRpcRequest {
+    request("eth_getBalance", json!(["0x0000000000000000000000000000000000000001", "latest"]))
+}
+
+/// Three endpoints answering `eth_getBalance` with `first` the first time each is asked and
+/// `then` afterwards.
+async fn endpoints(first: &str, then: &str) -> (Vec<MockServer>, RpcCalls) {
+    let mut servers = Vec::new();
+    for _ in 0..3 {
+        let server = MockServer::start().await;
+        mount_probe(&server, "0x10", Duration::ZERO).await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "eth_getBalance" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(first))))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        mount_method(&server, "eth_getBalance", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(then)))).await;
+        servers.push(server);
+    }
+    let handler = RpcHandler::new(config(settings(servers.iter().map(mk_rpc).collect())), None).await.unwrap();
+    handler.init().await.unwrap();
+    (servers, RpcCalls::new(handler))
+}
+
+fn options(recheck_after: Recheck, max_rechecks: usize) -> TemporalOptions {
+    TemporalOptions { max_rechecks, head_poll_interval: Duration::from_millis(10), ..TemporalOptions::new(recheck_after) }
+}
+
+#[tokio::test]
+async fn test_stable_answer_returns_after_two_rounds() {
+    let (_servers, calls) = endpoints("0x5", "0x5").await;
+
+    let (result, report) = calls.temporal_consensus_with_report::<String>(&balance(), 0.66, options(Recheck::Duration(Duration::from_millis(20)), 3)).await;
+    assert_eq!(result.unwrap(), "0x5");
+    assert_eq!(report.rounds.len(), 2);
+    for round in &report.rounds {
+        assert_eq!(round.value, Some(json!("0x5")));
+        assert!(round.report.votes.values().sum::<usize>() >= 2, "{}", round.report);
+    }
+}
+
+#[tokio::test]
+async fn test_changed_answer_fails_naming_both_values() {
+    let (_servers, calls) = endpoints("0x5", "0x6").await;
+
+    let (result, report) = calls.temporal_consensus_with_report::<String>(&balance(), 0.66, options(Recheck::Duration(Duration::from_millis(20)), 1)).await;
+    match result {
+        Err(RpcHandlerError::TemporalConsensus { previous, latest, rounds }) => {
+            assert!(previous.contains("0x5") && latest.contains("0x6"), "{previous} then {latest}");
+            assert_eq!(rounds, 2);
+        }
+        other => panic!("expected a temporal mismatch, got {other:?}"),
+    }
+    assert_eq!(report.rounds.iter().map(|round| round.value.clone()).collect::<Vec<_>>(), vec![Some(json!("0x5")), Some(json!("0x6"))]);
+}
+
+#[tokio::test]
+async fn test_answer_that_settles_is_returned_on_a_later_recheck() {
+    let (_servers, calls) = endpoints("0x5", "0x6").await;
+
+    let (result, report) = calls.temporal_consensus_with_report::<String>(&balance(), 0.66, options(Recheck::Duration(Duration::from_millis(20)), 2)).await;
+    assert_eq!(result.unwrap(), "0x6");
+    assert_eq!(report.rounds.len(), 3);
+}
+
+#[tokio::test]
+async fn test_block_recheck_waits_for_the_head_to_advance() {
+    let (servers, calls) = endpoints("0x5", "0x5").await;
+    // The head reads 0x10 twice, then 0x12
+    let reads = Arc::new(AtomicUsize::new(0));
+    for server in &servers {
+        let reads = Arc::clone(&reads);
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "eth_blockNumber" })))
+            .respond_with(move |_: &Request| {
+                let head = if reads.fetch_add(1, Ordering::SeqCst) < 2 { "0x10" } else { "0x12" };
+                ResponseTemplate::new(200).set_body_json(rpc_response(1, json!(head)))
+            })
+            .mount(server)
+            .await;
+    }
+
+    let (result, report) = calls.temporal_consensus_with_report::<String>(&balance(), 0.66, options(Recheck::Blocks(2), 1)).await;
+    assert_eq!(result.unwrap(), "0x5");
+    assert_eq!(report.rounds[1].head, Some(0x12));
+    assert!(reads.load(Ordering::SeqCst) >= 3, "the head was read before it advanced");
+}
+
+#[tokio::test]
+async fn test_cancellation_and_shutdown_end_the_wait() {
+    let (_servers, calls) = endpoints("0x5", "0x5").await;
+    let wait = Recheck::Duration(Duration::from_secs(60));
+
+    let cancel = CancellationToken::new();
+    let canceller = {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        })
+    };
+    let started = Instant::now();
+    let cancelled = calls.temporal_consensus::<String>(&balance(), 0.66, TemporalOptions { cancel: Some(cancel), ..options(wait, 1) }).await;
+    assert!(matches!(cancelled, Err(RpcHandlerError::TemporalConsensusCancelled { rounds: 1 })), "{cancelled:?}");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    canceller.await.unwrap();
+
+    calls.handler().shutdown();
+    let shut_down = calls.temporal_consensus::<String>(&balance(), 0.66, options(wait, 1)).await;
+    assert!(matches!(shut_down, Err(RpcHandlerError::TemporalConsensusCancelled { rounds: 1 })), "{shut_down:?}");
+}
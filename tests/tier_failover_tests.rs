@@ -0,0 +1,81 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+#[cfg(feature = "scenarios")]
+use ez_web3_rpc::scenario::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{MockServer, ResponseTemplate};
+
+fn chain_id_request() -> JsonRpcRequest {
+    request("eth_chainId", json!([]))
+}
+
+#[tokio::test]
+async fn test_slow_tier0_wins_over_fast_tier1() {
+    let slow_tier0 = MockServer::start().await;
+    let fast_tier1 = MockServer::start().await;
+    mount_probe(&slow_tier0, "0x10", Duration::from_millis(80)).await;
+    mount_probe(&fast_tier1, "0x10", Duration::ZERO).await;
+    mount_method(&slow_tier0, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    mount_method(&fast_tier1, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+
+    let mut settings = settings(vec![tiered(&fast_tier1, 1), tiered(&slow_tier0, 0)]);
+    settings.failover_policy = FailoverPolicy::TierStrict;
+
+    let handler = RpcHandler::new(config(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+
+    let latencies = handler.get_latencies().await;
+    assert!(latencies[&url_key(&slow_tier0)] > latencies[&url_key(&fast_tier1)]);
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&slow_tier0));
+
+    handler.try_proxy_request(chain_id_request()).await.unwrap();
+    assert_eq!(count_method(&slow_tier0, "eth_chainId").await, 1);
+    assert_eq!(count_method(&fast_tier1, "eth_chainId").await, 0, "tier 1 must not be touched while tier 0 works");
+
+    let report = handler.health_report().await;
+    let tier0 = report.endpoints.iter().find(|e| e.url == url_key(&slow_tier0)).unwrap();
+    assert_eq!(tier0.tier, Some(0));
+    assert!(tier0.active);
+    assert!(report.to_string().contains("tier   1"));
+}
+
+#[cfg(feature = "scenarios")]
+#[tokio::test]
+async fn test_failover_crosses_tiers_only_after_tier0_exhausted() {
+    let mut scenario = Scenario::new();
+    let tier1 = scenario.endpoint("tier1", Script::healthy().method("eth_chainId", Behavior::Answer(json!("0x2")))).await.unwrap();
+    let tier0_a = scenario.endpoint("tier0_a", Script::healthy().method("eth_chainId", Behavior::Status(500))).await.unwrap();
+    let tier0_b = scenario.endpoint("tier0_b", Script::healthy().method("eth_chainId", Behavior::Status(500))).await.unwrap();
+
+    let tiered = |endpoint: &ScenarioEndpoint, tier| RpcConfig { tier: Some(tier), ..endpoint.rpc().into() };
+    let mut settings = settings(vec![tiered(&tier1, 1), tiered(&tier0_a, 0), tiered(&tier0_b, 0)]);
+    settings.failover_policy = FailoverPolicy::TierStrict;
+
+    let handler = RpcHandler::new(scenario.config_with(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+
+    let resp = handler.try_proxy_request(chain_id_request()).await.unwrap();
+    assert_eq!(resp.result, Some(json!("0x2")));
+    assert_eq!(tier0_a.requests_for("eth_chainId"), 1);
+    assert_eq!(tier0_b.requests_for("eth_chainId"), 1);
+    assert_eq!(tier1.requests_for("eth_chainId"), 1);
+}
+
+#[tokio::test]
+async fn test_untiered_endpoints_sort_last() {
+    let untiered = MockServer::start().await;
+    let tiered = MockServer::start().await;
+    mount_probe(&untiered, "0x10", Duration::ZERO).await;
+    mount_probe(&tiered, "0x10", Duration::from_millis(50)).await;
+
+    let mut settings = settings(vec![mk_rpc(&untiered).into(), RpcConfig { tier: Some(7), ..mk_rpc(&tiered).into() }]);
+    settings.failover_policy = FailoverPolicy::TierStrict;
+
+    let handler = RpcHandler::new(config(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&tiered));
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use tokio::{
+    net::{TcpSocket, TcpStream},
+    sync::Notify,
+    task::JoinSet,
+};
+use wiremock::{MockServer, ResponseTemplate};
+
+/// Forwards to an upstream through a listener with a one-slot accept queue until `stall`.
+struct Gate {
+    addr: SocketAddr,
+    stop: Arc<Notify>,
+}
+
+impl Gate {
+    async fn open(upstream: &MockServer) -> Self {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let target = *upstream.address();
+        let stop = Arc::new(Notify::new());
+
+        let stopped = Arc::clone(&stop);
+        tokio::spawn(async move {
+            let mut forwarding = JoinSet::new();
+            loop {
+                tokio::select! {
+                    _ = stopped.notified() => break,
+                    Ok((mut inbound, _)) = listener.accept() => {
+                        forwarding.spawn(async move {
+                            if let Ok(mut outbound) = TcpStream::connect(target).await {
+                                let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                            }
+                        });
+                    }
+                }
+            }
+            forwarding.abort_all();
+            // Keep listening without accepting, so new connections queue up and then hang
+            std::future::pending::<()>().await;
+            drop(listener);
+        });
+        Self { addr, stop }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// Drop every forwarded connection and fill the accept queue. Returns the queued
+    /// connections, which must be kept alive for the queue to stay full.
+    async fn stall(&self) -> Vec<TcpStream> {
+        self.stop.notify_one();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut queued = Vec::new();
+        for _ in 0..4 {
+            if let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(100), TcpStream::connect(self.addr)).await {
+                queued.push(stream);
+            }
+        }
+        let hung = tokio::time::timeout(Duration::from_millis(200), TcpStream::connect(self.addr)).await;
+        assert!(hung.is_err(), "a full accept queue should leave the handshake hanging");
+        queued
+    }
+}
+
+fn proxy(retry_count: u32, rpc_call_timeout_ms: u64, connect_timeout_ms: u64) -> Option<ProxySettings> {
+    Some(ProxySettings { retry_count, retry_delay_ms: 10, rpc_call_timeout_ms, connect_timeout_ms: Some(connect_timeout_ms) })
+}
+
+#[tokio::test]
+async fn test_unconnectable_endpoint_fails_fast_and_sits_out_the_request() {
+    let upstream = MockServer::start().await;
+    mount_probe(&upstream, "0x10", Duration::ZERO).await;
+    mount_method(&upstream, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    let gate = Gate::open(&upstream).await;
+    let rpc = Rpc { url: gate.url().parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) };
+    let handler = RpcHandler::new(config(HandlerSettings { proxy_settings: proxy(3, 5000, 200), ..settings(vec![rpc]) }), None).await.unwrap();
+    handler.init().await.unwrap();
+    let _queued = gate.stall().await;
+
+    let started = Instant::now();
+    let err = handler.try_proxy_request(request("eth_chainId", json!([]))).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::ConnectTimeout { ref url } if *url == gate.url()), "got {err:?}");
+    assert_eq!(err.timeout_phase(), Some(TimeoutPhase::ConnectPhase));
+    assert!(err.is_transport());
+    // One connect budget, not three, and nowhere near the total budget
+    assert!(started.elapsed() < Duration::from_millis(1500), "took {:?}", started.elapsed());
+    assert!(handler.probe_schedule().should_skip(&gate.url(), Instant::now()));
+    assert_eq!(handler.probe_schedule().classification(&gate.url()), None);
+}
+
+#[tokio::test]
+async fn test_slow_heavy_call_runs_into_the_total_budget_and_stays_in_rotation() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    let slow = ResponseTemplate::new(200).set_body_json(rpc_response(1, json!([]))).set_delay(Duration::from_millis(500));
+    mount_method(&server, "eth_getLogs", slow).await;
+    let handler = RpcHandler::new(config(HandlerSettings { proxy_settings: proxy(2, 150, 100), ..settings(vec![mk_rpc(&server)]) }), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let err = handler.try_proxy_request(request("eth_getLogs", json!([]))).await.unwrap_err();
+    assert!(matches!(err, RpcHandlerError::RequestTimeout { configured_ms: 150, .. }), "got {err:?}");
+    assert_eq!(err.timeout_phase(), Some(TimeoutPhase::TotalBudget));
+    assert_eq!(count_method(&server, "eth_getLogs").await, 2, "a connected endpoint is retried");
+    assert!(!handler.probe_schedule().should_skip(&url_key(&server), Instant::now()));
+}
+
+#[cfg(feature = "consensus")]
+#[tokio::test]
+async fn test_heavy_timeouts_cool_down_lightly_in_consensus() {
+    let cooldown_for = |method: &'static str| async move {
+        let (slow, fast) = (MockServer::start().await, MockServer::start().await);
+        let answer = ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")));
+        mount_method(&slow, method, answer.clone().set_delay(Duration::from_millis(500))).await;
+        mount_method(&fast, method, answer).await;
+        let rpcs = vec![mk_rpc(&slow), mk_rpc(&fast)];
+        let calls = RpcCalls::new(RpcHandler::new(config(settings(rpcs)), None).await.unwrap());
+        let options = ConsensusOptions { timeout_ms: Some(150), cooldown_ms: Some(1000), per_host_concurrency: Some(4), ..ConsensusOptions::default() };
+        let (_, report) = calls.consensus_with_report::<String>(&request(method, json!([])), 1.0, Some(options)).await;
+        let cooldown = report.cooldowns.into_iter().next().unwrap();
+        assert_eq!(cooldown.url, url_key(&slow));
+        (cooldown.strikes, cooldown.delay_ms)
+    };
+
+    assert_eq!(cooldown_for("eth_getLogs").await, (0, 500), "half the base, no strike");
+    assert_eq!(cooldown_for("eth_blockNumber").await, (1, 1000));
+}
@@ -0,0 +1,141 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{matchers::{body_partial_json, method}, Mock, MockServer, Request, ResponseTemplate};
+
+const TEN_MINUTES: i64 = 10 * 60;
+const HASH: &str = "0x88df016429689c079f3b2f6ad39fa052532c56795b733da78a91ebe6a713944b";
+
+fn now_secs(clock: &MockClock) -> i64 {
+    clock.now_system().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+/// An endpoint whose blocks, probed head and by hash alike, are dated `offset` seconds from
+/// `clock`'s time; the offset can be changed while it runs.
+async fn endpoint(clock: &MockClock, offset: &Arc<AtomicI64>, probe_delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    for rpc_method in ["eth_getBlockByNumber", "eth_getBlockByHash"] {
+        let (clock, offset) = (clock.clone(), Arc::clone(offset));
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": rpc_method })))
+            .respond_with(move |_: &Request| {
+                let timestamp = now_secs(&clock) + offset.load(Ordering::SeqCst);
+                let block = json!({ "number": "0x10", "hash": HASH, "timestamp": format!("{timestamp:#x}") });
+                ResponseTemplate::new(200).set_body_json(rpc_response(1, block)).set_delay(probe_delay)
+            })
+            .mount(&server)
+            .await;
+    }
+    // Only its `eth_getCode` answer is used: the block mocks above were mounted first
+    mount_probe(&server, "0x10", probe_delay).await;
+    mount_method(&server, "eth_getBalance", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x5")))).await;
+    server
+}
+
+fn sanity(strict: bool) -> TimestampSanity {
+    TimestampSanity { max_future_drift_ms: 30_000, max_past_lag_ms: Some(120_000), strict, sane_to_clear: 3 }
+}
+
+async fn handler_with(rpcs: Vec<Rpc>, sanity: TimestampSanity, clock: &MockClock) -> Arc<RpcHandler> {
+    let settings = HandlerSettings { timestamp_sanity: Some(sanity), ..settings(rpcs) };
+    let components = HandlerComponents { clock: Some(Arc::new(clock.clone())), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config(settings), None, components).await.unwrap();
+    handler.init().await.unwrap();
+    handler
+}
+
+fn flags_of(report: &HealthReport, server: &MockServer) -> BTreeSet<HealthFlag> {
+    report.endpoints.iter().find(|endpoint| endpoint.url == url_key(server)).unwrap().flags.clone()
+}
+
+#[tokio::test]
+async fn test_a_clock_ahead_by_ten_minutes_is_flagged_demoted_and_cleared_by_sane_blocks() {
+    let clock = MockClock::new();
+    let ahead = Arc::new(AtomicI64::new(TEN_MINUTES));
+    let skewed = endpoint(&clock, &ahead, Duration::ZERO).await;
+    let sane = endpoint(&clock, &Arc::new(AtomicI64::new(0)), Duration::from_millis(80)).await;
+    let handler = handler_with(vec![mk_rpc(&skewed), mk_rpc(&sane)], sanity(false), &clock).await;
+
+    // The faster endpoint is only used once nothing trustworthy is left
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&sane));
+    assert_eq!(flags_of(&handler.health_report().await, &skewed), BTreeSet::from([HealthFlag::ClockSkewSuspected]));
+    assert!(flags_of(&handler.health_report().await, &sane).is_empty());
+
+    let plan = handler.plan_request(&block_number(), None).await.unwrap();
+    let last = plan.urls.last().unwrap();
+    assert_eq!((last.url.as_str(), &last.placement), (url_key(&skewed).as_str(), &Placement::ClockSkewSuspected));
+    assert!(last.batch > plan.urls[0].batch);
+
+    // Two sane sweeps aren't enough, the third clears the flag
+    ahead.store(0, Ordering::SeqCst);
+    for _ in 0..2 {
+        handler.refresh().await.unwrap();
+        assert!(handler.timestamp_flags().contains_key(&url_key(&skewed)));
+    }
+    handler.refresh().await.unwrap();
+    assert!(handler.timestamp_flags().is_empty());
+    let plan = handler.plan_request(&block_number(), None).await.unwrap();
+    assert!(plan.urls.iter().all(|planned| planned.placement != Placement::ClockSkewSuspected));
+}
+
+#[tokio::test]
+async fn test_strict_mode_fails_over_from_a_skewed_block() {
+    let clock = MockClock::new();
+    let offset = Arc::new(AtomicI64::new(0));
+    let skewed = endpoint(&clock, &offset, Duration::ZERO).await;
+    let sane = endpoint(&clock, &Arc::new(AtomicI64::new(0)), Duration::from_millis(80)).await;
+    let handler = handler_with(vec![mk_rpc(&skewed), mk_rpc(&sane)], sanity(true), &clock).await;
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(&skewed));
+
+    // The node's clock goes wrong after it was probed
+    offset.store(TEN_MINUTES, Ordering::SeqCst);
+    let (response, url) = handler.try_proxy_request_attributed(request("eth_getBlockByHash", json!([HASH, false]))).await.unwrap();
+    assert_eq!(url, url_key(&sane));
+    let timestamp = u64::from_str_radix(response.result.unwrap()["timestamp"].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+    assert!(timestamp as i64 <= now_secs(&clock));
+    assert_eq!(handler.timestamp_flags()[&url_key(&skewed)], BTreeSet::from([HealthFlag::ClockSkewSuspected]));
+    assert_eq!(handler.metrics_snapshot().endpoints[&url_key(&skewed)].failures.get(&FailureClass::Malformed), Some(&1));
+}
+
+#[tokio::test]
+async fn test_without_strict_mode_a_skewed_block_is_returned_but_flagged() {
+    let clock = MockClock::new();
+    let offset = Arc::new(AtomicI64::new(0));
+    let skewed = endpoint(&clock, &offset, Duration::ZERO).await;
+    let handler = handler_with(vec![mk_rpc(&skewed)], sanity(false), &clock).await;
+
+    offset.store(TEN_MINUTES, Ordering::SeqCst);
+    let (_, url) = handler.try_proxy_request_attributed(request("eth_getBlockByHash", json!([HASH, false]))).await.unwrap();
+    assert_eq!(url, url_key(&skewed));
+    assert_eq!(handler.timestamp_flags()[&url_key(&skewed)], BTreeSet::from([HealthFlag::ClockSkewSuspected]));
+}
+
+#[tokio::test]
+async fn test_an_old_head_block_marks_the_endpoint_lagging_for_state_reads() {
+    let clock = MockClock::new();
+    let stale = endpoint(&clock, &Arc::new(AtomicI64::new(-3600)), Duration::ZERO).await;
+    let fresh = endpoint(&clock, &Arc::new(AtomicI64::new(-5)), Duration::from_millis(80)).await;
+    let handler = handler_with(vec![mk_rpc(&stale), mk_rpc(&fresh)], sanity(false), &clock).await;
+    assert_eq!(flags_of(&handler.health_report().await, &stale), BTreeSet::from([HealthFlag::BlocksLagging]));
+
+    let balance = handler.plan_request(&request("eth_getBalance", json!(["0x0000000000000000000000000000000000000001", "latest"])), None).await.unwrap();
+    let excluded = balance.excluded.iter().find(|excluded| excluded.url == url_key(&stale));
+    assert_eq!(excluded.map(|excluded| excluded.reason), Some(Exclusion::Lagging));
+
+    // Methods that don't read chain state may still use it
+    let version = handler.plan_request(&request("net_version", json!([])), None).await.unwrap();
+    assert!(version.urls.iter().any(|planned| planned.url == url_key(&stale)));
+}
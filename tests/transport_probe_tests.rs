@@ -0,0 +1,160 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use common::*;
+use ez_web3_rpc::performance::{measure_rpcs, measure_rpcs_with_transport, MeasureOptions, TimeoutPolicy};
+use ez_web3_rpc::strategy::{first_responsive, get_fastest, get_first_healthy};
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+
+/// A node living in the test process, reached without any socket.
+struct InMemoryNode {
+    url: String,
+    delay: Duration,
+    rate_limited: bool,
+    requests: AtomicUsize,
+}
+
+#[async_trait]
+impl JsonRpcTransport for InMemoryNode {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn request(&self, request: &JsonRpcRequest) -> ez_web3_rpc::Result<JsonRpcResponse<Value>> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        if self.rate_limited {
+            return Err(RpcHandlerError::HttpStatus { url: self.url.clone(), status: 429 });
+        }
+        let result = match request.method.as_str() {
+            "eth_getBlockByNumber" => json!({ "number": "0x20", "hash": "0xabc", "timestamp": "0x65f1a2b3" }),
+            "eth_getCode" => json!(PERMIT2_CODE),
+            "eth_chainId" => json!("0x1"),
+            _ => return Ok(serde_json::from_value(json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32601, "message": "method not found" } })).unwrap()),
+        };
+        Ok(serde_json::from_value(rpc_response(1, result)).unwrap())
+    }
+}
+
+/// Hands out the in-memory nodes under `memory://` URLs, and nothing else.
+#[derive(Default)]
+struct InMemoryTransports(HashMap<String, Arc<InMemoryNode>>);
+
+impl InMemoryTransports {
+    fn node(mut self, name: &str, delay: Duration, rate_limited: bool) -> Self {
+        let url = format!("memory://{name}");
+        self.0.insert(url.clone(), Arc::new(InMemoryNode { url, delay, rate_limited, requests: AtomicUsize::new(0) }));
+        self
+    }
+
+    fn requests(&self, name: &str) -> usize {
+        self.0[&format!("memory://{name}")].requests.load(Ordering::SeqCst)
+    }
+
+    fn rpcs(&self) -> Vec<Rpc> {
+        let mut urls: Vec<&String> = self.0.keys().collect();
+        urls.sort();
+        urls.into_iter()
+            .map(|url| Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) })
+            .collect()
+    }
+}
+
+impl TransportFactory for InMemoryTransports {
+    fn transport(&self, url: &str) -> Arc<dyn JsonRpcTransport> {
+        self.0[url].clone()
+    }
+
+    fn supports(&self, url: &str) -> bool {
+        self.0.contains_key(url)
+    }
+}
+
+fn transports() -> InMemoryTransports {
+    InMemoryTransports::default()
+        .node("fast", Duration::from_millis(5), false)
+        .node("slow", Duration::from_millis(150), false)
+        .node("throttled", Duration::ZERO, true)
+}
+
+#[tokio::test]
+async fn test_in_memory_endpoints_are_probed_and_measured() {
+    let transports = transports();
+    let (latencies, results) = measure_rpcs(&transports, &transports.rpcs(), Duration::from_secs(2)).await.unwrap();
+
+    assert_eq!(latencies.len(), 2, "{latencies:?}");
+    assert!(latencies["memory://slow"] >= 150);
+    let fast = results.iter().find(|result| result.url == "memory://fast").unwrap();
+    assert!(fast.success && fast.bytecode_ok);
+    assert_eq!(fast.block_number.as_deref(), Some("0x20"));
+    assert_eq!(fast.block_timestamp, Some(0x65f1a2b3));
+    assert_eq!(fast.remote_ip, None);
+    let throttled = results.iter().find(|result| result.url == "memory://throttled").unwrap();
+    assert!(!throttled.success);
+    // Block and bytecode probes for each endpoint
+    assert_eq!((transports.requests("fast"), transports.requests("slow"), transports.requests("throttled")), (2, 2, 2));
+}
+
+#[tokio::test]
+async fn test_fastest_in_memory_endpoint_is_selected() {
+    let transports = transports();
+    let (fastest, latencies) = get_fastest(&transports, &transports.rpcs(), Duration::from_secs(2)).await.unwrap();
+
+    assert_eq!(fastest.as_deref(), Some("memory://fast"));
+    assert!(latencies["memory://fast"] < latencies["memory://slow"]);
+}
+
+#[tokio::test]
+async fn test_strategies_reach_schemes_the_factory_supports() {
+    let transports = InMemoryTransports::default().node("only", Duration::ZERO, false);
+    let rpcs = transports.rpcs();
+
+    let first = get_first_healthy(&transports, &rpcs, Duration::from_secs(2), None).await.unwrap();
+    assert_eq!(first.as_deref(), Some("memory://only"));
+    // Plain HTTP transports can't reach the endpoint, so it isn't tried
+    let http = HttpTransportFactory::new(Duration::from_secs(2));
+    assert_eq!(get_first_healthy(&http, &rpcs, Duration::from_secs(2), None).await.unwrap(), None);
+
+    let options = MeasureOptions::new(TimeoutPolicy::Fixed(Duration::from_secs(2)));
+    assert_eq!(first_responsive(&transports, &rpcs, &options).await.as_deref(), Some("memory://only"));
+}
+
+/// Healthy only where the chain id is mainnet's.
+struct Mainnet;
+
+#[async_trait]
+impl EndpointProbe for Mainnet {
+    fn name(&self) -> &str {
+        "mainnet"
+    }
+
+    async fn probe(&self, _url: &str, transport: &dyn JsonRpcTransport) -> ProbeOutcome {
+        let call = request("eth_chainId", json!([]));
+        match transport.request(&call).await.and_then(JsonRpcResponse::into_result) {
+            Ok(chain) if chain == json!("0x1") => ProbeOutcome::Pass,
+            Ok(chain) => ProbeOutcome::Fail(format!("chain {chain}")),
+            Err(e) => ProbeOutcome::Fail(e.to_string()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_custom_probes_go_through_the_endpoint_transport() {
+    let transports = InMemoryTransports::default().node("only", Duration::ZERO, false);
+    let options = MeasureOptions { custom_probes: vec![Arc::new(Mainnet)], ..MeasureOptions::new(TimeoutPolicy::Fixed(Duration::from_secs(2))) };
+
+    let (latencies, results) = measure_rpcs_with_transport(&transports, &transports.rpcs(), &options).await.unwrap();
+    assert!(latencies.contains_key("memory://only"));
+    assert_eq!(results[0].custom_probes, [NamedProbeOutcome { probe: "mainnet".to_string(), outcome: ProbeOutcome::Pass }]);
+    assert_eq!(transports.requests("only"), 3);
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::Arc,
+    time::Duration,
+};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+const SECRET: &str = "k3y-8f2d1c9a7b";
+
+#[derive(Default)]
+struct Secrets(HashMap<String, String>);
+
+impl Secrets {
+    fn with(mut self, name: &str, value: &str) -> Self {
+        self.0.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+impl SecretResolver for Secrets {
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.0.get(name).cloned()
+    }
+}
+
+/// A config file as it would be committed, with the key left as a placeholder.
+fn config_file(base: &str) -> String {
+    json!({
+        "network_id": TEST_NETWORK_ID,
+        "settings": {
+            "log_level": "Debug",
+            "tracking": "Yes",
+            "network_rpcs": [
+                { "url_template": format!("{base}/v2/{{PROVIDER_KEY}}"), "tracking": "None", "tracking_details": null, "is_open_source": true, "tier": 1 },
+                { "url": "http://127.0.0.1:1/", "tracking": null, "tracking_details": null, "is_open_source": null }
+            ],
+            "network_name": "local_testnet",
+            "rpc_probe_timeout_ms": 2000,
+            "proxy_settings": { "retry_count": 1, "retry_delay_ms": 10, "rpc_call_timeout_ms": 1000 },
+            "data_scope": "only_this_network"
+        }
+    })
+    .to_string()
+}
+
+#[derive(Clone, Default)]
+struct Captured(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_templated_config_round_trips_and_resolves() {
+    let file = config_file("https://eth-mainnet.example");
+    let config: HandlerConfig = serde_json::from_str(&file).unwrap();
+    let rpcs = &config.settings.as_ref().unwrap().network_rpcs;
+    assert_eq!(rpcs[0].url_template.as_deref(), Some("https://eth-mainnet.example/v2/{PROVIDER_KEY}"));
+    assert!(rpcs[0].url.is_none() && rpcs[1].url_template.is_none());
+
+    // Written back out, the template is still what's in the file
+    let written = serde_json::to_value(&config).unwrap();
+    assert_eq!(written["settings"]["network_rpcs"][0]["url_template"], json!("https://eth-mainnet.example/v2/{PROVIDER_KEY}"));
+    assert!(written["settings"]["network_rpcs"][0].get("url").is_none());
+    assert!(!written.to_string().contains(SECRET));
+    let reread: HandlerConfig = serde_json::from_value(written).unwrap();
+
+    let normalized = resolve_config_with(reread, &Secrets::default().with("PROVIDER_KEY", SECRET)).unwrap();
+    assert_eq!(normalized.injected_rpcs[0].url.as_str(), format!("https://eth-mainnet.example/v2/{SECRET}"));
+    assert_eq!(normalized.tiers.get(normalized.injected_rpcs[0].url.as_str()), Some(&1));
+    assert_eq!(normalized.injected_rpcs[1].url.as_str(), "http://127.0.0.1:1/");
+    assert_eq!(normalized.redactor.redact(normalized.injected_rpcs[0].url.as_str()), "https://eth-mainnet.example/v2/{PROVIDER_KEY}");
+    assert!(EnvSecretResolver.resolve("PATH").is_some());
+}
+
+#[test]
+fn test_missing_secret_names_the_placeholder_only() {
+    let mut settings = settings(Vec::<Rpc>::new());
+    settings.network_rpcs = vec![RpcConfig::template("https://{HOST_KEY}.example/v2/{PROVIDER_KEY}")];
+    let err = resolve_config_with(config(settings.clone()), &Secrets::default().with("HOST_KEY", SECRET)).unwrap_err();
+    assert!(matches!(err, RpcHandlerError::UnresolvedPlaceholder { ref placeholder, .. } if placeholder == "PROVIDER_KEY"), "got {err:?}");
+    assert!(err.to_string().contains("{PROVIDER_KEY}"));
+    assert!(!format!("{err} {err:?}").contains(SECRET), "the partly filled-in URL leaked: {err}");
+
+    settings.network_rpcs = vec![RpcConfig::template("https://eth.example/v2/{PROVIDER_KEY")];
+    let err = resolve_config_with(config(settings.clone()), &Secrets::default()).unwrap_err();
+    assert!(matches!(err, RpcHandlerError::InvalidUrlTemplate { .. }), "got {err:?}");
+
+    settings.network_rpcs = vec![RpcConfig::default()];
+    assert!(matches!(resolve_config_with(config(settings), &Secrets::default()), Err(RpcHandlerError::InvalidRpcConfig { .. })));
+}
+
+#[tokio::test]
+async fn test_reports_and_logs_show_only_the_template() {
+    let server = MockServer::start().await;
+    mount_probe(&server, "0x10", Duration::ZERO).await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(503)).mount(&server).await;
+    let base = server.uri();
+
+    let logs = Captured::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let config: HandlerConfig = serde_json::from_str(&config_file(&base)).unwrap();
+    let components = HandlerComponents { secret_resolver: Some(Arc::new(Secrets::default().with("PROVIDER_KEY", SECRET))), ..HandlerComponents::default() };
+    let handler = RpcHandler::with_components(config, None, components).await.unwrap();
+    handler.init().await.unwrap();
+    let request = request("eth_chainId", json!([]));
+    assert!(handler.try_proxy_request_with(request, CallOptions::default()).await.is_err());
+
+    let templated = format!("{base}/v2/{{PROVIDER_KEY}}");
+    let report = handler.health_report().await;
+    let exported = serde_json::to_string(&report).unwrap();
+    assert!(exported.contains(&templated), "{exported}");
+    assert_eq!(report.active_url.as_deref(), Some(templated.as_str()));
+    let doctor = handler.doctor().await;
+    for text in [exported, report.to_string(), serde_json::to_string(&doctor).unwrap(), doctor.to_string()] {
+        assert!(!text.contains(SECRET), "secret in {text}");
+    }
+
+    let logs = String::from_utf8(logs.0.lock().clone()).unwrap();
+    assert!(logs.contains("{PROVIDER_KEY}"), "the failure should have been logged: {logs}");
+    assert!(!logs.contains(SECRET), "secret in logs: {logs}");
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use common::*;
+use ez_web3_rpc::*;
+use serde_json::json;
+use wiremock::{
+    matchers::{body_partial_json, header, method},
+    Mock, MockServer, ResponseTemplate,
+};
+
+fn chain_id_request() -> JsonRpcRequest {
+    request("eth_chainId", json!([]))
+}
+
+/// Probe and `eth_chainId` mocks that only answer requests carrying `name: value`.
+async fn requiring_header(name: &'static str, value: &str) -> MockServer {
+    let server = MockServer::start().await;
+    for (rpc_method, result) in [("eth_getBlockByNumber", json!({ "number": "0x10", "hash": "0xabc" })), ("eth_getCode", json!(PERMIT2_CODE)), ("eth_chainId", json!("0x1"))] {
+        Mock::given(method("POST"))
+            .and(header(name, value))
+            .and(body_partial_json(json!({ "method": rpc_method })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(rpc_response(1, result)))
+            .mount(&server)
+            .await;
+    }
+    server
+}
+
+/// The values of `name` on every request `server` received.
+async fn received(server: &MockServer, name: &str) -> Vec<Option<String>> {
+    server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|request| request.headers.get(name).map(|value| value.to_str().unwrap().to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_probes_and_proxied_calls_send_the_default_user_agent() {
+    let server = requiring_header("user-agent", DEFAULT_USER_AGENT).await;
+    let handler = init_handler(settings(vec![mk_rpc(&server)])).await;
+
+    let response = handler.try_proxy_request(chain_id_request()).await.unwrap();
+    assert_eq!(response.result, Some(json!("0x1")));
+    assert_eq!(count_method(&server, "eth_chainId").await, 1);
+    assert!(received(&server, "user-agent").await.iter().all(|agent| agent.as_deref() == Some(DEFAULT_USER_AGENT)));
+}
+
+#[tokio::test]
+async fn test_configured_user_agent_and_minimal_headers() {
+    let server = requiring_header("user-agent", "acme-indexer/2.1").await;
+    let configured = init_handler(HandlerSettings { user_agent: Some("acme-indexer/2.1".to_string()), ..settings(vec![mk_rpc(&server)]) }).await;
+    assert!(configured.try_proxy_request(chain_id_request()).await.is_ok());
+
+    // With minimal headers and no configured agent, none is sent at all
+    let bare = MockServer::start().await;
+    mount_probe(&bare, "0x10", Duration::ZERO).await;
+    mount_method(&bare, "eth_chainId", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x1")))).await;
+    let minimal = init_handler(HandlerSettings { minimal_headers: true, ..settings(vec![mk_rpc(&bare)]) }).await;
+    assert!(minimal.try_proxy_request(chain_id_request()).await.is_ok());
+    let agents = received(&bare, "user-agent").await;
+    assert!(agents.len() >= 3 && agents.iter().all(Option::is_none), "{agents:?}");
+
+    // A configured agent is still sent under minimal headers
+    let kept = requiring_header("user-agent", "acme-indexer/2.1").await;
+    let settings = HandlerSettings { minimal_headers: true, user_agent: Some("acme-indexer/2.1".to_string()), ..settings(vec![mk_rpc(&kept)]) };
+    assert!(init_handler(settings).await.try_proxy_request(chain_id_request()).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_endpoint_headers_override_and_stay_with_their_endpoint() {
+    let keyed = requiring_header("x-api-key", "secret").await;
+    let plain = MockServer::start().await;
+    mount_probe(&plain, "0x10", Duration::ZERO).await;
+
+    let headers = BTreeMap::from([("x-api-key".to_string(), "secret".to_string()), ("user-agent".to_string(), "keyed-client".to_string())]);
+    let rpcs = vec![RpcConfig { headers: Some(headers), ..mk_rpc(&keyed).into() }, mk_rpc(&plain).into()];
+    let handler = init_handler(HandlerSettings { user_agent: Some("handler-wide".to_string()), ..settings(rpcs) }).await;
+    assert_eq!(handler.get_latencies().await.len(), 2, "both endpoints passed their probes");
+
+    let options = CallOptions { exclude: vec![url_key(&plain)], ..CallOptions::default() };
+    let (response, url) = handler.try_proxy_request_with(chain_id_request(), options).await.unwrap();
+    assert_eq!((response.result, url), (Some(json!("0x1")), url_key(&keyed)));
+
+    assert!(received(&keyed, "user-agent").await.iter().all(|agent| agent.as_deref() == Some("keyed-client")));
+    assert!(received(&plain, "x-api-key").await.iter().all(Option::is_none), "the key never reaches another endpoint");
+    assert!(received(&plain, "user-agent").await.iter().all(|agent| agent.as_deref() == Some("handler-wide")));
+}
+
+#[test]
+fn test_invalid_headers_are_rejected_when_resolving() {
+    let rpc = RpcConfig { url: Some("http://127.0.0.1:1/".parse().unwrap()), headers: Some(BTreeMap::from([("bad header".to_string(), "x".to_string())])), ..RpcConfig::default() };
+    assert!(matches!(resolve_config(config(settings(vec![rpc]))), Err(RpcHandlerError::InvalidRpcConfig { .. })));
+
+    let settings = HandlerSettings { user_agent: Some("line\nbreak".to_string()), ..settings(Vec::<Rpc>::new()) };
+    assert!(matches!(resolve_config(config(settings)), Err(RpcHandlerError::InvalidRpcConfig { .. })));
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use ez_web3_rpc::validation::*;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+use wiremock::{MockServer, ResponseTemplate};
+
+fn ok(result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": 1, "result": result })
+}
+
+#[test]
+fn test_jsonrpc_version() {
+    assert_eq!(check_jsonrpc_version(&request("eth_chainId", json!([])), &ok(json!("0x1"))), None);
+    assert!(check_jsonrpc_version(&request("eth_chainId", json!([])), &json!({ "jsonrpc": "1.0", "id": 1, "result": "0x1" })).is_some());
+    assert!(check_jsonrpc_version(&request("eth_chainId", json!([])), &json!({ "id": 1, "result": "0x1" })).is_some());
+}
+
+#[test]
+fn test_result_xor_error() {
+    let error = json!({ "code": -32000, "message": "boom" });
+    assert_eq!(check_result_xor_error(&request("eth_chainId", json!([])), &ok(json!("0x1"))), None);
+    assert_eq!(check_result_xor_error(&request("eth_chainId", json!([])), &json!({ "jsonrpc": "2.0", "id": 1, "error": error })), None);
+    assert!(check_result_xor_error(&request("eth_chainId", json!([])), &json!({ "jsonrpc": "2.0", "id": 1, "result": "0x1", "error": error })).is_some());
+    assert!(check_result_xor_error(&request("eth_chainId", json!([])), &json!({ "jsonrpc": "2.0", "id": 1 })).is_some());
+    assert!(check_result_xor_error(&block_number(), &ok(Value::Null)).is_some());
+    // A missing receipt is legitimately null
+    assert_eq!(check_result_xor_error(&request("eth_getTransactionReceipt", json!([])), &ok(Value::Null)), None);
+}
+
+#[test]
+fn test_id_matches() {
+    assert_eq!(check_id_matches(&request("eth_chainId", json!([])), &ok(json!("0x1"))), None);
+    assert!(check_id_matches(&request("eth_chainId", json!([])), &json!({ "jsonrpc": "2.0", "id": "1", "result": "0x1" })).is_some());
+    assert!(check_id_matches(&request("eth_chainId", json!([])), &json!({ "jsonrpc": "2.0", "id": 2, "result": "0x1" })).is_some());
+    let named = JsonRpcRequest { id: Some("1".into()), ..request("eth_chainId", json!([])) };
+    assert_eq!(check_id_matches(&named, &json!({ "jsonrpc": "2.0", "id": "1", "result": "0x1" })), None);
+    assert_eq!(check_id_matches(&named, &ok(json!("0x1"))), Some("expected id \"1\", got 1".to_string()));
+}
+
+#[test]
+fn test_integer_and_fractional_ids_differ() {
+    let sent_1 = JsonRpcRequest::new("eth_chainId", json!([])).with_id(1);
+    let fractional = json!({ "jsonrpc": "2.0", "id": 1.0, "result": "0x1" });
+    assert_eq!(check_id_matches(&sent_1, &fractional), Some("expected id 1, got 1.0".to_string()));
+    assert_eq!(check_id_matches(&sent_1, &ok(json!("0x1"))), None);
+
+    let sent_1_0 = JsonRpcRequest::new("eth_chainId", json!([])).with_id(JsonRpcId::Number(serde_json::Number::from_f64(1.0).unwrap()));
+    assert_eq!(check_id_matches(&sent_1_0, &fractional), None);
+    assert!(check_id_matches(&sent_1_0, &ok(json!("0x1"))).is_some());
+}
+
+#[test]
+fn test_result_shape() {
+    assert_eq!(check_result_shape(&block_number(), &ok(json!("0x10"))), None);
+    assert!(check_result_shape(&block_number(), &ok(json!(16))).is_some());
+    assert!(check_result_shape(&block_number(), &ok(json!("0x010"))).is_some());
+    assert_eq!(check_result_shape(&request("eth_getCode", json!([])), &ok(json!("0x6080"))), None);
+    assert!(check_result_shape(&request("eth_getCode", json!([])), &ok(json!("0x608"))).is_some());
+    assert_eq!(check_result_shape(&request("eth_getBlockByNumber", json!([])), &ok(json!({ "number": "0x1" }))), None);
+    assert!(check_result_shape(&request("eth_getBlockByNumber", json!([])), &ok(json!("0x1"))).is_some());
+    assert_eq!(check_result_shape(&request("eth_unknownMethod", json!([])), &ok(json!(42))), None);
+}
+
+#[test]
+fn test_hex_patterns() {
+    assert!(is_hex_quantity("0x0"));
+    assert!(is_hex_quantity("0xdeadBEEF"));
+    assert!(!is_hex_quantity("0x"));
+    assert!(!is_hex_quantity("0x00"));
+    assert!(!is_hex_quantity("10"));
+    assert!(is_hex_data("0x"));
+    assert!(!is_hex_data("0xzz"));
+}
+
+#[test]
+fn test_validate_response_names_the_failing_rule() {
+    let violation = validate_response(&block_number(), &ok(Value::Null)).unwrap();
+    assert!(violation.starts_with("result_xor_error"), "{violation}");
+}
+
+async fn null_then_good_backends() -> (MockServer, MockServer) {
+    let bad = MockServer::start().await;
+    let good = MockServer::start().await;
+    mount_probe(&bad, "0x10", Duration::ZERO).await;
+    mount_probe(&good, "0x10", Duration::from_millis(80)).await;
+    mount_method(&bad, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, Value::Null))).await;
+    mount_method(&good, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10")))).await;
+    (bad, good)
+}
+
+async fn block_number_with(mode: ValidationMode, bad: &MockServer, good: &MockServer) -> (std::sync::Arc<RpcHandler>, Result<JsonRpcResponse<Value>>) {
+    let mut settings = settings(vec![mk_rpc(bad), mk_rpc(good)]);
+    settings.validation_mode = mode;
+    let handler = RpcHandler::new(config(settings), Some(Strategy::Fastest)).await.unwrap();
+    handler.init().await.unwrap();
+    assert_eq!(handler.get_provider_url().await.unwrap(), url_key(bad));
+
+    let response = handler.try_proxy_request(block_number()).await;
+    (handler, response)
+}
+
+#[tokio::test]
+async fn test_strict_mode_fails_over_on_null_result() {
+    let (bad, good) = null_then_good_backends().await;
+    let (handler, response) = block_number_with(ValidationMode::Strict, &bad, &good).await;
+
+    assert_eq!(response.unwrap().result, Some(json!("0x10")));
+
+    let report = handler.health_report().await;
+    let bad_health = report.endpoints.iter().find(|e| e.url == url_key(&bad)).unwrap();
+    let good_health = report.endpoints.iter().find(|e| e.url == url_key(&good)).unwrap();
+    assert_eq!(bad_health.malformed_responses, 1);
+    assert_eq!(good_health.malformed_responses, 0);
+}
+
+#[tokio::test]
+async fn test_strict_mode_fails_over_when_1_comes_back_as_1_0() {
+    let (bad, good) = (MockServer::start().await, MockServer::start().await);
+    mount_probe(&bad, "0x10", Duration::ZERO).await;
+    mount_probe(&good, "0x10", Duration::from_millis(80)).await;
+    mount_method(&bad, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(json!({ "jsonrpc": "2.0", "id": 1.0, "result": "0x10" }))).await;
+    mount_method(&good, "eth_blockNumber", ResponseTemplate::new(200).set_body_json(rpc_response(1, json!("0x10")))).await;
+    let (handler, response) = block_number_with(ValidationMode::Strict, &bad, &good).await;
+
+    assert_eq!(response.unwrap().numeric_id(), Some(1));
+    let report = handler.health_report().await;
+    assert_eq!(report.endpoints.iter().find(|e| e.url == url_key(&bad)).unwrap().malformed_responses, 1);
+}
+
+#[tokio::test]
+async fn test_lenient_mode_keeps_null_result() {
+    let (bad, good) = null_then_good_backends().await;
+    let (handler, response) = block_number_with(ValidationMode::Lenient, &bad, &good).await;
+
+    let response = response.unwrap();
+    assert_eq!((response.result, response.error.is_none()), (Some(Value::Null), true), "the null is kept, not dropped as absent");
+    assert!(handler.health_report().await.endpoints.iter().all(|e| e.malformed_responses == 0));
+}
@@ -0,0 +1,125 @@
+use ez_web3_rpc::jsonrpc::diff::DEFAULT_IGNORED_FIELDS;
+use ez_web3_rpc::*;
+use serde_json::{json, Value};
+
+fn block() -> Value {
+    serde_json::from_str(include_str!("fixtures/block_with_receipts.json")).unwrap()
+}
+
+fn changed(path: &str, left: Value, right: Value) -> Difference {
+    Difference { path: path.to_string(), kind: DifferenceKind::Changed { left, right } }
+}
+
+#[test]
+fn test_identical_blocks_have_no_differences() {
+    let diff = diff_values(&block(), &block(), &DiffOptions::default());
+    assert!(diff.is_empty());
+    assert!(!diff.incomplete);
+}
+
+#[test]
+fn test_nested_receipt_field_is_the_one_semantic_difference() {
+    let production = block();
+    let mut provider = block();
+    // A different receipt status, an extra field only this provider returns, and upper-case hex
+    provider["receipts"][1]["status"] = json!("0x0");
+    provider["totalDifficulty"] = json!("0xc70d815d562d3cfa955");
+    provider["hash"] = json!(production["hash"].as_str().unwrap().to_uppercase().replacen("0X", "0x", 1));
+    provider["receipts"][1]["logs"][0]["address"] = json!("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+    let diff = diff_values(&production, &provider, &DiffOptions::default());
+    assert_eq!(diff.differences, [changed("$.receipts[1].status", json!("0x1"), json!("0x0"))]);
+    assert!(!diff.incomplete);
+    assert_eq!(diff.differences[0].to_string(), r#"$.receipts[1].status: "0x1" != "0x0""#);
+}
+
+#[test]
+fn test_hex_case_and_ignored_fields_count_when_turned_off() {
+    let production = block();
+    let mut provider = block();
+    provider["totalDifficulty"] = json!("0x0");
+    provider["miner"] = json!("0x95222290DD7278AA3DDD389CC1E1D165CC4BAFE5");
+
+    let strict = DiffOptions { ignore_fields: Vec::new(), ignore_hex_case: false, ..DiffOptions::default() };
+    let diff = diff_values(&production, &provider, &strict);
+    assert_eq!(diff.differences, [
+        changed("$.miner", json!("0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5"), json!("0x95222290DD7278AA3DDD389CC1E1D165CC4BAFE5")),
+        Difference { path: "$.totalDifficulty".to_string(), kind: DifferenceKind::OnlyRight { value: json!("0x0") } },
+    ]);
+    assert_eq!(DEFAULT_IGNORED_FIELDS, ["totalDifficulty"]);
+}
+
+#[test]
+fn test_custom_ignore_list_skips_provider_fields_at_any_depth() {
+    let production = block();
+    let mut provider = block();
+    provider["l1BlockNumber"] = json!("0x1");
+    provider["receipts"][0]["l1Fee"] = json!("0x2");
+
+    let options = DiffOptions { ignore_fields: vec!["l1BlockNumber".to_string(), "l1Fee".to_string()], ..DiffOptions::default() };
+    assert!(diff_values(&production, &provider, &options).is_empty());
+    assert_eq!(diff_values(&production, &provider, &DiffOptions::default()).differences.len(), 2);
+}
+
+#[test]
+fn test_array_length_mismatch_is_reported_once_and_common_elements_compared() {
+    let production = block();
+    let mut provider = block();
+    let receipts = provider["receipts"].as_array_mut().unwrap();
+    receipts.pop();
+    receipts[0]["gasUsed"] = json!("0x5209");
+
+    let diff = diff_values(&production, &provider, &DiffOptions::default());
+    assert_eq!(diff.differences, [
+        Difference { path: "$.receipts".to_string(), kind: DifferenceKind::LengthMismatch { left: 2, right: 1 } },
+        changed("$.receipts[0].gasUsed", json!("0x5208"), json!("0x5209")),
+    ]);
+    let top = diff.most_significant(1);
+    assert_eq!(top, [changed("$.receipts[0].gasUsed", json!("0x5208"), json!("0x5209"))], "changed values outrank lengths");
+}
+
+#[test]
+fn test_long_values_are_truncated() {
+    let options = DiffOptions { max_leaf_len: 10, ..DiffOptions::default() };
+    let left = json!({ "data": "0x0123456789abcdef" });
+    let right = json!({ "data": "0xfedcba9876543210" });
+
+    let diff = diff_values(&left, &right, &options);
+    assert_eq!(diff.differences, [changed("$.data", json!("0x01234567… (18 chars)"), json!("0xfedcba98… (18 chars)"))]);
+}
+
+#[test]
+fn test_subtrees_past_max_depth_are_compared_whole() {
+    let production = block();
+    let mut provider = block();
+    provider["receipts"][1]["logs"][0]["logIndex"] = json!("0x1");
+
+    let shallow = DiffOptions { max_depth: 2, max_leaf_len: 1_000, ..DiffOptions::default() };
+    let diff = diff_values(&production, &provider, &shallow);
+    assert_eq!(diff.differences.len(), 1);
+    assert_eq!(diff.differences[0].path, "$.receipts[1]");
+    assert!(matches!(diff.differences[0].kind, DifferenceKind::Changed { .. }));
+
+    // Hex case is still ignored inside a subtree compared whole
+    let mut upper = block();
+    upper["receipts"][1]["logs"][0]["address"] = json!("0xA0B86991C6218B36C1D19D4A2E9EB0CE3606EB48");
+    assert!(diff_values(&production, &upper, &shallow).is_empty());
+}
+
+#[test]
+fn test_node_and_difference_caps_mark_the_diff_incomplete() {
+    let left = Value::Array((0..1_000).map(|i| json!(format!("0x{i:x}"))).collect());
+    let right = Value::Array((0..1_000).map(|i| json!(format!("0x{:x}", i + 1))).collect());
+
+    let capped = diff_values(&left, &right, &DiffOptions { max_differences: 10, ..DiffOptions::default() });
+    assert_eq!(capped.differences.len(), 10);
+    assert!(capped.incomplete);
+
+    let capped = diff_values(&left, &right, &DiffOptions { max_nodes: 101, ..DiffOptions::default() });
+    assert_eq!(capped.differences.len(), 100, "the root and 100 elements");
+    assert!(capped.incomplete);
+
+    let complete = diff_values(&left, &right, &DiffOptions { max_differences: 1_000, ..DiffOptions::default() });
+    assert_eq!(complete.differences.len(), 1_000);
+    assert!(!complete.incomplete);
+}
@@ -0,0 +1,102 @@
+#![cfg(target_arch = "wasm32")]
+
+//! The handler on `wasm32`, with the JavaScript `fetch` the HTTP layer calls swapped for a
+//! double that answers like a probe-passing node. Runs under Node by default.
+
+use std::sync::Arc;
+
+use ez_web3_rpc::*;
+use serde_json::json;
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// A network id that won't exist in the generated chainlist data so tests stay hermetic.
+const TEST_NETWORK_ID: u64 = 424242;
+
+#[wasm_bindgen(inline_js = r#"
+const PERMIT2_CODE = "0x6040608081526004908136101561001557600080fd5b600090813560e01c";
+
+function answer(call) {
+    const results = {
+        eth_getBlockByNumber: { number: "0x10", hash: "0xabc" },
+        eth_getCode: PERMIT2_CODE,
+        eth_blockNumber: "0x10",
+        eth_chainId: "0x67932",
+    };
+    return { jsonrpc: "2.0", id: call.id, result: results[call.method] ?? null };
+}
+
+export function install_fetch_double() {
+    globalThis.fetchDoubleCalls = [];
+    globalThis.fetch = async (input, init) => {
+        const request = new Request(input, init);
+        const body = JSON.parse(await request.text());
+        globalThis.fetchDoubleCalls.push({ url: request.url, body });
+        const payload = Array.isArray(body) ? body.map(answer) : answer(body);
+        const response = new Response(JSON.stringify(payload), { status: 200, headers: { "content-type": "application/json" } });
+        // A constructed response has no URL, and reqwest reads the one it was served from
+        return Object.defineProperty(response, "url", { value: request.url });
+    };
+}
+
+export function fetch_double_calls(method) {
+    return globalThis.fetchDoubleCalls.filter((call) => call.body.method === method).length;
+}
+"#)]
+extern "C" {
+    fn install_fetch_double();
+    fn fetch_double_calls(method: &str) -> usize;
+}
+
+fn rpc(url: &str) -> Rpc {
+    Rpc { url: url.parse().unwrap(), tracking: None, tracking_details: None, is_open_source: Some(true) }
+}
+
+fn config(urls: &[&str], settings: HandlerSettings) -> HandlerConfig {
+    let settings = HandlerSettings {
+        log_level: LogLevel::Error,
+        network_rpcs: urls.iter().map(|url| RpcConfig::from(rpc(url))).collect(),
+        network_name: "local_testnet".to_string(),
+        rpc_probe_timeout_ms: 2000,
+        proxy_settings: Some(ProxySettings { retry_count: 1, retry_delay_ms: 10, rpc_call_timeout_ms: 1000, connect_timeout_ms: None }),
+        data_scope: DataScope::OnlyThisNetwork,
+        ..settings
+    };
+    HandlerConfig { network_id: TEST_NETWORK_ID, settings: Some(settings) }
+}
+
+#[wasm_bindgen_test]
+async fn test_proxy_request_goes_through_fetch() {
+    install_fetch_double();
+    let handler = RpcHandler::new(config(&["https://one.example/"], HandlerSettings::default()), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let response = handler.try_proxy_request(JsonRpcRequest::new("eth_chainId", json!([])).with_id(7)).await.unwrap();
+
+    assert_eq!(response.result, Some(json!("0x67932")));
+    assert!(response.error.is_none());
+    assert_eq!(fetch_double_calls("eth_chainId"), 1);
+    assert!(fetch_double_calls("eth_getCode") >= 1, "init probes over the same fetch");
+}
+
+#[wasm_bindgen_test]
+async fn test_consensus_reaches_quorum_across_fetched_endpoints() {
+    install_fetch_double();
+    let urls = ["https://one.example/", "https://two.example/", "https://three.example/"];
+    let handler = RpcHandler::new(config(&urls, HandlerSettings::default()), None).await.unwrap();
+    handler.init().await.unwrap();
+
+    let block: String = RpcCalls::new(Arc::clone(&handler)).consensus(&JsonRpcRequest::new("eth_blockNumber", json!([])), 0.66, None).await.unwrap();
+
+    assert_eq!(block, "0x10");
+}
+
+#[wasm_bindgen_test]
+async fn test_pinning_resolved_ips_is_unsupported() {
+    install_fetch_double();
+    let settings = HandlerSettings { pin_resolved_ips: true, ..HandlerSettings::default() };
+
+    let error = RpcHandler::new(config(&["https://one.example/"], settings), None).await.err().unwrap();
+
+    assert!(matches!(&error, RpcHandlerError::Unsupported { feature } if feature == "pin_resolved_ips"), "{error}");
+}
@@ -45,7 +45,8 @@ async fn ws_vs_http_latency() -> anyhow::Result<()> {
 
     // HTTP via existing handler
     let config = HandlerConfig::new(100); // Gnosis
-    let handler = RpcHandler::new(Some(config), 100).await?;
+    let handler = RpcHandler::new(config, None).await?;
+    handler.init().await?;
 
     // Methods to probe (lightweight)
     let lightweight_methods = ["eth_blockNumber", "eth_gasPrice"];
@@ -54,8 +55,8 @@ async fn ws_vs_http_latency() -> anyhow::Result<()> {
 
     for m in &lightweight_methods {
         // warmup
-        for _ in 0..warmup { let _ = handler.try_proxy_request(JsonRpcRequest { jsonrpc: "2.0".into(), method: (*m).into(), params: json!([]), id: Some(1) }).await?; }
-        for _ in 0..iterations { let start = Instant::now(); let _ = handler.try_proxy_request(JsonRpcRequest { jsonrpc: "2.0".into(), method: (*m).into(), params: json!([]), id: Some(1) }).await?; http_samples.get_mut(m).unwrap().push(start.elapsed()); }
+        for _ in 0..warmup { let _ = handler.try_proxy_request(JsonRpcRequest::new(*m, json!([])).with_id(1)).await?; }
+        for _ in 0..iterations { let start = Instant::now(); let _ = handler.try_proxy_request(JsonRpcRequest::new(*m, json!([])).with_id(1)).await?; http_samples.get_mut(m).unwrap().push(start.elapsed()); }
     }
 
     // WebSocket raw baseline
@@ -79,7 +80,7 @@ async fn ws_vs_http_latency() -> anyhow::Result<()> {
         // Choose a block tag (latest) or potentially random recent block for HTTP & WS parity
         let tag_param = heavy_block_tag.clone();
         // HTTP heavy
-        let req = JsonRpcRequest { jsonrpc: "2.0".into(), method: "eth_getBlockByNumber".into(), params: json!([tag_param, true]), id: Some(777) };
+        let req = JsonRpcRequest::new("eth_getBlockByNumber", json!([tag_param, true])).with_id(777);
         let start = Instant::now();
         let _ = handler.try_proxy_request(req).await?;
         heavy_http = Some(start.elapsed());